@@ -0,0 +1,36 @@
+//! Connects to ZigZag, logs in and prints the first messages of the session.
+//!
+//! ```sh
+//! cargo run --example login --no-default-features --features client -- 1002 23
+//! ```
+use flexi_logger::Logger;
+use std::time::Duration;
+use zigzag_bots::client::ZigzagClient;
+use zigzag_bots::connection::{Backoff, Connection, Heartbeat};
+
+const URL: &str = "wss://secret-thicket-93345.herokuapp.com";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    Logger::try_with_env_or_str("info")?.start()?;
+
+    let mut args = std::env::args().skip(1);
+    let chain_id = args.next().unwrap_or_else(|| "1002".into()).parse()?;
+    let user_id = args.next().unwrap_or_else(|| "23".into());
+    let url = std::env::var("ZIGZAG_WS_URL").unwrap_or_else(|_| URL.into());
+
+    let backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(30));
+    let heartbeat = Heartbeat {
+        interval: Duration::from_secs(10),
+        timeout: Duration::from_secs(30),
+    };
+    let connection = Connection::connect(&url, backoff, heartbeat).await?;
+    let mut client = ZigzagClient::new(connection);
+    client.login(chain_id, user_id).await?;
+    log::info!("Logged in on chain {}", chain_id);
+
+    for _ in 0..5 {
+        log::info!("{:?}", client.recv().await?);
+    }
+    client.close().await
+}
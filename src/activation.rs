@@ -0,0 +1,125 @@
+/// Waiting for the zksync account of a fresh wallet, which only exists once
+/// funds were deposited to its address.
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::time::Instant;
+use zksync::{provider::Provider, zksync_types::AccountId, Wallet};
+use zksync_eth_signer::EthereumSigner;
+
+/// Interval between lookups of the account.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Lookups between progress lines in the log.
+const POLLS_PER_LOG: u32 = 4;
+
+#[async_trait]
+pub trait AccountSource: Send + Sync {
+    /// Id of the account, none before its first deposit.
+    async fn account_id(&self) -> anyhow::Result<Option<AccountId>>;
+}
+
+#[async_trait]
+impl<S, P> AccountSource for Wallet<S, P>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    async fn account_id(&self) -> anyhow::Result<Option<AccountId>> {
+        Ok(self.account_info().await?.id)
+    }
+}
+
+/// Looks the account up every `interval` until it exists, for at most
+/// `timeout`. Failed lookups are retried.
+pub async fn wait_for_account(
+    source: &dyn AccountSource,
+    address: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> anyhow::Result<AccountId> {
+    let start = Instant::now();
+    let mut polls = 0;
+    loop {
+        match source.account_id().await {
+            Ok(Some(id)) => {
+                log::info!("zksync account {} of {} is active", id, address);
+                return Ok(id);
+            }
+            Ok(None) => (),
+            Err(e) => log::warn!("Looking up the zksync account of {}: {}", address, e),
+        }
+        if start.elapsed() + interval > timeout {
+            return Err(anyhow::anyhow!(
+                "No zksync account for {} after {:?}, deposit funds to it first!",
+                address,
+                timeout
+            ));
+        }
+        polls += 1;
+        if polls % POLLS_PER_LOG == 0 {
+            log::info!(
+                "Still waiting for a deposit to {}, {}s elapsed",
+                address,
+                start.elapsed().as_secs()
+            );
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Answers lookups from a script, then with the last answer.
+    struct Script(Mutex<Vec<anyhow::Result<Option<AccountId>>>>);
+
+    #[async_trait]
+    impl AccountSource for Script {
+        async fn account_id(&self) -> anyhow::Result<Option<AccountId>> {
+            let mut answers = self.0.lock().unwrap();
+            match answers.len() {
+                1 => match &answers[0] {
+                    Ok(id) => Ok(*id),
+                    Err(e) => Err(anyhow::anyhow!("{}", e)),
+                },
+                _ => answers.remove(0),
+            }
+        }
+    }
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    #[tokio::test]
+    async fn test_wait_for_account() {
+        let source = Script(Mutex::new(vec![
+            Ok(None),
+            Err(anyhow::anyhow!("timeout")),
+            Ok(None),
+            Ok(Some(AccountId(42))),
+        ]));
+        let id = wait_for_account(
+            &source,
+            ADDRESS,
+            Duration::from_millis(1),
+            Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(id.expect("wait_for_account"), AccountId(42));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_account_timeout() {
+        let source = Script(Mutex::new(vec![Ok(None)]));
+        let e = wait_for_account(
+            &source,
+            ADDRESS,
+            Duration::from_millis(5),
+            Duration::from_millis(20),
+        )
+        .await
+        .expect_err("wait_for_account");
+        assert!(e.to_string().contains(ADDRESS), "{}", e);
+    }
+}
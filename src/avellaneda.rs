@@ -0,0 +1,208 @@
+/// Avellaneda–Stoikov quoting: a reservation price shifted against the
+/// inventory, and an optimal spread growing with the risk aversion and the
+/// volatility.
+use crate::zigzag::Decimal;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use serde::Deserialize;
+
+/// Below this risk aversion the liquidity term is taken at its limit.
+const MIN_GAMMA: f64 = 1e-9;
+
+/// `[market_maker.avellaneda]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AvellanedaConfig {
+    /// Risk aversion
+    pub gamma: Decimal,
+    /// Order book liquidity: how fast the fill intensity decays with the
+    /// distance from the mid, per unit of the quote asset
+    pub kappa: Decimal,
+    /// Remaining trading horizon the inventory risk is priced over, in
+    /// seconds
+    pub horizon_secs: u64,
+    /// Inventory, in base units either way, at which the side growing it
+    /// stops quoting
+    pub max_inventory: Option<Decimal>,
+    pub min_spread_bps: Decimal,
+    pub max_spread_bps: Decimal,
+}
+
+impl Default for AvellanedaConfig {
+    fn default() -> Self {
+        Self {
+            gamma: Decimal::new(1, 1),
+            kappa: Decimal::new(15, 1),
+            horizon_secs: 300,
+            max_inventory: None,
+            min_spread_bps: Decimal::from(5),
+            max_spread_bps: Decimal::from(200),
+        }
+    }
+}
+
+/// Quotes of the model around one mid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AvellanedaQuote {
+    pub reservation: Decimal,
+    /// Distance of the bid and of the ask from the reservation price
+    pub half_spread: Decimal,
+    /// Whether each side is quoted, false once the inventory hits the cap
+    pub bid: bool,
+    pub ask: bool,
+}
+
+impl AvellanedaConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.gamma <= Decimal::ZERO || self.kappa <= Decimal::ZERO {
+            return Err(anyhow::anyhow!("gamma and kappa must be positive!"));
+        }
+        if self.min_spread_bps > self.max_spread_bps {
+            return Err(anyhow::anyhow!(
+                "min_spread_bps must not exceed max_spread_bps!"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Quotes around `mid` for `inventory` and a per-minute `volatility` of
+    /// log-returns:
+    ///
+    /// - reservation price `r = s - q * gamma * sigma^2 * T`
+    /// - spread `gamma * sigma^2 * T + 2 / gamma * ln(1 + gamma / kappa)`
+    ///
+    /// with `sigma` in quote units per square root of a minute and `T` the
+    /// horizon in minutes. The spread stays within the configured bounds,
+    /// and the reservation price within the largest half-spread of `mid`,
+    /// so degenerate parameters never produce unbounded or NaN quotes.
+    pub fn quote(&self, mid: Decimal, inventory: Decimal, volatility: f64) -> AvellanedaQuote {
+        let bps = |bps: Decimal| mid * bps / Decimal::from(20_000);
+        let (min_half, max_half) = (bps(self.min_spread_bps), bps(self.max_spread_bps));
+        let inventory = match self.max_inventory {
+            Some(max) => inventory.clamp(-max, max),
+            None => inventory,
+        };
+        let f = |value: Decimal| value.to_f64().unwrap_or_default();
+        let (gamma, kappa) = (f(self.gamma), f(self.kappa));
+        let sigma = if volatility.is_finite() {
+            volatility.abs() * f(mid)
+        } else {
+            0.0
+        };
+        let risk = gamma * sigma.powi(2) * self.horizon_secs as f64 / 60.0;
+        let liquidity = if gamma < MIN_GAMMA {
+            2.0 / kappa
+        } else {
+            2.0 / gamma * (gamma / kappa).ln_1p()
+        };
+        // Non-finite results saturate at the bounds.
+        let half_spread = Decimal::from_f64((risk + liquidity) / 2.0)
+            .unwrap_or(max_half)
+            .clamp(min_half, max_half);
+        let shift = match Decimal::from_f64(-f(inventory) * risk) {
+            Some(shift) => shift.clamp(-max_half, max_half),
+            None if inventory > Decimal::ZERO => -max_half,
+            None if inventory < Decimal::ZERO => max_half,
+            None => Decimal::ZERO,
+        };
+        let capped = |limit: Decimal| matches!(self.max_inventory, Some(max) if limit >= max);
+        AvellanedaQuote {
+            reservation: mid + shift,
+            half_spread,
+            bid: !capped(inventory),
+            ask: !capped(-inventory),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config() -> AvellanedaConfig {
+        AvellanedaConfig {
+            gamma: dec!(0.1),
+            kappa: dec!(1.5),
+            horizon_secs: 300,
+            max_inventory: Some(dec!(2)),
+            min_spread_bps: dec!(1),
+            max_spread_bps: dec!(500),
+        }
+    }
+
+    #[test]
+    fn test_reservation_against_inventory() {
+        let config = config();
+        // 0.1% a minute at 2000: sigma^2 * T = 4 * 5, times gamma is 2.
+        let flat = config.quote(dec!(2000), dec!(0), 0.001);
+        assert_eq!(flat.reservation, dec!(2000));
+        let long = config.quote(dec!(2000), dec!(1), 0.001);
+        let short = config.quote(dec!(2000), dec!(-1), 0.001);
+        assert!((long.reservation - dec!(1998)).abs() < dec!(0.000001));
+        assert!((short.reservation - dec!(2002)).abs() < dec!(0.000001));
+        assert_eq!(long.half_spread, flat.half_spread);
+        // 2 of inventory risk plus 20 * ln(1 + 1 / 15) of liquidity.
+        let expected = (2.0 + 20.0 * (1.0f64 + 1.0 / 15.0).ln()) / 2.0;
+        assert!((flat.half_spread.to_f64().unwrap() - expected).abs() < 1e-9);
+        assert!(flat.bid && flat.ask);
+
+        // At the cap the side growing the inventory is pulled.
+        let capped = config.quote(dec!(2000), dec!(3), 0.001);
+        assert!(!capped.bid && capped.ask);
+        assert_eq!(
+            capped.reservation,
+            config.quote(dec!(2000), dec!(2), 0.001).reservation
+        );
+        assert!(config.quote(dec!(2000), dec!(-2), 0.001).bid);
+        assert!(!config.quote(dec!(2000), dec!(-2), 0.001).ask);
+    }
+
+    #[test]
+    fn test_spread_grows_with_gamma_and_volatility() {
+        let config = config();
+        let half = |config: &AvellanedaConfig, volatility| {
+            config.quote(dec!(2000), dec!(0), volatility).half_spread
+        };
+        let risk_averse = AvellanedaConfig {
+            gamma: dec!(0.5),
+            ..config.clone()
+        };
+        assert!(half(&risk_averse, 0.001) > half(&config, 0.001));
+        assert!(half(&config, 0.002) > half(&config, 0.001));
+        assert!(half(&config, 0.001) > half(&config, 0.0));
+    }
+
+    #[test]
+    fn test_degenerate_parameters() {
+        let config = config();
+        // No volatility: no inventory shift, only the liquidity term.
+        let calm = config.quote(dec!(2000), dec!(1), 0.0);
+        assert_eq!(calm.reservation, dec!(2000));
+        assert!(calm.half_spread > Decimal::ZERO);
+        let nan = config.quote(dec!(2000), dec!(1), f64::NAN);
+        assert_eq!(nan, calm);
+
+        // A vanishing kappa saturates at the max spread, 500 bps.
+        let illiquid = AvellanedaConfig {
+            kappa: dec!(0.0000000000000000000000000001),
+            ..config.clone()
+        };
+        assert_eq!(
+            illiquid.quote(dec!(2000), dec!(0), 0.001).half_spread,
+            dec!(50)
+        );
+        let indifferent = AvellanedaConfig {
+            gamma: dec!(0),
+            ..config.clone()
+        };
+        let quote = indifferent.quote(dec!(2000), dec!(1), 0.001);
+        assert_eq!(quote.reservation, dec!(2000));
+        assert!((quote.half_spread - dec!(2) / dec!(1.5) / dec!(2)).abs() < dec!(0.000001));
+        assert!(indifferent.validate().is_err());
+
+        // Huge volatility keeps the reservation price within the max spread.
+        let wild = config.quote(dec!(2000), dec!(1), 1e10);
+        assert_eq!(wild.reservation, dec!(1950));
+        assert_eq!(wild.half_spread, dec!(50));
+    }
+}
@@ -0,0 +1,753 @@
+/// Backtests of a strategy over `--record` captures. The recorded market
+/// data drives the strategies on the clock of the capture, and our quotes
+/// fill whenever the recorded market trades through them. Nothing depends
+/// on the wall clock, so a capture and a config always give the same
+/// report.
+use crate::capture::{Direction, Frame};
+use crate::dispatcher::{DispatcherHandle, MarketRouter, Outbox, Route};
+use crate::fees::FeeSource;
+use crate::marketdata::SummaryCache;
+use crate::orders::{to_raw, OrderParams, OrderSigner};
+use crate::portfolio::is_traded;
+use crate::strategy::{self, MarketMakerConfig, Strategy, StrategyContext, StrategyRegistry};
+use crate::zigzag::{
+    Amount, Decimal, Fill, FillId, Liquidity, Market, MarketInfo, Operation, OrderStatus, Side,
+    Timestamp, ZksyncOrder,
+};
+use async_trait::async_trait;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use num::BigUint;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+use zksync::zksync_types::TokenId;
+
+/// Seconds between strategy ticks, as in live mode.
+const TICK_SECS: Timestamp = 1;
+
+/// Counterparty of the simulated fills.
+const TAKER: &str = "backtest-taker";
+const MAKER: &str = "backtest";
+
+/// Incoming operations of a capture, or of every `.jsonl` capture of a
+/// directory in file name order, with the second they were received.
+/// Frames that are not operations are skipped.
+pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<(Timestamp, Operation)>> {
+    let path = path.as_ref();
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<_>>()?;
+        files.retain(|file| matches!(file.extension(), Some(ext) if ext == "jsonl"));
+        files.sort();
+        files
+    } else {
+        vec![path.to_owned()]
+    };
+    let mut ops = Vec::new();
+    for file in files {
+        let reader = BufReader::new(File::open(&file)?);
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let frame: Frame = serde_json::from_str(&line).map_err(|e| {
+                anyhow::anyhow!("Invalid frame at {}:{}: {}", file.display(), index + 1, e)
+            })?;
+            if frame.direction != Direction::In {
+                continue;
+            }
+            match serde_json::from_str(&frame.text) {
+                Ok(op) => ops.push((frame.timestamp_ms / 1000, op)),
+                Err(e) => log::debug!("Skipping frame {}: {}", frame.text, e),
+            }
+        }
+    }
+    Ok(ops)
+}
+
+/// Swap fees as the recorded markets advertise them: the base fee of a
+/// market, charged in its base asset.
+#[derive(Clone, Default)]
+pub struct RecordedFees(Arc<Mutex<HashMap<u32, BigUint>>>);
+
+impl RecordedFees {
+    fn insert(&self, info: &MarketInfo) -> anyhow::Result<()> {
+        let fee = to_raw(info.base_fee.value()?, info.base_asset.decimals)?;
+        self.0.lock().unwrap().insert(info.base_asset.id, fee);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FeeSource for RecordedFees {
+    async fn swap_fee(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&token.0)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No recorded fee for token {}!", token.0))
+    }
+}
+
+/// Signer of backtests, where no taker asks us to fill.
+pub struct NoSigner;
+
+#[async_trait]
+impl OrderSigner for NoSigner {
+    async fn sign_order(&self, _params: OrderParams) -> anyhow::Result<ZksyncOrder> {
+        Err(anyhow::anyhow!("Backtests do not sign orders!"))
+    }
+}
+
+/// One simulated fill, as we traded it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimFill {
+    pub timestamp: Timestamp,
+    pub market: Market,
+    pub side: Side,
+    pub price: Decimal,
+    pub base_quantity: Amount,
+    /// Swap fee, in the quote asset
+    pub fee: Decimal,
+    /// Position of the market after this fill
+    pub position: Amount,
+    /// PnL of the market after this fill, marked at its price
+    pub pnl: Decimal,
+}
+
+/// Results of one market, in its quote asset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MarketReport {
+    pub fills: usize,
+    /// Base quantity traded
+    pub volume: Amount,
+    pub fees: Decimal,
+    pub position: Amount,
+    /// Largest position either way
+    pub max_position: Amount,
+    /// Net of fees, the position marked at the last trade
+    pub pnl: Decimal,
+    pub max_drawdown: Decimal,
+}
+
+/// Results of a backtest. Totals add the markets up, whatever their quote
+/// assets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub markets: BTreeMap<Market, MarketReport>,
+    pub fills: Vec<SimFill>,
+    pub max_drawdown: Decimal,
+}
+
+impl Report {
+    pub fn pnl(&self) -> Decimal {
+        self.markets.values().map(|m| m.pnl).sum()
+    }
+
+    pub fn fees(&self) -> Decimal {
+        self.markets.values().map(|m| m.fees).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (market, report) in &self.markets {
+            writeln!(
+                f,
+                "{}: {} fills, volume {}, fees {}, position {} (max {}), PnL {}, max drawdown {}",
+                market,
+                report.fills,
+                report.volume.normalize(),
+                report.fees.normalize(),
+                report.position.normalize(),
+                report.max_position.normalize(),
+                report.pnl.normalize(),
+                report.max_drawdown.normalize()
+            )?;
+        }
+        write!(
+            f,
+            "Total: {} fills, fees {}, PnL {}, max drawdown {}",
+            self.fills.len(),
+            self.fees().normalize(),
+            self.pnl().normalize(),
+            self.max_drawdown.normalize()
+        )
+    }
+}
+
+/// Writes the simulated fills as CSV, oldest first.
+pub fn write_csv(fills: &[SimFill], writer: impl io::Write) -> anyhow::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record([
+        "timestamp",
+        "market",
+        "side",
+        "price",
+        "base_quantity",
+        "fee",
+        "position",
+        "pnl",
+    ])?;
+    let amount = |amount: Decimal| amount.normalize().to_string();
+    for fill in fills {
+        let time = Utc
+            .timestamp_opt(fill.timestamp as i64, 0)
+            .single()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid timestamp {} of the {:?} fill on {} at {}!",
+                    fill.timestamp,
+                    fill.side,
+                    fill.market,
+                    fill.price
+                )
+            })?;
+        csv.write_record([
+            time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            fill.market.clone(),
+            match fill.side {
+                Side::Buy => "buy".to_owned(),
+                Side::Sell => "sell".to_owned(),
+            },
+            amount(fill.price),
+            amount(fill.base_quantity),
+            amount(fill.fee),
+            amount(fill.position),
+            amount(fill.pnl),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Takes what a trade at `price` reaches of our quotes, best levels first:
+/// bids at or above the price and asks at or below it, up to `quantity`
+/// when the size of the trade is known. Returns the side, price and size
+/// of each level hit.
+fn take(
+    quotes: &mut [Liquidity],
+    price: Decimal,
+    quantity: Option<Amount>,
+    now: Timestamp,
+) -> Vec<(Side, Decimal, Amount)> {
+    let mut hit: Vec<(usize, Decimal)> = quotes
+        .iter()
+        .enumerate()
+        .filter(|(_, level)| !matches!(level.expires, Some(expires) if expires <= now))
+        .filter_map(|(index, level)| {
+            let level_price = level.price.value().ok()?;
+            let crossed = match level.side {
+                Side::Buy => level_price >= price,
+                Side::Sell => level_price <= price,
+            };
+            crossed.then_some((index, level_price))
+        })
+        .collect();
+    // Highest bids and lowest asks first.
+    hit.sort_by(|(a_index, a), (b_index, b)| {
+        match quotes[*a_index].side {
+            Side::Buy => b.cmp(a),
+            Side::Sell => a.cmp(b),
+        }
+        .then(a_index.cmp(b_index))
+    });
+    let mut left = quantity;
+    let mut fills = Vec::new();
+    for (index, level_price) in hit {
+        let level = &mut quotes[index];
+        let size = match left {
+            Some(left) => level.base_quantity.min(left),
+            None => level.base_quantity,
+        };
+        if size <= Decimal::ZERO {
+            continue;
+        }
+        level.base_quantity -= size;
+        left = left.map(|left| left - size);
+        fills.push((level.side.clone(), level_price, size));
+    }
+    fills
+}
+
+/// A strategy running on one recorded market.
+struct SimMarket {
+    strategy: Box<dyn Strategy>,
+    ctx: StrategyContext,
+    ops: mpsc::UnboundedReceiver<Operation>,
+    outbox: Outbox,
+    position: watch::Sender<Amount>,
+    /// Our advertised liquidity, less what filled
+    quotes: Vec<Liquidity>,
+    /// Base fee of the market, in its base asset
+    base_fee: Decimal,
+    /// Last traded price, marking the position
+    mark: Option<Decimal>,
+    /// Quote asset spent and received, net of fees
+    cash: Decimal,
+    peak: Decimal,
+    last_tick: Timestamp,
+    report: MarketReport,
+}
+
+impl SimMarket {
+    fn pnl(&self) -> Decimal {
+        self.cash + self.report.position * self.mark.unwrap_or_default()
+    }
+
+    fn update(&mut self) {
+        let pnl = self.pnl();
+        self.report.pnl = pnl;
+        self.peak = self.peak.max(pnl);
+        self.report.max_drawdown = self.report.max_drawdown.max(self.peak - pnl);
+    }
+
+    /// Takes the liquidity the strategy sent since the last call.
+    fn collect_quotes(&mut self) {
+        for op in self.outbox.drain() {
+            match op {
+                Operation::Indicateliq2(args) if args.market == self.ctx.market() => {
+                    self.quotes = args.liquidity;
+                }
+                op => log::debug!("Backtest not sending {:?}", op),
+            }
+        }
+    }
+}
+
+/// Runs one strategy per recorded market over a capture.
+pub struct Backtest {
+    registry: StrategyRegistry,
+    strategy: String,
+    template: MarketMakerConfig,
+    markets: Vec<Market>,
+    fees: RecordedFees,
+    summaries: SummaryCache,
+    router: MarketRouter,
+    sims: BTreeMap<Market, SimMarket>,
+    seen_fills: HashSet<FillId>,
+    next_fill_id: FillId,
+    fills: Vec<SimFill>,
+    peak: Decimal,
+    max_drawdown: Decimal,
+}
+
+impl Backtest {
+    /// Builds `strategy` from `registry` for each market, with the market
+    /// maker settings of `template`.
+    pub fn new(registry: StrategyRegistry, strategy: &str, template: MarketMakerConfig) -> Self {
+        Self {
+            registry,
+            strategy: strategy.to_owned(),
+            template,
+            markets: Vec::new(),
+            fees: RecordedFees::default(),
+            summaries: SummaryCache::new(),
+            router: MarketRouter::default(),
+            sims: BTreeMap::new(),
+            seen_fills: HashSet::new(),
+            next_fill_id: 1,
+            fills: Vec::new(),
+            peak: Decimal::ZERO,
+            max_drawdown: Decimal::ZERO,
+        }
+    }
+
+    /// Only trades these markets instead of every recorded one.
+    pub fn with_markets(mut self, markets: Vec<Market>) -> Self {
+        self.markets = markets;
+        self
+    }
+
+    /// Records the fees of the markets into `fees`, for strategies
+    /// estimating them.
+    pub fn with_fees(mut self, fees: RecordedFees) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub async fn run(mut self, ops: Vec<(Timestamp, Operation)>) -> anyhow::Result<Report> {
+        for (now, op) in ops {
+            self.tick(now).await?;
+            match &op {
+                Operation::Marketinfo(args) => self.add_market(&args.market_info, now).await?,
+                Operation::Marketinfo2(args) => {
+                    for info in &args.market_infos {
+                        self.add_market(info, now).await?;
+                    }
+                }
+                Operation::Fills(args) => {
+                    for fill in &args.fills {
+                        if is_traded(fill) && self.seen_fills.insert(fill.id) {
+                            let price = fill.price.value()?;
+                            self.trade(&fill.market, price, Some(fill.base_quantity), now)
+                                .await?;
+                        }
+                    }
+                }
+                Operation::Lastprice(args) => {
+                    for update in &args.updates {
+                        match update.price.value() {
+                            Ok(price) => self.last_price(&update.market, price, now).await?,
+                            Err(e) => log::debug!("Ignoring last price: {}", e),
+                        }
+                    }
+                }
+                _ => (),
+            }
+            if Route::of(&op) == Route::MarketData {
+                self.summaries.apply(&op, now);
+                self.router.route(op);
+                self.dispatch(now).await?;
+            }
+        }
+        let mut report = Report {
+            fills: self.fills,
+            max_drawdown: self.max_drawdown,
+            ..Report::default()
+        };
+        for (market, mut sim) in self.sims {
+            sim.strategy.on_shutdown(&sim.ctx).await?;
+            report.markets.insert(market, sim.report);
+        }
+        Ok(report)
+    }
+
+    async fn add_market(&mut self, info: &MarketInfo, now: Timestamp) -> anyhow::Result<()> {
+        let wanted = self.markets.is_empty() || self.markets.contains(&info.alias);
+        if !wanted || self.sims.contains_key(&info.alias) {
+            return Ok(());
+        }
+        self.fees.insert(info)?;
+        let config = MarketMakerConfig {
+            market: info.alias.clone(),
+            ..self.template.clone()
+        };
+        let mut strategy = self.registry.build(&self.strategy, &config)?;
+        let (handle, outbox) = DispatcherHandle::offline();
+        let (position, position_rx) = watch::channel(Decimal::ZERO);
+        let ctx = StrategyContext::new(info.clone(), handle, self.summaries.clone())
+            .with_position(position_rx);
+        strategy.on_start(&ctx).await?;
+        let mut sim = SimMarket {
+            strategy,
+            ctx,
+            ops: self.router.add(info),
+            outbox,
+            position,
+            quotes: Vec::new(),
+            base_fee: info.base_fee.value()?,
+            mark: None,
+            cash: Decimal::ZERO,
+            peak: Decimal::ZERO,
+            last_tick: now,
+            report: MarketReport::default(),
+        };
+        sim.collect_quotes();
+        log::info!("Backtesting {} on {}", self.strategy, info.alias);
+        self.sims.insert(info.alias.clone(), sim);
+        Ok(())
+    }
+
+    /// Ticks the strategies once the capture's clock has moved on.
+    async fn tick(&mut self, now: Timestamp) -> anyhow::Result<()> {
+        for sim in self.sims.values_mut() {
+            if now < sim.last_tick + TICK_SECS {
+                continue;
+            }
+            sim.last_tick = now;
+            sim.ctx.book().prune(now);
+            sim.strategy.on_tick(&sim.ctx, now).await?;
+            sim.collect_quotes();
+        }
+        Ok(())
+    }
+
+    /// Hands the routed market data to the strategies.
+    async fn dispatch(&mut self, now: Timestamp) -> anyhow::Result<()> {
+        for sim in self.sims.values_mut() {
+            while let Ok(op) = sim.ops.try_recv() {
+                strategy::dispatch_at(sim.strategy.as_mut(), &sim.ctx, op, now).await?;
+            }
+            sim.collect_quotes();
+        }
+        Ok(())
+    }
+
+    /// A last price that moved counts as a trade of unknown size. The
+    /// first one only marks the position.
+    async fn last_price(
+        &mut self,
+        market: &str,
+        price: Decimal,
+        now: Timestamp,
+    ) -> anyhow::Result<()> {
+        let sim = match self.sims.get_mut(market) {
+            Some(sim) => sim,
+            None => return Ok(()),
+        };
+        match sim.mark {
+            Some(mark) if mark != price => self.trade(market, price, None, now).await,
+            _ => {
+                sim.mark = Some(price);
+                Ok(())
+            }
+        }
+    }
+
+    /// Fills our quotes of `market` that a trade went through.
+    async fn trade(
+        &mut self,
+        market: &str,
+        price: Decimal,
+        quantity: Option<Amount>,
+        now: Timestamp,
+    ) -> anyhow::Result<()> {
+        let sim = match self.sims.get_mut(market) {
+            Some(sim) => sim,
+            None => return Ok(()),
+        };
+        sim.mark = Some(price);
+        for (side, price, size) in take(&mut sim.quotes, price, quantity, now) {
+            let fee = sim.base_fee * price;
+            match side {
+                Side::Buy => {
+                    sim.cash -= price * size;
+                    sim.report.position += size;
+                }
+                Side::Sell => {
+                    sim.cash += price * size;
+                    sim.report.position -= size;
+                }
+            }
+            sim.cash -= fee;
+            sim.report.fills += 1;
+            sim.report.volume += size;
+            sim.report.fees += fee;
+            sim.report.max_position = sim.report.max_position.max(sim.report.position.abs());
+            let pnl = sim.cash + sim.report.position * price;
+            let _ = sim.position.send(sim.report.position);
+            log::debug!(
+                "Backtest fill on {}: {:?} {} @ {}",
+                market,
+                side,
+                size,
+                price
+            );
+            self.fills.push(SimFill {
+                timestamp: now,
+                market: market.to_owned(),
+                side: side.clone(),
+                price,
+                base_quantity: size,
+                fee,
+                position: sim.report.position,
+                pnl,
+            });
+            let fill = Fill {
+                chain_id: sim.ctx.market_info().zigzag_chain_id,
+                id: self.next_fill_id,
+                market: market.to_owned(),
+                side: side.opposite(),
+                price: price.into(),
+                base_quantity: size,
+                fill_status: OrderStatus::Filled,
+                tx_hash: None,
+                taker_user_id: TAKER.to_owned(),
+                maker_user_id: MAKER.to_owned(),
+                fee_amount: None,
+                fee_token: None,
+                timestamp: None,
+            };
+            self.next_fill_id += 1;
+            strategy::dispatch_at(
+                sim.strategy.as_mut(),
+                &sim.ctx,
+                Operation::Fillreceipt(fill),
+                now,
+            )
+            .await?;
+            sim.collect_quotes();
+        }
+        sim.update();
+        let total: Decimal = self.sims.values().map(SimMarket::pnl).sum();
+        self.peak = self.peak.max(total);
+        self.max_drawdown = self.max_drawdown.max(self.peak - total);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::market_maker::{self, MarketMaker};
+    use crate::zigzag::{fixtures, LastpriceArgs, MarketinfoArgs, PriceUpdate};
+    use rust_decimal_macros::dec;
+
+    fn level(side: Side, price: Decimal, size: Decimal, expires: Timestamp) -> Liquidity {
+        Liquidity {
+            side,
+            price: price.into(),
+            base_quantity: size,
+            expires: Some(expires),
+        }
+    }
+
+    fn last_price(price: Decimal) -> Operation {
+        Operation::Lastprice(LastpriceArgs {
+            updates: vec![PriceUpdate {
+                market: "ETH-USDC".into(),
+                price: price.into(),
+                price_change: Decimal::ZERO.into(),
+                quote_volume: None,
+                base_volume: None,
+            }],
+        })
+    }
+
+    fn backtest() -> Backtest {
+        let mut registry = StrategyRegistry::new();
+        registry.register("spread", |config| {
+            Ok(Box::new(MarketMaker::new(
+                config.clone(),
+                Arc::new(NoSigner),
+            )))
+        });
+        Backtest::new(registry, "spread", market_maker::tests::config())
+    }
+
+    #[test]
+    fn test_take() {
+        let mut quotes = vec![
+            level(Side::Buy, dec!(1990), dec!(1), 100),
+            level(Side::Buy, dec!(1998), dec!(0.5), 100),
+            level(Side::Buy, dec!(1999), dec!(1), 50),
+            level(Side::Sell, dec!(2002), dec!(0.5), 100),
+        ];
+        // Best bid first, the expired one left alone.
+        assert_eq!(
+            take(&mut quotes, dec!(1989), Some(dec!(0.7)), 60),
+            vec![
+                (Side::Buy, dec!(1998), dec!(0.5)),
+                (Side::Buy, dec!(1990), dec!(0.2))
+            ]
+        );
+        assert_eq!(quotes[0].base_quantity, dec!(0.8));
+        assert_eq!(quotes[1].base_quantity, Decimal::ZERO);
+        assert_eq!(
+            take(&mut quotes, dec!(2005), None, 60),
+            vec![(Side::Sell, dec!(2002), dec!(0.5))]
+        );
+        assert!(take(&mut quotes, dec!(2000), None, 60).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fill_through_quotes() {
+        let ops = vec![
+            (
+                1_000,
+                Operation::Marketinfo(MarketinfoArgs {
+                    market_info: fixtures::market_info("ETH-USDC", 0, 2),
+                }),
+            ),
+            // Quotes 1998 and 2002 around the last price.
+            (1_001, last_price(dec!(2000))),
+            (1_002, last_price(dec!(1997))),
+        ];
+        let report = backtest().run(ops.clone()).await.expect("run");
+        assert_eq!(report.fills.len(), 1);
+        let fill = &report.fills[0];
+        assert_eq!(
+            (fill.side.clone(), fill.price, fill.base_quantity),
+            (Side::Buy, dec!(1998), dec!(0.5))
+        );
+        // 0.0003 ETH of fee at 1998
+        assert_eq!(fill.fee, dec!(0.5994));
+        let eth = &report.markets["ETH-USDC"];
+        assert_eq!((eth.position, eth.max_position), (dec!(0.5), dec!(0.5)));
+        // Bought 0.5 at 1998, marked at 1997
+        assert_eq!(eth.pnl, dec!(-1.0994));
+        assert_eq!(eth.max_drawdown, dec!(1.0994));
+        assert_eq!(report.max_drawdown, dec!(1.0994));
+
+        // The same capture gives the same report.
+        assert_eq!(backtest().run(ops).await.expect("run"), report);
+    }
+
+    #[tokio::test]
+    async fn test_only_wanted_markets() {
+        let ops = vec![(
+            1_000,
+            Operation::Marketinfo(MarketinfoArgs {
+                market_info: fixtures::market_info("ETH-USDC", 0, 2),
+            }),
+        )];
+        let report = backtest()
+            .with_markets(vec!["WBTC-USDC".into()])
+            .run(ops)
+            .await
+            .expect("run");
+        assert!(report.markets.is_empty());
+    }
+
+    #[test]
+    fn test_load_and_csv() {
+        let dir = std::env::temp_dir().join(format!("zigzag-bots-backtest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create_dir_all");
+        let frame = |timestamp_ms, direction, text: &str| {
+            serde_json::to_string(&Frame {
+                timestamp_ms,
+                direction,
+                text: text.into(),
+            })
+            .expect("to_string")
+        };
+        let lastprice = r#"{"op":"lastprice","args":[[["ETH-USDC",2000,1]]]}"#;
+        let capture = [
+            frame(2_500, Direction::In, lastprice),
+            frame(2_600, Direction::Out, lastprice),
+            frame(2_700, Direction::In, "not an operation"),
+        ]
+        .join("\n");
+        std::fs::write(dir.join("b.jsonl"), capture).expect("write");
+        std::fs::write(dir.join("a.jsonl"), frame(1_000, Direction::In, lastprice)).expect("write");
+        std::fs::write(dir.join("notes.txt"), "not a capture").expect("write");
+        let ops = load(&dir).expect("load");
+        let _ = std::fs::remove_dir_all(&dir);
+        let times: Vec<_> = ops.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, vec![1, 2]);
+
+        let fill = SimFill {
+            timestamp: 1_660_000_000,
+            market: "ETH-USDC".into(),
+            side: Side::Sell,
+            price: dec!(2000.50),
+            base_quantity: dec!(0.5),
+            fee: dec!(0.6),
+            position: dec!(-0.5),
+            pnl: dec!(-0.6),
+        };
+        let mut csv = Vec::new();
+        write_csv(std::slice::from_ref(&fill), &mut csv).expect("write_csv");
+        assert_eq!(
+            String::from_utf8(csv).expect("utf8"),
+            "timestamp,market,side,price,base_quantity,fee,position,pnl\n\
+             2022-08-08T23:06:40Z,ETH-USDC,sell,2000.5,0.5,0.6,-0.5,-0.6\n"
+        );
+
+        // A timestamp out of chrono's range fails rather than panics.
+        let fill = SimFill {
+            timestamp: i64::MAX as u64,
+            ..fill
+        };
+        let err = write_csv(&[fill], Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("ETH-USDC"), "{}", err);
+    }
+}
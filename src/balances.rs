@@ -0,0 +1,309 @@
+/// Monitor of the committed zksync balances of the tokens we quote. Market
+/// makers shrink the sides the balances cannot cover, and low balances are
+/// reported once each time they drop below what the quotes need.
+use crate::marketdata::SummaryCache;
+use crate::metrics::Metrics;
+use crate::notify::{Event, Notifications};
+use crate::orders::to_units;
+use crate::zigzag::{Amount, Asset, Decimal, MarketInfo};
+use async_trait::async_trait;
+use num::BigUint;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use zksync::{
+    provider::Provider,
+    zksync_types::{BlockStatus, TokenId},
+    Wallet,
+};
+use zksync_eth_signer::EthereumSigner;
+
+/// Committed balances in human units, by token id.
+pub type Balances = HashMap<u32, Amount>;
+
+/// `[balances]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BalanceConfig {
+    pub poll_interval_secs: u64,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 30,
+        }
+    }
+}
+
+#[async_trait]
+pub trait BalanceSource: Send + Sync {
+    /// Committed balance of `token`, in raw units.
+    async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint>;
+}
+
+#[async_trait]
+impl<S, P> BalanceSource for Wallet<S, P>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        Ok(self.get_balance(BlockStatus::Committed, token).await?)
+    }
+}
+
+/// Sizes of the bid and ask the balances can honor at `price`: bids spend
+/// the quote asset, asks the base asset. Tokens without a known balance
+/// leave their side alone, empty balances pull it.
+pub fn clamp_sizes(
+    market_info: &MarketInfo,
+    balances: &Balances,
+    price: Decimal,
+    bid_size: Option<Amount>,
+    ask_size: Option<Amount>,
+) -> (Option<Amount>, Option<Amount>) {
+    let clamp = |size: Option<Amount>, available: Option<Amount>| {
+        let size = size?;
+        match available {
+            Some(available) if available < size => (available > Decimal::ZERO).then_some(available),
+            _ => Some(size),
+        }
+    };
+    let quote_balance = balances.get(&market_info.quote_asset.id);
+    let base_balance = balances.get(&market_info.base_asset.id);
+    (
+        clamp(
+            bid_size,
+            quote_balance
+                .filter(|_| price > Decimal::ZERO)
+                .map(|balance| balance / price),
+        ),
+        clamp(ask_size, base_balance.copied()),
+    )
+}
+
+/// Amount of each token needed to quote `quote_size` on both sides of
+/// every market, at the given prices. Markets without a price only count
+/// their base asset.
+pub fn needed(
+    markets: &[MarketInfo],
+    quote_size: Amount,
+    price: impl Fn(&str) -> Option<Decimal>,
+) -> HashMap<u32, Amount> {
+    let mut needed = HashMap::new();
+    for market_info in markets {
+        *needed.entry(market_info.base_asset.id).or_default() += quote_size;
+        if let Some(price) = price(&market_info.alias) {
+            *needed.entry(market_info.quote_asset.id).or_default() += quote_size * price;
+        }
+    }
+    needed
+}
+
+pub struct BalanceMonitor {
+    source: Arc<dyn BalanceSource>,
+    markets: Vec<MarketInfo>,
+    quote_size: Amount,
+    summaries: SummaryCache,
+    metrics: Arc<Metrics>,
+    notifications: Notifications,
+    balances: watch::Sender<Balances>,
+    /// Tokens reported low, until they recover
+    low: HashSet<u32>,
+}
+
+impl BalanceMonitor {
+    pub fn new(
+        source: Arc<dyn BalanceSource>,
+        markets: Vec<MarketInfo>,
+        quote_size: Amount,
+        summaries: SummaryCache,
+        metrics: Arc<Metrics>,
+        notifications: Notifications,
+    ) -> (Self, watch::Receiver<Balances>) {
+        let (balances, receiver) = watch::channel(Balances::new());
+        let monitor = Self {
+            source,
+            markets,
+            quote_size,
+            summaries,
+            metrics,
+            notifications,
+            balances,
+            low: HashSet::new(),
+        };
+        (monitor, receiver)
+    }
+
+    /// Polls the balances every `interval` until `shutdown` flips.
+    pub async fn run(mut self, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.poll().await,
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    async fn poll(&mut self) {
+        let mut balances = self.balances.borrow().clone();
+        for asset in self.assets() {
+            match self.source.committed_balance(TokenId(asset.id)).await {
+                Ok(raw) => match to_units(&raw, asset.decimals) {
+                    Ok(balance) => {
+                        self.metrics
+                            .set_gauge(&format!("balance_{}", asset.symbol), balance);
+                        balances.insert(asset.id, balance);
+                    }
+                    Err(e) => log::warn!("Invalid {} balance: {}", asset.symbol, e),
+                },
+                Err(e) => log::warn!("Polling the {} balance failed: {}", asset.symbol, e),
+            }
+        }
+        self.check(&balances);
+        self.balances.send_replace(balances);
+    }
+
+    /// Every token of the markets, once.
+    fn assets(&self) -> Vec<Asset> {
+        let mut seen = HashSet::new();
+        self.markets
+            .iter()
+            .flat_map(|info| [info.base_asset.clone(), info.quote_asset.clone()])
+            .filter(|asset| seen.insert(asset.id))
+            .collect()
+    }
+
+    /// Reports tokens whose balance just dropped below what the quotes need.
+    fn check(&mut self, balances: &Balances) {
+        let summaries = &self.summaries;
+        let needed = needed(&self.markets, self.quote_size, |market| {
+            summaries
+                .reference(market)
+                .map(|reference| reference.price)
+                .or_else(|| summaries.mid(market))
+        });
+        for asset in self.assets() {
+            let (balance, needed) = match (balances.get(&asset.id), needed.get(&asset.id)) {
+                (Some(balance), Some(needed)) => (*balance, *needed),
+                _ => continue,
+            };
+            if balance >= needed {
+                if self.low.remove(&asset.id) {
+                    log::info!("{} balance of {} is enough again", asset.symbol, balance);
+                }
+            } else if self.low.insert(asset.id) {
+                log::warn!(
+                    "Low {} balance: {}, quotes need {}",
+                    asset.symbol,
+                    balance,
+                    needed
+                );
+                self.notifications.notify(Event::LowBalance {
+                    token: asset.symbol.clone(),
+                    balance,
+                    needed,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_clamp_sizes() {
+        let info = fixtures::market_info("ETH-USDC", 0, 2);
+        let size = Some(dec!(0.5));
+        let clamp = |balances: &[(u32, Amount)]| {
+            let balances = balances.iter().copied().collect();
+            clamp_sizes(&info, &balances, dec!(2000), size, size)
+        };
+        // Unknown balances leave the quotes alone.
+        assert_eq!(clamp(&[]), (size, size));
+        assert_eq!(clamp(&[(0, dec!(3)), (2, dec!(5000))]), (size, size));
+        // 600 USDC buy 0.3 ETH at 2000.
+        assert_eq!(
+            clamp(&[(0, dec!(0.2)), (2, dec!(600))]),
+            (Some(dec!(0.3)), Some(dec!(0.2)))
+        );
+        assert_eq!(clamp(&[(0, dec!(0)), (2, dec!(0))]), (None, None));
+        // Sides already pulled stay pulled.
+        let balances = [(0, dec!(3))].into_iter().collect();
+        assert_eq!(
+            clamp_sizes(&info, &balances, dec!(2000), None, size),
+            (None, size)
+        );
+    }
+
+    #[test]
+    fn test_low_balance_reported_once() {
+        let markets = vec![
+            fixtures::market_info("ETH-USDC", 0, 2),
+            fixtures::market_info("WBTC-USDC", 5, 2),
+        ];
+        let summaries = SummaryCache::new();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let notifications = Notifications::from_senders(vec![sender]);
+        let (mut monitor, _balances) = BalanceMonitor::new(
+            Arc::new(NoBalances),
+            markets,
+            dec!(0.5),
+            summaries,
+            Arc::new(Metrics::new()),
+            notifications,
+        );
+        let needed = needed(&monitor.markets, dec!(0.5), |market| match market {
+            "ETH-USDC" => Some(dec!(2000)),
+            _ => None,
+        });
+        assert_eq!(needed[&0], dec!(0.5));
+        assert_eq!(needed[&2], dec!(1000));
+        assert_eq!(needed[&5], dec!(0.5));
+
+        // No price yet: only base assets are checked.
+        let balances: Balances = [(0, dec!(0.4)), (2, dec!(10)), (5, dec!(1))]
+            .into_iter()
+            .collect();
+        monitor.check(&balances);
+        assert_eq!(
+            receiver.try_recv().ok(),
+            Some(Event::LowBalance {
+                token: "ETH".into(),
+                balance: dec!(0.4),
+                needed: dec!(0.5),
+            })
+        );
+        monitor.check(&balances);
+        assert!(receiver.try_recv().is_err(), "reported once");
+
+        // Recovered, then low again.
+        let mut balances = balances;
+        balances.insert(0, dec!(1));
+        monitor.check(&balances);
+        balances.insert(0, dec!(0.1));
+        monitor.check(&balances);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::LowBalance { balance, .. }) if balance == dec!(0.1)
+        ));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    struct NoBalances;
+
+    #[async_trait]
+    impl BalanceSource for NoBalances {
+        async fn committed_balance(&self, _token: TokenId) -> anyhow::Result<BigUint> {
+            Err(anyhow::anyhow!("no balances in tests"))
+        }
+    }
+}
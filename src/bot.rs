@@ -0,0 +1,2474 @@
+/// Runtime of the `zigzag-bots` binary: connects to zigzag and zksync, and
+/// runs the market makers and their feeds, or one of the subcommands.
+use crate::activation;
+use crate::backtest::{self, Backtest, NoSigner, RecordedFees};
+use crate::balances::{BalanceMonitor, Balances};
+use crate::capture::{Recording, Replay};
+use crate::cli::{
+    ArgNetwork, Args, BacktestCommand, Command, ExecAlgo, ExecCommand, ExportFillsCommand,
+    MarketsCommand, QuoteCommand, ReceiptCommand, TwapCommand, VolumeCommand, WithdrawCommand,
+};
+use crate::client::{Transport, ZigzagClient, DEFAULT_REQUEST_TIMEOUT};
+use crate::clock::SyncedClock;
+use crate::config::{Config, ConfigFile, MarketMakerSettings, DEFAULT_WALLET};
+use crate::connection::{Backoff, Connection, Heartbeat};
+use crate::dedup::Dedup;
+use crate::deposit::{AutoDeposit, EthereumL1};
+use crate::dispatcher::{
+    self, Dispatcher, DispatcherHandle, MarketRouter, Receivers, SessionReceivers,
+};
+use crate::evm::{Domain, EvmOrderParams, EvmSigner};
+use crate::export::FillFilter;
+use crate::feeds::{chainlink::RpcEthCall, FeedsConfig, Source};
+use crate::fees::{self, ChangePubKeyFee, FeeConfig, FeeEstimator};
+use crate::hedge::{self, binance::Binance, DryRun, Hedge, Hedger};
+use crate::killswitch::KillSwitch;
+use crate::marketdata::SummaryCache;
+use crate::metrics::Metrics;
+use crate::notify::{self, Event, Notifications};
+use crate::orderbook::Snapshot;
+use crate::orders::{build_order, to_units, OrderSigner, Signer};
+use crate::portfolio::{FillTracker, Settlement};
+use crate::ratelimit::RateLimiter;
+use crate::receipt::{self, ReceiptError, ReceiptKind};
+use crate::reconcile::{self, OpenOrders};
+use crate::reload::{self, ReloadSignal};
+use crate::repl::Repl;
+use crate::rfq::{QuoteError, RfqConfig};
+use crate::risk::RiskEngine;
+use crate::rpc::{self, Failover};
+use crate::signals::triangle::{Leg, Triangle, TriangleConfig};
+use crate::signals::Volatility;
+use crate::signals::VolatilityConfig;
+use crate::snapshot::SnapshotStore;
+use crate::status::{StatusBoard, StatusServer};
+use crate::stops::{Stops, StopsConfig};
+use crate::storage::{Recorder, Storage};
+use crate::strategy::{
+    self, MarketMaker, MarketMakerConfig, SkewConfig, Strategy, StrategyContext, StrategyRegistry,
+};
+use crate::tokens::{self, Registry};
+use crate::tui::{Dashboard, Input, MarketView, Tui};
+use crate::twap::{Twap, TwapConfig};
+use crate::valuation::{Valuation, Valuer};
+use crate::volume::{VolumeFilter, VolumeReport};
+use crate::withdraw::WithdrawAmount;
+use crate::zigzag::{
+    unix_timestamp, Amount, Asset, CancelallArgs, ChainId, Decimal, Exchange, ExchangeOrder,
+    FillsArgs, Liquidity, Market, MarketInfo, MarketinfoArgs, Operation, OrderId, OrderStatus,
+    RemainingOrError, RequestquoteArgs, Side, SubscribemarketArgs, Timestamp, UserId,
+};
+use crate::{display, export, feeds, logging, markets, proxy, rfq, volume, withdraw};
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use futures::future;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use zeroize::Zeroize;
+use zksync::{
+    provider::{Provider, RpcProvider},
+    zksync_types::{BlockStatus, TokenId, TxFeeTypes, H256},
+    Wallet, WalletCredentials,
+};
+use zksync_eth_signer::{EthereumSigner, PrivateKeySigner};
+
+/// Runs the command line: the market makers until shutdown, or one of the
+/// subcommands.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let mut config = load_config(&args)?;
+    let replay = match &args.command {
+        Some(Command::Replay(command)) => Some(command),
+        _ => None,
+    };
+    if replay.is_some() {
+        // Replays are dry runs, nothing reaches zksync either.
+        log::info!("Replaying, no order or deposit will be sent!");
+        config.auto_deposit = None;
+        config.cancel_on_exit = false;
+        if config.quoting_wallets().len() > 1 {
+            return Err(anyhow::anyhow!(
+                "Replays cover a single session, please quote every market from one wallet!"
+            ));
+        }
+    }
+    if config.network == ArgNetwork::Rinkeby {
+        log::warn!("Rinkeby has been sunset, please switch to --network goerli!");
+    }
+    if args.notify_test {
+        return notify_test(&config.notify).await;
+    }
+    if args.print_config {
+        print!("{}", config.describe_markets());
+        return Ok(());
+    }
+    for market in &config.markets {
+        log::info!("{}: {:?}", market, config.market_settings(market));
+    }
+    if let Some(Command::Backtest(command)) = &args.command {
+        return run_backtest(command, &config).await;
+    }
+
+    let backoff = Backoff::new(
+        Duration::from_millis(config.reconnect_min_delay_ms),
+        Duration::from_millis(config.reconnect_max_delay_ms),
+    );
+    let heartbeat = Heartbeat {
+        interval: Duration::from_secs(config.ping_interval_secs),
+        timeout: Duration::from_secs(config.pong_timeout_secs),
+    };
+    if let Some(Command::Volume(command)) = &args.command {
+        return run_volume(command, &config, backoff, heartbeat).await;
+    }
+    if let Some(Command::Markets(command)) = &args.command {
+        return run_markets(command, &config, backoff, heartbeat).await;
+    }
+    if let Some(Command::Order(command)) = &args.command {
+        return run_receipt(command, ReceiptKind::Order, &config, backoff, heartbeat).await;
+    }
+    if let Some(Command::Fill(command)) = &args.command {
+        return run_receipt(command, ReceiptKind::Fill, &config, backoff, heartbeat).await;
+    }
+    if Exchange::of(config.zigzag_chain_id) == Exchange::Evm {
+        return exit_on_quote_error(run_evm(&args, &config, backoff, heartbeat).await);
+    }
+
+    // The session opened first: the taker's for a quote, and when market
+    // making the one of the first quoting wallet, which also receives the
+    // market data of every market.
+    let primary = match &args.command {
+        Some(Command::Quote(command)) => config.take_wallet(&command.market.to_string()),
+        Some(Command::Exec(ExecCommand {
+            algo: ExecAlgo::Twap(command),
+        })) => config.take_wallet(&command.market.to_string()),
+        None | Some(Command::Replay(_)) => config
+            .quoting_wallets()
+            .first()
+            .copied()
+            .unwrap_or(DEFAULT_WALLET),
+        Some(_) => DEFAULT_WALLET,
+    }
+    .to_owned();
+    let rpc = activated_wallet(&config, &primary).await?;
+    let wallet = rpc.current();
+
+    if let Some(Command::ExportFills(command)) = &args.command {
+        let user_id = user_id(&wallet)?;
+        return export_fills(command, config.db_path.as_deref(), &user_id);
+    }
+
+    let provider_url = config.provider_url.clone().ok_or_else(|| {
+        anyhow::anyhow!("Please specify ethereum provider URL via ETH_PROVIDER_URL environment variable, the config file, or a cli argument!")
+    })?.trim().to_owned();
+
+    let tokens = Registry::load(&rpc).await?;
+    // Market making activates the account once the market infos tell which
+    // tokens are enabled for fees.
+    if replay.is_none() && args.command.is_some() {
+        set_signing_key(&wallet, &config, &tokens, &[]).await?;
+    }
+
+    if let Some(Command::Withdraw(command)) = &args.command {
+        return run_withdraw(command, &wallet, &tokens).await;
+    }
+
+    let zigzag_chainid = config.zigzag_chain_id;
+
+    let (notifications, mut notifiers) = Notifications::spawn(&config.notify);
+    let mut replay_finished = None;
+    let (transport, connection_status): (Box<dyn Transport>, _) = match replay {
+        Some(command) => {
+            let replay = Replay::open(&command.path, command.speed)?;
+            replay_finished = Some(replay.finished());
+            // A replay never disconnects.
+            let (_, status) = watch::channel(true);
+            (Box::new(replay), status)
+        }
+        None => {
+            let connection = Connection::connect_through(
+                &config.zigzag_url,
+                config.proxy.as_ref(),
+                backoff.clone(),
+                heartbeat,
+            )
+            .await?;
+            log::info!("Connected to zigzag!");
+            let status = connection.status();
+            if !config.notify.is_empty() {
+                tokio::spawn(notify::watch_connection(
+                    status.clone(),
+                    Duration::from_secs(config.notify.disconnected_secs),
+                    notifications.clone(),
+                ));
+            }
+            // Only the first session is recorded, it carries the market
+            // data.
+            match &args.record {
+                Some(path) => (
+                    Box::new(Recording::append(connection, path)?.redact(args.redact_record)),
+                    status,
+                ),
+                None => (Box::new(connection), status),
+            }
+        }
+    };
+
+    let mut sent = None;
+    let mut sent_tx = None;
+    if args.tui || args.status_addr.is_some() || config.snapshot_dir.is_some() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        sent_tx = Some(tx);
+        sent = Some(rx);
+    }
+    let (account, mut receivers, dispatcher) = Account::open(
+        &primary,
+        rpc,
+        transport,
+        connection_status.clone(),
+        &config,
+        args.resume,
+        sent_tx.as_ref(),
+    )
+    .await?;
+
+    // One backend, one clock for every account.
+    let clock = SyncedClock::new(config.clock.clone());
+    if let Some(Command::Repl(command)) = &args.command {
+        return Repl::new(
+            zigzag_chainid,
+            account.user_id.clone(),
+            account.handle.clone(),
+            account.signer.clone(),
+            account.wallet.clone(),
+        )
+        .with_yes(command.yes)
+        .with_order_expires_secs(command.order_expires_secs)
+        .with_clock(clock)
+        .run(receivers)
+        .await;
+    }
+    if let Some(Command::Quote(command)) = &args.command {
+        let result = run_quote(
+            command,
+            zigzag_chainid,
+            &account.handle,
+            &mut receivers,
+            account.signer.as_ref(),
+            &clock,
+        )
+        .await;
+        return exit_on_quote_error(result);
+    }
+    if let Some(Command::Exec(ExecCommand {
+        algo: ExecAlgo::Twap(command),
+    })) = &args.command
+    {
+        return run_twap(
+            command,
+            zigzag_chainid,
+            &account.handle,
+            &mut receivers,
+            account.signer.as_ref(),
+            &clock,
+        )
+        .await;
+    }
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let token_refresh = tokio::spawn(tokens.clone().run(
+        account.rpc.clone(),
+        tokens::REFRESH_INTERVAL,
+        shutdown_rx.clone(),
+    ));
+    let mut market_makers = Vec::new();
+    let summaries = SummaryCache::new();
+    let mut feeds = Vec::new();
+    let http_client = proxy::http_client(config.proxy.as_ref())?;
+    if let Some(binance) = config.feeds.binance.clone() {
+        feeds.push(tokio::spawn(feeds::binance::run(
+            binance,
+            config.proxy.clone(),
+            summaries.clone(),
+            backoff.clone(),
+            shutdown_rx.clone(),
+        )));
+    }
+    if let Some(chainlink) = config.feeds.chainlink.clone() {
+        let eth = RpcEthCall::new(provider_url.clone()).with_client(http_client.clone());
+        let mut markets = feeds::chainlink::read_decimals(&eth, &chainlink).await?;
+        // Markets Binance covers keep its fresher reference.
+        let chainlink_markets = config.feeds.markets_of(Source::Chainlink, &config.markets);
+        markets.retain(|(market, _)| chainlink_markets.contains(market));
+        feeds.push(tokio::spawn(feeds::chainlink::run(
+            eth,
+            chainlink,
+            markets,
+            summaries.clone(),
+            shutdown_rx.clone(),
+        )));
+    }
+    if let Some(coingecko) = config.feeds.coingecko.clone() {
+        // Fail at startup on ids CoinGecko does not know.
+        let prices = feeds::coingecko::fetch(&http_client, &coingecko).await?;
+        coingecko.check_ids(&prices)?;
+        let markets = config.feeds.markets_of(Source::CoinGecko, &config.markets);
+        feeds.push(tokio::spawn(feeds::coingecko::run(
+            http_client.clone(),
+            coingecko,
+            markets,
+            summaries.clone(),
+            shutdown_rx.clone(),
+        )));
+    }
+    let subscribed = config.subscribed_markets();
+    for market in &subscribed {
+        account
+            .handle
+            .send(Operation::Subscribemarket(SubscribemarketArgs {
+                chain_id: zigzag_chainid,
+                market: market.clone(),
+            }))?;
+    }
+    let mut positions = HashMap::new();
+    let mut price_decimals = HashMap::new();
+    let mut cooldowns = HashMap::new();
+    // Market maker settings of each market, updated on reloads
+    let mut strategy_configs = HashMap::new();
+    let (pause_tx, pause_rx) = watch::channel(false);
+    let status = StatusBoard::new(&config.markets);
+    let mut dashboard = Dashboard::default();
+    let market_infos =
+        wait_for_market_infos(&mut receivers.other, &subscribed, DEFAULT_REQUEST_TIMEOUT).await?;
+    config.check_markets(&market_infos)?;
+    let mut triangles = config
+        .triangles
+        .iter()
+        .map(|triangle| Triangle::new(triangle.clone(), &market_infos))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // Held while a triangle's legs are taken, one cycle at a time.
+    let triangle_locks: Vec<_> = triangles
+        .iter()
+        .map(|_| Arc::new(tokio::sync::Mutex::new(())))
+        .collect();
+    let mut books: HashMap<Market, Snapshot> = HashMap::new();
+    for market_info in &market_infos {
+        for problem in tokens.check_market(market_info) {
+            log::warn!("{}: {}", market_info.alias, problem);
+        }
+    }
+    let assets: Vec<_> = market_infos
+        .iter()
+        .flat_map(|info| [info.base_asset.clone(), info.quote_asset.clone()])
+        .collect();
+    if replay.is_none() {
+        set_signing_key(&account.wallet, &config, &tokens, &assets).await?;
+    }
+
+    let mut sessions = SessionReceivers::default();
+    sessions.add(account.user_id.clone(), receivers);
+    let mut accounts = vec![account];
+    let mut dispatchers = vec![dispatcher];
+    for name in config.quoting_wallets() {
+        if name == primary {
+            continue;
+        }
+        let rpc = activated_wallet(&config, name).await?;
+        set_signing_key(&rpc.current(), &config, &tokens, &assets).await?;
+        let connection = Connection::connect_through(
+            &config.zigzag_url,
+            config.proxy.as_ref(),
+            backoff.clone(),
+            heartbeat,
+        )
+        .await?;
+        log::info!("Connected to zigzag for the {} wallet!", name);
+        let connection_status = connection.status();
+        let (account, receivers, dispatcher) = Account::open(
+            name,
+            rpc,
+            Box::new(connection),
+            connection_status,
+            &config,
+            args.resume,
+            sent_tx.as_ref(),
+        )
+        .await?;
+        if let Some(other) = accounts.iter().find(|a| a.user_id == account.user_id) {
+            return Err(anyhow::anyhow!(
+                "Wallets {} and {} are the same account {}!",
+                other.name,
+                name,
+                account.user_id
+            ));
+        }
+        // Liquidity is advertised and fill requests answered on the session
+        // of the wallet quoting, its market data is dropped for that of the
+        // first session.
+        for market in &account.markets {
+            account
+                .handle
+                .send(Operation::Subscribemarket(SubscribemarketArgs {
+                    chain_id: zigzag_chainid,
+                    market: market.clone(),
+                }))?;
+        }
+        sessions.add(account.user_id.clone(), receivers);
+        accounts.push(account);
+        dispatchers.push(dispatcher);
+    }
+    for account in &accounts {
+        status.refresh(&account.markets, &account.fills, &account.open_orders);
+    }
+    let (feeds_tx, feeds_rx) = watch::channel(config.feeds.clone());
+    if let Some(addr) = args.status_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        log::info!("Serving status on http://{}", addr);
+        let mut server = StatusServer::new(status.clone(), summaries.clone(), feeds_rx.clone());
+        for account in &accounts {
+            server = server
+                .with_metrics(&account.name, account.metrics.clone())
+                .with_rpc(&account.name, account.rpc.clone());
+        }
+        tokio::spawn(server.serve(listener));
+        tokio::spawn(status.clone().watch_connection(connection_status.clone()));
+    }
+
+    let mut balance_monitors = Vec::new();
+    let mut auto_deposits = Vec::new();
+    let volatility = volatility(&config);
+    for account in &mut accounts {
+        let infos: Vec<_> = market_infos
+            .iter()
+            .filter(|info| account.markets.contains(&info.alias))
+            .cloned()
+            .collect();
+        let mut balances = None;
+        if let Some(balance_config) = &config.balances {
+            let (monitor, receiver) = BalanceMonitor::new(
+                account.rpc.clone(),
+                infos.clone(),
+                account
+                    .markets
+                    .iter()
+                    .map(|market| config.market_settings(market).max_side_size())
+                    .max()
+                    .unwrap_or_default(),
+                summaries.clone(),
+                account.metrics.clone(),
+                notifications.clone(),
+            );
+            balance_monitors.push(tokio::spawn(monitor.run(
+                Duration::from_secs(balance_config.poll_interval_secs),
+                shutdown_rx.clone(),
+            )));
+            account.balances = Some(receiver.clone());
+            balances = Some(receiver);
+        }
+        if let Some(deposit_config) = config.auto_deposit.clone() {
+            let ethereum = account.wallet.ethereum(&provider_url).await?;
+            let assets: Vec<_> = infos
+                .iter()
+                .flat_map(|info| [info.base_asset.clone(), info.quote_asset.clone()])
+                .collect();
+            let deposits = AutoDeposit::new(
+                deposit_config,
+                Arc::new(EthereumL1::new(ethereum, account.wallet.address)),
+                account.wallet.clone(),
+                &assets,
+            );
+            auto_deposits.push(tokio::spawn(deposits.run(shutdown_rx.clone())));
+        }
+        let fees =
+            fee_config(&config).map(|fees| FeeEstimator::new(account.rpc.clone(), fees.ttl_secs));
+        let market_maker = market_maker_factory(
+            account.signer.clone(),
+            fees,
+            volatility.clone(),
+            account.metrics.clone(),
+            Some(CooldownReporting {
+                user_id: account.user_id.clone(),
+                notifications: notifications.clone(),
+                status: status.clone(),
+            }),
+        );
+        let mut registry = StrategyRegistry::new();
+        registry.register("spread", market_maker.clone());
+        registry.register("avellaneda", market_maker);
+        for market_info in infos {
+            let settings = config.market_settings(&market_info.alias);
+            let mm_config = market_maker_config(settings, &market_info.alias, &config.feeds);
+            let strategy = registry.build(&settings.strategy, &mm_config)?;
+            let (config_tx, config_rx) = watch::channel(mm_config);
+            strategy_configs.insert(market_info.alias.clone(), config_tx);
+            let ops = account.router.add(&market_info);
+            let (position_tx, position_rx) =
+                watch::channel(account.fills.position(&market_info.alias));
+            positions.insert(market_info.alias.clone(), position_tx);
+            price_decimals.insert(
+                market_info.alias.clone(),
+                market_info.price_precision_decimal,
+            );
+            let (cooldown_tx, cooldown_rx) = watch::channel(false);
+            cooldowns.insert(market_info.alias.clone(), cooldown_tx);
+            let mut ctx =
+                StrategyContext::new(market_info, account.handle.clone(), summaries.clone())
+                    .with_position(position_rx)
+                    .with_connection(account.connection.clone())
+                    .with_pause(pause_rx.clone())
+                    .with_cooldown(cooldown_rx)
+                    .with_clock(clock.clone())
+                    .with_config(config_rx);
+            dashboard.add_market(MarketView::new(
+                ctx.market_info().alias.clone(),
+                ctx.book().clone(),
+            ));
+            if let Some(balances) = &balances {
+                ctx = ctx.with_balances(balances.clone());
+            }
+            let market = ctx.market_info().alias.clone();
+            market_makers.push(tokio::spawn(logging::in_market(
+                market,
+                strategy::run(strategy, ctx, ops, shutdown_rx.clone()),
+            )));
+        }
+    }
+
+    let mut hedge_tx = None;
+    let mut hedger_task = None;
+    if let Some(hedge_config) = config.hedge.clone() {
+        let hedger: Box<dyn Hedger> = match &hedge_config.binance {
+            Some(binance) if !hedge_config.dry_run => {
+                Box::new(Binance::new(binance.clone(), http_client.clone()))
+            }
+            _ => Box::new(DryRun),
+        };
+        log::info!(
+            "Hedging {} market(s) on {}",
+            hedge_config.markets.len(),
+            hedger.name()
+        );
+        let (tx, rx) = mpsc::unbounded_channel();
+        hedge_tx = Some(tx);
+        hedger_task = Some(tokio::spawn(hedge::run(
+            Hedge::new(hedge_config),
+            hedger,
+            rx,
+            accounts[0].metrics.clone(),
+            notifications.clone(),
+            shutdown_rx.clone(),
+        )));
+    }
+
+    let shutdown = async move {
+        match replay_finished {
+            // Stop at the end of the capture too.
+            Some(mut finished) => tokio::select! {
+                result = shutdown_signal() => result,
+                Ok(()) = finished.changed() => Ok(()),
+            },
+            None => shutdown_signal().await,
+        }
+    };
+    tokio::pin!(shutdown);
+
+    let mut kill_switch = config.kill_switch.clone().map(KillSwitch::new);
+    let mut stops = config.stops.clone().map(Stops::new);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut halted_ticker = tokio::time::interval(HALTED_LOG_INTERVAL);
+    let expiring = config
+        .markets
+        .iter()
+        .any(|market| config.market_settings(market).order_ttl_secs.is_some());
+    let mut ttl_ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut snapshot_ticker = tokio::time::interval(Duration::from_secs(config.snapshot_secs));
+    let mut latency_ticker =
+        tokio::time::interval(Duration::from_secs(config.latency_log_secs.max(1)));
+    let valuer = Valuer::new(&config.valuation_currency, market_infos.clone());
+    let mut valuation_ticker = tokio::time::interval(VALUATION_INTERVAL);
+    let mut valuation_log_ticker =
+        tokio::time::interval(Duration::from_secs(config.valuation_log_secs.max(1)));
+    let mut settlement_ticker = tokio::time::interval(SETTLEMENT_CHECK_INTERVAL);
+    // Replays run on the config they started with.
+    let mut reload_signal = match (&args.config, replay) {
+        (Some(_), None) => ReloadSignal::new()?,
+        _ => ReloadSignal::default(),
+    };
+    let mut tui = match args.tui {
+        true => Some(Tui::start()?),
+        false => None,
+    };
+
+    // Below is the playground now
+    loop {
+        if let Some(tui) = tui.as_mut() {
+            refresh_dashboard(&mut dashboard, &accounts, &summaries, &config.feeds);
+            tui.draw(&dashboard, unix_timestamp())?;
+        }
+        let watching = matches!(&kill_switch, Some(ks) if ks.tripped().is_none());
+        // Once halted, positions are left alone.
+        let stopping = stops.is_some() && !market_makers.is_empty();
+        let mut tripped = None;
+        tokio::select! {
+            _ = ticker.tick(), if watching || stopping => {
+                let now = unix_timestamp();
+                for market in &config.markets {
+                    let price = summaries
+                        .reference(market)
+                        .map(|reference| reference.price)
+                        .or_else(|| summaries.mid(market));
+                    if let (Some(ks), Some(price)) = (kill_switch.as_mut(), price) {
+                        tripped = tripped.or(ks.on_price(market, price, now));
+                    }
+                    let stops = match stops.as_mut().filter(|_| stopping) {
+                        Some(stops) => stops,
+                        None => continue,
+                    };
+                    let account = accounts.iter().find(|account| account.markets.contains(market));
+                    if let (Some(account), Some(price)) = (account, price) {
+                        let trigger = stops.on_price(
+                            market,
+                            price,
+                            account.fills.position(market),
+                            account.fills.avg_entry_price(market),
+                            now,
+                        );
+                        let market_info = market_infos.iter().find(|info| info.alias == *market);
+                        if let (Some(trigger), Some(market_info)) = (trigger, market_info) {
+                            log::warn!("Triggered {}, flattening", trigger);
+                            dashboard.log(format!("Triggered {}", trigger));
+                            notifications.notify(Event::Stop {
+                                trigger: trigger.to_string(),
+                            });
+                            tokio::spawn(logging::in_market(
+                                market.clone(),
+                                flatten_position(
+                                    stops.config().clone(),
+                                    market_info.clone(),
+                                    account.handle.clone(),
+                                    account.signer.clone(),
+                                    positions[market].subscribe(),
+                                    summaries.clone(),
+                                    notifications.clone(),
+                                    clock.clone(),
+                                ),
+                            ));
+                        }
+                    }
+                    if let Some(cooldown_tx) = cooldowns.get(market) {
+                        let cooling = stops.in_cooldown(market, now);
+                        cooldown_tx.send_if_modified(|c| std::mem::replace(c, cooling) != cooling);
+                    }
+                }
+            }
+            _ = ttl_ticker.tick(), if expiring => {
+                for account in &mut accounts {
+                    let expired = account.open_orders.expired(
+                        |market| config.market_settings(market).order_ttl_secs,
+                        clock.now(),
+                    );
+                    if expired.is_empty() {
+                        continue;
+                    }
+                    log::info!(
+                        "Canceling orders of the {} wallet past their time-to-live: {:?}",
+                        account.name,
+                        expired
+                    );
+                    if config.ttl_cancel_all {
+                        account.handle.send(Operation::Cancelall(CancelallArgs {
+                            chain_id: zigzag_chainid,
+                            user_id: account.user_id.clone(),
+                        }))?;
+                    } else {
+                        for cancel in account.open_orders.cancels(&expired) {
+                            account.handle.send(cancel)?;
+                        }
+                    }
+                }
+            }
+            _ = snapshot_ticker.tick(), if config.snapshot_dir.is_some() => {
+                for account in &accounts {
+                    account.save_snapshot();
+                }
+            }
+            _ = latency_ticker.tick(), if config.latency_log_secs > 0 => {
+                for account in &accounts {
+                    if let Some(summary) = account.metrics.latency_summary() {
+                        log::info!("Response latencies of the {} wallet: {}", account.name, summary);
+                    }
+                }
+            }
+            _ = valuation_ticker.tick() => {
+                let valuations = value_accounts(&valuer, &accounts, &summaries);
+                status.set_valuation(&Valuation::combine(&config.valuation_currency, &valuations));
+            }
+            _ = valuation_log_ticker.tick(), if config.valuation_log_secs > 0 => {
+                let valuations = value_accounts(&valuer, &accounts, &summaries);
+                for (account, valuation) in accounts.iter().zip(valuations) {
+                    log::info!("Valuation of the {} wallet: {}", account.name, valuation.summary());
+                }
+            }
+            _ = settlement_ticker.tick(), if config.unsettled_fill_secs > 0 => {
+                for account in &mut accounts {
+                    let unsettled = account
+                        .fills
+                        .unsettled(config.unsettled_fill_secs, unix_timestamp());
+                    for fill in unsettled {
+                        log::warn!(
+                            "Fill {} on {} of the {} wallet still {} after {}s",
+                            fill.id,
+                            fill.market,
+                            account.name,
+                            fill.status,
+                            fill.age_secs
+                        );
+                        notifications.notify(Event::UnsettledFill {
+                            market: fill.market,
+                            fill_id: fill.id,
+                            status: fill.status,
+                            age_secs: fill.age_secs,
+                        });
+                    }
+                }
+            }
+            _ = reload_signal.recv() => {
+                let reloaded = match load_config(&args) {
+                    Ok(reloaded) => reloaded,
+                    Err(e) => {
+                        log::error!("Failed to reload the config, keeping the running one: {:#}", e);
+                        continue;
+                    }
+                };
+                let reload = reload::apply(&mut config, &reloaded);
+                reload.log();
+                // Strategies take their settings on their next tick.
+                for (market, config_tx) in &strategy_configs {
+                    let mm_config =
+                        market_maker_config(config.market_settings(market), market, &config.feeds);
+                    if *config_tx.borrow() != mm_config {
+                        let _ = config_tx.send(mm_config);
+                    }
+                }
+                if reload.applied_under("risk.") {
+                    for risk in accounts.iter().filter_map(|account| account.risk.as_ref()) {
+                        risk.set_limits(config.risk.clone());
+                    }
+                }
+                if reload.applied_under("notify.") {
+                    notifiers.extend(notifications.reconfigure(&config.notify));
+                }
+                if reload.applied_under("feeds.") {
+                    feeds_tx.send_replace(config.feeds.clone());
+                }
+            }
+            _ = halted_ticker.tick(), if kill_switch.is_some() && !watching => {
+                if let Some(reason) = kill_switch.as_ref().and_then(KillSwitch::tripped) {
+                    log::error!("Halted by the kill switch ({}), restart to resume", reason);
+                }
+            }
+            Some((session, op)) = sessions.market_data.recv() => {
+                // The first session subscribes every market, the others
+                // only repeat it.
+                if session == accounts[0].user_id {
+                    log::debug!("Market data: {:?}", op);
+                    summaries.apply(&op, unix_timestamp());
+                    let watched = match &op {
+                        Operation::Liquidity2(args) => triangles
+                            .iter()
+                            .any(|triangle| triangle.markets().contains(&args.market))
+                            .then_some(args),
+                        _ => None,
+                    };
+                    if let Some(args) = watched {
+                        let now = unix_timestamp();
+                        books.insert(args.market.clone(), Snapshot::new(&args.liquidity, now));
+                        for (triangle, lock) in triangles.iter_mut().zip(&triangle_locks) {
+                            if !triangle.markets().contains(&args.market) {
+                                continue;
+                            }
+                            let [first, second, third] = triangle
+                                .markets()
+                                .clone()
+                                .map(|market| books.get(&market).map(|book| book.pruned(now)));
+                            let opportunity = match (first, second, third) {
+                                (Some(first), Some(second), Some(third)) => {
+                                    triangle.on_books([&first, &second, &third])
+                                }
+                                _ => None,
+                            };
+                            let opportunity = match opportunity {
+                                Some(opportunity) => opportunity,
+                                None => continue,
+                            };
+                            accounts[0].metrics.incr("triangle_opportunities");
+                            log::info!("Triangle {} opportunity: {}", triangle.name(), opportunity);
+                            if !triangle.config().active {
+                                continue;
+                            }
+                            match lock.clone().try_lock_owned() {
+                                Ok(guard) => {
+                                    tokio::spawn(take_triangle(
+                                        triangle.config().clone(),
+                                        triangle.market_infos().to_vec(),
+                                        opportunity.legs,
+                                        accounts[0].handle.clone(),
+                                        accounts[0].signer.clone(),
+                                        notifications.clone(),
+                                        clock.clone(),
+                                        guard,
+                                    ));
+                                }
+                                Err(_) => log::info!(
+                                    "Triangle {} still takes a cycle, skipping",
+                                    triangle.name()
+                                ),
+                            }
+                        }
+                    }
+                    for account in &mut accounts {
+                        account.router.route(op.clone());
+                    }
+                }
+            }
+            Some((session, op)) = sessions.orders.recv() => {
+                let account = match session_account(&mut accounts, &session) {
+                    Some(account) => account,
+                    None => continue,
+                };
+                let trades = match &op {
+                    // Snapshots come again after every reconnect, only
+                    // account the fills they are the first to report.
+                    Operation::Fills(args) => account.fills.on_operation(&Operation::Fills(FillsArgs {
+                        fills: account.open_orders.unseen_fills(args),
+                    })),
+                    op => {
+                        account.open_orders.on_operation(op, clock.now());
+                        account.fills.on_operation(op)
+                    }
+                };
+                if let Operation::Fillstatus(args) = &op {
+                    for fill_status in &args.statuses {
+                        let settlement = account.fills.on_fill_status(fill_status);
+                        if let Some((market, Settlement::Rejected)) = settlement {
+                            dashboard.log(format!(
+                                "Settlement of fill {} on {} rejected",
+                                fill_status.full_id, market
+                            ));
+                            notifications.notify(Event::SettlementRejected {
+                                market,
+                                fill_id: fill_status.full_id,
+                            });
+                        }
+                    }
+                }
+                if let Operation::Orders(args) = &op {
+                    let markets = reconcile::covered_markets(args, &account.markets);
+                    let result = account.open_orders.reconcile_orders(args, &markets);
+                    if !result.is_empty() {
+                        log::warn!(
+                            "Orders snapshot of the {} wallet differs from ours: {:?}",
+                            account.name,
+                            result
+                        );
+                    }
+                    if config.cancel_missing_orders {
+                        for cancel in account.open_orders.cancels(&result.missing) {
+                            account.handle.send(cancel)?;
+                        }
+                    }
+                }
+                clock.on_operation(&op);
+                status.set_clock_offset(clock.offset_millis());
+                status.on_received(&op);
+                status.refresh(&account.markets, &account.fills, &account.open_orders);
+                for trade in trades {
+                    if let Some(hedge_tx) = &hedge_tx {
+                        let _ = hedge_tx.send(trade.clone());
+                    }
+                    status.on_trade(&trade, unix_timestamp());
+                    dashboard.log(format!(
+                        "Filled {:?} {} {} @ {}",
+                        trade.side, trade.quantity, trade.market, trade.price
+                    ));
+                    notifications.notify(Event::Fill {
+                        price_decimals: price_decimals.get(&trade.market).copied(),
+                        market: trade.market,
+                        side: trade.side,
+                        price: trade.price,
+                        base_quantity: trade.quantity,
+                    });
+                }
+                for market in &account.markets {
+                    if let Some(position_tx) = positions.get(market) {
+                        let position = account.fills.position(market);
+                        if *position_tx.borrow() != position {
+                            let _ = position_tx.send(position);
+                        }
+                    }
+                }
+                match op {
+                    Operation::Fillreceipt(_) => {
+                        log::info!("Order update: {}", display::order_update(&op, |market| {
+                            price_decimals.get(market).copied()
+                        }));
+                        account.router.route(op);
+                    }
+                    Operation::Fillrequest(_)
+                    | Operation::Requestquote(_)
+                    | Operation::Orders(_)
+                    | Operation::Fills(_) => {
+                        account.router.route(op);
+                    }
+                    op => log::info!("Order update: {}", display::order_update(&op, |market| {
+                        price_decimals.get(market).copied()
+                    })),
+                }
+            }
+            Some((session, e)) = sessions.errors.recv() => {
+                log::error!("{}", e);
+                dashboard.log(e.to_string());
+                if let Some(account) = session_account(&mut accounts, &session) {
+                    account.signer.on_rejected(&e.error);
+                    if e.error.is_retryable() {
+                        account.router.route(Operation::Error(e.clone()));
+                    }
+                }
+                notifications.notify(Event::Error {
+                    operation: e.operation.to_string(),
+                    error: e.error.to_string(),
+                });
+                tripped = match kill_switch.as_mut() {
+                    Some(ks) if e.error.is_fatal() => ks.on_fatal_error(&e),
+                    Some(ks) => ks.on_error(unix_timestamp()),
+                    // Without a kill switch, still stop sending orders
+                    // that cannot succeed.
+                    None if e.error.is_fatal() && !market_makers.is_empty() => {
+                        Some(format!("fatal error: {}", e))
+                    }
+                    None => None,
+                };
+            }
+            Some((session, op)) = sessions.other.recv() => match op {
+                Operation::Refreshliquidity(_) => {
+                    if let Some(account) = session_account(&mut accounts, &session) {
+                        account.router.route(op);
+                    }
+                }
+                op => log::debug!("Received from zigzag: {:?}", op),
+            },
+            Some((session, op)) = tap(&mut sent) => {
+                if let Operation::Indicateliq2(args) = &op {
+                    if let Some(account) = session_account(&mut accounts, &session) {
+                        account.liquidity.insert(args.market.clone(), args.liquidity.clone());
+                    }
+                }
+                status.on_sent(&op, unix_timestamp());
+                dashboard.on_sent(&op);
+            }
+            Some(input) = input(&mut tui) => match input {
+                Input::Quit => {
+                    log::info!("Shutting down!");
+                    break;
+                }
+                Input::TogglePause => {
+                    let paused = !dashboard.is_paused();
+                    let _ = pause_tx.send(paused);
+                    dashboard.set_paused(paused);
+                    dashboard.log(format!("Quoting {}", if paused { "paused" } else { "resumed" }));
+                }
+                Input::Resize => {}
+            },
+            (result, _, _) = future::select_all(dispatchers.iter_mut()) => return result?,
+            result = &mut shutdown => {
+                result?;
+                log::info!("Shutting down!");
+                break;
+            }
+        }
+        if let Some(reason) = tripped {
+            log::error!("Kill switch tripped: {}! Halting", reason);
+            dashboard.log(format!("Kill switch tripped: {}", reason));
+            notifications.notify(Event::Halted {
+                reason: reason.clone(),
+            });
+            let _ = shutdown_tx.send(true);
+            for task in market_makers.drain(..) {
+                task.await??;
+            }
+            if replay.is_some() {
+                continue;
+            }
+            for account in &accounts {
+                match account
+                    .handle
+                    .cancel_all(
+                        zigzag_chainid,
+                        account.user_id.clone(),
+                        DEFAULT_REQUEST_TIMEOUT,
+                    )
+                    .await
+                {
+                    Ok(()) => {
+                        log::info!("Canceled all open orders of the {} wallet!", account.name)
+                    }
+                    Err(e) => log::warn!("Canceling open orders on halt: {}", e),
+                }
+            }
+        }
+    }
+
+    for account in &accounts {
+        account.save_snapshot();
+    }
+    // Stop quoting first so that nothing new is placed while canceling.
+    let _ = shutdown_tx.send(true);
+    for task in market_makers {
+        task.await??;
+    }
+    for task in feeds {
+        task.await??;
+    }
+    for task in balance_monitors {
+        task.await?;
+    }
+    for task in auto_deposits {
+        task.await?;
+    }
+    // Hedges what was filled up to the end.
+    if let Some(task) = hedger_task {
+        task.await?;
+    }
+    token_refresh.await?;
+    for account in &accounts {
+        if config.cancel_on_exit {
+            match account
+                .handle
+                .cancel_all(
+                    zigzag_chainid,
+                    account.user_id.clone(),
+                    DEFAULT_REQUEST_TIMEOUT,
+                )
+                .await
+            {
+                Ok(()) => log::info!("Canceled all open orders of the {} wallet!", account.name),
+                Err(e) => log::warn!("Canceling open orders on exit: {}", e),
+            }
+        }
+        if let Err(e) = account.handle.close(DEFAULT_REQUEST_TIMEOUT).await {
+            log::warn!("Closing zigzag connection: {}", e);
+        }
+    }
+    // Dropping the dispatchers drops the connections and the recorders,
+    // which stops the connection watcher and lets the database writers
+    // flush their queue.
+    for dispatcher in dispatchers {
+        dispatcher.abort();
+        let _ = dispatcher.await;
+    }
+    for writer in accounts.into_iter().filter_map(|account| account.writer) {
+        writer.await?;
+    }
+    // Give the notifiers a moment to deliver the last events.
+    drop(notifications);
+    let _ = tokio::time::timeout(NOTIFY_GRACE, future::join_all(notifiers)).await;
+    Ok(())
+}
+
+type ZkWallet = Wallet<PrivateKeySigner, RpcProvider>;
+
+/// Private key of the wallet named `name` in the config.
+fn private_key(config: &Config, name: &str) -> anyhow::Result<H256> {
+    config
+        .wallet(name)
+        .ok_or_else(|| match name {
+            DEFAULT_WALLET => anyhow::anyhow!("Please specify private key or mnemonic either via ETH_PRIVKEY or ETH_MNEMONIC environment variables, the config file, or one of the cli arguments!"),
+            name => anyhow::anyhow!("Please specify the keys of the {} wallet in a [wallets.{}] table!", name, name),
+        })?
+        .private_key()
+}
+
+/// Wallet named `name` in the config, on each zksync endpoint of `[rpc]`
+/// that answers, or on the network's default one.
+async fn open_wallet(config: &Config, name: &str) -> anyhow::Result<Failover<ZkWallet>> {
+    let mut private_key = private_key(config, name)?;
+    let network = config.network.try_into()?;
+    let providers = match config.rpc.urls.is_empty() {
+        true => vec![("default".to_owned(), RpcProvider::new(network))],
+        false => config
+            .rpc
+            .urls
+            .iter()
+            .map(|url| {
+                (
+                    rpc::endpoint_name(url),
+                    RpcProvider::from_addr_and_network(url.as_str(), network),
+                )
+            })
+            .collect(),
+    };
+    let mut wallets = Vec::new();
+    let mut last_error = None;
+    for (endpoint, provider) in providers {
+        let eth_signer = PrivateKeySigner::new(private_key);
+        let address = eth_signer.get_address().await?;
+        let credential = WalletCredentials::from_eth_signer(address, eth_signer, network).await?;
+        match Wallet::new(provider, credential).await {
+            Ok(wallet) => wallets.push((endpoint, Arc::new(wallet))),
+            Err(e) => {
+                log::warn!("zksync RPC endpoint {} unavailable: {}", endpoint, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    private_key.0.zeroize();
+    match (wallets.is_empty(), last_error) {
+        (true, Some(e)) => Err(e.into()),
+        _ => Failover::new(config.rpc.clone(), wallets),
+    }
+}
+
+/// Wallet named `name` in the config, once its zksync account exists. A
+/// fresh wallet has none before its first deposit, which is waited for with
+/// `--wait-for-activation`.
+async fn activated_wallet(config: &Config, name: &str) -> anyhow::Result<Failover<ZkWallet>> {
+    let rpc = open_wallet(config, name).await?;
+    let wallet = rpc.current();
+    if wallet.account_id().is_some() {
+        return Ok(rpc);
+    }
+    let address = format!("{:?}", wallet.address);
+    log::info!(
+        "The {} wallet has no zksync account yet, deposit to {} to create it",
+        name,
+        address
+    );
+    if !config.wait_for_activation {
+        return Err(anyhow::anyhow!(
+            "No zksync account for {}, deposit funds to it or run with --wait-for-activation!",
+            address
+        ));
+    }
+    activation::wait_for_account(
+        &rpc,
+        &address,
+        activation::POLL_INTERVAL,
+        Duration::from_secs(config.activation_timeout_secs),
+    )
+    .await?;
+    // The wallet only looks its account id up when opened.
+    open_wallet(config, name).await
+}
+
+/// Zigzag user id of `wallet`, its zksync account id.
+fn user_id(wallet: &ZkWallet) -> anyhow::Result<UserId> {
+    wallet
+        .account_id()
+        .map(|id| id.to_string())
+        .ok_or_else(|| anyhow::anyhow!("No zksync account for {:?}!", wallet.address))
+}
+
+/// Enables the wallet if needed, paying in the first of the config's fee
+/// tokens that can, as far as `assets` tell which are enabled for fees.
+async fn set_signing_key(
+    wallet: &ZkWallet,
+    config: &Config,
+    tokens: &Registry,
+    assets: &[Asset],
+) -> anyhow::Result<()> {
+    if wallet.is_signing_key_set().await? {
+        return Ok(());
+    }
+    let fee_token =
+        fees::choose_fee_token(&config.fee_tokens, tokens, assets, &ChangePubKeyFee(wallet))
+            .await?;
+    log::info!(
+        "Setting signing key, paying the fee in {}!",
+        fee_token.symbol
+    );
+    let change_pubkey = wallet
+        .start_change_pubkey()
+        .fee_token(TokenId(fee_token.id))?
+        .send()
+        .await?;
+    let change_pubkey_receipt = change_pubkey.wait_for_commit().await?;
+
+    if !change_pubkey_receipt.success.unwrap_or(false) {
+        log::error!(
+            "Change pubkey failure: {:?}",
+            change_pubkey_receipt.fail_reason
+        );
+    }
+    Ok(())
+}
+
+/// A wallet logged in on a session of its own, since the backend keys them
+/// by user id, and the accounting of its orders and fills.
+struct Account {
+    /// Name of the wallet in the config
+    name: String,
+    user_id: UserId,
+    /// Wallet on the zksync endpoint in use when opened
+    wallet: Arc<ZkWallet>,
+    /// Reads of the wallet, retried and failed over
+    rpc: Arc<Failover<ZkWallet>>,
+    handle: DispatcherHandle,
+    /// Every order is signed through it, to number them without gaps.
+    signer: Arc<Signer>,
+    metrics: Arc<Metrics>,
+    connection: watch::Receiver<bool>,
+    fills: FillTracker,
+    open_orders: OpenOrders,
+    snapshots: Option<SnapshotStore>,
+    /// Liquidity last advertised on each market, for the snapshots
+    liquidity: BTreeMap<Market, Vec<Liquidity>>,
+    /// Markets the wallet quotes on, whose strategies `router` feeds
+    markets: Vec<Market>,
+    router: MarketRouter,
+    /// Database writer of the recorder
+    writer: Option<JoinHandle<()>>,
+    /// Balances polled with `[balances]`
+    balances: Option<watch::Receiver<Balances>>,
+    /// Checks of the orders, with `[risk]` limits
+    risk: Option<Arc<RiskEngine>>,
+}
+
+impl Account {
+    /// Logs the wallet of `rpc` in over `transport` and starts its dispatcher, set up
+    /// as `config` says, and resumes from its snapshot with `--resume`. The
+    /// operations sent are copied to `tap`, tagged with the session.
+    async fn open(
+        name: &str,
+        rpc: Failover<ZkWallet>,
+        transport: Box<dyn Transport>,
+        connection: watch::Receiver<bool>,
+        config: &Config,
+        resume: bool,
+        tap: Option<&mpsc::UnboundedSender<(UserId, Operation)>>,
+    ) -> anyhow::Result<(Self, Receivers, JoinHandle<anyhow::Result<()>>)> {
+        let zigzag_chainid = config.zigzag_chain_id;
+        let wallet = rpc.current();
+        let user_id = user_id(&wallet)?;
+        let mut client = zigzag_client(transport, config);
+        client.login(zigzag_chainid, user_id.clone()).await?;
+
+        let metrics = Arc::new(Metrics::new());
+        let mut fills = FillTracker::new(user_id.clone());
+        let mut open_orders = OpenOrders::new(user_id.clone()).with_metrics(metrics.clone());
+        let mut stored_fills = Vec::new();
+        let mut writer = None;
+        let (mut dispatcher, handle, receivers) = Dispatcher::new(client);
+        if let Some(path) = &config.db_path {
+            let storage = Storage::open(path)?;
+            stored_fills = storage.fills()?;
+            fills.restore(&stored_fills);
+            let (recorder, task) = Recorder::spawn(storage, user_id.clone());
+            dispatcher = dispatcher.with_recorder(recorder);
+            writer = Some(task);
+        }
+        let mut risk = None;
+        if !config.risk.is_empty() {
+            let engine = RiskEngine::new(config.risk.clone(), user_id.clone(), metrics.clone())
+                .with_override(config.risk_override);
+            engine.restore(&stored_fills);
+            let engine = Arc::new(engine);
+            dispatcher = dispatcher.with_risk(engine.clone());
+            risk = Some(engine);
+        }
+        let snapshots = config
+            .snapshot_dir
+            .as_ref()
+            .map(|dir| SnapshotStore::new(dir, &format!("{:?}", wallet.address), zigzag_chainid));
+        let mut liquidity = BTreeMap::new();
+        if let (true, Some(store)) = (resume, &snapshots) {
+            match store.load()? {
+                Some(snapshot) => {
+                    log::info!(
+                        "Resuming from {} saved at {}, last fill {:?}",
+                        store.path().display(),
+                        snapshot.saved_at,
+                        snapshot.last_fill_id
+                    );
+                    liquidity = snapshot.resume(&mut fills, &mut open_orders, unix_timestamp());
+                    for (market, levels) in &liquidity {
+                        let levels: Vec<_> = levels.iter().map(Liquidity::to_string).collect();
+                        log::info!(
+                            "Liquidity still advertised on {}: {}",
+                            market,
+                            levels.join(", ")
+                        );
+                    }
+                }
+                None => log::warn!(
+                    "No snapshot at {} to resume from, starting afresh",
+                    store.path().display()
+                ),
+            }
+        }
+        dispatcher = dispatcher
+            .with_chain_check(zigzag_chainid, config.chain_id_check)
+            .with_metrics(metrics.clone())
+            .with_dedup(Dedup::new(&config.dedup));
+        if let Some(rate_limit) = &config.rate_limit {
+            dispatcher = dispatcher.with_rate_limit(RateLimiter::new(rate_limit, metrics.clone()));
+        }
+        if let Some(tap) = tap {
+            let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+            dispatcher = dispatcher.with_tap(sent_tx);
+            dispatcher::forward_tagged(user_id.clone(), sent_rx, tap.clone());
+        }
+        let dispatcher = tokio::spawn(dispatcher.run());
+        let rpc = Arc::new(rpc.with_metrics(metrics.clone()));
+        let signer = Arc::new(Signer::spawn(rpc.clone()));
+        let account = Self {
+            name: name.to_owned(),
+            user_id,
+            wallet,
+            rpc,
+            handle,
+            signer,
+            metrics,
+            connection,
+            fills,
+            open_orders,
+            snapshots,
+            liquidity,
+            markets: config.markets_quoted_by(name),
+            router: MarketRouter::default(),
+            writer,
+            balances: None,
+            risk,
+        };
+        Ok((account, receivers, dispatcher))
+    }
+
+    /// Saves a snapshot when they are kept, failures are only logged.
+    fn save_snapshot(&self) {
+        if let Some(store) = &self.snapshots {
+            let snapshot = store.take(
+                &self.fills,
+                &self.open_orders,
+                &self.liquidity,
+                unix_timestamp(),
+            );
+            if let Err(e) = store.save(&snapshot) {
+                log::warn!("{}", e);
+            }
+        }
+    }
+}
+
+/// Account logged in on the session of `user_id`.
+fn session_account<'a>(accounts: &'a mut [Account], user_id: &str) -> Option<&'a mut Account> {
+    accounts
+        .iter_mut()
+        .find(|account| account.user_id == user_id)
+}
+
+/// Resolves the config of `args`, reading its file if any.
+fn load_config(args: &Args) -> anyhow::Result<Config> {
+    let file = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    Config::resolve(args, |key| std::env::var(key).ok(), file)
+}
+
+/// Client of `transport`, as strict about the protocol as `config` asks.
+fn zigzag_client<T: Transport>(transport: T, config: &Config) -> ZigzagClient<T> {
+    let mut client = ZigzagClient::new(transport);
+    client.set_strict_protocol(config.strict_protocol);
+    client
+}
+
+/// Market maker settings of `market`, quoting around the reference of the
+/// feed covering it in `feeds`, if any.
+fn market_maker_config(
+    settings: &MarketMakerSettings,
+    market: &str,
+    feeds: &FeedsConfig,
+) -> MarketMakerConfig {
+    MarketMakerConfig {
+        market: market.to_owned(),
+        spread_bps: settings.spread_bps,
+        quote_size: settings.quote_size,
+        expires_secs: settings.quote_expires_secs,
+        requote_threshold_bps: settings.requote_threshold_bps,
+        requote_margin_secs: settings.requote_margin_secs,
+        clock_skew_secs: settings.clock_skew_secs,
+        rfq: settings.rfq_max_size.map(|max| RfqConfig {
+            markup_bps: settings.rfq_markup_bps,
+            max_base_quantity: max,
+        }),
+        skew: settings.max_position.map(|max| SkewConfig {
+            max_position: max,
+            price_skew_bps: settings.price_skew_bps,
+            size_skew: settings.size_skew,
+        }),
+        fees: settings.fees.clone(),
+        ladder: settings.ladder.clone(),
+        volatility: settings.volatility.clone(),
+        avellaneda: (settings.strategy == "avellaneda").then(|| settings.avellaneda.clone()),
+        fill_cooldown: settings.fill_cooldown.clone(),
+        reference_max_age: feeds
+            .source(market)
+            .and_then(|source| feeds.max_age(source)),
+    }
+}
+
+/// Volatility estimate shared by the market makers, if any uses one, with
+/// the half-life of the first. The Avellaneda–Stoikov model always needs it.
+fn volatility(config: &Config) -> Option<Volatility> {
+    let settings = config
+        .markets
+        .iter()
+        .map(|market| config.market_settings(market))
+        .chain([&config.market_maker]);
+    settings
+        .filter_map(|settings| match &settings.volatility {
+            Some(v) => Some(v.half_life_secs),
+            None if settings.strategy == "avellaneda" => {
+                Some(VolatilityConfig::default().half_life_secs)
+            }
+            None => None,
+        })
+        .next()
+        .map(|half_life_secs| Volatility::new(Duration::from_secs(half_life_secs)))
+}
+
+/// Fee settings of the first market covering fees, for the estimator they
+/// share.
+fn fee_config(config: &Config) -> Option<&FeeConfig> {
+    config
+        .markets
+        .iter()
+        .map(|market| config.market_settings(market))
+        .chain([&config.market_maker])
+        .find_map(|settings| settings.fees.as_ref())
+}
+
+/// Where the market makers of a live account report their fill cooldown.
+#[derive(Clone)]
+struct CooldownReporting {
+    user_id: UserId,
+    notifications: Notifications,
+    status: StatusBoard,
+}
+
+/// Factory of the "spread" and "avellaneda" strategies.
+fn market_maker_factory<O: OrderSigner + 'static>(
+    signer: Arc<O>,
+    fees: Option<FeeEstimator>,
+    volatility: Option<Volatility>,
+    metrics: Arc<Metrics>,
+    reporting: Option<CooldownReporting>,
+) -> impl Fn(&MarketMakerConfig) -> anyhow::Result<Box<dyn Strategy>> + Clone + Send + Sync + 'static
+{
+    move |mm_config: &MarketMakerConfig| -> anyhow::Result<Box<dyn Strategy>> {
+        let mut mm =
+            MarketMaker::new(mm_config.clone(), signer.clone()).with_metrics(metrics.clone());
+        if let Some(reporting) = &reporting {
+            mm = mm
+                .with_user(reporting.user_id.clone())
+                .with_notifications(reporting.notifications.clone())
+                .with_status(reporting.status.clone());
+        }
+        if let Some(fees) = &fees {
+            mm = mm.with_fees(fees.clone());
+        }
+        if let Some(volatility) = &volatility {
+            mm = mm.with_volatility(volatility.clone());
+        }
+        Ok(Box::new(mm))
+    }
+}
+
+/// Runs the configured strategy over recorded sessions and prints the
+/// report. External feeds are not recorded, so the strategies only see the
+/// market data of the captures.
+async fn run_backtest(command: &BacktestCommand, config: &Config) -> anyhow::Result<()> {
+    let ops = backtest::load(&command.path)?;
+    let recorded_fees = RecordedFees::default();
+    let fees = fee_config(config)
+        .map(|fees| FeeEstimator::new(Arc::new(recorded_fees.clone()), fees.ttl_secs));
+    let market_maker = market_maker_factory(
+        Arc::new(NoSigner),
+        fees,
+        volatility(config),
+        Arc::new(Metrics::new()),
+        None,
+    );
+    let mut registry = StrategyRegistry::new();
+    registry.register("spread", market_maker.clone());
+    registry.register("avellaneda", market_maker);
+    let report = Backtest::new(
+        registry,
+        &config.market_maker.strategy,
+        market_maker_config(&config.market_maker, "", &FeedsConfig::default()),
+    )
+    .with_markets(config.markets.clone())
+    .with_fees(recorded_fees)
+    .run(ops)
+    .await?;
+    println!("{}", report);
+    if let Some(path) = &command.csv {
+        backtest::write_csv(&report.fills, std::fs::File::create(path)?)?;
+    }
+    Ok(())
+}
+
+fn export_fills(
+    command: &ExportFillsCommand,
+    db_path: Option<&str>,
+    user_id: &str,
+) -> anyhow::Result<()> {
+    let db_path =
+        db_path.ok_or_else(|| anyhow::anyhow!("Please specify the database with --db-path!"))?;
+    let history = Storage::open(db_path)?.fill_history()?;
+    let filter = FillFilter {
+        from: command.from,
+        to: command.to,
+        market: command.market.clone(),
+    };
+    let rows = export::rows(user_id, &history, &filter);
+    match &command.output {
+        Some(path) => export::write_csv(&rows, std::fs::File::create(path)?)?,
+        None => export::write_csv(&rows, std::io::stdout().lock())?,
+    }
+    log::info!("Exported {} fills", rows.len());
+    Ok(())
+}
+
+async fn run_withdraw<S, P>(
+    command: &WithdrawCommand,
+    wallet: &Wallet<S, P>,
+    tokens: &Registry,
+) -> anyhow::Result<()>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    let token = tokens
+        .by_symbol(&command.token)
+        .ok_or_else(|| anyhow::anyhow!("Unknown token {}!", command.token))?;
+    let token_id = TokenId(token.id);
+    let to = match &command.to {
+        Some(address) => withdraw::parse_address(address)?,
+        None => wallet.address,
+    };
+    let fee = wallet
+        .provider
+        .get_tx_fee(TxFeeTypes::Withdraw, to, token_id)
+        .await?
+        .total_fee;
+    let balance = wallet.get_balance(BlockStatus::Committed, token_id).await?;
+    let amount = match command.amount {
+        Some(amount) => WithdrawAmount::Exact(amount),
+        None => WithdrawAmount::All,
+    };
+    let decimals = token.decimals;
+    let raw = withdraw::raw_amount(&amount, &token.symbol, decimals, &balance, &fee)?;
+
+    println!(
+        "Withdrawing {} {} to {:?}, for a fee of {} {}",
+        to_units(&raw, decimals)?,
+        token.symbol,
+        to,
+        to_units(&fee, decimals)?,
+        token.symbol
+    );
+    if !command.yes {
+        print!("Proceed? [y/N] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+
+    let handle = wallet
+        .start_withdraw()
+        .token(token_id)?
+        .amount(raw)
+        .to(to)
+        .fee(fee)
+        .send()
+        .await?;
+    log::info!("Sent withdrawal {}, waiting for commit", handle.hash());
+    let receipt = handle.wait_for_commit().await?;
+    if !receipt.success.unwrap_or(false) {
+        return Err(anyhow::anyhow!(
+            "Withdrawal failed: {}",
+            receipt.fail_reason.as_deref().unwrap_or("unknown reason")
+        ));
+    }
+    println!("Withdrawal committed");
+    Ok(())
+}
+
+/// Prints the infos of the markets of the chain, which need no login.
+async fn run_markets(
+    command: &MarketsCommand,
+    config: &Config,
+    backoff: Backoff,
+    heartbeat: Heartbeat,
+) -> anyhow::Result<()> {
+    let connection = Connection::connect_through(
+        &config.zigzag_url,
+        config.proxy.as_ref(),
+        backoff,
+        heartbeat,
+    )
+    .await?;
+    let (dispatcher, handle, _receivers) = Dispatcher::new(zigzag_client(connection, config));
+    let dispatcher = tokio::spawn(dispatcher.run());
+    let timeout = Duration::from_secs(command.timeout_secs);
+    let infos = match &command.market {
+        Some(market) => handle
+            .market_info(config.zigzag_chain_id, market.to_string(), timeout)
+            .await
+            .map(|info| vec![info]),
+        None => handle.market_infos(config.zigzag_chain_id, timeout).await,
+    };
+    if let Err(e) = handle.close(DEFAULT_REQUEST_TIMEOUT).await {
+        log::warn!("{}", e);
+    }
+    dispatcher.abort();
+    let infos = infos?;
+
+    match (command.json, infos.as_slice()) {
+        (true, [info]) if command.market.is_some() => {
+            println!("{}", serde_json::to_string_pretty(info)?)
+        }
+        (true, infos) => println!("{}", serde_json::to_string_pretty(infos)?),
+        (false, infos) => println!("{}", markets::table_of(infos)),
+    }
+    Ok(())
+}
+
+/// Prints the receipt of an order or of its fill, which need no login, and
+/// exits with the code of the outcome.
+async fn run_receipt(
+    command: &ReceiptCommand,
+    kind: ReceiptKind,
+    config: &Config,
+    backoff: Backoff,
+    heartbeat: Heartbeat,
+) -> anyhow::Result<()> {
+    let connection = Connection::connect_through(
+        &config.zigzag_url,
+        config.proxy.as_ref(),
+        backoff,
+        heartbeat,
+    )
+    .await?;
+    let mut client = zigzag_client(connection, config);
+    client.set_request_timeout(Duration::from_secs(command.timeout_secs));
+    let receipt = receipt::watch(
+        &mut client,
+        kind,
+        config.zigzag_chain_id,
+        command.id,
+        command.watch.map(Duration::from_secs),
+        |receipt| println!("{}", receipt),
+    )
+    .await;
+    if let Err(e) = client.close().await {
+        log::warn!("{}", e);
+    }
+    match receipt {
+        Ok(receipt) if receipt.exit_code() != 0 => std::process::exit(receipt.exit_code()),
+        Ok(_) => Ok(()),
+        Err(e) => match e.downcast_ref::<ReceiptError>() {
+            Some(error) => {
+                log::error!("{}", error);
+                std::process::exit(error.exit_code());
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Prints the daily volumes of the chain, which need no login.
+async fn run_volume(
+    command: &VolumeCommand,
+    config: &Config,
+    backoff: Backoff,
+    heartbeat: Heartbeat,
+) -> anyhow::Result<()> {
+    let today = Utc
+        .timestamp_opt(unix_timestamp() as i64, 0)
+        .single()
+        .ok_or_else(|| anyhow::anyhow!("System time out of range!"))?
+        .date_naive();
+    let filter = VolumeFilter::new(command.market.clone(), command.days, today);
+    let mut report = VolumeReport::new(config.zigzag_chain_id, filter);
+
+    let connection = Connection::connect_through(
+        &config.zigzag_url,
+        config.proxy.as_ref(),
+        backoff,
+        heartbeat,
+    )
+    .await?;
+    let (dispatcher, handle, _receivers) = Dispatcher::new(zigzag_client(connection, config));
+    let dispatcher = tokio::spawn(dispatcher.run());
+    let mut frames = handle.daily_volume(config.zigzag_chain_id)?;
+    let collected = volume::collect(
+        &mut frames,
+        &mut report,
+        Duration::from_secs(command.timeout_secs),
+        volume::FRAME_GAP,
+    )
+    .await;
+    if let Err(e) = handle.close(DEFAULT_REQUEST_TIMEOUT).await {
+        log::warn!("{}", e);
+    }
+    dispatcher.abort();
+    collected?;
+
+    match command.json {
+        true if !report.is_empty() => println!("{}", report.json_lines()),
+        true => (),
+        false => println!("{}", report.table()),
+    }
+    Ok(())
+}
+
+async fn notify_test(config: &notify::NotifyConfig) -> anyhow::Result<()> {
+    let notifiers = config.notifiers();
+    if notifiers.is_empty() {
+        return Err(anyhow::anyhow!("No notifier in the config file!"));
+    }
+    for notifier in notifiers {
+        notifier
+            .send(&[Event::Test])
+            .await
+            .map_err(|e| anyhow::anyhow!("Sending to {} failed: {}", notifier.name(), e))?;
+        log::info!("Sent a test message to {}", notifier.name());
+    }
+    Ok(())
+}
+
+/// Time left to the notifiers to deliver the last events on exit.
+const NOTIFY_GRACE: Duration = Duration::from_secs(5);
+
+/// How often a halted bot says so.
+const HALTED_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often positions are marked to market for the status and metrics.
+const VALUATION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often pending fills are checked for a settlement long overdue.
+const SETTLEMENT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Values every account at the latest prices, setting its gauges.
+fn value_accounts(
+    valuer: &Valuer,
+    accounts: &[Account],
+    summaries: &SummaryCache,
+) -> Vec<Valuation> {
+    let price = |market: &str| {
+        summaries
+            .reference(market)
+            .map(|reference| reference.price)
+            .or_else(|| summaries.mid(market))
+    };
+    accounts
+        .iter()
+        .map(|account| {
+            let balances = account.balances.as_ref().map(|b| b.borrow().clone());
+            let valuation =
+                valuer.value(&account.markets, &account.fills, balances.as_ref(), &price);
+            valuation.record(&account.metrics);
+            valuation
+        })
+        .collect()
+}
+
+/// Operations the dispatcher sent, never resolving without the tap.
+async fn tap(
+    sent: &mut Option<mpsc::UnboundedReceiver<(UserId, Operation)>>,
+) -> Option<(UserId, Operation)> {
+    match sent {
+        Some(sent) => sent.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// Keys pressed on the dashboard, never resolving without one.
+async fn input(tui: &mut Option<Tui>) -> Option<Input> {
+    match tui {
+        Some(tui) => tui.input().await,
+        None => future::pending().await,
+    }
+}
+
+/// Updates the position, PnL and reference of each market on the dashboard.
+fn refresh_dashboard(
+    dashboard: &mut Dashboard,
+    accounts: &[Account],
+    summaries: &SummaryCache,
+    feeds: &FeedsConfig,
+) {
+    for view in dashboard.markets_mut() {
+        let market = view.market().to_owned();
+        let fills = match accounts.iter().find(|a| a.markets.contains(&market)) {
+            Some(account) => &account.fills,
+            None => continue,
+        };
+        view.position = fills.position(&market);
+        view.realized_pnl = fills.realized_pnl(&market);
+        view.avg_entry_price = fills.avg_entry_price(&market);
+        view.reference = summaries.reference(&market);
+        view.reference_max_age = view
+            .reference
+            .as_ref()
+            .and_then(|reference| feeds.max_age(reference.source));
+    }
+}
+
+/// Resolves on SIGINT, or SIGTERM on unix.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// Runs a command on an EVM deployment of ZigZag, where users log in with
+/// their address and sign orders as EIP-712 typed data. Only quotes are
+/// supported so far.
+async fn run_evm(
+    args: &Args,
+    config: &Config,
+    backoff: Backoff,
+    heartbeat: Heartbeat,
+) -> anyhow::Result<()> {
+    let command = match &args.command {
+        Some(Command::Quote(command)) => command,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Only the quote command runs on chain {} so far!",
+                config.zigzag_chain_id
+            ))
+        }
+    };
+    let exchange = config.exchange_address.as_deref().ok_or_else(|| {
+        anyhow::anyhow!("Please specify the address of the exchange contract orders are signed for with exchange_address in the config file!")
+    })?;
+    let mut private_key = private_key(config, config.take_wallet(&command.market.to_string()))?;
+    let signer = EvmSigner::new(
+        private_key.as_bytes(),
+        Domain::zigzag(config.zigzag_chain_id, exchange),
+    );
+    private_key.0.zeroize();
+    let signer = signer?;
+
+    let connection = Connection::connect_through(
+        &config.zigzag_url,
+        config.proxy.as_ref(),
+        backoff,
+        heartbeat,
+    )
+    .await?;
+    log::info!("Connected to zigzag!");
+    let mut client = zigzag_client(connection, config);
+    client
+        .login(config.zigzag_chain_id, signer.address().to_owned())
+        .await?;
+    let (dispatcher, handle, mut receivers) = Dispatcher::new(client);
+    tokio::spawn(
+        dispatcher
+            .with_chain_check(config.zigzag_chain_id, config.chain_id_check)
+            .run(),
+    );
+    run_quote(
+        command,
+        config.zigzag_chain_id,
+        &handle,
+        &mut receivers,
+        &signer,
+        &SyncedClock::new(config.clock.clone()),
+    )
+    .await
+}
+
+/// Exits with the code of quote errors, for scripts to tell them apart.
+fn exit_on_quote_error(result: anyhow::Result<()>) -> anyhow::Result<()> {
+    if let Err(e) = &result {
+        if let Some(e) = e.downcast_ref::<QuoteError>() {
+            log::error!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+    result
+}
+
+/// Signs the order of a quote in the format of the exchange.
+#[async_trait]
+trait QuoteSigner: Send + Sync {
+    async fn sign_quote(
+        &self,
+        market_info: &MarketInfo,
+        side: Side,
+        price: Decimal,
+        base_quantity: Amount,
+        expires: Timestamp,
+    ) -> anyhow::Result<ExchangeOrder>;
+}
+
+#[async_trait]
+impl<O: OrderSigner> QuoteSigner for O {
+    async fn sign_quote(
+        &self,
+        market_info: &MarketInfo,
+        side: Side,
+        price: Decimal,
+        base_quantity: Amount,
+        expires: Timestamp,
+    ) -> anyhow::Result<ExchangeOrder> {
+        let order = build_order(self, market_info, side, price, base_quantity, expires).await?;
+        Ok(order.into())
+    }
+}
+
+#[async_trait]
+impl QuoteSigner for EvmSigner {
+    async fn sign_quote(
+        &self,
+        market_info: &MarketInfo,
+        side: Side,
+        price: Decimal,
+        base_quantity: Amount,
+        expires: Timestamp,
+    ) -> anyhow::Result<ExchangeOrder> {
+        let params = EvmOrderParams::new(market_info, side, price, base_quantity, expires)?;
+        Ok(self.sign_order(&params)?.into())
+    }
+}
+
+/// Requests a quote and prints it, then submits an order at the quoted price
+/// if asked to and the price is close enough to the last price.
+async fn run_quote<O: QuoteSigner>(
+    command: &QuoteCommand,
+    chain_id: ChainId,
+    handle: &DispatcherHandle,
+    receivers: &mut Receivers,
+    signer: &O,
+    clock: &SyncedClock,
+) -> anyhow::Result<()> {
+    let market = command.market.to_string();
+    let side = command.side.clone();
+    let timeout = Duration::from_secs(command.timeout_secs);
+    handle.send(Operation::Subscribemarket(SubscribemarketArgs {
+        chain_id,
+        market: market.clone(),
+    }))?;
+    let market_info =
+        wait_for_market_infos(&mut receivers.other, std::slice::from_ref(&market), timeout)
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{} is not listed by ZigZag!", market))?;
+
+    let quote = handle
+        .request_quote(
+            RequestquoteArgs {
+                chain_id,
+                market: market.clone(),
+                side: side.clone(),
+                base_quantity: Some(command.base_quantity),
+                quote_quantity: None,
+            },
+            timeout,
+        )
+        .await?;
+    let price = quote.price.value()?;
+    println!(
+        "{} {:?} {} @ {} ({} quote)",
+        market, side, quote.base_quantity, price, quote.quote_quantity
+    );
+    if !command.execute {
+        return Ok(());
+    }
+
+    let last_price = tokio::time::timeout(timeout, async {
+        loop {
+            match receivers.market_data.recv().await {
+                Some(Operation::Lastprice(args)) => {
+                    if let Some(update) = args.updates.iter().rev().find(|u| u.market == market) {
+                        return Ok(update.price.value()?);
+                    }
+                }
+                Some(_) => (),
+                None => return Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            }
+        }
+    })
+    .await
+    .map_err(|_| QuoteError::Timeout(timeout))??;
+    if !rfq::within_slippage(&side, price, last_price, command.max_slippage_bps) {
+        return Err(QuoteError::Rejected(format!(
+            "price {} is more than {} bps worse than the last price {}",
+            price, command.max_slippage_bps, last_price
+        ))
+        .into());
+    }
+
+    let order = signer
+        .sign_quote(
+            &market_info,
+            side,
+            price,
+            quote.base_quantity,
+            clock.now() + command.order_expires_secs,
+        )
+        .await?;
+    let ack = handle
+        .submit_order(&market_info, order, DEFAULT_REQUEST_TIMEOUT)
+        .await?;
+    println!("Submitted order {}", ack.id);
+    Ok(())
+}
+
+/// Works `command` in RFQ slices, logging the progress after each. A
+/// SIGINT stops it once the slice in flight is done.
+async fn run_twap<O: QuoteSigner>(
+    command: &TwapCommand,
+    chain_id: ChainId,
+    handle: &DispatcherHandle,
+    receivers: &mut Receivers,
+    signer: &O,
+    clock: &SyncedClock,
+) -> anyhow::Result<()> {
+    let market = command.market.to_string();
+    let timeout = Duration::from_secs(command.timeout_secs);
+    handle.send(Operation::Subscribemarket(SubscribemarketArgs {
+        chain_id,
+        market: market.clone(),
+    }))?;
+    let market_info =
+        wait_for_market_infos(&mut receivers.other, std::slice::from_ref(&market), timeout)
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{} is not listed by ZigZag!", market))?;
+
+    let summaries = SummaryCache::new();
+    let arrival_price = tokio::time::timeout(timeout, async {
+        loop {
+            match receivers.market_data.recv().await {
+                Some(op) => {
+                    summaries.apply(&op, unix_timestamp());
+                    if let Some(price) = summaries.mid(&market) {
+                        return Ok(price);
+                    }
+                }
+                None => return Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("No last price of {} within {:?}", market, timeout))??;
+    let mut twap = Twap::new(
+        TwapConfig {
+            side: command.side.clone(),
+            quantity: command.base_quantity,
+            slices: command.slices,
+            window: Duration::from_secs(command.duration_secs),
+            tolerance_bps: command.tolerance_bps,
+            catch_up: command.catch_up,
+            decimals: market_info.base_asset.decimals,
+        },
+        arrival_price,
+    )?;
+
+    let (stop_tx, mut stop) = watch::channel(false);
+    tokio::spawn(async move {
+        if shutdown_signal().await.is_ok() {
+            let _ = stop_tx.send(true);
+        }
+    });
+    let start = tokio::time::Instant::now();
+    while let Some(slice) = twap.next_slice() {
+        tokio::select! {
+            biased;
+            Ok(()) = stop.changed() => {
+                log::info!("Stopping the TWAP of {}", market);
+                break;
+            }
+            _ = tokio::time::sleep_until(start + twap.offset(slice)) => {}
+        }
+        while let Ok(op) = receivers.market_data.try_recv() {
+            summaries.apply(&op, unix_timestamp());
+        }
+        let quantity = twap.quantity();
+        match twap_slice(
+            command,
+            &market_info,
+            quantity,
+            summaries.mid(&market),
+            &twap,
+            handle,
+            receivers,
+            signer,
+            clock,
+        )
+        .await
+        {
+            Ok((quantity, price)) => twap.fill(quantity, price),
+            Err(e) => {
+                log::warn!("Skipping slice {} of {}: {}", slice + 1, market, e);
+                twap.skip(quantity);
+            }
+        }
+        log::info!("TWAP of {}: {}", market, twap.progress());
+    }
+    println!("{} {:?}: {}", market, command.side, twap.progress());
+    Ok(())
+}
+
+/// Takes `quantity` through an RFQ if the quote is within the tolerance
+/// of `reference`, returning what it filled at which price.
+#[allow(clippy::too_many_arguments)]
+async fn twap_slice<O: QuoteSigner>(
+    command: &TwapCommand,
+    market_info: &MarketInfo,
+    quantity: Amount,
+    reference: Option<Decimal>,
+    twap: &Twap,
+    handle: &DispatcherHandle,
+    receivers: &mut Receivers,
+    signer: &O,
+    clock: &SyncedClock,
+) -> anyhow::Result<(Amount, Decimal)> {
+    let reference = reference.ok_or_else(|| anyhow::anyhow!("no last price"))?;
+    let quantity = market_info.round_quantity(quantity)?;
+    let timeout = Duration::from_secs(command.timeout_secs);
+    let quote = handle
+        .request_quote(
+            RequestquoteArgs {
+                chain_id: market_info.zigzag_chain_id,
+                market: market_info.alias.clone(),
+                side: command.side.clone(),
+                base_quantity: Some(quantity),
+                quote_quantity: None,
+            },
+            timeout,
+        )
+        .await?;
+    let price = quote.price.value()?;
+    if !twap.accepts(price, reference) {
+        return Err(anyhow::anyhow!(
+            "quoted {}, beyond the limit of {}",
+            price,
+            twap.limit_price(reference)
+        ));
+    }
+    let order = signer
+        .sign_quote(
+            market_info,
+            command.side.clone(),
+            price,
+            quote.base_quantity,
+            clock.now() + command.order_expires_secs,
+        )
+        .await?;
+    let ack = handle
+        .submit_order(market_info, order, DEFAULT_REQUEST_TIMEOUT)
+        .await?;
+    wait_for_fill(&mut receivers.orders, ack.id, timeout).await?;
+    Ok((quote.base_quantity, price))
+}
+
+/// Waits for the order updates on `orders` to report `order_id` matched or
+/// filled. Fails when it is canceled, expires or is rejected first, or
+/// after `timeout`.
+async fn wait_for_fill(
+    orders: &mut mpsc::UnboundedReceiver<Operation>,
+    order_id: OrderId,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    tokio::time::timeout(timeout, async {
+        loop {
+            let updates = match orders.recv().await {
+                Some(Operation::Orderstatus(args)) => args.updates,
+                Some(_) => continue,
+                None => return Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            };
+            for update in updates.iter().filter(|u| u.order_id == order_id) {
+                if let Some(error) = update.detail.remaining().and_then(RemainingOrError::error) {
+                    return Err(anyhow::anyhow!("order {} rejected: {}", order_id, error));
+                }
+                match update.status() {
+                    OrderStatus::Matched | OrderStatus::Filled => return Ok(()),
+                    OrderStatus::Canceled | OrderStatus::Expired | OrderStatus::Rejected => {
+                        return Err(anyhow::anyhow!("order {} {:?}", order_id, update.status()))
+                    }
+                    _ => (),
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("order {} not filled within {:?}", order_id, timeout))?
+}
+
+/// Takes the legs of a triangle opportunity one after the other, each
+/// through an RFQ quote at most `max_slippage_bps` worse than its book
+/// price, reporting a failure to the notifiers. A failed leg leaves the
+/// tokens the earlier ones bought.
+#[allow(clippy::too_many_arguments)]
+async fn take_triangle<O: QuoteSigner>(
+    config: TriangleConfig,
+    market_infos: Vec<MarketInfo>,
+    legs: Vec<Leg>,
+    handle: DispatcherHandle,
+    signer: Arc<O>,
+    notifications: Notifications,
+    clock: SyncedClock,
+    _taking: tokio::sync::OwnedMutexGuard<()>,
+) {
+    let name = config.markets.join("/");
+    for (i, leg) in legs.iter().enumerate() {
+        match take_leg(
+            &config,
+            &market_infos,
+            leg,
+            &handle,
+            signer.as_ref(),
+            &clock,
+        )
+        .await
+        {
+            Ok(price) => log::info!(
+                "Triangle {} leg {}: {:?} {} {} @ {}",
+                name,
+                i + 1,
+                leg.side,
+                leg.base_quantity.round_dp(8).normalize(),
+                leg.market,
+                price
+            ),
+            Err(e) => {
+                log::error!("Triangle {} leg {} on {}: {}", name, i + 1, leg.market, e);
+                notifications.notify(Event::Error {
+                    operation: format!("triangle {} leg {}", name, i + 1),
+                    error: e.to_string(),
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// Takes one leg of a triangle, returning the price it traded at.
+async fn take_leg<O: QuoteSigner>(
+    config: &TriangleConfig,
+    market_infos: &[MarketInfo],
+    leg: &Leg,
+    handle: &DispatcherHandle,
+    signer: &O,
+    clock: &SyncedClock,
+) -> anyhow::Result<Decimal> {
+    let market_info = market_infos
+        .iter()
+        .find(|info| info.alias == leg.market)
+        .ok_or_else(|| anyhow::anyhow!("no market info"))?;
+    let quantity = market_info.round_quantity(leg.base_quantity)?;
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let quote = handle
+        .request_quote(
+            RequestquoteArgs {
+                chain_id: market_info.zigzag_chain_id,
+                market: leg.market.clone(),
+                side: leg.side.clone(),
+                base_quantity: Some(quantity),
+                quote_quantity: None,
+            },
+            timeout,
+        )
+        .await?;
+    let price = quote.price.value()?;
+    if !rfq::within_slippage(&leg.side, price, leg.price, config.max_slippage_bps) {
+        return Err(anyhow::anyhow!(
+            "quoted {}, more than {} bps off the book price {}",
+            price,
+            config.max_slippage_bps,
+            leg.price
+        ));
+    }
+    let order = signer
+        .sign_quote(
+            market_info,
+            leg.side.clone(),
+            price,
+            quote.base_quantity,
+            clock.now() + config.order_expires_secs,
+        )
+        .await?;
+    // Listening before submitting, not to miss a fast fill.
+    let mut orders = handle.stream(|op| matches!(op, Operation::Orderstatus(_)));
+    let ack = handle
+        .submit_order(market_info, order, DEFAULT_REQUEST_TIMEOUT)
+        .await?;
+    wait_for_fill(&mut orders, ack.id, timeout).await?;
+    Ok(price)
+}
+
+/// Flattens the position of a market whose stop triggered, reporting a
+/// failure to the notifiers.
+#[allow(clippy::too_many_arguments)]
+async fn flatten_position<O: QuoteSigner>(
+    config: StopsConfig,
+    market_info: MarketInfo,
+    handle: DispatcherHandle,
+    signer: Arc<O>,
+    position: watch::Receiver<Amount>,
+    summaries: SummaryCache,
+    notifications: Notifications,
+    clock: SyncedClock,
+) {
+    let market = market_info.alias.clone();
+    match flatten(
+        &config,
+        &market_info,
+        &handle,
+        signer.as_ref(),
+        position,
+        &summaries,
+        &clock,
+    )
+    .await
+    {
+        Ok(()) => log::info!("Flattened the position on {}", market),
+        Err(e) => {
+            log::error!("Flattening the position on {}: {}", market, e);
+            notifications.notify(Event::Error {
+                operation: format!("flatten {}", market),
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// Takes RFQ quotes against `position` until it is flat, in `slices`
+/// orders at most. Each slice waits for the position to move before the
+/// next one.
+async fn flatten<O: QuoteSigner>(
+    config: &StopsConfig,
+    market_info: &MarketInfo,
+    handle: &DispatcherHandle,
+    signer: &O,
+    mut position: watch::Receiver<Amount>,
+    summaries: &SummaryCache,
+    clock: &SyncedClock,
+) -> anyhow::Result<()> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    for slice in 0..config.slices {
+        let open = *position.borrow_and_update();
+        if open.is_zero() {
+            return Ok(());
+        }
+        let side = match open > Decimal::ZERO {
+            true => Side::Sell,
+            false => Side::Buy,
+        };
+        // The last slice takes whatever is left.
+        let quantity =
+            market_info.round_quantity(open.abs() / Decimal::from(config.slices - slice))?;
+        let reference = summaries
+            .reference(&market_info.alias)
+            .map(|reference| reference.price)
+            .or_else(|| summaries.mid(&market_info.alias))
+            .ok_or_else(|| anyhow::anyhow!("no reference price"))?;
+        let quote = handle
+            .request_quote(
+                RequestquoteArgs {
+                    chain_id: market_info.zigzag_chain_id,
+                    market: market_info.alias.clone(),
+                    side: side.clone(),
+                    base_quantity: Some(quantity),
+                    quote_quantity: None,
+                },
+                timeout,
+            )
+            .await?;
+        let price = quote.price.value()?;
+        if !rfq::within_slippage(&side, price, reference, config.max_slippage_bps) {
+            return Err(anyhow::anyhow!(
+                "quoted {}, more than {} bps off the reference {}",
+                price,
+                config.max_slippage_bps,
+                reference
+            ));
+        }
+        let order = signer
+            .sign_quote(
+                market_info,
+                side,
+                price,
+                quote.base_quantity,
+                clock.now() + config.order_expires_secs,
+            )
+            .await?;
+        let ack = handle
+            .submit_order(market_info, order, DEFAULT_REQUEST_TIMEOUT)
+            .await?;
+        tokio::time::timeout(timeout, position.changed())
+            .await
+            .map_err(|_| anyhow::anyhow!("order {} not filled within {:?}", ack.id, timeout))??;
+    }
+    match *position.borrow() {
+        open if open.is_zero() => Ok(()),
+        open => Err(anyhow::anyhow!(
+            "{} left after {} slices",
+            open.normalize(),
+            config.slices
+        )),
+    }
+}
+
+/// Waits for the `marketinfo` messages the backend sends after subscribing
+/// to markets, logging anything else received in the meantime.
+async fn wait_for_market_infos(
+    other: &mut mpsc::UnboundedReceiver<Operation>,
+    markets: &[String],
+    timeout: Duration,
+) -> anyhow::Result<Vec<MarketInfo>> {
+    let mut infos: Vec<MarketInfo> = Vec::with_capacity(markets.len());
+    let deadline = tokio::time::Instant::now() + timeout;
+    while infos.len() < markets.len() {
+        // Markets ZigZag does not know never get an answer.
+        let op = match tokio::time::timeout_at(deadline, other.recv()).await {
+            Ok(op) => op,
+            Err(_) => break,
+        };
+        match op {
+            Some(Operation::Marketinfo(MarketinfoArgs { market_info }))
+                if markets.contains(&market_info.alias)
+                    && !infos.iter().any(|i| i.alias == market_info.alias) =>
+            {
+                infos.push(market_info)
+            }
+            Some(op) => log::debug!("Received from zigzag: {:?}", op),
+            None => {
+                return Err(anyhow::anyhow!(
+                    "Zigzag dispatcher stopped before sending market info for {:?}!",
+                    markets
+                ))
+            }
+        }
+    }
+    Ok(infos)
+}
@@ -0,0 +1,417 @@
+/// Captures of websocket sessions, one JSON frame per line. `Recording`
+/// wraps a transport and appends every frame it sees to a capture, and
+/// `Replay` plays a capture's incoming frames back as a transport of its
+/// own, so the bot runs against recorded market data without a websocket.
+use crate::client::Transport;
+use crate::zigzag::Operation;
+use async_trait::async_trait;
+use async_tungstenite::tungstenite::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// Stands in for redacted signed orders.
+const REDACTED: &str = "redacted";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// One line of a capture.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Milliseconds since the epoch
+    pub timestamp_ms: u64,
+    pub direction: Direction,
+    /// Text of the frame, as sent or received
+    pub text: String,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Outgoing operation as captured, with the zksync orders we signed
+/// replaced when redacting.
+pub fn outgoing_text(op: &Operation, redact: bool) -> anyhow::Result<String> {
+    if !redact || !matches!(op, Operation::Submitorder3(_) | Operation::Fillrequest(_)) {
+        return Ok(serde_json::to_string(op)?);
+    }
+    let mut value = serde_json::to_value(op)?;
+    value["args"][2] = Value::String(REDACTED.into());
+    Ok(serde_json::to_string(&value)?)
+}
+
+/// Transport appending the frames of `inner` to a capture.
+pub struct Recording<T> {
+    inner: T,
+    writer: Box<dyn Write + Send>,
+    redact: bool,
+}
+
+impl<T: Transport> Recording<T> {
+    /// Records to `path`, appending to an earlier capture if any.
+    pub fn append(inner: T, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(inner, LineWriter::new(file)))
+    }
+
+    pub fn new(inner: T, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            inner,
+            writer: Box::new(writer),
+            redact: false,
+        }
+    }
+
+    /// Leaves the zksync orders of `submitorder3` and `fillrequest` out of
+    /// the capture.
+    pub fn redact(mut self, redact: bool) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    /// A failed write only loses the frame from the capture.
+    fn write(&mut self, direction: Direction, text: String) {
+        let frame = Frame {
+            timestamp_ms: now_ms(),
+            direction,
+            text,
+        };
+        let result = serde_json::to_writer(&mut self.writer, &frame)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| Ok(self.writer.write_all(b"\n")?));
+        if let Err(e) = result {
+            log::warn!("Failed to record a frame: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for Recording<T> {
+    async fn send(&mut self, op: &Operation) -> anyhow::Result<()> {
+        self.inner.send(op).await?;
+        let text = outgoing_text(op, self.redact)?;
+        self.write(Direction::Out, text);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> anyhow::Result<Message> {
+        let message = self.inner.next().await?;
+        match &message {
+            Message::Text(text) => self.write(Direction::In, text.clone()),
+            Message::Binary(data) => match std::str::from_utf8(data) {
+                Ok(text) => self.write(Direction::In, text.to_owned()),
+                Err(e) => log::warn!("Not recording a non UTF-8 binary frame: {}", e),
+            },
+            _ => (),
+        }
+        Ok(message)
+    }
+
+    async fn close(&mut self) -> anyhow::Result<()> {
+        self.inner.close().await
+    }
+}
+
+/// Transport playing back the incoming frames of a capture. Whatever is
+/// sent goes nowhere, so replays are always dry runs.
+pub struct Replay {
+    lines: Box<dyn Iterator<Item = std::io::Result<String>> + Send>,
+    /// Playback speed over the original, 0 for as fast as possible
+    speed: f64,
+    last_ms: Option<u64>,
+    finished: watch::Sender<bool>,
+}
+
+impl Replay {
+    pub fn open(path: impl AsRef<Path>, speed: f64) -> anyhow::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?), speed))
+    }
+
+    pub fn new(reader: impl BufRead + Send + 'static, speed: f64) -> Self {
+        let (finished, _) = watch::channel(false);
+        Self {
+            lines: Box::new(reader.lines()),
+            speed,
+            last_ms: None,
+            finished,
+        }
+    }
+
+    /// Flips once the whole capture has been played.
+    pub fn finished(&self) -> watch::Receiver<bool> {
+        self.finished.subscribe()
+    }
+
+    /// Next incoming frame, none at the end of the capture.
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let frame: Frame = serde_json::from_str(&line)?;
+            if frame.direction == Direction::In {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Transport for Replay {
+    async fn send(&mut self, op: &Operation) -> anyhow::Result<()> {
+        log::debug!("Replay, not sending {}", outgoing_text(op, true)?);
+        Ok(())
+    }
+
+    /// Waits out the original gap before each frame, scaled by the speed.
+    /// Never returns once the capture is over.
+    async fn next(&mut self) -> anyhow::Result<Message> {
+        let frame = match self.next_frame()? {
+            Some(frame) => frame,
+            None => {
+                if !self.finished.send_replace(true) {
+                    log::info!("Replay finished");
+                }
+                return futures::future::pending().await;
+            }
+        };
+        if let Some(last_ms) = self.last_ms {
+            let gap = frame.timestamp_ms.saturating_sub(last_ms);
+            if self.speed > 0.0 && gap > 0 {
+                tokio::time::sleep(Duration::from_secs_f64(gap as f64 / 1000.0 / self.speed)).await;
+            }
+        }
+        self.last_ms = Some(frame.timestamp_ms);
+        Ok(Message::Text(frame.text))
+    }
+
+    async fn close(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::MockTransport;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer sharing what it writes.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frames(capture: &Shared) -> Vec<Frame> {
+        let bytes = capture.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .expect("utf8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("frame"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_redact() {
+        let incoming = r#"{"op":"lastprice","args":[[["ETH-USDC",2000,1]]]}"#;
+        let transport =
+            MockTransport::with_frames([Message::Text(incoming.into()), Message::Ping(Vec::new())]);
+        let capture = Shared::default();
+        let mut recording = Recording::new(transport, capture.clone()).redact(true);
+        let op: Operation =
+            serde_json::from_value(json!({"op": "login", "args": [1000, "23"]})).expect("login");
+        recording.send(&op).await.expect("send");
+        assert_eq!(
+            recording.next().await.expect("next"),
+            Message::Text(incoming.into())
+        );
+        recording.next().await.expect("next");
+
+        // Control frames are not captured.
+        let frames = frames(&capture);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].direction, Direction::Out);
+        assert_eq!(frames[0].text, r#"{"op":"login","args":[1000,"23"]}"#);
+        assert_eq!(frames[1].direction, Direction::In);
+        assert_eq!(frames[1].text, incoming);
+        assert!(frames[0].timestamp_ms <= frames[1].timestamp_ms);
+    }
+
+    #[cfg(not(feature = "zksync"))]
+    #[test]
+    fn test_redact_signed_orders() {
+        let op: Operation = serde_json::from_value(json!(
+            {"op": "submitorder3", "args": [1000, "ETH-USDC", {"signature": "secret"}]}
+        ))
+        .expect("submitorder3");
+        assert_eq!(
+            outgoing_text(&op, true).expect("outgoing_text"),
+            r#"{"args":[1000,"ETH-USDC","redacted"],"op":"submitorder3"}"#
+        );
+        assert!(outgoing_text(&op, false)
+            .expect("outgoing_text")
+            .contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_replay_timing() {
+        let frame = |timestamp_ms, direction, text: &str| {
+            serde_json::to_string(&Frame {
+                timestamp_ms,
+                direction,
+                text: text.into(),
+            })
+            .expect("to_string")
+        };
+        let capture = [
+            frame(1_000, Direction::Out, "sent"),
+            frame(1_000, Direction::In, "first"),
+            String::new(),
+            frame(1_200, Direction::In, "second"),
+        ]
+        .join("\n");
+        let mut replay = Replay::new(std::io::Cursor::new(capture), 2.0);
+        let mut finished = replay.finished();
+        replay
+            .send(&Operation::Orders(crate::zigzag::OrdersArgs {
+                orders: Vec::new(),
+            }))
+            .await
+            .expect("send");
+        assert_eq!(
+            replay.next().await.expect("next"),
+            Message::Text("first".into())
+        );
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            replay.next().await.expect("next"),
+            Message::Text("second".into())
+        );
+        // 200 ms at twice the speed.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(!*finished.borrow());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), replay.next())
+                .await
+                .is_err()
+        );
+        finished.changed().await.expect("changed");
+        assert!(*finished.borrow());
+    }
+
+    /// Records a session with the mock server through a dispatcher, then
+    /// replays the capture through another one: both hand out the same
+    /// operations.
+    #[cfg(feature = "bot")]
+    #[tokio::test]
+    async fn test_replay_matches_recording() {
+        use crate::client::ZigzagClient;
+        use crate::connection::{Backoff, Connection, Heartbeat};
+        use crate::dispatcher::{Dispatcher, Receivers};
+        use crate::mockserver::MockServer;
+        use crate::zigzag::{fixtures, SubscribemarketArgs};
+        use rust_decimal_macros::dec;
+
+        fn drain(receivers: &mut Receivers) -> Vec<String> {
+            let mut ops = Vec::new();
+            while let Ok(op) = receivers.market_data.try_recv() {
+                ops.push(format!("market data {:?}", op));
+            }
+            while let Ok(op) = receivers.orders.try_recv() {
+                ops.push(format!("orders {:?}", op));
+            }
+            while let Ok(op) = receivers.other.try_recv() {
+                ops.push(format!("other {:?}", op));
+            }
+            while let Ok(e) = receivers.errors.try_recv() {
+                ops.push(format!("error {:?}", e));
+            }
+            ops
+        }
+
+        let server = MockServer::start()
+            .await
+            .expect("start")
+            .with_market(fixtures::market_info("ETH-USDC", 0, 2), dec!(2000));
+        let connection = Connection::connect(
+            server.url(),
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(10)),
+            Heartbeat {
+                interval: Duration::from_secs(60),
+                timeout: Duration::from_secs(60),
+            },
+        )
+        .await
+        .expect("connect");
+        let capture = Shared::default();
+        let recording = Recording::new(connection, capture.clone());
+        let (dispatcher, handle, mut receivers) = Dispatcher::new(ZigzagClient::new(recording));
+        let live = tokio::spawn(dispatcher.run());
+        let op = |value| serde_json::from_value::<Operation>(value).expect("from_value");
+        handle
+            .send(op(json!({"op": "login", "args": [1000, "23"]})))
+            .expect("send");
+        handle
+            .send(Operation::Subscribemarket(SubscribemarketArgs {
+                chain_id: 1000,
+                market: "ETH-USDC".into(),
+            }))
+            .expect("send");
+        server.inject_text("garbage").expect("inject_text");
+        server
+            .inject_error(
+                crate::zigzag::OperationName::Indicateliq2,
+                "Rate limit exceeded",
+            )
+            .expect("inject_error");
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_secs(5), receivers.market_data.recv())
+                .await
+                .expect("liquidity2");
+        }
+        handle.close(Duration::from_secs(5)).await.expect("close");
+        live.await.expect("join").expect("run");
+        let recorded = drain(&mut receivers);
+
+        let text: Vec<String> = frames(&capture)
+            .iter()
+            .map(|frame| serde_json::to_string(frame).expect("to_string"))
+            .collect();
+        let replay = Replay::new(std::io::Cursor::new(text.join("\n")), 0.0);
+        let mut finished = replay.finished();
+        let (dispatcher, _handle, mut receivers) = Dispatcher::new(ZigzagClient::new(replay));
+        let replayed = tokio::spawn(dispatcher.run());
+        // The last frame is dispatched before the replay looks for another.
+        finished.changed().await.expect("finished");
+        replayed.abort();
+        let mut ops = drain(&mut receivers);
+        // The first three liquidity updates were taken out live.
+        ops.drain(..3);
+        assert_eq!(ops, recorded);
+        assert!(ops.iter().any(|op| op.starts_with("other Marketinfo")));
+        assert!(ops.iter().any(|op| op.starts_with("error")));
+    }
+}
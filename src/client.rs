@@ -0,0 +1,605 @@
+/// Typed client for the ZigZag websocket API. The client works in terms of
+/// `Operation`s and hides the frame-level details of the underlying
+/// transport, so the rest of the bot never has to touch raw messages.
+use crate::metrics::Metrics;
+use crate::receipt::ReceiptError;
+use crate::zigzag::{
+    CancelorderArgs, ChainId, ErrorArgs, Fill, FillreceiptreqArgs, LoginArgs, Market, Operation,
+    OperationName, Order, OrderId, OrderUpdateDetail, OrderreceiptreqArgs, OrderstatusArgs, Strict,
+    SubscribemarketArgs, UserId, ZigzagError,
+};
+use async_trait::async_trait;
+use async_tungstenite::tungstenite::Message;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long request helpers such as `cancel_order` wait for a response.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Frame-level transport the client runs on, implemented by the
+/// reconnecting `Connection` and by mocks in tests.
+#[async_trait]
+pub trait Transport: Send {
+    async fn send(&mut self, op: &Operation) -> anyhow::Result<()>;
+    async fn next(&mut self) -> anyhow::Result<Message>;
+    /// Closes the connection with a close frame.
+    async fn close(&mut self) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl Transport for Box<dyn Transport> {
+    async fn send(&mut self, op: &Operation) -> anyhow::Result<()> {
+        (**self).send(op).await
+    }
+
+    async fn next(&mut self) -> anyhow::Result<Message> {
+        (**self).next().await
+    }
+
+    async fn close(&mut self) -> anyhow::Result<()> {
+        (**self).close().await
+    }
+}
+
+pub struct ZigzagClient<T> {
+    transport: T,
+    chain_id: Option<ChainId>,
+    request_timeout: Duration,
+    // Operations received while a request helper was waiting for its
+    // response, handed out by `recv` before reading anything new.
+    pending: VecDeque<Operation>,
+    metrics: Option<Arc<Metrics>>,
+    strict: bool,
+}
+
+impl<T: Transport> ZigzagClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            chain_id: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            pending: VecDeque::new(),
+            metrics: None,
+            strict: false,
+        }
+    }
+
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    /// Counts the operations sent and received by name, as `sent_<op>` and
+    /// `received_<op>`, and their bytes as `bytes_sent` and `bytes_received`.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Skips fills and orders with more elements than we know as malformed,
+    /// rather than reading what we know of them.
+    pub fn set_strict_protocol(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Chain id of the current session, available after `login`.
+    pub fn chain_id(&self) -> Option<ChainId> {
+        self.chain_id
+    }
+
+    pub async fn send(&mut self, op: Operation) -> anyhow::Result<()> {
+        self.transport.send(&op).await?;
+        if let Some(metrics) = &self.metrics {
+            metrics.incr(&format!("sent_{}", op.name()));
+            // The transport serializes on its own, only for the count.
+            metrics.add("bytes_sent", serde_json::to_string(&op)?.len() as u64);
+        }
+        Ok(())
+    }
+
+    pub async fn close(&mut self) -> anyhow::Result<()> {
+        self.transport.close().await
+    }
+
+    /// Returns the next operation sent by the backend. Frames that cannot be
+    /// parsed are logged and skipped rather than treated as errors.
+    pub async fn recv(&mut self) -> anyhow::Result<Operation> {
+        if let Some(op) = self.pending.pop_front() {
+            return Ok(op);
+        }
+        self.recv_new().await
+    }
+
+    async fn recv_new(&mut self) -> anyhow::Result<Operation> {
+        loop {
+            let text = match self.transport.next().await? {
+                Message::Text(text) => text,
+                Message::Binary(data) => match String::from_utf8(data) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        log::warn!("Skipping non UTF-8 binary frame from zigzag: {}", e);
+                        continue;
+                    }
+                },
+                Message::Close(frame) => {
+                    log::info!("Zigzag closed the connection: {:?}", frame);
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(metrics) = &self.metrics {
+                metrics.add("bytes_received", text.len() as u64);
+            }
+            let op = if self.strict {
+                serde_json::from_str::<Strict<Operation>>(&text).map(|Strict(op)| op)
+            } else {
+                serde_json::from_str::<Operation>(&text)
+            };
+            match op {
+                Ok(op) => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.incr(&format!("received_{}", op.name()));
+                    }
+                    return Ok(op);
+                }
+                Err(e) => log::warn!("Skipping malformed message from zigzag ({}): {}", e, text),
+            }
+        }
+    }
+
+    pub async fn login(&mut self, chain_id: ChainId, user_id: UserId) -> anyhow::Result<()> {
+        self.send(Operation::Login(LoginArgs { chain_id, user_id }))
+            .await?;
+        self.chain_id = Some(chain_id);
+        Ok(())
+    }
+
+    pub async fn subscribe_market(&mut self, market: Market) -> anyhow::Result<()> {
+        let chain_id = self
+            .chain_id
+            .ok_or_else(|| anyhow::anyhow!("Please login before subscribing to a market!"))?;
+        self.send(Operation::Subscribemarket(SubscribemarketArgs {
+            chain_id,
+            market,
+        }))
+        .await
+    }
+
+    /// Cancels an order and waits until the backend acknowledges it, either
+    /// through `cancelorderack` or a canceled `orderstatus` update. Other
+    /// operations received in the meantime, errors of other requests and
+    /// orders included, are kept for `recv`.
+    pub async fn cancel_order(
+        &mut self,
+        chain_id: ChainId,
+        order_id: OrderId,
+    ) -> anyhow::Result<()> {
+        self.send(Operation::Cancelorder(CancelorderArgs {
+            chain_id,
+            order_id,
+        }))
+        .await?;
+        let timeout = self.request_timeout;
+        let ack = async {
+            loop {
+                let op = self.recv_new().await?;
+                match &op {
+                    Operation::Cancelorderack(ack) if ack.order_ids.contains(&order_id) => {
+                        return Ok(());
+                    }
+                    Operation::Orderstatus(OrderstatusArgs { updates })
+                        if updates.iter().any(|u| {
+                            u.order_id == order_id && u.detail == OrderUpdateDetail::Canceled
+                        }) =>
+                    {
+                        self.pending.push_back(op);
+                        return Ok(());
+                    }
+                    Operation::Error(e) if refers_to_cancel(e, order_id) => {
+                        return Err(e.clone().into());
+                    }
+                    _ => self.pending.push_back(op),
+                }
+            }
+        };
+        tokio::time::timeout(timeout, ack).await.map_err(|_| {
+            anyhow::anyhow!(
+                "No cancel acknowledgment for order {} within {:?}",
+                order_id,
+                timeout
+            )
+        })?
+    }
+
+    /// Requests the current state of an order. Fails with
+    /// `ReceiptError::NotFound` when the backend does not know it and with
+    /// `ReceiptError::Timeout` when it does not answer.
+    pub async fn order_receipt(
+        &mut self,
+        chain_id: ChainId,
+        order_id: OrderId,
+    ) -> anyhow::Result<Order> {
+        let request = Operation::Orderreceiptreq(OrderreceiptreqArgs { chain_id, order_id });
+        self.receipt(request, OperationName::Orderreceiptreq, |op| match op {
+            Operation::Orderreceipt(order) if order.id == order_id => Some(order.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Requests the fill of an order, which the backend looks up by the
+    /// order id and answers with it as the fill id. Fails like
+    /// `order_receipt`.
+    pub async fn fill_receipt(
+        &mut self,
+        chain_id: ChainId,
+        order_id: OrderId,
+    ) -> anyhow::Result<Fill> {
+        let request = Operation::Fillreceiptreq(FillreceiptreqArgs { chain_id, order_id });
+        self.receipt(request, OperationName::Fillreceiptreq, |op| match op {
+            Operation::Fillreceipt(fill) if fill.id == order_id => Some(fill.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Sends `request` and waits for the operation `answer` picks, or for
+    /// an error of the `operation`. Other operations are kept for `recv`.
+    async fn receipt<R>(
+        &mut self,
+        request: Operation,
+        operation: OperationName,
+        answer: impl Fn(&Operation) -> Option<R>,
+    ) -> anyhow::Result<R> {
+        self.send(request).await?;
+        let timeout = self.request_timeout;
+        let reply = async {
+            loop {
+                let op = self.recv_new().await?;
+                if let Some(receipt) = answer(&op) {
+                    return Ok(receipt);
+                }
+                match op {
+                    Operation::Error(ErrorArgs {
+                        operation: name,
+                        error: ZigzagError::OrderNotFound(message),
+                    }) if name == operation => {
+                        return Err(ReceiptError::NotFound(message).into());
+                    }
+                    Operation::Error(e) if e.operation == operation => return Err(e.into()),
+                    op => self.pending.push_back(op),
+                }
+            }
+        };
+        tokio::time::timeout(timeout, reply)
+            .await
+            .map_err(|_| ReceiptError::Timeout(timeout))?
+    }
+}
+
+/// Whether an error answers the cancel of `order_id`: it names that order,
+/// or it is a `cancelorder` error naming no order at all. Errors of other
+/// requests, or cancels of other orders, are not ours to fail on.
+fn refers_to_cancel(e: &ErrorArgs, order_id: OrderId) -> bool {
+    match named_order(e.error.message()) {
+        Some(named) => named == order_id,
+        None => e.operation == OperationName::Cancelorder,
+    }
+}
+
+/// The order id an error message names, as in "Order 40 not found".
+fn named_order(message: &str) -> Option<OrderId> {
+    let mut words = message.split_whitespace();
+    while let Some(word) = words.next() {
+        if word.eq_ignore_ascii_case("order") {
+            let id = words.next()?.trim_start_matches('#');
+            if let Ok(id) = id.trim_end_matches(|c: char| !c.is_ascii_digit()).parse() {
+                return Some(id);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::zigzag::{Decimal, OrderStatus};
+    use async_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+    use futures::future;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Transport replaying canned frames and recording sent operations.
+    /// Once the frames run out it either fails, or hangs like an idle
+    /// connection when `hang` is set. `sent` and `closed` are shared so they
+    /// can be inspected after the transport has been moved.
+    #[derive(Default)]
+    pub struct MockTransport {
+        pub incoming: VecDeque<Message>,
+        pub sent: Arc<Mutex<Vec<Value>>>,
+        pub closed: Arc<AtomicBool>,
+        pub hang: bool,
+    }
+
+    impl MockTransport {
+        pub fn with_frames<I: IntoIterator<Item = Message>>(frames: I) -> Self {
+            Self {
+                incoming: frames.into_iter().collect(),
+                ..Default::default()
+            }
+        }
+
+        pub fn hanging<I: IntoIterator<Item = Message>>(frames: I) -> Self {
+            Self {
+                hang: true,
+                ..Self::with_frames(frames)
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        async fn send(&mut self, op: &Operation) -> anyhow::Result<()> {
+            self.sent.lock().unwrap().push(serde_json::to_value(op)?);
+            Ok(())
+        }
+
+        async fn next(&mut self) -> anyhow::Result<Message> {
+            match self.incoming.pop_front() {
+                Some(message) => Ok(message),
+                None if self.hang => future::pending().await,
+                None => Err(anyhow::anyhow!("Mock transport exhausted")),
+            }
+        }
+
+        async fn close(&mut self) -> anyhow::Result<()> {
+            self.closed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_text_and_binary() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            Message::Text(r#"{"op":"login","args":[1000,"27334"]}"#.into()),
+            Message::Binary(br#"{"op":"refreshliquidity","args":[1,"ETH-USDT"]}"#.to_vec()),
+        ]));
+        let metrics = Arc::new(Metrics::new());
+        client.set_metrics(metrics.clone());
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Login(LoginArgs { chain_id: 1000, .. })
+        ));
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Refreshliquidity(args) if args.market == "ETH-USDT"
+        ));
+        assert!(client.recv().await.is_err());
+        assert_eq!(metrics.counter("received_login"), 1);
+        assert_eq!(metrics.counter("received_refreshliquidity"), 1);
+        assert_eq!(metrics.counter("bytes_received"), 83);
+    }
+
+    #[tokio::test]
+    async fn test_recv_skips_malformed_and_control_frames() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            Message::Text("not json".into()),
+            Message::Text(r#"{"op":"nosuchop","args":[]}"#.into()),
+            Message::Binary(vec![0xff, 0xfe]),
+            Message::Ping(vec![1]),
+            Message::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "bye".into(),
+            })),
+            Message::Text(r#"{"op":"error","args":["submitorder3","Bad order"]}"#.into()),
+        ]));
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Error(args) if args.operation == OperationName::Submitorder3
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recv_strict() {
+        let frames = || {
+            MockTransport::with_frames([
+                Message::Text(include_str!("../tests/fixtures/v2/fills.json").into()),
+                Message::Text(r#"{"op":"login","args":[1000,"27334"]}"#.into()),
+            ])
+        };
+        let mut client = ZigzagClient::new(frames());
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Fills(_)
+        ));
+        let mut client = ZigzagClient::new(frames());
+        client.set_strict_protocol(true);
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Login(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_and_subscribe() {
+        let mut client = ZigzagClient::new(MockTransport::default());
+        let metrics = Arc::new(Metrics::new());
+        client.set_metrics(metrics.clone());
+        assert!(client.subscribe_market("ETH-USDT".into()).await.is_err());
+        client.login(1000, "27334".into()).await.expect("login");
+        client
+            .subscribe_market("ETH-USDT".into())
+            .await
+            .expect("subscribe_market");
+        assert_eq!(client.chain_id(), Some(1000));
+        assert_eq!(
+            *client.transport.sent.lock().unwrap(),
+            vec![
+                json!({"op": "login", "args": [1000, "27334"]}),
+                json!({"op": "subscribemarket", "args": [1000, "ETH-USDT"]}),
+            ]
+        );
+        assert_eq!(metrics.counter("sent_login"), 1);
+        assert_eq!(metrics.counter("sent_subscribemarket"), 1);
+        assert_eq!(
+            metrics.counter("bytes_sent") as usize,
+            r#"{"op":"login","args":[1000,"27334"]}{"op":"subscribemarket","args":[1000,"ETH-USDT"]}"#
+                .len()
+        );
+    }
+
+    fn text(s: &str) -> Message {
+        Message::Text(s.into())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_order_ack() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            text(r#"{"op":"refreshliquidity","args":[1000,"ETH-USDT"]}"#),
+            text(r#"{"op":"cancelorderack","args":[[41]]}"#),
+            text(r#"{"op":"cancelorderack","args":[[40]]}"#),
+            text(r#"{"op":"refreshliquidity","args":[1000,"WBTC-USDT"]}"#),
+        ]));
+        client.cancel_order(1000, 40).await.expect("cancel_order");
+        assert_eq!(
+            *client.transport.sent.lock().unwrap(),
+            vec![json!({"op": "cancelorder", "args": [1000, 40]})]
+        );
+        // Messages that arrived while waiting for the ack are not lost.
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Refreshliquidity(args) if args.market == "ETH-USDT"
+        ));
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Cancelorderack(args) if args.order_ids == vec![41]
+        ));
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Refreshliquidity(args) if args.market == "WBTC-USDT"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_order_status_update() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([text(
+            r#"{"op":"orderstatus","args":[[[1000,40,"c"]]]}"#,
+        )]));
+        client.cancel_order(1000, 40).await.expect("cancel_order");
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Orderstatus(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_order_rejected() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([text(
+            r#"{"op":"error","args":["cancelorder","Order 40 not found"]}"#,
+        )]));
+        let err = client.cancel_order(1000, 40).await.unwrap_err();
+        assert!(err.to_string().contains("Order 40 not found"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_order_other_errors() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            text(r#"{"op":"error","args":["submitorder3","Order is too small"]}"#),
+            text(r#"{"op":"error","args":["cancelorder","Order 41 not found"]}"#),
+            text(r#"{"op":"error","args":["cancelall","Order #40 is not open"]}"#),
+        ]));
+        let err = client.cancel_order(1000, 40).await.unwrap_err();
+        assert!(err.to_string().contains("Order #40 is not open"), "{}", err);
+        // The errors of other requests and orders are kept for `recv`.
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Error(e) if e.operation == OperationName::Submitorder3
+        ));
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Error(e) if e.error.message() == "Order 41 not found"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_order_timeout() {
+        let mut client = ZigzagClient::new(MockTransport::hanging([]));
+        client.set_request_timeout(Duration::from_millis(20));
+        let err = client.cancel_order(1000, 40).await.unwrap_err();
+        assert!(
+            err.to_string().contains("No cancel acknowledgment"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_order_receipt() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            text(
+                r#"{"op":"orderreceipt","args":[1000,8461,"ETH-USDC","s","1970.5",0.5,985.25,1666262459,"48213","f",0]}"#,
+            ),
+            text(
+                r#"{"op":"orderreceipt","args":[1000,8462,"ETH-USDC","s","1970.5",0.5,985.25,1666262459,"48213","pf",0.2,"0x8a3c2f1e0d9b8a7c6e5d4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a39"]}"#,
+            ),
+        ]));
+        let order = client
+            .order_receipt(1000, 8462)
+            .await
+            .expect("order_receipt");
+        assert_eq!(order.order_status, OrderStatus::PartialFill);
+        assert_eq!(
+            order.remaining.and_then(|r| r.amount()),
+            Some(Decimal::new(2, 1))
+        );
+        assert!(order.tx_hash.is_some());
+        assert_eq!(
+            *client.transport.sent.lock().unwrap(),
+            vec![json!({"op": "orderreceiptreq", "args": [1000, 8462]})]
+        );
+        // The receipt of another order is kept.
+        assert!(matches!(
+            client.recv().await.expect("recv"),
+            Operation::Orderreceipt(order) if order.id == 8461
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fill_receipt() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([text(
+            r#"{"op":"fillreceipt","args":[1000,3310,"ETH-USDC","b",1969.8,0.25,"f","0x8a3c2f1e0d9b8a7c6e5d4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a39","48213","51277",0.0003,"ETH","2022-10-20T10:41:07.312Z"]}"#,
+        )]));
+        let fill = client.fill_receipt(1000, 3310).await.expect("fill_receipt");
+        assert_eq!(fill.fill_status, OrderStatus::Filled);
+        assert_eq!(
+            *client.transport.sent.lock().unwrap(),
+            vec![json!({"op": "fillreceiptreq", "args": [1000, 3310]})]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receipt_errors() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            text(r#"{"op":"error","args":["cancelorder","Order 40 not found"]}"#),
+            text(r#"{"op":"error","args":["orderreceiptreq","Order not found"]}"#),
+            text(r#"{"op":"error","args":["fillreceiptreq","Not logged in"]}"#),
+        ]));
+        let err = client.order_receipt(1000, 40).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReceiptError>(),
+            Some(&ReceiptError::NotFound("Order not found".into()))
+        );
+        let err = client.fill_receipt(1000, 40).await.unwrap_err();
+        assert!(err.downcast_ref::<ReceiptError>().is_none());
+        assert!(err.to_string().contains("Not logged in"), "{}", err);
+
+        let mut client = ZigzagClient::new(MockTransport::hanging([]));
+        client.set_request_timeout(Duration::from_millis(20));
+        let err = client.order_receipt(1000, 40).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReceiptError>(),
+            Some(&ReceiptError::Timeout(Duration::from_millis(20)))
+        );
+    }
+}
@@ -0,0 +1,276 @@
+/// Estimate of the backend's clock. ZigZag checks `expires` fields and
+/// order ages against its own time, so a drifting local clock makes quotes
+/// and orders expire early or late. The offset is learned from the
+/// timestamps of fresh fill receipts and fill statuses, against the local
+/// time they were received at.
+use crate::zigzag::{Fill, Operation, OrderStatus, Timestamp};
+use chrono::DateTime;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `[clock]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    /// Local time after which an observation weighs as much as the
+    /// estimate before it
+    pub half_life_secs: u64,
+    /// Offsets beyond this are logged as warnings
+    pub warn_offset_secs: u64,
+    /// Observations further off than this are late receipts of old fills,
+    /// not drift, and ignored
+    pub max_offset_secs: u64,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            half_life_secs: 300,
+            warn_offset_secs: 5,
+            max_offset_secs: 600,
+        }
+    }
+}
+
+impl ClockConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.half_life_secs == 0 {
+            return Err(anyhow::anyhow!("clock.half_life_secs must be at least 1!"));
+        }
+        if self.warn_offset_secs >= self.max_offset_secs {
+            return Err(anyhow::anyhow!(
+                "clock.warn_offset_secs must be below clock.max_offset_secs!"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Local unix time in milliseconds.
+pub fn local_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Estimate {
+    /// Backend time minus local time
+    offset_ms: f64,
+    /// Local time of the last observation
+    observed_ms: i64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    estimate: Option<Estimate>,
+    /// Whether the offset was last beyond the warning
+    warned: bool,
+}
+
+/// Local time corrected by the estimated offset of the backend's clock,
+/// the local time until something was observed. Clones share the estimate.
+#[derive(Clone, Debug)]
+pub struct SyncedClock {
+    config: ClockConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for SyncedClock {
+    fn default() -> Self {
+        Self::new(ClockConfig::default())
+    }
+}
+
+impl SyncedClock {
+    pub fn new(config: ClockConfig) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    /// Backend time in seconds, as in `expires` fields.
+    pub fn now(&self) -> Timestamp {
+        self.at(local_millis())
+    }
+
+    /// Backend time at the local time `local` in seconds.
+    pub fn backend_time(&self, local: Timestamp) -> Timestamp {
+        match secs_to_millis(local) {
+            Some(local_ms) => self.at(local_ms),
+            None => local,
+        }
+    }
+
+    /// Backend time at the local time `local_ms`.
+    pub fn at(&self, local_ms: i64) -> Timestamp {
+        let backend_ms = local_ms.saturating_add(self.offset_millis().unwrap_or(0));
+        (backend_ms.max(0) / 1000) as Timestamp
+    }
+
+    /// Backend time minus local time, none before the first observation.
+    pub fn offset_millis(&self) -> Option<i64> {
+        let state = self.state.lock().unwrap();
+        state
+            .estimate
+            .map(|estimate| estimate.offset_ms.round() as i64)
+    }
+
+    /// Observes the timestamps of an operation received just now. Only
+    /// receipts of fills matched just now and fill statuses are stamped
+    /// when they are sent, snapshots replay older fills.
+    pub fn on_operation(&self, op: &Operation) {
+        let received_ms = local_millis();
+        match op {
+            Operation::Fillreceipt(fill) if fill.fill_status == OrderStatus::Matched => {
+                if let Some(backend_ms) = fill_millis(fill) {
+                    self.observe(backend_ms, received_ms);
+                }
+            }
+            Operation::Fillstatus(args) => {
+                for status in &args.statuses {
+                    // Whole seconds, on average half a second before the
+                    // exact time.
+                    match secs_to_millis(status.timestamp).and_then(|ms| ms.checked_add(500)) {
+                        Some(backend_ms) => self.observe(backend_ms, received_ms),
+                        None => log::debug!(
+                            "Ignoring the out of range time {} of fill {}",
+                            status.timestamp,
+                            status.full_id
+                        ),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes the backend's time `backend_ms` seen at the local time
+    /// `local_ms` into the smoothed offset.
+    pub fn observe(&self, backend_ms: i64, local_ms: i64) {
+        let sample = backend_ms.saturating_sub(local_ms) as f64;
+        if sample.abs() > (self.config.max_offset_secs * 1000) as f64 {
+            log::debug!("Ignoring a backend time {} ms off ours", sample);
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let estimate = match state.estimate {
+            Some(estimate) => {
+                let elapsed = local_ms.saturating_sub(estimate.observed_ms).max(0) as f64 / 1000.0;
+                let weight = 1.0 - 0.5f64.powf(elapsed / self.config.half_life_secs as f64);
+                Estimate {
+                    offset_ms: estimate.offset_ms + weight * (sample - estimate.offset_ms),
+                    observed_ms: estimate.observed_ms.max(local_ms),
+                }
+            }
+            None => Estimate {
+                offset_ms: sample,
+                observed_ms: local_ms,
+            },
+        };
+        state.estimate = Some(estimate);
+        let off = estimate.offset_ms.abs() > (self.config.warn_offset_secs * 1000) as f64;
+        if off && !state.warned {
+            log::warn!(
+                "The backend's clock is {:.0} ms off ours, expiries follow the backend",
+                estimate.offset_ms
+            );
+        } else if !off && state.warned {
+            log::info!(
+                "The backend's clock is back within {}s of ours",
+                self.config.warn_offset_secs
+            );
+        }
+        state.warned = off;
+    }
+}
+
+/// Milliseconds of a unix time in seconds, none when out of range.
+fn secs_to_millis(secs: Timestamp) -> Option<i64> {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| secs.checked_mul(1000))
+}
+
+fn fill_millis(fill: &Fill) -> Option<i64> {
+    fill.timestamp
+        .as_deref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock() -> SyncedClock {
+        SyncedClock::new(ClockConfig {
+            half_life_secs: 60,
+            warn_offset_secs: 5,
+            max_offset_secs: 600,
+        })
+    }
+
+    fn warned(clock: &SyncedClock) -> bool {
+        clock.state.lock().unwrap().warned
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let clock = clock();
+        assert_eq!(clock.offset_millis(), None);
+        assert_eq!(clock.at(100_000), 100);
+
+        // The first observation is taken as is.
+        clock.observe(120_000, 100_000);
+        assert_eq!(clock.offset_millis(), Some(20_000));
+        assert_eq!(clock.at(100_000), 120);
+        assert!(warned(&clock));
+        // A half-life later, an observation weighs as much as the estimate.
+        clock.observe(160_000, 160_000);
+        assert_eq!(clock.offset_millis(), Some(10_000));
+        // The same instant adds nothing, a burst of fills counts once.
+        clock.observe(160_000, 160_000);
+        assert_eq!(clock.offset_millis(), Some(10_000));
+        clock.observe(220_000, 220_000);
+        assert_eq!(clock.offset_millis(), Some(5_000));
+        assert!(!warned(&clock));
+
+        // Clones share the estimate.
+        assert_eq!(clock.clone().offset_millis(), Some(5_000));
+    }
+
+    #[test]
+    fn test_old_fills_ignored() {
+        let clock = clock();
+        // A fill stamped 15 minutes ago is a late receipt, not drift.
+        clock.observe(100_000, 1_000_000);
+        assert_eq!(clock.offset_millis(), None);
+        clock.observe(998_000, 1_000_000);
+        assert_eq!(clock.offset_millis(), Some(-2_000));
+        assert_eq!(clock.at(1_000_000), 998);
+        assert_eq!(clock.backend_time(1_000), 998);
+    }
+
+    #[test]
+    fn test_out_of_range_times() {
+        let clock = clock();
+        clock.observe(102_000, 100_000);
+        assert_eq!(clock.backend_time(u64::MAX), u64::MAX);
+        assert_eq!(secs_to_millis(i64::MAX as u64), None);
+        assert_eq!(clock.at(i64::MAX), (i64::MAX / 1000) as Timestamp);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ClockConfig::default().validate().is_ok());
+        let config = ClockConfig {
+            warn_offset_secs: 600,
+            ..ClockConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,1855 @@
+/// Bot configuration, merged from CLI flags, environment variables and an
+/// optional TOML file, in that order of precedence, falling back to
+/// defaults.
+use crate::avellaneda::AvellanedaConfig;
+use crate::balances::BalanceConfig;
+use crate::cli::{ArgNetwork, Args};
+use crate::clock::ClockConfig;
+use crate::dedup::DedupConfig;
+use crate::deposit::DepositConfig;
+use crate::dispatcher::ChainCheck;
+use crate::feeds::{FeedsConfig, Source};
+use crate::fees::FeeConfig;
+use crate::hedge::HedgeConfig;
+use crate::keys::KeySource;
+use crate::killswitch::KillSwitchConfig;
+use crate::notify::NotifyConfig;
+use crate::proxy::Proxy;
+use crate::ratelimit::RateLimitConfig;
+use crate::risk::RiskLimits;
+use crate::rpc::RpcConfig;
+use crate::signals::triangle::TriangleConfig;
+use crate::signals::VolatilityConfig;
+use crate::stops::StopsConfig;
+use crate::strategy::cooldown::FillCooldownConfig;
+use crate::strategy::{LadderConfig, DEFAULT_STRATEGY};
+use crate::zigzag::{ChainId, Decimal, MarketInfo, MarketPair};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use zksync::zksync_types::H256;
+
+/// Contents of a `--config` file. Every key is optional.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub network: Option<ArgNetwork>,
+    pub private_key: Option<String>,
+    pub private_key_file: Option<String>,
+    pub mnemonic: Option<String>,
+    pub mnemonic_file: Option<String>,
+    /// `--key-from` spec
+    pub key_from: Option<String>,
+    pub derivation_index: Option<u32>,
+    pub provider_url: Option<String>,
+    pub fee_token: Option<String>,
+    pub fee_token_fallbacks: Option<Vec<String>>,
+    pub zigzag_url: Option<String>,
+    pub zigzag_chain_id: Option<ChainId>,
+    pub chain_id_check: Option<ChainCheck>,
+    pub exchange_address: Option<String>,
+    pub proxy: Option<String>,
+    pub reconnect_min_delay_ms: Option<u64>,
+    pub reconnect_max_delay_ms: Option<u64>,
+    pub ping_interval_secs: Option<u64>,
+    pub pong_timeout_secs: Option<u64>,
+    pub cancel_on_exit: Option<bool>,
+    pub wait_for_activation: Option<bool>,
+    pub activation_timeout_secs: Option<u64>,
+    pub cancel_missing_orders: Option<bool>,
+    pub ttl_cancel_all: Option<bool>,
+    pub latency_log_secs: Option<u64>,
+    pub valuation_currency: Option<String>,
+    pub valuation_log_secs: Option<u64>,
+    pub db_path: Option<String>,
+    pub snapshot_dir: Option<String>,
+    pub snapshot_secs: Option<u64>,
+    pub unsettled_fill_secs: Option<u64>,
+    pub markets: MarketsFile,
+    #[serde(alias = "defaults")]
+    pub market_maker: MarketMakerFile,
+    pub feeds: FeedsConfig,
+    pub risk: RiskLimits,
+    pub kill_switch: Option<KillSwitchConfig>,
+    pub stops: Option<StopsConfig>,
+    pub triangles: Vec<TriangleConfig>,
+    pub hedge: Option<HedgeConfig>,
+    pub rpc: RpcConfig,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub dedup: DedupConfig,
+    pub clock: ClockConfig,
+    pub balances: Option<BalanceConfig>,
+    pub auto_deposit: Option<bool>,
+    pub deposit: Option<DepositConfig>,
+    pub notify: NotifyConfig,
+    pub wallets: BTreeMap<String, WalletFile>,
+}
+
+/// Name of the wallet of the top-level keys, which quotes and takes on the
+/// markets that do not name another.
+pub const DEFAULT_WALLET: &str = "default";
+
+/// `[wallets.<name>]` table of the config file: the keys of a wallet other
+/// than the default one.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalletFile {
+    pub private_key: Option<String>,
+    pub private_key_file: Option<String>,
+    pub mnemonic: Option<String>,
+    pub mnemonic_file: Option<String>,
+    pub key_from: Option<String>,
+    pub derivation_index: Option<u32>,
+}
+
+impl WalletFile {
+    fn resolve(self, name: &str) -> anyhow::Result<WalletConfig> {
+        let key_from = self
+            .key_from
+            .map(|spec| spec.parse())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Wallet {}: {}", name, e))?;
+        let mut sources = [
+            self.private_key.map(KeySource::Raw),
+            self.private_key_file.map(KeySource::File),
+            self.mnemonic.map(KeySource::Mnemonic),
+            self.mnemonic_file.map(KeySource::MnemonicFile),
+            key_from,
+        ]
+        .into_iter()
+        .flatten();
+        match (sources.next(), sources.next()) {
+            (Some(key_source), None) => Ok(WalletConfig {
+                key_source,
+                derivation_index: self.derivation_index.unwrap_or(0),
+            }),
+            _ => Err(anyhow::anyhow!(
+                "Wallet {} needs exactly one of private_key, private_key_file, mnemonic, mnemonic_file or key_from!",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalletConfig {
+    pub key_source: KeySource,
+    pub derivation_index: u32,
+}
+
+impl WalletConfig {
+    pub fn private_key(&self) -> anyhow::Result<H256> {
+        self.key_source.private_key(self.derivation_index)
+    }
+}
+
+/// `[market_maker]` table of the config file.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketMakerFile {
+    pub spread_bps: Option<Decimal>,
+    pub quote_size: Option<Decimal>,
+    pub quote_expires_secs: Option<u64>,
+    pub requote_threshold_bps: Option<Decimal>,
+    pub requote_margin_secs: Option<u64>,
+    pub clock_skew_secs: Option<u64>,
+    pub rfq_markup_bps: Option<Decimal>,
+    pub rfq_max_size: Option<Decimal>,
+    pub max_position: Option<Decimal>,
+    pub price_skew_bps: Option<Decimal>,
+    pub size_skew: Option<Decimal>,
+    pub fees: Option<FeeConfig>,
+    pub ladder: Option<LadderConfig>,
+    pub volatility: Option<VolatilityConfig>,
+    pub strategy: Option<String>,
+    pub avellaneda: Option<AvellanedaConfig>,
+    pub fill_cooldown: Option<FillCooldownConfig>,
+    pub feed: Option<Source>,
+    pub order_ttl_secs: Option<u64>,
+    pub quote_wallet: Option<String>,
+    pub take_wallet: Option<String>,
+}
+
+impl MarketMakerFile {
+    /// Keys of `self`, falling back to those of `defaults`.
+    fn or(self, defaults: &Self) -> Self {
+        let defaults = defaults.clone();
+        Self {
+            spread_bps: self.spread_bps.or(defaults.spread_bps),
+            quote_size: self.quote_size.or(defaults.quote_size),
+            quote_expires_secs: self.quote_expires_secs.or(defaults.quote_expires_secs),
+            requote_threshold_bps: self
+                .requote_threshold_bps
+                .or(defaults.requote_threshold_bps),
+            requote_margin_secs: self.requote_margin_secs.or(defaults.requote_margin_secs),
+            clock_skew_secs: self.clock_skew_secs.or(defaults.clock_skew_secs),
+            rfq_markup_bps: self.rfq_markup_bps.or(defaults.rfq_markup_bps),
+            rfq_max_size: self.rfq_max_size.or(defaults.rfq_max_size),
+            max_position: self.max_position.or(defaults.max_position),
+            price_skew_bps: self.price_skew_bps.or(defaults.price_skew_bps),
+            size_skew: self.size_skew.or(defaults.size_skew),
+            fees: self.fees.or(defaults.fees),
+            ladder: self.ladder.or(defaults.ladder),
+            volatility: self.volatility.or(defaults.volatility),
+            strategy: self.strategy.or(defaults.strategy),
+            avellaneda: self.avellaneda.or(defaults.avellaneda),
+            fill_cooldown: self.fill_cooldown.or(defaults.fill_cooldown),
+            feed: self.feed.or(defaults.feed),
+            order_ttl_secs: self.order_ttl_secs.or(defaults.order_ttl_secs),
+            quote_wallet: self.quote_wallet.or(defaults.quote_wallet),
+            take_wallet: self.take_wallet.or(defaults.take_wallet),
+        }
+    }
+}
+
+/// `markets` of the config file: either a list of markets, or one
+/// `[markets."ETH-USDT"]` table per market, with the keys of
+/// `[market_maker]` (also named `[defaults]`) that differ on that market.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MarketsFile(pub Vec<(String, MarketMakerFile)>);
+
+impl<'de> Deserialize<'de> for MarketsFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MarketsVisitor;
+
+        impl<'de> Visitor<'de> for MarketsVisitor {
+            type Value = MarketsFile;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of markets or a table of market settings")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut markets = Vec::new();
+                while let Some(market) = seq.next_element::<String>()? {
+                    markets.push((market, MarketMakerFile::default()));
+                }
+                Ok(MarketsFile(markets))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut markets: Vec<(String, MarketMakerFile)> = Vec::new();
+                while let Some((market, settings)) = map.next_entry()? {
+                    if markets.iter().any(|(m, _)| *m == market) {
+                        return Err(de::Error::custom(format!("duplicate market {}", market)));
+                    }
+                    markets.push((market, settings));
+                }
+                Ok(MarketsFile(markets))
+            }
+        }
+
+        deserializer.deserialize_any(MarketsVisitor)
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Reading config {} failed: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Parses a config file, naming the offending key on errors.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut deserializer = toml::Deserializer::new(text);
+        serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+            let key = e.path().to_string();
+            let inner = e.into_inner();
+            if key == "." {
+                anyhow::anyhow!("{}", inner)
+            } else {
+                anyhow::anyhow!("key `{}`: {}", key, inner)
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub network: ArgNetwork,
+    pub key_source: Option<KeySource>,
+    pub derivation_index: u32,
+    /// Wallets other than the default one, by name, only configurable in
+    /// the file
+    pub wallets: BTreeMap<String, WalletConfig>,
+    pub provider_url: Option<String>,
+    /// Tokens zksync fees are paid in, by order of preference: the fee
+    /// token, then the fallbacks, which are only configurable in the file
+    pub fee_tokens: Vec<String>,
+    pub zigzag_url: String,
+    pub zigzag_chain_id: ChainId,
+    /// What to do with messages for another chain, only configurable in the
+    /// file
+    pub chain_id_check: ChainCheck,
+    /// Exchange contract EVM orders are signed for, only configurable in
+    /// the file
+    pub exchange_address: Option<String>,
+    /// Proxy of the websocket and the price feeds
+    pub proxy: Option<Proxy>,
+    pub reconnect_min_delay_ms: u64,
+    pub reconnect_max_delay_ms: u64,
+    pub ping_interval_secs: u64,
+    pub pong_timeout_secs: u64,
+    /// Cancel all open orders when shutting down
+    pub cancel_on_exit: bool,
+    /// Wait for the zksync account of a fresh wallet to be created
+    pub wait_for_activation: bool,
+    pub activation_timeout_secs: u64,
+    /// Cancel orders we thought open that a snapshot no longer lists, only
+    /// configurable in the file
+    pub cancel_missing_orders: bool,
+    /// Cancel orders past their `order_ttl_secs` with a single `cancelall`,
+    /// only configurable in the file
+    pub ttl_cancel_all: bool,
+    /// Interval of the log line summarizing response latencies, 0 for none,
+    /// only configurable in the file
+    pub latency_log_secs: u64,
+    /// Token equity and unrealized PnL are valued in
+    pub valuation_currency: String,
+    /// Interval of the log line of the valuation, 0 for none, only
+    /// configurable in the file
+    pub valuation_log_secs: u64,
+    /// SQLite history of orders, fills and liquidity, kept when set
+    pub db_path: Option<String>,
+    /// Directory of the state snapshots, saved when set
+    pub snapshot_dir: Option<String>,
+    /// Interval between snapshots, only configurable in the file
+    pub snapshot_secs: u64,
+    /// Age past which a fill still unsettled is alerted on, 0 for never,
+    /// only configurable in the file
+    pub unsettled_fill_secs: u64,
+    pub markets: Vec<String>,
+    /// Settings of the markets without a `[markets.<market>]` table
+    pub market_maker: MarketMakerSettings,
+    /// Settings of the markets with a table, merged with `market_maker`,
+    /// only configurable in the file
+    pub market_overrides: HashMap<String, MarketMakerSettings>,
+    /// External reference price feeds, only configurable in the file
+    pub feeds: FeedsConfig,
+    /// Risk limits, only configurable in the file
+    pub risk: RiskLimits,
+    /// Only warn about risk limit breaches
+    pub risk_override: bool,
+    /// Skip fills and orders with more elements than this version knows
+    pub strict_protocol: bool,
+    /// Halt on abnormal price moves or error bursts, only configurable in
+    /// the file
+    pub kill_switch: Option<KillSwitchConfig>,
+    /// Stop-loss and take-profit of the positions, only configurable in
+    /// the file
+    pub stops: Option<StopsConfig>,
+    /// Triangular consistency checks of three markets each, only
+    /// configurable in the file
+    pub triangles: Vec<TriangleConfig>,
+    /// Offset the fills on another exchange, only configurable in the file
+    pub hedge: Option<HedgeConfig>,
+    /// Endpoints, timeouts and retries of the zksync RPC, only
+    /// configurable in the file
+    pub rpc: RpcConfig,
+    /// Pace outgoing operations, only configurable in the file
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Memory of the fills and order updates already seen, only
+    /// configurable in the file
+    pub dedup: DedupConfig,
+    /// Estimate of the backend's clock that expiries follow, only
+    /// configurable in the file
+    pub clock: ClockConfig,
+    /// Fit quotes to the committed balances, only configurable in the file
+    pub balances: Option<BalanceConfig>,
+    /// Top up from L1, only configurable in the file and only set with
+    /// `auto_deposit = true`
+    pub auto_deposit: Option<DepositConfig>,
+    /// Chat notifications, only configurable in the file
+    pub notify: NotifyConfig,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarketMakerSettings {
+    pub spread_bps: Decimal,
+    pub quote_size: Decimal,
+    pub quote_expires_secs: u64,
+    pub requote_threshold_bps: Decimal,
+    pub requote_margin_secs: u64,
+    /// Safety margin for the backend's clock running ahead of ours, only
+    /// configurable in the file
+    pub clock_skew_secs: u64,
+    pub rfq_markup_bps: Decimal,
+    /// RFQ requests are only answered when set
+    pub rfq_max_size: Option<Decimal>,
+    /// Quotes are only skewed against the inventory when set
+    pub max_position: Option<Decimal>,
+    pub price_skew_bps: Decimal,
+    pub size_skew: Decimal,
+    /// Quotes only cover zksync swap fees when set, only configurable in
+    /// the file
+    pub fees: Option<FeeConfig>,
+    /// Replaces `spread_bps` and `quote_size` when set, only configurable
+    /// in the file
+    pub ladder: Option<LadderConfig>,
+    /// Spreads widen with the volatility when set, only configurable in the
+    /// file
+    pub volatility: Option<VolatilityConfig>,
+    /// Name of the registered strategy run on each market, only
+    /// configurable in the file
+    pub strategy: String,
+    /// Parameters of the Avellaneda–Stoikov model, only configurable in the
+    /// file
+    pub avellaneda: AvellanedaConfig,
+    /// Sides rest after their fills when set, only configurable in the file
+    pub fill_cooldown: Option<FillCooldownConfig>,
+    /// Feed the reference price is taken from instead of the preferred one,
+    /// only configurable in the file
+    pub feed: Option<Source>,
+    /// Our orders still open this long after their ack are canceled when
+    /// set, only configurable in the file
+    pub order_ttl_secs: Option<u64>,
+    /// Wallet advertising liquidity and answering fill requests, only
+    /// configurable in the file
+    pub quote_wallet: String,
+    /// Wallet taking quotes, only configurable in the file
+    pub take_wallet: String,
+}
+
+impl MarketMakerSettings {
+    /// Problems of the settings, every one of them, empty when valid.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.size_skew < Decimal::ZERO || self.size_skew > Decimal::ONE {
+            problems.push("size_skew must be between 0 and 1!".to_string());
+        }
+        if self.order_ttl_secs == Some(0) {
+            problems.push("order_ttl_secs must be at least 1!".to_string());
+        }
+        let checks = [
+            self.ladder.as_ref().map(LadderConfig::validate),
+            self.volatility.as_ref().map(VolatilityConfig::validate),
+            (self.strategy == "avellaneda").then(|| self.avellaneda.validate()),
+            self.fill_cooldown
+                .as_ref()
+                .map(FillCooldownConfig::validate),
+        ];
+        for check in checks {
+            if let Some(Err(e)) = check {
+                problems.push(e.to_string());
+            }
+        }
+        problems
+    }
+
+    /// Largest total size advertised on one side.
+    pub fn max_side_size(&self) -> Decimal {
+        self.ladder
+            .as_ref()
+            .map_or(self.quote_size, LadderConfig::max_side_size)
+    }
+}
+
+impl Config {
+    /// Merges CLI flags, environment variables (looked up through `env`) and
+    /// the config file.
+    pub fn resolve(
+        args: &Args,
+        env: impl Fn(&str) -> Option<String>,
+        file: ConfigFile,
+    ) -> anyhow::Result<Self> {
+        let key_source = args
+            .private_key
+            .clone()
+            .map(KeySource::Raw)
+            .or_else(|| args.private_key_file.clone().map(KeySource::File))
+            .or_else(|| args.mnemonic.clone().map(KeySource::Mnemonic))
+            .or_else(|| args.mnemonic_file.clone().map(KeySource::MnemonicFile))
+            .map(Ok)
+            .or_else(|| args.key_from.as_deref().map(str::parse))
+            .transpose()?
+            .or_else(|| env("ETH_PRIVKEY").map(KeySource::Raw))
+            .or_else(|| env("ETH_MNEMONIC").map(KeySource::Mnemonic))
+            .or_else(|| file.private_key.map(KeySource::Raw))
+            .or_else(|| file.private_key_file.map(KeySource::File))
+            .or_else(|| file.mnemonic.map(KeySource::Mnemonic))
+            .or_else(|| file.mnemonic_file.map(KeySource::MnemonicFile))
+            .map(Ok)
+            .or_else(|| file.key_from.as_deref().map(str::parse))
+            .transpose()?;
+        let network = args.network.or(file.network).unwrap_or(ArgNetwork::Rinkeby);
+        // The URL and chain id fall back to the network's endpoint
+        // independently, so pointing at another host keeps the chain id.
+        let (default_zigzag_url, default_zigzag_chain_id) = network.zigzag_endpoint();
+        let zigzag_url = args
+            .zigzag_url
+            .clone()
+            .or_else(|| env("ZIGZAG_URL"))
+            .or(file.zigzag_url)
+            .unwrap_or_else(|| default_zigzag_url.to_owned());
+        if !zigzag_url.starts_with("ws://") && !zigzag_url.starts_with("wss://") {
+            return Err(anyhow::anyhow!(
+                "Zigzag URL {} must start with ws:// or wss://!",
+                zigzag_url
+            ));
+        }
+        let zigzag_chain_id =
+            match (args.zigzag_chain_id, env("ZIGZAG_CHAIN_ID")) {
+                (Some(chain_id), _) => Some(chain_id),
+                (None, Some(chain_id)) => Some(chain_id.trim().parse().map_err(|e| {
+                    anyhow::anyhow!("Invalid ZIGZAG_CHAIN_ID {:?}: {}", chain_id, e)
+                })?),
+                (None, None) => file.zigzag_chain_id,
+            }
+            .unwrap_or(default_zigzag_chain_id);
+
+        if file.wallets.contains_key(DEFAULT_WALLET) {
+            return Err(anyhow::anyhow!(
+                "The {} wallet is the one of the top-level keys, name the others!",
+                DEFAULT_WALLET
+            ));
+        }
+        let wallets = file
+            .wallets
+            .into_iter()
+            .map(|(name, wallet)| Ok((name.clone(), wallet.resolve(&name)?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        let mut fee_tokens: Vec<String> = args
+            .fee_token
+            .clone()
+            .or(file.fee_token)
+            .into_iter()
+            .collect();
+        for symbol in file
+            .fee_token_fallbacks
+            .unwrap_or_else(|| vec!["ETH".to_owned()])
+        {
+            if !fee_tokens.iter().any(|s| s.eq_ignore_ascii_case(&symbol)) {
+                fee_tokens.push(symbol);
+            }
+        }
+
+        let mm = file.market_maker;
+        let mut markets = Vec::new();
+        let mut market_overrides = HashMap::new();
+        for (market, settings) in file.markets.0 {
+            // Aliases are uppercased, as ZigZag lists them.
+            let market = String::from(market.parse::<MarketPair>()?);
+            if settings != MarketMakerFile::default() {
+                market_overrides.insert(
+                    market.clone(),
+                    market_maker_settings(args, settings.or(&mm)),
+                );
+            }
+            markets.push(market);
+        }
+        let mut config = Self {
+            network,
+            key_source,
+            derivation_index: args.derivation_index.or(file.derivation_index).unwrap_or(0),
+            wallets,
+            provider_url: args
+                .provider_url
+                .clone()
+                .or_else(|| env("ETH_PROVIDER_URL"))
+                .or(file.provider_url),
+            fee_tokens,
+            zigzag_url,
+            zigzag_chain_id,
+            chain_id_check: file.chain_id_check.unwrap_or_default(),
+            exchange_address: file.exchange_address,
+            proxy: args
+                .proxy
+                .clone()
+                .or_else(|| env("ALL_PROXY"))
+                .or_else(|| env("all_proxy"))
+                .or(file.proxy)
+                .map(|proxy| proxy.parse())
+                .transpose()?,
+            reconnect_min_delay_ms: args
+                .reconnect_min_delay_ms
+                .or(file.reconnect_min_delay_ms)
+                .unwrap_or(500),
+            reconnect_max_delay_ms: args
+                .reconnect_max_delay_ms
+                .or(file.reconnect_max_delay_ms)
+                .unwrap_or(30_000),
+            ping_interval_secs: args
+                .ping_interval_secs
+                .or(file.ping_interval_secs)
+                .unwrap_or(10),
+            pong_timeout_secs: args
+                .pong_timeout_secs
+                .or(file.pong_timeout_secs)
+                .unwrap_or(5),
+            cancel_on_exit: !args.no_cancel_on_exit && file.cancel_on_exit.unwrap_or(true),
+            wait_for_activation: args.wait_for_activation
+                || file.wait_for_activation.unwrap_or(false),
+            activation_timeout_secs: args
+                .activation_timeout_secs
+                .or(file.activation_timeout_secs)
+                .unwrap_or(3600),
+            cancel_missing_orders: file.cancel_missing_orders.unwrap_or(false),
+            ttl_cancel_all: file.ttl_cancel_all.unwrap_or(false),
+            latency_log_secs: file.latency_log_secs.unwrap_or(300),
+            valuation_currency: args
+                .valuation_currency
+                .clone()
+                .or(file.valuation_currency)
+                .unwrap_or_else(|| "USDC".into())
+                .to_uppercase(),
+            valuation_log_secs: file.valuation_log_secs.unwrap_or(300),
+            db_path: args.db_path.clone().or(file.db_path),
+            snapshot_dir: args.snapshot_dir.clone().or(file.snapshot_dir),
+            snapshot_secs: file.snapshot_secs.unwrap_or(60),
+            unsettled_fill_secs: file.unsettled_fill_secs.unwrap_or(600),
+            markets: if args.market.is_empty() {
+                markets
+            } else {
+                args.market
+                    .iter()
+                    .map(|market| market.parse::<MarketPair>().map(String::from))
+                    .collect::<anyhow::Result<_>>()?
+            },
+            market_maker: market_maker_settings(args, mm.clone()),
+            market_overrides,
+            feeds: file.feeds,
+            risk: file.risk,
+            risk_override: args.risk_override,
+            strict_protocol: args.strict_protocol,
+            kill_switch: file.kill_switch,
+            stops: file.stops,
+            triangles: file.triangles,
+            hedge: file.hedge,
+            rpc: file.rpc,
+            rate_limit: file.rate_limit,
+            dedup: file.dedup,
+            clock: file.clock,
+            balances: file.balances,
+            auto_deposit: match (file.auto_deposit, file.deposit) {
+                (Some(true), Some(deposit)) => Some(deposit),
+                (Some(true), None) => {
+                    return Err(anyhow::anyhow!("auto_deposit needs a [deposit] table!"))
+                }
+                _ => None,
+            },
+            notify: file.notify,
+        };
+        config.feeds.pinned = config
+            .markets
+            .iter()
+            .filter_map(|market| Some((market.clone(), config.market_settings(market).feed?)))
+            .collect();
+        // Everything is checked before failing, to report all the problems
+        // at once.
+        let mut problems = Vec::new();
+        if config.ping_interval_secs == 0 {
+            problems.push("ping_interval_secs must be at least 1!".to_string());
+        }
+        if config.snapshot_secs == 0 {
+            problems.push("snapshot_secs must be at least 1!".to_string());
+        }
+        if args.resume && config.snapshot_dir.is_none() {
+            problems.push("Please specify the snapshot_dir to --resume from!".to_string());
+        }
+        let checks = [
+            config.rate_limit.as_ref().map(RateLimitConfig::validate),
+            Some(config.dedup.validate()),
+            Some(config.clock.validate()),
+            config.stops.as_ref().map(StopsConfig::validate),
+            config.hedge.as_ref().map(HedgeConfig::validate),
+            Some(config.rpc.validate()),
+        ];
+        for check in checks {
+            if let Some(Err(e)) = check {
+                problems.push(e.to_string());
+            }
+        }
+        problems.extend(config.market_maker.problems());
+        for market in &config.markets {
+            if let Some(settings) = config.market_overrides.get(market) {
+                problems.extend(
+                    settings
+                        .problems()
+                        .into_iter()
+                        .map(|problem| format!("{}: {}", market, problem)),
+                );
+            }
+            let settings = config.market_settings(market);
+            if let Some(feed) = settings.feed {
+                if config.feeds.source(market) != Some(feed) {
+                    problems.push(format!("{}: no {:?} feed symbol for it", market, feed));
+                }
+            }
+            for wallet in [&settings.quote_wallet, &settings.take_wallet] {
+                if wallet != DEFAULT_WALLET && !config.wallets.contains_key(wallet) {
+                    problems.push(format!("{}: no [wallets.{}] table", market, wallet));
+                }
+            }
+        }
+        fail_on(problems)?;
+        Ok(config)
+    }
+
+    /// Effective market maker settings of `market`.
+    pub fn market_settings(&self, market: &str) -> &MarketMakerSettings {
+        self.market_overrides
+            .get(market)
+            .unwrap_or(&self.market_maker)
+    }
+
+    /// Keys of the wallet named `name`, `None` when they are not set.
+    pub fn wallet(&self, name: &str) -> Option<WalletConfig> {
+        match name {
+            DEFAULT_WALLET => Some(WalletConfig {
+                key_source: self.key_source.clone()?,
+                derivation_index: self.derivation_index,
+            }),
+            name => self.wallets.get(name).cloned(),
+        }
+    }
+
+    /// Wallet quoting on `market`.
+    pub fn quote_wallet(&self, market: &str) -> &str {
+        &self.market_settings(market).quote_wallet
+    }
+
+    /// Wallet taking on `market`.
+    pub fn take_wallet(&self, market: &str) -> &str {
+        &self.market_settings(market).take_wallet
+    }
+
+    /// Wallets quoting on any of the markets, each once, in the order of
+    /// their first market: the sessions market making logs in.
+    pub fn quoting_wallets(&self) -> Vec<&str> {
+        let mut wallets = Vec::new();
+        for market in &self.markets {
+            let wallet = self.quote_wallet(market);
+            if !wallets.contains(&wallet) {
+                wallets.push(wallet);
+            }
+        }
+        wallets
+    }
+
+    /// Markets `wallet` quotes on.
+    pub fn markets_quoted_by(&self, wallet: &str) -> Vec<String> {
+        self.markets
+            .iter()
+            .filter(|market| self.quote_wallet(market) == wallet)
+            .cloned()
+            .collect()
+    }
+
+    /// Markets to subscribe to: the quoted ones, then those only the
+    /// triangles watch.
+    pub fn subscribed_markets(&self) -> Vec<String> {
+        let mut markets = self.markets.clone();
+        for market in self.triangles.iter().flat_map(|t| &t.markets) {
+            if !markets.contains(market) {
+                markets.push(market.clone());
+            }
+        }
+        markets
+    }
+
+    /// Checks the markets against what ZigZag lists, reporting every
+    /// problem at once: unlisted markets and sizes below the minimum.
+    pub fn check_markets(&self, infos: &[MarketInfo]) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        for market in &self.markets {
+            let info = match infos.iter().find(|info| info.alias == *market) {
+                Some(info) => info,
+                None => {
+                    problems.push(format!("{}: not listed by ZigZag", market));
+                    continue;
+                }
+            };
+            let settings = self.market_settings(market);
+            let sizes = match &settings.ladder {
+                Some(ladder) => ladder
+                    .bids
+                    .iter()
+                    .chain(&ladder.asks)
+                    .map(|l| l.size)
+                    .collect(),
+                None => vec![settings.quote_size],
+            };
+            for size in sizes {
+                if let Err(e) = info.check_size(size) {
+                    problems.push(e.to_string());
+                }
+            }
+        }
+        fail_on(problems)
+    }
+
+    /// Effective settings of every market, one block per market.
+    pub fn describe_markets(&self) -> String {
+        self.markets
+            .iter()
+            .map(|market| format!("[{}]\n{:#?}\n", market, self.market_settings(market)))
+            .collect()
+    }
+}
+
+fn fail_on(problems: Vec<String>) -> anyhow::Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "Invalid configuration:\n  {}",
+        problems.join("\n  ")
+    ))
+}
+
+/// Market maker settings from the flags, then `mm`, then the defaults.
+fn market_maker_settings(args: &Args, mm: MarketMakerFile) -> MarketMakerSettings {
+    MarketMakerSettings {
+        spread_bps: args
+            .spread_bps
+            .or(mm.spread_bps)
+            .unwrap_or_else(|| Decimal::from(20)),
+        quote_size: args
+            .quote_size
+            .or(mm.quote_size)
+            .unwrap_or_else(|| Decimal::new(1, 1)),
+        quote_expires_secs: args
+            .quote_expires_secs
+            .or(mm.quote_expires_secs)
+            .unwrap_or(30),
+        requote_threshold_bps: args
+            .requote_threshold_bps
+            .or(mm.requote_threshold_bps)
+            .unwrap_or_else(|| Decimal::from(5)),
+        requote_margin_secs: args
+            .requote_margin_secs
+            .or(mm.requote_margin_secs)
+            .unwrap_or(5),
+        clock_skew_secs: mm.clock_skew_secs.unwrap_or(1),
+        rfq_markup_bps: args
+            .rfq_markup_bps
+            .or(mm.rfq_markup_bps)
+            .unwrap_or_else(|| Decimal::from(10)),
+        rfq_max_size: args.rfq_max_size.or(mm.rfq_max_size),
+        max_position: args.max_position.or(mm.max_position),
+        price_skew_bps: args
+            .price_skew_bps
+            .or(mm.price_skew_bps)
+            .unwrap_or_else(|| Decimal::from(10)),
+        size_skew: args
+            .size_skew
+            .or(mm.size_skew)
+            .unwrap_or_else(|| Decimal::new(5, 1)),
+        fees: mm.fees,
+        ladder: mm.ladder,
+        volatility: mm.volatility,
+        strategy: mm.strategy.unwrap_or_else(|| DEFAULT_STRATEGY.to_owned()),
+        avellaneda: mm.avellaneda.unwrap_or_default(),
+        fill_cooldown: mm.fill_cooldown,
+        feed: mm.feed,
+        order_ttl_secs: mm.order_ttl_secs,
+        quote_wallet: mm.quote_wallet.unwrap_or_else(|| DEFAULT_WALLET.to_owned()),
+        take_wallet: mm.take_wallet.unwrap_or_else(|| DEFAULT_WALLET.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use clap::Parser;
+    use rust_decimal_macros::dec;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_defaults() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert_eq!(config.network, ArgNetwork::Rinkeby);
+        assert_eq!(config.key_source, None);
+        assert_eq!(config.ping_interval_secs, 10);
+        assert_eq!(config.market_maker.quote_expires_secs, 30);
+        assert!(config.cancel_on_exit);
+        assert!(!config.cancel_missing_orders);
+        assert!(!config.ttl_cancel_all);
+        assert_eq!(config.latency_log_secs, 300);
+        assert_eq!(config.valuation_currency, "USDC");
+        assert_eq!(config.market_maker.order_ttl_secs, None);
+        assert_eq!(config.market_maker.clock_skew_secs, 1);
+        assert_eq!(config.db_path, None);
+        assert_eq!(config.snapshot_dir, None);
+        assert_eq!(config.snapshot_secs, 60);
+        assert_eq!(config.unsettled_fill_secs, 600);
+    }
+
+    #[test]
+    fn test_proxy() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let file = ConfigFile::parse(r#"proxy = "http://file:3128""#).expect("parse");
+        let env = |key: &str| (key == "ALL_PROXY").then(|| "socks5://env:1080".to_owned());
+        let config = Config::resolve(&args, env, file.clone()).expect("resolve");
+        assert_eq!(
+            config.proxy,
+            Some("socks5://env:1080".parse().expect("parse"))
+        );
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(
+            config.proxy,
+            Some("http://file:3128".parse().expect("parse"))
+        );
+
+        let args = Args::parse_from(["zigzag-bots", "--proxy", "ftp://cli"]);
+        assert!(Config::resolve(&args, env, ConfigFile::default()).is_err());
+    }
+
+    #[test]
+    fn test_db_path() {
+        let file = ConfigFile::parse(r#"db_path = "file.db""#).expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file.clone()).expect("resolve");
+        assert_eq!(config.db_path.as_deref(), Some("file.db"));
+
+        let args = Args::parse_from(["zigzag-bots", "--db-path", "flag.db"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.db_path.as_deref(), Some("flag.db"));
+    }
+
+    #[test]
+    fn test_snapshot_dir() {
+        let args = Args::parse_from(["zigzag-bots", "--resume"]);
+        assert!(Config::resolve(&args, no_env, ConfigFile::default()).is_err());
+
+        let file = ConfigFile::parse(
+            r#"
+            snapshot_dir = "state"
+            snapshot_secs = 30
+            "#,
+        )
+        .expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.snapshot_dir.as_deref(), Some("state"));
+        assert_eq!(config.snapshot_secs, 30);
+
+        let file = ConfigFile::parse("snapshot_secs = 0").expect("parse");
+        let args = Args::parse_from(["zigzag-bots", "--snapshot-dir", "flag"]);
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_cancel_on_exit() {
+        let file = ConfigFile::parse("cancel_on_exit = false").expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert!(!config.cancel_on_exit);
+
+        let file = ConfigFile::parse("cancel_on_exit = true").expect("parse");
+        let args = Args::parse_from(["zigzag-bots", "--no-cancel-on-exit"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert!(!config.cancel_on_exit);
+
+        let file = ConfigFile::parse("cancel_missing_orders = true").expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert!(config.cancel_missing_orders);
+        let file = ConfigFile::parse("latency_log_secs = 0").expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.latency_log_secs, 0);
+        let file = ConfigFile::parse("valuation_currency = \"usdt\"").expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.valuation_currency, "USDT");
+    }
+
+    #[test]
+    fn test_wait_for_activation() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert!(!config.wait_for_activation);
+        assert_eq!(config.activation_timeout_secs, 3600);
+
+        let file = ConfigFile::parse("wait_for_activation = true\nactivation_timeout_secs = 600")
+            .expect("parse");
+        let args = Args::parse_from(["zigzag-bots", "--activation-timeout-secs", "60"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert!(config.wait_for_activation);
+        assert_eq!(config.activation_timeout_secs, 60);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let file = ConfigFile::parse(
+            r#"
+            network = "mainnet"
+            private_key_file = "key.txt"
+            provider_url = "http://file"
+            zigzag_url = "wss://file"
+            ping_interval_secs = 20
+            pong_timeout_secs = 7
+            markets = ["ETH-USDC", "WBTC-USDC"]
+
+            [market_maker]
+            spread_bps = 30.0
+            quote_size = 0.5
+            rfq_max_size = 2.0
+            "#,
+        )
+        .expect("parse");
+        let env: HashMap<_, _> = [("ETH_PROVIDER_URL", "http://env")].into_iter().collect();
+        let env = |key: &str| env.get(key).map(|v| v.to_string());
+        let args = Args::parse_from([
+            "zigzag-bots",
+            "--ping-interval-secs",
+            "15",
+            "--spread-bps",
+            "40",
+        ]);
+
+        let config = Config::resolve(&args, env, file.clone()).expect("resolve");
+        assert_eq!(config.network, ArgNetwork::Mainnet);
+        assert_eq!(config.key_source, Some(KeySource::File("key.txt".into())));
+        assert_eq!(config.provider_url.as_deref(), Some("http://env"));
+        assert_eq!(config.zigzag_url, "wss://file");
+        assert_eq!(config.zigzag_chain_id, 1);
+        assert_eq!(config.ping_interval_secs, 15);
+        assert_eq!(config.pong_timeout_secs, 7);
+        assert_eq!(config.reconnect_min_delay_ms, 500);
+        assert_eq!(config.markets, vec!["ETH-USDC", "WBTC-USDC"]);
+        assert_eq!(config.market_maker.spread_bps, dec!(40));
+        assert_eq!(config.market_maker.quote_size, dec!(0.5));
+        assert_eq!(config.market_maker.rfq_max_size, Some(dec!(2)));
+        assert_eq!(config.market_maker.rfq_markup_bps, dec!(10));
+
+        let args = Args::parse_from([
+            "zigzag-bots",
+            "--private-key",
+            "cli",
+            "--provider-url",
+            "http://cli",
+        ]);
+        let env = |key: &str| key.starts_with("ETH_").then(|| format!("env {}", key));
+        let config = Config::resolve(&args, env, file.clone()).expect("resolve");
+        assert_eq!(config.key_source, Some(KeySource::Raw("cli".into())));
+        assert_eq!(config.provider_url.as_deref(), Some("http://cli"));
+
+        let args = Args::parse_from([
+            "zigzag-bots",
+            "--market",
+            "ETH-USDT",
+            "--market",
+            "dai-usdt",
+        ]);
+        let config = Config::resolve(&args, no_env, file.clone()).expect("resolve");
+        assert_eq!(config.markets, vec!["ETH-USDT", "DAI-USDT"]);
+        let args = Args::parse_from(["zigzag-bots", "--market", "ETHUSDT"]);
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_env_key_over_file() {
+        let file = ConfigFile::parse(r#"private_key = "file""#).expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let env = |key: &str| (key == "ETH_PRIVKEY").then(|| "env".to_string());
+        let config = Config::resolve(&args, env, file).expect("resolve");
+        assert_eq!(config.key_source, Some(KeySource::Raw("env".into())));
+    }
+
+    #[test]
+    fn test_mnemonic_sources() {
+        let file = ConfigFile::parse(
+            r#"
+            mnemonic_file = "words.txt"
+            derivation_index = 2
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file.clone()).expect("resolve");
+        assert_eq!(
+            config.key_source,
+            Some(KeySource::MnemonicFile("words.txt".into()))
+        );
+        assert_eq!(config.derivation_index, 2);
+
+        let env = |key: &str| (key == "ETH_MNEMONIC").then(|| "env words".to_string());
+        let args = Args::parse_from(["zigzag-bots", "--derivation-index", "3"]);
+        let config = Config::resolve(&args, env, file).expect("resolve");
+        assert_eq!(
+            config.key_source,
+            Some(KeySource::Mnemonic("env words".into()))
+        );
+        assert_eq!(config.derivation_index, 3);
+
+        assert!(
+            Args::try_parse_from(["zigzag-bots", "--private-key", "k", "--mnemonic", "m"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_key_from() {
+        let file = ConfigFile::parse(
+            r#"
+            key_from = "exec:pass show zigzag"
+
+            [wallets.taker]
+            key_from = "keyring:zigzag-bots/taker"
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file.clone()).expect("resolve");
+        assert_eq!(
+            config.key_source,
+            Some(KeySource::Exec("pass show zigzag".into()))
+        );
+        assert_eq!(
+            config.wallet("taker").map(|wallet| wallet.key_source),
+            Some(KeySource::Keyring {
+                service: "zigzag-bots".into(),
+                user: "taker".into()
+            })
+        );
+
+        // The flag wins over the environment.
+        let env = |key: &str| (key == "ETH_PRIVKEY").then(|| "env".to_string());
+        let args = Args::parse_from(["zigzag-bots", "--key-from", "keyring:zigzag-bots/main"]);
+        let config = Config::resolve(&args, env, file).expect("resolve");
+        assert_eq!(
+            config.key_source,
+            Some(KeySource::Keyring {
+                service: "zigzag-bots".into(),
+                user: "main".into()
+            })
+        );
+
+        let args = Args::parse_from(["zigzag-bots", "--key-from", "vault:zigzag"]);
+        assert!(Config::resolve(&args, no_env, ConfigFile::default()).is_err());
+        assert!(Args::try_parse_from([
+            "zigzag-bots",
+            "--private-key",
+            "k",
+            "--key-from",
+            "exec:cat key"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_zigzag_endpoint_overrides() {
+        let args = Args::parse_from(["zigzag-bots", "--network", "goerli"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert_eq!(
+            config.zigzag_url,
+            "wss://secret-thicket-93345.herokuapp.com"
+        );
+        assert_eq!(config.zigzag_chain_id, 1002);
+
+        // Only overriding the URL keeps the network's chain id.
+        let env = |key: &str| (key == "ZIGZAG_URL").then(|| "ws://localhost:3004".to_string());
+        let config = Config::resolve(&args, env, ConfigFile::default()).expect("resolve");
+        assert_eq!(config.zigzag_url, "ws://localhost:3004");
+        assert_eq!(config.zigzag_chain_id, 1002);
+
+        let file = ConfigFile::parse("zigzag_chain_id = 7").expect("parse");
+        let env = |key: &str| (key == "ZIGZAG_CHAIN_ID").then(|| "5".to_string());
+        let config = Config::resolve(&args, env, file.clone()).expect("resolve");
+        assert_eq!(config.zigzag_chain_id, 5);
+        let config = Config::resolve(&args, no_env, file.clone()).expect("resolve");
+        assert_eq!(config.zigzag_chain_id, 7);
+        let args = Args::parse_from(["zigzag-bots", "--zigzag-chain-id", "9"]);
+        let config = Config::resolve(&args, env, file).expect("resolve");
+        assert_eq!(config.zigzag_chain_id, 9);
+        assert_eq!(config.chain_id_check, ChainCheck::Drop);
+
+        let file = ConfigFile::parse(r#"chain_id_check = "warn-only""#).expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.chain_id_check, ChainCheck::WarnOnly);
+        assert!(ConfigFile::parse(r#"chain_id_check = "ignore""#).is_err());
+    }
+
+    #[test]
+    fn test_fee_tokens() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert_eq!(config.fee_tokens, vec!["ETH"]);
+
+        let file = ConfigFile::parse(
+            r#"
+            fee_token = "DAI"
+            fee_token_fallbacks = ["USDC", "dai", "ETH"]
+            "#,
+        )
+        .expect("parse");
+        let config = Config::resolve(&args, no_env, file.clone()).expect("resolve");
+        assert_eq!(config.fee_tokens, vec!["DAI", "USDC", "ETH"]);
+        let args = Args::parse_from(["zigzag-bots", "--fee-token", "USDC"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.fee_tokens, vec!["USDC", "dai", "ETH"]);
+    }
+
+    #[test]
+    fn test_arbitrum() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let file = ConfigFile::parse(
+            r#"
+            network = "arbitrum"
+            exchange_address = "0xcccccccccccccccccccccccccccccccccccccccc"
+            "#,
+        )
+        .expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.network, ArgNetwork::Arbitrum);
+        assert_eq!(config.zigzag_chain_id, 42161);
+        assert_eq!(
+            config.exchange_address.as_deref(),
+            Some("0xcccccccccccccccccccccccccccccccccccccccc")
+        );
+    }
+
+    #[test]
+    fn test_invalid_zigzag_endpoint() {
+        let args = Args::parse_from(["zigzag-bots", "--zigzag-url", "https://example.com"]);
+        assert!(Config::resolve(&args, no_env, ConfigFile::default()).is_err());
+        let args = Args::parse_from(["zigzag-bots"]);
+        let env = |key: &str| (key == "ZIGZAG_CHAIN_ID").then(|| "goerli".to_string());
+        let err = Config::resolve(&args, env, ConfigFile::default()).unwrap_err();
+        assert!(err.to_string().contains("ZIGZAG_CHAIN_ID"), "{}", err);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let file = ConfigFile::parse("ping_interval_secs = 0").expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        assert!(Config::resolve(&args, no_env, file).is_err());
+        let file = ConfigFile::parse("[market_maker]\nsize_skew = 1.5").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+
+        let file = ConfigFile::parse(
+            r#"
+            ping_interval_secs = 0
+
+            [clock]
+            half_life_secs = 0
+
+            [market_maker]
+            size_skew = 1.5
+            order_ttl_secs = 0
+            "#,
+        )
+        .expect("parse");
+        let e = Config::resolve(&args, no_env, file)
+            .expect_err("invalid")
+            .to_string();
+        let problems: Vec<_> = e.lines().skip(1).map(str::trim).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "ping_interval_secs must be at least 1!",
+                "clock.half_life_secs must be at least 1!",
+                "size_skew must be between 0 and 1!",
+                "order_ttl_secs must be at least 1!",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feeds() {
+        let file = ConfigFile::parse(
+            r#"
+            [feeds.binance]
+            max_age_secs = 5
+
+            [feeds.binance.symbols]
+            "ETH-USDT" = "ethusdt"
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let binance = config.feeds.binance.expect("binance");
+        assert_eq!(binance.max_age_secs, 5);
+        assert_eq!(binance.url, crate::feeds::binance::DEFAULT_URL);
+        assert_eq!(binance.symbols["ETH-USDT"], "ethusdt");
+
+        let err = ConfigFile::parse(
+            "[feeds.binance]
+max_age_secs = 5",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("feeds.binance"), "{}", err);
+    }
+
+    #[test]
+    fn test_risk_limits() {
+        let file = ConfigFile::parse(
+            r#"
+            [risk]
+            max_position = 2.5
+            max_open_notional = 10000
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots", "--risk-override"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.risk.max_position, Some(dec!(2.5)));
+        assert_eq!(config.risk.max_order_size, None);
+        assert_eq!(config.risk.max_open_notional, Some(dec!(10000)));
+        assert!(config.risk_override);
+        assert!(ConfigFile::default().risk.is_empty());
+    }
+
+    #[test]
+    fn test_kill_switch() {
+        let file = ConfigFile::parse(
+            r#"
+            [kill_switch]
+            max_errors = 20
+            error_window_secs = 60
+
+            [kill_switch.markets.ETH-USDC]
+            max_move_pct = 3
+            window_secs = 30
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let kill_switch = config.kill_switch.expect("kill_switch");
+        assert_eq!(kill_switch.max_errors, Some(20));
+        assert_eq!(kill_switch.price_move, None);
+        assert_eq!(kill_switch.markets["ETH-USDC"].max_move_pct, dec!(3));
+        assert_eq!(ConfigFile::default().kill_switch, None);
+    }
+
+    #[test]
+    fn test_stops() {
+        let file = ConfigFile::parse(
+            r#"
+            [stops]
+            cooldown_secs = 900
+            slices = 3
+
+            [stops.markets.ETH-USDC]
+            stop_loss_bps = 300
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let stops = config.stops.expect("stops");
+        assert_eq!(stops.cooldown_secs, 900);
+        assert_eq!(stops.hysteresis_bps, dec!(20));
+        assert_eq!(stops.markets["ETH-USDC"].stop_loss_bps, Some(dec!(300)));
+        assert_eq!(stops.markets["ETH-USDC"].take_profit_bps, None);
+
+        let file = ConfigFile::parse(
+            r#"
+            [stops.markets.ETH-USDC]
+            take_profit_bps = -1
+            "#,
+        )
+        .expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_triangles() {
+        let file = ConfigFile::parse(
+            r#"
+            markets = ["ETH-USDT", "WBTC-USDT"]
+
+            [[triangles]]
+            markets = ["ETH-USDT", "WBTC-ETH", "WBTC-USDT"]
+            min_edge_bps = 30
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.triangles[0].min_edge_bps, dec!(30));
+        assert!(!config.triangles[0].active);
+        assert_eq!(
+            config.subscribed_markets(),
+            ["ETH-USDT", "WBTC-USDT", "WBTC-ETH"]
+        );
+        assert!(ConfigFile::parse("[[triangles]]\nmarkets = [\"ETH-USDT\"]").is_err());
+    }
+
+    #[test]
+    fn test_hedge() {
+        let file = ConfigFile::parse(
+            r#"
+            [hedge]
+            order_type = "limit"
+
+            [hedge.markets.ETH-USDC]
+            symbol = "ETHUSDC"
+            quantity_decimals = 4
+
+            [hedge.binance]
+            api_key = "key"
+            api_secret = "secret"
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let hedge = config.hedge.expect("hedge");
+        assert_eq!(hedge.order_type, crate::hedge::OrderType::Limit);
+        assert_eq!(hedge.interval_secs, 2);
+        assert_eq!(hedge.markets["ETH-USDC"].quantity_decimals, 4);
+        assert_eq!(hedge.markets["ETH-USDC"].price_decimals, 8);
+        let binance = hedge.binance.expect("binance");
+        assert_eq!(binance.url, crate::hedge::binance::DEFAULT_URL);
+        assert_eq!(binance.recv_window_ms, 5000);
+
+        // Orders go nowhere without credentials, unless in a dry run.
+        let file = ConfigFile::parse("[hedge.markets]").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+        let file = ConfigFile::parse("[hedge]\ndry_run = true\nmarkets = {}").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_ok());
+    }
+
+    #[test]
+    fn test_rpc() {
+        let file = ConfigFile::parse(
+            r#"
+            [rpc]
+            urls = ["https://api.zksync.io/jsrpc", "https://zksync.example.org/jsrpc"]
+            retries = 5
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.rpc.urls.len(), 2);
+        assert_eq!(config.rpc.retries, 5);
+        assert_eq!(config.rpc.timeout_ms, 10_000);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert!(config.rpc.urls.is_empty());
+
+        let file = ConfigFile::parse("[rpc]\nunhealthy_after = 0").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_market_tables() {
+        let file = ConfigFile::parse(
+            r#"
+            [defaults]
+            spread_bps = 30
+            quote_size = 0.5
+            order_ttl_secs = 300
+
+            [markets.eth-usdt]
+            spread_bps = 15
+            feed = "binance"
+            order_ttl_secs = 60
+
+            [markets."WBTC-USDT"]
+
+            [feeds.binance.symbols]
+            "ETH-USDT" = "ethusdt"
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots", "--quote-size", "0.2"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.markets, vec!["ETH-USDT", "WBTC-USDT"]);
+        let eth = config.market_settings("ETH-USDT");
+        assert_eq!(eth.spread_bps, dec!(15));
+        // Flags still come first.
+        assert_eq!(eth.quote_size, dec!(0.2));
+        assert_eq!(eth.feed, Some(Source::Binance));
+        assert_eq!(eth.order_ttl_secs, Some(60));
+        assert_eq!(config.market_settings("WBTC-USDT"), &config.market_maker);
+        assert_eq!(config.market_maker.spread_bps, dec!(30));
+        assert_eq!(config.market_maker.order_ttl_secs, Some(300));
+        assert_eq!(config.feeds.pinned.len(), 1);
+        assert!(config.describe_markets().starts_with("[ETH-USDT]\n"));
+
+        let mut eth_info = fixtures::market_info("ETH-USDT", 0, 4);
+        eth_info.min_size = Some(dec!(0.5));
+        let wbtc_info = fixtures::market_info("WBTC-USDT", 15, 4);
+        assert!(config
+            .check_markets(std::slice::from_ref(&wbtc_info))
+            .is_err());
+        let e = config
+            .check_markets(&[eth_info.clone(), wbtc_info])
+            .expect_err("min size");
+        assert_eq!(e.to_string().lines().count(), 2);
+        eth_info.min_size = Some(dec!(0.1));
+        let wbtc_info = fixtures::market_info("WBTC-USDT", 15, 4);
+        assert!(config.check_markets(&[eth_info, wbtc_info]).is_ok());
+    }
+
+    #[test]
+    fn test_wallets() {
+        let file = ConfigFile::parse(
+            r#"
+            private_key_file = "maker.txt"
+
+            [wallets.taker]
+            mnemonic_file = "taker.txt"
+            derivation_index = 2
+
+            [wallets.second]
+            private_key = "key"
+
+            [defaults]
+            take_wallet = "taker"
+
+            [markets.ETH-USDC]
+            quote_wallet = "default"
+            take_wallet = "second"
+
+            [markets.ETH-USDT]
+
+            [markets.WBTC-USDT]
+            quote_wallet = "second"
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(
+            config.wallet("taker"),
+            Some(WalletConfig {
+                key_source: KeySource::MnemonicFile("taker.txt".to_owned()),
+                derivation_index: 2,
+            })
+        );
+        assert_eq!(
+            config
+                .wallet(DEFAULT_WALLET)
+                .map(|wallet| wallet.key_source),
+            Some(KeySource::File("maker.txt".to_owned()))
+        );
+        assert_eq!(config.wallet("other"), None);
+        assert_eq!(config.quote_wallet("ETH-USDT"), DEFAULT_WALLET);
+        assert_eq!(config.take_wallet("ETH-USDT"), "taker");
+        assert_eq!(config.quote_wallet("WBTC-USDT"), "second");
+        assert_eq!(config.take_wallet("WBTC-USDT"), "taker");
+        assert_eq!(config.take_wallet("ETH-USDC"), "second");
+        // Markets not configured go to the defaults.
+        assert_eq!(config.quote_wallet("LINK-USDT"), DEFAULT_WALLET);
+        assert_eq!(config.quoting_wallets(), vec![DEFAULT_WALLET, "second"]);
+        assert_eq!(
+            config.markets_quoted_by(DEFAULT_WALLET),
+            vec!["ETH-USDC", "ETH-USDT"]
+        );
+
+        let args = Args::parse_from(["zigzag-bots"]);
+        for text in [
+            "[wallets.default]\nprivate_key = \"key\"",
+            "[wallets.taker]\nderivation_index = 1",
+            "[wallets.taker]\nprivate_key = \"key\"\nmnemonic = \"words\"",
+            "[markets.ETH-USDT]\nquote_wallet = \"missing\"",
+        ] {
+            let file = ConfigFile::parse(text).expect("parse");
+            assert!(Config::resolve(&args, no_env, file).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_market_problems() {
+        let file = ConfigFile::parse(
+            r#"
+            [markets.ETH-USDT]
+            size_skew = 2
+            feed = "chainlink"
+
+            [markets.WBTC-USDT]
+            size_skew = -1
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let e = Config::resolve(&args, no_env, file)
+            .expect_err("invalid")
+            .to_string();
+        let problems: Vec<_> = e.lines().skip(1).map(str::trim).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "ETH-USDT: size_skew must be between 0 and 1!",
+                "ETH-USDT: no Chainlink feed symbol for it",
+                "WBTC-USDT: size_skew must be between 0 and 1!",
+            ]
+        );
+        let e = ConfigFile::parse("[markets.ETH-USDT]\nspred_bps = 1").expect_err("unknown");
+        assert!(e.to_string().contains("spred_bps"), "{}", e);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let file = ConfigFile::parse(
+            r#"
+            [rate_limit.updates]
+            per_sec = 0.5
+            burst = 2
+            "#,
+        )
+        .expect("parse");
+        let rate_limit = Config::resolve(&args, no_env, file)
+            .expect("resolve")
+            .rate_limit
+            .expect("rate_limit");
+        assert_eq!(rate_limit.updates.per_sec, dec!(0.5));
+        assert_eq!(rate_limit.requests, RateLimitConfig::default().requests);
+
+        let file =
+            ConfigFile::parse("[rate_limit.requests]\nper_sec = 0\nburst = 1").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert_eq!(config.dedup, DedupConfig::default());
+        let file = ConfigFile::parse("[dedup]\ncapacity = 500").expect("parse");
+        let dedup = Config::resolve(&args, no_env, file).expect("resolve").dedup;
+        assert_eq!(dedup.capacity, 500);
+        assert_eq!(dedup.retention_secs, DedupConfig::default().retention_secs);
+
+        let file = ConfigFile::parse("[dedup]\nretention_secs = 0").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_clock() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert_eq!(config.clock, ClockConfig::default());
+        let file = ConfigFile::parse("[clock]\nwarn_offset_secs = 2").expect("parse");
+        let clock = Config::resolve(&args, no_env, file).expect("resolve").clock;
+        assert_eq!(clock.warn_offset_secs, 2);
+
+        let file = ConfigFile::parse("[clock]\nhalf_life_secs = 0").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_auto_deposit() {
+        let text = r#"
+            [deposit]
+            max_per_day = 1
+
+            [deposit.tokens.USDC]
+            floor = 500
+            amount = 2000
+            "#;
+        let args = Args::parse_from(["zigzag-bots"]);
+        let file = ConfigFile::parse(text).expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.auto_deposit, None, "not enabled");
+
+        let file = ConfigFile::parse(&format!("auto_deposit = true\n{}", text)).expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let deposit = config.auto_deposit.expect("auto_deposit");
+        assert_eq!(deposit.max_per_day, 1);
+        assert_eq!(deposit.tokens["USDC"].floor, dec!(500));
+
+        let file = ConfigFile::parse("auto_deposit = true").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_balances() {
+        let file = ConfigFile::parse("[balances]").expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.balances, Some(BalanceConfig::default()));
+        let file = ConfigFile::parse("[balances]\npoll_interval_secs = 10").expect("parse");
+        assert_eq!(file.balances.map(|b| b.poll_interval_secs), Some(10));
+    }
+
+    #[test]
+    fn test_fees() {
+        let file = ConfigFile::parse(
+            r#"
+            [market_maker]
+            spread_bps = 30
+
+            [market_maker.fees]
+            min_edge_bps = 2
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let fees = config.market_maker.fees.expect("fees");
+        assert_eq!(fees.min_edge_bps, dec!(2));
+        assert_eq!(fees.max_fee_share, dec!(0.01));
+        assert_eq!(fees.ttl_secs, 60);
+        assert_eq!(ConfigFile::default().market_maker.fees, None);
+    }
+
+    #[test]
+    fn test_ladder() {
+        let file = ConfigFile::parse(
+            r#"
+            [market_maker.ladder]
+            bids = [{ offset_bps = 10, size = 0.5 }, { offset_bps = 25, size = 1 }]
+            asks = [{ offset_bps = 15, size = 0.5 }]
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let ladder = config.market_maker.ladder.expect("ladder");
+        assert_eq!(ladder.bids.len(), 2);
+        assert_eq!(ladder.bids[1].offset_bps, dec!(25));
+        assert_eq!(ladder.asks[0].size, dec!(0.5));
+
+        let file = ConfigFile::parse(
+            r#"
+            [market_maker.ladder]
+            bids = [{ offset_bps = 25, size = 0.5 }, { offset_bps = 10, size = 1 }]
+            "#,
+        )
+        .expect("parse");
+        let error = Config::resolve(&args, no_env, file).expect_err("resolve");
+        assert!(error.to_string().contains("increasing"));
+    }
+
+    #[test]
+    fn test_volatility() {
+        let file = ConfigFile::parse(
+            r#"
+            [market_maker.volatility]
+            half_life_secs = 120
+            max_spread_bps = 80
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let volatility = config.market_maker.volatility.expect("volatility");
+        assert_eq!(volatility.half_life_secs, 120);
+        assert_eq!(volatility.multiplier, dec!(1));
+        assert_eq!(volatility.min_spread_bps, dec!(10));
+        assert_eq!(volatility.max_spread_bps, dec!(80));
+
+        let file =
+            ConfigFile::parse("[market_maker.volatility]\nhalf_life_secs = 0").expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_avellaneda() {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, ConfigFile::default()).expect("resolve");
+        assert_eq!(config.market_maker.strategy, "spread");
+
+        let file = ConfigFile::parse(
+            r#"
+            [market_maker]
+            strategy = "avellaneda"
+
+            [market_maker.avellaneda]
+            gamma = 0.5
+            max_inventory = 2
+            "#,
+        )
+        .expect("parse");
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.market_maker.strategy, "avellaneda");
+        let avellaneda = config.market_maker.avellaneda;
+        assert_eq!(avellaneda.gamma, dec!(0.5));
+        assert_eq!(avellaneda.kappa, dec!(1.5));
+        assert_eq!(avellaneda.max_inventory, Some(dec!(2)));
+
+        let file = ConfigFile::parse(
+            "[market_maker]\nstrategy = \"avellaneda\"\n[market_maker.avellaneda]\nkappa = 0",
+        )
+        .expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_fill_cooldown() {
+        let file = ConfigFile::parse(
+            r#"
+            [market_maker.fill_cooldown]
+            secs = 20
+            min_fill_interval_secs = 2
+
+            [markets.eth-usdt.fill_cooldown]
+            widen_bps = 25
+
+            [markets."WBTC-USDT"]
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        let defaults = config.market_maker.fill_cooldown.clone().expect("defaults");
+        assert_eq!(defaults.secs, 20);
+        assert_eq!(defaults.widen_bps, None);
+        assert_eq!(defaults.min_fill_interval_secs, Some(2));
+        assert_eq!(defaults.halt_secs, 300);
+        // A market table replaces the whole cooldown.
+        let eth = config.market_settings("ETH-USDT").fill_cooldown.clone();
+        assert_eq!(
+            eth,
+            Some(FillCooldownConfig {
+                widen_bps: Some(dec!(25)),
+                ..FillCooldownConfig::default()
+            })
+        );
+        assert_eq!(
+            config.market_settings("WBTC-USDT").fill_cooldown,
+            Some(defaults)
+        );
+
+        let file = ConfigFile::parse(
+            "[market_maker.fill_cooldown]
+halt_secs = 0",
+        )
+        .expect("parse");
+        assert!(Config::resolve(&args, no_env, file).is_err());
+    }
+
+    #[test]
+    fn test_notify() {
+        let file = ConfigFile::parse(
+            r#"
+            [notify]
+            min_interval_secs = 10
+
+            [notify.telegram]
+            bot_token = "123456:secret"
+            chat_id = "42"
+
+            [notify.discord]
+            webhook_url = "https://discord.com/api/webhooks/1/secret"
+            "#,
+        )
+        .expect("parse");
+        let args = Args::parse_from(["zigzag-bots"]);
+        let config = Config::resolve(&args, no_env, file).expect("resolve");
+        assert_eq!(config.notify.min_interval_secs, 10);
+        assert_eq!(config.notify.disconnected_secs, 60);
+        assert_eq!(config.notify.notifiers().len(), 2);
+        assert_eq!(config.notify.telegram.expect("telegram").chat_id, "42");
+        assert!(ConfigFile::default().notify.is_empty());
+    }
+
+    #[test]
+    fn test_errors_name_key() {
+        let err = ConfigFile::parse("[market_maker]\nspread_bps = \"wide\"").unwrap_err();
+        assert!(
+            err.to_string().contains("market_maker.spread_bps"),
+            "{}",
+            err
+        );
+        let err = ConfigFile::parse("network = \"moonnet\"").unwrap_err();
+        assert!(err.to_string().contains("network"), "{}", err);
+        let err = ConfigFile::parse("[market_maker]\nspred_bps = 10.0").unwrap_err();
+        assert!(err.to_string().contains("spred_bps"), "{}", err);
+    }
+}
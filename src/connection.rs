@@ -0,0 +1,512 @@
+/// Websocket connection to ZigZag that transparently reconnects when the
+/// backend drops it. Heroku closes idle connections after roughly a minute,
+/// so the bot must be able to re-establish the session without restarting.
+use crate::client::Transport;
+use crate::proxy::{self, Proxy};
+use crate::session::SessionState;
+use crate::zigzag::Operation;
+use async_trait::async_trait;
+use async_tungstenite::{
+    tokio::ConnectStream,
+    tungstenite::{self, Message},
+    WebSocketStream,
+};
+use futures::{prelude::*, stream::SplitSink};
+use rand::Rng;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, watch, Mutex, Notify},
+    task::JoinHandle,
+    time::Instant,
+};
+
+pub type WsStream = WebSocketStream<ConnectStream>;
+pub type WsSink = SplitSink<WsStream, Message>;
+
+/// Exponential backoff with jitter, used to space out reconnect attempts.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            attempt: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Upper bound of the delay for the current attempt: `min * 2^attempt`,
+    /// capped at `max`.
+    fn ceiling(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.attempt).unwrap_or(u32::MAX);
+        self.min.saturating_mul(factor).min(self.max)
+    }
+
+    /// Returns the delay to wait before the next attempt. Half of the delay
+    /// is fixed and the other half is random, so that several bots dropped
+    /// at the same time do not hammer the backend in lockstep.
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        self.attempt = self.attempt.saturating_add(1);
+        let half = ceiling / 2;
+        let jitter = rand::thread_rng().gen_range(0..=(ceiling - half).as_millis() as u64);
+        half + Duration::from_millis(jitter)
+    }
+}
+
+/// Keepalive settings: a `Ping` is sent every `interval`, and the connection
+/// is considered dead when no `Pong` arrives within `timeout`.
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// One live websocket connection. The write half is shared with the
+/// heartbeat task, which only holds the lock while sending a single ping.
+/// The read half is drained by the reader task, so that pongs are seen even
+/// while nobody asks for the next message.
+struct Session {
+    sink: Arc<Mutex<WsSink>>,
+    messages: mpsc::UnboundedReceiver<Result<Message, tungstenite::Error>>,
+    dead: Arc<Notify>,
+    reader: JoinHandle<()>,
+    heartbeat: JoinHandle<()>,
+}
+
+impl Session {
+    async fn open(url: &str, proxy: Option<&Proxy>, heartbeat: Heartbeat) -> anyhow::Result<Self> {
+        let ws_stream = proxy::connect_websocket(url, proxy).await?;
+        let (sink, stream) = ws_stream.split();
+        let sink = Arc::new(Mutex::new(sink));
+        let (messages_tx, messages) = mpsc::unbounded_channel();
+        let (pong_tx, pong) = watch::channel(Instant::now());
+        let dead = Arc::new(Notify::new());
+        let reader = tokio::spawn(run_reader(stream, messages_tx, pong_tx));
+        let heartbeat = tokio::spawn(run_heartbeat(heartbeat, sink.clone(), pong, dead.clone()));
+        Ok(Self {
+            sink,
+            messages,
+            dead,
+            reader,
+            heartbeat,
+        })
+    }
+
+    async fn send(&self, message: Message) -> anyhow::Result<()> {
+        self.sink.lock().await.send(message).await?;
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.heartbeat.abort();
+    }
+}
+
+/// Forwards the messages of `stream` to `messages` up to its first error,
+/// but for pongs, whose arrival is stored in `pong` instead.
+async fn run_reader<S, E>(
+    mut stream: S,
+    messages: mpsc::UnboundedSender<Result<Message, E>>,
+    pong: watch::Sender<Instant>,
+) where
+    S: Stream<Item = Result<Message, E>> + Unpin,
+{
+    while let Some(message) = stream.next().await {
+        let failed = message.is_err();
+        match message {
+            Ok(Message::Pong(_)) => {
+                pong.send_replace(Instant::now());
+            }
+            message => {
+                if messages.send(message).is_err() {
+                    break;
+                }
+            }
+        }
+        if failed {
+            break;
+        }
+    }
+}
+
+/// Pings through `sink` every interval, and notifies `dead` when `pong` has
+/// not moved past the ping within the timeout. Earlier pongs, such as a
+/// late answer to the previous ping, do not count.
+async fn run_heartbeat<S>(
+    heartbeat: Heartbeat,
+    sink: Arc<Mutex<S>>,
+    mut pong: watch::Receiver<Instant>,
+    dead: Arc<Notify>,
+) where
+    S: Sink<Message> + Unpin,
+    S::Error: fmt::Display,
+{
+    let mut interval = tokio::time::interval(heartbeat.interval);
+    // The first tick of a tokio interval completes immediately.
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let sent = Instant::now();
+        if let Err(e) = sink.lock().await.send(Message::Ping(Vec::new())).await {
+            log::warn!("Sending ping to zigzag failed: {}", e);
+            break;
+        }
+        let answered = async {
+            while *pong.borrow_and_update() < sent {
+                if pong.changed().await.is_err() {
+                    // The reader is gone, and the stream with it.
+                    future::pending::<()>().await;
+                }
+            }
+        };
+        if tokio::time::timeout(heartbeat.timeout, answered)
+            .await
+            .is_err()
+        {
+            log::warn!("No pong from zigzag within {:?}!", heartbeat.timeout);
+            break;
+        }
+    }
+    dead.notify_one();
+}
+
+pub struct Connection {
+    url: String,
+    proxy: Option<Proxy>,
+    session: Session,
+    backoff: Backoff,
+    heartbeat: Heartbeat,
+    // Stateful operations that must be replayed after a reconnect so the
+    // backend restores our session.
+    state: SessionState,
+    reconnects: u64,
+    connected: watch::Sender<bool>,
+    // Set while the session needs to be re-established. A reconnect can be
+    // interrupted when the future driving it is dropped, e.g. by the
+    // dispatcher sending something in the meantime, so it is resumed on the
+    // next call instead of using a half restored session.
+    broken: bool,
+}
+
+impl Connection {
+    pub async fn connect(
+        url: &str,
+        backoff: Backoff,
+        heartbeat: Heartbeat,
+    ) -> anyhow::Result<Self> {
+        Self::connect_through(url, None, backoff, heartbeat).await
+    }
+
+    /// Connects through `proxy`, and reconnects through it too.
+    pub async fn connect_through(
+        url: &str,
+        proxy: Option<&Proxy>,
+        backoff: Backoff,
+        heartbeat: Heartbeat,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: url.to_owned(),
+            proxy: proxy.cloned(),
+            session: Session::open(url, proxy, heartbeat).await?,
+            backoff,
+            heartbeat,
+            state: SessionState::default(),
+            reconnects: 0,
+            connected: watch::channel(true).0,
+            broken: false,
+        })
+    }
+
+    /// Whether the session is up, false while reconnecting.
+    pub fn status(&self) -> watch::Receiver<bool> {
+        self.connected.subscribe()
+    }
+
+    async fn reconnect(&mut self) -> anyhow::Result<()> {
+        self.broken = true;
+        self.connected.send_replace(false);
+        loop {
+            let delay = self.backoff.next_delay();
+            log::info!("Reconnecting to zigzag in {:?}", delay);
+            tokio::time::sleep(delay).await;
+
+            match Session::open(&self.url, self.proxy.as_ref(), self.heartbeat).await {
+                Ok(session) => {
+                    self.session = session;
+                    if let Err(e) = self.replay().await {
+                        log::warn!("Restoring zigzag session failed: {}", e);
+                        continue;
+                    }
+                    self.backoff.reset();
+                    self.broken = false;
+                    self.connected.send_replace(true);
+                    self.reconnects += 1;
+                    log::info!(
+                        "Reconnected to zigzag! (reconnects so far: {})",
+                        self.reconnects
+                    );
+                    return Ok(());
+                }
+                Err(e) => log::warn!("Reconnecting to zigzag failed: {}", e),
+            }
+        }
+    }
+
+    async fn replay(&mut self) -> anyhow::Result<()> {
+        self.state
+            .replay(&mut *self.session.sink.lock().await)
+            .await
+    }
+}
+
+#[async_trait]
+impl Transport for Connection {
+    /// Sends an operation, reconnecting if the connection turns out to be
+    /// dead. Login, market subscriptions and liquidity are remembered for
+    /// replay.
+    async fn send(&mut self, op: &Operation) -> anyhow::Result<()> {
+        let stateful = self.state.record(op);
+        if self.broken {
+            self.reconnect().await?;
+            if stateful {
+                return Ok(());
+            }
+        }
+        let text = serde_json::to_string(op)?;
+        if let Err(e) = self.session.send(Message::Text(text.clone())).await {
+            log::warn!("Sending to zigzag failed: {}", e);
+            self.reconnect().await?;
+            // Stateful operations have already been replayed by reconnect.
+            if !stateful {
+                self.session.send(Message::Text(text)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the next websocket message, reconnecting whenever the stream
+    /// errors out, ends, or the heartbeat declares it dead. Pongs are
+    /// consumed by the reader task and never returned.
+    async fn next(&mut self) -> anyhow::Result<Message> {
+        loop {
+            if self.broken {
+                self.reconnect().await?;
+            }
+            let session = &mut self.session;
+            tokio::select! {
+                message = session.messages.recv() => match message {
+                    Some(Ok(message)) => return Ok(message),
+                    Some(Err(e)) => log::warn!("Zigzag connection error: {}", e),
+                    None => log::warn!("Zigzag connection closed!"),
+                },
+                _ = session.dead.notified() => log::warn!("Zigzag heartbeat failed!"),
+            }
+            self.reconnect().await?;
+        }
+    }
+
+    async fn close(&mut self) -> anyhow::Result<()> {
+        self.session.send(Message::Close(None)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let ceilings = [100, 200, 400, 800, 1000, 1000];
+        for ceiling in ceilings {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_millis(ceiling / 2), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(ceiling), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn test_backoff_reset() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(10));
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_backoff_max_below_min() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(1));
+        let delay = backoff.next_delay();
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+    }
+
+    /// Heartbeat of 5 second timeouts after 10 second intervals, pinging
+    /// into the returned receiver.
+    fn heartbeat(
+        pong: watch::Receiver<Instant>,
+    ) -> (
+        Arc<Notify>,
+        futures::channel::mpsc::UnboundedReceiver<Message>,
+    ) {
+        let (sink, pings) = futures::channel::mpsc::unbounded();
+        let dead = Arc::new(Notify::new());
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        };
+        tokio::spawn(run_heartbeat(
+            heartbeat,
+            Arc::new(Mutex::new(sink)),
+            pong,
+            dead.clone(),
+        ));
+        (dead, pings)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_timeout() {
+        let (_pong, pong_rx) = watch::channel(Instant::now());
+        let (dead, mut pings) = heartbeat(pong_rx);
+        let start = Instant::now();
+        dead.notified().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(15));
+        assert_eq!(pings.next().await, Some(Message::Ping(Vec::new())));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_pong() {
+        let (pong, pong_rx) = watch::channel(Instant::now());
+        let (dead, mut pings) = heartbeat(pong_rx);
+        tokio::spawn(async move {
+            while pings.next().await.is_some() {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                pong.send_replace(Instant::now());
+            }
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_secs(300), dead.notified())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_stale_pong() {
+        let (pong, pong_rx) = watch::channel(Instant::now());
+        let (dead, mut pings) = heartbeat(pong_rx);
+        let start = Instant::now();
+        tokio::spawn(async move {
+            // Answers the first ping twice, the second time between pings,
+            // and the second ping not at all.
+            pings.next().await;
+            pong.send_replace(Instant::now());
+            tokio::time::sleep(Duration::from_secs(8)).await;
+            pong.send_replace(Instant::now());
+            while pings.next().await.is_some() {}
+        });
+        dead.notified().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(25));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reader_records_pongs() {
+        let (messages_tx, mut messages) = mpsc::unbounded_channel();
+        let (pong_tx, pong) = watch::channel(Instant::now());
+        let stream = stream::iter([
+            Ok(Message::Pong(Vec::new())),
+            Ok(Message::Text("fills".into())),
+            Err("reset"),
+            Ok(Message::Text("after the error".into())),
+        ]);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        // Nobody reads the messages while the pong arrives.
+        run_reader(stream, messages_tx, pong_tx).await;
+        assert_eq!(*pong.borrow(), Instant::now());
+        assert_eq!(
+            messages.recv().await,
+            Some(Ok(Message::Text("fills".into())))
+        );
+        assert_eq!(messages.recv().await, Some(Err("reset")));
+        assert_eq!(messages.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_drop() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let url = format!("ws://{}", listener.local_addr().expect("local_addr"));
+        let sent = [
+            json!({"op": "login", "args": [1000, "23"]}),
+            json!({"op": "subscribemarket", "args": [1000, "ETH-USDC"]}),
+            json!({"op": "indicateliq2", "args": [1000, "ETH-USDC", [["b", 2000.0, 0.5]]]}),
+        ];
+        let expected = sent.clone();
+        let server = tokio::spawn(async move {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let (tcp, _) = listener.accept().await.expect("accept");
+                let mut ws = async_tungstenite::tokio::accept_async(tcp)
+                    .await
+                    .expect("accept_async");
+                let mut ops = Vec::new();
+                while ops.len() < 3 {
+                    if let Message::Text(text) = ws.next().await.expect("message").expect("ok") {
+                        ops.push(serde_json::from_str::<Value>(&text).expect("json"));
+                    }
+                }
+                received.push(ops);
+                if received.len() == 2 {
+                    ws.send(Message::Text("restored".into()))
+                        .await
+                        .expect("send");
+                }
+            }
+            received
+        });
+
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(10));
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(60),
+        };
+        let mut connection = Connection::connect(&url, backoff, heartbeat)
+            .await
+            .expect("connect");
+        let mut status = connection.status();
+        for op in &sent {
+            let op: Operation = serde_json::from_value(op.clone()).expect("from_value");
+            connection.send(&op).await.expect("send");
+        }
+        // The server drops the first connection once it has the session, and
+        // answers on the second one once it is restored.
+        let message = tokio::time::timeout(Duration::from_secs(5), connection.next())
+            .await
+            .expect("reconnected")
+            .expect("next");
+        assert_eq!(message, Message::Text("restored".into()));
+        assert_eq!(connection.reconnects, 1);
+        assert!(*status.borrow_and_update());
+
+        let received = server.await.expect("server");
+        assert_eq!(received, [expected.to_vec(), expected.to_vec()]);
+    }
+}
@@ -0,0 +1,303 @@
+/// Guard against the messages the backend repeats, most of all after a
+/// reconnect: fill receipts and order status updates are let through once
+/// per state, so that accounting, storage and notifications only see each
+/// transition once.
+use crate::zigzag::{ChainId, FillId, FillsArgs, Operation, OrderId, OrderStatus, OrderstatusArgs};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// `[dedup]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupConfig {
+    /// Transitions remembered, beyond which the least recently seen are
+    /// forgotten. Fills within the retention are kept regardless.
+    pub capacity: usize,
+    /// How long a transition is remembered after it was last seen
+    pub retention_secs: u64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            retention_secs: 24 * 3600,
+        }
+    }
+}
+
+impl DedupConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.capacity == 0 || self.retention_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "dedup needs a positive capacity and retention_secs!"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A state an order or fill went through, by the short status code.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Transition {
+    Fill(ChainId, FillId, &'static str),
+    Order(ChainId, OrderId, &'static str),
+}
+
+impl Transition {
+    fn fill(chain_id: ChainId, id: FillId, status: &OrderStatus) -> Self {
+        Transition::Fill(chain_id, id, status.code())
+    }
+
+    fn order(chain_id: ChainId, id: OrderId, status: &OrderStatus) -> Self {
+        Transition::Order(chain_id, id, status.code())
+    }
+}
+
+/// Transitions seen, least recently seen first.
+pub struct Dedup {
+    capacity: usize,
+    retention: Duration,
+    // Last sighting of each transition, and the transitions by sighting.
+    seen: HashMap<Transition, (Instant, u64)>,
+    order: BTreeMap<u64, Transition>,
+    next: u64,
+}
+
+impl Dedup {
+    pub fn new(config: &DedupConfig) -> Self {
+        Self {
+            capacity: config.capacity,
+            retention: Duration::from_secs(config.retention_secs),
+            seen: HashMap::new(),
+            order: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// The operation without what was already seen, or `None` when nothing
+    /// is left of it. Snapshots of orders are let through whole, as
+    /// reconciliation needs the full list, but their orders count as seen.
+    pub fn filter(&mut self, op: Operation, now: Instant) -> Option<Operation> {
+        self.expire(now);
+        let op = match op {
+            Operation::Fillreceipt(fill) => {
+                let transition = Transition::fill(fill.chain_id, fill.id, &fill.fill_status);
+                self.first(transition, now)
+                    .then_some(Operation::Fillreceipt(fill))
+            }
+            Operation::Fills(args) => Some(Operation::Fills(FillsArgs {
+                fills: args
+                    .fills
+                    .into_iter()
+                    .filter(|fill| {
+                        self.first(
+                            Transition::fill(fill.chain_id, fill.id, &fill.fill_status),
+                            now,
+                        )
+                    })
+                    .collect(),
+            })),
+            Operation::Userorderack(ack) => {
+                let transition = Transition::order(ack.chain_id, ack.id, &ack.order_status);
+                self.first(transition, now)
+                    .then_some(Operation::Userorderack(ack))
+            }
+            Operation::Orderstatus(args) => {
+                let updates: Vec<_> = args
+                    .updates
+                    .into_iter()
+                    .filter(|update| {
+                        self.first(
+                            Transition::order(update.chain_id, update.order_id, &update.status()),
+                            now,
+                        )
+                    })
+                    .collect();
+                (!updates.is_empty()).then_some(Operation::Orderstatus(OrderstatusArgs { updates }))
+            }
+            Operation::Orders(args) => {
+                for order in &args.orders {
+                    self.first(
+                        Transition::order(order.chain_id, order.id, &order.order_status),
+                        now,
+                    );
+                }
+                Some(Operation::Orders(args))
+            }
+            op => Some(op),
+        };
+        self.evict(now);
+        op
+    }
+
+    /// Records a sighting, returning whether it is the first one.
+    fn first(&mut self, transition: Transition, now: Instant) -> bool {
+        let seq = self.next;
+        self.next += 1;
+        self.order.insert(seq, transition.clone());
+        match self.seen.insert(transition, (now, seq)) {
+            Some((_, previous)) => {
+                self.order.remove(&previous);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Forgets what was last seen longer than the retention ago.
+    fn expire(&mut self, now: Instant) {
+        while let Some((&seq, transition)) = self.order.iter().next() {
+            let (seen_at, _) = self.seen[transition];
+            if now.saturating_duration_since(seen_at) < self.retention {
+                break;
+            }
+            self.seen.remove(transition);
+            self.order.remove(&seq);
+        }
+    }
+
+    /// Forgets the least recently seen beyond the capacity. Fills are only
+    /// forgotten once past the retention, so that none is let through twice
+    /// within it.
+    fn evict(&mut self, now: Instant) {
+        let excess = self.seen.len().saturating_sub(self.capacity);
+        if excess == 0 {
+            return;
+        }
+        let evicted: Vec<_> = self
+            .order
+            .iter()
+            .filter(|(_, transition)| match transition {
+                Transition::Fill(..) => {
+                    now.saturating_duration_since(self.seen[*transition].0) >= self.retention
+                }
+                Transition::Order(..) => true,
+            })
+            .take(excess)
+            .map(|(seq, _)| *seq)
+            .collect();
+        for seq in evicted {
+            if let Some(transition) = self.order.remove(&seq) {
+                self.seen.remove(&transition);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, OrderUpdate, OrderUpdateDetail, Side};
+    use rust_decimal_macros::dec;
+
+    fn canceled(order_id: OrderId) -> OrderUpdate {
+        OrderUpdate {
+            chain_id: 1000,
+            order_id,
+            detail: OrderUpdateDetail::Canceled,
+        }
+    }
+
+    // Operations only compare through JSON, for their zksync orders.
+    fn json(ops: &[Operation]) -> serde_json::Value {
+        serde_json::to_value(ops).expect("to_value")
+    }
+
+    fn config(capacity: usize) -> DedupConfig {
+        DedupConfig {
+            capacity,
+            retention_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_replayed_sequence() {
+        let fill = |id, status| fixtures::fill(id, Side::Buy, dec!(3300), dec!(0.5), status);
+        let mut dedup = Dedup::new(&config(100));
+        let now = Instant::now();
+        let sequence = vec![
+            Operation::Orders(crate::zigzag::OrdersArgs {
+                orders: vec![
+                    fixtures::order(1, OrderStatus::Open),
+                    fixtures::order(2, OrderStatus::Open),
+                ],
+            }),
+            Operation::Fills(FillsArgs {
+                fills: vec![fill(7, OrderStatus::Matched)],
+            }),
+            Operation::Fillreceipt(fill(7, OrderStatus::Filled)),
+            Operation::Orderstatus(OrderstatusArgs {
+                updates: vec![canceled(1)],
+            }),
+        ];
+        let passed: Vec<_> = sequence
+            .iter()
+            .cloned()
+            .filter_map(|op| dedup.filter(op, now))
+            .collect();
+        assert_eq!(json(&passed), json(&sequence));
+
+        // The same again after a reconnect, then a new fill.
+        let mut replay = sequence.clone();
+        replay.push(Operation::Fillreceipt(fill(8, OrderStatus::Filled)));
+        let passed: Vec<_> = replay
+            .into_iter()
+            .filter_map(|op| dedup.filter(op, now))
+            .collect();
+        assert_eq!(
+            json(&passed),
+            json(&[
+                // Snapshots still come, without the fills already seen.
+                sequence[0].clone(),
+                Operation::Fills(FillsArgs { fills: Vec::new() }),
+                Operation::Fillreceipt(fill(8, OrderStatus::Filled)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_eviction_keeps_fills() {
+        let receipt = |id| {
+            let fill = fixtures::fill(id, Side::Buy, dec!(3300), dec!(0.5), OrderStatus::Filled);
+            Operation::Fillreceipt(fill)
+        };
+        let mut dedup = Dedup::new(&config(2));
+        let now = Instant::now();
+        for id in 1..=3 {
+            assert!(dedup.filter(receipt(id), now).is_some());
+        }
+        let updates = vec![canceled(1), canceled(2)];
+        assert!(dedup
+            .filter(Operation::Orderstatus(OrderstatusArgs { updates }), now)
+            .is_some());
+        // Over capacity, order statuses go but fills stay until their
+        // retention ends.
+        assert_eq!(dedup.len(), 3);
+        let later = now + Duration::from_secs(59);
+        for id in 1..=3 {
+            assert!(dedup.filter(receipt(id), later).is_none());
+        }
+        let updates = vec![canceled(1)];
+        assert!(dedup
+            .filter(Operation::Orderstatus(OrderstatusArgs { updates }), later)
+            .is_some());
+
+        // Sightings extend the retention.
+        let retained = now + Duration::from_secs(60);
+        assert!(dedup.filter(receipt(1), retained).is_none());
+        let expired = later + Duration::from_secs(60);
+        assert!(dedup.filter(receipt(2), expired).is_some());
+        assert!(dedup.filter(receipt(1), expired).is_none());
+    }
+}
@@ -0,0 +1,599 @@
+/// Automatic L1 to zksync deposits topping up tokens whose committed
+/// balance falls below a floor. Each top-up goes idle → depositing →
+/// waiting → done or failed, one token at a time, and a daily limit caps
+/// how many deposits are made.
+use crate::balances::BalanceSource;
+use crate::orders::{to_raw, to_units};
+use crate::zigzag::{unix_timestamp, Amount, Asset, Timestamp, Token};
+use async_trait::async_trait;
+use num::BigUint;
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use zksync::{
+    zksync_types::{Address, TokenId, H256, U256},
+    EthereumProvider,
+};
+use zksync_eth_signer::EthereumSigner;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// `[deposit]` table of the config file, only used with
+/// `auto_deposit = true`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DepositConfig {
+    /// Tokens topped up, by symbol
+    pub tokens: BTreeMap<Token, TopUp>,
+    /// Deposits allowed within any 24 hours
+    pub max_per_day: usize,
+    /// Gas a deposit is assumed to use, for the L1 balance check
+    pub gas_limit: u64,
+    pub poll_interval_secs: u64,
+    /// Give up on a deposit not committed on zksync after this long
+    pub commit_timeout_secs: u64,
+}
+
+impl Default for DepositConfig {
+    fn default() -> Self {
+        Self {
+            tokens: BTreeMap::new(),
+            max_per_day: 2,
+            gas_limit: 200_000,
+            poll_interval_secs: 60,
+            commit_timeout_secs: 3600,
+        }
+    }
+}
+
+/// Deposit `amount` when the committed balance drops below `floor`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TopUp {
+    pub floor: Amount,
+    pub amount: Amount,
+}
+
+/// Ethereum calls made by deposits. Amounts are in raw units.
+#[async_trait]
+pub trait L1Wallet: Send + Sync {
+    /// L1 balance of `asset`, the ETH balance for token 0.
+    async fn balance(&self, asset: &Asset) -> anyhow::Result<BigUint>;
+
+    async fn gas_price(&self) -> anyhow::Result<BigUint>;
+
+    /// Sends a deposit to our zksync account, returning the transaction
+    /// hash.
+    async fn deposit(&self, asset: &Asset, amount: BigUint) -> anyhow::Result<H256>;
+
+    /// Waits for the transaction to be mined, returning whether it
+    /// succeeded.
+    async fn wait_for_tx(&self, tx_hash: H256) -> anyhow::Result<bool>;
+}
+
+/// `L1Wallet` of the ethereum provider of a zksync wallet.
+pub struct EthereumL1<S: EthereumSigner> {
+    ethereum: EthereumProvider<S>,
+    address: Address,
+}
+
+impl<S: EthereumSigner> EthereumL1<S> {
+    pub fn new(ethereum: EthereumProvider<S>, address: Address) -> Self {
+        Self { ethereum, address }
+    }
+}
+
+fn to_biguint(value: U256) -> BigUint {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    BigUint::from_bytes_be(&bytes)
+}
+
+fn to_u256(value: &BigUint) -> anyhow::Result<U256> {
+    let bytes = value.to_bytes_be();
+    if bytes.len() > 32 {
+        return Err(anyhow::anyhow!("Amount {} is out of range!", value));
+    }
+    Ok(U256::from_big_endian(&bytes))
+}
+
+#[async_trait]
+impl<S: EthereumSigner> L1Wallet for EthereumL1<S> {
+    async fn balance(&self, asset: &Asset) -> anyhow::Result<BigUint> {
+        let balance = if asset.id == 0 {
+            self.ethereum.balance().await?
+        } else {
+            self.ethereum
+                .erc20_balance(self.address, TokenId(asset.id))
+                .await?
+        };
+        Ok(to_biguint(balance))
+    }
+
+    async fn gas_price(&self) -> anyhow::Result<BigUint> {
+        Ok(to_biguint(self.ethereum.client().get_gas_price().await?))
+    }
+
+    async fn deposit(&self, asset: &Asset, amount: BigUint) -> anyhow::Result<H256> {
+        let token = TokenId(asset.id);
+        if asset.id != 0 && !self.ethereum.is_erc20_deposit_approved(token).await? {
+            return Err(anyhow::anyhow!(
+                "{} deposits are not approved on L1",
+                asset.symbol
+            ));
+        }
+        Ok(self
+            .ethereum
+            .deposit(token, to_u256(&amount)?, self.address)
+            .await?)
+    }
+
+    async fn wait_for_tx(&self, tx_hash: H256) -> anyhow::Result<bool> {
+        let receipt = self.ethereum.wait_for_tx(tx_hash).await?;
+        Ok(receipt.status == Some(1.into()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DepositState {
+    Idle,
+    /// Sent on L1, waiting for it to be mined
+    Depositing {
+        token: Token,
+        tx_hash: H256,
+    },
+    /// Mined, waiting for the committed balance to rise above `balance`
+    Waiting {
+        token: Token,
+        tx_hash: H256,
+        balance: Amount,
+        since: Timestamp,
+    },
+    Done {
+        token: Token,
+        tx_hash: H256,
+    },
+    Failed {
+        token: Token,
+        reason: String,
+    },
+}
+
+pub struct AutoDeposit {
+    config: DepositConfig,
+    l1: Arc<dyn L1Wallet>,
+    l2: Arc<dyn BalanceSource>,
+    /// Assets of the configured tokens
+    assets: Vec<Asset>,
+    /// Times of the deposits of the last 24 hours
+    recent: VecDeque<Timestamp>,
+    state: DepositState,
+}
+
+impl AutoDeposit {
+    /// Tops up the configured tokens among `assets`. Tokens of no market
+    /// are left out with a warning.
+    pub fn new(
+        config: DepositConfig,
+        l1: Arc<dyn L1Wallet>,
+        l2: Arc<dyn BalanceSource>,
+        assets: &[Asset],
+    ) -> Self {
+        let mut known = Vec::new();
+        for token in config.tokens.keys() {
+            match assets.iter().find(|asset| &asset.symbol == token) {
+                Some(asset) => known.push(asset.clone()),
+                None => log::warn!("Not topping up {}: no configured market trades it", token),
+            }
+        }
+        Self {
+            config,
+            l1,
+            l2,
+            assets: known,
+            recent: VecDeque::new(),
+            state: DepositState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> &DepositState {
+        &self.state
+    }
+
+    /// Polls every `poll_interval_secs` until `shutdown` flips.
+    pub async fn run(mut self, mut shutdown: watch::Receiver<bool>) {
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.poll_interval_secs));
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.step(unix_timestamp()).await,
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    /// Moves the state machine one step forward.
+    pub async fn step(&mut self, now: Timestamp) {
+        let state = std::mem::replace(&mut self.state, DepositState::Idle);
+        self.state = match state {
+            DepositState::Idle => self.start(now).await,
+            DepositState::Depositing { token, tx_hash } => self.mined(token, tx_hash, now).await,
+            DepositState::Waiting {
+                token,
+                tx_hash,
+                balance,
+                since,
+            } => self.committed(token, tx_hash, balance, since, now).await,
+            DepositState::Done { .. } | DepositState::Failed { .. } => DepositState::Idle,
+        };
+    }
+
+    fn asset(&self, token: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.symbol == token)
+    }
+
+    async fn committed_balance(&self, asset: &Asset) -> anyhow::Result<Amount> {
+        let raw = self.l2.committed_balance(TokenId(asset.id)).await?;
+        to_units(&raw, asset.decimals)
+    }
+
+    /// Deposits into the first token below its floor, if any.
+    async fn start(&mut self, now: Timestamp) -> DepositState {
+        while matches!(self.recent.front(), Some(t) if t + DAY_SECS <= now) {
+            self.recent.pop_front();
+        }
+        for asset in self.assets.clone() {
+            let top_up = &self.config.tokens[&asset.symbol];
+            let balance = match self.committed_balance(&asset).await {
+                Ok(balance) => balance,
+                Err(e) => {
+                    log::warn!("Polling the {} balance failed: {}", asset.symbol, e);
+                    continue;
+                }
+            };
+            if balance >= top_up.floor {
+                continue;
+            }
+            if self.recent.len() >= self.config.max_per_day {
+                log::warn!(
+                    "Not topping up {} at {}: {} deposits made within 24 hours already",
+                    asset.symbol,
+                    balance,
+                    self.recent.len()
+                );
+                return DepositState::Idle;
+            }
+            let amount = top_up.amount;
+            return match self.deposit(&asset, amount).await {
+                Ok(tx_hash) => {
+                    log::info!(
+                        "Depositing {} {} from L1, {} below the floor of {}: tx {:?}",
+                        amount,
+                        asset.symbol,
+                        balance,
+                        top_up.floor,
+                        tx_hash
+                    );
+                    self.recent.push_back(now);
+                    DepositState::Depositing {
+                        token: asset.symbol.clone(),
+                        tx_hash,
+                    }
+                }
+                Err(e) => self.failed(&asset.symbol, e.to_string()),
+            };
+        }
+        DepositState::Idle
+    }
+
+    /// Checks the L1 balances cover `amount` and the gas, then deposits.
+    async fn deposit(&self, asset: &Asset, amount: Amount) -> anyhow::Result<H256> {
+        let raw = to_raw(amount, asset.decimals)?;
+        let gas = self.l1.gas_price().await? * BigUint::from(self.config.gas_limit);
+        let eth = self.l1.balance(&ETH).await?;
+        let (needed, available) = if asset.id == 0 {
+            (&raw + &gas, eth.clone())
+        } else {
+            (raw.clone(), self.l1.balance(asset).await?)
+        };
+        if available < needed {
+            return Err(anyhow::anyhow!(
+                "L1 balance of {} {} does not cover {} plus gas",
+                to_units(&available, asset.decimals)?,
+                asset.symbol,
+                amount
+            ));
+        }
+        if eth < gas {
+            return Err(anyhow::anyhow!(
+                "L1 balance of {} ETH does not cover the gas",
+                to_units(&eth, ETH.decimals)?
+            ));
+        }
+        self.l1.deposit(asset, raw).await
+    }
+
+    async fn mined(&self, token: Token, tx_hash: H256, now: Timestamp) -> DepositState {
+        let asset = match self.asset(&token) {
+            Some(asset) => asset,
+            None => return self.failed(&token, "unknown token".into()),
+        };
+        match self.l1.wait_for_tx(tx_hash).await {
+            Ok(true) => {}
+            Ok(false) => return self.failed(&token, format!("tx {:?} reverted", tx_hash)),
+            Err(e) => return self.failed(&token, format!("tx {:?}: {}", tx_hash, e)),
+        }
+        // Balances polled since may already include the deposit, which is
+        // then only noticed at the timeout: read it right after mining.
+        match self.committed_balance(asset).await {
+            Ok(balance) => {
+                log::info!(
+                    "Deposit of {} mined in tx {:?}, waiting for zksync",
+                    token,
+                    tx_hash
+                );
+                DepositState::Waiting {
+                    token,
+                    tx_hash,
+                    balance,
+                    since: now,
+                }
+            }
+            Err(e) => self.failed(&token, format!("polling the balance failed: {}", e)),
+        }
+    }
+
+    async fn committed(
+        &self,
+        token: Token,
+        tx_hash: H256,
+        balance: Amount,
+        since: Timestamp,
+        now: Timestamp,
+    ) -> DepositState {
+        let asset = match self.asset(&token) {
+            Some(asset) => asset,
+            None => return self.failed(&token, "unknown token".into()),
+        };
+        match self.committed_balance(asset).await {
+            Ok(current) if current > balance => {
+                log::info!(
+                    "Deposit of {} in tx {:?} committed, balance {}",
+                    token,
+                    tx_hash,
+                    current
+                );
+                return DepositState::Done { token, tx_hash };
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Polling the {} balance failed: {}", token, e),
+        }
+        if now >= since + self.config.commit_timeout_secs {
+            return self.failed(
+                &token,
+                format!(
+                    "tx {:?} not committed after {}s",
+                    tx_hash, self.config.commit_timeout_secs
+                ),
+            );
+        }
+        DepositState::Waiting {
+            token,
+            tx_hash,
+            balance,
+            since,
+        }
+    }
+
+    fn failed(&self, token: &str, reason: String) -> DepositState {
+        log::error!("Depositing {} failed: {}", token, reason);
+        DepositState::Failed {
+            token: token.to_owned(),
+            reason,
+        }
+    }
+}
+
+/// Ether, which pays the gas.
+const ETH: Asset = Asset {
+    id: 0,
+    address: String::new(),
+    symbol: String::new(),
+    decimals: 18,
+    enabled_for_fees: true,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const ETHER: u64 = 1_000_000_000_000_000_000;
+
+    /// Chains moving only when told to: deposits land on L2 with `land`.
+    #[derive(Default)]
+    struct MockChains {
+        l1: Mutex<HashMap<u32, BigUint>>,
+        l2: Mutex<HashMap<u32, BigUint>>,
+        deposits: Mutex<Vec<(u32, BigUint)>>,
+        revert: bool,
+    }
+
+    impl MockChains {
+        fn set_l1(&self, token: u32, raw: u64) {
+            self.l1.lock().unwrap().insert(token, BigUint::from(raw));
+        }
+
+        fn set_l2(&self, token: u32, raw: u64) {
+            self.l2.lock().unwrap().insert(token, BigUint::from(raw));
+        }
+
+        /// Credits every pending deposit on L2.
+        fn land(&self) {
+            let mut l2 = self.l2.lock().unwrap();
+            for (token, amount) in self.deposits.lock().unwrap().drain(..) {
+                *l2.entry(token).or_default() += amount;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl L1Wallet for MockChains {
+        async fn balance(&self, asset: &Asset) -> anyhow::Result<BigUint> {
+            Ok(self
+                .l1
+                .lock()
+                .unwrap()
+                .get(&asset.id)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn gas_price(&self) -> anyhow::Result<BigUint> {
+            // 100 gwei, 0.02 ETH for 200k gas.
+            Ok(BigUint::from(100_000_000_000u64))
+        }
+
+        async fn deposit(&self, asset: &Asset, amount: BigUint) -> anyhow::Result<H256> {
+            self.deposits.lock().unwrap().push((asset.id, amount));
+            Ok(H256::repeat_byte(asset.id as u8 + 1))
+        }
+
+        async fn wait_for_tx(&self, _tx_hash: H256) -> anyhow::Result<bool> {
+            Ok(!self.revert)
+        }
+    }
+
+    #[async_trait]
+    impl BalanceSource for MockChains {
+        async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint> {
+            Ok(self
+                .l2
+                .lock()
+                .unwrap()
+                .get(&token.0)
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+
+    fn auto_deposit(chains: &Arc<MockChains>, max_per_day: usize) -> AutoDeposit {
+        let info = fixtures::market_info("ETH-USDC", 0, 2);
+        let config = DepositConfig {
+            tokens: [(
+                "ETH".to_owned(),
+                TopUp {
+                    floor: dec!(1),
+                    amount: dec!(2),
+                },
+            )]
+            .into_iter()
+            .collect(),
+            max_per_day,
+            commit_timeout_secs: 600,
+            ..DepositConfig::default()
+        };
+        AutoDeposit::new(
+            config,
+            chains.clone(),
+            chains.clone(),
+            &[info.base_asset, info.quote_asset],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_deposit_cycle() {
+        let chains = Arc::new(MockChains::default());
+        chains.set_l1(0, 5 * ETHER);
+        chains.set_l2(0, ETHER);
+        let mut deposits = auto_deposit(&chains, 1);
+        deposits.step(100).await;
+        assert_eq!(deposits.state(), &DepositState::Idle, "at the floor");
+
+        chains.set_l2(0, ETHER / 2);
+        deposits.step(200).await;
+        let tx_hash = H256::repeat_byte(1);
+        assert_eq!(
+            deposits.state(),
+            &DepositState::Depositing {
+                token: "ETH".into(),
+                tx_hash
+            }
+        );
+        assert_eq!(
+            chains.deposits.lock().unwrap()[0],
+            (0, BigUint::from(2 * ETHER))
+        );
+        deposits.step(300).await;
+        assert!(matches!(
+            deposits.state(),
+            DepositState::Waiting { balance, since: 300, .. } if *balance == dec!(0.5)
+        ));
+        deposits.step(400).await;
+        assert!(matches!(deposits.state(), DepositState::Waiting { .. }));
+        chains.land();
+        deposits.step(500).await;
+        assert_eq!(
+            deposits.state(),
+            &DepositState::Done {
+                token: "ETH".into(),
+                tx_hash
+            }
+        );
+        deposits.step(600).await;
+        assert_eq!(deposits.state(), &DepositState::Idle);
+
+        // One deposit a day.
+        chains.set_l2(0, ETHER / 2);
+        deposits.step(700).await;
+        assert_eq!(deposits.state(), &DepositState::Idle);
+        assert!(chains.deposits.lock().unwrap().is_empty());
+        deposits.step(200 + DAY_SECS).await;
+        assert!(matches!(deposits.state(), DepositState::Depositing { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_deposit_failures() {
+        // 2 ETH plus 0.02 of gas are more than the L1 balance.
+        let chains = Arc::new(MockChains::default());
+        chains.set_l1(0, 2 * ETHER);
+        let mut deposits = auto_deposit(&chains, 5);
+        deposits.step(100).await;
+        assert!(matches!(
+            deposits.state(),
+            DepositState::Failed { reason, .. } if reason.contains("does not cover 2 plus gas")
+        ));
+        assert!(chains.deposits.lock().unwrap().is_empty());
+        deposits.step(200).await;
+        assert_eq!(deposits.state(), &DepositState::Idle);
+
+        // Never credited on L2.
+        chains.set_l1(0, 3 * ETHER);
+        deposits.step(300).await;
+        deposits.step(400).await;
+        deposits.step(999).await;
+        assert!(matches!(deposits.state(), DepositState::Waiting { .. }));
+        deposits.step(1000).await;
+        assert!(matches!(
+            deposits.state(),
+            DepositState::Failed { reason, .. } if reason.contains("not committed after 600s")
+        ));
+
+        let chains = Arc::new(MockChains {
+            revert: true,
+            ..MockChains::default()
+        });
+        chains.set_l1(0, 3 * ETHER);
+        let mut deposits = auto_deposit(&chains, 5);
+        deposits.step(100).await;
+        deposits.step(200).await;
+        assert!(matches!(
+            deposits.state(),
+            DepositState::Failed { reason, .. } if reason.contains("reverted")
+        ));
+    }
+}
@@ -0,0 +1,1612 @@
+/// Dispatcher task that owns the `ZigzagClient` and fans incoming operations
+/// out to per-kind channels, so that e.g. waiting for an order receipt does
+/// not stall processing of market data broadcasts.
+use crate::client::{Transport, ZigzagClient};
+use crate::dedup::Dedup;
+use crate::display::{format_amount, format_price};
+use crate::evm;
+use crate::metrics::Metrics;
+use crate::orders::{OrderParams, OrderTerms};
+use crate::ratelimit::RateLimiter;
+use crate::rfq::{self, QuoteError};
+use crate::risk::RiskEngine;
+use crate::storage::Recorder;
+use crate::zigzag::{
+    approx_eq, CancelallArgs, ChainId, DailyvolumereqArgs, ErrorArgs, ExchangeOrder, LastpriceArgs,
+    Market, MarketInfo, MarketreqArgs, Operation, OperationName, Order, OrderId, OrderStatus,
+    QuoteArgs, RemainingOrError, RequestquoteArgs, Submitorder3Args, SubscribemarketArgs, UserId,
+    UserorderackArgs,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+
+/// Channel an incoming operation is routed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    MarketData,
+    Orders,
+    Errors,
+    Other,
+}
+
+impl Route {
+    pub fn of(op: &Operation) -> Self {
+        match op {
+            Operation::Lastprice(_) | Operation::Liquidity2(_) | Operation::Marketsummary(_) => {
+                Route::MarketData
+            }
+            Operation::Userorderack(_)
+            | Operation::Orderreceipt(_)
+            | Operation::Fillreceipt(_)
+            | Operation::Orders(_)
+            | Operation::Fills(_)
+            | Operation::Orderstatus(_)
+            | Operation::Fillstatus(_)
+            | Operation::Cancelorderack(_)
+            | Operation::Fillrequest(_)
+            | Operation::Userordermatch(_)
+            | Operation::Requestquote(_)
+            | Operation::Quote(_) => Route::Orders,
+            Operation::Error(_) => Route::Errors,
+            _ => Route::Other,
+        }
+    }
+}
+
+/// What the dispatcher does with messages for another chain than the one
+/// of the session.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChainCheck {
+    #[default]
+    Drop,
+    /// Pass them on all the same
+    WarnOnly,
+    /// Stop the dispatcher
+    Error,
+}
+
+/// Receiving ends of the dispatcher channels.
+pub struct Receivers {
+    pub market_data: mpsc::UnboundedReceiver<Operation>,
+    pub orders: mpsc::UnboundedReceiver<Operation>,
+    pub errors: mpsc::UnboundedReceiver<ErrorArgs>,
+    pub other: mpsc::UnboundedReceiver<Operation>,
+}
+
+struct Senders {
+    market_data: mpsc::UnboundedSender<Operation>,
+    orders: mpsc::UnboundedSender<Operation>,
+    errors: mpsc::UnboundedSender<ErrorArgs>,
+    other: mpsc::UnboundedSender<Operation>,
+}
+
+/// Receiving ends of the dispatchers of several sessions, merged, with each
+/// message tagged with the user id of the session it arrived on.
+pub struct SessionReceivers {
+    pub market_data: mpsc::UnboundedReceiver<(UserId, Operation)>,
+    pub orders: mpsc::UnboundedReceiver<(UserId, Operation)>,
+    pub errors: mpsc::UnboundedReceiver<(UserId, ErrorArgs)>,
+    pub other: mpsc::UnboundedReceiver<(UserId, Operation)>,
+    senders: SessionSenders,
+}
+
+struct SessionSenders {
+    market_data: mpsc::UnboundedSender<(UserId, Operation)>,
+    orders: mpsc::UnboundedSender<(UserId, Operation)>,
+    errors: mpsc::UnboundedSender<(UserId, ErrorArgs)>,
+    other: mpsc::UnboundedSender<(UserId, Operation)>,
+}
+
+impl Default for SessionReceivers {
+    fn default() -> Self {
+        let (market_data_tx, market_data) = mpsc::unbounded_channel();
+        let (orders_tx, orders) = mpsc::unbounded_channel();
+        let (errors_tx, errors) = mpsc::unbounded_channel();
+        let (other_tx, other) = mpsc::unbounded_channel();
+        Self {
+            market_data,
+            orders,
+            errors,
+            other,
+            senders: SessionSenders {
+                market_data: market_data_tx,
+                orders: orders_tx,
+                errors: errors_tx,
+                other: other_tx,
+            },
+        }
+    }
+}
+
+impl SessionReceivers {
+    /// Merges in the receivers of the dispatcher of the session logged in as
+    /// `user_id`. The order of each channel is kept.
+    pub fn add(&mut self, user_id: UserId, receivers: Receivers) {
+        let senders = &self.senders;
+        forward_tagged(
+            user_id.clone(),
+            receivers.market_data,
+            senders.market_data.clone(),
+        );
+        forward_tagged(user_id.clone(), receivers.orders, senders.orders.clone());
+        forward_tagged(user_id.clone(), receivers.errors, senders.errors.clone());
+        forward_tagged(user_id, receivers.other, senders.other.clone());
+    }
+}
+
+/// Forwards what `rx` receives to `tx`, tagged with `session`, until either
+/// end is gone.
+pub fn forward_tagged<V: Send + 'static>(
+    session: UserId,
+    mut rx: mpsc::UnboundedReceiver<V>,
+    tx: mpsc::UnboundedSender<(UserId, V)>,
+) {
+    tokio::spawn(async move {
+        while let Some(value) = rx.recv().await {
+            if tx.send((session.clone(), value)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+type ReceiptWaiters = Arc<Mutex<HashMap<OrderId, Vec<oneshot::Sender<Order>>>>>;
+
+/// What the `userorderack` of a submitted order is expected to contain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedAck {
+    pub market: Market,
+    pub user_id: UserId,
+    pub terms: OrderTerms,
+}
+
+impl ExpectedAck {
+    fn matches(&self, ack: &UserorderackArgs) -> bool {
+        // Prices and quantities are recomputed by the backend from the raw
+        // order, so allow for floating point noise.
+        ack.market == self.market
+            && ack.user_id == self.user_id
+            && ack.side == self.terms.side
+            && matches!(ack.price.value(), Ok(price) if approx_eq(price, self.terms.price))
+            && approx_eq(ack.base_quantity, self.terms.base_quantity)
+    }
+}
+
+type AckWaiters = Arc<
+    Mutex<
+        VecDeque<(
+            ExpectedAck,
+            oneshot::Sender<anyhow::Result<UserorderackArgs>>,
+        )>,
+    >,
+>;
+
+/// How long the response to a request is timed before giving up on it.
+const LATENCY_TIMEOUT: Duration = Duration::from_secs(60);
+
+type OpFilter = Box<dyn Fn(&Operation) -> bool + Send>;
+type OpWaiters = Arc<Mutex<Vec<(OpFilter, Reply)>>>;
+
+/// What a waiter does with the operation it was waiting for.
+enum Reply {
+    Op(oneshot::Sender<Operation>),
+    // Every operation accepted, until the receiver is dropped.
+    Stream(mpsc::UnboundedSender<Operation>),
+    // Times the response to the request of that name, sent at that instant.
+    Latency(&'static str, Instant),
+}
+
+/// Filter of the operations answering `request`, or `None` when nothing
+/// does.
+fn response_filter(request: &Operation) -> Option<OpFilter> {
+    let filter: OpFilter = match request {
+        Operation::Submitorder3(args) => {
+            // Acks do not tell which submission they are for, the oldest
+            // one on the market stands in.
+            let market = args.market.clone();
+            // Starknet orders are not ours, nothing to time.
+            let user_id = args.zk_order.zksync()?.account_id.to_string();
+            Box::new(move |op| match op {
+                Operation::Userorderack(ack) => ack.market == market && ack.user_id == user_id,
+                Operation::Error(e) => e.operation == OperationName::Submitorder3,
+                _ => false,
+            })
+        }
+        Operation::Orderreceiptreq(args) => {
+            let order_id = args.order_id;
+            Box::new(move |op| match op {
+                Operation::Orderreceipt(order) => order.id == order_id,
+                Operation::Error(e) => e.operation == OperationName::Orderreceiptreq,
+                _ => false,
+            })
+        }
+        Operation::Requestquote(args) => quote_filter(args.clone()),
+        Operation::Dailyvolumereq(args) => volume_filter(args.chain_req),
+        Operation::Marketreq(args) => market_infos_filter(args.chain_id),
+        Operation::Fillrequest(args) => {
+            let order = args.fill_order.zksync()?;
+            let (account_id, nonce) = (order.account_id, order.nonce);
+            Box::new(move |op| match op {
+                Operation::Userordermatch(args) => matches!(
+                    args.maker_order.zksync(),
+                    Some(maker) if maker.account_id == account_id && maker.nonce == nonce
+                ),
+                Operation::Error(e) => e.operation == OperationName::Fillrequest,
+                _ => false,
+            })
+        }
+        _ => return None,
+    };
+    Some(filter)
+}
+
+fn quote_filter(request: RequestquoteArgs) -> OpFilter {
+    Box::new(move |op| match op {
+        Operation::Quote(quote) => rfq::answers(&request, quote),
+        Operation::Error(e) => e.operation == OperationName::Requestquote,
+        _ => false,
+    })
+}
+
+/// Filter of the market list of `chain_id` and of the errors of its
+/// requests.
+fn market_infos_filter(chain_id: ChainId) -> OpFilter {
+    Box::new(move |op| match op {
+        Operation::Marketinfo2(args) => args
+            .market_infos
+            .iter()
+            .all(|info| info.zigzag_chain_id == chain_id),
+        Operation::Error(e) => e.operation == OperationName::Marketreq,
+        _ => false,
+    })
+}
+
+/// Filter of the daily volumes of `chain_id`, which may come in several
+/// frames, and of the errors of their requests.
+fn volume_filter(chain_id: ChainId) -> OpFilter {
+    Box::new(move |op| match op {
+        Operation::Dailyvolume(args) => args.volumes.iter().all(|v| v.chain_id == chain_id),
+        Operation::Error(e) => e.operation == OperationName::Dailyvolumereq,
+        _ => false,
+    })
+}
+
+// Nearly every command is a send, so boxing it would only add allocations.
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    Send(Operation),
+    // Sent back to back, or not at all.
+    Batch(Vec<Operation>),
+    // Closes the connection and stops the dispatcher.
+    Close(oneshot::Sender<()>),
+}
+
+/// Operations of one strategy tick staged to go out together, e.g. a
+/// `cancelall`, new orders and the liquidity of the market. The risk engine
+/// checks them as a unit and the dispatcher sends them back to back, without
+/// anything else in between, or sends none of them.
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    ops: Vec<Operation>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, op: Operation) {
+        self.ops.push(op);
+    }
+
+    pub fn ops(&self) -> &[Operation] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Fails on the first operation that would not make it to the wire:
+    /// one not serializing, or carrying a price that does not parse.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (i, op) in self.ops.iter().enumerate() {
+            if let Err(e) = check_wire(op) {
+                return Err(anyhow::anyhow!(
+                    "Not sending batch, its {} #{} is invalid: {}",
+                    op.name(),
+                    i + 1,
+                    e
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_wire(op: &Operation) -> anyhow::Result<()> {
+    serde_json::to_string(op)?;
+    let prices = match op {
+        Operation::Indicateliq2(args) => args.liquidity.iter().map(|l| &l.price).collect(),
+        Operation::Quote(args) => vec![&args.price],
+        _ => Vec::new(),
+    };
+    for price in prices {
+        price.value()?;
+    }
+    Ok(())
+}
+
+/// Cloneable handle for sending operations through the dispatcher and
+/// registering interest in order receipts.
+#[derive(Clone)]
+pub struct DispatcherHandle {
+    outgoing: mpsc::UnboundedSender<Command>,
+    receipts: ReceiptWaiters,
+    acks: AckWaiters,
+    waiters: OpWaiters,
+}
+
+impl DispatcherHandle {
+    /// Handle whose operations are only queued in the returned outbox, for
+    /// running strategies offline.
+    pub fn offline() -> (Self, Outbox) {
+        let (outgoing, outbox) = mpsc::unbounded_channel();
+        let handle = Self {
+            outgoing,
+            receipts: ReceiptWaiters::default(),
+            acks: AckWaiters::default(),
+            waiters: OpWaiters::default(),
+        };
+        (handle, Outbox(outbox))
+    }
+
+    pub fn send(&self, op: Operation) -> anyhow::Result<()> {
+        self.outgoing
+            .send(Command::Send(op))
+            .map_err(|_| anyhow::anyhow!("Zigzag dispatcher has stopped!"))
+    }
+
+    /// Sends the operations of `batch` back to back. A batch failing
+    /// validation is rejected whole, before anything is sent.
+    pub fn send_batch(&self, batch: Batch) -> anyhow::Result<()> {
+        batch.validate()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.outgoing
+            .send(Command::Batch(batch.ops))
+            .map_err(|_| anyhow::anyhow!("Zigzag dispatcher has stopped!"))
+    }
+
+    /// Returns a receiver resolved with the first incoming operation
+    /// accepted by `filter`.
+    pub fn wait_for<F>(&self, filter: F) -> oneshot::Receiver<Operation>
+    where
+        F: Fn(&Operation) -> bool + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.waiters
+            .lock()
+            .unwrap()
+            .push((Box::new(filter), Reply::Op(tx)));
+        rx
+    }
+
+    /// Returns a receiver of every incoming operation accepted by `filter`,
+    /// for responses split across frames. Dropping it unregisters the
+    /// filter.
+    pub fn stream<F>(&self, filter: F) -> mpsc::UnboundedReceiver<Operation>
+    where
+        F: Fn(&Operation) -> bool + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.waiters
+            .lock()
+            .unwrap()
+            .push((Box::new(filter), Reply::Stream(tx)));
+        rx
+    }
+
+    /// Cancels all open orders of the user and waits until the backend
+    /// reports them canceled. The backend sends nothing when there was no
+    /// open order, in which case this times out.
+    pub async fn cancel_all(
+        &self,
+        chain_id: ChainId,
+        user_id: UserId,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let reply = self.wait_for(|op| match op {
+            Operation::Orderstatus(args) => {
+                !args.updates.is_empty()
+                    && args
+                        .updates
+                        .iter()
+                        .all(|u| u.status() == OrderStatus::Canceled)
+            }
+            Operation::Error(e) => e.operation == OperationName::Cancelall,
+            _ => false,
+        });
+        self.send(Operation::Cancelall(CancelallArgs { chain_id, user_id }))?;
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(Operation::Error(e))) => Err(e.into()),
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(_)) => Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            Err(_) => Err(anyhow::anyhow!(
+                "No cancellation confirmed within {:?}",
+                timeout
+            )),
+        }
+    }
+
+    /// Sends an RFQ quote request and waits for a quote answering it. A
+    /// timeout fails with `QuoteError::Timeout`.
+    pub async fn request_quote(
+        &self,
+        request: RequestquoteArgs,
+        timeout: Duration,
+    ) -> anyhow::Result<QuoteArgs> {
+        let reply = self.wait_for(quote_filter(request.clone()));
+        self.send(Operation::Requestquote(request))?;
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(Operation::Quote(quote))) => Ok(quote),
+            Ok(Ok(Operation::Error(e))) => Err(e.into()),
+            Ok(Ok(op)) => Err(anyhow::anyhow!(
+                "Unexpected reply to quote request: {:?}",
+                op
+            )),
+            Ok(Err(_)) => Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            Err(_) => Err(QuoteError::Timeout(timeout).into()),
+        }
+    }
+
+    /// Requests the detailed infos of every market of `chain_id`.
+    pub async fn market_infos(
+        &self,
+        chain_id: ChainId,
+        timeout: Duration,
+    ) -> anyhow::Result<Vec<MarketInfo>> {
+        let reply = self.wait_for(market_infos_filter(chain_id));
+        self.send(Operation::Marketreq(MarketreqArgs {
+            chain_id,
+            detailed: true,
+        }))?;
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(Operation::Marketinfo2(args))) => Ok(args.market_infos),
+            Ok(Ok(Operation::Error(e))) => Err(e.into()),
+            Ok(Ok(op)) => Err(anyhow::anyhow!(
+                "Unexpected reply to market request: {:?}",
+                op
+            )),
+            Ok(Err(_)) => Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            Err(_) => Err(anyhow::anyhow!("No market list within {:?}", timeout)),
+        }
+    }
+
+    /// Requests the info of `market`, which the backend sends to its
+    /// subscribers.
+    pub async fn market_info(
+        &self,
+        chain_id: ChainId,
+        market: Market,
+        timeout: Duration,
+    ) -> anyhow::Result<MarketInfo> {
+        let alias = market.clone();
+        let reply = self.wait_for(move |op| match op {
+            Operation::Marketinfo(args) => args.market_info.alias == alias,
+            Operation::Error(e) => e.operation == OperationName::Subscribemarket,
+            _ => false,
+        });
+        self.send(Operation::Subscribemarket(SubscribemarketArgs {
+            chain_id,
+            market: market.clone(),
+        }))?;
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(Operation::Marketinfo(args))) => Ok(args.market_info),
+            Ok(Ok(Operation::Error(e))) => Err(e.into()),
+            Ok(Ok(op)) => Err(anyhow::anyhow!(
+                "Unexpected reply to market subscription: {:?}",
+                op
+            )),
+            Ok(Err(_)) => Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            Err(_) => Err(anyhow::anyhow!(
+                "No info for {} within {:?}, is it listed?",
+                market,
+                timeout
+            )),
+        }
+    }
+
+    /// Requests the daily volumes of `chain_id`, returning a receiver of
+    /// the frames answering it, or of the error rejecting it.
+    pub fn daily_volume(
+        &self,
+        chain_id: ChainId,
+    ) -> anyhow::Result<mpsc::UnboundedReceiver<Operation>> {
+        let frames = self.stream(volume_filter(chain_id));
+        self.send(Operation::Dailyvolumereq(DailyvolumereqArgs {
+            chain_req: chain_id,
+        }))?;
+        Ok(frames)
+    }
+
+    /// Closes the websocket with a close frame and stops the dispatcher,
+    /// waiting at most `timeout` for it.
+    pub async fn close(&self, timeout: Duration) -> anyhow::Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.outgoing
+            .send(Command::Close(tx))
+            .map_err(|_| anyhow::anyhow!("Zigzag dispatcher has stopped!"))?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(anyhow::anyhow!("Closing zigzag connection failed!")),
+            Err(_) => Err(anyhow::anyhow!(
+                "Zigzag connection not closed within {:?}",
+                timeout
+            )),
+        }
+    }
+
+    /// Returns a receiver resolved with the next `Orderreceipt` for the
+    /// given order. Waiters whose receiver was dropped, by callers that
+    /// timed out or gave up, are pruned here, since their receipt may never
+    /// come.
+    pub fn wait_for_receipt(&self, order_id: OrderId) -> oneshot::Receiver<Order> {
+        let (tx, rx) = oneshot::channel();
+        let mut receipts = self.receipts.lock().unwrap();
+        receipts.retain(|_, waiters| {
+            waiters.retain(|waiter| !waiter.is_closed());
+            !waiters.is_empty()
+        });
+        receipts.entry(order_id).or_default().push(tx);
+        rx
+    }
+
+    /// Returns a receiver resolved with the first `userorderack` matching
+    /// `expected`, or with the error of the next failed `submitorder3` if
+    /// this is the oldest submission still waiting.
+    pub fn wait_for_ack(
+        &self,
+        expected: ExpectedAck,
+    ) -> oneshot::Receiver<anyhow::Result<UserorderackArgs>> {
+        let (tx, rx) = oneshot::channel();
+        self.acks.lock().unwrap().push_back((expected, tx));
+        rx
+    }
+
+    /// Submits a signed order and waits until the backend acknowledges it.
+    /// The backend does not echo any client id, so the ack is matched on
+    /// market, user id, side, price and quantity.
+    pub async fn submit_order(
+        &self,
+        market_info: &MarketInfo,
+        zk_order: impl Into<ExchangeOrder>,
+        timeout: Duration,
+    ) -> anyhow::Result<UserorderackArgs> {
+        let zk_order = zk_order.into();
+        let (user_id, terms) = match &zk_order {
+            ExchangeOrder::Zksync(order) => (
+                order.account_id.to_string(),
+                OrderParams::from_order(order).terms(market_info)?,
+            ),
+            ExchangeOrder::Evm(order) => (order.user.clone(), evm::terms(order, market_info)?),
+            ExchangeOrder::Starknet(_) => {
+                return Err(anyhow::anyhow!("Starknet orders cannot be submitted yet!"))
+            }
+        };
+        log::info!(
+            "Submitting {} {:?} {} @ {}",
+            market_info.alias,
+            terms.side,
+            format_amount(terms.base_quantity),
+            format_price(terms.price, Some(market_info.price_precision_decimal))
+        );
+        let expected = ExpectedAck {
+            market: market_info.alias.clone(),
+            user_id,
+            terms,
+        };
+        let ack = self.wait_for_ack(expected);
+        self.send(Operation::Submitorder3(Box::new(Submitorder3Args {
+            chain_id: market_info.zigzag_chain_id,
+            market: market_info.alias.clone(),
+            zk_order,
+        })))?;
+        match tokio::time::timeout(timeout, ack).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            Err(_) => Err(anyhow::anyhow!(
+                "No acknowledgment for order on {} within {:?}",
+                market_info.alias,
+                timeout
+            )),
+        }
+    }
+}
+
+/// Operations sent through an offline `DispatcherHandle`.
+pub struct Outbox(mpsc::UnboundedReceiver<Command>);
+
+impl Outbox {
+    /// Operations sent since the last call, oldest first. Closing the
+    /// handle succeeds right away.
+    pub fn drain(&mut self) -> Vec<Operation> {
+        let mut ops = Vec::new();
+        while let Ok(command) = self.0.try_recv() {
+            match command {
+                Command::Send(op) => ops.push(op),
+                Command::Batch(batch) => ops.extend(batch),
+                Command::Close(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+        ops
+    }
+}
+
+/// Splits market data and fill requests by market, so that each market's
+/// task only receives its own messages.
+#[derive(Default)]
+pub struct MarketRouter {
+    markets: HashMap<Market, MarketRoute>,
+}
+
+struct MarketRoute {
+    // Base and quote token ids, used to match fill requests.
+    tokens: (u32, u32),
+    sender: mpsc::UnboundedSender<Operation>,
+}
+
+impl MarketRouter {
+    /// Starts routing a market, replacing any previous route for it.
+    pub fn add(&mut self, market_info: &MarketInfo) -> mpsc::UnboundedReceiver<Operation> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let tokens = (market_info.base_asset.id, market_info.quote_asset.id);
+        self.markets
+            .insert(market_info.alias.clone(), MarketRoute { tokens, sender });
+        receiver
+    }
+
+    /// Stops routing a market, which closes the channel of its task.
+    pub fn remove(&mut self, market: &str) -> bool {
+        self.markets.remove(market).is_some()
+    }
+
+    pub fn markets(&self) -> impl Iterator<Item = &Market> {
+        self.markets.keys()
+    }
+
+    /// Delivers an operation to the tasks of the markets it concerns.
+    /// Returns false if no market took it.
+    pub fn route(&mut self, op: Operation) -> bool {
+        let market = match &op {
+            Operation::Lastprice(args) => {
+                // Price updates of several markets come in one message.
+                let mut delivered = false;
+                for (market, route) in &self.markets {
+                    let updates: Vec<_> = args
+                        .updates
+                        .iter()
+                        .filter(|u| &u.market == market)
+                        .cloned()
+                        .collect();
+                    if !updates.is_empty() {
+                        let op = Operation::Lastprice(LastpriceArgs { updates });
+                        delivered |= route.sender.send(op).is_ok();
+                    }
+                }
+                return delivered;
+            }
+            // Errors and order snapshots do not name their market.
+            Operation::Error(_) | Operation::Orders(_) | Operation::Fills(_) => {
+                let mut delivered = false;
+                for route in self.markets.values() {
+                    delivered |= route.sender.send(op.clone()).is_ok();
+                }
+                return delivered;
+            }
+            Operation::Liquidity2(args) => Some(args.market.clone()),
+            Operation::Refreshliquidity(args) => Some(args.market.clone()),
+            Operation::Fillreceipt(fill) => Some(fill.market.clone()),
+            Operation::Marketsummary(args) => Some(args.market.clone()),
+            Operation::Requestquote(args) => Some(args.market.clone()),
+            Operation::Fillrequest(args) => args.fill_order.zksync().and_then(|order| {
+                let tokens = (*order.token_sell, *order.token_buy);
+                self.markets
+                    .iter()
+                    .find(|(_, r)| r.tokens == tokens || r.tokens == (tokens.1, tokens.0))
+                    .map(|(market, _)| market.clone())
+            }),
+            _ => None,
+        };
+        match market.and_then(|m| self.markets.get(&m)) {
+            Some(route) => route.sender.send(op).is_ok(),
+            None => false,
+        }
+    }
+}
+
+pub struct Dispatcher<T> {
+    client: ZigzagClient<T>,
+    outgoing: mpsc::UnboundedReceiver<Command>,
+    senders: Senders,
+    receipts: ReceiptWaiters,
+    acks: AckWaiters,
+    waiters: OpWaiters,
+    risk: Option<Arc<RiskEngine>>,
+    recorder: Option<Recorder>,
+    limiter: Option<RateLimiter>,
+    tap: Option<mpsc::UnboundedSender<Operation>>,
+    dedup: Option<Dedup>,
+    chain: Option<(ChainId, ChainCheck)>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<T: Transport> Dispatcher<T> {
+    pub fn new(client: ZigzagClient<T>) -> (Self, DispatcherHandle, Receivers) {
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (market_data_tx, market_data_rx) = mpsc::unbounded_channel();
+        let (orders_tx, orders_rx) = mpsc::unbounded_channel();
+        let (errors_tx, errors_rx) = mpsc::unbounded_channel();
+        let (other_tx, other_rx) = mpsc::unbounded_channel();
+        let receipts = ReceiptWaiters::default();
+        let acks = AckWaiters::default();
+        let waiters = OpWaiters::default();
+        let dispatcher = Self {
+            client,
+            outgoing: outgoing_rx,
+            senders: Senders {
+                market_data: market_data_tx,
+                orders: orders_tx,
+                errors: errors_tx,
+                other: other_tx,
+            },
+            receipts: receipts.clone(),
+            acks: acks.clone(),
+            waiters: waiters.clone(),
+            risk: None,
+            recorder: None,
+            limiter: None,
+            tap: None,
+            dedup: None,
+            chain: None,
+            metrics: None,
+        };
+        let handle = DispatcherHandle {
+            outgoing: outgoing_tx,
+            receipts,
+            acks,
+            waiters,
+        };
+        let receivers = Receivers {
+            market_data: market_data_rx,
+            orders: orders_rx,
+            errors: errors_rx,
+            other: other_rx,
+        };
+        (dispatcher, handle, receivers)
+    }
+
+    /// Checks outgoing orders, liquidity and quotes against `risk`, which
+    /// follows the incoming operations.
+    pub fn with_risk(mut self, risk: Arc<RiskEngine>) -> Self {
+        self.risk = Some(risk);
+        self
+    }
+
+    /// Stores our orders and fills, and the liquidity actually sent.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Copies the operations actually sent to `tap`.
+    pub fn with_tap(mut self, tap: mpsc::UnboundedSender<Operation>) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Drops the fills and order updates `dedup` already saw, before anything
+    /// else gets to see them.
+    pub fn with_dedup(mut self, dedup: Dedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Checks the chain id of incoming messages against `chain_id`, those
+    /// without one pass.
+    pub fn with_chain_check(mut self, chain_id: ChainId, check: ChainCheck) -> Self {
+        self.chain = Some((chain_id, check));
+        self
+    }
+
+    /// Counts the messages for another chain as `chain_id_mismatches`, and
+    /// the messages of the client. The responses to requests are timed into
+    /// `latency_<request>` histograms, or counted as `unanswered_<request>`
+    /// when none came.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.client.set_metrics(metrics.clone());
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Holds outgoing operations back to the rates of `limiter`.
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Runs until the client fails or a `DispatcherHandle` closes the
+    /// connection. Outgoing operations queued through a handle are sent in
+    /// between incoming messages.
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            // Whatever the limits allow goes out before waiting again.
+            while let Some(op) = self
+                .limiter
+                .as_mut()
+                .and_then(|limiter| limiter.pop(Instant::now()))
+            {
+                self.send(op).await?;
+            }
+            while let Some(ops) = self
+                .limiter
+                .as_mut()
+                .and_then(|limiter| limiter.pop_batch(Instant::now()))
+            {
+                self.send_batch(ops).await?;
+            }
+            let next_at = self.limiter.as_ref().and_then(RateLimiter::next_at);
+            tokio::select! {
+                op = self.client.recv() => self.dispatch(op?)?,
+                Some(command) = self.outgoing.recv() => match command {
+                    Command::Send(op) => {
+                        let op = match &self.risk {
+                            Some(risk) => risk.check(op),
+                            None => Some(op),
+                        };
+                        match (op, &mut self.limiter) {
+                            (Some(op), Some(limiter)) => limiter.push(op),
+                            (Some(op), None) => self.send(op).await?,
+                            (None, _) => {}
+                        }
+                    }
+                    Command::Batch(ops) => {
+                        let ops = match &self.risk {
+                            Some(risk) => risk.check_batch(ops),
+                            None => Some(ops),
+                        };
+                        match (ops, &mut self.limiter) {
+                            (Some(ops), Some(limiter)) => limiter.push_batch(ops),
+                            (Some(ops), None) => self.send_batch(ops).await?,
+                            (None, _) => {}
+                        }
+                    }
+                    Command::Close(done) => {
+                        if let Some(limiter) = &mut self.limiter {
+                            limiter.clear();
+                        }
+                        self.client.close().await?;
+                        let _ = done.send(());
+                        return Ok(());
+                    }
+                },
+                _ = sleep_until(next_at.unwrap_or_else(Instant::now)), if next_at.is_some() => {}
+            }
+        }
+    }
+
+    async fn send(&mut self, op: Operation) -> anyhow::Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.outgoing(&op);
+        }
+        if let Some(tap) = &self.tap {
+            let _ = tap.send(op.clone());
+        }
+        if let (Some(_), Some(filter)) = (&self.metrics, response_filter(&op)) {
+            let timer = Reply::Latency(op.name(), Instant::now());
+            self.waiters.lock().unwrap().push((filter, timer));
+        }
+        self.client.send(op).await
+    }
+
+    async fn send_batch(&mut self, ops: Vec<Operation>) -> anyhow::Result<()> {
+        for op in ops {
+            self.send(op).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether an incoming operation is for the chain of the session, or
+    /// should be passed on anyway.
+    fn check_chain(&self, op: &Operation) -> anyhow::Result<bool> {
+        let (chain_id, check) = match self.chain {
+            Some(chain) => chain,
+            None => return Ok(true),
+        };
+        let other = match op.chain_ids().into_iter().find(|id| *id != chain_id) {
+            Some(other) => other,
+            None => return Ok(true),
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.incr("chain_id_mismatches");
+        }
+        match check {
+            ChainCheck::Drop => {
+                log::warn!(
+                    "Dropping {} for chain {}, not {}",
+                    op.name(),
+                    other,
+                    chain_id
+                );
+                Ok(false)
+            }
+            ChainCheck::WarnOnly => {
+                log::warn!(
+                    "Received {} for chain {}, not {}",
+                    op.name(),
+                    other,
+                    chain_id
+                );
+                Ok(true)
+            }
+            ChainCheck::Error => Err(anyhow::anyhow!(
+                "Received {} for chain {}, logged into chain {}!",
+                op.name(),
+                other,
+                chain_id
+            )),
+        }
+    }
+
+    fn dispatch(&mut self, op: Operation) -> anyhow::Result<()> {
+        if !self.check_chain(&op)? {
+            return Ok(());
+        }
+        let op = match &mut self.dedup {
+            Some(dedup) => match dedup.filter(op, Instant::now()) {
+                Some(op) => op,
+                None => return Ok(()),
+            },
+            None => op,
+        };
+        if let Some(risk) = &self.risk {
+            risk.on_incoming(&op);
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.incoming(&op);
+        }
+        if let Operation::Orderreceipt(order) = &op {
+            let waiters = self.receipts.lock().unwrap().remove(&order.id);
+            for waiter in waiters.into_iter().flatten() {
+                let _ = waiter.send(order.clone());
+            }
+        }
+        self.resolve_acks(&op);
+        self.resolve_waiters(&op);
+        // A closed channel only means nobody is interested in that kind of
+        // message, which is not an error.
+        let _ = match (Route::of(&op), op) {
+            (_, Operation::Error(args)) => self.senders.errors.send(args).map_err(drop),
+            (Route::MarketData, op) => self.senders.market_data.send(op).map_err(drop),
+            (Route::Orders, op) => self.senders.orders.send(op).map_err(drop),
+            (_, op) => self.senders.other.send(op).map_err(drop),
+        };
+        Ok(())
+    }
+
+    fn resolve_waiters(&self, op: &Operation) {
+        let now = Instant::now();
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.retain(|(_, reply)| match reply {
+            Reply::Op(tx) => !tx.is_closed(),
+            Reply::Stream(tx) => !tx.is_closed(),
+            Reply::Latency(name, sent) => {
+                let pending = now.saturating_duration_since(*sent) < LATENCY_TIMEOUT;
+                if let (false, Some(metrics)) = (pending, &self.metrics) {
+                    metrics.incr(&format!("unanswered_{}", name));
+                }
+                pending
+            }
+        });
+        // Any number of waiters may want the operation, but it only answers
+        // the oldest request.
+        let mut timed = false;
+        let mut i = 0;
+        while i < waiters.len() {
+            let (filter, reply) = &waiters[i];
+            if !filter(op) || (timed && matches!(reply, Reply::Latency(..))) {
+                i += 1;
+                continue;
+            }
+            if let Reply::Stream(tx) = reply {
+                let _ = tx.send(op.clone());
+                i += 1;
+                continue;
+            }
+            match waiters.remove(i).1 {
+                Reply::Op(tx) => {
+                    let _ = tx.send(op.clone());
+                }
+                Reply::Stream(_) => unreachable!("streams stay registered"),
+                Reply::Latency(name, sent) => {
+                    timed = true;
+                    if let Some(metrics) = &self.metrics {
+                        metrics.observe(
+                            &format!("latency_{}", name),
+                            now.saturating_duration_since(sent),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn resolve_acks(&self, op: &Operation) {
+        let mut acks = self.acks.lock().unwrap();
+        // Submissions that timed out have dropped their receiver.
+        acks.retain(|(_, tx)| !tx.is_closed());
+        match op {
+            Operation::Userorderack(ack) => {
+                if let Some(i) = acks.iter().position(|(expected, _)| expected.matches(ack)) {
+                    let (_, tx) = acks.remove(i).unwrap();
+                    // The backend sends why it refused the order in place
+                    // of the remaining quantity.
+                    let result = match ack.remaining.as_ref().and_then(RemainingOrError::error) {
+                        Some(error) => Err(anyhow::anyhow!(
+                            "Order {} on {} rejected: {}",
+                            ack.id,
+                            ack.market,
+                            error
+                        )),
+                        None => Ok(ack.clone()),
+                    };
+                    let _ = tx.send(result);
+                }
+            }
+            Operation::Error(e) if e.operation == OperationName::Submitorder3 => {
+                if let Some((_, tx)) = acks.pop_front() {
+                    let _ = tx.send(Err(e.clone().into()));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::MockTransport;
+    use crate::zigzag::{
+        Decimal, Indicateliq2Args, Liquidity, OrderreceiptreqArgs, Price, Side, ZigzagError,
+    };
+    use async_tungstenite::tungstenite::Message;
+    use rust_decimal_macros::dec;
+
+    fn drain<V>(rx: &mut mpsc::UnboundedReceiver<V>) -> Vec<V> {
+        let mut values = Vec::new();
+        while let Ok(value) = rx.try_recv() {
+            values.push(value);
+        }
+        values
+    }
+
+    const ORDER_RECEIPT: &str = r#"{"op":"orderreceipt","args":[1000,40,"ETH-USDT","s",3370.93,0.1,337.093,4294967295,"23","o"]}"#;
+
+    #[tokio::test]
+    async fn test_dispatch_mixed_sequence() {
+        let frames = [
+            r#"{"op":"lastprice","args":[[["ETH-USDT",3370.93,12.5]]]}"#,
+            r#"{"op":"error","args":["submitorder3","Order is too small"]}"#,
+            ORDER_RECEIPT,
+            r#"{"op":"liquidity2","args":[1000,"ETH-USDT",[["b",3300,0.5]]]}"#,
+            r#"{"op":"marketinfo2","args":[[]]}"#,
+        ];
+        let client = ZigzagClient::new(MockTransport::with_frames(
+            frames.iter().map(|f| Message::Text(f.to_string())),
+        ));
+        let (dispatcher, handle, mut receivers) = Dispatcher::new(client);
+        let receipt = handle.wait_for_receipt(40);
+        let other_receipt = handle.wait_for_receipt(41);
+        // The mock transport errors out once all frames are consumed.
+        assert!(dispatcher.run().await.is_err());
+
+        let market_data = drain(&mut receivers.market_data);
+        assert_eq!(market_data.len(), 2);
+        assert!(matches!(market_data[0], Operation::Lastprice(_)));
+        assert!(matches!(market_data[1], Operation::Liquidity2(_)));
+
+        let orders = drain(&mut receivers.orders);
+        assert_eq!(orders.len(), 1);
+        assert!(matches!(&orders[0], Operation::Orderreceipt(order) if order.id == 40));
+
+        let errors = drain(&mut receivers.errors);
+        assert_eq!(
+            errors,
+            vec![ErrorArgs {
+                operation: OperationName::Submitorder3,
+                error: ZigzagError::OrderTooSmall("Order is too small".into()),
+            }]
+        );
+
+        let other = drain(&mut receivers.other);
+        assert_eq!(other.len(), 1);
+        assert!(matches!(other[0], Operation::Marketinfo2(_)));
+
+        assert_eq!(receipt.await.expect("receipt").market, "ETH-USDT");
+        drop(handle);
+        assert!(other_receipt.await.is_err());
+    }
+
+    #[test]
+    fn test_dropped_receipt_waiters_pruned() {
+        let (_dispatcher, handle, _receivers) =
+            Dispatcher::new(ZigzagClient::new(MockTransport::default()));
+        let abandoned = handle.wait_for_receipt(40);
+        let _other = handle.wait_for_receipt(41);
+        drop(abandoned);
+        let _receipt = handle.wait_for_receipt(42);
+        let receipts = handle.receipts.lock().unwrap();
+        assert!(!receipts.contains_key(&40));
+        assert_eq!(receipts[&41].len(), 1);
+        assert_eq!(receipts[&42].len(), 1);
+    }
+
+    fn expected_ack(price: Decimal) -> ExpectedAck {
+        ExpectedAck {
+            market: "ETH-USDT".into(),
+            user_id: "23".into(),
+            terms: OrderTerms {
+                side: Side::Sell,
+                price,
+                base_quantity: dec!(0.1),
+            },
+        }
+    }
+
+    const USER_ORDER_ACK: &str = r#"{"op":"userorderack","args":[1000,40,"ETH-USDT","s",3370.93,0.1,337.093,4294967295,"23","o",null,0.1]}"#;
+
+    #[tokio::test]
+    async fn test_ack_matching() {
+        let client = ZigzagClient::new(MockTransport::with_frames(
+            [
+                r#"{"op":"lastprice","args":[[["ETH-USDT",3370.93,12.5]]]}"#,
+                USER_ORDER_ACK,
+            ]
+            .iter()
+            .map(|f| Message::Text(f.to_string())),
+        ));
+        let (dispatcher, handle, mut receivers) = Dispatcher::new(client);
+        let other = handle.wait_for_ack(expected_ack(dec!(3300)));
+        let ack = handle.wait_for_ack(expected_ack(dec!(3370.93)));
+        assert!(dispatcher.run().await.is_err());
+
+        assert_eq!(ack.await.expect("ack").expect("accepted").id, 40);
+        // Other messages keep flowing while waiting for the ack.
+        assert_eq!(drain(&mut receivers.market_data).len(), 1);
+        assert_eq!(drain(&mut receivers.orders).len(), 1);
+        drop(handle);
+        assert!(other.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dedup_replayed_messages() {
+        const FILL_RECEIPT: &str = r#"{"op":"fillreceipt","args":[1000,3310,"ETH-USDT","b",3370.93,0.1,"f",null,"23","42",null,null]}"#;
+        let frames = [
+            USER_ORDER_ACK,
+            FILL_RECEIPT,
+            // Sent again after a reconnect.
+            USER_ORDER_ACK,
+            FILL_RECEIPT,
+            r#"{"op":"fills","args":[[[1000,3310,"ETH-USDT","b",3370.93,0.1,"f",null,"23","42",null,null]]]}"#,
+        ];
+        let client = ZigzagClient::new(MockTransport::with_frames(
+            frames.iter().map(|f| Message::Text(f.to_string())),
+        ));
+        let (dispatcher, handle, mut receivers) = Dispatcher::new(client);
+        let dispatcher = dispatcher.with_dedup(Dedup::new(&Default::default()));
+        let ack = handle.wait_for_ack(expected_ack(dec!(3370.93)));
+        assert!(dispatcher.run().await.is_err());
+
+        assert_eq!(ack.await.expect("ack").expect("accepted").id, 40);
+        let orders = drain(&mut receivers.orders);
+        assert_eq!(orders.len(), 3, "{:?}", orders);
+        assert!(matches!(&orders[0], Operation::Userorderack(ack) if ack.id == 40));
+        assert!(matches!(&orders[1], Operation::Fillreceipt(fill) if fill.id == 3310));
+        assert!(matches!(&orders[2], Operation::Fills(args) if args.fills.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_chain_check() {
+        let frames = [
+            r#"{"op":"lastprice","args":[[["ETH-USDT",3370.93,12.5]]]}"#,
+            r#"{"op":"fillreceipt","args":[1000,1,"ETH-USDT","b",3370.93,0.1,"f",null,"23","42",null,null]}"#,
+            r#"{"op":"fillreceipt","args":[1,2,"ETH-USDT","b",3370.93,0.1,"f",null,"23","42",null,null]}"#,
+            ORDER_RECEIPT,
+        ];
+        let run = |check| async move {
+            let client = ZigzagClient::new(MockTransport::with_frames(
+                frames.iter().map(|f| Message::Text(f.to_string())),
+            ));
+            let (dispatcher, _handle, mut receivers) = Dispatcher::new(client);
+            let metrics = Arc::new(Metrics::new());
+            let result = dispatcher
+                .with_chain_check(1000, check)
+                .with_metrics(metrics.clone())
+                .run()
+                .await;
+            assert_eq!(drain(&mut receivers.market_data).len(), 1);
+            assert_eq!(metrics.counter("chain_id_mismatches"), 1);
+            (
+                result.expect_err("run").to_string(),
+                drain(&mut receivers.orders).len(),
+            )
+        };
+        assert_eq!(
+            run(ChainCheck::Drop).await,
+            ("Mock transport exhausted".to_owned(), 2)
+        );
+        assert_eq!(
+            run(ChainCheck::WarnOnly).await,
+            ("Mock transport exhausted".to_owned(), 3)
+        );
+        let (error, orders) = run(ChainCheck::Error).await;
+        assert_eq!(
+            error,
+            "Received fillreceipt for chain 1, logged into chain 1000!"
+        );
+        assert_eq!(orders, 1);
+    }
+
+    #[tokio::test]
+    async fn test_response_latency() {
+        let (dispatcher, _handle, _receivers) =
+            Dispatcher::new(ZigzagClient::new(MockTransport::default()));
+        let metrics = Arc::new(Metrics::new());
+        let mut dispatcher = dispatcher.with_metrics(metrics.clone());
+        let receipt_request = |order_id| {
+            Operation::Orderreceiptreq(OrderreceiptreqArgs {
+                chain_id: 1000,
+                order_id,
+            })
+        };
+        dispatcher.send(receipt_request(40)).await.expect("send");
+        dispatcher.send(receipt_request(40)).await.expect("send");
+        dispatcher.send(receipt_request(41)).await.expect("send");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let receipt = || serde_json::from_str::<Operation>(ORDER_RECEIPT).expect("from_str");
+        dispatcher.dispatch(receipt()).expect("dispatch");
+
+        // A response only answers one request.
+        let latency = metrics
+            .histogram("latency_orderreceiptreq")
+            .expect("histogram");
+        assert_eq!(latency.count, 1);
+        assert!(latency.max_ms >= 20, "{:?}", latency);
+        dispatcher.dispatch(receipt()).expect("dispatch");
+        dispatcher.dispatch(receipt()).expect("dispatch");
+        let latency = metrics
+            .histogram("latency_orderreceiptreq")
+            .expect("histogram");
+        assert_eq!(latency.count, 2);
+        assert_eq!(dispatcher.waiters.lock().unwrap().len(), 1);
+
+        assert_eq!(metrics.counter("sent_orderreceiptreq"), 3);
+        assert_eq!(
+            metrics.counter("bytes_sent") as usize,
+            3 * r#"{"op":"orderreceiptreq","args":[1000,40]}"#.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ack_error() {
+        let client = ZigzagClient::new(MockTransport::with_frames([Message::Text(
+            r#"{"op":"error","args":["submitorder3","Order is too small"]}"#.into(),
+        )]));
+        let (dispatcher, handle, mut receivers) = Dispatcher::new(client);
+        let first = handle.wait_for_ack(expected_ack(dec!(3370.93)));
+        let second = handle.wait_for_ack(expected_ack(dec!(3370.93)));
+        assert!(dispatcher.run().await.is_err());
+
+        let err = first.await.expect("resolved").unwrap_err();
+        assert!(err.to_string().contains("Order is too small"), "{}", err);
+        let error = err.downcast_ref::<ErrorArgs>().expect("ErrorArgs");
+        assert!(matches!(error.error, ZigzagError::OrderTooSmall(_)));
+        assert_eq!(drain(&mut receivers.errors).len(), 1);
+        drop(handle);
+        assert!(second.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ack_rejected() {
+        let client = ZigzagClient::new(MockTransport::with_frames([Message::Text(
+            r#"{"op":"userorderack","args":[1000,40,"ETH-USDT","s",3370.93,0.1,337.093,4294967295,"23","r",null,"Not enough balance"]}"#.into(),
+        )]));
+        let (dispatcher, handle, _receivers) = Dispatcher::new(client);
+        let ack = handle.wait_for_ack(expected_ack(dec!(3370.93)));
+        assert!(dispatcher.run().await.is_err());
+        let err = ack.await.expect("resolved").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Order 40 on ETH-USDT rejected: Not enough balance"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_ack_waiter_skipped() {
+        let client = ZigzagClient::new(MockTransport::with_frames([Message::Text(
+            USER_ORDER_ACK.into(),
+        )]));
+        let (dispatcher, handle, _receivers) = Dispatcher::new(client);
+        // Like a submission that timed out.
+        drop(handle.wait_for_ack(expected_ack(dec!(3370.93))));
+        let ack = handle.wait_for_ack(expected_ack(dec!(3370.93)));
+        assert!(dispatcher.run().await.is_err());
+        assert!(ack.await.expect("ack").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_and_close() {
+        let transport = MockTransport::hanging([Message::Text(
+            r#"{"op":"orderstatus","args":[[[1000,40,"c"],[1000,41,"c"]]]}"#.into(),
+        )]);
+        let (sent, closed) = (transport.sent.clone(), transport.closed.clone());
+        let (dispatcher, handle, mut receivers) = Dispatcher::new(ZigzagClient::new(transport));
+        let cancel = {
+            let handle = handle.clone();
+            tokio::spawn(async move {
+                handle
+                    .cancel_all(1000, "23".into(), Duration::from_secs(5))
+                    .await
+            })
+        };
+        // Register the waiter before the frame is read.
+        tokio::task::yield_now().await;
+        let dispatcher = tokio::spawn(dispatcher.run());
+        cancel.await.unwrap().expect("cancel_all");
+        assert_eq!(drain(&mut receivers.orders).len(), 1);
+
+        handle.close(Duration::from_secs(5)).await.expect("close");
+        dispatcher.await.unwrap().expect("run");
+        assert!(closed.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![serde_json::json!({"op": "cancelall", "args": [1000, "23"]})]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let transport = MockTransport::hanging([]);
+        let sent = transport.sent.clone();
+        let (dispatcher, handle, _receivers) = Dispatcher::new(ZigzagClient::new(transport));
+        let subscribe = |market: &str| {
+            Operation::Subscribemarket(SubscribemarketArgs {
+                chain_id: 1000,
+                market: market.into(),
+            })
+        };
+        let liquidity = |price: Price| {
+            Operation::Indicateliq2(Indicateliq2Args {
+                chain_id: 1000,
+                market: "ETH-USDT".into(),
+                liquidity: vec![Liquidity {
+                    side: Side::Buy,
+                    price,
+                    base_quantity: dec!(0.1),
+                    expires: None,
+                }],
+            })
+        };
+        let cancel = Operation::Cancelall(CancelallArgs {
+            chain_id: 1000,
+            user_id: "23".into(),
+        });
+
+        // Nothing of a batch with an invalid message is sent.
+        let mut invalid = Batch::new();
+        invalid.push(cancel.clone());
+        invalid.push(liquidity(Price::String("cheap".into())));
+        let err = handle.send_batch(invalid).unwrap_err();
+        assert!(err.to_string().contains("indicateliq2 #2"), "{}", err);
+
+        handle.send(subscribe("ETH-USDT")).expect("send");
+        let mut batch = Batch::new();
+        batch.push(cancel);
+        batch.push(liquidity(dec!(3300).into()));
+        batch.push(subscribe("WBTC-USDT"));
+        handle.send_batch(batch).expect("send_batch");
+        handle.send(subscribe("ETH-USDC")).expect("send");
+        let dispatcher = tokio::spawn(dispatcher.run());
+        handle.close(Duration::from_secs(5)).await.expect("close");
+        dispatcher.await.unwrap().expect("run");
+
+        let sent: Vec<_> = sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|op| (op["op"].clone(), op["args"][1].clone()))
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                ("subscribemarket".into(), "ETH-USDT".into()),
+                ("cancelall".into(), "23".into()),
+                ("indicateliq2".into(), "ETH-USDT".into()),
+                ("subscribemarket".into(), "WBTC-USDT".into()),
+                ("subscribemarket".into(), "ETH-USDC".into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_daily_volume_frames() {
+        let transport = MockTransport::hanging([
+            Message::Text(
+                r#"{"op":"dailyvolume","args":[[[1000,"ETH-USDC","2022-05-01",1.5,4500]]]}"#.into(),
+            ),
+            Message::Text(
+                r#"{"op":"dailyvolume","args":[[[1,"ETH-USDC","2022-05-01",9,9]]]}"#.into(),
+            ),
+            Message::Text(
+                r#"{"op":"dailyvolume","args":[[[1000,"WBTC-USDC","2022-05-01",0.1,3000]]]}"#
+                    .into(),
+            ),
+        ]);
+        let sent = transport.sent.clone();
+        let (dispatcher, handle, _receivers) = Dispatcher::new(ZigzagClient::new(transport));
+        let mut frames = handle.daily_volume(1000).expect("daily_volume");
+        tokio::spawn(dispatcher.run());
+        for market in ["ETH-USDC", "WBTC-USDC"] {
+            match tokio::time::timeout(Duration::from_secs(5), frames.recv()).await {
+                Ok(Some(Operation::Dailyvolume(args))) => {
+                    assert_eq!(args.volumes[0].market, market)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![serde_json::json!({"op": "dailyvolumereq", "args": [1000]})]
+        );
+        handle.close(Duration::from_secs(5)).await.expect("close");
+    }
+
+    async fn recv_tagged<V>(rx: &mut mpsc::UnboundedReceiver<(UserId, V)>) -> (UserId, V) {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("timeout")
+            .expect("recv")
+    }
+
+    #[tokio::test]
+    async fn test_session_tagging() {
+        use crate::connection::{Backoff, Connection, Heartbeat};
+        use crate::mockserver::MockServer;
+        use crate::zigzag::{fixtures, Indicateliq2Args, Liquidity};
+
+        let server = MockServer::start()
+            .await
+            .expect("start")
+            .with_market(fixtures::market_info("ETH-USDC", 0, 2), dec!(2000));
+        server.fill_quotes(true);
+        let mut sessions = SessionReceivers::default();
+        let mut handles = Vec::new();
+        for user_id in ["23", "24"] {
+            let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(10));
+            let heartbeat = Heartbeat {
+                interval: Duration::from_secs(60),
+                timeout: Duration::from_secs(60),
+            };
+            let connection = Connection::connect(server.url(), backoff, heartbeat)
+                .await
+                .expect("connect");
+            let mut client = ZigzagClient::new(connection);
+            client.login(1000, user_id.into()).await.expect("login");
+            let (dispatcher, handle, receivers) = Dispatcher::new(client);
+            tokio::spawn(dispatcher.run());
+            sessions.add(user_id.into(), receivers);
+            handles.push(handle);
+        }
+
+        // Both logins are answered with an orders and a fills snapshot.
+        let mut tags = Vec::new();
+        for _ in 0..4 {
+            tags.push(recv_tagged(&mut sessions.orders).await.0);
+        }
+        tags.sort();
+        assert_eq!(tags, ["23", "23", "24", "24"]);
+
+        // Fills of the liquidity of a session arrive on it.
+        for (handle, user_id) in handles.iter().zip(["23", "24"]) {
+            handle
+                .send(Operation::Indicateliq2(Indicateliq2Args {
+                    chain_id: 1000,
+                    market: "ETH-USDC".into(),
+                    liquidity: vec![Liquidity {
+                        side: Side::Sell,
+                        price: dec!(2001).into(),
+                        base_quantity: dec!(0.1),
+                        expires: None,
+                    }],
+                }))
+                .expect("send");
+            let (tag, op) = recv_tagged(&mut sessions.orders).await;
+            assert_eq!(tag, user_id);
+            assert!(matches!(op, Operation::Fillreceipt(fill) if fill.maker_user_id == user_id));
+        }
+
+        // The server injects on the latest connection.
+        server
+            .inject_error(OperationName::Submitorder3, "Order is too small")
+            .expect("inject_error");
+        let (tag, error) = recv_tagged(&mut sessions.errors).await;
+        assert_eq!(tag, "24");
+        assert_eq!(error.operation, OperationName::Submitorder3);
+    }
+
+    #[tokio::test]
+    async fn test_market_router_isolation() {
+        use crate::zigzag::fixtures::market_info;
+
+        let op = |json: &str| serde_json::from_str::<Operation>(json).expect("from_str");
+        let mut router = MarketRouter::default();
+        let mut eth = router.add(&market_info("ETH-USDT", 0, 4));
+        let mut btc = router.add(&market_info("WBTC-USDT", 15, 4));
+
+        assert!(router.route(op(
+            r#"{"op":"lastprice","args":[[["ETH-USDT",3370.93,12.5],["WBTC-USDT",30000,100],["DAI-USDT",1,0]]]}"#
+        )));
+        assert!(router.route(op(
+            r#"{"op":"liquidity2","args":[1000,"WBTC-USDT",[["b",29990,0.5]]]}"#
+        )));
+        assert!(!router.route(op(
+            r#"{"op":"liquidity2","args":[1000,"DAI-USDT",[["b",1,5]]]}"#
+        )));
+        assert!(!router.route(op(ORDER_RECEIPT)));
+        // Order snapshots go to every market.
+        assert!(router.route(op(r#"{"op":"orders","args":[[]]}"#)));
+
+        let eth_ops = drain(&mut eth);
+        assert_eq!(eth_ops.len(), 2);
+        assert!(matches!(&eth_ops[1], Operation::Orders(_)));
+        assert!(matches!(&eth_ops[0], Operation::Lastprice(args)
+            if args.updates.len() == 1 && args.updates[0].market == "ETH-USDT"));
+        let btc_ops = drain(&mut btc);
+        assert_eq!(btc_ops.len(), 3);
+        assert!(matches!(&btc_ops[0], Operation::Lastprice(args)
+            if args.updates.len() == 1 && args.updates[0].market == "WBTC-USDT"));
+        assert!(matches!(&btc_ops[1], Operation::Liquidity2(_)));
+
+        // Removing one market closes its channel and leaves the other alone.
+        assert!(router.remove("ETH-USDT"));
+        assert!(eth.recv().await.is_none());
+        assert!(router.route(op(
+            r#"{"op":"lastprice","args":[[["ETH-USDT",3371,12.5],["WBTC-USDT",30001,100]]]}"#
+        )));
+        assert_eq!(drain(&mut btc).len(), 1);
+        assert_eq!(router.markets().collect::<Vec<_>>(), vec!["WBTC-USDT"]);
+    }
+
+    #[test]
+    fn test_route() {
+        let op: Operation = serde_json::from_str(ORDER_RECEIPT).expect("from_str");
+        assert_eq!(Route::of(&op), Route::Orders);
+        let op: Operation =
+            serde_json::from_str(r#"{"op":"subscribemarket","args":[1000,"ETH-USDT"]}"#)
+                .expect("from_str");
+        assert_eq!(Route::of(&op), Route::Other);
+    }
+}
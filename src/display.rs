@@ -0,0 +1,348 @@
+/// Human readable rendering of ZigZag messages for logs and notifications,
+/// as in `FILL ETH-USDT SELL 0.25 @ 1,843.20 fee 0.42 USDC (tx 0x600a…1ed9)`:
+/// prices at the precision of their market when known, numbers grouped by
+/// thousands, hashes shortened and absent fields left out.
+use crate::zigzag::{
+    Decimal, Fill, Liquidity, MarketsummaryArgs, Operation, Order, OrderStatus, Price,
+    RemainingOrError, Side, UserorderackArgs, H256,
+};
+use std::fmt;
+
+/// `value` with its integer part grouped by commas, as in "1,843.2".
+fn group_thousands(value: Decimal) -> String {
+    let s = value.to_string();
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", s.as_str()),
+    };
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let mut grouped = String::from(sign);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        grouped.push('.');
+        grouped.push_str(fraction);
+    }
+    grouped
+}
+
+/// `price` with exactly `decimals` decimals, or without trailing zeros when
+/// unknown, grouped by thousands.
+pub fn format_price(price: Decimal, decimals: Option<u32>) -> String {
+    let price = match decimals {
+        Some(decimals) => {
+            let mut price = price.round_dp(decimals);
+            price.rescale(decimals);
+            price
+        }
+        None => price.normalize(),
+    };
+    group_thousands(price)
+}
+
+/// `amount` without trailing zeros, grouped by thousands.
+pub fn format_amount(amount: Decimal) -> String {
+    group_thousands(amount.normalize())
+}
+
+/// A price of the wire, as sent when it does not parse.
+fn wire_price(price: &Price, decimals: Option<u32>) -> String {
+    match (price.signed_value(), price) {
+        (Ok(value), _) => format_price(value, decimals),
+        (Err(_), Price::String(s)) => s.clone(),
+        (Err(_), Price::Decimal(value)) => value.to_string(),
+    }
+}
+
+#[cfg(feature = "zksync")]
+fn hex(hash: &H256) -> String {
+    format!("{:#x}", hash)
+}
+
+#[cfg(not(feature = "zksync"))]
+fn hex(hash: &H256) -> String {
+    hash.clone()
+}
+
+/// First 4 and last 4 hex digits of a hash, as in "0x600a…1ed9".
+pub fn short_hash(hash: &H256) -> String {
+    let hex = hex(hash);
+    match (hex.get(..6), hex.get(hex.len().saturating_sub(4)..)) {
+        (Some(head), Some(tail)) if hex.len() > 12 => format!("{}…{}", head, tail),
+        _ => hex,
+    }
+}
+
+fn side(side: &Side) -> &'static str {
+    match side {
+        Side::Buy => "BUY",
+        Side::Sell => "SELL",
+    }
+}
+
+fn write_tx(f: &mut fmt::Formatter, tx_hash: &Option<H256>) -> fmt::Result {
+    match tx_hash {
+        Some(hash) => write!(f, " (tx {})", short_hash(hash)),
+        None => Ok(()),
+    }
+}
+
+/// The remaining quantity, or in parentheses why the order failed.
+fn write_remaining(f: &mut fmt::Formatter, remaining: &Option<RemainingOrError>) -> fmt::Result {
+    match remaining {
+        Some(RemainingOrError::Remaining(remaining)) => {
+            write!(f, " remaining {}", format_amount(*remaining))
+        }
+        Some(RemainingOrError::Error(error)) => write!(f, " ({})", error),
+        None => Ok(()),
+    }
+}
+
+/// A message rendered for humans, with its prices at `price_decimals`
+/// decimals when known. `Display` renders without the precision.
+pub trait Render {
+    fn render(&self, f: &mut fmt::Formatter, price_decimals: Option<u32>) -> fmt::Result;
+
+    /// Renders with prices at the precision of the market.
+    fn with_precision(&self, price_decimals: Option<u32>) -> Rendered<'_, Self> {
+        Rendered {
+            value: self,
+            price_decimals,
+        }
+    }
+}
+
+pub struct Rendered<'a, T: ?Sized> {
+    value: &'a T,
+    price_decimals: Option<u32>,
+}
+
+impl<T: Render + ?Sized> fmt::Display for Rendered<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.render(f, self.price_decimals)
+    }
+}
+
+macro_rules! display_rendered {
+    ($($type:ty),*) => {
+        $(impl fmt::Display for $type {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.render(f, None)
+            }
+        })*
+    };
+}
+
+display_rendered!(Fill, Order, UserorderackArgs, Liquidity, MarketsummaryArgs);
+
+/// The taker's side, the status while not filled yet, and the fee when
+/// charged.
+impl Render for Fill {
+    fn render(&self, f: &mut fmt::Formatter, price_decimals: Option<u32>) -> fmt::Result {
+        write!(
+            f,
+            "FILL {} {} {} @ {}",
+            self.market,
+            side(&self.side),
+            format_amount(self.base_quantity),
+            wire_price(&self.price, price_decimals)
+        )?;
+        if self.fill_status != OrderStatus::Filled {
+            write!(f, " {}", self.fill_status)?;
+        }
+        if let Some(fee) = self.fee_amount {
+            write!(f, " fee {}", format_amount(fee))?;
+            if let Some(token) = &self.fee_token {
+                write!(f, " {}", token)?;
+            }
+        }
+        write_tx(f, &self.tx_hash)
+    }
+}
+
+impl Render for Order {
+    fn render(&self, f: &mut fmt::Formatter, price_decimals: Option<u32>) -> fmt::Result {
+        write!(
+            f,
+            "ORDER {} {} {} {} @ {} {}",
+            self.id,
+            self.market,
+            side(&self.side),
+            format_amount(self.base_quantity),
+            wire_price(&self.price, price_decimals),
+            self.order_status
+        )?;
+        write_remaining(f, &self.remaining)?;
+        write_tx(f, &self.tx_hash)
+    }
+}
+
+impl Render for UserorderackArgs {
+    fn render(&self, f: &mut fmt::Formatter, price_decimals: Option<u32>) -> fmt::Result {
+        write!(
+            f,
+            "ACK {} {} {} {} @ {} {}",
+            self.id,
+            self.market,
+            side(&self.side),
+            format_amount(self.base_quantity),
+            wire_price(&self.price, price_decimals),
+            self.order_status
+        )?;
+        write_remaining(f, &self.remaining)?;
+        write_tx(f, &self.tx_hash)
+    }
+}
+
+/// A level, without its market.
+impl Render for Liquidity {
+    fn render(&self, f: &mut fmt::Formatter, price_decimals: Option<u32>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} @ {}",
+            side(&self.side),
+            format_amount(self.base_quantity),
+            wire_price(&self.price, price_decimals)
+        )?;
+        match self.expires {
+            Some(expires) => write!(f, " until {}", expires),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Volumes are in the assets the market is named after.
+impl Render for MarketsummaryArgs {
+    fn render(&self, f: &mut fmt::Formatter, price_decimals: Option<u32>) -> fmt::Result {
+        let (base, quote) = match self.market.split_once('-') {
+            Some((base, quote)) => (format!(" {}", base), format!(" {}", quote)),
+            None => Default::default(),
+        };
+        write!(
+            f,
+            "SUMMARY {} {} high {} low {} change {} volume {}{} / {}{}",
+            self.market,
+            wire_price(&self.price, price_decimals),
+            wire_price(&self.high_24, price_decimals),
+            wire_price(&self.low_24, price_decimals),
+            wire_price(&self.price_change, price_decimals),
+            format_amount(self.base_volume),
+            base,
+            format_amount(self.quote_volume),
+            quote
+        )
+    }
+}
+
+/// An order update for the logs: orders, acks and fills rendered at the
+/// precision `price_decimals` gives their market, the rest in debug form.
+pub fn order_update(op: &Operation, price_decimals: impl Fn(&str) -> Option<u32>) -> String {
+    match op {
+        Operation::Fillreceipt(fill) => fill
+            .with_precision(price_decimals(&fill.market))
+            .to_string(),
+        Operation::Orderreceipt(order) => order
+            .with_precision(price_decimals(&order.market))
+            .to_string(),
+        Operation::Userorderack(ack) => ack.with_precision(price_decimals(&ack.market)).to_string(),
+        op => format!("{:?}", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const TX_HASH: &str = "0x600a2d8a66d8a23c7d9d7b2a5e91e4f2b9a6c3d2e1f0a9b8c7d6e5f4a3b21ed9";
+
+    #[test]
+    fn test_format_numbers() {
+        assert_eq!(format_price(dec!(1843.2), Some(2)), "1,843.20");
+        assert_eq!(format_price(dec!(1843.205), None), "1,843.205");
+        assert_eq!(format_price(dec!(-1234567.891), Some(1)), "-1,234,567.9");
+        assert_eq!(format_price(dec!(999), Some(0)), "999");
+        assert_eq!(format_amount(dec!(0.2500)), "0.25");
+        assert_eq!(format_amount(dec!(2275000)), "2,275,000");
+    }
+
+    #[test]
+    fn test_fill() {
+        let fill: Fill = serde_json::from_str(&format!(
+            r#"[1000,7,"ETH-USDT","s","1843.2",0.25,"f","{}","42","23",0.42,"USDC"]"#,
+            TX_HASH
+        ))
+        .expect("from_str");
+        assert_eq!(
+            fill.with_precision(Some(2)).to_string(),
+            "FILL ETH-USDT SELL 0.25 @ 1,843.20 fee 0.42 USDC (tx 0x600a…1ed9)"
+        );
+        let fill: Fill = serde_json::from_str(
+            r#"[1000,7,"ETH-USDT","b",1843.2,0.25,"m",null,"42","23",null,null]"#,
+        )
+        .expect("from_str");
+        assert_eq!(fill.to_string(), "FILL ETH-USDT BUY 0.25 @ 1,843.2 matched");
+    }
+
+    #[test]
+    fn test_order_and_ack() {
+        let order: Order = serde_json::from_str(
+            r#"[1000,40,"ETH-USDT","s",3370.93,1.5,5056.395,4294967295,"23","pf",0.5]"#,
+        )
+        .expect("from_str");
+        assert_eq!(
+            order.with_precision(Some(1)).to_string(),
+            "ORDER 40 ETH-USDT SELL 1.5 @ 3,370.9 partial_fill remaining 0.5"
+        );
+        let ack: UserorderackArgs = serde_json::from_str(&format!(
+            r#"[1000,41,"ETH-USDT","b","bad",0.1,337,1700000000,"23","o","{}",0.1]"#,
+            TX_HASH
+        ))
+        .expect("from_str");
+        assert_eq!(
+            ack.to_string(),
+            "ACK 41 ETH-USDT BUY 0.1 @ bad open remaining 0.1 (tx 0x600a…1ed9)"
+        );
+        assert_eq!(
+            order_update(&Operation::Userorderack(ack), |_| Some(2)),
+            "ACK 41 ETH-USDT BUY 0.1 @ bad open remaining 0.1 (tx 0x600a…1ed9)"
+        );
+        let rejected: UserorderackArgs = serde_json::from_str(
+            r#"[1000,42,"ETH-USDT","b",1843.2,0.1,184.32,1700000000,"23","r",null,"Not enough balance"]"#,
+        )
+        .expect("from_str");
+        assert_eq!(
+            rejected.with_precision(Some(2)).to_string(),
+            "ACK 42 ETH-USDT BUY 0.1 @ 1,843.20 rejected (Not enough balance)"
+        );
+    }
+
+    #[test]
+    fn test_liquidity_and_summary() {
+        let liquidity = Liquidity {
+            side: Side::Buy,
+            price: dec!(1998).into(),
+            base_quantity: dec!(0.500),
+            expires: Some(1700000030),
+        };
+        assert_eq!(
+            liquidity.with_precision(Some(2)).to_string(),
+            "BUY 0.5 @ 1,998.00 until 1700000030"
+        );
+        let summary: MarketsummaryArgs =
+            serde_json::from_str(r#"["ETH-USDT",1843.2,1900,"1800",-12.3,1234.5,2275000]"#)
+                .expect("from_str");
+        assert_eq!(
+            summary.with_precision(Some(2)).to_string(),
+            "SUMMARY ETH-USDT 1,843.20 high 1,900.00 low 1,800.00 change -12.30 \
+             volume 1,234.5 ETH / 2,275,000 USDT"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+/// Signing of ZigZag orders on EVM chains, as EIP-712 typed data of the
+/// exchange contract.
+use crate::orders::{to_units, OrderParams, OrderTerms};
+use crate::zigzag::{Amount, ChainId, Decimal, EvmOrder, MarketInfo, Side, Timestamp};
+use k256::ecdsa::{recoverable, signature::hazmat::PrehashSigner, SigningKey};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use num::{BigUint, Zero};
+use sha3::{Digest, Keccak256};
+
+/// Name and version of the EIP-712 domain of the ZigZag exchange contract.
+pub const DOMAIN_NAME: &str = "ZigZag";
+pub const DOMAIN_VERSION: &str = "2.1";
+
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+const ORDER_TYPE: &str = "Order(address user,address sellToken,address buyToken,uint256 sellAmount,uint256 buyAmount,uint256 expirationTimeSeconds)";
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// EIP-712 domain orders are signed for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Domain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: ChainId,
+    pub verifying_contract: String,
+}
+
+impl Domain {
+    /// Domain of the ZigZag exchange contract at `exchange` on `chain_id`.
+    pub fn zigzag(chain_id: ChainId, exchange: &str) -> Self {
+        Self {
+            name: DOMAIN_NAME.to_owned(),
+            version: DOMAIN_VERSION.to_owned(),
+            chain_id,
+            verifying_contract: exchange.to_owned(),
+        }
+    }
+
+    pub fn separator(&self) -> anyhow::Result<[u8; 32]> {
+        let mut encoded = Vec::with_capacity(5 * 32);
+        encoded.extend(keccak256(DOMAIN_TYPE.as_bytes()));
+        encoded.extend(keccak256(self.name.as_bytes()));
+        encoded.extend(keccak256(self.version.as_bytes()));
+        encoded.extend(uint_word(&BigUint::from(self.chain_id))?);
+        encoded.extend(address_word(&self.verifying_contract)?);
+        Ok(keccak256(&encoded))
+    }
+}
+
+/// Parameters of an EVM order before signing, in raw token units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvmOrderParams {
+    pub sell_token: String,
+    pub buy_token: String,
+    pub sell_amount: BigUint,
+    pub buy_amount: BigUint,
+    pub expiration_time_seconds: Timestamp,
+}
+
+impl EvmOrderParams {
+    /// Parameters of an order trading `base_quantity` at `price`, rounded
+    /// and checked against the market minimums as `OrderParams::new` does
+    /// for zksync orders. Tokens are given by their address.
+    pub fn new(
+        market_info: &MarketInfo,
+        side: Side,
+        price: Decimal,
+        base_quantity: Amount,
+        expires: Timestamp,
+    ) -> anyhow::Result<Self> {
+        let (sell, buy) = match side {
+            Side::Sell => (&market_info.base_asset, &market_info.quote_asset),
+            Side::Buy => (&market_info.quote_asset, &market_info.base_asset),
+        };
+        let params = OrderParams::new(market_info, side, price, base_quantity, expires)?;
+        let (sell_amount, buy_amount) = params.ratio;
+        Ok(Self {
+            sell_token: sell.address.clone(),
+            buy_token: buy.address.clone(),
+            sell_amount,
+            buy_amount,
+            expiration_time_seconds: params.valid_until,
+        })
+    }
+
+    fn struct_hash(&self, user: &str) -> anyhow::Result<[u8; 32]> {
+        let mut encoded = Vec::with_capacity(7 * 32);
+        encoded.extend(keccak256(ORDER_TYPE.as_bytes()));
+        encoded.extend(address_word(user)?);
+        encoded.extend(address_word(&self.sell_token)?);
+        encoded.extend(address_word(&self.buy_token)?);
+        encoded.extend(uint_word(&self.sell_amount)?);
+        encoded.extend(uint_word(&self.buy_amount)?);
+        encoded.extend(uint_word(&BigUint::from(self.expiration_time_seconds))?);
+        Ok(keccak256(&encoded))
+    }
+}
+
+/// Side, price and base quantity of a signed order on the given market, in
+/// human units.
+pub fn terms(order: &EvmOrder, market_info: &MarketInfo) -> anyhow::Result<OrderTerms> {
+    let base = &market_info.base_asset;
+    let quote = &market_info.quote_asset;
+    let same = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+    let sell_amount: BigUint = order.sell_amount.parse()?;
+    let buy_amount: BigUint = order.buy_amount.parse()?;
+    let (side, base_raw, quote_raw) =
+        if same(&order.sell_token, &base.address) && same(&order.buy_token, &quote.address) {
+            (Side::Sell, sell_amount, buy_amount)
+        } else if same(&order.sell_token, &quote.address) && same(&order.buy_token, &base.address) {
+            (Side::Buy, buy_amount, sell_amount)
+        } else {
+            return Err(anyhow::anyhow!(
+                "Order is not for market {}!",
+                market_info.alias
+            ));
+        };
+    if base_raw.is_zero() || quote_raw.is_zero() {
+        return Err(anyhow::anyhow!("Order has a zero amount!"));
+    }
+    let base_quantity = to_units(&base_raw, base.decimals)?;
+    Ok(OrderTerms {
+        side,
+        price: to_units(&quote_raw, quote.decimals)? / base_quantity,
+        base_quantity,
+    })
+}
+
+/// Signs the orders of one private key, whose address is the user id on
+/// ZigZag.
+pub struct EvmSigner {
+    key: SigningKey,
+    address: String,
+    domain: Domain,
+}
+
+impl EvmSigner {
+    pub fn new(private_key: &[u8], domain: Domain) -> anyhow::Result<Self> {
+        let key = SigningKey::from_bytes(private_key)
+            .map_err(|_| anyhow::anyhow!("Invalid private key!"))?;
+        let point = key.verifying_key().to_encoded_point(false);
+        // The address is the end of the hash of the uncompressed key,
+        // without its tag byte.
+        let hash = keccak256(&point.as_bytes()[1..]);
+        Ok(Self {
+            key,
+            address: format!("0x{}", hex::encode(&hash[12..])),
+            domain,
+        })
+    }
+
+    /// Lowercase 0x-prefixed hex address.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn sign_order(&self, params: &EvmOrderParams) -> anyhow::Result<EvmOrder> {
+        let digest = self.digest(params)?;
+        let signature: recoverable::Signature = self
+            .key
+            .sign_prehash(&digest)
+            .map_err(|e| anyhow::anyhow!("Signing order failed: {}", e))?;
+        // r, s and v, which Ethereum offsets by 27.
+        let mut signature = signature.as_ref().to_vec();
+        signature[64] += 27;
+        Ok(EvmOrder {
+            user: self.address.clone(),
+            sell_token: params.sell_token.clone(),
+            buy_token: params.buy_token.clone(),
+            sell_amount: params.sell_amount.to_string(),
+            buy_amount: params.buy_amount.to_string(),
+            expiration_time_seconds: params.expiration_time_seconds.to_string(),
+            signature: format!("0x{}", hex::encode(signature)),
+        })
+    }
+
+    /// EIP-712 hash of the order of this signer's address.
+    fn digest(&self, params: &EvmOrderParams) -> anyhow::Result<[u8; 32]> {
+        let mut message = vec![0x19, 0x01];
+        message.extend(self.domain.separator()?);
+        message.extend(params.struct_hash(&self.address)?);
+        Ok(keccak256(&message))
+    }
+}
+
+/// ABI encoding of an address, left padded to 32 bytes.
+fn address_word(address: &str) -> anyhow::Result<[u8; 32]> {
+    let bytes = hex::decode(address.trim_start_matches("0x"))
+        .ok()
+        .filter(|bytes| bytes.len() == 20)
+        .ok_or_else(|| anyhow::anyhow!("Invalid address {}!", address))?;
+    let mut word = [0; 32];
+    word[12..].copy_from_slice(&bytes);
+    Ok(word)
+}
+
+/// ABI encoding of a `uint256`.
+fn uint_word(value: &BigUint) -> anyhow::Result<[u8; 32]> {
+    let bytes = value.to_bytes_be();
+    if bytes.len() > 32 {
+        return Err(anyhow::anyhow!("{} does not fit in 256 bits!", value));
+    }
+    let mut word = [0; 32];
+    word[32 - bytes.len()..].copy_from_slice(&bytes);
+    Ok(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+
+    const PRIVATE_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn market_info() -> MarketInfo {
+        let mut info = fixtures::market_info("ETH-USDC", 0, 2);
+        info.zigzag_chain_id = 42161;
+        info.base_asset.address = "0x82af49447d8a07e3bd95bd0d56f35241523fbab1".into();
+        info.quote_asset.address = "0xff970a61a04b1ca14834a43f5de4533ebddb5cc8".into();
+        info
+    }
+
+    fn signer() -> EvmSigner {
+        let domain = Domain::zigzag(42161, "0xcccccccccccccccccccccccccccccccccccccccc");
+        EvmSigner::new(&hex::decode(PRIVATE_KEY).expect("hex"), domain).expect("new")
+    }
+
+    #[test]
+    fn test_keccak256() {
+        assert_eq!(
+            hex::encode(keccak256(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+    }
+
+    #[test]
+    fn test_domain_separator() {
+        // The domain of the example of EIP-712.
+        let domain = Domain {
+            name: "Ether Mail".into(),
+            version: "1".into(),
+            chain_id: 1,
+            verifying_contract: "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC".into(),
+        };
+        assert_eq!(
+            hex::encode(domain.separator().expect("separator")),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(
+            signer().address(),
+            "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+        );
+        assert!(EvmSigner::new(&[0; 32], Domain::zigzag(42161, "0x00")).is_err());
+    }
+
+    #[test]
+    fn test_order_params() {
+        let info = market_info();
+        let params =
+            EvmOrderParams::new(&info, Side::Sell, dec!(1970.5), dec!(0.5), 100).expect("new");
+        assert_eq!(params.sell_token, info.base_asset.address);
+        assert_eq!(params.buy_token, info.quote_asset.address);
+        assert_eq!(
+            params.sell_amount,
+            BigUint::from(500_000_000_000_000_000u64)
+        );
+        assert_eq!(params.buy_amount, BigUint::from(985_250_000u64));
+        let params =
+            EvmOrderParams::new(&info, Side::Buy, dec!(1970.5), dec!(0.5), 100).expect("new");
+        assert_eq!(params.sell_token, info.quote_asset.address);
+        assert_eq!(params.sell_amount, BigUint::from(985_250_000u64));
+    }
+
+    #[test]
+    fn test_sign_order() {
+        let info = market_info();
+        let signer = signer();
+        let params =
+            EvmOrderParams::new(&info, Side::Buy, dec!(1970.5), dec!(0.5), 100).expect("new");
+        let order = signer.sign_order(&params).expect("sign_order");
+        assert_eq!(order.user, signer.address());
+        assert_eq!(order.sell_amount, "985250000");
+        assert_eq!(order.expiration_time_seconds, "100");
+
+        let mut signature = hex::decode(&order.signature[2..]).expect("hex");
+        assert_eq!(signature.len(), 65);
+        assert!([27, 28].contains(&signature[64]));
+        signature[64] -= 27;
+        let signature = recoverable::Signature::try_from(signature.as_slice()).expect("signature");
+        let digest = signer.digest(&params).expect("digest");
+        let key = signature
+            .recover_verifying_key_from_digest_bytes(&digest.into())
+            .expect("recover");
+        assert_eq!(key, signer.key.verifying_key());
+
+        let terms = terms(&order, &info).expect("terms");
+        assert_eq!(terms.side, Side::Buy);
+        assert_eq!(terms.price, dec!(1970.5));
+        assert_eq!(terms.base_quantity, dec!(0.5));
+    }
+}
@@ -0,0 +1,259 @@
+/// CSV export of our fill history with the running position, for
+/// bookkeeping. Amounts are written as normalized decimals and times as
+/// RFC 3339 UTC, so the output only depends on the fills.
+use crate::portfolio::{is_traded, our_side};
+use crate::zigzag::{Amount, Decimal, Fill, FillId, Market, Side, Timestamp, Token, UserId, H256};
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeZone, Utc};
+use std::collections::HashMap;
+use std::io;
+
+/// Fills to export. Days are UTC, both ends included.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FillFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub market: Option<Market>,
+}
+
+impl FillFilter {
+    fn contains(&self, market: &str, time: &DateTime<Utc>) -> bool {
+        let day = time.date_naive();
+        self.from.iter().all(|from| day >= *from)
+            && self.to.iter().all(|to| day <= *to)
+            && self.market.iter().all(|m| m == market)
+    }
+}
+
+/// One fill, as we traded it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FillRow {
+    pub time: DateTime<Utc>,
+    pub market: Market,
+    pub side: Side,
+    pub price: Decimal,
+    pub base_quantity: Amount,
+    /// Only takers pay fees
+    pub fee_amount: Option<Decimal>,
+    pub fee_token: Option<Token>,
+    pub tx_hash: Option<H256>,
+    pub counterparty: UserId,
+    /// Position of the market after this fill
+    pub position: Amount,
+}
+
+/// Builds the rows of `user_id`'s fills from receipts recorded at the given
+/// times, oldest first. Each fill takes its last receipt, and positions run
+/// over the whole history whatever the filter.
+pub fn rows(user_id: &str, history: &[(Timestamp, Fill)], filter: &FillFilter) -> Vec<FillRow> {
+    let mut order: Vec<FillId> = Vec::new();
+    let mut latest: HashMap<FillId, (Timestamp, &Fill)> = HashMap::new();
+    for (recorded_at, fill) in history {
+        match latest.get_mut(&fill.id) {
+            Some((_, last)) => *last = fill,
+            None => {
+                order.push(fill.id);
+                latest.insert(fill.id, (*recorded_at, fill));
+            }
+        }
+    }
+
+    let mut positions: HashMap<&str, Amount> = HashMap::new();
+    let mut rows = Vec::new();
+    for id in order {
+        let (recorded_at, fill) = latest[&id];
+        let (side, is_taker) = match our_side(fill, user_id) {
+            Some(ours) if is_traded(fill) => ours,
+            _ => continue,
+        };
+        let position = positions.entry(&fill.market).or_default();
+        match side {
+            Side::Buy => *position += fill.base_quantity,
+            Side::Sell => *position -= fill.base_quantity,
+        }
+        let time = match fill_time(fill, recorded_at) {
+            Some(time) => time,
+            None => {
+                log::warn!(
+                    "Not exporting fill {}: invalid recorded time {}",
+                    fill.id,
+                    recorded_at
+                );
+                continue;
+            }
+        };
+        if !filter.contains(&fill.market, &time) {
+            continue;
+        }
+        let price = match fill.price.value() {
+            Ok(price) => price,
+            Err(e) => {
+                log::warn!("Not exporting fill {}: {}", fill.id, e);
+                continue;
+            }
+        };
+        rows.push(FillRow {
+            time,
+            market: fill.market.clone(),
+            side,
+            price,
+            base_quantity: fill.base_quantity,
+            fee_amount: fill.fee_amount.filter(|_| is_taker),
+            fee_token: fill.fee_token.clone().filter(|_| is_taker),
+            tx_hash: fill.tx_hash,
+            counterparty: if is_taker {
+                fill.maker_user_id.clone()
+            } else {
+                fill.taker_user_id.clone()
+            },
+            position: *position,
+        });
+    }
+    rows
+}
+
+/// Time of the fill from the backend, or when we recorded it. `None` when
+/// neither is a valid time, as with a corrupt store.
+fn fill_time(fill: &Fill, recorded_at: Timestamp) -> Option<DateTime<Utc>> {
+    fill.timestamp
+        .as_deref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|| Utc.timestamp_opt(recorded_at as i64, 0).single())
+}
+
+pub fn write_csv(rows: &[FillRow], writer: impl io::Write) -> anyhow::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record([
+        "timestamp",
+        "market",
+        "side",
+        "price",
+        "base_quantity",
+        "fee_amount",
+        "fee_token",
+        "tx_hash",
+        "counterparty",
+        "position",
+    ])?;
+    let amount = |amount: Decimal| amount.normalize().to_string();
+    for row in rows {
+        csv.write_record([
+            row.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            row.market.clone(),
+            match row.side {
+                Side::Buy => "buy".to_owned(),
+                Side::Sell => "sell".to_owned(),
+            },
+            amount(row.price),
+            amount(row.base_quantity),
+            row.fee_amount.map(amount).unwrap_or_default(),
+            row.fee_token.clone().unwrap_or_default(),
+            row.tx_hash
+                .map(|hash| format!("{:?}", hash))
+                .unwrap_or_default(),
+            row.counterparty.clone(),
+            amount(row.position),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, OrderStatus};
+    use rust_decimal_macros::dec;
+
+    fn export(history: &[(Timestamp, Fill)], filter: &FillFilter) -> String {
+        let mut out = Vec::new();
+        write_csv(&rows("23", history, filter), &mut out).expect("write_csv");
+        String::from_utf8(out).expect("utf8")
+    }
+
+    #[test]
+    fn test_export_fills() {
+        let mut taker =
+            fixtures::fill(1, Side::Buy, dec!(3000.50), dec!(0.10), OrderStatus::Filled);
+        taker.fee_amount = Some(Decimal::new(15, 1));
+        taker.fee_token = Some("USDC".into());
+        taker.timestamp = Some("2022-10-01T23:59:59.500Z".into());
+        taker.tx_hash = Some(H256::repeat_byte(0xab));
+        // As maker we sold, pay no fee and face the taker.
+        let mut maker = fixtures::fill(2, Side::Buy, dec!(3100), dec!(0.25), OrderStatus::Filled);
+        maker.taker_user_id = "7".into();
+        maker.maker_user_id = "23".into();
+        maker.fee_amount = Some(Decimal::new(2, 0));
+        maker.fee_token = Some("USDC".into());
+        let history = [
+            (1_664_668_800, taker),
+            (
+                1_664_668_900,
+                fixtures::fill(3, Side::Buy, dec!(3200), dec!(1), OrderStatus::Matched),
+            ),
+            (1_664_669_000, maker),
+            // Rejected later, never traded.
+            (
+                1_664_669_100,
+                fixtures::fill(3, Side::Buy, dec!(3200), dec!(1), OrderStatus::Rejected),
+            ),
+            (
+                1_664_669_200,
+                fixtures::fill(
+                    4,
+                    Side::Sell,
+                    dec!(3150),
+                    dec!(0.5),
+                    OrderStatus::PartialFill,
+                ),
+            ),
+            (
+                1_664_669_300,
+                fixtures::fill(4, Side::Sell, dec!(3150), dec!(0.75), OrderStatus::Filled),
+            ),
+        ];
+        assert_eq!(
+            export(&history, &FillFilter::default()),
+            format!(
+                "timestamp,market,side,price,base_quantity,fee_amount,fee_token,tx_hash,counterparty,position\n\
+                 2022-10-01T23:59:59Z,ETH-USDC,buy,3000.5,0.1,1.5,USDC,0x{},42,0.1\n\
+                 2022-10-02T00:03:20Z,ETH-USDC,sell,3100,0.25,,,,7,-0.15\n\
+                 2022-10-02T00:06:40Z,ETH-USDC,sell,3150,0.75,,,,42,-0.9\n",
+                "ab".repeat(32)
+            )
+        );
+
+        // Positions still count the fills left out.
+        let filter = FillFilter {
+            from: NaiveDate::from_ymd_opt(2022, 10, 2),
+            to: NaiveDate::from_ymd_opt(2022, 10, 2),
+            market: Some("ETH-USDC".into()),
+        };
+        let rows = rows("23", &history, &filter);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].position, Decimal::new(-15, 2));
+        let filter = FillFilter {
+            market: Some("WBTC-USDC".into()),
+            ..FillFilter::default()
+        };
+        assert_eq!(export(&history, &filter).lines().count(), 1, "header only");
+    }
+
+    #[test]
+    fn test_invalid_recorded_time_skipped() {
+        let history = [
+            (
+                i64::MAX as Timestamp,
+                fixtures::fill(1, Side::Buy, dec!(3000), dec!(0.1), OrderStatus::Filled),
+            ),
+            (
+                1_664_668_800,
+                fixtures::fill(2, Side::Buy, dec!(3100), dec!(0.2), OrderStatus::Filled),
+            ),
+        ];
+        let rows = rows("23", &history, &FillFilter::default());
+        assert_eq!(rows.len(), 1);
+        // The skipped fill still counts in the position.
+        assert_eq!(rows[0].position, Decimal::new(3, 1));
+    }
+}
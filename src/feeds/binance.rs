@@ -0,0 +1,245 @@
+/// Binance `@bookTicker` websocket feed: the mid of the best bid and ask of
+/// each mapped symbol is published as the reference price of its market.
+use super::{Reference, Source};
+use crate::connection::Backoff;
+use crate::marketdata::SummaryCache;
+use crate::proxy::{self, Proxy};
+use crate::zigzag::{unix_timestamp, Decimal, Market, Timestamp};
+use async_tungstenite::tungstenite::Message;
+use futures::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::watch;
+
+pub const DEFAULT_URL: &str = "wss://stream.binance.com:9443";
+
+/// Reconnect when Binance has been silent for this long. Book tickers of
+/// liquid symbols update many times per second.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `[feeds.binance]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BinanceConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Quotes are pulled when the reference is older than this
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    /// ZigZag market to Binance symbol, e.g. "ETH-USDT" = "ethusdt"
+    pub symbols: HashMap<Market, String>,
+}
+
+fn default_url() -> String {
+    DEFAULT_URL.to_owned()
+}
+
+fn default_max_age_secs() -> u64 {
+    10
+}
+
+impl BinanceConfig {
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
+    }
+
+    /// Combined stream URL of the book tickers of all mapped symbols.
+    pub fn stream_url(&self) -> String {
+        let mut streams: Vec<_> = self
+            .symbols
+            .values()
+            .map(|symbol| format!("{}@bookTicker", symbol.to_lowercase()))
+            .collect();
+        streams.sort();
+        format!(
+            "{}/stream?streams={}",
+            self.url.trim_end_matches('/'),
+            streams.join("/")
+        )
+    }
+
+    fn market_of(&self, symbol: &str) -> Option<&Market> {
+        self.symbols
+            .iter()
+            .find(|(_, s)| s.eq_ignore_ascii_case(symbol))
+            .map(|(market, _)| market)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BookTicker {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "b")]
+    pub bid: Decimal,
+    #[serde(rename = "B")]
+    pub bid_quantity: Decimal,
+    #[serde(rename = "a")]
+    pub ask: Decimal,
+    #[serde(rename = "A")]
+    pub ask_quantity: Decimal,
+}
+
+impl BookTicker {
+    pub fn mid(&self) -> Option<Decimal> {
+        let valid = self.bid > Decimal::ZERO && self.ask >= self.bid;
+        valid.then(|| (self.bid + self.ask) / Decimal::TWO)
+    }
+}
+
+/// Frame of a combined stream.
+#[derive(Deserialize, Debug)]
+struct StreamFrame {
+    stream: String,
+    data: BookTicker,
+}
+
+/// Publishes the mid of a book ticker frame as the reference of its market.
+/// Returns the market, or `None` for frames of unmapped symbols or with an
+/// empty side.
+pub fn publish(
+    config: &BinanceConfig,
+    cache: &SummaryCache,
+    text: &str,
+    now: Timestamp,
+) -> anyhow::Result<Option<Market>> {
+    let frame: StreamFrame = serde_json::from_str(text)?;
+    let market = match config.market_of(&frame.data.symbol) {
+        Some(market) => market,
+        None => return Ok(None),
+    };
+    let price = match frame.data.mid() {
+        Some(price) => price,
+        None => {
+            log::debug!("Ignoring one-sided Binance ticker on {}", frame.stream);
+            return Ok(None);
+        }
+    };
+    cache.set_reference(
+        market.clone(),
+        Reference {
+            price,
+            source: Source::Binance,
+            updated: now,
+        },
+    );
+    Ok(Some(market.clone()))
+}
+
+/// Streams book tickers into `cache` until `shutdown` flips, reconnecting
+/// with `backoff` whenever the connection fails or goes idle.
+pub async fn run(
+    config: BinanceConfig,
+    proxy: Option<Proxy>,
+    cache: SummaryCache,
+    mut backoff: Backoff,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let url = config.stream_url();
+    loop {
+        tokio::select! {
+            result = stream(&config, &url, proxy.as_ref(), &cache, &mut backoff) => {
+                if let Err(e) = result {
+                    log::warn!("Binance feed failed: {}", e);
+                }
+            }
+            _ = shutdown.changed() => break,
+        }
+        let delay = backoff.next_delay();
+        log::info!("Reconnecting to Binance in {:?}", delay);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = shutdown.changed() => break,
+        }
+    }
+    log::info!("Binance feed stopped");
+    Ok(())
+}
+
+async fn stream(
+    config: &BinanceConfig,
+    url: &str,
+    proxy: Option<&Proxy>,
+    cache: &SummaryCache,
+    backoff: &mut Backoff,
+) -> anyhow::Result<()> {
+    let mut ws_stream = proxy::connect_websocket(url, proxy).await?;
+    log::info!("Connected to Binance: {}", url);
+    backoff.reset();
+    loop {
+        let message = tokio::time::timeout(IDLE_TIMEOUT, ws_stream.next())
+            .await
+            .map_err(|_| anyhow::anyhow!("no ticker within {:?}", IDLE_TIMEOUT))?;
+        match message {
+            Some(Ok(Message::Text(text))) => {
+                if let Err(e) = publish(config, cache, &text, unix_timestamp()) {
+                    log::warn!("Invalid Binance frame {}: {}", text, e);
+                }
+            }
+            // Pings are answered by tungstenite.
+            Some(Ok(Message::Close(frame))) => {
+                return Err(anyhow::anyhow!("closed by Binance: {:?}", frame))
+            }
+            Some(Ok(_)) => (),
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err(anyhow::anyhow!("connection closed")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config() -> BinanceConfig {
+        toml::from_str(
+            r#"
+            symbols = { "ETH-USDT" = "ethusdt", "WBTC-USDT" = "BTCUSDT" }
+            "#,
+        )
+        .expect("from_str")
+    }
+
+    #[test]
+    fn test_stream_url() {
+        let config = config();
+        assert_eq!(config.max_age_secs, 10);
+        assert_eq!(
+            config.stream_url(),
+            "wss://stream.binance.com:9443/stream?streams=btcusdt@bookTicker/ethusdt@bookTicker"
+        );
+    }
+
+    #[test]
+    fn test_publish_book_ticker() {
+        let cache = SummaryCache::new();
+        let frame = r#"{"stream":"ethusdt@bookTicker","data":{"u":19714588537,"s":"ETHUSDT","b":"1598.36000000","B":"12.48930000","a":"1598.37000000","A":"3.10080000"}}"#;
+        let market = publish(&config(), &cache, frame, 100).expect("publish");
+        assert_eq!(market.as_deref(), Some("ETH-USDT"));
+        assert_eq!(
+            cache.reference("ETH-USDT"),
+            Some(Reference {
+                price: dec!(1598.365),
+                source: Source::Binance,
+                updated: 100,
+            })
+        );
+
+        let frame = r#"{"stream":"solusdt@bookTicker","data":{"u":1,"s":"SOLUSDT","b":"31.1","B":"1","a":"31.2","A":"1"}}"#;
+        assert_eq!(
+            publish(&config(), &cache, frame, 100).expect("publish"),
+            None
+        );
+        let frame = r#"{"stream":"btcusdt@bookTicker","data":{"u":1,"s":"BTCUSDT","b":"0","B":"0","a":"19000","A":"1"}}"#;
+        assert_eq!(
+            publish(&config(), &cache, frame, 100).expect("publish"),
+            None
+        );
+        assert_eq!(cache.reference("WBTC-USDT"), None);
+        assert!(publish(&config(), &cache, r#"{"result":null,"id":1}"#, 100).is_err());
+    }
+}
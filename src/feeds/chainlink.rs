@@ -0,0 +1,380 @@
+/// Chainlink aggregators read with `eth_call` over the Ethereum provider the
+/// wallet uses. Rounds older than `max_age_secs`, or carried over from an
+/// earlier round, are flagged and not published.
+use super::{Reference, Source};
+use crate::marketdata::SummaryCache;
+use crate::zigzag::{unix_timestamp, Decimal, Market, Timestamp};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// `latestRoundData()`
+const LATEST_ROUND_DATA: [u8; 4] = [0xfe, 0xaf, 0x96, 0x8c];
+/// `decimals()`
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+/// `[feeds.chainlink]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ChainlinkConfig {
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Rounds updated longer ago than this are stale
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    /// ZigZag market to aggregator address
+    pub aggregators: HashMap<Market, String>,
+}
+
+fn default_interval_secs() -> u64 {
+    30
+}
+
+fn default_max_age_secs() -> u64 {
+    3600
+}
+
+impl ChainlinkConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
+    }
+}
+
+/// Read-only contract calls, mocked in tests.
+#[async_trait]
+pub trait EthCall {
+    async fn call(&self, to: &str, data: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// `eth_call` over JSON-RPC.
+pub struct RpcEthCall {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl RpcEthCall {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+        }
+    }
+
+    /// Sends the calls with `client`, e.g. one going through a proxy.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<serde_json::Value>,
+}
+
+#[async_trait]
+impl EthCall for RpcEthCall {
+    async fn call(&self, to: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_call",
+            "params": [{"to": to, "data": format!("0x{}", hex::encode(data))}, "latest"],
+        });
+        let response: RpcResponse = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match (response.result, response.error) {
+            (Some(result), None) => Ok(hex::decode(result.trim_start_matches("0x"))?),
+            (_, error) => Err(anyhow::anyhow!("eth_call to {} failed: {:?}", to, error)),
+        }
+    }
+}
+
+/// Decoded `latestRoundData()`. The answer is kept signed, aggregators may
+/// report negative values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Round {
+    pub round_id: u128,
+    pub answer: i128,
+    pub updated_at: Timestamp,
+    pub answered_in_round: u128,
+}
+
+impl Round {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 5 * 32 {
+            return Err(anyhow::anyhow!(
+                "latestRoundData returned {} bytes",
+                data.len()
+            ));
+        }
+        let word = |i: usize| &data[i * 32..(i + 1) * 32];
+        Ok(Self {
+            round_id: uint(word(0))?,
+            answer: int(word(1))?,
+            updated_at: uint(word(3))?.try_into()?,
+            answered_in_round: uint(word(4))?,
+        })
+    }
+
+    /// Whether the round is too old to be used at `now`, or only repeats
+    /// the answer of an earlier round.
+    pub fn is_stale(&self, max_age: Duration, now: Timestamp) -> bool {
+        self.answered_in_round < self.round_id
+            || now.saturating_sub(self.updated_at) > max_age.as_secs()
+    }
+
+    pub fn price(&self, decimals: u32) -> anyhow::Result<Decimal> {
+        if self.answer <= 0 {
+            return Err(anyhow::anyhow!("non positive answer {}", self.answer));
+        }
+        Ok(Decimal::try_from_i128_with_scale(self.answer, decimals)?.normalize())
+    }
+}
+
+/// Unsigned 256 bit word that must fit in 128 bits.
+fn uint(word: &[u8]) -> anyhow::Result<u128> {
+    if word[..16].iter().any(|b| *b != 0) {
+        return Err(anyhow::anyhow!(
+            "value out of range: 0x{}",
+            hex::encode(word)
+        ));
+    }
+    Ok(u128::from_be_bytes(word[16..].try_into()?))
+}
+
+/// Two's complement 256 bit word that must fit in 128 bits.
+fn int(word: &[u8]) -> anyhow::Result<i128> {
+    let low = i128::from_be_bytes(word[16..].try_into()?);
+    let sign = if low < 0 { 0xff } else { 0 };
+    if word[..16].iter().any(|b| *b != sign) {
+        return Err(anyhow::anyhow!(
+            "value out of range: 0x{}",
+            hex::encode(word)
+        ));
+    }
+    Ok(low)
+}
+
+pub async fn decimals(eth: &impl EthCall, aggregator: &str) -> anyhow::Result<u32> {
+    let data = eth.call(aggregator, &DECIMALS).await?;
+    if data.len() < 32 {
+        return Err(anyhow::anyhow!("decimals returned {} bytes", data.len()));
+    }
+    Ok(uint(&data[..32])?.try_into()?)
+}
+
+pub async fn latest_round(eth: &impl EthCall, aggregator: &str) -> anyhow::Result<Round> {
+    Round::decode(&eth.call(aggregator, &LATEST_ROUND_DATA).await?)
+}
+
+/// Reads the latest round of `market`'s aggregator and publishes its answer
+/// unless it is stale. Returns whether a price was published.
+pub async fn poll(
+    eth: &impl EthCall,
+    config: &ChainlinkConfig,
+    cache: &SummaryCache,
+    market: &Market,
+    decimals: u32,
+    now: Timestamp,
+) -> anyhow::Result<bool> {
+    let aggregator = &config.aggregators[market];
+    let round = latest_round(eth, aggregator).await?;
+    if round.is_stale(config.max_age(), now) {
+        log::warn!(
+            "Stale Chainlink round {} on {}: updated at {}, answered in round {}",
+            round.round_id,
+            market,
+            round.updated_at,
+            round.answered_in_round
+        );
+        return Ok(false);
+    }
+    cache.set_reference(
+        market.clone(),
+        Reference {
+            price: round.price(decimals)?,
+            source: Source::Chainlink,
+            updated: round.updated_at,
+        },
+    );
+    Ok(true)
+}
+
+/// Reads the decimals of every aggregator, once at startup so that
+/// addresses that are not aggregators are reported right away.
+pub async fn read_decimals(
+    eth: &impl EthCall,
+    config: &ChainlinkConfig,
+) -> anyhow::Result<Vec<(Market, u32)>> {
+    let mut markets = Vec::with_capacity(config.aggregators.len());
+    for (market, aggregator) in &config.aggregators {
+        let decimals = decimals(eth, aggregator).await.map_err(|e| {
+            anyhow::anyhow!(
+                "Reading Chainlink aggregator {} of {}: {}",
+                aggregator,
+                market,
+                e
+            )
+        })?;
+        markets.push((market.clone(), decimals));
+    }
+    Ok(markets)
+}
+
+/// Polls the aggregators of `markets`, with their decimals, into `cache`
+/// until `shutdown` flips.
+pub async fn run(
+    eth: impl EthCall,
+    config: ChainlinkConfig,
+    markets: Vec<(Market, u32)>,
+    cache: SummaryCache,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut ticker = tokio::time::interval(config.interval());
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                for (market, decimals) in &markets {
+                    let now = unix_timestamp();
+                    if let Err(e) = poll(&eth, &config, &cache, market, *decimals, now).await {
+                        log::warn!("Polling Chainlink on {} failed: {}", market, e);
+                    }
+                }
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+    log::info!("Chainlink feed stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const ETH_USD: &str = "0x5f4ec3df9cbd43714fe2740f5e3616155c5b8419";
+
+    /// Fixed return data per selector.
+    struct MockEth {
+        decimals: Vec<u8>,
+        round: Vec<u8>,
+    }
+
+    #[async_trait]
+    impl EthCall for MockEth {
+        async fn call(&self, to: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+            assert_eq!(to, ETH_USD);
+            match data {
+                d if d == DECIMALS => Ok(self.decimals.clone()),
+                d if d == LATEST_ROUND_DATA => Ok(self.round.clone()),
+                _ => Err(anyhow::anyhow!("unexpected call")),
+            }
+        }
+    }
+
+    fn words(words: &[&str]) -> Vec<u8> {
+        hex::decode(
+            words
+                .iter()
+                .map(|w| format!("{:0>64}", w))
+                .collect::<String>(),
+        )
+        .expect("hex")
+    }
+
+    fn mock(answer: &str, updated_at: u64) -> MockEth {
+        MockEth {
+            decimals: words(&["8"]),
+            round: words(&[
+                "5000000000000123a",
+                answer,
+                &format!("{:x}", updated_at),
+                &format!("{:x}", updated_at),
+                "5000000000000123a",
+            ]),
+        }
+    }
+
+    fn config() -> ChainlinkConfig {
+        toml::from_str(&format!(
+            r#"
+            max_age_secs = 3600
+            aggregators = {{ "ETH-USDC" = "{}" }}
+            "#,
+            ETH_USD
+        ))
+        .expect("from_str")
+    }
+
+    #[tokio::test]
+    async fn test_latest_round() {
+        // 1594.12345678 with 8 decimals, updated at 1665000000.
+        let eth = mock("251db75b4e", 1_665_000_000);
+        assert_eq!(
+            read_decimals(&eth, &config()).await.expect("read_decimals"),
+            vec![("ETH-USDC".to_owned(), 8)]
+        );
+        let round = latest_round(&eth, ETH_USD).await.expect("latest_round");
+        assert_eq!(round.answer, 159_412_345_678);
+        assert_eq!(round.updated_at, 1_665_000_000);
+        assert_eq!(round.price(8).expect("price"), dec!(1594.12345678));
+
+        let cache = SummaryCache::new();
+        let market = "ETH-USDC".to_owned();
+        assert!(poll(&eth, &config(), &cache, &market, 8, 1_665_000_060)
+            .await
+            .expect("poll"));
+        let reference = cache.reference("ETH-USDC").expect("reference");
+        assert_eq!(reference.source, Source::Chainlink);
+        assert_eq!(reference.updated, 1_665_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_stale_and_invalid_rounds() {
+        let cache = SummaryCache::new();
+        let market = "ETH-USDC".to_owned();
+        let eth = mock("2524ed7e4e", 1_665_000_000);
+        assert!(!poll(&eth, &config(), &cache, &market, 8, 1_665_003_601)
+            .await
+            .expect("poll"));
+        assert_eq!(cache.reference("ETH-USDC"), None);
+
+        let mut round = latest_round(&eth, ETH_USD).await.expect("latest_round");
+        round.answered_in_round -= 1;
+        assert!(round.is_stale(Duration::from_secs(3600), 1_665_000_000));
+
+        let negative = mock(&"f".repeat(64), 1_665_000_000);
+        let round = latest_round(&negative, ETH_USD)
+            .await
+            .expect("latest_round");
+        assert_eq!(round.answer, -1);
+        assert!(round.price(8).is_err());
+
+        assert!(Round::decode(&words(&["1", "2"])).is_err());
+        assert!(Round::decode(&words(&[
+            "1",
+            &format!("1{}", "0".repeat(40)),
+            "0",
+            "0",
+            "1"
+        ]))
+        .is_err());
+    }
+}
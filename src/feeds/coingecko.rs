@@ -0,0 +1,278 @@
+/// CoinGecko `simple/price` poller, a low-frequency fallback for tokens
+/// Binance does not list. The reference price of a market is the price of
+/// its base token divided by the price of its quote token, both in
+/// `vs_currency`.
+use super::{Reference, Source};
+use crate::connection::Backoff;
+use crate::marketdata::SummaryCache;
+use crate::zigzag::{unix_timestamp, Decimal, Market, MarketPair, Timestamp};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::watch;
+
+pub const DEFAULT_URL: &str = "https://api.coingecko.com/api/v3";
+
+/// The public API allows a few dozen calls per minute, polls are spaced by
+/// at least this much whatever the config says.
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest wait after repeated 429s.
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// `[feeds.coingecko]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CoinGeckoConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Quotes are pulled when the reference is older than this
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    #[serde(default = "default_vs_currency")]
+    pub vs_currency: String,
+    /// Token symbol to CoinGecko coin id, e.g. ETH = "ethereum"
+    pub ids: HashMap<String, String>,
+}
+
+fn default_url() -> String {
+    DEFAULT_URL.to_owned()
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+fn default_max_age_secs() -> u64 {
+    300
+}
+
+fn default_vs_currency() -> String {
+    "usd".to_owned()
+}
+
+/// `simple/price` response: coin id to currency to price.
+pub type Prices = HashMap<String, HashMap<String, Decimal>>;
+
+impl CoinGeckoConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs).max(MIN_INTERVAL)
+    }
+
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
+    }
+
+    /// Whether both tokens of `market` have a coin id.
+    pub fn covers(&self, market: &str) -> bool {
+        self.tokens(market).is_some()
+    }
+
+    fn tokens(&self, market: &str) -> Option<(&String, &String)> {
+        let pair: MarketPair = market.parse().ok()?;
+        Some((self.ids.get(pair.base())?, self.ids.get(pair.quote())?))
+    }
+
+    fn price(&self, prices: &Prices, id: &str) -> Option<Decimal> {
+        prices
+            .get(id)?
+            .get(&self.vs_currency)
+            .copied()
+            .filter(|price| *price > Decimal::ZERO)
+    }
+
+    /// Checks that CoinGecko priced every configured id. Unknown ids are
+    /// left out of the response rather than reported.
+    pub fn check_ids(&self, prices: &Prices) -> anyhow::Result<()> {
+        let mut unknown: Vec<_> = self
+            .ids
+            .iter()
+            .filter(|(_, id)| self.price(prices, id).is_none())
+            .map(|(symbol, id)| format!("{} ({})", id, symbol))
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(anyhow::anyhow!(
+            "No CoinGecko {} price for ids: {}!",
+            self.vs_currency,
+            unknown.join(", ")
+        ))
+    }
+
+    /// Reference price of `market`, if both of its tokens were priced.
+    pub fn market_price(&self, prices: &Prices, market: &str) -> Option<Decimal> {
+        let (base, quote) = self.tokens(market)?;
+        Some(self.price(prices, base)? / self.price(prices, quote)?)
+    }
+}
+
+/// A 429 from CoinGecko, with the delay it asked for if any.
+#[derive(Debug, PartialEq)]
+pub struct RateLimited(pub Option<Duration>);
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Rate limited by CoinGecko")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+pub async fn fetch(client: &reqwest::Client, config: &CoinGeckoConfig) -> anyhow::Result<Prices> {
+    let mut ids: Vec<_> = config.ids.values().map(String::as_str).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let response = client
+        .get(format!("{}/simple/price", config.url.trim_end_matches('/')))
+        .query(&[
+            ("ids", ids.join(",")),
+            ("vs_currencies", config.vs_currency.clone()),
+        ])
+        .send()
+        .await?;
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+            .map(Duration::from_secs);
+        return Err(RateLimited(retry_after).into());
+    }
+    Ok(response.error_for_status()?.json().await?)
+}
+
+/// Publishes the reference price of each of `markets` that was priced.
+pub fn publish(
+    config: &CoinGeckoConfig,
+    cache: &SummaryCache,
+    markets: &[Market],
+    prices: &Prices,
+    now: Timestamp,
+) {
+    for market in markets {
+        match config.market_price(prices, market) {
+            Some(price) => cache.set_reference(
+                market.clone(),
+                Reference {
+                    price,
+                    source: Source::CoinGecko,
+                    updated: now,
+                },
+            ),
+            None => log::warn!("No CoinGecko price for {}", market),
+        }
+    }
+}
+
+/// Polls prices of `markets` into `cache` until `shutdown` flips, backing
+/// off exponentially while rate limited.
+pub async fn run(
+    client: reqwest::Client,
+    config: CoinGeckoConfig,
+    markets: Vec<Market>,
+    cache: SummaryCache,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    // Waits after a 429 start at twice the regular interval.
+    let mut backoff = Backoff::new(config.interval() * 2, MAX_BACKOFF);
+    loop {
+        let delay = match fetch(&client, &config).await {
+            Ok(prices) => {
+                publish(&config, &cache, &markets, &prices, unix_timestamp());
+                backoff.reset();
+                config.interval()
+            }
+            Err(e) => match e.downcast_ref::<RateLimited>() {
+                Some(RateLimited(retry_after)) => {
+                    let delay = backoff.next_delay().max(retry_after.unwrap_or_default());
+                    log::warn!("Rate limited by CoinGecko, retrying in {:?}", delay);
+                    delay
+                }
+                None => {
+                    log::warn!("Polling CoinGecko failed: {}", e);
+                    config.interval()
+                }
+            },
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = shutdown.changed() => break,
+        }
+    }
+    log::info!("CoinGecko feed stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config() -> CoinGeckoConfig {
+        toml::from_str(
+            r#"
+            interval_secs = 1
+            ids = { ETH = "ethereum", USDC = "usd-coin", LINK = "chainlink" }
+            "#,
+        )
+        .expect("from_str")
+    }
+
+    fn prices(json: &str) -> Prices {
+        serde_json::from_str(json).expect("from_str")
+    }
+
+    #[test]
+    fn test_market_prices() {
+        let config = config();
+        assert_eq!(config.interval(), MIN_INTERVAL);
+        assert_eq!(config.vs_currency, "usd");
+        let prices = prices(
+            r#"{"chainlink":{"usd":7.5},"ethereum":{"usd":1600.5},"usd-coin":{"usd":1.0005}}"#,
+        );
+        assert!(config.check_ids(&prices).is_ok());
+        assert_eq!(
+            config
+                .market_price(&prices, "ETH-USDC")
+                .map(|p| p.round_dp(6)),
+            Some(dec!(1599.700150))
+        );
+        assert_eq!(
+            config
+                .market_price(&prices, "LINK-ETH")
+                .map(|p| p.round_dp(8)),
+            Some(dec!(0.00468604))
+        );
+        assert!(!config.covers("WBTC-USDC"));
+        assert_eq!(config.market_price(&prices, "WBTC-USDC"), None);
+
+        let cache = SummaryCache::new();
+        publish(
+            &config,
+            &cache,
+            &["LINK-USDC".to_owned(), "WBTC-USDC".to_owned()],
+            &prices,
+            100,
+        );
+        let reference = cache.reference("LINK-USDC").expect("reference");
+        assert_eq!(reference.source, Source::CoinGecko);
+        assert_eq!(reference.updated, 100);
+        assert_eq!(cache.reference("WBTC-USDC"), None);
+    }
+
+    #[test]
+    fn test_unknown_ids() {
+        let config = config();
+        let prices = prices(r#"{"ethereum":{"usd":1600.5},"usd-coin":{"usd":0}}"#);
+        let err = config.check_ids(&prices).unwrap_err().to_string();
+        assert!(err.contains("chainlink (LINK)"), "{}", err);
+        assert!(err.contains("usd-coin (USDC)"), "{}", err);
+        assert!(!err.contains("ethereum"), "{}", err);
+        assert_eq!(config.market_price(&prices, "ETH-USDC"), None);
+    }
+}
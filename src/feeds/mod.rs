@@ -0,0 +1,132 @@
+/// External reference prices, published into the `SummaryCache` so that
+/// strategies do not quote off ZigZag's own, possibly thin, last price.
+pub mod binance;
+pub mod chainlink;
+pub mod coingecko;
+
+use crate::zigzag::{Decimal, Market, Timestamp};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Binance,
+    Chainlink,
+    CoinGecko,
+}
+
+/// Reference price of a market as last published by a feed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub price: Decimal,
+    pub source: Source,
+    pub updated: Timestamp,
+}
+
+impl Reference {
+    pub fn is_stale(&self, max_age: Duration, now: Timestamp) -> bool {
+        now.saturating_sub(self.updated) > max_age.as_secs()
+    }
+}
+
+/// `[feeds]` table of the config file.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedsConfig {
+    pub binance: Option<binance::BinanceConfig>,
+    pub chainlink: Option<chainlink::ChainlinkConfig>,
+    pub coingecko: Option<coingecko::CoinGeckoConfig>,
+    /// Feed set per market in `[markets]`, by `Config::resolve`
+    #[serde(skip)]
+    pub pinned: HashMap<Market, Source>,
+}
+
+impl FeedsConfig {
+    /// Feed providing the reference price of `market`: the one pinned for
+    /// it, or by order of preference Binance, Chainlink and CoinGecko as a
+    /// fallback.
+    pub fn source(&self, market: &str) -> Option<Source> {
+        if let Some(source) = self.pinned.get(market) {
+            return self.covers(*source, market).then_some(*source);
+        }
+        [Source::Binance, Source::Chainlink, Source::CoinGecko]
+            .into_iter()
+            .find(|source| self.covers(*source, market))
+    }
+
+    fn covers(&self, source: Source, market: &str) -> bool {
+        match source {
+            Source::Binance => {
+                matches!(&self.binance, Some(binance) if binance.symbols.contains_key(market))
+            }
+            Source::Chainlink => {
+                matches!(&self.chainlink, Some(chainlink) if chainlink.aggregators.contains_key(market))
+            }
+            Source::CoinGecko => {
+                matches!(&self.coingecko, Some(coingecko) if coingecko.covers(market))
+            }
+        }
+    }
+
+    /// Age past which references of `source` are stale.
+    pub fn max_age(&self, source: Source) -> Option<Duration> {
+        match source {
+            Source::Binance => self.binance.as_ref().map(|binance| binance.max_age()),
+            Source::Chainlink => self.chainlink.as_ref().map(|chainlink| chainlink.max_age()),
+            Source::CoinGecko => self.coingecko.as_ref().map(|coingecko| coingecko.max_age()),
+        }
+    }
+
+    /// Markets of `markets` whose reference comes from `source`.
+    pub fn markets_of(&self, source: Source, markets: &[Market]) -> Vec<Market> {
+        markets
+            .iter()
+            .filter(|market| self.source(market) == Some(source))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_per_market() {
+        let feeds: FeedsConfig = toml::from_str(
+            r#"
+            [binance.symbols]
+            "ETH-USDC" = "ethusdc"
+
+            [chainlink.aggregators]
+            "LINK-USDC" = "0x2c1d072e956affc0d435cb7ac38ef18d24d9127c"
+
+            [coingecko]
+            max_age_secs = 120
+            ids = { ETH = "ethereum", USDC = "usd-coin", LINK = "chainlink" }
+            "#,
+        )
+        .expect("from_str");
+        assert_eq!(feeds.source("ETH-USDC"), Some(Source::Binance));
+        assert_eq!(feeds.source("LINK-USDC"), Some(Source::Chainlink));
+        assert_eq!(feeds.source("LINK-ETH"), Some(Source::CoinGecko));
+        assert_eq!(feeds.source("WBTC-USDC"), None);
+        assert_eq!(
+            feeds.max_age(Source::CoinGecko),
+            Some(Duration::from_secs(120))
+        );
+        let markets = ["ETH-USDC".to_owned(), "LINK-ETH".to_owned()];
+        assert_eq!(
+            feeds.markets_of(Source::CoinGecko, &markets),
+            vec!["LINK-ETH"]
+        );
+
+        let mut feeds = feeds;
+        feeds.pinned.insert("ETH-USDC".into(), Source::CoinGecko);
+        feeds.pinned.insert("LINK-ETH".into(), Source::Binance);
+        assert_eq!(feeds.source("ETH-USDC"), Some(Source::CoinGecko));
+        assert_eq!(feeds.source("LINK-ETH"), None);
+    }
+}
@@ -0,0 +1,309 @@
+/// zksync swap fees, which makers pay on every fill. Fees are looked up with
+/// the zksync provider in the base asset of a market, cached for a while,
+/// and converted into the quote asset at the reference price. Fees of the
+/// bot's own zksync operations are paid in a token chosen from the config.
+use crate::orders::to_units;
+use crate::tokens::{Registry, TokenInfo};
+use crate::zigzag::{Amount, Asset, Decimal, MarketInfo, Timestamp};
+use async_trait::async_trait;
+use num::BigUint;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use zksync::{
+    provider::Provider,
+    zksync_types::{
+        tokens::ChangePubKeyFeeTypeArg, tx::ChangePubKeyType, BlockStatus, TokenId, TxFeeTypes,
+    },
+    Wallet,
+};
+use zksync_eth_signer::EthereumSigner;
+
+/// `[fees]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeConfig {
+    /// Edge kept over the fee on each side, in basis points of the mid
+    pub min_edge_bps: Decimal,
+    /// Sides whose fee exceeds this share of their notional are not quoted
+    pub max_fee_share: Decimal,
+    /// How long a fee estimate is reused, in seconds
+    pub ttl_secs: u64,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            min_edge_bps: Decimal::from(5),
+            max_fee_share: Decimal::new(1, 2),
+            ttl_secs: 60,
+        }
+    }
+}
+
+#[async_trait]
+pub trait FeeSource: Send + Sync {
+    /// Fee of a swap paid in `token`, in raw units.
+    async fn swap_fee(&self, token: TokenId) -> anyhow::Result<BigUint>;
+}
+
+#[async_trait]
+impl<S, P> FeeSource for Wallet<S, P>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    async fn swap_fee(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        let fee = self
+            .provider
+            .get_tx_fee(TxFeeTypes::Swap, self.address, token)
+            .await?;
+        Ok(fee.total_fee)
+    }
+}
+
+/// Balances and fees of a zksync operation the bot pays for.
+#[async_trait]
+pub trait FeePayer: Send + Sync {
+    async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint>;
+
+    /// Fee of the operation paid in `token`, in raw units.
+    async fn fee(&self, token: TokenId) -> anyhow::Result<BigUint>;
+}
+
+/// The change_pubkey setting the signing key of `wallet`'s account.
+pub struct ChangePubKeyFee<'a, S, P>(pub &'a Wallet<S, P>);
+
+#[async_trait]
+impl<S, P> FeePayer for ChangePubKeyFee<'_, S, P>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        Ok(self.0.get_balance(BlockStatus::Committed, token).await?)
+    }
+
+    async fn fee(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        let fee_type = TxFeeTypes::ChangePubKey(ChangePubKeyFeeTypeArg::ContractsV4Version(
+            ChangePubKeyType::ECDSA,
+        ));
+        let fee = self
+            .0
+            .provider
+            .get_tx_fee(fee_type, self.0.address, token)
+            .await?;
+        Ok(fee.total_fee)
+    }
+}
+
+/// Token paying an operation: the first of `candidates` zksync lists, that
+/// none of `assets` says is disabled for fees, and whose balance covers the
+/// fee. The candidates passed over are logged with the reason.
+pub async fn choose_fee_token(
+    candidates: &[String],
+    tokens: &Registry,
+    assets: &[Asset],
+    payer: &dyn FeePayer,
+) -> anyhow::Result<TokenInfo> {
+    let mut skipped = Vec::new();
+    for symbol in candidates {
+        let token = match tokens.by_symbol(symbol) {
+            Some(token) => token,
+            None => {
+                skipped.push(format!("{} is not a zksync token", symbol));
+                continue;
+            }
+        };
+        if assets
+            .iter()
+            .any(|asset| asset.id == token.id && !asset.enabled_for_fees)
+        {
+            skipped.push(format!("{} is not enabled for fees", token.symbol));
+            continue;
+        }
+        let id = TokenId(token.id);
+        let covered = async {
+            let balance = payer.committed_balance(id).await?;
+            let fee = payer.fee(id).await?;
+            if balance < fee {
+                return Err(anyhow::anyhow!(
+                    "the balance of {} {} does not cover the fee of {}",
+                    to_units(&balance, token.decimals)?,
+                    token.symbol,
+                    to_units(&fee, token.decimals)?
+                ));
+            }
+            Ok(())
+        };
+        match covered.await {
+            Ok(()) => {
+                if !skipped.is_empty() {
+                    log::warn!("Paying fees in {}: {}", token.symbol, skipped.join(", "));
+                }
+                return Ok(token);
+            }
+            Err(e) => skipped.push(format!("{}: {}", token.symbol, e)),
+        }
+    }
+    Err(anyhow::anyhow!(
+        "No token to pay the fee with: {}!",
+        skipped.join(", ")
+    ))
+}
+
+/// Swap fees by token, shared by the market makers.
+#[derive(Clone)]
+pub struct FeeEstimator {
+    source: Arc<dyn FeeSource>,
+    ttl_secs: u64,
+    cache: Arc<Mutex<HashMap<u32, (Timestamp, Amount)>>>,
+}
+
+impl FeeEstimator {
+    pub fn new(source: Arc<dyn FeeSource>, ttl_secs: u64) -> Self {
+        Self {
+            source,
+            ttl_secs,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Fee of a swap paid in `asset`, in human units. Estimates younger than
+    /// the TTL are reused.
+    pub async fn fee(&self, asset: &Asset, now: Timestamp) -> anyhow::Result<Amount> {
+        let cached = self.cache.lock().unwrap().get(&asset.id).copied();
+        if let Some((fetched, fee)) = cached {
+            if now < fetched + self.ttl_secs {
+                return Ok(fee);
+            }
+        }
+        let raw = self.source.swap_fee(TokenId(asset.id)).await?;
+        let fee = to_units(&raw, asset.decimals)?;
+        self.cache.lock().unwrap().insert(asset.id, (now, fee));
+        Ok(fee)
+    }
+
+    /// Fee of a swap on the market, in its quote asset. The fee is paid in
+    /// the base asset and converted at `price`.
+    pub async fn fee_in_quote(
+        &self,
+        market_info: &MarketInfo,
+        price: Decimal,
+        now: Timestamp,
+    ) -> anyhow::Result<Decimal> {
+        Ok(self.fee(&market_info.base_asset, now).await? * price)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fee source charging a fixed raw fee, counting lookups.
+    #[derive(Default)]
+    pub struct FixedFee {
+        pub raw: u64,
+        pub lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl FeeSource for FixedFee {
+        async fn swap_fee(&self, _token: TokenId) -> anyhow::Result<BigUint> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(BigUint::from(self.raw))
+        }
+    }
+
+    /// Balances and fees by token id.
+    struct Payer(HashMap<u32, (u64, u64)>);
+
+    #[async_trait]
+    impl FeePayer for Payer {
+        async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint> {
+            Ok(BigUint::from(self.0.get(&token).map_or(0, |b| b.0)))
+        }
+
+        async fn fee(&self, token: TokenId) -> anyhow::Result<BigUint> {
+            self.0
+                .get(&token)
+                .map(|b| BigUint::from(b.1))
+                .ok_or_else(|| anyhow::anyhow!("not suitable for paying fees"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_choose_fee_token() {
+        let token = |id, symbol: &str, decimals| TokenInfo {
+            id,
+            symbol: symbol.into(),
+            address: String::new(),
+            decimals,
+        };
+        let tokens = Registry::new(vec![
+            token(0, "ETH", 18),
+            token(1, "DAI", 18),
+            token(2, "USDC", 6),
+        ]);
+        let candidates =
+            |symbols: &[&str]| symbols.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let info = fixtures::market_info("ETH-USDC", 0, 2);
+        let assets = [info.base_asset.clone(), info.quote_asset.clone()];
+        // USDC covers its fee, DAI is not held, ETH is short.
+        let payer = Payer(HashMap::from([
+            (0, (1_000, 2_000)),
+            (1, (0, 3_000_000_000_000_000_000)),
+            (2, (5_000_000, 1_500_000)),
+        ]));
+        let choose = |symbols, assets| {
+            let candidates = candidates(symbols);
+            let (tokens, payer) = (&tokens, &payer);
+            async move { choose_fee_token(&candidates, tokens, assets, payer).await }
+        };
+
+        let chosen = choose(&["USDC", "DAI", "ETH"], &assets).await;
+        assert_eq!(chosen.expect("choose").symbol, "USDC");
+        let chosen = choose(&["WBTC", "DAI", "usdc", "ETH"], &assets).await;
+        assert_eq!(chosen.expect("choose").symbol, "USDC");
+
+        let mut disabled = assets.clone();
+        disabled[1].enabled_for_fees = false;
+        let e = choose(&["USDC", "DAI", "ETH"], &disabled)
+            .await
+            .expect_err("choose");
+        assert_eq!(
+            e.to_string(),
+            "No token to pay the fee with: USDC is not enabled for fees, \
+             DAI: the balance of 0 DAI does not cover the fee of 3, \
+             ETH: the balance of 0.000000000000001 ETH does not cover the fee of 0.000000000000002!"
+        );
+
+        // Tokens zksync does not take fees in are passed over too.
+        let payer = Payer(HashMap::from([(0, (2_000, 2_000))]));
+        let chosen = choose_fee_token(&candidates(&["DAI", "ETH"]), &tokens, &[], &payer).await;
+        assert_eq!(chosen.expect("choose").symbol, "ETH");
+    }
+
+    #[tokio::test]
+    async fn test_fee_in_quote() {
+        // 0.0005 ETH
+        let source = Arc::new(FixedFee {
+            raw: 500_000_000_000_000,
+            ..FixedFee::default()
+        });
+        let fees = FeeEstimator::new(source.clone(), 60);
+        let info = fixtures::market_info("ETH-USDC", 0, 2);
+        let fee = fees.fee_in_quote(&info, dec!(2000), 100).await;
+        assert_eq!(fee.expect("fee_in_quote"), dec!(1));
+        let fee = fees.fee_in_quote(&info, dec!(3000), 159).await;
+        assert_eq!(fee.expect("fee_in_quote"), dec!(1.5));
+        assert_eq!(source.lookups.load(Ordering::SeqCst), 1);
+
+        // The estimate is refreshed after the TTL.
+        fees.fee(&info.base_asset, 160).await.expect("fee");
+        assert_eq!(source.lookups.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,213 @@
+/// Binance spot hedger, placing orders through the signed REST API: the
+/// query string is signed with HMAC-SHA256 of the API secret.
+use super::{HedgeFill, HedgeOrder, Hedger};
+use crate::zigzag::{Decimal, Side};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_URL: &str = "https://api.binance.com";
+
+/// `[hedge.binance]` table of the config file.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BinanceHedgeConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    pub api_key: String,
+    pub api_secret: String,
+    /// Binance rejects requests older than this
+    #[serde(default = "default_recv_window_ms")]
+    pub recv_window_ms: u64,
+}
+
+fn default_url() -> String {
+    DEFAULT_URL.to_owned()
+}
+
+fn default_recv_window_ms() -> u64 {
+    5000
+}
+
+// Keeps the API credentials out of logs.
+impl std::fmt::Debug for BinanceHedgeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BinanceHedgeConfig")
+            .field("url", &self.url)
+            .field("api_key", &"<redacted>")
+            .field("api_secret", &"<redacted>")
+            .field("recv_window_ms", &self.recv_window_ms)
+            .finish()
+    }
+}
+
+/// Reply to an order placed with `newOrderRespType=RESULT`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OrderResult {
+    executed_qty: String,
+    cummulative_quote_qty: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    code: i64,
+    msg: String,
+}
+
+pub struct Binance {
+    client: reqwest::Client,
+    config: BinanceHedgeConfig,
+}
+
+impl Binance {
+    pub fn new(config: BinanceHedgeConfig, client: reqwest::Client) -> Self {
+        Self { client, config }
+    }
+
+    /// Parameters of the order, in the order they are signed.
+    pub fn order_query(&self, order: &HedgeOrder, timestamp_ms: u64) -> String {
+        let side = match order.side {
+            Side::Buy => "BUY",
+            Side::Sell => "SELL",
+        };
+        let mut params = vec![("symbol", order.symbol.clone()), ("side", side.to_owned())];
+        match order.limit_price {
+            Some(price) => {
+                params.push(("type", "LIMIT".to_owned()));
+                params.push(("timeInForce", "IOC".to_owned()));
+                params.push(("quantity", order.base_quantity.normalize().to_string()));
+                params.push(("price", price.normalize().to_string()));
+            }
+            None => {
+                params.push(("type", "MARKET".to_owned()));
+                params.push(("quantity", order.base_quantity.normalize().to_string()));
+            }
+        }
+        params.push(("newOrderRespType", "RESULT".to_owned()));
+        params.push(("recvWindow", self.config.recv_window_ms.to_string()));
+        params.push(("timestamp", timestamp_ms.to_string()));
+        params
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Hex HMAC-SHA256 of `query` with the API secret.
+    pub fn sign(&self, query: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.api_secret.as_bytes())
+            .expect("HMAC takes keys of any size");
+        mac.update(query.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+fn parse_decimal(name: &str, value: &str) -> anyhow::Result<Decimal> {
+    Decimal::from_str(value).map_err(|e| anyhow::anyhow!("Bad {} {:?}: {}", name, value, e))
+}
+
+#[async_trait]
+impl Hedger for Binance {
+    fn name(&self) -> &'static str {
+        "Binance"
+    }
+
+    async fn place(&self, order: &HedgeOrder) -> anyhow::Result<HedgeFill> {
+        let query = self.order_query(order, now_ms());
+        let url = format!(
+            "{}/api/v3/order?{}&signature={}",
+            self.config.url.trim_end_matches('/'),
+            query,
+            self.sign(&query)
+        );
+        let response = self
+            .client
+            .post(url)
+            .header("X-MBX-APIKEY", &self.config.api_key)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e.without_url()))?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(match serde_json::from_str::<ApiError>(&body) {
+                Ok(error) => anyhow::anyhow!("Binance error {}: {}", error.code, error.msg),
+                Err(_) => anyhow::anyhow!("Binance replied {}: {}", status, body),
+            });
+        }
+        let result: OrderResult = serde_json::from_str(&body)?;
+        let base_quantity = parse_decimal("executedQty", &result.executed_qty)?;
+        let quote_quantity = parse_decimal("cummulativeQuoteQty", &result.cummulative_quote_qty)?;
+        Ok(HedgeFill {
+            base_quantity,
+            avg_price: (!base_quantity.is_zero()).then(|| quote_quantity / base_quantity),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn binance(api_secret: &str) -> Binance {
+        Binance::new(
+            BinanceHedgeConfig {
+                url: DEFAULT_URL.into(),
+                api_key: "key".into(),
+                api_secret: api_secret.into(),
+                recv_window_ms: 5000,
+            },
+            reqwest::Client::new(),
+        )
+    }
+
+    #[test]
+    fn test_sign() {
+        // Example of the Binance API documentation.
+        let binance = binance("NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j");
+        assert_eq!(
+            binance.sign(
+                "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1\
+                 &recvWindow=5000&timestamp=1499827319559"
+            ),
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+    }
+
+    #[test]
+    fn test_order_query() {
+        let binance = binance("hunter2");
+        let mut order = HedgeOrder {
+            market: "ETH-USDC".into(),
+            symbol: "ETHUSDC".into(),
+            side: Side::Sell,
+            base_quantity: dec!(1.500),
+            reference_price: dec!(2000),
+            limit_price: None,
+        };
+        assert_eq!(
+            binance.order_query(&order, 1499827319559),
+            "symbol=ETHUSDC&side=SELL&type=MARKET&quantity=1.5&newOrderRespType=RESULT\
+             &recvWindow=5000&timestamp=1499827319559"
+        );
+        order.side = Side::Buy;
+        order.limit_price = Some(dec!(2002.00));
+        assert_eq!(
+            binance.order_query(&order, 1499827319559),
+            "symbol=ETHUSDC&side=BUY&type=LIMIT&timeInForce=IOC&quantity=1.5&price=2002\
+             &newOrderRespType=RESULT&recvWindow=5000&timestamp=1499827319559"
+        );
+        assert!(!format!("{:?}", binance.config).contains("hunter2"));
+    }
+}
@@ -0,0 +1,536 @@
+/// Hedging of our ZigZag fills on a centralized exchange, to stay flat as a
+/// maker. Fills are netted per market over an interval into one opposite
+/// order, placed through a `Hedger`. The slippage of the hedges versus the
+/// ZigZag prices is tracked, and failures are notified since they leave
+/// the position unhedged until the next attempt.
+pub mod binance;
+
+use crate::metrics::Metrics;
+use crate::notify::{Event, Notifications};
+use crate::portfolio::Trade;
+use crate::zigzag::{Amount, Decimal, Market, Side};
+use async_trait::async_trait;
+use binance::BinanceHedgeConfig;
+use rust_decimal::RoundingStrategy;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// `[hedge]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HedgeConfig {
+    /// Fills within this many seconds are hedged with one order
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Log the hedge orders instead of placing them
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub order_type: OrderType,
+    /// How far past the ZigZag price limit orders go, in basis points
+    #[serde(default = "default_limit_offset_bps")]
+    pub limit_offset_bps: Decimal,
+    /// ZigZag market to the symbol it is hedged with
+    pub markets: HashMap<Market, HedgeMarket>,
+    pub binance: Option<BinanceHedgeConfig>,
+}
+
+fn default_interval_secs() -> u64 {
+    2
+}
+
+fn default_limit_offset_bps() -> Decimal {
+    Decimal::from(20)
+}
+
+impl HedgeConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.interval_secs == 0 {
+            return Err(anyhow::anyhow!("hedge.interval_secs must be at least 1!"));
+        }
+        if !self.dry_run && self.binance.is_none() {
+            return Err(anyhow::anyhow!(
+                "hedge needs a [hedge.binance] table, or dry_run = true!"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderType {
+    #[default]
+    Market,
+    /// Immediate-or-cancel limit order `limit_offset_bps` past the ZigZag
+    /// price, what it does not fill being retried
+    Limit,
+}
+
+/// Symbol a market is hedged with on the exchange.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HedgeMarket {
+    pub symbol: String,
+    /// Precision the exchange accepts for quantities of the symbol
+    #[serde(default = "default_decimals")]
+    pub quantity_decimals: u32,
+    /// and for its prices
+    #[serde(default = "default_decimals")]
+    pub price_decimals: u32,
+}
+
+fn default_decimals() -> u32 {
+    8
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HedgeOrder {
+    pub market: Market,
+    pub symbol: String,
+    pub side: Side,
+    pub base_quantity: Amount,
+    /// Price the ZigZag fills netted to
+    pub reference_price: Decimal,
+    /// Worst price, for a limit order
+    pub limit_price: Option<Decimal>,
+}
+
+/// What a hedge order filled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HedgeFill {
+    pub base_quantity: Amount,
+    /// Unknown without a fill, or in a dry run
+    pub avg_price: Option<Decimal>,
+}
+
+/// An exchange hedge orders are placed on.
+#[async_trait]
+pub trait Hedger: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Places `order`, returning what it filled.
+    async fn place(&self, order: &HedgeOrder) -> anyhow::Result<HedgeFill>;
+}
+
+/// Logs the orders, as if they filled in full.
+pub struct DryRun;
+
+#[async_trait]
+impl Hedger for DryRun {
+    fn name(&self) -> &'static str {
+        "dry run"
+    }
+
+    async fn place(&self, order: &HedgeOrder) -> anyhow::Result<HedgeFill> {
+        let price = match order.limit_price {
+            Some(price) => format!("limit {}", price),
+            None => "market".to_owned(),
+        };
+        log::info!(
+            "Dry run: would {:?} {} {} at {} to hedge {}",
+            order.side,
+            order.base_quantity,
+            order.symbol,
+            price,
+            order.market
+        );
+        Ok(HedgeFill {
+            base_quantity: order.base_quantity,
+            avg_price: None,
+        })
+    }
+}
+
+/// Base quantity still to hedge on a market, positive when we bought on
+/// ZigZag, with the notional it was traded for.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Pending {
+    quantity: Amount,
+    notional: Decimal,
+}
+
+impl Pending {
+    fn add(&mut self, quantity: Amount, price: Decimal) {
+        self.quantity += quantity;
+        self.notional += quantity * price;
+    }
+
+    /// Price the fills netted to: hedging at it breaks even.
+    fn price(&self) -> Option<Decimal> {
+        (!self.quantity.is_zero()).then(|| self.notional / self.quantity)
+    }
+}
+
+/// Fills waiting to be hedged, and the slippage of the hedges so far.
+pub struct Hedge {
+    config: HedgeConfig,
+    pending: BTreeMap<Market, Pending>,
+    /// Base quantity hedged with a known price and its slippage times it,
+    /// per market
+    slippage: BTreeMap<Market, (Amount, Decimal)>,
+}
+
+impl Hedge {
+    pub fn new(config: HedgeConfig) -> Self {
+        Self {
+            config,
+            pending: BTreeMap::new(),
+            slippage: BTreeMap::new(),
+        }
+    }
+
+    /// Queues the offset of one of our fills, on a mapped market.
+    pub fn on_trade(&mut self, trade: &Trade) {
+        if !self.config.markets.contains_key(&trade.market) {
+            return;
+        }
+        let quantity = match trade.side {
+            Side::Buy => trade.quantity,
+            Side::Sell => -trade.quantity,
+        };
+        self.pending
+            .entry(trade.market.clone())
+            .or_default()
+            .add(quantity, trade.price);
+    }
+
+    /// Base quantity of `market` not hedged yet, positive when long.
+    pub fn unhedged(&self, market: &str) -> Amount {
+        self.pending
+            .get(market)
+            .map_or(Decimal::ZERO, |pending| pending.quantity)
+    }
+
+    /// One order per market offsetting the fills queued so far. What
+    /// rounds below the precision of the symbol stays queued.
+    pub fn orders(&mut self) -> Vec<HedgeOrder> {
+        let mut orders = Vec::new();
+        for (market, pending) in &mut self.pending {
+            let (hedge_market, price) = match (self.config.markets.get(market), pending.price()) {
+                (Some(hedge_market), Some(price)) => (hedge_market, price),
+                _ => continue,
+            };
+            let base_quantity = pending
+                .quantity
+                .abs()
+                .round_dp_with_strategy(hedge_market.quantity_decimals, RoundingStrategy::ToZero);
+            if base_quantity.is_zero() {
+                continue;
+            }
+            // We sell what we bought on ZigZag, and the other way round.
+            let (side, hedged) = match pending.quantity > Decimal::ZERO {
+                true => (Side::Sell, -base_quantity),
+                false => (Side::Buy, base_quantity),
+            };
+            pending.add(hedged, price);
+            let offset = self.config.limit_offset_bps / Decimal::from(10_000);
+            let limit_price = match (self.config.order_type, &side) {
+                (OrderType::Market, _) => None,
+                (OrderType::Limit, Side::Buy) => Some(price * (Decimal::ONE + offset)),
+                (OrderType::Limit, Side::Sell) => Some(price * (Decimal::ONE - offset)),
+            }
+            .map(|limit| limit.round_dp(hedge_market.price_decimals));
+            orders.push(HedgeOrder {
+                market: market.clone(),
+                symbol: hedge_market.symbol.clone(),
+                side,
+                base_quantity,
+                reference_price: price,
+                limit_price,
+            });
+        }
+        orders
+    }
+
+    /// Records what `order` filled, queuing the rest again. Returns the
+    /// slippage of the fill versus the ZigZag price, in basis points,
+    /// positive when worse.
+    pub fn on_hedged(&mut self, order: &HedgeOrder, fill: &HedgeFill) -> Option<Decimal> {
+        let unfilled = (order.base_quantity - fill.base_quantity).max(Decimal::ZERO);
+        if !unfilled.is_zero() {
+            self.requeue(order, unfilled);
+        }
+        let price = fill.avg_price.filter(|_| !fill.base_quantity.is_zero())?;
+        if order.reference_price.is_zero() {
+            return None;
+        }
+        let worse = match order.side {
+            Side::Buy => price - order.reference_price,
+            Side::Sell => order.reference_price - price,
+        };
+        let slippage_bps = worse / order.reference_price * Decimal::from(10_000);
+        let (quantity, weighted) = self.slippage.entry(order.market.clone()).or_default();
+        *quantity += fill.base_quantity;
+        *weighted += fill.base_quantity * slippage_bps;
+        Some(slippage_bps)
+    }
+
+    /// Queues `order` again after it failed.
+    pub fn on_failed(&mut self, order: &HedgeOrder) {
+        self.requeue(order, order.base_quantity);
+    }
+
+    fn requeue(&mut self, order: &HedgeOrder, base_quantity: Amount) {
+        let quantity = match order.side {
+            Side::Sell => base_quantity,
+            Side::Buy => -base_quantity,
+        };
+        self.pending
+            .entry(order.market.clone())
+            .or_default()
+            .add(quantity, order.reference_price);
+    }
+
+    /// Average slippage of the hedges of `market`, weighted by quantity.
+    pub fn avg_slippage_bps(&self, market: &str) -> Option<Decimal> {
+        let (quantity, weighted) = self.slippage.get(market)?;
+        (!quantity.is_zero()).then(|| weighted / quantity)
+    }
+}
+
+/// Hedges the trades coming on `trades` every `interval_secs` until
+/// `shutdown` flips, then one last time.
+pub async fn run(
+    mut hedge: Hedge,
+    hedger: Box<dyn Hedger>,
+    mut trades: mpsc::UnboundedReceiver<Trade>,
+    metrics: std::sync::Arc<Metrics>,
+    notifications: Notifications,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(hedge.config.interval());
+    loop {
+        tokio::select! {
+            Some(trade) = trades.recv() => hedge.on_trade(&trade),
+            _ = ticker.tick() => place(&mut hedge, hedger.as_ref(), &metrics, &notifications).await,
+            _ = shutdown.changed() => break,
+        }
+    }
+    while let Ok(trade) = trades.try_recv() {
+        hedge.on_trade(&trade);
+    }
+    place(&mut hedge, hedger.as_ref(), &metrics, &notifications).await;
+}
+
+async fn place(
+    hedge: &mut Hedge,
+    hedger: &dyn Hedger,
+    metrics: &Metrics,
+    notifications: &Notifications,
+) {
+    for order in hedge.orders() {
+        match hedger.place(&order).await {
+            Ok(fill) => {
+                metrics.incr("hedges");
+                let slippage = hedge.on_hedged(&order, &fill);
+                log::info!(
+                    "Hedged {}: {:?} {} of {} {} on {}, slippage {}",
+                    order.market,
+                    order.side,
+                    fill.base_quantity,
+                    order.base_quantity,
+                    order.symbol,
+                    hedger.name(),
+                    slippage.map_or("unknown".to_owned(), |bps| format!(
+                        "{} bps",
+                        bps.round_dp(1).normalize()
+                    ))
+                );
+                if let Some(avg) = hedge.avg_slippage_bps(&order.market) {
+                    metrics.set_gauge(&format!("hedge_slippage_bps_{}", order.market), avg);
+                }
+            }
+            Err(e) => {
+                metrics.incr("hedge_failures");
+                hedge.on_failed(&order);
+                log::error!(
+                    "Hedging {} on {} failed: {}",
+                    order.market,
+                    hedger.name(),
+                    e
+                );
+                notifications.notify(Event::HedgeFailed {
+                    market: order.market.clone(),
+                    unhedged: hedge.unhedged(&order.market),
+                    error: e.to_string(),
+                });
+            }
+        }
+        metrics.set_gauge(
+            &format!("unhedged_{}", order.market),
+            hedge.unhedged(&order.market),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::sync::{Arc, Mutex};
+
+    fn config(order_type: OrderType) -> HedgeConfig {
+        HedgeConfig {
+            interval_secs: 1,
+            dry_run: true,
+            order_type,
+            limit_offset_bps: dec!(10),
+            markets: HashMap::from([(
+                "ETH-USDC".into(),
+                HedgeMarket {
+                    symbol: "ETHUSDC".into(),
+                    quantity_decimals: 3,
+                    price_decimals: 2,
+                },
+            )]),
+            binance: None,
+        }
+    }
+
+    fn trade(market: &str, side: Side, quantity: Amount, price: Decimal) -> Trade {
+        Trade {
+            market: market.into(),
+            side,
+            quantity,
+            price,
+        }
+    }
+
+    #[test]
+    fn test_orders() {
+        let mut hedge = Hedge::new(config(OrderType::Market));
+        // Rapid fills net into one order, at the price they net to.
+        hedge.on_trade(&trade("ETH-USDC", Side::Buy, dec!(1), dec!(2000)));
+        hedge.on_trade(&trade("ETH-USDC", Side::Buy, dec!(0.5005), dec!(2010)));
+        hedge.on_trade(&trade("ETH-USDC", Side::Sell, dec!(0.5), dec!(2020)));
+        hedge.on_trade(&trade("WBTC-USDC", Side::Buy, dec!(1), dec!(30000)));
+        let orders = hedge.orders();
+        assert_eq!(
+            orders,
+            vec![HedgeOrder {
+                market: "ETH-USDC".into(),
+                symbol: "ETHUSDC".into(),
+                side: Side::Sell,
+                base_quantity: dec!(1),
+                reference_price: orders[0].reference_price,
+                limit_price: None,
+            }]
+        );
+        assert_eq!(orders[0].reference_price.round_dp(4), dec!(1995.0075));
+        // The 0.0005 below the precision of the symbol waits.
+        assert_eq!(hedge.unhedged("ETH-USDC"), dec!(0.0005));
+        assert_eq!(hedge.unhedged("WBTC-USDC"), Decimal::ZERO);
+        assert!(hedge.orders().is_empty());
+
+        let mut hedge = Hedge::new(config(OrderType::Limit));
+        hedge.on_trade(&trade("ETH-USDC", Side::Sell, dec!(2), dec!(2000)));
+        let orders = hedge.orders();
+        assert_eq!(orders[0].side, Side::Buy);
+        assert_eq!(orders[0].limit_price, Some(dec!(2002)));
+    }
+
+    #[test]
+    fn test_slippage() {
+        let mut hedge = Hedge::new(config(OrderType::Limit));
+        hedge.on_trade(&trade("ETH-USDC", Side::Buy, dec!(2), dec!(2000)));
+        let order = hedge.orders().remove(0);
+        assert_eq!(order.limit_price, Some(dec!(1998)));
+        // Selling at 1999 what we bought at 2000 is 5 bps worse.
+        let fill = HedgeFill {
+            base_quantity: dec!(1.5),
+            avg_price: Some(dec!(1999)),
+        };
+        assert_eq!(hedge.on_hedged(&order, &fill), Some(dec!(5)));
+        // The unfilled rest is retried, from the same price.
+        assert_eq!(hedge.unhedged("ETH-USDC"), dec!(0.5));
+        let order = hedge.orders().remove(0);
+        assert_eq!(order.reference_price, dec!(2000));
+        let fill = HedgeFill {
+            base_quantity: dec!(0.5),
+            avg_price: Some(dec!(2001)),
+        };
+        assert_eq!(hedge.on_hedged(&order, &fill), Some(dec!(-5)));
+        assert_eq!(hedge.avg_slippage_bps("ETH-USDC"), Some(dec!(2.5)));
+        assert_eq!(hedge.unhedged("ETH-USDC"), Decimal::ZERO);
+
+        hedge.on_trade(&trade("ETH-USDC", Side::Sell, dec!(1), dec!(2000)));
+        let order = hedge.orders().remove(0);
+        hedge.on_failed(&order);
+        assert_eq!(hedge.unhedged("ETH-USDC"), dec!(-1));
+        assert_eq!(hedge.orders(), vec![order]);
+    }
+
+    /// Fails the first order, fills the others in full at 2000.
+    struct Flaky {
+        orders: Arc<Mutex<Vec<HedgeOrder>>>,
+    }
+
+    #[async_trait]
+    impl Hedger for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        async fn place(&self, order: &HedgeOrder) -> anyhow::Result<HedgeFill> {
+            let mut orders = self.orders.lock().unwrap();
+            orders.push(order.clone());
+            if orders.len() == 1 {
+                return Err(anyhow::anyhow!(
+                    "Timestamp for this request is outside of the recvWindow"
+                ));
+            }
+            Ok(HedgeFill {
+                base_quantity: order.base_quantity,
+                avg_price: Some(dec!(2000)),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let orders = Arc::new(Mutex::new(Vec::new()));
+        let (trades_tx, trades) = mpsc::unbounded_channel();
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let metrics = Arc::new(Metrics::new());
+        let task = tokio::spawn(run(
+            Hedge::new(config(OrderType::Market)),
+            Box::new(Flaky {
+                orders: orders.clone(),
+            }),
+            trades,
+            metrics.clone(),
+            Notifications::from_senders(vec![events_tx]),
+            shutdown,
+        ));
+        trades_tx
+            .send(trade("ETH-USDC", Side::Buy, dec!(1), dec!(2000)))
+            .unwrap();
+        // Hedging fails, which leaves the position open.
+        let event = events.recv().await.expect("event");
+        assert!(matches!(
+            event,
+            Event::HedgeFailed { unhedged, .. } if unhedged == dec!(1)
+        ));
+        shutdown_tx.send(true).unwrap();
+        task.await.expect("run");
+        // Retried on the way out.
+        let orders = orders.lock().unwrap();
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[1].base_quantity, dec!(1));
+        assert_eq!(metrics.counter("hedges"), 1);
+        assert_eq!(metrics.counter("hedge_failures"), 1);
+        assert_eq!(metrics.gauge("unhedged_ETH-USDC"), Some(Decimal::ZERO));
+        assert_eq!(
+            metrics.gauge("hedge_slippage_bps_ETH-USDC"),
+            Some(Decimal::ZERO)
+        );
+    }
+}
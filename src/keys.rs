@@ -0,0 +1,347 @@
+/// Loading of the Ethereum signing key, either as a raw hex private key or
+/// derived from a BIP-39 mnemonic, read from the config, a file, the OS
+/// keyring or the output of a command. Key material read here is zeroized
+/// once parsed, and errors never echo it.
+use bip32::{DerivationPath, XPrv};
+use std::fmt;
+use std::fs;
+use std::process::Command;
+use std::str::FromStr;
+use zeroize::{Zeroize, Zeroizing};
+use zksync::zksync_types::H256;
+
+/// Where the signing key comes from.
+#[derive(Clone, PartialEq, Eq)]
+pub enum KeySource {
+    Raw(String),
+    File(String),
+    Mnemonic(String),
+    MnemonicFile(String),
+    /// Entry of the OS keyring, holding a hex key or a mnemonic
+    Keyring {
+        service: String,
+        user: String,
+    },
+    /// Shell command printing a hex key or a mnemonic, e.g. `pass show zigzag`
+    Exec(String),
+}
+
+// Keeps inline keys out of logs.
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeySource::Raw(_) => write!(f, "Raw(<redacted>)"),
+            KeySource::File(path) => f.debug_tuple("File").field(path).finish(),
+            KeySource::Mnemonic(_) => write!(f, "Mnemonic(<redacted>)"),
+            KeySource::MnemonicFile(path) => f.debug_tuple("MnemonicFile").field(path).finish(),
+            KeySource::Keyring { service, user } => f
+                .debug_struct("Keyring")
+                .field("service", service)
+                .field("user", user)
+                .finish(),
+            KeySource::Exec(command) => f.debug_tuple("Exec").field(command).finish(),
+        }
+    }
+}
+
+/// Parses the `--key-from` spec: `hex:<key>`, `file:<path>`,
+/// `mnemonic:<phrase>`, `mnemonic-file:<path>`, `keyring:<service>/<user>`
+/// or `exec:<command>`.
+impl FromStr for KeySource {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> anyhow::Result<Self> {
+        let (scheme, rest) = spec.split_once(':').ok_or_else(|| {
+            anyhow::anyhow!("Key source needs a scheme, such as file:, keyring: or exec:!")
+        })?;
+        if rest.is_empty() {
+            return Err(anyhow::anyhow!("Key source {}: is empty!", scheme));
+        }
+        match scheme {
+            "hex" => Ok(KeySource::Raw(rest.to_owned())),
+            "file" => Ok(KeySource::File(rest.to_owned())),
+            "mnemonic" => Ok(KeySource::Mnemonic(rest.to_owned())),
+            "mnemonic-file" => Ok(KeySource::MnemonicFile(rest.to_owned())),
+            "keyring" => match rest.rsplit_once('/') {
+                Some((service, user)) if !service.is_empty() && !user.is_empty() => {
+                    Ok(KeySource::Keyring {
+                        service: service.to_owned(),
+                        user: user.to_owned(),
+                    })
+                }
+                _ => Err(anyhow::anyhow!(
+                    "Keyring source {:?} is not <service>/<user>!",
+                    rest
+                )),
+            },
+            "exec" => Ok(KeySource::Exec(rest.to_owned())),
+            // The rest may be a key, which is not echoed.
+            scheme => Err(anyhow::anyhow!("Unknown key source {}:!", scheme)),
+        }
+    }
+}
+
+impl KeySource {
+    /// Loads the private key. Mnemonics are derived at
+    /// `m/44'/60'/0'/0/{derivation_index}`.
+    pub fn private_key(&self, derivation_index: u32) -> anyhow::Result<H256> {
+        match self {
+            KeySource::Raw(key) => parse_private_key(key),
+            KeySource::File(path) => parse_private_key(&read_file(path)?),
+            KeySource::Mnemonic(phrase) => mnemonic_private_key(phrase, derivation_index),
+            KeySource::MnemonicFile(path) => {
+                mnemonic_private_key(&read_file(path)?, derivation_index)
+            }
+            KeySource::Keyring { service, user } => {
+                parse_secret(&keyring_secret(service, user)?, derivation_index)
+            }
+            KeySource::Exec(command) => parse_secret(&exec_secret(command)?, derivation_index),
+        }
+    }
+}
+
+fn read_file(path: &str) -> anyhow::Result<Zeroizing<String>> {
+    fs::read_to_string(path)
+        .map(Zeroizing::new)
+        .map_err(|e| anyhow::anyhow!("Reading key file {}: {}", path, e))
+}
+
+/// A hex private key, or a mnemonic when there are several words.
+fn parse_secret(secret: &str, derivation_index: u32) -> anyhow::Result<H256> {
+    match secret.split_whitespace().nth(1) {
+        Some(_) => mnemonic_private_key(secret, derivation_index),
+        None => parse_private_key(secret),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_secret(service: &str, user: &str) -> anyhow::Result<Zeroizing<String>> {
+    keyring::Entry::new(service, user)
+        .and_then(|entry| entry.get_password())
+        .map(Zeroizing::new)
+        .map_err(|e| anyhow::anyhow!("Reading keyring entry {}/{}: {}", service, user, e))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_secret(service: &str, user: &str) -> anyhow::Result<Zeroizing<String>> {
+    Err(anyhow::anyhow!(
+        "Cannot read keyring entry {}/{}: built without the keyring feature!",
+        service,
+        user
+    ))
+}
+
+/// Standard output of `command`, run by `sh`.
+fn exec_secret(command: &str) -> anyhow::Result<Zeroizing<String>> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Running key command {:?}: {}", command, e))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Key command {:?} failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    match std::str::from_utf8(&stdout) {
+        Ok(secret) => Ok(Zeroizing::new(secret.to_owned())),
+        Err(_) => Err(anyhow::anyhow!(
+            "Key command {:?} printed invalid UTF-8!",
+            command
+        )),
+    }
+}
+
+pub fn parse_private_key(raw: &str) -> anyhow::Result<H256> {
+    let raw = raw.trim();
+    if raw.len() != 64 {
+        return Err(anyhow::anyhow!("Private key is not in a valid format!"));
+    }
+    let mut data = [0u8; 32];
+    // The decoding error would point at the offending character.
+    let decoded = hex::decode_to_slice(raw, &mut data[..])
+        .map_err(|_| anyhow::anyhow!("Private key is not valid hex!"));
+    let key = H256(data);
+    data.zeroize();
+    decoded.map(|()| key)
+}
+
+pub fn mnemonic_private_key(phrase: &str, derivation_index: u32) -> anyhow::Result<H256> {
+    let words: Vec<_> = phrase.split_whitespace().collect();
+    let normalized = Zeroizing::new(words.join(" "));
+    let mnemonic = bip39::Mnemonic::parse_normalized(&normalized).map_err(|e| match e {
+        // The word itself may be a typo of the right one.
+        bip39::Error::UnknownWord(i) => {
+            anyhow::anyhow!("Mnemonic word {} is not in the BIP-39 wordlist!", i + 1)
+        }
+        bip39::Error::BadWordCount(n) => {
+            anyhow::anyhow!("Mnemonic has {} words, expected 12 or 24!", n)
+        }
+        bip39::Error::InvalidChecksum => {
+            anyhow::anyhow!("Mnemonic checksum is invalid, please check the words and their order!")
+        }
+        _ => anyhow::anyhow!("Mnemonic is not valid!"),
+    })?;
+    let path: DerivationPath = format!("m/44'/60'/0'/0/{}", derivation_index).parse()?;
+    let seed = Zeroizing::new(mnemonic.to_seed(""));
+    let key = XPrv::derive_from_path(&seed[..], &path)?;
+    Ok(H256(key.private_key().to_bytes().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zksync_eth_signer::{EthereumSigner, PrivateKeySigner};
+
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    #[tokio::test]
+    async fn test_mnemonic_address() {
+        let phrase = format!("  {}\n", MNEMONIC.replace(' ', " \n "));
+        let key = mnemonic_private_key(&phrase, 0).expect("mnemonic_private_key");
+        assert_eq!(
+            key,
+            parse_private_key("ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80")
+                .expect("parse_private_key")
+        );
+        let address = PrivateKeySigner::new(key)
+            .get_address()
+            .await
+            .expect("get_address");
+        assert_eq!(
+            hex::encode(address.as_bytes()),
+            "f39fd6e51aad88f6f4ce6ab8827279cfffb92266"
+        );
+
+        let address = PrivateKeySigner::new(mnemonic_private_key(MNEMONIC, 1).expect("index 1"))
+            .get_address()
+            .await
+            .expect("get_address");
+        assert_eq!(
+            hex::encode(address.as_bytes()),
+            "70997970c51812dc3a010c7d01b50e0d17dc79c8"
+        );
+    }
+
+    #[test]
+    fn test_mnemonic_errors() {
+        let err = mnemonic_private_key(&MNEMONIC.replacen("test", "tset", 1), 0).unwrap_err();
+        assert!(err.to_string().contains("word 1 "), "{}", err);
+        assert!(!err.to_string().contains("tset"), "{}", err);
+        let err = mnemonic_private_key("test test test", 0).unwrap_err();
+        assert!(err.to_string().contains("3 words"), "{}", err);
+        let err = mnemonic_private_key(&MNEMONIC.replace("junk", "test"), 0).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_private_key() {
+        assert!(parse_private_key("00").is_err());
+        let err = parse_private_key(&format!("{}zz", "ab".repeat(31))).unwrap_err();
+        assert!(!err.to_string().contains("ab"), "{}", err);
+        assert!(!err.to_string().contains('z'), "{}", err);
+        assert_eq!(
+            parse_private_key(&format!("{}\n", "11".repeat(32))).expect("parse_private_key"),
+            H256([0x11; 32])
+        );
+    }
+
+    #[test]
+    fn test_key_from() {
+        assert_eq!(
+            "keyring:zigzag-bots/main"
+                .parse::<KeySource>()
+                .expect("keyring"),
+            KeySource::Keyring {
+                service: "zigzag-bots".into(),
+                user: "main".into()
+            }
+        );
+        assert_eq!(
+            "exec:pass show zigzag".parse::<KeySource>().expect("exec"),
+            KeySource::Exec("pass show zigzag".into())
+        );
+        assert_eq!(
+            "mnemonic-file:words.txt"
+                .parse::<KeySource>()
+                .expect("file"),
+            KeySource::MnemonicFile("words.txt".into())
+        );
+        assert!("keyring:main".parse::<KeySource>().is_err());
+        assert!("exec:".parse::<KeySource>().is_err());
+        let err = "hexa:1111".parse::<KeySource>().unwrap_err();
+        assert!(!err.to_string().contains("1111"), "{}", err);
+        let key = "11".repeat(32);
+        let source = format!("hex:{}", key).parse::<KeySource>().expect("hex");
+        assert!(!format!("{:?}", source).contains(&key));
+        assert_eq!(source.private_key(0).expect("hex"), H256([0x11; 32]));
+    }
+
+    #[test]
+    fn test_file() {
+        let dir = std::env::temp_dir().join(format!("zigzag-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create_dir_all");
+        let path = dir.join("key.txt");
+        fs::write(&path, format!("{}\n", "22".repeat(32))).expect("write");
+        let source = KeySource::File(path.to_string_lossy().into_owned());
+        assert_eq!(source.private_key(0).expect("file"), H256([0x22; 32]));
+        let path = dir.join("words.txt");
+        fs::write(&path, MNEMONIC).expect("write");
+        let source = KeySource::MnemonicFile(path.to_string_lossy().into_owned());
+        assert_eq!(
+            source.private_key(1).expect("mnemonic file"),
+            mnemonic_private_key(MNEMONIC, 1).expect("mnemonic")
+        );
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+        assert!(source.private_key(0).is_err());
+    }
+
+    #[test]
+    fn test_exec() {
+        let source = KeySource::Exec(format!("echo {}", "33".repeat(32)));
+        assert_eq!(source.private_key(0).expect("exec"), H256([0x33; 32]));
+        let source = KeySource::Exec(format!("printf '%s\\n' '{}'", MNEMONIC));
+        assert_eq!(
+            source.private_key(0).expect("exec mnemonic"),
+            mnemonic_private_key(MNEMONIC, 0).expect("mnemonic")
+        );
+        let err = KeySource::Exec("echo oops >&2; exit 3".into())
+            .private_key(0)
+            .unwrap_err();
+        assert!(err.to_string().contains("oops"), "{}", err);
+        // What the command printed is not echoed when it is no key.
+        let err = KeySource::Exec("echo 4444".into())
+            .private_key(0)
+            .unwrap_err();
+        assert!(!err.to_string().contains("4444"), "{}", err);
+    }
+
+    #[cfg(feature = "keyring")]
+    #[test]
+    fn test_keyring() {
+        let source = KeySource::Keyring {
+            service: "zigzag-bots-test".into(),
+            user: format!("missing-{}", std::process::id()),
+        };
+        let err = source.private_key(0).unwrap_err();
+        assert!(
+            err.to_string().contains("zigzag-bots-test/missing-"),
+            "{}",
+            err
+        );
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    #[test]
+    fn test_keyring() {
+        let source = "keyring:zigzag-bots/main"
+            .parse::<KeySource>()
+            .expect("keyring");
+        let err = source.private_key(0).unwrap_err();
+        assert!(err.to_string().contains("keyring feature"), "{}", err);
+    }
+}
@@ -0,0 +1,243 @@
+/// Kill switch halting the bot on abnormal reference price moves, bursts
+/// of errors from ZigZag, or a single fatal one. Once tripped it stays tripped: resuming takes a
+/// restart.
+use crate::zigzag::{Decimal, ErrorArgs, Market, Timestamp};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+
+/// `[kill_switch]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KillSwitchConfig {
+    /// Price move limit of the markets not listed in `markets`
+    pub price_move: Option<PriceMoveLimit>,
+    pub markets: HashMap<Market, PriceMoveLimit>,
+    /// Trip on more than `max_errors` errors within `error_window_secs`
+    pub max_errors: Option<usize>,
+    pub error_window_secs: u64,
+}
+
+impl Default for KillSwitchConfig {
+    fn default() -> Self {
+        Self {
+            price_move: None,
+            markets: HashMap::new(),
+            max_errors: None,
+            error_window_secs: 60,
+        }
+    }
+}
+
+/// Trip when the reference price moves by more than `max_move_pct` percent
+/// within `window_secs`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PriceMoveLimit {
+    pub max_move_pct: Decimal,
+    pub window_secs: u64,
+}
+
+/// Prices seen over the last `window_secs`.
+struct PriceWindow {
+    limit: PriceMoveLimit,
+    samples: VecDeque<(Timestamp, Decimal)>,
+}
+
+impl PriceWindow {
+    /// Returns the move, in percent, when it exceeds the limit.
+    fn observe(&mut self, price: Decimal, now: Timestamp) -> Option<Decimal> {
+        while matches!(self.samples.front(), Some((t, _)) if t + self.limit.window_secs < now) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, price));
+        let prices = self.samples.iter().map(|(_, price)| *price);
+        let low = prices.clone().min()?;
+        let high = prices.max()?;
+        if low <= Decimal::ZERO {
+            return None;
+        }
+        let moved_pct = (high - low) / low * Decimal::from(100);
+        (moved_pct > self.limit.max_move_pct).then_some(moved_pct)
+    }
+}
+
+pub struct KillSwitch {
+    config: KillSwitchConfig,
+    prices: HashMap<Market, PriceWindow>,
+    errors: VecDeque<Timestamp>,
+    tripped: Option<String>,
+}
+
+impl KillSwitch {
+    pub fn new(config: KillSwitchConfig) -> Self {
+        Self {
+            config,
+            prices: HashMap::new(),
+            errors: VecDeque::new(),
+            tripped: None,
+        }
+    }
+
+    /// Why the switch tripped, if it did.
+    pub fn tripped(&self) -> Option<&str> {
+        self.tripped.as_deref()
+    }
+
+    /// Records the reference price of `market`. Returns the reason when this
+    /// trips the switch.
+    pub fn on_price(&mut self, market: &str, price: Decimal, now: Timestamp) -> Option<String> {
+        if self.tripped.is_some() {
+            return None;
+        }
+        let limit = self
+            .config
+            .markets
+            .get(market)
+            .or(self.config.price_move.as_ref())?;
+        let window = self
+            .prices
+            .entry(market.to_owned())
+            .or_insert_with(|| PriceWindow {
+                limit: limit.clone(),
+                samples: VecDeque::new(),
+            });
+        let moved_pct = window.observe(price, now)?;
+        let reason = format!(
+            "{} moved {}% within {}s, above {}%",
+            market,
+            moved_pct.round_dp(2),
+            window.limit.window_secs,
+            window.limit.max_move_pct
+        );
+        self.trip(reason)
+    }
+
+    /// Records an error from ZigZag. Returns the reason when this trips the
+    /// switch.
+    pub fn on_error(&mut self, now: Timestamp) -> Option<String> {
+        if self.tripped.is_some() {
+            return None;
+        }
+        let max_errors = self.config.max_errors?;
+        let window = self.config.error_window_secs;
+        while matches!(self.errors.front(), Some(t) if t + window < now) {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(now);
+        if self.errors.len() <= max_errors {
+            return None;
+        }
+        self.trip(format!(
+            "{} errors within {}s, above {}",
+            self.errors.len(),
+            window,
+            max_errors
+        ))
+    }
+
+    /// Trips on an error every further order would run into, such as a
+    /// rejected signature.
+    pub fn on_fatal_error(&mut self, error: &ErrorArgs) -> Option<String> {
+        if self.tripped.is_some() {
+            return None;
+        }
+        self.trip(format!("fatal error: {}", error))
+    }
+
+    fn trip(&mut self, reason: String) -> Option<String> {
+        self.tripped = Some(reason.clone());
+        Some(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::OperationName;
+    use rust_decimal_macros::dec;
+
+    fn kill_switch() -> KillSwitch {
+        KillSwitch::new(
+            toml::from_str(
+                r#"
+                price_move = { max_move_pct = 5, window_secs = 60 }
+                max_errors = 3
+                error_window_secs = 10
+
+                [markets.WBTC-USDC]
+                max_move_pct = 2
+                window_secs = 30
+                "#,
+            )
+            .expect("from_str"),
+        )
+    }
+
+    #[test]
+    fn test_gradual_moves_do_not_trip() {
+        let mut ks = kill_switch();
+        // 1% a minute for ten minutes, never 5% within a window.
+        let mut price = dec!(2000);
+        for minute in 0..10u64 {
+            for second in (0..60).step_by(10) {
+                assert_eq!(ks.on_price("ETH-USDC", price, minute * 60 + second), None);
+            }
+            price *= dec!(1.01);
+        }
+        // A flash move of another market is judged on that market alone.
+        assert_eq!(ks.on_price("WBTC-USDC", dec!(20000), 600), None);
+        assert_eq!(ks.on_price("WBTC-USDC", dec!(20300), 610), None);
+        assert_eq!(ks.tripped(), None);
+        // Markets without any limit are not watched.
+        let mut ks = KillSwitch::new(KillSwitchConfig::default());
+        assert_eq!(ks.on_price("ETH-USDC", dec!(2000), 0), None);
+        assert_eq!(ks.on_price("ETH-USDC", dec!(1), 1), None);
+    }
+
+    #[test]
+    fn test_flash_crash_trips() {
+        let mut ks = kill_switch();
+        assert_eq!(ks.on_price("ETH-USDC", dec!(2000), 100), None);
+        assert_eq!(ks.on_price("ETH-USDC", dec!(1950), 120), None);
+        let reason = ks.on_price("ETH-USDC", dec!(1880), 150).expect("trip");
+        assert!(reason.contains("ETH-USDC moved 6.38%"), "{}", reason);
+        assert_eq!(ks.tripped(), Some(reason.as_str()));
+        // Tripping is reported once.
+        assert_eq!(ks.on_price("ETH-USDC", dec!(1000), 151), None);
+
+        // The tighter per market limit applies.
+        let mut ks = kill_switch();
+        assert_eq!(ks.on_price("WBTC-USDC", dec!(20000), 100), None);
+        // Prices that left the window no longer count.
+        assert_eq!(ks.on_price("WBTC-USDC", dec!(20500), 131), None);
+        assert!(ks.on_price("WBTC-USDC", dec!(20000), 140).is_some());
+    }
+
+    #[test]
+    fn test_error_burst_trips() {
+        let mut ks = kill_switch();
+        for t in [0, 5, 10, 16, 20, 25] {
+            assert_eq!(ks.on_error(t), None, "{}", t);
+        }
+        assert_eq!(ks.on_error(27), None);
+        assert!(ks
+            .on_error(27)
+            .expect("trip")
+            .contains("4 errors within 10s"));
+    }
+
+    #[test]
+    fn test_fatal_error_trips() {
+        let mut ks = KillSwitch::new(KillSwitchConfig::default());
+        let error = ErrorArgs {
+            operation: OperationName::Submitorder3,
+            error: "Invalid signature".into(),
+        };
+        let reason = ks.on_fatal_error(&error).expect("trip");
+        assert_eq!(
+            reason,
+            "fatal error: Zigzag rejected submitorder3: Invalid signature"
+        );
+        assert_eq!(ks.on_fatal_error(&error), None);
+    }
+}
@@ -0,0 +1,146 @@
+//! Client and bots for the [ZigZag Exchange](https://zigzag.exchange) API.
+//!
+//! [`zigzag`] holds the message types of the websocket API and always
+//! compiles, with serde alone. The optional features add the rest:
+//!
+//! - `client`: websocket [`connection`] with reconnects and heartbeats, and
+//!   the request/response [`client`] on top of it.
+//! - `zksync`: zksync order and hash types in [`zigzag`], instead of raw JSON
+//!   and strings.
+//! - `test-util`: an in-process ZigZag server to test clients against, see
+//!   `mockserver`.
+//! - `bot`, the default: both of the above and the market maker of the
+//!   `zigzag-bots` binary, see [`bot::run`], with its database, HTTP
+//!   clients and terminal UI.
+//!
+//! ```no_run
+//! # #[cfg(feature = "client")]
+//! # async fn login() -> anyhow::Result<()> {
+//! use std::time::Duration;
+//! use zigzag_bots::client::ZigzagClient;
+//! use zigzag_bots::connection::{Backoff, Connection, Heartbeat};
+//!
+//! let heartbeat = Heartbeat {
+//!     interval: Duration::from_secs(10),
+//!     timeout: Duration::from_secs(30),
+//! };
+//! let connection = Connection::connect(
+//!     "wss://secret-thicket-93345.herokuapp.com",
+//!     Backoff::new(Duration::from_millis(500), Duration::from_secs(30)),
+//!     heartbeat,
+//! )
+//! .await?;
+//! let mut client = ZigzagClient::new(connection);
+//! client.login(1002, "23".into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod metrics;
+pub mod zigzag;
+
+#[cfg(feature = "client")]
+pub mod capture;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod connection;
+#[cfg(all(feature = "client", any(test, feature = "test-util")))]
+pub mod mockserver;
+#[cfg(feature = "client")]
+pub mod proxy;
+#[cfg(feature = "client")]
+pub mod receipt;
+#[cfg(feature = "client")]
+pub mod session;
+
+#[cfg(feature = "bot")]
+pub mod activation;
+#[cfg(feature = "bot")]
+pub mod avellaneda;
+#[cfg(feature = "bot")]
+pub mod backtest;
+#[cfg(feature = "bot")]
+pub mod balances;
+#[cfg(feature = "bot")]
+pub mod bot;
+#[cfg(feature = "bot")]
+pub mod cli;
+#[cfg(feature = "bot")]
+pub mod clock;
+#[cfg(feature = "bot")]
+pub mod config;
+#[cfg(feature = "bot")]
+pub mod dedup;
+#[cfg(feature = "bot")]
+pub mod deposit;
+#[cfg(feature = "bot")]
+pub mod dispatcher;
+#[cfg(feature = "bot")]
+pub mod display;
+#[cfg(feature = "bot")]
+pub mod evm;
+#[cfg(feature = "bot")]
+pub mod export;
+#[cfg(feature = "bot")]
+pub mod feeds;
+#[cfg(feature = "bot")]
+pub mod fees;
+#[cfg(feature = "bot")]
+pub mod hedge;
+#[cfg(feature = "bot")]
+pub mod keys;
+#[cfg(feature = "bot")]
+pub mod killswitch;
+#[cfg(feature = "bot")]
+pub mod logging;
+#[cfg(feature = "bot")]
+pub mod marketdata;
+#[cfg(feature = "bot")]
+pub mod markets;
+#[cfg(feature = "bot")]
+pub mod notify;
+#[cfg(feature = "bot")]
+pub mod orderbook;
+#[cfg(feature = "bot")]
+pub mod orders;
+#[cfg(feature = "bot")]
+pub mod portfolio;
+#[cfg(feature = "bot")]
+pub mod ratelimit;
+#[cfg(feature = "bot")]
+pub mod reconcile;
+#[cfg(feature = "bot")]
+pub mod reload;
+#[cfg(feature = "bot")]
+pub mod repl;
+#[cfg(feature = "bot")]
+pub mod rfq;
+#[cfg(feature = "bot")]
+pub mod risk;
+#[cfg(feature = "bot")]
+pub mod rpc;
+#[cfg(feature = "bot")]
+pub mod signals;
+#[cfg(feature = "bot")]
+pub mod snapshot;
+#[cfg(feature = "bot")]
+pub mod status;
+#[cfg(feature = "bot")]
+pub mod stops;
+#[cfg(feature = "bot")]
+pub mod storage;
+#[cfg(feature = "bot")]
+pub mod strategy;
+#[cfg(feature = "bot")]
+pub mod tokens;
+#[cfg(feature = "bot")]
+pub mod tui;
+#[cfg(feature = "bot")]
+pub mod twap;
+#[cfg(feature = "bot")]
+pub mod valuation;
+#[cfg(feature = "bot")]
+pub mod volume;
+#[cfg(feature = "bot")]
+pub mod withdraw;
@@ -0,0 +1,132 @@
+/// Context of log records, and the JSON format of `--log-format json`. Code
+/// running for a market, or handling an order or fill, runs within a scope
+/// setting it, and the JSON format writes the context as fields of the
+/// record instead of it being formatted into the message.
+use crate::zigzag::{FillId, Market, OrderId};
+use flexi_logger::{DeferredNow, Record, TS_DASHES_BLANK_COLONS_DOT_BLANK};
+use serde::Serialize;
+use std::future::Future;
+use std::io::{self, Write};
+
+/// What the records logged in a scope are about.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    pub market: Option<Market>,
+    pub order_id: Option<OrderId>,
+    pub fill_id: Option<FillId>,
+}
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+/// Context of the current scope, empty outside of any.
+pub fn context() -> Context {
+    CONTEXT.try_with(Context::clone).unwrap_or_default()
+}
+
+/// Runs `future` with its records tagged with `market`.
+pub async fn in_market<F: Future>(market: Market, future: F) -> F::Output {
+    let context = Context {
+        market: Some(market),
+        ..context()
+    };
+    CONTEXT.scope(context, future).await
+}
+
+/// Calls `f` with its records tagged with `order_id`.
+pub fn with_order<R>(order_id: OrderId, f: impl FnOnce() -> R) -> R {
+    let context = Context {
+        order_id: Some(order_id),
+        ..context()
+    };
+    CONTEXT.sync_scope(context, f)
+}
+
+/// Calls `f` with its records tagged with `fill_id`.
+pub fn with_fill<R>(fill_id: FillId, f: impl FnOnce() -> R) -> R {
+    let context = Context {
+        fill_id: Some(fill_id),
+        ..context()
+    };
+    CONTEXT.sync_scope(context, f)
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: &'a str,
+    module: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    market: Option<Market>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_id: Option<OrderId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fill_id: Option<FillId>,
+    message: String,
+}
+
+/// Writes a record as a JSON object, on one line, for flexi_logger.
+pub fn json_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    let context = context();
+    let record = JsonRecord {
+        timestamp: now.format(TS_DASHES_BLANK_COLONS_DOT_BLANK),
+        level: record.level().as_str(),
+        module: record.module_path().unwrap_or("<unnamed>"),
+        market: context.market,
+        order_id: context.order_id,
+        fill_id: context.fill_id,
+        message: record.args().to_string(),
+    };
+    serde_json::to_writer(w, &record)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use serde_json::Value;
+
+    fn format(message: &str) -> Value {
+        let mut line = Vec::new();
+        json_format(
+            &mut line,
+            &mut DeferredNow::new(),
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Warn)
+                .module_path(Some("zigzag_bots::portfolio"))
+                .build(),
+        )
+        .expect("json_format");
+        assert!(!line.contains(&b'\n'));
+        serde_json::from_slice(&line).expect("from_slice")
+    }
+
+    #[tokio::test]
+    async fn test_json_format() {
+        let record = format("Connected");
+        let mut keys: Vec<_> = record
+            .as_object()
+            .expect("object")
+            .keys()
+            .cloned()
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["level", "message", "module", "timestamp"]);
+
+        let record = in_market("ETH-USDC".into(), async {
+            with_fill(12, || with_order(34, || format("Ignoring fill \"12\"")))
+        })
+        .await;
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["module"], "zigzag_bots::portfolio");
+        assert_eq!(record["market"], "ETH-USDC");
+        assert_eq!(record["order_id"], 34);
+        assert_eq!(record["fill_id"], 12);
+        assert_eq!(record["message"], "Ignoring fill \"12\"");
+        assert!(record["timestamp"].is_string());
+        assert_eq!(context(), Context::default());
+    }
+}
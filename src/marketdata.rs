@@ -0,0 +1,242 @@
+/// Market statistics kept from `marketsummary` and `lastprice` broadcasts,
+/// and reference prices of external feeds, shared between the tasks that
+/// read market data.
+use crate::feeds::Reference;
+use crate::zigzag::{
+    unix_timestamp, Amount, Decimal, LastpriceArgs, Market, MarketsummaryArgs, Operation,
+    PriceParseError, PriceUpdate, Timestamp,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Latest known statistics of a market. `lastprice` updates do not carry
+/// the 24h range, which is only known once a `marketsummary` was received.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarketSummary {
+    pub price: Decimal,
+    pub high_24: Option<Decimal>,
+    pub low_24: Option<Decimal>,
+    pub price_change: Decimal,
+    pub base_volume: Option<Amount>,
+    pub quote_volume: Option<Amount>,
+    pub updated: Timestamp,
+}
+
+/// Cloning the cache shares it.
+#[derive(Clone, Debug, Default)]
+pub struct SummaryCache {
+    markets: Arc<RwLock<HashMap<Market, MarketSummary>>>,
+    references: Arc<RwLock<HashMap<Market, Reference>>>,
+}
+
+impl SummaryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the cache from a `marketsummary` or `lastprice` operation.
+    /// Returns false for other operations.
+    pub fn apply(&self, op: &Operation, now: Timestamp) -> bool {
+        match op {
+            Operation::Marketsummary(args) => self.apply_summary(args, now),
+            Operation::Lastprice(args) => self.apply_lastprice(args, now),
+            _ => return false,
+        }
+        true
+    }
+
+    fn apply_summary(&self, args: &MarketsummaryArgs, now: Timestamp) {
+        let (price, high_24, low_24, price_change) = match summary_prices(args) {
+            Ok(prices) => prices,
+            Err(e) => {
+                log::warn!("Ignoring market summary of {}: {}", args.market, e);
+                return;
+            }
+        };
+        let summary = MarketSummary {
+            price,
+            high_24: Some(high_24),
+            low_24: Some(low_24),
+            price_change,
+            base_volume: Some(args.base_volume),
+            quote_volume: Some(args.quote_volume),
+            updated: now,
+        };
+        self.markets
+            .write()
+            .unwrap()
+            .insert(args.market.clone(), summary);
+    }
+
+    fn apply_lastprice(&self, args: &LastpriceArgs, now: Timestamp) {
+        let mut markets = self.markets.write().unwrap();
+        for PriceUpdate {
+            market,
+            price,
+            price_change,
+            quote_volume,
+            base_volume,
+        } in &args.updates
+        {
+            let (price, price_change) = match (price.value(), price_change.signed_value()) {
+                (Ok(price), Ok(price_change)) => (price, price_change),
+                (Err(e), _) | (_, Err(e)) => {
+                    log::warn!("Ignoring last price of {}: {}", market, e);
+                    continue;
+                }
+            };
+            match markets.get_mut(market) {
+                Some(summary) => {
+                    summary.price = price;
+                    summary.price_change = price_change;
+                    summary.base_volume = base_volume.or(summary.base_volume);
+                    summary.quote_volume = quote_volume.or(summary.quote_volume);
+                    summary.updated = now;
+                }
+                None => {
+                    markets.insert(
+                        market.clone(),
+                        MarketSummary {
+                            price,
+                            high_24: None,
+                            low_24: None,
+                            price_change,
+                            base_volume: *base_volume,
+                            quote_volume: *quote_volume,
+                            updated: now,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, market: &str) -> Option<MarketSummary> {
+        self.markets.read().unwrap().get(market).cloned()
+    }
+
+    /// Last traded price of `market`, the reference strategies quote around.
+    pub fn mid(&self, market: &str) -> Option<Decimal> {
+        self.markets
+            .read()
+            .unwrap()
+            .get(market)
+            .map(|summary| summary.price)
+    }
+
+    /// Whether `market` has not been updated within `max_age`, or never.
+    pub fn is_stale(&self, market: &str, max_age: Duration) -> bool {
+        self.is_stale_at(market, max_age, unix_timestamp())
+    }
+
+    pub fn is_stale_at(&self, market: &str, max_age: Duration, now: Timestamp) -> bool {
+        match self.markets.read().unwrap().get(market) {
+            Some(summary) => now.saturating_sub(summary.updated) > max_age.as_secs(),
+            None => true,
+        }
+    }
+
+    /// Replaces the external reference price of `market`.
+    pub fn set_reference(&self, market: Market, reference: Reference) {
+        self.references.write().unwrap().insert(market, reference);
+    }
+
+    pub fn reference(&self, market: &str) -> Option<Reference> {
+        self.references.read().unwrap().get(market).cloned()
+    }
+}
+
+/// Price, 24h high and low, and price change of a market summary.
+fn summary_prices(
+    args: &MarketsummaryArgs,
+) -> Result<(Decimal, Decimal, Decimal, Decimal), PriceParseError> {
+    Ok((
+        args.price.value()?,
+        args.high_24.value()?,
+        args.low_24.value()?,
+        args.price_change.signed_value()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn op(json: &str) -> Operation {
+        serde_json::from_str(json).expect("from_str")
+    }
+
+    const ETH_SUMMARY: &str =
+        r#"{"op":"marketsummary","args":["ETH-USDT",3370.93,3450.1,3300.5,12.5,154.2,519000.3]}"#;
+
+    #[test]
+    fn test_interleaved_updates() {
+        let cache = SummaryCache::new();
+        let shared = cache.clone();
+        assert_eq!(cache.mid("ETH-USDT"), None);
+
+        // A last price before any summary has no 24h range.
+        assert!(cache.apply(
+            &op(r#"{"op":"lastprice","args":[[["ETH-USDT",3360,2.5],["WBTC-USDT",30000,100]]]}"#),
+            100
+        ));
+        let eth = shared.get("ETH-USDT").expect("ETH-USDT");
+        assert_eq!(eth.price, dec!(3360));
+        assert_eq!(eth.high_24, None);
+        assert_eq!(eth.base_volume, None);
+        assert_eq!(shared.mid("WBTC-USDT"), Some(dec!(30000)));
+
+        assert!(cache.apply(&op(ETH_SUMMARY), 110));
+        let eth = shared.get("ETH-USDT").expect("ETH-USDT");
+        assert_eq!(eth.price, dec!(3370.93));
+        assert_eq!(eth.high_24, Some(dec!(3450.1)));
+        assert_eq!(eth.low_24, Some(dec!(3300.5)));
+        assert_eq!(eth.quote_volume, Some(dec!(519000.3)));
+
+        // Later last prices keep the range and volumes they do not carry.
+        assert!(cache.apply(
+            &op(r#"{"op":"lastprice","args":[[["ETH-USDT",3380,22.5,520000]]]}"#),
+            120
+        ));
+        let eth = shared.get("ETH-USDT").expect("ETH-USDT");
+        assert_eq!(eth.price, dec!(3380));
+        assert_eq!(eth.price_change, dec!(22.5));
+        assert_eq!(eth.high_24, Some(dec!(3450.1)));
+        assert_eq!(eth.quote_volume, Some(dec!(520000)));
+        assert_eq!(eth.base_volume, Some(dec!(154.2)));
+        assert_eq!(eth.updated, 120);
+
+        assert!(!cache.apply(
+            &op(r#"{"op":"subscribemarket","args":[1000,"ETH-USDT"]}"#),
+            130
+        ));
+    }
+
+    #[test]
+    fn test_staleness() {
+        let cache = SummaryCache::new();
+        let max_age = Duration::from_secs(30);
+        assert!(cache.is_stale_at("ETH-USDT", max_age, 100));
+
+        cache.apply(&op(ETH_SUMMARY), 100);
+        assert!(!cache.is_stale_at("ETH-USDT", max_age, 100));
+        assert!(!cache.is_stale_at("ETH-USDT", max_age, 130));
+        assert!(cache.is_stale_at("ETH-USDT", max_age, 131));
+
+        // A last price refreshes the market it mentions only.
+        cache.apply(
+            &op(r#"{"op":"lastprice","args":[[["WBTC-USDT",30000,100]]]}"#),
+            125,
+        );
+        assert!(cache.is_stale_at("ETH-USDT", max_age, 140));
+        cache.apply(
+            &op(r#"{"op":"lastprice","args":[[["ETH-USDT",3371,12.5]]]}"#),
+            135,
+        );
+        assert!(!cache.is_stale_at("ETH-USDT", max_age, 140));
+        assert!(!cache.is_stale_at("WBTC-USDT", max_age, 140));
+        assert!(cache.is_stale_at("WBTC-USDT", max_age, 156));
+    }
+}
@@ -0,0 +1,81 @@
+/// Market infos as printed by the `markets` subcommand, to check a market
+/// and its fees before quoting it.
+use crate::repl::table;
+use crate::zigzag::{Amount, Asset, MarketInfo, Price};
+
+fn asset(asset: &Asset) -> String {
+    format!("{} ({})", asset.symbol, asset.decimals)
+}
+
+/// Fees the backend sent as they are when they do not parse.
+fn fee(fee: &Price) -> String {
+    match fee.value() {
+        Ok(value) => value.normalize().to_string(),
+        Err(_) => match fee {
+            Price::String(s) => s.clone(),
+            Price::Decimal(d) => d.to_string(),
+        },
+    }
+}
+
+fn size(size: Option<Amount>) -> String {
+    size.map(|s| s.normalize().to_string())
+        .unwrap_or_else(|| "-".into())
+}
+
+/// One row per market, by alias, assets with their decimals.
+pub fn table_of(infos: &[MarketInfo]) -> String {
+    let mut infos: Vec<_> = infos.iter().collect();
+    infos.sort_by(|a, b| a.alias.cmp(&b.alias));
+    let rows: Vec<_> = infos
+        .into_iter()
+        .map(|info| {
+            vec![
+                info.alias.clone(),
+                asset(&info.base_asset),
+                asset(&info.quote_asset),
+                fee(&info.base_fee),
+                fee(&info.quote_fee),
+                info.price_precision_decimal.to_string(),
+                size(info.min_size),
+                size(info.max_size),
+            ]
+        })
+        .collect();
+    table(
+        &[
+            "market",
+            "base",
+            "quote",
+            "base fee",
+            "quote fee",
+            "price decimals",
+            "min size",
+            "max size",
+        ],
+        &rows,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_table_of() {
+        let mut wbtc = fixtures::market_info("WBTC-USDC", 4, 2);
+        wbtc.base_asset.decimals = 8;
+        wbtc.base_fee = Price::String("0.0000150".into());
+        wbtc.min_size = Some(dec!(0.0005));
+        wbtc.max_size = Some(dec!(10.0));
+        let eth = fixtures::market_info("ETH-USDC", 0, 2);
+        assert_eq!(
+            table_of(&[wbtc, eth]),
+            "market     base      quote     base fee  quote fee  price decimals  min size  max size\n\
+             ETH-USDC   ETH (18)  USDC (6)  0.0003    1          2               -         -\n\
+             WBTC-USDC  WBTC (8)  USDC (6)  0.000015  1          2               0.0005    10"
+        );
+    }
+}
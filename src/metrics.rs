@@ -0,0 +1,193 @@
+/// Named counters, gauges and latency histograms shared between the
+/// components of the bot.
+use crate::zigzag::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, in milliseconds. Slower observations
+/// go to one more bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 13] =
+    [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000];
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Observations of each of `LATENCY_BUCKETS_MS`, then of the slower
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0,
+            max_ms: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    /// Upper bound in milliseconds of the bucket holding the `q` quantile,
+    /// at most the slowest observation.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = LATENCY_BUCKETS_MS.get(bucket).copied().unwrap_or(u64::MAX);
+                return Some(bound.min(self.max_ms));
+            }
+        }
+        Some(self.max_ms)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<String, u64>>,
+    gauges: Mutex<BTreeMap<String, Decimal>>,
+    histograms: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn incr(&self, name: &str) {
+        self.add(name, 1);
+    }
+
+    pub fn add(&self, name: &str, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default() += value;
+    }
+
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Copy of every counter, sorted by name.
+    pub fn counters(&self) -> BTreeMap<String, u64> {
+        self.counters.lock().unwrap().clone()
+    }
+
+    pub fn set_gauge(&self, name: &str, value: Decimal) {
+        self.gauges.lock().unwrap().insert(name.to_owned(), value);
+    }
+
+    pub fn remove_gauge(&self, name: &str) {
+        self.gauges.lock().unwrap().remove(name);
+    }
+
+    pub fn gauge(&self, name: &str) -> Option<Decimal> {
+        self.gauges.lock().unwrap().get(name).copied()
+    }
+
+    /// Copy of every gauge, sorted by name.
+    pub fn gauges(&self) -> BTreeMap<String, Decimal> {
+        self.gauges.lock().unwrap().clone()
+    }
+
+    pub fn observe(&self, name: &str, latency: Duration) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .observe(latency);
+    }
+
+    pub fn histogram(&self, name: &str) -> Option<Histogram> {
+        self.histograms.lock().unwrap().get(name).cloned()
+    }
+
+    /// Copy of every histogram, sorted by name.
+    pub fn histograms(&self) -> BTreeMap<String, Histogram> {
+        self.histograms.lock().unwrap().clone()
+    }
+
+    /// One line of the p50 and p99 of every histogram, `None` before any
+    /// observation.
+    pub fn latency_summary(&self) -> Option<String> {
+        let histograms = self.histograms.lock().unwrap();
+        let parts: Vec<_> = histograms
+            .iter()
+            .filter_map(|(name, histogram)| {
+                Some(format!(
+                    "{} p50 {}ms p99 {}ms ({})",
+                    name,
+                    histogram.quantile(0.5)?,
+                    histogram.quantile(0.99)?,
+                    histogram.count
+                ))
+            })
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.latency_summary(), None);
+        for ms in [3, 4, 8, 15, 40, 40, 45, 90, 150, 12_000] {
+            metrics.observe("latency_submitorder3", Duration::from_millis(ms));
+        }
+        let histogram = metrics
+            .histogram("latency_submitorder3")
+            .expect("histogram");
+        assert_eq!(histogram.count, 10);
+        assert_eq!(histogram.sum_ms, 12_395);
+        assert_eq!(histogram.buckets[2], 2);
+        assert_eq!(histogram.buckets[LATENCY_BUCKETS_MS.len()], 1);
+        assert_eq!(histogram.quantile(0.0), Some(5));
+        assert_eq!(histogram.quantile(0.5), Some(50));
+        assert_eq!(histogram.quantile(0.9), Some(200));
+        // The slowest went past the last bucket.
+        assert_eq!(histogram.quantile(0.99), Some(12_000));
+
+        metrics.observe("latency_requestquote", Duration::from_micros(300));
+        assert_eq!(
+            metrics.latency_summary().as_deref(),
+            Some(
+                "latency_requestquote p50 0ms p99 0ms (1), \
+                 latency_submitorder3 p50 50ms p99 12000ms (10)"
+            )
+        );
+    }
+}
@@ -0,0 +1,648 @@
+/// In-process ZigZag backend for tests. `MockServer` speaks enough of the
+/// websocket protocol for the client and the bot to run against it: it
+/// answers logins with empty order and fill snapshots of that user, subscriptions with
+/// the market info and periodic `liquidity2` updates, and `submitorder3`
+/// with a `userorderack`. Tests script errors, fills of our quotes,
+/// disconnects and malformed frames, and inspect everything received.
+use crate::zigzag::{
+    Decimal, ErrorArgs, Fill, FillId, FillsArgs, Liquidity, Liquidity2Args, Market, MarketInfo,
+    MarketinfoArgs, Operation, OperationName, OrderId, OrderStatus, OrdersArgs, RemainingOrError,
+    Side, UserId, UserorderackArgs, ZigzagError,
+};
+use async_tungstenite::tungstenite::Message;
+use futures::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// Interval between the `liquidity2` updates of subscribed markets.
+const LIQUIDITY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// User id of the tests' logins. The orders and fills of a connection are
+/// of the user it logged in as, and of this one before it does.
+const USER_ID: &str = "23";
+
+/// An operation received by the server, on its `connection`-th connection
+/// counting from 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Received {
+    pub connection: usize,
+    pub op: Value,
+}
+
+enum Command {
+    Send(Message),
+    Disconnect,
+}
+
+#[derive(Clone)]
+struct MockMarket {
+    info: MarketInfo,
+    /// Liquidity advertised in the periodic updates
+    liquidity: Vec<Liquidity>,
+}
+
+#[derive(Default)]
+struct Shared {
+    markets: HashMap<Market, MockMarket>,
+    /// Errors answered instead of handling the next operation of a name
+    rejections: Vec<(OperationName, String)>,
+    /// Whether advertised liquidity is filled at once
+    fill_quotes: bool,
+    last_order_id: OrderId,
+    last_fill_id: FillId,
+    /// Commands of each connection accepted, the latest last
+    commands: Vec<mpsc::UnboundedSender<Command>>,
+    received: Vec<Received>,
+}
+
+/// Stops accepting connections and drops the open ones when dropped.
+pub struct MockServer {
+    url: String,
+    shared: Arc<Mutex<Shared>>,
+    /// Number of operations received so far
+    received: watch::Receiver<usize>,
+    accept: JoinHandle<()>,
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.accept.abort();
+        for commands in &self.shared().commands {
+            let _ = commands.send(Command::Disconnect);
+        }
+    }
+}
+
+impl MockServer {
+    /// Listens on a free local port.
+    pub async fn start() -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let (received_tx, received) = watch::channel(0);
+        let accept = tokio::spawn(accept(listener, shared.clone(), received_tx));
+        Ok(Self {
+            url,
+            shared,
+            received,
+            accept,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Serves `info` on subscriptions, with one level of size 1 on each side
+    /// 10 bps around `mid`.
+    pub fn with_market(self, info: MarketInfo, mid: Decimal) -> Self {
+        let distance = mid / Decimal::from(1000);
+        let level = |side, price: Decimal| Liquidity {
+            side,
+            price: price.into(),
+            base_quantity: Decimal::ONE,
+            expires: None,
+        };
+        let liquidity = vec![
+            level(Side::Buy, mid - distance),
+            level(Side::Sell, mid + distance),
+        ];
+        self.shared()
+            .markets
+            .insert(info.alias.clone(), MockMarket { info, liquidity });
+        self
+    }
+
+    fn shared(&self) -> std::sync::MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap()
+    }
+
+    fn command(&self, command: Command) -> anyhow::Result<()> {
+        self.shared()
+            .commands
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("No client connected"))?
+            .send(command)
+            .map_err(|_| anyhow::anyhow!("Client disconnected"))
+    }
+
+    /// Sends an operation to the latest client connected.
+    pub fn inject(&self, op: &Operation) -> anyhow::Result<()> {
+        self.inject_text(serde_json::to_string(op)?)
+    }
+
+    /// Sends a raw text frame, e.g. a malformed one.
+    pub fn inject_text(&self, text: impl Into<String>) -> anyhow::Result<()> {
+        self.command(Command::Send(Message::Text(text.into())))
+    }
+
+    /// Sends an `error` for `operation` to the connected client.
+    pub fn inject_error(&self, operation: OperationName, error: &str) -> anyhow::Result<()> {
+        self.inject(&Operation::Error(ErrorArgs {
+            operation,
+            error: ZigzagError::classify(error.into()),
+        }))
+    }
+
+    /// Answers the next `operation` received with `error` instead of
+    /// handling it.
+    pub fn reject_next(&self, operation: OperationName, error: &str) {
+        self.shared().rejections.push((operation, error.into()));
+    }
+
+    /// Fills the best level of every `indicateliq2` received from now on,
+    /// with a `fillreceipt`.
+    pub fn fill_quotes(&self, fill: bool) {
+        self.shared().fill_quotes = fill;
+    }
+
+    /// Drops the latest client connected without a close frame.
+    pub fn disconnect(&self) -> anyhow::Result<()> {
+        self.command(Command::Disconnect)
+    }
+
+    /// Number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.shared().commands.len()
+    }
+
+    /// Everything received so far, in order.
+    pub fn received(&self) -> Vec<Received> {
+        self.shared().received.clone()
+    }
+
+    /// Waits until an operation named `op` arrives after the first `skip`
+    /// ones received, returning it with its index.
+    pub async fn wait_for(
+        &self,
+        op: &str,
+        skip: usize,
+        timeout: Duration,
+    ) -> anyhow::Result<(usize, Received)> {
+        let mut received = self.received.clone();
+        let wait = async {
+            loop {
+                let found = self
+                    .shared()
+                    .received
+                    .iter()
+                    .enumerate()
+                    .skip(skip)
+                    .find(|(_, r)| r.op["op"] == op)
+                    .map(|(index, r)| (index, r.clone()));
+                if let Some(found) = found {
+                    return Ok(found);
+                }
+                received.changed().await?;
+            }
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| anyhow::anyhow!("No {} received within {:?}", op, timeout))?
+    }
+}
+
+async fn accept(listener: TcpListener, shared: Arc<Mutex<Shared>>, received: watch::Sender<usize>) {
+    let received = Arc::new(received);
+    loop {
+        let tcp = match listener.accept().await {
+            Ok((tcp, _)) => tcp,
+            Err(e) => {
+                log::warn!("Mock server failed to accept: {}", e);
+                continue;
+            }
+        };
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let connection = {
+            let mut shared = shared.lock().unwrap();
+            shared.commands.push(commands_tx);
+            shared.commands.len() - 1
+        };
+        let (shared, received) = (shared.clone(), received.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve(tcp, connection, commands, &shared, &received).await {
+                log::debug!("Mock server connection {} ended: {}", connection, e);
+            }
+        });
+    }
+}
+
+/// Serves one client until it leaves or is disconnected.
+async fn serve(
+    tcp: TcpStream,
+    connection: usize,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    shared: &Mutex<Shared>,
+    received: &watch::Sender<usize>,
+) -> anyhow::Result<()> {
+    let mut ws = async_tungstenite::tokio::accept_async(tcp).await?;
+    let mut subscriptions: Vec<Market> = Vec::new();
+    let mut user_id: UserId = USER_ID.into();
+    let mut ticker = tokio::time::interval(LIQUIDITY_INTERVAL);
+    loop {
+        let replies = tokio::select! {
+            message = ws.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                let op: Value = serde_json::from_str(&text)?;
+                let mut shared = shared.lock().unwrap();
+                shared.received.push(Received {
+                    connection,
+                    op: op.clone(),
+                });
+                let _ = received.send_replace(shared.received.len());
+                shared.answer(&op, &mut subscriptions, &mut user_id)
+            }
+            command = commands.recv() => match command {
+                Some(Command::Send(message)) => vec![message],
+                Some(Command::Disconnect) | None => return Ok(()),
+            },
+            _ = ticker.tick() => {
+                let shared = shared.lock().unwrap();
+                subscriptions
+                    .iter()
+                    .filter_map(|market| shared.markets.get(market))
+                    .map(|market| {
+                        to_message(&Operation::Liquidity2(Liquidity2Args {
+                            chain_id: market.info.zigzag_chain_id,
+                            market: market.info.alias.clone(),
+                            liquidity: market.liquidity.clone(),
+                        }))
+                    })
+                    .collect()
+            }
+        };
+        for reply in replies {
+            ws.send(reply).await?;
+        }
+    }
+}
+
+fn to_message(op: &Operation) -> Message {
+    Message::Text(serde_json::to_string(op).expect("operations serialize"))
+}
+
+impl Shared {
+    /// Replies to an operation of the client.
+    fn answer(
+        &mut self,
+        op: &Value,
+        subscriptions: &mut Vec<Market>,
+        user_id: &mut UserId,
+    ) -> Vec<Message> {
+        let name = op["op"].as_str().unwrap_or_default();
+        let args = &op["args"];
+        let rejected = self
+            .rejections
+            .iter()
+            .position(|(operation, _)| operation.as_str() == name);
+        if let Some(index) = rejected {
+            let (operation, error) = self.rejections.remove(index);
+            return vec![to_message(&Operation::Error(ErrorArgs {
+                operation,
+                error: ZigzagError::classify(error),
+            }))];
+        }
+        let market = args[1].as_str().unwrap_or_default();
+        let ops = match name {
+            "login" => {
+                if let Some(login) = args[1].as_str() {
+                    *user_id = login.to_owned();
+                }
+                vec![
+                    Operation::Orders(OrdersArgs { orders: Vec::new() }),
+                    Operation::Fills(FillsArgs { fills: Vec::new() }),
+                ]
+            }
+            "subscribemarket" => match self.markets.get(market) {
+                Some(mock) => {
+                    if !subscriptions.iter().any(|m| m == market) {
+                        subscriptions.push(market.to_owned());
+                    }
+                    vec![Operation::Marketinfo(MarketinfoArgs {
+                        market_info: mock.info.clone(),
+                    })]
+                }
+                None => vec![Operation::Error(ErrorArgs {
+                    operation: OperationName::Subscribemarket,
+                    error: ZigzagError::classify(format!("Market not found: {}", market)),
+                })],
+            },
+            "unsubscribemarket" => {
+                subscriptions.retain(|m| m != market);
+                Vec::new()
+            }
+            "submitorder3" => self
+                .acknowledge(market, &args[2], user_id)
+                .into_iter()
+                .collect(),
+            "indicateliq2" if self.fill_quotes => {
+                self.fill(market, &args[2], user_id).into_iter().collect()
+            }
+            _ => Vec::new(),
+        };
+        ops.iter().map(to_message).collect()
+    }
+
+    /// `userorderack` of a submitted zksync order.
+    fn acknowledge(&mut self, market: &str, order: &Value, user_id: &str) -> Option<Operation> {
+        let info = &self.markets.get(market)?.info;
+        let (side, price, base_quantity) = order_terms(info, order)?;
+        self.last_order_id += 1;
+        Some(Operation::Userorderack(UserorderackArgs {
+            chain_id: info.zigzag_chain_id,
+            id: self.last_order_id,
+            market: market.to_owned(),
+            side,
+            price: price.into(),
+            base_quantity,
+            quote_quantity: base_quantity * price,
+            expires: order["validUntil"].as_u64().unwrap_or_default(),
+            user_id: user_id.into(),
+            order_status: OrderStatus::Open,
+            tx_hash: None,
+            remaining: Some(RemainingOrError::Remaining(base_quantity)),
+        }))
+    }
+
+    /// `fillreceipt` of a taker hitting the first level of `liquidity`.
+    fn fill(&mut self, market: &str, liquidity: &Value, user_id: &str) -> Option<Operation> {
+        let chain_id = self.markets.get(market)?.info.zigzag_chain_id;
+        let levels: Vec<Liquidity> = serde_json::from_value(liquidity.clone()).ok()?;
+        let level = levels.into_iter().next()?;
+        self.last_fill_id += 1;
+        Some(Operation::Fillreceipt(Fill {
+            chain_id,
+            id: self.last_fill_id,
+            market: market.to_owned(),
+            side: level.side,
+            price: level.price,
+            base_quantity: level.base_quantity,
+            fill_status: OrderStatus::Filled,
+            tx_hash: None,
+            taker_user_id: "taker".into(),
+            maker_user_id: user_id.into(),
+            fee_amount: None,
+            fee_token: None,
+            timestamp: None,
+        }))
+    }
+}
+
+/// Side, price and base quantity of a zksync order in its JSON form: the
+/// raw `amount` of `tokenSell`, and the `ratio` of sold to bought raw
+/// units.
+fn order_terms(info: &MarketInfo, order: &Value) -> Option<(Side, Decimal, Decimal)> {
+    let raw = |value: &Value, decimals: u32| -> Option<Decimal> {
+        let units = match value {
+            Value::String(units) => units.parse::<i128>().ok()?,
+            value => value.as_u64()?.into(),
+        };
+        Decimal::try_from_i128_with_scale(units, decimals).ok()
+    };
+    let (base, quote) = (info.base_asset.decimals, info.quote_asset.decimals);
+    let ratio = order["ratio"].as_array()?;
+    if order["tokenSell"].as_u64()? == u64::from(info.base_asset.id) {
+        let price = raw(&ratio[1], quote)? / raw(&ratio[0], base)?;
+        Some((Side::Sell, price, raw(&order["amount"], base)?))
+    } else {
+        let price = raw(&ratio[0], quote)? / raw(&ratio[1], base)?;
+        Some((Side::Buy, price, raw(&order["amount"], quote)? / price))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ZigzagClient;
+    use crate::connection::{Backoff, Connection, Heartbeat};
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn connect(server: &MockServer) -> ZigzagClient<Connection> {
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(10));
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(60),
+        };
+        let connection = Connection::connect(server.url(), backoff, heartbeat)
+            .await
+            .expect("connect");
+        ZigzagClient::new(connection)
+    }
+
+    async fn server() -> MockServer {
+        MockServer::start()
+            .await
+            .expect("start")
+            .with_market(fixtures::market_info("ETH-USDC", 0, 2), dec!(2000))
+    }
+
+    /// Next operation of the client accepted by `filter`, skipping the rest.
+    async fn recv_until<F>(client: &mut ZigzagClient<Connection>, filter: F) -> Operation
+    where
+        F: Fn(&Operation) -> bool,
+    {
+        let recv = async {
+            loop {
+                let op = client.recv().await.expect("recv");
+                if filter(&op) {
+                    return op;
+                }
+            }
+        };
+        tokio::time::timeout(TIMEOUT, recv).await.expect("timeout")
+    }
+
+    fn indicateliq2() -> Operation {
+        serde_json::from_value(json!(
+            {"op": "indicateliq2", "args": [1000, "ETH-USDC", [["b", 1998.0, 0.5], ["s", 2002.0, 0.5]]]}
+        ))
+        .expect("from_value")
+    }
+
+    #[tokio::test]
+    async fn test_login_subscribe_quote_fill() {
+        let server = server().await;
+        let mut client = connect(&server).await;
+        client.login(1000, USER_ID.into()).await.expect("login");
+        let op = recv_until(&mut client, |_| true).await;
+        assert!(matches!(op, Operation::Orders(_)));
+
+        client
+            .subscribe_market("ETH-USDC".into())
+            .await
+            .expect("subscribe_market");
+        let op = recv_until(&mut client, |op| matches!(op, Operation::Marketinfo(_))).await;
+        let info = match op {
+            Operation::Marketinfo(args) => args.market_info,
+            op => panic!("unexpected {:?}", op),
+        };
+        assert_eq!(info.alias, "ETH-USDC");
+        let op = recv_until(&mut client, |op| matches!(op, Operation::Liquidity2(_))).await;
+        match op {
+            Operation::Liquidity2(args) => {
+                let prices: Vec<_> = args.liquidity.iter().map(|l| l.price.value()).collect();
+                assert_eq!(prices, [Ok(dec!(1998)), Ok(dec!(2002))]);
+            }
+            op => panic!("unexpected {:?}", op),
+        }
+
+        // A taker hits our bid.
+        server.fill_quotes(true);
+        client.send(indicateliq2()).await.expect("send");
+        let op = recv_until(&mut client, |op| matches!(op, Operation::Fillreceipt(_))).await;
+        match op {
+            Operation::Fillreceipt(fill) => {
+                assert_eq!(fill.side, Side::Buy);
+                assert_eq!(fill.price.value(), Ok(dec!(1998)));
+                assert_eq!(fill.base_quantity, dec!(0.5));
+                assert_eq!(fill.maker_user_id, USER_ID);
+            }
+            op => panic!("unexpected {:?}", op),
+        }
+        let ops: Vec<_> = server
+            .received()
+            .into_iter()
+            .map(|r| r.op["op"].clone())
+            .collect();
+        assert_eq!(ops, ["login", "subscribemarket", "indicateliq2"]);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_replays_session() {
+        let server = server().await;
+        let mut client = connect(&server).await;
+        client.login(1000, USER_ID.into()).await.expect("login");
+        client
+            .subscribe_market("ETH-USDC".into())
+            .await
+            .expect("subscribe_market");
+        client.send(indicateliq2()).await.expect("send");
+        recv_until(&mut client, |op| matches!(op, Operation::Marketinfo(_))).await;
+
+        // The client reconnects while reading, and the replayed login is
+        // answered with a fresh snapshot.
+        server.disconnect().expect("disconnect");
+        recv_until(&mut client, |op| matches!(op, Operation::Orders(_))).await;
+        let (index, _) = server
+            .wait_for("indicateliq2", 3, TIMEOUT)
+            .await
+            .expect("replayed");
+        assert_eq!(index, 5);
+        let received = server.received();
+        let first: Vec<_> = received[..3].iter().map(|r| r.op.clone()).collect();
+        let replayed: Vec<_> = received[3..].iter().map(|r| r.op.clone()).collect();
+        assert_eq!(first, replayed);
+        assert!(received[3..].iter().all(|r| r.connection == 1));
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_errors_and_frames() {
+        let server = server().await;
+        let mut client = connect(&server).await;
+        client.login(1000, USER_ID.into()).await.expect("login");
+        server
+            .wait_for("login", 0, TIMEOUT)
+            .await
+            .expect("wait_for");
+
+        // Malformed frames are skipped by the client.
+        server.inject_text("not json").expect("inject_text");
+        server
+            .inject_error(OperationName::Cancelorder, "Order not found")
+            .expect("inject_error");
+        let op = recv_until(&mut client, |op| matches!(op, Operation::Error(_))).await;
+        match op {
+            Operation::Error(e) => assert!(matches!(e.error, ZigzagError::OrderNotFound(_))),
+            op => panic!("unexpected {:?}", op),
+        }
+
+        server.reject_next(OperationName::Subscribemarket, "Rate limit exceeded");
+        client
+            .subscribe_market("ETH-USDC".into())
+            .await
+            .expect("subscribe_market");
+        let op = recv_until(&mut client, |op| matches!(op, Operation::Error(_))).await;
+        match op {
+            Operation::Error(e) => {
+                assert_eq!(e.operation, OperationName::Subscribemarket);
+                assert!(e.error.is_retryable());
+            }
+            op => panic!("unexpected {:?}", op),
+        }
+        client
+            .subscribe_market("WBTC-USDC".into())
+            .await
+            .expect("subscribe_market");
+        let op = recv_until(&mut client, |op| matches!(op, Operation::Error(_))).await;
+        assert!(
+            matches!(op, Operation::Error(e) if e.error == ZigzagError::MarketNotFound("Market not found: WBTC-USDC".into()))
+        );
+    }
+
+    #[test]
+    fn test_acknowledge_order() {
+        let mut shared = Shared::default();
+        let info = fixtures::market_info("ETH-USDC", 0, 2);
+        shared.markets.insert(
+            info.alias.clone(),
+            MockMarket {
+                info,
+                liquidity: Vec::new(),
+            },
+        );
+        // Sells half an ETH at 3300 USDC.
+        let sell = json!({
+            "tokenSell": 0,
+            "tokenBuy": 2,
+            "amount": "500000000000000000",
+            "ratio": ["1000000000000000000", "3300000000"],
+            "validUntil": 4294967295u64,
+        });
+        let op = json!({"op": "submitorder3", "args": [1000, "ETH-USDC", sell]});
+        let replies = shared.answer(&op, &mut Vec::new(), &mut USER_ID.into());
+        let ack: Value = serde_json::from_str(replies[0].to_text().expect("text")).expect("json");
+        assert_eq!(
+            ack,
+            json!({"op": "userorderack", "args": [
+                1000, 1, "ETH-USDC", "s", 3300.0, 0.5, 1650.0, 4294967295u64, USER_ID, "o", null, 0.5
+            ]})
+        );
+
+        // Buys the same with 1650 USDC.
+        let buy = json!({
+            "tokenSell": 2,
+            "tokenBuy": 0,
+            "amount": "1650000000",
+            "ratio": ["3300000000", "1000000000000000000"],
+            "validUntil": 4294967295u64,
+        });
+        let op = json!({"op": "submitorder3", "args": [1000, "ETH-USDC", buy]});
+        match serde_json::from_str(
+            shared.answer(&op, &mut Vec::new(), &mut "24".into())[0]
+                .to_text()
+                .expect("text"),
+        ) {
+            Ok(Operation::Userorderack(ack)) => {
+                assert_eq!((ack.id, ack.side), (2, Side::Buy));
+                assert_eq!(ack.user_id, "24");
+                assert_eq!(ack.base_quantity, dec!(0.5));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
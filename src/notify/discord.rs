@@ -0,0 +1,231 @@
+/// Discord webhook notifier, posting one embed per event: green for fills,
+/// red for errors, halts and outages.
+use super::{Event, Notifier};
+use crate::display::{format_amount, format_price};
+use crate::zigzag::Side;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Discord takes at most this many embeds per message.
+const MAX_EMBEDS: usize = 10;
+
+const GREEN: u32 = 0x2ecc71;
+const RED: u32 = 0xe74c3c;
+const BLUE: u32 = 0x3498db;
+
+/// `[notify.discord]` table of the config file.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub webhook_url: String,
+    /// Overrides the name of the webhook
+    pub username: Option<String>,
+}
+
+// Keeps the webhook token out of logs.
+impl std::fmt::Debug for DiscordConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DiscordConfig")
+            .field("webhook_url", &"<redacted>")
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+pub struct Discord {
+    client: reqwest::Client,
+    config: DiscordConfig,
+}
+
+impl Discord {
+    pub fn new(config: DiscordConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    pub fn payload(&self, events: &[Event]) -> Value {
+        let mut embeds: Vec<_> = events.iter().take(MAX_EMBEDS).map(embed).collect();
+        if events.len() > MAX_EMBEDS {
+            embeds.truncate(MAX_EMBEDS - 1);
+            embeds.push(json!({
+                "description": format!("… and {} more", events.len() - embeds.len()),
+                "color": BLUE,
+            }));
+        }
+        let mut payload = json!({ "embeds": embeds });
+        if let Some(username) = &self.config.username {
+            payload["username"] = json!(username);
+        }
+        payload
+    }
+}
+
+fn embed(event: &Event) -> Value {
+    let color = match event {
+        Event::Fill { .. } => GREEN,
+        _ if event.is_alert() => RED,
+        _ => BLUE,
+    };
+    match event {
+        Event::Fill {
+            market,
+            side,
+            price,
+            price_decimals,
+            base_quantity,
+        } => json!({
+            "title": event.title(),
+            "color": color,
+            "fields": [
+                { "name": "Market", "value": market, "inline": true },
+                {
+                    "name": "Side",
+                    "value": match side {
+                        Side::Buy => "Buy",
+                        Side::Sell => "Sell",
+                    },
+                    "inline": true,
+                },
+                {
+                    "name": "Price",
+                    "value": format_price(*price, *price_decimals),
+                    "inline": true,
+                },
+                {
+                    "name": "Size",
+                    "value": format_amount(*base_quantity),
+                    "inline": true,
+                },
+            ],
+        }),
+        _ => json!({
+            "title": event.title(),
+            "description": event.to_string(),
+            "color": color,
+        }),
+    }
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()> {
+        // Errors carry the URL, and with it the webhook token.
+        self.client
+            .post(&self.config.webhook_url)
+            .json(&self.payload(events))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow::anyhow!("{}", e.without_url()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+
+    fn discord(username: Option<&str>) -> Discord {
+        Discord::new(DiscordConfig {
+            webhook_url: "https://discord.com/api/webhooks/1/secret".into(),
+            username: username.map(str::to_owned),
+        })
+    }
+
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).expect("from_str")
+    }
+
+    #[test]
+    fn test_fill_payload() {
+        let fill = Event::Fill {
+            market: "ETH-USDC".into(),
+            side: Side::Buy,
+            price: dec!(1600.4567),
+            price_decimals: Some(2),
+            base_quantity: dec!(0.2500),
+        };
+        assert_eq!(
+            discord(Some("zigzag")).payload(&[fill]),
+            fixture(
+                r#"{
+                    "username": "zigzag",
+                    "embeds": [{
+                        "title": "Fill",
+                        "color": 3066993,
+                        "fields": [
+                            {"name": "Market", "value": "ETH-USDC", "inline": true},
+                            {"name": "Side", "value": "Buy", "inline": true},
+                            {"name": "Price", "value": "1,600.46", "inline": true},
+                            {"name": "Size", "value": "0.25", "inline": true}
+                        ]
+                    }]
+                }"#
+            )
+        );
+        assert!(!format!("{:?}", discord(None).config).contains("secret"));
+    }
+
+    #[test]
+    fn test_alert_payloads() {
+        let events = [
+            Event::Error {
+                operation: "submitorder3".into(),
+                error: "Order is too small".into(),
+            },
+            Event::Halted {
+                reason: "ETH-USDC moved 6.38% within 60s, above 5%".into(),
+            },
+            Event::Disconnected {
+                since: Duration::from_secs(60),
+            },
+            Event::Reconnected,
+        ];
+        assert_eq!(
+            discord(None).payload(&events),
+            fixture(
+                r#"{
+                    "embeds": [
+                        {
+                            "title": "Error",
+                            "description": "ZigZag error on submitorder3: Order is too small",
+                            "color": 15158332
+                        },
+                        {
+                            "title": "Halted",
+                            "description": "Halted by the kill switch: ETH-USDC moved 6.38% within 60s, above 5%. Restart to resume",
+                            "color": 15158332
+                        },
+                        {
+                            "title": "Disconnected",
+                            "description": "ZigZag connection down for 60s",
+                            "color": 15158332
+                        },
+                        {
+                            "title": "Reconnected",
+                            "description": "ZigZag connection restored",
+                            "color": 3447003
+                        }
+                    ]
+                }"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_embed_limit() {
+        let payload = discord(None).payload(&vec![Event::Test; 12]);
+        let embeds = payload["embeds"].as_array().expect("embeds");
+        assert_eq!(embeds.len(), MAX_EMBEDS);
+        assert_eq!(embeds[9]["description"], "… and 3 more");
+    }
+}
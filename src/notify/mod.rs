@@ -0,0 +1,476 @@
+/// Notifications about fills, errors, halts and outages, delivered by
+/// background tasks so that a slow or failing chat service never holds up
+/// trading. Events are batched into at most one message per interval.
+pub mod discord;
+pub mod telegram;
+
+use crate::connection::Backoff;
+use crate::display::{format_amount, format_price};
+use crate::zigzag::{Amount, Decimal, FillId, Market, OrderStatus, Side, Token};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Events beyond this many are only counted in a message.
+const MAX_EVENTS_LISTED: usize = 20;
+
+/// Attempts at delivering a message before dropping it.
+const MAX_ATTEMPTS: usize = 3;
+
+/// `[notify]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Shortest time between two messages, events in between are batched
+    pub min_interval_secs: u64,
+    /// Report the ZigZag connection when it has been down for this long
+    pub disconnected_secs: u64,
+    pub telegram: Option<telegram::TelegramConfig>,
+    pub discord: Option<discord::DiscordConfig>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            min_interval_secs: 30,
+            disconnected_secs: 60,
+            telegram: None,
+            discord: None,
+        }
+    }
+}
+
+impl NotifyConfig {
+    pub fn is_empty(&self) -> bool {
+        self.notifiers().is_empty()
+    }
+
+    /// Every configured notifier, they all get every event.
+    pub fn notifiers(&self) -> Vec<Box<dyn Notifier>> {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        if let Some(config) = &self.telegram {
+            notifiers.push(Box::new(telegram::Telegram::new(config.clone())));
+        }
+        if let Some(config) = &self.discord {
+            notifiers.push(Box::new(discord::Discord::new(config.clone())));
+        }
+        notifiers
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        Duration::from_secs(self.min_interval_secs)
+    }
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the service, for logs.
+    fn name(&self) -> &'static str;
+
+    /// Delivers a batch of events as one message.
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Fill {
+        market: Market,
+        side: Side,
+        price: Decimal,
+        /// Price precision of the market, if known
+        price_decimals: Option<u32>,
+        base_quantity: Amount,
+    },
+    Error {
+        operation: String,
+        error: String,
+    },
+    Halted {
+        reason: String,
+    },
+    Disconnected {
+        since: Duration,
+    },
+    Reconnected,
+    /// A balance dropped below what the quotes need
+    LowBalance {
+        token: Token,
+        balance: Amount,
+        needed: Amount,
+    },
+    /// A stop-loss or take-profit flattens a position
+    Stop {
+        trigger: String,
+    },
+    /// A hedge order failed, leaving fills unhedged
+    HedgeFailed {
+        market: Market,
+        /// Base quantity, positive when long
+        unhedged: Amount,
+        error: String,
+    },
+    /// Fills closer together than the minimum interval halt a market
+    FillBurst {
+        market: Market,
+        interval_secs: u64,
+        halt_secs: u64,
+    },
+    /// A fill of ours still not settled long after it matched
+    UnsettledFill {
+        market: Market,
+        fill_id: FillId,
+        status: OrderStatus,
+        age_secs: u64,
+    },
+    /// The settlement of a fill of ours failed, its trade is taken back
+    SettlementRejected {
+        market: Market,
+        fill_id: FillId,
+    },
+    /// Sent by `--notify-test`
+    Test,
+}
+
+impl Event {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Event::Fill { .. } => "Fill",
+            Event::Error { .. } => "Error",
+            Event::Halted { .. } => "Halted",
+            Event::Disconnected { .. } => "Disconnected",
+            Event::Reconnected => "Reconnected",
+            Event::LowBalance { .. } => "Low balance",
+            Event::Stop { .. } => "Stop",
+            Event::HedgeFailed { .. } => "Hedge failed",
+            Event::FillBurst { .. } => "Fill burst",
+            Event::UnsettledFill { .. } => "Unsettled fill",
+            Event::SettlementRejected { .. } => "Settlement rejected",
+            Event::Test => "Test",
+        }
+    }
+
+    /// Whether the event needs attention.
+    pub fn is_alert(&self) -> bool {
+        matches!(
+            self,
+            Event::Error { .. }
+                | Event::Halted { .. }
+                | Event::Disconnected { .. }
+                | Event::LowBalance { .. }
+                | Event::Stop { .. }
+                | Event::HedgeFailed { .. }
+                | Event::FillBurst { .. }
+                | Event::UnsettledFill { .. }
+                | Event::SettlementRejected { .. }
+        )
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Fill {
+                market,
+                side,
+                price,
+                price_decimals,
+                base_quantity,
+            } => {
+                let side = match side {
+                    Side::Buy => "Bought",
+                    Side::Sell => "Sold",
+                };
+                write!(
+                    f,
+                    "{} {} {} @ {}",
+                    side,
+                    format_amount(*base_quantity),
+                    market,
+                    format_price(*price, *price_decimals)
+                )
+            }
+            Event::Error { operation, error } => {
+                write!(f, "ZigZag error on {}: {}", operation, error)
+            }
+            Event::Halted { reason } => {
+                write!(
+                    f,
+                    "Halted by the kill switch: {}. Restart to resume",
+                    reason
+                )
+            }
+            Event::Disconnected { since } => {
+                write!(f, "ZigZag connection down for {}s", since.as_secs())
+            }
+            Event::Reconnected => write!(f, "ZigZag connection restored"),
+            Event::LowBalance {
+                token,
+                balance,
+                needed,
+            } => write!(
+                f,
+                "Low {} balance: {} left, quotes need {}",
+                token,
+                format_amount(*balance),
+                format_amount(*needed)
+            ),
+            Event::Stop { trigger } => write!(f, "Triggered {}, flattening", trigger),
+            Event::HedgeFailed {
+                market,
+                unhedged,
+                error,
+            } => write!(
+                f,
+                "Hedging {} failed: {}. Carrying {} unhedged",
+                market,
+                error,
+                format_amount(*unhedged)
+            ),
+            Event::FillBurst {
+                market,
+                interval_secs,
+                halt_secs,
+            } => write!(
+                f,
+                "Filled on {} {}s after the previous fill, halting it for {}s",
+                market, interval_secs, halt_secs
+            ),
+            Event::UnsettledFill {
+                market,
+                fill_id,
+                status,
+                age_secs,
+            } => write!(
+                f,
+                "Fill {} on {} still {} after {}s",
+                fill_id, market, status, age_secs
+            ),
+            Event::SettlementRejected { market, fill_id } => write!(
+                f,
+                "Settlement of fill {} on {} was rejected, its trade is taken back",
+                fill_id, market
+            ),
+            Event::Test => write!(f, "Hello from zigzag-bots, notifications work"),
+        }
+    }
+}
+
+/// Text of one message reporting `events`.
+pub fn summary(events: &[Event]) -> String {
+    if let [event] = events {
+        return event.to_string();
+    }
+    let mut text = format!("{} events:", events.len());
+    for event in events.iter().take(MAX_EVENTS_LISTED) {
+        text.push_str("\n- ");
+        text.push_str(&event.to_string());
+    }
+    if events.len() > MAX_EVENTS_LISTED {
+        text.push_str(&format!(
+            "\n… and {} more",
+            events.len() - MAX_EVENTS_LISTED
+        ));
+    }
+    text
+}
+
+/// Cloneable handle queueing events for the notifier tasks. Does nothing
+/// when no notifier is configured. Clones share the notifiers.
+#[derive(Clone, Default)]
+pub struct Notifications {
+    senders: Arc<Mutex<Vec<mpsc::UnboundedSender<Event>>>>,
+}
+
+impl Notifications {
+    /// Starts a task per configured notifier. The tasks stop once every
+    /// handle is dropped.
+    pub fn spawn(config: &NotifyConfig) -> (Self, Vec<JoinHandle<()>>) {
+        let notifications = Self::default();
+        let tasks = notifications.reconfigure(config);
+        (notifications, tasks)
+    }
+
+    /// Replaces the notifiers with those of `config`, returning their
+    /// tasks. The previous tasks stop after sending what they hold.
+    pub fn reconfigure(&self, config: &NotifyConfig) -> Vec<JoinHandle<()>> {
+        let mut senders = Vec::new();
+        let mut tasks = Vec::new();
+        for notifier in config.notifiers() {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            senders.push(sender);
+            tasks.push(tokio::spawn(run(notifier, receiver, config.min_interval())));
+        }
+        *self.senders.lock().unwrap() = senders;
+        tasks
+    }
+
+    #[cfg(test)]
+    pub fn from_senders(senders: Vec<mpsc::UnboundedSender<Event>>) -> Self {
+        Self {
+            senders: Arc::new(Mutex::new(senders)),
+        }
+    }
+
+    pub fn notify(&self, event: Event) {
+        for sender in self.senders.lock().unwrap().iter() {
+            // A stopped notifier already logged why.
+            let _ = sender.send(event.clone());
+        }
+    }
+}
+
+/// Sends the events as they come, batched into at most one message per
+/// `min_interval`, until every sender is dropped. Failed messages are
+/// retried a few times, then dropped.
+pub async fn run(
+    notifier: Box<dyn Notifier>,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    min_interval: Duration,
+) {
+    let mut next_message = Instant::now();
+    while let Some(event) = events.recv().await {
+        // Events coming in the meantime join the batch.
+        tokio::time::sleep_until(next_message).await;
+        let mut batch = vec![event];
+        while let Ok(event) = events.try_recv() {
+            batch.push(event);
+        }
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(30));
+        for attempt in 1..=MAX_ATTEMPTS {
+            match notifier.send(&batch).await {
+                Ok(()) => break,
+                Err(e) if attempt == MAX_ATTEMPTS => log::warn!(
+                    "Sending to {} failed: {}, dropping {} events",
+                    notifier.name(),
+                    e,
+                    batch.len()
+                ),
+                Err(e) => {
+                    log::warn!("Sending to {} failed: {}, retrying", notifier.name(), e);
+                    tokio::time::sleep(backoff.next_delay()).await;
+                }
+            }
+        }
+        next_message = Instant::now() + min_interval;
+    }
+}
+
+/// Reports the connection once it has been down for `threshold`, and again
+/// when it is back. Stops with the connection.
+pub async fn watch_connection(
+    mut status: watch::Receiver<bool>,
+    threshold: Duration,
+    notifications: Notifications,
+) {
+    loop {
+        // Wait for the connection to drop.
+        while *status.borrow() {
+            if status.changed().await.is_err() {
+                return;
+            }
+        }
+        let reconnected = async {
+            while !*status.borrow() {
+                if status.changed().await.is_err() {
+                    return false;
+                }
+            }
+            true
+        };
+        tokio::pin!(reconnected);
+        tokio::select! {
+            up = &mut reconnected => if !up {
+                return;
+            },
+            _ = tokio::time::sleep(threshold) => {
+                notifications.notify(Event::Disconnected { since: threshold });
+                if !reconnected.await {
+                    return;
+                }
+                notifications.notify(Event::Reconnected);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_summary() {
+        let fill = Event::Fill {
+            market: "ETH-USDC".into(),
+            side: Side::Sell,
+            price: dec!(1600.5),
+            price_decimals: Some(2),
+            base_quantity: dec!(0.250),
+        };
+        assert_eq!(
+            summary(std::slice::from_ref(&fill)),
+            "Sold 0.25 ETH-USDC @ 1,600.50"
+        );
+        let error = Event::Error {
+            operation: "submitorder3".into(),
+            error: "Order is too small".into(),
+        };
+        assert_eq!(
+            summary(&[fill.clone(), error]),
+            "2 events:\n- Sold 0.25 ETH-USDC @ 1,600.50\n- ZigZag error on submitorder3: Order is too small"
+        );
+        let text = summary(&vec![fill; 25]);
+        assert!(text.starts_with("25 events:"));
+        assert!(text.ends_with("\n… and 5 more"));
+        assert_eq!(text.lines().count(), 22);
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let notifications = Notifications::from_senders(vec![sender]);
+        // Clones follow, without a notifier nothing is sent anymore.
+        let clone = notifications.clone();
+        assert!(notifications
+            .reconfigure(&NotifyConfig::default())
+            .is_empty());
+        clone.notify(Event::Halted {
+            reason: "test".into(),
+        });
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_watch_connection() {
+        let (status_tx, status_rx) = watch::channel(true);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let notifications = Notifications::from_senders(vec![sender]);
+        let threshold = Duration::from_millis(50);
+        let watcher = tokio::spawn(watch_connection(status_rx, threshold, notifications));
+
+        // A short outage is not reported.
+        status_tx.send_replace(false);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        status_tx.send_replace(true);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+
+        status_tx.send_replace(false);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            receiver.try_recv().ok(),
+            Some(Event::Disconnected { since: threshold })
+        );
+        status_tx.send_replace(true);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(receiver.try_recv().ok(), Some(Event::Reconnected));
+
+        drop(status_tx);
+        watcher.await.expect("watcher");
+    }
+}
@@ -0,0 +1,108 @@
+/// Telegram bot notifier, posting to one chat through `sendMessage`.
+use super::{summary, Event, Notifier};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+pub const DEFAULT_URL: &str = "https://api.telegram.org";
+
+/// `[notify.telegram]` table of the config file.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    pub bot_token: String,
+    /// Numeric id of the chat, or @name of a channel
+    pub chat_id: String,
+}
+
+fn default_url() -> String {
+    DEFAULT_URL.to_owned()
+}
+
+// Keeps the bot token out of logs.
+impl std::fmt::Debug for TelegramConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TelegramConfig")
+            .field("url", &self.url)
+            .field("bot_token", &"<redacted>")
+            .field("chat_id", &self.chat_id)
+            .finish()
+    }
+}
+
+pub struct Telegram {
+    client: reqwest::Client,
+    config: TelegramConfig,
+}
+
+impl Telegram {
+    pub fn new(config: TelegramConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    fn endpoint(&self) -> String {
+        format!(
+            "{}/bot{}/sendMessage",
+            self.config.url.trim_end_matches('/'),
+            self.config.bot_token
+        )
+    }
+
+    pub fn payload(&self, text: &str) -> serde_json::Value {
+        json!({
+            "chat_id": self.config.chat_id,
+            "text": text,
+            "disable_web_page_preview": true,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()> {
+        // Errors carry the URL, and with it the bot token.
+        self.client
+            .post(self.endpoint())
+            .json(&self.payload(&summary(events)))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow::anyhow!("{}", e.without_url()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config: TelegramConfig = toml::from_str(
+            r#"
+            bot_token = "123456:secret"
+            chat_id = "-1001234"
+            "#,
+        )
+        .expect("from_str");
+        assert!(!format!("{:?}", config).contains("secret"));
+        let telegram = Telegram::new(config);
+        assert_eq!(
+            telegram.endpoint(),
+            "https://api.telegram.org/bot123456:secret/sendMessage"
+        );
+        assert_eq!(
+            telegram.payload("Hello").to_string(),
+            r#"{"chat_id":"-1001234","disable_web_page_preview":true,"text":"Hello"}"#
+        );
+    }
+}
@@ -0,0 +1,249 @@
+/// Local order book of a market, maintained from `liquidity2` broadcasts.
+/// ZigZag sends full snapshots of the advertised liquidity rather than
+/// diffs, so every message replaces the whole book.
+use crate::zigzag::{Amount, Decimal, Liquidity, Liquidity2Args, Market, Side, Timestamp};
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub price: Decimal,
+    pub base_quantity: Amount,
+    pub expires: Option<Timestamp>,
+}
+
+impl Level {
+    fn is_live(&self, now: Timestamp) -> bool {
+        !matches!(self.expires, Some(expires) if expires <= now)
+    }
+}
+
+/// Immutable view of the book. Bids are sorted best (highest) first, asks
+/// best (lowest) first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl Snapshot {
+    /// Builds a snapshot from advertised liquidity, dropping levels that
+    /// have already expired.
+    pub fn new(liquidity: &[Liquidity], now: Timestamp) -> Self {
+        let mut snapshot = Self::default();
+        for l in liquidity {
+            let price = match l.price.value() {
+                Ok(price) => price,
+                Err(e) => {
+                    log::warn!("Skipping liquidity level: {}", e);
+                    continue;
+                }
+            };
+            let level = Level {
+                price,
+                base_quantity: l.base_quantity,
+                expires: l.expires,
+            };
+            let valid = level.price > Decimal::ZERO && level.base_quantity > Decimal::ZERO;
+            if !valid || !level.is_live(now) {
+                continue;
+            }
+            match l.side {
+                Side::Buy => snapshot.bids.push(level),
+                Side::Sell => snapshot.asks.push(level),
+            }
+        }
+        snapshot.bids.sort_by_key(|l| Reverse(l.price));
+        snapshot.asks.sort_by_key(|l| l.price);
+        snapshot
+    }
+
+    /// Copy of the snapshot without the levels expired at `now`.
+    pub fn pruned(&self, now: Timestamp) -> Self {
+        Self {
+            bids: self
+                .bids
+                .iter()
+                .filter(|l| l.is_live(now))
+                .cloned()
+                .collect(),
+            asks: self
+                .asks
+                .iter()
+                .filter(|l| l.is_live(now))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<&Level> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&Level> {
+        self.asks.first()
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        Some((self.best_bid()?.price + self.best_ask()?.price) / Decimal::TWO)
+    }
+
+    /// Distance between best bid and best ask, in basis points of the mid
+    /// price.
+    pub fn spread_bps(&self) -> Option<Decimal> {
+        let (bid, ask) = (self.best_bid()?.price, self.best_ask()?.price);
+        Some((ask - bid) / ((ask + bid) / Decimal::TWO) * Decimal::from(10_000))
+    }
+
+    /// Base quantity a taker could trade at `price` or better: asks priced
+    /// at or below it when buying through the ask, bids priced at or above
+    /// it when selling through the bid, zero inside the spread.
+    pub fn depth_at(&self, price: Decimal) -> Amount {
+        let asks: Amount = self
+            .asks
+            .iter()
+            .take_while(|l| l.price <= price)
+            .map(|l| l.base_quantity)
+            .sum();
+        let bids: Amount = self
+            .bids
+            .iter()
+            .take_while(|l| l.price >= price)
+            .map(|l| l.base_quantity)
+            .sum();
+        asks + bids
+    }
+}
+
+/// Order book of one market, shared between tasks. Readers get an `Arc` of
+/// the current snapshot, which updates replace as a whole, so they never see
+/// a half applied message.
+pub struct OrderBook {
+    market: Market,
+    snapshot: Mutex<Arc<Snapshot>>,
+}
+
+impl OrderBook {
+    pub fn new(market: Market) -> Self {
+        Self {
+            market,
+            snapshot: Mutex::new(Arc::new(Snapshot::default())),
+        }
+    }
+
+    pub fn market(&self) -> &Market {
+        &self.market
+    }
+
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    /// Replaces the book with a `liquidity2` snapshot. Returns false if the
+    /// message is for another market.
+    pub fn apply(&self, args: &Liquidity2Args, now: Timestamp) -> bool {
+        if args.market != self.market {
+            return false;
+        }
+        let snapshot = Arc::new(Snapshot::new(&args.liquidity, now));
+        *self.snapshot.lock().unwrap() = snapshot;
+        true
+    }
+
+    /// Drops levels expired at `now`.
+    pub fn prune(&self, now: Timestamp) {
+        let mut current = self.snapshot.lock().unwrap();
+        let pruned = current.pruned(now);
+        if pruned != **current {
+            *current = Arc::new(pruned);
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<Level> {
+        self.snapshot().best_bid().cloned()
+    }
+
+    pub fn best_ask(&self) -> Option<Level> {
+        self.snapshot().best_ask().cloned()
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        self.snapshot().mid_price()
+    }
+
+    pub fn spread_bps(&self) -> Option<Decimal> {
+        self.snapshot().spread_bps()
+    }
+
+    pub fn depth_at(&self, price: Decimal) -> Amount {
+        self.snapshot().depth_at(price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn liquidity2(market: &str, json: &str) -> Liquidity2Args {
+        Liquidity2Args {
+            chain_id: 1000,
+            market: market.into(),
+            liquidity: serde_json::from_str(json).expect("from_str"),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_sorted() {
+        let book = OrderBook::new("ETH-USDT".into());
+        assert!(book.apply(
+            &liquidity2(
+                "ETH-USDT",
+                r#"[["s",3310,1.0],["b",3290,0.5],["s",3305,0.2],["b",3295,0.3]]"#
+            ),
+            100
+        ));
+        let snapshot = book.snapshot();
+        let prices = |levels: &[Level]| levels.iter().map(|l| l.price).collect::<Vec<_>>();
+        assert_eq!(prices(&snapshot.bids), vec![dec!(3295), dec!(3290)]);
+        assert_eq!(prices(&snapshot.asks), vec![dec!(3305), dec!(3310)]);
+        assert_eq!(book.mid_price(), Some(dec!(3300)));
+        assert_eq!(book.spread_bps().unwrap().round_dp(6), dec!(30.303030));
+        assert_eq!(book.depth_at(dec!(3310)), dec!(1.2));
+        assert_eq!(book.depth_at(dec!(3305)), dec!(0.2));
+        assert_eq!(book.depth_at(dec!(3290)), dec!(0.8));
+        assert_eq!(book.depth_at(dec!(3300)), dec!(0));
+    }
+
+    #[test]
+    fn test_snapshots_replace_and_expire() {
+        let book = OrderBook::new("ETH-USDT".into());
+        book.apply(
+            &liquidity2(
+                "ETH-USDT",
+                r#"[["b",3295,0.3,150],["b",3290,0.5],["s",3305,0.2,120],["s",3310,1.0,90]]"#,
+            ),
+            100,
+        );
+        // Already expired levels are dropped on arrival.
+        assert_eq!(book.snapshot().asks.len(), 1);
+        let before = book.snapshot();
+
+        book.prune(120);
+        assert_eq!(book.best_bid().unwrap().price, dec!(3295));
+        assert_eq!(book.best_ask(), None);
+        assert_eq!(book.mid_price(), None);
+        // Readers holding the old snapshot keep a consistent view.
+        assert_eq!(before.best_ask().unwrap().price, dec!(3305));
+
+        book.prune(150);
+        assert_eq!(book.best_bid().unwrap().price, dec!(3290));
+
+        // Messages for other markets are ignored, the next one replaces
+        // the book entirely.
+        assert!(!book.apply(&liquidity2("WBTC-USDT", r#"[["s",1,1]]"#), 150));
+        book.apply(&liquidity2("ETH-USDT", r#"[["s",3320,0.4]]"#), 150);
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.best_ask().unwrap().price, dec!(3320));
+    }
+}
@@ -0,0 +1,603 @@
+/// Construction and signing of zksync orders used on ZigZag.
+use crate::zigzag::{Amount, Decimal, MarketInfo, Side, Timestamp, ZigzagError, ZksyncOrder};
+use async_trait::async_trait;
+use num::{rational::Ratio, BigUint, ToPrimitive, Zero};
+use rust_decimal::RoundingStrategy;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use zksync::{
+    provider::Provider,
+    zksync_types::{Nonce, TimeRange, TokenId},
+    Wallet,
+};
+use zksync_eth_signer::EthereumSigner;
+
+/// Parameters of a zksync order before signing. `ratio` is the
+/// `(sell, buy)` exchange ratio in raw token units, the same way zksync
+/// encodes `Order::price`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderParams {
+    pub token_sell: TokenId,
+    pub token_buy: TokenId,
+    pub ratio: (BigUint, BigUint),
+    pub amount: BigUint,
+    pub valid_until: Timestamp,
+}
+
+impl OrderParams {
+    /// Parameters of an order trading `base_quantity` of the market's base
+    /// asset at `price`, rounded with `MarketInfo::round_price` and
+    /// `round_quantity`. Buy orders sell the quote asset, sell orders sell
+    /// the base asset.
+    pub fn new(
+        market_info: &MarketInfo,
+        side: Side,
+        price: Decimal,
+        base_quantity: Amount,
+        expires: Timestamp,
+    ) -> anyhow::Result<Self> {
+        let base = &market_info.base_asset;
+        let quote = &market_info.quote_asset;
+        let precision = market_info.price_precision_decimal;
+        let price = market_info.round_price(price, &side);
+        let base_quantity = market_info.round_quantity(base_quantity)?;
+        let price_raw = to_raw(price, precision)?;
+        let base_raw = to_raw(base_quantity, base.decimals)?;
+        let quote_raw = &base_raw * &price_raw * BigUint::from(10u32).pow(quote.decimals)
+            / BigUint::from(10u32).pow(base.decimals + precision);
+        if price_raw.is_zero() || base_raw.is_zero() || quote_raw.is_zero() {
+            return Err(anyhow::anyhow!(
+                "Order of {} at {} rounds down to nothing!",
+                base_quantity,
+                price
+            ));
+        }
+
+        // ZigZag charges its fee out of the sold amount, so orders must at
+        // least cover it.
+        let (min, quantity, symbol) = match side {
+            Side::Sell => (market_info.base_fee.value()?, base_quantity, &base.symbol),
+            Side::Buy => (
+                market_info.quote_fee.value()?,
+                base_quantity * price,
+                &quote.symbol,
+            ),
+        };
+        if quantity <= min {
+            return Err(anyhow::anyhow!(
+                "Order of {} {} is below the market minimum of {} {}!",
+                quantity,
+                symbol,
+                min,
+                symbol
+            ));
+        }
+
+        let (base_id, quote_id) = (TokenId(base.id), TokenId(quote.id));
+        Ok(match side {
+            Side::Sell => Self {
+                token_sell: base_id,
+                token_buy: quote_id,
+                amount: base_raw.clone(),
+                ratio: (base_raw, quote_raw),
+                valid_until: expires,
+            },
+            Side::Buy => Self {
+                token_sell: quote_id,
+                token_buy: base_id,
+                amount: quote_raw.clone(),
+                ratio: (quote_raw, base_raw),
+                valid_until: expires,
+            },
+        })
+    }
+
+    /// Parameters of an already signed order.
+    pub fn from_order(order: &ZksyncOrder) -> Self {
+        Self {
+            token_sell: order.token_sell,
+            token_buy: order.token_buy,
+            ratio: order.price.clone(),
+            amount: order.amount.clone(),
+            valid_until: order.time_range.valid_until,
+        }
+    }
+
+    /// Parameters of the order taking the other side of `order`: it sells
+    /// what `order` buys at the inverse ratio, for the full amount.
+    pub fn counter(order: &ZksyncOrder, valid_until: Timestamp) -> anyhow::Result<Self> {
+        Self {
+            valid_until,
+            ..Self::from_order(order)
+        }
+        .inverse()
+    }
+
+    /// Side, price and base quantity of the order on the given market, in
+    /// human units.
+    pub fn terms(&self, market_info: &MarketInfo) -> anyhow::Result<OrderTerms> {
+        let base = &market_info.base_asset;
+        let quote = &market_info.quote_asset;
+        let (sell, buy) = &self.ratio;
+        let (side, base_raw, quote_raw) =
+            if *self.token_sell == base.id && *self.token_buy == quote.id {
+                (Side::Sell, sell, buy)
+            } else if *self.token_sell == quote.id && *self.token_buy == base.id {
+                (Side::Buy, buy, sell)
+            } else {
+                return Err(anyhow::anyhow!(
+                    "Order is not for market {}!",
+                    market_info.alias
+                ));
+            };
+        let base_units = to_units(base_raw, base.decimals)?;
+        if base_units.is_zero() {
+            return Err(anyhow::anyhow!("Order has a zero price ratio!"));
+        }
+        let price = to_units(quote_raw, quote.decimals)? / base_units;
+        if price.is_zero() {
+            return Err(anyhow::anyhow!("Order has a zero price ratio!"));
+        }
+        let base_quantity = match side {
+            Side::Sell => to_units(&self.amount, base.decimals)?,
+            Side::Buy => to_units(&self.amount, quote.decimals)? / price,
+        };
+        Ok(OrderTerms {
+            side,
+            price,
+            base_quantity,
+        })
+    }
+
+    fn inverse(self) -> anyhow::Result<Self> {
+        let (sell, buy) = self.ratio;
+        if sell.is_zero() || buy.is_zero() {
+            return Err(anyhow::anyhow!("Order has a zero price ratio!"));
+        }
+        Ok(Self {
+            token_sell: self.token_buy,
+            token_buy: self.token_sell,
+            amount: self.amount * &buy / &sell,
+            ratio: (buy, sell),
+            valid_until: self.valid_until,
+        })
+    }
+}
+
+/// Human readable terms of an order, as ZigZag reports them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderTerms {
+    pub side: Side,
+    pub price: Decimal,
+    pub base_quantity: Amount,
+}
+
+/// Converts raw token units into a human readable amount. Fails for
+/// amounts beyond the range of `Decimal`, about 7.9e28 raw units.
+pub fn to_units(raw: &BigUint, decimals: u32) -> anyhow::Result<Amount> {
+    raw.to_i128()
+        .and_then(|raw| Decimal::try_from_i128_with_scale(raw, decimals).ok())
+        .map(|units| units.normalize())
+        .ok_or_else(|| anyhow::anyhow!("Amount {} is out of range!", raw))
+}
+
+/// Converts a human readable amount into raw token units, truncating
+/// digits beyond `decimals`.
+pub fn to_raw(value: Decimal, decimals: u32) -> anyhow::Result<BigUint> {
+    if value.is_sign_negative() && !value.is_zero() {
+        return Err(anyhow::anyhow!("Invalid order amount {}!", value));
+    }
+    let value = value.round_dp_with_strategy(decimals, RoundingStrategy::ToZero);
+    let mantissa = BigUint::from(value.mantissa().unsigned_abs());
+    Ok(mantissa * BigUint::from(10u32).pow(decimals - value.scale()))
+}
+
+/// Builds and signs an order ready to be sent with `Operation::Submitorder3`.
+pub async fn build_order<O: OrderSigner + ?Sized>(
+    wallet: &O,
+    market_info: &MarketInfo,
+    side: Side,
+    price: Decimal,
+    base_quantity: Amount,
+    expires: Timestamp,
+) -> anyhow::Result<ZksyncOrder> {
+    let params = OrderParams::new(market_info, side, price, base_quantity, expires)?;
+    wallet.sign_order(params).await
+}
+
+#[async_trait]
+pub trait OrderSigner: Send + Sync {
+    async fn sign_order(&self, params: OrderParams) -> anyhow::Result<ZksyncOrder>;
+
+    /// Called with the errors ZigZag answers orders with.
+    fn on_rejected(&self, _error: &ZigzagError) {}
+}
+
+/// Signing with an explicit nonce, which `Signer` keeps track of.
+#[async_trait]
+pub trait NonceSigner: Send + Sync {
+    /// Nonce of the account's next transaction, as of the last committed
+    /// block.
+    async fn committed_nonce(&self) -> anyhow::Result<Nonce>;
+
+    async fn sign_with_nonce(
+        &self,
+        params: OrderParams,
+        nonce: Nonce,
+    ) -> anyhow::Result<ZksyncOrder>;
+}
+
+#[async_trait]
+impl<S, P> NonceSigner for Wallet<S, P>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    async fn committed_nonce(&self) -> anyhow::Result<Nonce> {
+        Ok(self.account_info().await?.committed.nonce)
+    }
+
+    async fn sign_with_nonce(
+        &self,
+        params: OrderParams,
+        nonce: Nonce,
+    ) -> anyhow::Result<ZksyncOrder> {
+        let (sell, buy) = params.ratio;
+        let order = self
+            .get_order(
+                params.token_sell,
+                params.token_buy,
+                Ratio::new_raw(sell, buy),
+                params.amount,
+                &self.address,
+                Some(nonce),
+                Some(TimeRange::new(0, params.valid_until)),
+            )
+            .await?;
+        Ok(order)
+    }
+}
+
+enum Request {
+    Sign(OrderParams, oneshot::Sender<anyhow::Result<ZksyncOrder>>),
+    RefreshNonce,
+}
+
+/// Signs the orders of every task with one wallet, one at a time, so that
+/// concurrent orders get consecutive nonces. The next nonce is tracked
+/// locally, and fetched from the provider at the first order and after
+/// ZigZag rejected one for its nonce.
+#[derive(Clone)]
+pub struct Signer {
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+impl Signer {
+    /// Spawns the task owning `wallet`, which ends with the last clone.
+    pub fn spawn<W: NonceSigner + ?Sized + 'static>(wallet: Arc<W>) -> Self {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::run(wallet, receiver));
+        Self { requests }
+    }
+
+    /// Fetches the nonce again before signing the next order.
+    pub fn refresh_nonce(&self) {
+        let _ = self.requests.send(Request::RefreshNonce);
+    }
+
+    async fn run<W: NonceSigner + ?Sized>(
+        wallet: Arc<W>,
+        mut requests: mpsc::UnboundedReceiver<Request>,
+    ) {
+        let mut next_nonce = None;
+        while let Some(request) = requests.recv().await {
+            match request {
+                Request::Sign(params, reply) => {
+                    let order = Self::sign(wallet.as_ref(), &mut next_nonce, params).await;
+                    // The caller may have timed out.
+                    let _ = reply.send(order);
+                }
+                Request::RefreshNonce => next_nonce = None,
+            }
+        }
+    }
+
+    async fn sign<W: NonceSigner + ?Sized>(
+        wallet: &W,
+        next_nonce: &mut Option<Nonce>,
+        params: OrderParams,
+    ) -> anyhow::Result<ZksyncOrder> {
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => {
+                let nonce = wallet.committed_nonce().await?;
+                log::debug!("Signing orders from nonce {}", nonce);
+                *next_nonce = Some(nonce);
+                nonce
+            }
+        };
+        let order = wallet.sign_with_nonce(params, nonce).await?;
+        *next_nonce = Some(Nonce(*nonce + 1));
+        Ok(order)
+    }
+}
+
+#[async_trait]
+impl OrderSigner for Signer {
+    async fn sign_order(&self, params: OrderParams) -> anyhow::Result<ZksyncOrder> {
+        let (reply, order) = oneshot::channel();
+        self.requests
+            .send(Request::Sign(params, reply))
+            .map_err(|_| anyhow::anyhow!("Signer stopped!"))?;
+        order
+            .await
+            .map_err(|_| anyhow::anyhow!("Signer stopped!"))?
+    }
+
+    fn on_rejected(&self, error: &ZigzagError) {
+        if let ZigzagError::InvalidNonce(_) = error {
+            log::warn!("Order nonce rejected, fetching it again");
+            self.refresh_nonce();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use futures::future;
+    use rust_decimal_macros::dec;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+    use zksync::zksync_types::{tx::TxSignature, AccountId, Address};
+
+    fn market_info() -> MarketInfo {
+        fixtures::market_info("ETH-USDC", 0, 2)
+    }
+
+    #[test]
+    fn test_sell_order_params() {
+        let params = OrderParams::new(&market_info(), Side::Sell, dec!(3300.126), dec!(0.5), 100)
+            .expect("new");
+        assert_eq!(params.token_sell, TokenId(0));
+        assert_eq!(params.token_buy, TokenId(2));
+        // Asks round down: 0.5 ETH at 3300.12 USDC is 1650.06 USDC.
+        assert_eq!(params.amount, BigUint::from(500_000_000_000_000_000u64));
+        assert_eq!(
+            params.ratio,
+            (
+                BigUint::from(500_000_000_000_000_000u64),
+                BigUint::from(1_650_060_000u64)
+            )
+        );
+        // Bids round up.
+        let params = OrderParams::new(&market_info(), Side::Buy, dec!(3300.121), dec!(0.5), 100)
+            .expect("new");
+        assert_eq!(params.amount, BigUint::from(1_650_065_000u64));
+        assert_eq!(params.valid_until, 100);
+    }
+
+    #[test]
+    fn test_buy_order_params() {
+        let params =
+            OrderParams::new(&market_info(), Side::Buy, dec!(3300), dec!(0.1), 100).expect("new");
+        assert_eq!(params.token_sell, TokenId(2));
+        assert_eq!(params.token_buy, TokenId(0));
+        assert_eq!(params.amount, BigUint::from(330_000_000u64));
+        assert_eq!(
+            params.ratio,
+            (
+                BigUint::from(330_000_000u64),
+                BigUint::from(100_000_000_000_000_000u64)
+            )
+        );
+    }
+
+    #[test]
+    fn test_order_below_minimum() {
+        let info = market_info();
+        let new =
+            |side, price, base_quantity| OrderParams::new(&info, side, price, base_quantity, 100);
+        assert!(new(Side::Sell, dec!(3300), dec!(0.0002)).is_err());
+        assert!(new(Side::Buy, dec!(3300), dec!(0.0002)).is_err());
+        assert!(new(Side::Sell, dec!(3300), dec!(0)).is_err());
+        assert!(new(Side::Sell, dec!(0.001), dec!(1)).is_err());
+        assert!(new(Side::Sell, dec!(3300), dec!(-1)).is_err());
+
+        let mut info = market_info();
+        info.min_size = Some(dec!(0.01));
+        info.max_size = Some(dec!(10));
+        let new =
+            |base_quantity| OrderParams::new(&info, Side::Buy, dec!(3300), base_quantity, 100);
+        assert!(new(dec!(0.009)).is_err());
+        assert!(new(dec!(0.01)).is_ok());
+        assert!(new(dec!(10)).is_ok());
+        assert!(new(dec!(10.5)).is_err());
+    }
+
+    #[test]
+    fn test_order_terms() {
+        let info = market_info();
+        for side in [Side::Buy, Side::Sell] {
+            let terms = OrderParams::new(&info, side.clone(), dec!(3300.12), dec!(0.5), 100)
+                .expect("new")
+                .terms(&info)
+                .expect("terms");
+            assert_eq!(terms.side, side);
+            assert_eq!(terms.price, dec!(3300.12));
+            assert_eq!(terms.base_quantity, dec!(0.5));
+        }
+        let mut other = market_info();
+        other.quote_asset.id = 1;
+        let params = OrderParams::new(&info, Side::Sell, dec!(3300), dec!(0.5), 100).expect("new");
+        assert!(params.terms(&other).is_err());
+    }
+
+    #[test]
+    fn test_to_units() {
+        assert_eq!(
+            to_units(&BigUint::from(1_650_000_000u64), 6).expect("to_units"),
+            dec!(1650)
+        );
+        assert_eq!(
+            to_units(&BigUint::from(500_000_000_000_000_000u64), 18).expect("to_units"),
+            dec!(0.5)
+        );
+        assert!(to_units(&BigUint::from(10u32).pow(40), 18).is_err());
+    }
+
+    #[test]
+    fn test_to_raw() {
+        assert_eq!(
+            to_raw(dec!(0.1), 18).expect("to_raw"),
+            BigUint::from(100_000_000_000_000_000u64)
+        );
+        assert_eq!(
+            to_raw(dec!(12.3456789), 6).expect("to_raw"),
+            BigUint::from(12_345_678u64)
+        );
+        assert_eq!(to_raw(dec!(7), 0).expect("to_raw"), BigUint::from(7u32));
+        assert!(to_raw(dec!(-1), 6).is_err());
+    }
+
+    #[test]
+    fn test_exact_scaling() {
+        // 0.1 * 3370.93 is 337.09299999999996 in f64.
+        let params = OrderParams::new(&market_info(), Side::Buy, dec!(3370.93), dec!(0.1), 100)
+            .expect("new");
+        assert_eq!(params.amount, BigUint::from(337_093_000u64));
+        assert_eq!(params.ratio.1, BigUint::from(100_000_000_000_000_000u64));
+        let sum: Amount = (0..10).map(|_| dec!(0.1)).sum();
+        assert_eq!(
+            to_raw(sum, 18).expect("to_raw"),
+            BigUint::from(1_000_000_000_000_000_000u64)
+        );
+    }
+
+    #[test]
+    fn test_counter_order_params() {
+        // Taker sells half an ETH (18 decimals) for USDC (6 decimals) at 3300.
+        let taker = OrderParams {
+            token_sell: TokenId(0),
+            token_buy: TokenId(2),
+            ratio: (
+                BigUint::from(1_000_000_000_000_000_000u64),
+                BigUint::from(3_300_000_000u64),
+            ),
+            amount: BigUint::from(500_000_000_000_000_000u64),
+            valid_until: 4294967295,
+        };
+        let params = taker.inverse().expect("inverse");
+        assert_eq!(params.token_sell, TokenId(2));
+        assert_eq!(params.token_buy, TokenId(0));
+        assert_eq!(
+            params.ratio,
+            (
+                BigUint::from(3_300_000_000u64),
+                BigUint::from(1_000_000_000_000_000_000u64)
+            )
+        );
+        assert_eq!(params.amount, BigUint::from(1_650_000_000u64));
+    }
+
+    #[test]
+    fn test_counter_order_zero_ratio() {
+        let taker = OrderParams {
+            token_sell: TokenId(0),
+            token_buy: TokenId(2),
+            ratio: (BigUint::zero(), BigUint::from(1u32)),
+            amount: BigUint::from(1u32),
+            valid_until: 0,
+        };
+        assert!(taker.inverse().is_err());
+    }
+
+    /// Signs instantly, from a committed nonce the test sets.
+    #[derive(Default)]
+    struct MockWallet {
+        committed: AtomicU32,
+        fetches: AtomicUsize,
+        signing: AtomicBool,
+    }
+
+    #[async_trait]
+    impl NonceSigner for MockWallet {
+        async fn committed_nonce(&self) -> anyhow::Result<Nonce> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(Nonce(self.committed.load(Ordering::SeqCst)))
+        }
+
+        async fn sign_with_nonce(
+            &self,
+            params: OrderParams,
+            nonce: Nonce,
+        ) -> anyhow::Result<ZksyncOrder> {
+            assert!(
+                !self.signing.swap(true, Ordering::SeqCst),
+                "concurrent signing"
+            );
+            tokio::task::yield_now().await;
+            self.signing.store(false, Ordering::SeqCst);
+            Ok(ZksyncOrder {
+                account_id: AccountId(1),
+                recipient_address: Address::zero(),
+                nonce,
+                token_buy: params.token_buy,
+                token_sell: params.token_sell,
+                price: params.ratio,
+                amount: params.amount,
+                time_range: TimeRange::new(0, params.valid_until),
+                signature: TxSignature::default(),
+            })
+        }
+    }
+
+    fn params(valid_until: Timestamp) -> OrderParams {
+        OrderParams::new(
+            &market_info(),
+            Side::Sell,
+            dec!(3300),
+            dec!(0.5),
+            valid_until,
+        )
+        .expect("new")
+    }
+
+    #[tokio::test]
+    async fn test_signer_orders_concurrent_requests() {
+        let wallet = Arc::new(MockWallet::default());
+        wallet.committed.store(7, Ordering::SeqCst);
+        let signer = Signer::spawn(wallet.clone());
+        let orders = future::join_all((0..10).map(|i| signer.sign_order(params(100 + i)))).await;
+        for (i, order) in orders.into_iter().enumerate() {
+            let order = order.expect("sign_order");
+            assert_eq!(order.time_range.valid_until, 100 + i as u64);
+            assert_eq!(order.nonce, Nonce(7 + i as u32));
+        }
+        assert_eq!(wallet.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_signer_refreshes_rejected_nonce() {
+        let wallet = Arc::new(MockWallet::default());
+        wallet.committed.store(3, Ordering::SeqCst);
+        let signer = Signer::spawn(wallet.clone());
+        let order = signer.sign_order(params(100)).await.expect("sign_order");
+        assert_eq!(order.nonce, Nonce(3));
+
+        // Other errors keep the local nonce.
+        signer.on_rejected(&ZigzagError::from("Order is too small"));
+        let order = signer.sign_order(params(100)).await.expect("sign_order");
+        assert_eq!(order.nonce, Nonce(4));
+        assert_eq!(wallet.fetches.load(Ordering::SeqCst), 1);
+
+        // Another client of the account used nonces meanwhile.
+        wallet.committed.store(9, Ordering::SeqCst);
+        signer.on_rejected(&ZigzagError::from("Invalid nonce"));
+        let order = signer.sign_order(params(100)).await.expect("sign_order");
+        assert_eq!(order.nonce, Nonce(9));
+        let order = signer.sign_order(params(100)).await.expect("sign_order");
+        assert_eq!(order.nonce, Nonce(10));
+        assert_eq!(wallet.fetches.load(Ordering::SeqCst), 2);
+    }
+}
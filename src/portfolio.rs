@@ -0,0 +1,717 @@
+/// Position and realized PnL accounting from our own fills.
+use crate::logging;
+use crate::zigzag::{
+    Amount, Decimal, Fill, FillId, FillStatus, Market, MarketPair, Operation, OrderStatus, Side,
+    Timestamp, UserId,
+};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use rust_decimal::prelude::Signed;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Running totals of one market. `position` is in base units, negative
+/// when short, PnL and fees are in quote units.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MarketPosition {
+    pub position: Amount,
+    pub avg_entry_price: Decimal,
+    /// Realized PnL, net of fees
+    pub realized_pnl: Decimal,
+    pub fees: Decimal,
+}
+
+impl MarketPosition {
+    /// Trades `quantity` base units at `price`, closing the opposite
+    /// position first and realizing PnL on the closed part.
+    fn trade(&mut self, side: Side, quantity: Amount, price: Decimal) {
+        let signed = match side {
+            Side::Buy => quantity,
+            Side::Sell => -quantity,
+        };
+        if self.position * signed < Decimal::ZERO {
+            let closed = quantity.min(self.position.abs());
+            // Long positions gain when selling above entry, shorts when
+            // buying below it.
+            self.realized_pnl += closed * (price - self.avg_entry_price) * self.position.signum();
+            self.position += closed * signed.signum();
+            let opened = quantity - closed;
+            if opened > Decimal::ZERO {
+                self.position = opened * signed.signum();
+                self.avg_entry_price = price;
+            } else if self.position.is_zero() {
+                self.avg_entry_price = Decimal::ZERO;
+            }
+        } else {
+            let total = self.position.abs() + quantity;
+            self.avg_entry_price =
+                (self.position.abs() * self.avg_entry_price + quantity * price) / total;
+            self.position += signed;
+        }
+    }
+
+    /// Takes back a fill whose settlement was rejected. Exact as long as no
+    /// later fill moved the entry price, which is kept otherwise.
+    fn undo(&mut self, fill: &PendingFill) {
+        self.position -= fill.position;
+        self.realized_pnl += fill.fee - fill.realized_pnl;
+        self.fees -= fill.fee;
+        if self.position.is_zero() {
+            self.avg_entry_price = Decimal::ZERO;
+        } else if self.avg_entry_price == fill.entry_after {
+            self.avg_entry_price = fill.entry_before;
+        }
+    }
+}
+
+/// One of our fills accounted before it settled, with what it changed, to
+/// be taken back if its settlement is rejected.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingFill {
+    pub market: Market,
+    pub status: OrderStatus,
+    /// Takers pay the fee
+    pub is_taker: bool,
+    pub price: Decimal,
+    /// Change of the position, negative when selling
+    pub position: Amount,
+    /// PnL the fill realized, before its fee
+    pub realized_pnl: Decimal,
+    /// Fee accounted, in quote units
+    pub fee: Decimal,
+    pub entry_before: Decimal,
+    pub entry_after: Decimal,
+    /// When it matched, or else when first found unsettled
+    pub since: Option<Timestamp>,
+    /// Already reported as unsettled
+    pub flagged: bool,
+}
+
+/// How a pending fill of ours ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Settlement {
+    Settled,
+    /// Rejected or otherwise failed, its trade taken back
+    Rejected,
+}
+
+/// A fill of ours still unsettled past the age alerted on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsettled {
+    pub id: FillId,
+    pub market: Market,
+    pub status: OrderStatus,
+    pub age_secs: u64,
+}
+
+/// Base quantity newly traded by one of our fills, on our side.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Trade {
+    pub market: Market,
+    pub side: Side,
+    pub quantity: Amount,
+    pub price: Decimal,
+}
+
+/// What a `FillTracker` accounted, carried over restarts by snapshots.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FillState {
+    pub markets: BTreeMap<Market, MarketPosition>,
+    /// Base quantity accounted of each fill
+    pub applied: BTreeMap<FillId, Amount>,
+    /// Fills accounted but not settled yet
+    #[serde(default)]
+    pub pending: BTreeMap<FillId, PendingFill>,
+}
+
+pub struct FillTracker {
+    user_id: UserId,
+    markets: HashMap<Market, MarketPosition>,
+    // Base quantity already accounted for each fill, so that repeated
+    // receipts and partial fills are only counted once.
+    applied: HashMap<FillId, Amount>,
+    // Fills traded but not settled yet, until a terminal status.
+    pending: HashMap<FillId, PendingFill>,
+}
+
+impl FillTracker {
+    pub fn new(user_id: UserId) -> Self {
+        Self {
+            user_id,
+            markets: HashMap::new(),
+            applied: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Accounts for our fills among an operation and returns what they
+    /// traded.
+    pub fn on_operation(&mut self, op: &Operation) -> Vec<Trade> {
+        match op {
+            Operation::Fillreceipt(fill) => self.trade(fill).into_iter().collect(),
+            Operation::Fills(args) => args.fills.iter().filter_map(|f| self.trade(f)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Accounts for one of our fills. Fills of other users and fills that
+    /// did not go through are ignored. Returns whether anything changed.
+    pub fn apply(&mut self, fill: &Fill) -> bool {
+        self.trade(fill).is_some()
+    }
+
+    fn trade(&mut self, fill: &Fill) -> Option<Trade> {
+        let trade = self.account(fill)?;
+        let market = &self.markets[&fill.market];
+        log::info!(
+            "Fill {} on {}: {:?} {} @ {}, position {}, avg entry {}, realized PnL {}",
+            fill.id,
+            fill.market,
+            trade.side,
+            trade.quantity,
+            trade.price,
+            market.position,
+            market.avg_entry_price,
+            market.realized_pnl
+        );
+        Some(trade)
+    }
+
+    /// Replays stored fills, oldest first, logging the resulting positions
+    /// rather than every fill.
+    pub fn restore(&mut self, fills: &[Fill]) {
+        for fill in fills {
+            self.account(fill);
+        }
+        for (market, position) in &self.markets {
+            log::info!(
+                "Restored {} from {} fills: position {}, avg entry {}, realized PnL {}",
+                market,
+                fills.len(),
+                position.position,
+                position.avg_entry_price,
+                position.realized_pnl
+            );
+        }
+    }
+
+    pub fn state(&self) -> FillState {
+        FillState {
+            markets: self
+                .markets
+                .iter()
+                .map(|(market, position)| (market.clone(), position.clone()))
+                .collect(),
+            applied: self.applied.iter().map(|(id, q)| (*id, *q)).collect(),
+            pending: self
+                .pending
+                .iter()
+                .map(|(id, fill)| (*id, fill.clone()))
+                .collect(),
+        }
+    }
+
+    /// Takes over what a previous run accounted, in place of anything
+    /// accounted so far.
+    pub fn resume(&mut self, state: FillState) {
+        self.markets = state.markets.into_iter().collect();
+        self.applied = state.applied.into_iter().collect();
+        self.pending = state.pending.into_iter().collect();
+    }
+
+    /// Highest id of the fills accounted.
+    pub fn last_fill_id(&self) -> Option<FillId> {
+        self.applied.keys().max().copied()
+    }
+
+    /// Returns what a fill traded that was not accounted yet, if anything.
+    fn account(&mut self, fill: &Fill) -> Option<Trade> {
+        let (side, is_taker) = our_side(fill, &self.user_id)?;
+        if self.pending.contains_key(&fill.id) && fill.fill_status != OrderStatus::Matched {
+            self.settle(fill.id, &fill.fill_status);
+        }
+        if !is_traded(fill) {
+            return None;
+        }
+
+        let price = match fill.price.value() {
+            Ok(price) => price,
+            Err(e) => {
+                logging::with_fill(fill.id, || log::warn!("Ignoring fill {}: {}", fill.id, e));
+                return None;
+            }
+        };
+        let applied = self.applied.entry(fill.id).or_default();
+        let quantity = fill.base_quantity - *applied;
+        if quantity <= Decimal::ZERO {
+            return None;
+        }
+        let first_receipt = applied.is_zero();
+        *applied = fill.base_quantity;
+
+        let market = self.markets.entry(fill.market.clone()).or_default();
+        let (entry_before, realized_before) = (market.avg_entry_price, market.realized_pnl);
+        market.trade(side.clone(), quantity, price);
+        let realized_pnl = market.realized_pnl - realized_before;
+        // ZigZag charges the fee to the taker once per fill.
+        let mut fee = Decimal::ZERO;
+        if is_taker && first_receipt {
+            if let (Some(amount), Some(token)) = (fill.fee_amount, &fill.fee_token) {
+                fee = fee_in_quote(fill.id, &fill.market, amount, token, price);
+            }
+            market.fees += fee;
+            market.realized_pnl -= fee;
+        }
+        if fill.fill_status != OrderStatus::Filled {
+            let signed = match side {
+                Side::Buy => quantity,
+                Side::Sell => -quantity,
+            };
+            let pending = self.pending.entry(fill.id).or_insert_with(|| PendingFill {
+                market: fill.market.clone(),
+                status: fill.fill_status.clone(),
+                is_taker,
+                price,
+                position: Decimal::ZERO,
+                realized_pnl: Decimal::ZERO,
+                fee: Decimal::ZERO,
+                entry_before,
+                entry_after: entry_before,
+                since: fill_time(fill),
+                flagged: false,
+            });
+            pending.status = fill.fill_status.clone();
+            pending.position += signed;
+            pending.realized_pnl += realized_pnl;
+            pending.fee += fee;
+            pending.entry_after = market.avg_entry_price;
+        }
+        Some(Trade {
+            market: fill.market.clone(),
+            side,
+            quantity,
+            price,
+        })
+    }
+
+    /// Follows the settlement of one of our pending fills: its fee is
+    /// corrected to the one the status carries, a terminal status settles
+    /// it, and a rejection takes its trade back. Returns how it ended, if it
+    /// did.
+    pub fn on_fill_status(&mut self, status: &FillStatus) -> Option<(Market, Settlement)> {
+        let pending = self.pending.get_mut(&status.full_id)?;
+        if pending.is_taker && is_traded_status(&status.status) {
+            let fee = fee_in_quote(
+                status.full_id,
+                &pending.market,
+                status.fee_amount,
+                &status.fee_token,
+                pending.price,
+            );
+            let correction = fee - pending.fee;
+            if !correction.is_zero() {
+                log::info!(
+                    "Fee of fill {} on {} corrected from {} to {}",
+                    status.full_id,
+                    pending.market,
+                    pending.fee,
+                    fee
+                );
+                pending.fee = fee;
+                let market = self.markets.entry(pending.market.clone()).or_default();
+                market.fees += correction;
+                market.realized_pnl -= correction;
+            }
+        }
+        pending.since = pending.since.or(Some(status.timestamp));
+        let market = pending.market.clone();
+        self.settle(status.full_id, &status.status)
+            .map(|settlement| (market, settlement))
+    }
+
+    /// Settles a pending fill on a terminal status, or records its new one.
+    fn settle(&mut self, id: FillId, status: &OrderStatus) -> Option<Settlement> {
+        let pending = self.pending.get_mut(&id)?;
+        if *status != OrderStatus::Filled && is_traded_status(status) {
+            pending.status = status.clone();
+            return None;
+        }
+        let pending = self.pending.remove(&id)?;
+        if *status == OrderStatus::Filled {
+            log::info!("Fill {} on {} settled", id, pending.market);
+            return Some(Settlement::Settled);
+        }
+        let market = self.markets.entry(pending.market.clone()).or_default();
+        market.undo(&pending);
+        log::warn!(
+            "Fill {} on {} ended {:?}, took back its trade of {}: position {}, realized PnL {}",
+            id,
+            pending.market,
+            status,
+            pending.position,
+            market.position,
+            market.realized_pnl
+        );
+        Some(Settlement::Rejected)
+    }
+
+    /// Pending fills older than `max_age_secs` at `now`, each reported once.
+    /// Fills without a time age from the first call that sees them.
+    pub fn unsettled(&mut self, max_age_secs: u64, now: Timestamp) -> Vec<Unsettled> {
+        let mut unsettled: Vec<_> = self
+            .pending
+            .iter_mut()
+            .filter_map(|(id, fill)| {
+                let since = *fill.since.get_or_insert(now);
+                let age_secs = now.saturating_sub(since);
+                if fill.flagged || age_secs < max_age_secs {
+                    return None;
+                }
+                fill.flagged = true;
+                Some(Unsettled {
+                    id: *id,
+                    market: fill.market.clone(),
+                    status: fill.status.clone(),
+                    age_secs,
+                })
+            })
+            .collect();
+        unsettled.sort_unstable_by_key(|fill| fill.id);
+        unsettled
+    }
+
+    pub fn pending(&self, id: FillId) -> Option<&PendingFill> {
+        self.pending.get(&id)
+    }
+
+    pub fn market(&self, market: &str) -> Option<&MarketPosition> {
+        self.markets.get(market)
+    }
+
+    pub fn position(&self, market: &str) -> Amount {
+        self.market(market).map_or(Decimal::ZERO, |m| m.position)
+    }
+
+    pub fn realized_pnl(&self, market: &str) -> Decimal {
+        self.market(market)
+            .map_or(Decimal::ZERO, |m| m.realized_pnl)
+    }
+
+    /// Average entry price of the open position, if any.
+    pub fn avg_entry_price(&self, market: &str) -> Option<Decimal> {
+        self.market(market)
+            .filter(|m| !m.position.is_zero())
+            .map(|m| m.avg_entry_price)
+    }
+}
+
+/// Side `user_id` traded in a fill and whether it was the taker, or `None`
+/// if it was not a party. The fill side is the taker's, makers trade the
+/// other way.
+pub fn our_side(fill: &Fill, user_id: &str) -> Option<(Side, bool)> {
+    if fill.taker_user_id == user_id {
+        Some((fill.side.clone(), true))
+    } else if fill.maker_user_id == user_id {
+        Some((fill.side.opposite(), false))
+    } else {
+        None
+    }
+}
+
+/// Whether a fill went through, or is on its way.
+pub fn is_traded(fill: &Fill) -> bool {
+    is_traded_status(&fill.fill_status)
+}
+
+fn is_traded_status(status: &OrderStatus) -> bool {
+    matches!(
+        status,
+        OrderStatus::Matched
+            | OrderStatus::Broadcasted
+            | OrderStatus::Filled
+            | OrderStatus::PartialFill
+    )
+}
+
+/// Takes the settlement details of a `fillstatus` into a stored fill.
+pub fn apply_status(fill: &mut Fill, status: &FillStatus) {
+    fill.fill_status = status.status.clone();
+    if status.tx_hash.is_some() {
+        fill.tx_hash.clone_from(&status.tx_hash);
+    }
+    fill.fee_amount = Some(status.fee_amount);
+    fill.fee_token = Some(status.fee_token.clone());
+    if let Some(time) = Utc.timestamp_opt(status.timestamp as i64, 0).single() {
+        fill.timestamp = Some(time.to_rfc3339_opts(SecondsFormat::Secs, true));
+    }
+}
+
+fn fill_time(fill: &Fill) -> Option<Timestamp> {
+    fill.timestamp
+        .as_deref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.timestamp().max(0) as Timestamp)
+}
+
+/// Fee of a fill in quote units. Fees paid in the base asset are converted
+/// at the fill price.
+fn fee_in_quote(id: FillId, market: &str, amount: Decimal, token: &str, price: Decimal) -> Decimal {
+    match market.parse::<MarketPair>() {
+        Ok(pair) if token == pair.base() => amount * price,
+        Ok(pair) if token == pair.quote() => amount,
+        _ => {
+            log::warn!(
+                "Ignoring fee of {} {} on fill {} in {}",
+                amount,
+                token,
+                id,
+                market
+            );
+            Decimal::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_buy_then_partial_sell() {
+        let mut tracker = FillTracker::new("23".into());
+        assert!(tracker.apply(&fixtures::fill(
+            1,
+            Side::Buy,
+            dec!(3000),
+            dec!(1),
+            OrderStatus::Filled
+        )));
+        assert!(tracker.apply(&fixtures::fill(
+            2,
+            Side::Buy,
+            dec!(3300),
+            dec!(1),
+            OrderStatus::Filled
+        )));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(2));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC").unwrap(), dec!(3150));
+
+        // Selling 0.5 then 1.0 of the same fill only counts the increment.
+        assert!(tracker.apply(&fixtures::fill(
+            3,
+            Side::Sell,
+            dec!(3250),
+            dec!(0.5),
+            OrderStatus::PartialFill
+        )));
+        assert!(tracker.apply(&fixtures::fill(
+            3,
+            Side::Sell,
+            dec!(3250),
+            dec!(1),
+            OrderStatus::PartialFill
+        )));
+        assert!(!tracker.apply(&fixtures::fill(
+            3,
+            Side::Sell,
+            dec!(3250),
+            dec!(1),
+            OrderStatus::Filled
+        )));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(1));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(100));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC").unwrap(), dec!(3150));
+
+        // Flipping short realizes the rest and reopens at the fill price.
+        assert!(tracker.apply(&fixtures::fill(
+            4,
+            Side::Sell,
+            dec!(3100),
+            dec!(1.5),
+            OrderStatus::Filled
+        )));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(-0.5));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(50));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC").unwrap(), dec!(3100));
+
+        assert!(tracker.apply(&fixtures::fill(
+            5,
+            Side::Buy,
+            dec!(3000),
+            dec!(0.5),
+            OrderStatus::Filled
+        )));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(100));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC"), None);
+    }
+
+    #[test]
+    fn test_fees_and_ignored_fills() {
+        let mut tracker = FillTracker::new("23".into());
+        let mut buy = fixtures::fill(1, Side::Buy, dec!(3000), dec!(1), OrderStatus::Filled);
+        buy.fee_amount = Some(dec!(0.001));
+        buy.fee_token = Some("ETH".into());
+        assert!(tracker.apply(&buy));
+        let mut sell = fixtures::fill(2, Side::Sell, dec!(3100), dec!(1), OrderStatus::Filled);
+        sell.fee_amount = Some(dec!(2));
+        sell.fee_token = Some("USDC".into());
+        assert!(tracker.apply(&sell));
+        // 100 profit, minus 3 USDC of fee paid in ETH and 2 in USDC.
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(95));
+        assert_eq!(tracker.market("ETH-USDC").unwrap().fees, dec!(5));
+
+        // As maker we trade the opposite side and pay no fee.
+        let mut maker = fixtures::fill(3, Side::Sell, dec!(3000), dec!(1), OrderStatus::Filled);
+        maker.taker_user_id = "42".into();
+        maker.maker_user_id = "23".into();
+        maker.fee_amount = Some(dec!(5));
+        maker.fee_token = Some("USDC".into());
+        assert!(tracker.apply(&maker));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(1));
+        assert_eq!(tracker.market("ETH-USDC").unwrap().fees, dec!(5));
+
+        assert!(!tracker.apply(&fixtures::fill(
+            4,
+            Side::Buy,
+            dec!(3000),
+            dec!(1),
+            OrderStatus::Rejected
+        )));
+        let mut other = fixtures::fill(5, Side::Buy, dec!(3000), dec!(1), OrderStatus::Filled);
+        other.taker_user_id = "7".into();
+        assert!(!tracker.apply(&other));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(1));
+    }
+
+    fn fill_status(id: FillId, status: &str, fee: Decimal, timestamp: Timestamp) -> FillStatus {
+        serde_json::from_str(&format!(
+            r#"[1000,{},"{}",null,0,{},"USDC",{}]"#,
+            id, status, fee, timestamp
+        ))
+        .expect("from_str")
+    }
+
+    #[test]
+    fn test_settlement() {
+        let mut tracker = FillTracker::new("23".into());
+        assert!(tracker.apply(&fixtures::fill(
+            1,
+            Side::Buy,
+            dec!(3000),
+            dec!(1),
+            OrderStatus::Matched
+        )));
+        assert_eq!(
+            tracker.pending(1).expect("pending").status,
+            OrderStatus::Matched
+        );
+        // The fee missing from the receipt comes with the status.
+        assert_eq!(
+            tracker.on_fill_status(&fill_status(1, "b", dec!(1.5), 1700000000)),
+            None
+        );
+        assert_eq!(tracker.market("ETH-USDC").unwrap().fees, dec!(1.5));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(-1.5));
+        assert_eq!(
+            tracker.on_fill_status(&fill_status(1, "f", dec!(2), 1700000005)),
+            Some(("ETH-USDC".into(), Settlement::Settled))
+        );
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(-2));
+        assert_eq!(tracker.pending(1), None);
+        // Settled fills are left alone, as are fills of others.
+        assert_eq!(
+            tracker.on_fill_status(&fill_status(1, "r", dec!(2), 1700000010)),
+            None
+        );
+        assert_eq!(
+            tracker.on_fill_status(&fill_status(9, "r", dec!(2), 1700000010)),
+            None
+        );
+        assert_eq!(tracker.position("ETH-USDC"), dec!(1));
+    }
+
+    #[test]
+    fn test_rejected_settlement() {
+        let mut tracker = FillTracker::new("23".into());
+        assert!(tracker.apply(&fixtures::fill(
+            1,
+            Side::Buy,
+            dec!(3000),
+            dec!(1),
+            OrderStatus::Filled
+        )));
+        let mut pending = fixtures::fill(2, Side::Buy, dec!(3300), dec!(1), OrderStatus::Matched);
+        pending.fee_amount = Some(dec!(3));
+        pending.fee_token = Some("USDC".into());
+        assert!(tracker.apply(&pending));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(2));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC").unwrap(), dec!(3150));
+
+        // The failed settlement takes back the position, entry and fee.
+        assert_eq!(
+            tracker.on_fill_status(&fill_status(2, "r", dec!(0), 1700000005)),
+            Some(("ETH-USDC".into(), Settlement::Rejected))
+        );
+        assert_eq!(tracker.position("ETH-USDC"), dec!(1));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC").unwrap(), dec!(3000));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(0));
+        assert_eq!(tracker.market("ETH-USDC").unwrap().fees, dec!(0));
+
+        // A sell closing part of the position, rejected by its receipt.
+        assert!(tracker.apply(&fixtures::fill(
+            3,
+            Side::Sell,
+            dec!(3200),
+            dec!(0.5),
+            OrderStatus::Matched
+        )));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(100));
+        assert!(!tracker.apply(&fixtures::fill(
+            3,
+            Side::Sell,
+            dec!(3200),
+            dec!(0.5),
+            OrderStatus::Rejected
+        )));
+        assert_eq!(tracker.position("ETH-USDC"), dec!(1));
+        assert_eq!(tracker.realized_pnl("ETH-USDC"), dec!(0));
+        assert_eq!(tracker.avg_entry_price("ETH-USDC").unwrap(), dec!(3000));
+    }
+
+    #[test]
+    fn test_unsettled() {
+        let mut tracker = FillTracker::new("23".into());
+        let mut matched = fixtures::fill(1, Side::Buy, dec!(3000), dec!(1), OrderStatus::Matched);
+        matched.timestamp = Some("2023-11-14T22:13:20Z".into());
+        tracker.apply(&matched);
+        tracker.apply(&fixtures::fill(
+            2,
+            Side::Sell,
+            dec!(3000),
+            dec!(1),
+            OrderStatus::Broadcasted,
+        ));
+        assert!(tracker.unsettled(600, 1700000599).is_empty());
+        assert_eq!(
+            tracker.unsettled(600, 1700000600),
+            vec![Unsettled {
+                id: 1,
+                market: "ETH-USDC".into(),
+                status: OrderStatus::Matched,
+                age_secs: 600,
+            }]
+        );
+        // Reported once, fills without a time age from the first check.
+        assert!(tracker.unsettled(600, 1700001198).is_empty());
+        assert_eq!(tracker.unsettled(600, 1700001199)[0].id, 2);
+
+        // Pending fills survive snapshots.
+        let mut resumed = FillTracker::new("23".into());
+        resumed.resume(tracker.state());
+        assert_eq!(
+            resumed.on_fill_status(&fill_status(2, "f", dec!(0), 1700001300)),
+            Some(("ETH-USDC".into(), Settlement::Settled))
+        );
+    }
+}
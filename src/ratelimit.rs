@@ -0,0 +1,390 @@
+/// Rate limiting of the operations we send, ahead of ZigZag's own limits.
+/// Liquidity updates and other requests each draw from a token bucket.
+/// Updates waiting for a token are coalesced per market, so a fast feed
+/// only ever has the latest liquidity of a market queued. Fill requests and
+/// cancels are never held back. A batch goes out whole for a single request
+/// token.
+use crate::metrics::Metrics;
+use crate::zigzag::{Decimal, Market, Operation, ToPrimitive};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// `[rate_limit]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Liquidity indications
+    pub updates: BucketConfig,
+    /// Orders, quotes and the other requests
+    pub requests: BucketConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            updates: BucketConfig {
+                per_sec: Decimal::from(2),
+                burst: 5,
+            },
+            requests: BucketConfig {
+                per_sec: Decimal::from(5),
+                burst: 10,
+            },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, bucket) in [("updates", &self.updates), ("requests", &self.requests)] {
+            if bucket.per_sec <= Decimal::ZERO || bucket.burst == 0 {
+                return Err(anyhow::anyhow!(
+                    "rate_limit.{} needs a positive per_sec and burst!",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sustained rate and burst of a token bucket.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub per_sec: Decimal,
+    pub burst: u32,
+}
+
+/// How an outgoing operation is limited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    /// Liquidity indications, superseded by the next one of their market
+    Updates,
+    Requests,
+    /// Sent right away: answers to fill requests, cancels and the session
+    Critical,
+}
+
+impl Class {
+    pub fn of(op: &Operation) -> Self {
+        match op {
+            Operation::Indicateliq2(_) => Class::Updates,
+            Operation::Fillrequest(_)
+            | Operation::Cancelall(_)
+            | Operation::Cancelorder(_)
+            | Operation::Login(_) => Class::Critical,
+            _ => Class::Requests,
+        }
+    }
+}
+
+struct TokenBucket {
+    per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    fn new(config: &BucketConfig, now: Instant) -> Self {
+        let burst = f64::from(config.burst);
+        Self {
+            per_sec: config.per_sec.to_f64().unwrap_or_default(),
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.burst);
+        self.updated = now;
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// When the next token is available, as of the last refill.
+    fn ready_at(&self) -> Instant {
+        let missing = (1.0 - self.tokens).max(0.0);
+        self.updated + Duration::from_secs_f64(missing / self.per_sec)
+    }
+}
+
+/// Queue of the operations to send, handing them out as the limits allow.
+pub struct RateLimiter {
+    updates: TokenBucket,
+    requests: TokenBucket,
+    critical: VecDeque<Operation>,
+    /// Latest liquidity of each market, in the order markets were queued
+    queued_updates: VecDeque<(Market, Operation)>,
+    queued_requests: VecDeque<Operation>,
+    queued_batches: VecDeque<Vec<Operation>>,
+    metrics: Arc<Metrics>,
+}
+
+impl RateLimiter {
+    /// Counts coalesced and dropped operations in `metrics`.
+    pub fn new(config: &RateLimitConfig, metrics: Arc<Metrics>) -> Self {
+        let now = Instant::now();
+        Self {
+            updates: TokenBucket::new(&config.updates, now),
+            requests: TokenBucket::new(&config.requests, now),
+            critical: VecDeque::new(),
+            queued_updates: VecDeque::new(),
+            queued_requests: VecDeque::new(),
+            queued_batches: VecDeque::new(),
+            metrics,
+        }
+    }
+
+    /// Queues an operation. A queued liquidity update of the same market is
+    /// replaced, keeping its place in the queue.
+    pub fn push(&mut self, op: Operation) {
+        match (Class::of(&op), &op) {
+            (Class::Critical, _) => self.critical.push_back(op),
+            (Class::Updates, Operation::Indicateliq2(args)) => {
+                let market = args.market.clone();
+                match self.queued_updates.iter_mut().find(|(m, _)| *m == market) {
+                    Some((_, queued)) => {
+                        log::debug!("Coalescing liquidity of {}", market);
+                        self.metrics.incr("ratelimit_coalesced");
+                        *queued = op;
+                    }
+                    None => self.queued_updates.push_back((market, op)),
+                }
+            }
+            _ => self.queued_requests.push_back(op),
+        }
+    }
+
+    /// Next operation that may be sent at `now`: critical ones first, then
+    /// requests, then updates.
+    pub fn pop(&mut self, now: Instant) -> Option<Operation> {
+        if let Some(op) = self.critical.pop_front() {
+            return Some(op);
+        }
+        if !self.queued_requests.is_empty() && self.requests.try_take(now) {
+            return self.queued_requests.pop_front();
+        }
+        if !self.queued_updates.is_empty() && self.updates.try_take(now) {
+            return self.queued_updates.pop_front().map(|(_, op)| op);
+        }
+        None
+    }
+
+    /// Queues operations to send back to back.
+    pub fn push_batch(&mut self, ops: Vec<Operation>) {
+        self.queued_batches.push_back(ops);
+    }
+
+    /// Next batch that may be sent at `now`, once no critical operation is
+    /// waiting. Requests queued before it may still be waiting.
+    pub fn pop_batch(&mut self, now: Instant) -> Option<Vec<Operation>> {
+        if !self.critical.is_empty()
+            || self.queued_batches.is_empty()
+            || !self.requests.try_take(now)
+        {
+            return None;
+        }
+        self.queued_batches.pop_front()
+    }
+
+    /// When an operation still queued after `pop` may go out.
+    pub fn next_at(&self) -> Option<Instant> {
+        let waiting = !self.queued_requests.is_empty() || !self.queued_batches.is_empty();
+        let requests = waiting.then(|| self.requests.ready_at());
+        let updates = (!self.queued_updates.is_empty()).then(|| self.updates.ready_at());
+        requests.into_iter().chain(updates).min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.critical.len()
+            + self.queued_requests.len()
+            + self.queued_updates.len()
+            + self.queued_batches.iter().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops what is still queued, on shutdown, counting it.
+    pub fn clear(&mut self) {
+        let dropped = self.queued_requests.len()
+            + self.queued_updates.len()
+            + self.queued_batches.iter().map(Vec::len).sum::<usize>();
+        if dropped > 0 {
+            log::warn!("Dropping {} rate limited operations", dropped);
+            self.metrics.add("ratelimit_dropped", dropped as u64);
+        }
+        self.queued_requests.clear();
+        self.queued_updates.clear();
+        self.queued_batches.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{CancelallArgs, Indicateliq2Args, Liquidity, Side, SubscribemarketArgs};
+    use rust_decimal_macros::dec;
+
+    fn config(per_sec: Decimal, burst: u32) -> RateLimitConfig {
+        let bucket = BucketConfig { per_sec, burst };
+        RateLimitConfig {
+            updates: bucket.clone(),
+            requests: bucket,
+        }
+    }
+
+    fn liquidity(market: &str, price: Decimal) -> Operation {
+        Operation::Indicateliq2(Indicateliq2Args {
+            chain_id: 1000,
+            market: market.into(),
+            liquidity: vec![Liquidity {
+                side: Side::Buy,
+                price: price.into(),
+                base_quantity: dec!(0.5),
+                expires: None,
+            }],
+        })
+    }
+
+    fn prices(op: Option<Operation>) -> Option<(Market, Decimal)> {
+        match op? {
+            Operation::Indicateliq2(args) => {
+                Some((args.market, args.liquidity[0].price.value().expect("price")))
+            }
+            op => panic!("Unexpected {:?}", op),
+        }
+    }
+
+    #[test]
+    fn test_coalesce_keeps_latest() {
+        let metrics = Arc::new(Metrics::new());
+        let mut limiter = RateLimiter::new(&config(dec!(1), 1), metrics.clone());
+        let now = Instant::now();
+        limiter.push(liquidity("ETH-USDC", dec!(1999)));
+        assert_eq!(
+            prices(limiter.pop(now)),
+            Some(("ETH-USDC".into(), dec!(1999)))
+        );
+
+        // Out of tokens: three updates of a market become the last one.
+        limiter.push(liquidity("ETH-USDC", dec!(2000)));
+        limiter.push(liquidity("WBTC-USDC", dec!(30000)));
+        limiter.push(liquidity("ETH-USDC", dec!(2001)));
+        limiter.push(liquidity("ETH-USDC", dec!(2002)));
+        assert_eq!(limiter.len(), 2);
+        assert_eq!(metrics.counter("ratelimit_coalesced"), 2);
+        assert!(limiter.pop(now).is_none());
+        assert_eq!(limiter.next_at(), Some(now + Duration::from_secs(1)));
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            prices(limiter.pop(later)),
+            Some(("ETH-USDC".into(), dec!(2002)))
+        );
+        assert!(limiter.pop(later).is_none());
+        let later = later + Duration::from_secs(1);
+        assert_eq!(
+            prices(limiter.pop(later)),
+            Some(("WBTC-USDC".into(), dec!(30000)))
+        );
+        assert!(limiter.is_empty());
+        assert_eq!(limiter.next_at(), None);
+    }
+
+    #[test]
+    fn test_critical_bypass() {
+        let metrics = Arc::new(Metrics::new());
+        let mut limiter = RateLimiter::new(&config(dec!(0.5), 2), metrics.clone());
+        let now = Instant::now();
+        let subscribe = || {
+            Operation::Subscribemarket(SubscribemarketArgs {
+                chain_id: 1000,
+                market: "ETH-USDC".into(),
+            })
+        };
+        for _ in 0..3 {
+            limiter.push(subscribe());
+        }
+        limiter.push(Operation::Cancelall(CancelallArgs {
+            chain_id: 1000,
+            user_id: "23".into(),
+        }));
+        assert!(matches!(limiter.pop(now), Some(Operation::Cancelall(_))));
+        // A burst of two, then one every two seconds.
+        assert!(matches!(
+            limiter.pop(now),
+            Some(Operation::Subscribemarket(_))
+        ));
+        assert!(matches!(
+            limiter.pop(now),
+            Some(Operation::Subscribemarket(_))
+        ));
+        assert!(limiter.pop(now).is_none());
+        assert_eq!(limiter.next_at(), Some(now + Duration::from_secs(2)));
+        assert!(limiter.pop(now + Duration::from_secs(1)).is_none());
+
+        limiter.clear();
+        assert!(limiter.is_empty());
+        assert_eq!(metrics.counter("ratelimit_dropped"), 1);
+    }
+
+    #[test]
+    fn test_batch_goes_out_whole() {
+        let metrics = Arc::new(Metrics::new());
+        let mut limiter = RateLimiter::new(&config(dec!(1), 1), metrics.clone());
+        let now = Instant::now();
+        let cancel = Operation::Cancelall(CancelallArgs {
+            chain_id: 1000,
+            user_id: "23".into(),
+        });
+        limiter.push_batch(vec![
+            cancel.clone(),
+            liquidity("ETH-USDC", dec!(2000)),
+            liquidity("ETH-USDC", dec!(2001)),
+        ]);
+        limiter.push_batch(vec![liquidity("ETH-USDC", dec!(2002))]);
+        limiter.push(cancel);
+        assert_eq!(limiter.len(), 5);
+        // Critical operations go first.
+        assert!(limiter.pop_batch(now).is_none());
+        assert!(matches!(limiter.pop(now), Some(Operation::Cancelall(_))));
+        // Nothing of a batch is coalesced.
+        let batch = limiter.pop_batch(now).expect("batch");
+        assert_eq!(batch.len(), 3);
+        assert!(matches!(batch[0], Operation::Cancelall(_)));
+        assert_eq!(
+            prices(Some(batch[2].clone())),
+            Some(("ETH-USDC".into(), dec!(2001)))
+        );
+        assert!(limiter.pop_batch(now).is_none());
+        assert_eq!(limiter.next_at(), Some(now + Duration::from_secs(1)));
+
+        limiter.clear();
+        assert!(limiter.is_empty());
+        assert_eq!(metrics.counter("ratelimit_dropped"), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(RateLimitConfig::default().validate().is_ok());
+        assert!(config(dec!(0), 1).validate().is_err());
+        assert!(config(dec!(1), 0).validate().is_err());
+    }
+}
@@ -0,0 +1,193 @@
+/// Order and fill receipts of the `order` and `fill` subcommands, polled
+/// until the order or fill reaches a final status.
+use crate::client::{Transport, ZigzagClient};
+use crate::zigzag::{ChainId, Fill, Order, OrderId, OrderStatus};
+use std::fmt;
+use std::time::Duration;
+
+/// Failures of a receipt request that scripts may want to tell apart, see
+/// `exit_code`.
+#[derive(Debug, PartialEq)]
+pub enum ReceiptError {
+    Timeout(Duration),
+    /// The backend knows no such order, with its message
+    NotFound(String),
+}
+
+impl ReceiptError {
+    /// Process exit code, distinct from the ones of `Receipt::exit_code`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ReceiptError::Timeout(_) => 3,
+            ReceiptError::NotFound(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiptError::Timeout(timeout) => write!(f, "No receipt received within {:?}", timeout),
+            ReceiptError::NotFound(message) => write!(f, "Not found: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ReceiptError {}
+
+/// What a receipt request looks up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReceiptKind {
+    Order,
+    Fill,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Receipt {
+    Order(Order),
+    Fill(Fill),
+}
+
+impl Receipt {
+    pub async fn fetch<T: Transport>(
+        client: &mut ZigzagClient<T>,
+        kind: ReceiptKind,
+        chain_id: ChainId,
+        order_id: OrderId,
+    ) -> anyhow::Result<Self> {
+        Ok(match kind {
+            ReceiptKind::Order => Receipt::Order(client.order_receipt(chain_id, order_id).await?),
+            ReceiptKind::Fill => Receipt::Fill(client.fill_receipt(chain_id, order_id).await?),
+        })
+    }
+
+    pub fn status(&self) -> &OrderStatus {
+        match self {
+            Receipt::Order(order) => &order.order_status,
+            Receipt::Fill(fill) => &fill.fill_status,
+        }
+    }
+
+    /// Whether the status can no longer change.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self.status(),
+            OrderStatus::Filled
+                | OrderStatus::Canceled
+                | OrderStatus::Expired
+                | OrderStatus::Rejected
+        )
+    }
+
+    /// Process exit code of the outcome: 0 when filled or still live, 4
+    /// when rejected, 6 when canceled and 7 when expired.
+    pub fn exit_code(&self) -> i32 {
+        match self.status() {
+            OrderStatus::Rejected => 4,
+            OrderStatus::Canceled => 6,
+            OrderStatus::Expired => 7,
+            _ => 0,
+        }
+    }
+}
+
+/// Orders and fills are displayed by `display`, with the bot.
+#[cfg(feature = "bot")]
+impl fmt::Display for Receipt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Receipt::Order(order) => order.fmt(f),
+            Receipt::Fill(fill) => fill.fmt(f),
+        }
+    }
+}
+
+/// Fetches a receipt and, with an `interval`, fetches it again after each
+/// interval until it is final. `on_change` sees the first receipt and every
+/// one differing from the previous. Returns the last receipt.
+pub async fn watch<T: Transport>(
+    client: &mut ZigzagClient<T>,
+    kind: ReceiptKind,
+    chain_id: ChainId,
+    order_id: OrderId,
+    interval: Option<Duration>,
+    mut on_change: impl FnMut(&Receipt),
+) -> anyhow::Result<Receipt> {
+    let mut last = None;
+    loop {
+        let receipt = Receipt::fetch(client, kind, chain_id, order_id).await?;
+        if last.as_ref() != Some(&receipt) {
+            on_change(&receipt);
+        }
+        match interval {
+            Some(interval) if !receipt.is_final() => tokio::time::sleep(interval).await,
+            _ => return Ok(receipt),
+        }
+        last = Some(receipt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::MockTransport;
+    use async_tungstenite::tungstenite::Message;
+
+    fn order_receipt(status: &str, remaining: &str) -> Message {
+        Message::Text(format!(
+            r#"{{"op":"orderreceipt","args":[1000,8462,"ETH-USDC","s","1970.5",0.5,985.25,1666262459,"48213","{}",{}]}}"#,
+            status, remaining
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let mut client = ZigzagClient::new(MockTransport::with_frames([
+            order_receipt("o", "0.5"),
+            order_receipt("o", "0.5"),
+            order_receipt("pf", "0.2"),
+            order_receipt("c", "0.2"),
+        ]));
+        let mut changes = Vec::new();
+        let receipt = watch(
+            &mut client,
+            ReceiptKind::Order,
+            1000,
+            8462,
+            Some(Duration::from_millis(1)),
+            |receipt| changes.push(receipt.status().clone()),
+        )
+        .await
+        .expect("watch");
+        assert_eq!(
+            changes,
+            vec![
+                OrderStatus::Open,
+                OrderStatus::PartialFill,
+                OrderStatus::Canceled
+            ]
+        );
+        assert!(receipt.is_final());
+        assert_eq!(receipt.exit_code(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_once() {
+        // Without an interval a live order is reported as is.
+        let mut client = ZigzagClient::new(MockTransport::with_frames([order_receipt("o", "0.5")]));
+        let receipt = watch(&mut client, ReceiptKind::Order, 1000, 8462, None, |_| ())
+            .await
+            .expect("watch");
+        assert!(!receipt.is_final());
+        assert_eq!(receipt.exit_code(), 0);
+
+        let mut client = ZigzagClient::new(MockTransport::with_frames([order_receipt(
+            "r",
+            r#""Not enough balance""#,
+        )]));
+        let receipt = watch(&mut client, ReceiptKind::Order, 1000, 8462, None, |_| ())
+            .await
+            .expect("watch");
+        assert_eq!(receipt.exit_code(), 4);
+    }
+}
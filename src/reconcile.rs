@@ -0,0 +1,645 @@
+/// Reconciliation of the orders and fills we track against the `orders` and
+/// `fills` snapshots the backend sends on login and on every subscription,
+/// so at startup and again after each reconnect. Orders left open past
+/// their market's time-to-live are canceled from here too.
+use crate::metrics::Metrics;
+use crate::portfolio::{is_traded, our_side};
+use crate::zigzag::{
+    Amount, CancelorderArgs, ChainId, Fill, FillId, FillsArgs, Market, Operation, OrderId,
+    OrderStatus, OrdersArgs, RemainingOrError, Timestamp, UserId, H256,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// One of our orders, as last reported.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrackedOrder {
+    pub chain_id: ChainId,
+    pub market: Market,
+    pub status: OrderStatus,
+    /// Thought open but missing from the last snapshot of its market
+    pub unknown: bool,
+    /// When its `userorderack` came, orders only known from receipts and
+    /// snapshots never expire
+    pub acked: Option<Timestamp>,
+    /// When we last canceled it for its age, until its status confirms
+    pub cancel_sent: Option<Timestamp>,
+    /// Base quantity left to fill, as last reported
+    #[serde(default)]
+    pub remaining: Option<Amount>,
+    /// Transaction of its latest fill, which `fillstatus` updates name
+    #[serde(default)]
+    pub tx_hash: Option<H256>,
+}
+
+impl TrackedOrder {
+    pub fn is_cancel_pending(&self) -> bool {
+        self.cancel_sent.is_some()
+    }
+}
+
+/// How a snapshot differed from what we tracked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reconciliation {
+    /// Orders we thought open that the snapshot does not list, now unknown
+    pub missing: Vec<OrderId>,
+    /// Orders listed with another status than ours, with the new status
+    pub changed: Vec<(OrderId, OrderStatus)>,
+    /// Open orders of ours we did not track yet
+    pub adopted: Vec<OrderId>,
+}
+
+impl Reconciliation {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.adopted.is_empty()
+    }
+}
+
+/// What `OpenOrders` tracked, carried over restarts by snapshots.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct OrdersState {
+    pub orders: BTreeMap<OrderId, TrackedOrder>,
+    /// Base quantity passed on of each of our fills
+    pub fills: BTreeMap<FillId, Amount>,
+}
+
+/// Our orders and the fills already accounted, kept in line with the
+/// backend's snapshots.
+pub struct OpenOrders {
+    user_id: UserId,
+    orders: HashMap<OrderId, TrackedOrder>,
+    // Base quantity of each of our fills already passed on, so that
+    // snapshots repeated on reconnect only pass on what is new.
+    fills: HashMap<FillId, Amount>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl OpenOrders {
+    pub fn new(user_id: UserId) -> Self {
+        Self {
+            user_id,
+            orders: HashMap::new(),
+            fills: HashMap::new(),
+            metrics: None,
+        }
+    }
+
+    /// Counts the orders canceled for their age as `ttl_cancels`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn state(&self) -> OrdersState {
+        OrdersState {
+            orders: self
+                .orders
+                .iter()
+                .map(|(id, order)| (*id, order.clone()))
+                .collect(),
+            fills: self.fills.iter().map(|(id, q)| (*id, *q)).collect(),
+        }
+    }
+
+    /// Takes over what a previous run tracked, to be checked against the
+    /// next snapshots of the backend.
+    pub fn resume(&mut self, state: OrdersState) {
+        self.orders = state.orders.into_iter().collect();
+        self.fills = state.fills.into_iter().collect();
+    }
+
+    /// Follows the acks, receipts and status updates of our orders, and our
+    /// fills as they come. The remaining quantity of a `fillstatus` goes to
+    /// the order whose fill went out in the same transaction.
+    pub fn on_operation(&mut self, op: &Operation, now: Timestamp) {
+        match op {
+            Operation::Userorderack(ack) if ack.user_id == self.user_id => {
+                self.update(ack.id, ack.chain_id, &ack.market, ack.order_status.clone());
+                self.fill_details(ack.id, ack.remaining.as_ref(), ack.tx_hash.as_ref());
+                if let Some(order) = self.orders.get_mut(&ack.id) {
+                    order.acked.get_or_insert(now);
+                }
+            }
+            Operation::Orderreceipt(order) if order.user_id == self.user_id => {
+                self.update(
+                    order.id,
+                    order.chain_id,
+                    &order.market,
+                    order.order_status.clone(),
+                );
+                self.fill_details(order.id, order.remaining.as_ref(), order.tx_hash.as_ref());
+            }
+            Operation::Orderstatus(args) => {
+                for update in &args.updates {
+                    if let Some(order) = self.orders.get(&update.order_id) {
+                        let (chain_id, market) = (order.chain_id, order.market.clone());
+                        self.update(update.order_id, chain_id, &market, update.status());
+                        self.fill_details(
+                            update.order_id,
+                            update.detail.remaining(),
+                            update.detail.tx_hash(),
+                        );
+                    }
+                }
+            }
+            Operation::Fillreceipt(fill) => {
+                self.unseen(fill);
+            }
+            Operation::Fillstatus(args) => {
+                for status in &args.statuses {
+                    let order = self
+                        .orders
+                        .values_mut()
+                        .find(|order| status.tx_hash.is_some() && order.tx_hash == status.tx_hash);
+                    if let Some(order) = order {
+                        order.remaining = Some(status.remaining);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Records what an update of a tracked order says of its fills. An
+    /// error in place of the remaining quantity rejects the order.
+    fn fill_details(
+        &mut self,
+        id: OrderId,
+        remaining: Option<&RemainingOrError>,
+        tx_hash: Option<&H256>,
+    ) {
+        if let Some(RemainingOrError::Error(error)) = remaining {
+            if let Some(order) = self.orders.remove(&id) {
+                log::warn!("Order {} on {} rejected: {}", id, order.market, error);
+                self.on_closed(id, &order, &OrderStatus::Rejected);
+            }
+            return;
+        }
+        if let Some(order) = self.orders.get_mut(&id) {
+            order.remaining = remaining
+                .and_then(RemainingOrError::amount)
+                .or(order.remaining);
+            if tx_hash.is_some() {
+                order.tx_hash = tx_hash.cloned();
+            }
+        }
+    }
+
+    /// Records the latest status of an order, forgetting it once closed.
+    fn update(&mut self, id: OrderId, chain_id: ChainId, market: &str, status: OrderStatus) {
+        if is_closed(&status) {
+            if let Some(order) = self.orders.remove(&id) {
+                self.on_closed(id, &order, &status);
+            }
+            return;
+        }
+        let order = self.orders.entry(id).or_insert_with(|| TrackedOrder {
+            chain_id,
+            market: market.to_owned(),
+            status: status.clone(),
+            unknown: false,
+            acked: None,
+            cancel_sent: None,
+            remaining: None,
+            tx_hash: None,
+        });
+        order.status = status;
+        order.unknown = false;
+    }
+
+    /// Only cancels the status confirms count, an order that filled while
+    /// its cancel was in flight was already accounted as a fill.
+    fn on_closed(&self, id: OrderId, order: &TrackedOrder, status: &OrderStatus) {
+        if !order.is_cancel_pending() {
+            return;
+        }
+        match status {
+            OrderStatus::Canceled => {
+                log::info!("Order {} canceled for its age", id);
+                if let Some(metrics) = &self.metrics {
+                    metrics.incr("ttl_cancels");
+                }
+            }
+            status => log::info!("Order {} closed as {:?} before its cancel", id, status),
+        }
+    }
+
+    /// Orders older than the time-to-live of their market without a
+    /// terminal status, marked as cancel-pending. A cancel still
+    /// unconfirmed after another time-to-live is sent again.
+    pub fn expired(
+        &mut self,
+        ttl_secs: impl Fn(&Market) -> Option<u64>,
+        now: Timestamp,
+    ) -> Vec<OrderId> {
+        let mut expired: Vec<_> = self
+            .orders
+            .iter_mut()
+            .filter_map(|(id, order)| {
+                let since = order.cancel_sent.or(order.acked)?;
+                let ttl = ttl_secs(&order.market)?;
+                if now < since.saturating_add(ttl) {
+                    return None;
+                }
+                order.cancel_sent = Some(now);
+                Some(*id)
+            })
+            .collect();
+        expired.sort_unstable();
+        expired
+    }
+
+    pub fn get(&self, id: OrderId) -> Option<&TrackedOrder> {
+        self.orders.get(&id)
+    }
+
+    /// Orders we know to be open.
+    pub fn open(&self) -> impl Iterator<Item = (&OrderId, &TrackedOrder)> {
+        self.orders
+            .iter()
+            .filter(|(_, order)| !order.unknown && order.status.is_open())
+    }
+
+    /// Compares an orders snapshot of `markets` with what we track. Orders
+    /// we thought open on those markets but missing from it become unknown,
+    /// listed ones take the status of the snapshot.
+    pub fn reconcile_orders(
+        &mut self,
+        snapshot: &OrdersArgs,
+        markets: &[Market],
+    ) -> Reconciliation {
+        let mut result = Reconciliation::default();
+        let ours: HashMap<_, _> = snapshot
+            .orders
+            .iter()
+            .filter(|order| order.user_id == self.user_id)
+            .map(|order| (order.id, order))
+            .collect();
+        for (id, order) in &ours {
+            match self.orders.get(id) {
+                Some(tracked) if tracked.status == order.order_status && !tracked.unknown => {}
+                Some(_) => result.changed.push((*id, order.order_status.clone())),
+                None if order.order_status.is_open() => result.adopted.push(*id),
+                None => continue,
+            }
+            self.update(
+                *id,
+                order.chain_id,
+                &order.market,
+                order.order_status.clone(),
+            );
+        }
+        for (id, order) in &mut self.orders {
+            if !ours.contains_key(id)
+                && !order.unknown
+                && order.status.is_open()
+                && markets.contains(&order.market)
+            {
+                order.unknown = true;
+                result.missing.push(*id);
+            }
+        }
+        result.missing.sort_unstable();
+        result.changed.sort_unstable_by_key(|(id, _)| *id);
+        result.adopted.sort_unstable();
+        result
+    }
+
+    /// Our traded fills of a snapshot that were not passed on yet, or only
+    /// partly, each returned once.
+    pub fn unseen_fills(&mut self, snapshot: &FillsArgs) -> Vec<Fill> {
+        snapshot
+            .fills
+            .iter()
+            .filter(|fill| self.unseen(fill))
+            .cloned()
+            .collect()
+    }
+
+    fn unseen(&mut self, fill: &Fill) -> bool {
+        if our_side(fill, &self.user_id).is_none() || !is_traded(fill) {
+            return false;
+        }
+        let seen = self.fills.entry(fill.id).or_default();
+        if fill.base_quantity <= *seen && !seen.is_zero() {
+            return false;
+        }
+        *seen = fill.base_quantity;
+        true
+    }
+
+    /// Cancels of the given orders, sent in case they are still open
+    /// despite the snapshot.
+    pub fn cancels(&self, ids: &[OrderId]) -> Vec<Operation> {
+        ids.iter()
+            .filter_map(|id| {
+                self.orders.get(id).map(|order| {
+                    Operation::Cancelorder(CancelorderArgs {
+                        chain_id: order.chain_id,
+                        order_id: *id,
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Markets an orders snapshot covers. Snapshots do not name their market,
+/// so this is those of the orders listed, or the only subscribed market for
+/// an empty snapshot. An empty snapshot with several subscriptions covers
+/// none, as it cannot be told apart.
+pub fn covered_markets(snapshot: &OrdersArgs, subscribed: &[Market]) -> Vec<Market> {
+    let mut markets: Vec<_> = snapshot
+        .orders
+        .iter()
+        .map(|order| order.market.clone())
+        .collect();
+    markets.sort();
+    markets.dedup();
+    match subscribed {
+        [market] if markets.is_empty() => vec![market.clone()],
+        _ => markets,
+    }
+}
+
+/// Whether an order can no longer trade.
+fn is_closed(status: &OrderStatus) -> bool {
+    matches!(
+        status,
+        OrderStatus::Canceled | OrderStatus::Expired | OrderStatus::Rejected | OrderStatus::Filled
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, Order, OrderUpdate, OrderUpdateDetail, OrderstatusArgs, Side};
+    use rust_decimal_macros::dec;
+
+    fn tracked(orders: &[Order]) -> OpenOrders {
+        let mut open = OpenOrders::new("23".into());
+        for order in orders {
+            open.on_operation(&Operation::Orderreceipt(order.clone()), 0);
+        }
+        open
+    }
+
+    fn snapshot(orders: Vec<Order>) -> OrdersArgs {
+        OrdersArgs { orders }
+    }
+
+    #[test]
+    fn test_missing_orders_unknown() {
+        let mut open = tracked(&[
+            fixtures::order(1, OrderStatus::Open),
+            fixtures::order(2, OrderStatus::Open),
+            Order {
+                market: "WBTC-USDC".into(),
+                ..fixtures::order(3, OrderStatus::Open)
+            },
+        ]);
+        let markets = vec!["ETH-USDC".to_owned()];
+        let result = open.reconcile_orders(
+            &snapshot(vec![fixtures::order(2, OrderStatus::Open)]),
+            &markets,
+        );
+        // Orders of other markets are not covered by the snapshot.
+        assert_eq!(result.missing, vec![1]);
+        assert!(result.changed.is_empty());
+        assert!(open.get(1).expect("order").unknown);
+        assert!(!open.get(3).expect("order").unknown);
+        assert_eq!(open.open().count(), 2);
+        assert!(matches!(
+            open.cancels(&result.missing).as_slice(),
+            [Operation::Cancelorder(CancelorderArgs {
+                chain_id: 1000,
+                order_id: 1
+            })]
+        ));
+
+        // Reported once, until a snapshot lists it again.
+        let result = open.reconcile_orders(&snapshot(Vec::new()), &markets);
+        assert_eq!(result.missing, vec![2]);
+        let result = open.reconcile_orders(
+            &snapshot(vec![fixtures::order(1, OrderStatus::Open)]),
+            &markets,
+        );
+        assert_eq!(result.changed, vec![(1, OrderStatus::Open)]);
+        assert!(!open.get(1).expect("order").unknown);
+    }
+
+    #[test]
+    fn test_changed_status() {
+        let mut open = tracked(&[
+            fixtures::order(1, OrderStatus::Open),
+            fixtures::order(2, OrderStatus::Open),
+        ]);
+        let markets = vec!["ETH-USDC".to_owned()];
+        let result = open.reconcile_orders(
+            &snapshot(vec![
+                fixtures::order(1, OrderStatus::PartialFill),
+                fixtures::order(2, OrderStatus::Canceled),
+                fixtures::order(4, OrderStatus::Open),
+                Order {
+                    user_id: "42".into(),
+                    ..fixtures::order(5, OrderStatus::Open)
+                },
+            ]),
+            &markets,
+        );
+        assert!(result.missing.is_empty());
+        assert_eq!(
+            result.changed,
+            vec![(1, OrderStatus::PartialFill), (2, OrderStatus::Canceled)]
+        );
+        assert_eq!(result.adopted, vec![4]);
+        assert_eq!(open.get(1).expect("order").status, OrderStatus::PartialFill);
+        // Closed orders are forgotten, others' orders ignored.
+        assert!(open.get(2).is_none());
+        assert!(open.get(5).is_none());
+
+        open.on_operation(
+            &Operation::Orderstatus(OrderstatusArgs {
+                updates: vec![OrderUpdate {
+                    chain_id: 1000,
+                    order_id: 4,
+                    detail: OrderUpdateDetail::Filled {
+                        tx_hash: None,
+                        remaining: None,
+                    },
+                }],
+            }),
+            0,
+        );
+        assert!(open.get(4).is_none());
+        let result = open.reconcile_orders(
+            &snapshot(vec![fixtures::order(1, OrderStatus::PartialFill)]),
+            &markets,
+        );
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_unseen_fills_once() {
+        let mut open = OpenOrders::new("23".into());
+        open.on_operation(
+            &Operation::Fillreceipt(fixtures::fill(
+                1,
+                Side::Buy,
+                dec!(3300),
+                dec!(0.5),
+                OrderStatus::Filled,
+            )),
+            0,
+        );
+        let mut other = fixtures::fill(4, Side::Buy, dec!(3300), dec!(1), OrderStatus::Filled);
+        other.taker_user_id = "7".into();
+        let fills = FillsArgs {
+            fills: vec![
+                fixtures::fill(1, Side::Buy, dec!(3300), dec!(0.5), OrderStatus::Filled),
+                fixtures::fill(
+                    2,
+                    Side::Buy,
+                    dec!(3300),
+                    dec!(0.25),
+                    OrderStatus::PartialFill,
+                ),
+                fixtures::fill(3, Side::Buy, dec!(3300), dec!(1), OrderStatus::Rejected),
+                other,
+            ],
+        };
+        let ids = |fills: Vec<Fill>| fills.iter().map(|fill| fill.id).collect::<Vec<_>>();
+        assert_eq!(ids(open.unseen_fills(&fills)), vec![2]);
+        // A snapshot sent again after a reconnect.
+        assert!(open.unseen_fills(&fills).is_empty());
+        // Only more of a partial fill gets through.
+        let fills = FillsArgs {
+            fills: vec![fixtures::fill(
+                2,
+                Side::Buy,
+                dec!(3300),
+                dec!(0.5),
+                OrderStatus::Filled,
+            )],
+        };
+        assert_eq!(ids(open.unseen_fills(&fills)), vec![2]);
+    }
+
+    fn ack(id: OrderId, market: &str, status: &str) -> Operation {
+        serde_json::from_str(&format!(
+            r#"{{"op":"userorderack","args":[1000,{},"{}","s","3300",0.5,1650,1666262459,"23","{}",null,0.5]}}"#,
+            id, market, status
+        ))
+        .expect("from_str")
+    }
+
+    fn status(order_id: OrderId, detail: OrderUpdateDetail) -> Operation {
+        Operation::Orderstatus(OrderstatusArgs {
+            updates: vec![OrderUpdate {
+                chain_id: 1000,
+                order_id,
+                detail,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_ttl_cancel_races_fill() {
+        let metrics = Arc::new(Metrics::new());
+        let mut open = OpenOrders::new("23".into()).with_metrics(metrics.clone());
+        let ttl = |market: &Market| (market == "ETH-USDC").then_some(60);
+        open.on_operation(&ack(1, "ETH-USDC", "o"), 1000);
+        open.on_operation(&ack(2, "ETH-USDC", "b"), 1010);
+        open.on_operation(&ack(3, "WBTC-USDC", "o"), 1000);
+        // Receipts do not restart the clock.
+        open.on_operation(
+            &Operation::Orderreceipt(fixtures::order(1, OrderStatus::Open)),
+            1050,
+        );
+        assert!(open.expired(ttl, 1059).is_empty());
+        assert_eq!(open.expired(ttl, 1060), vec![1]);
+        assert!(open.get(1).expect("order").is_cancel_pending());
+        assert_eq!(open.expired(ttl, 1070), vec![2]);
+        assert!(open.expired(ttl, 1100).is_empty());
+
+        // Order 1 fills while its cancel is in flight, whose status then
+        // comes late.
+        let filled = OrderUpdateDetail::Filled {
+            tx_hash: None,
+            remaining: None,
+        };
+        open.on_operation(&status(1, filled), 1101);
+        open.on_operation(&status(1, OrderUpdateDetail::Canceled), 1102);
+        assert!(open.get(1).is_none());
+        assert_eq!(metrics.counter("ttl_cancels"), 0);
+
+        // Order 2 partly fills, stays pending and gets its cancel again.
+        let partial = OrderUpdateDetail::PartialFill {
+            tx_hash: None,
+            remaining: None,
+        };
+        open.on_operation(&status(2, partial), 1103);
+        assert!(open.get(2).expect("order").is_cancel_pending());
+        assert!(open.expired(ttl, 1129).is_empty());
+        assert_eq!(open.expired(ttl, 1130), vec![2]);
+        open.on_operation(&status(2, OrderUpdateDetail::Canceled), 1131);
+        assert!(open.get(2).is_none());
+        assert_eq!(metrics.counter("ttl_cancels"), 1);
+        assert!(open.expired(ttl, 5000).is_empty());
+    }
+
+    #[test]
+    fn test_remaining() {
+        const TX_HASH: &str = "0x600a2d8a66d8a23c7d9d7b2a5e91e4f2b9a6c3d2e1f0a9b8c7d6e5f4a3b21ed9";
+        let parse = |s: String| serde_json::from_str::<Operation>(&s).expect("from_str");
+        let mut open = OpenOrders::new("23".into());
+        open.on_operation(&ack(1, "ETH-USDC", "o"), 1000);
+        open.on_operation(&ack(2, "ETH-USDC", "o"), 1000);
+        assert_eq!(open.get(1).expect("order").remaining, Some(dec!(0.5)));
+
+        let matched = format!(
+            r#"{{"op":"orderstatus","args":[[[1000,1,"pf","{}",0.3]]]}}"#,
+            TX_HASH
+        );
+        open.on_operation(&parse(matched), 1001);
+        assert_eq!(open.get(1).expect("order").remaining, Some(dec!(0.3)));
+        // Settling, the fill names the order by its transaction.
+        let settled = format!(
+            r#"{{"op":"fillstatus","args":[[[1000,7,"f","{}",0.2,0.01,"USDC",1700000000]]]}}"#,
+            TX_HASH
+        );
+        open.on_operation(&parse(settled), 1002);
+        assert_eq!(open.get(1).expect("order").remaining, Some(dec!(0.2)));
+        assert_eq!(open.get(2).expect("order").remaining, Some(dec!(0.5)));
+        let unnamed = r#"{"op":"fillstatus","args":[[[1000,8,"f",null,0,0,"USDC",1700000000]]]}"#;
+        open.on_operation(&parse(unnamed.to_owned()), 1003);
+        assert_eq!(open.get(2).expect("order").remaining, Some(dec!(0.5)));
+
+        // An error in place of the remaining quantity is no quantity, the
+        // order is rejected.
+        let failed = r#"{"op":"orderstatus","args":[[[1000,2,"pf",null,"Not enough balance"]]]}"#;
+        open.on_operation(&parse(failed.to_owned()), 1004);
+        assert!(open.get(2).is_none());
+    }
+
+    #[test]
+    fn test_covered_markets() {
+        let eth = "ETH-USDC".to_owned();
+        let btc = "WBTC-USDC".to_owned();
+        let listed = snapshot(vec![Order {
+            market: btc.clone(),
+            user_id: "42".into(),
+            ..fixtures::order(1, OrderStatus::Open)
+        }]);
+        assert_eq!(
+            covered_markets(&listed, &[eth.clone(), btc.clone()]),
+            vec![btc.clone()]
+        );
+        assert_eq!(
+            covered_markets(&snapshot(Vec::new()), std::slice::from_ref(&eth)),
+            vec![eth.clone()]
+        );
+        assert!(covered_markets(&snapshot(Vec::new()), &[eth, btc]).is_empty());
+    }
+}
@@ -0,0 +1,518 @@
+/// Reload of the config file on SIGHUP. Settings the running bot can take
+/// on the fly are applied: market maker settings, risk limits, notifiers
+/// and the reference age of the feeds. Any other change only takes effect
+/// after a restart and is reported as such.
+use crate::config::{Config, MarketMakerSettings};
+use crate::feeds::FeedsConfig;
+use std::fmt;
+
+/// A setting applied on the fly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// Path of the setting, as in `markets.ETH-USDC.spread_bps`
+    pub key: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl Change {
+    fn new<T: fmt::Debug>(key: String, old: &T, new: &T) -> Self {
+        Self {
+            key,
+            old: format!("{:?}", old),
+            new: format!("{:?}", new),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.key, self.old, self.new)
+    }
+}
+
+/// What a reload changed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reload {
+    pub applied: Vec<Change>,
+    /// Settings that changed in the file but wait for a restart
+    pub needs_restart: Vec<String>,
+}
+
+impl Reload {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.needs_restart.is_empty()
+    }
+
+    /// Whether a setting under `prefix` was applied.
+    pub fn applied_under(&self, prefix: &str) -> bool {
+        self.applied
+            .iter()
+            .any(|change| change.key.starts_with(prefix))
+    }
+
+    pub fn log(&self) {
+        if self.is_empty() {
+            log::info!("Config reloaded, nothing changed");
+        }
+        for change in &self.applied {
+            log::info!("Config reloaded, {}", change);
+        }
+        for key in &self.needs_restart {
+            log::warn!("Config reloaded, {} changed but needs a restart", key);
+        }
+    }
+}
+
+/// Takes the `fields` of `reloaded` that differ into `running`, recording
+/// them under `prefix`.
+macro_rules! apply_fields {
+    ($reload:expr, $prefix:expr, $running:expr, $reloaded:expr, $($field:ident),+ $(,)?) => {
+        $(
+            if $running.$field != $reloaded.$field {
+                $reload.applied.push(Change::new(
+                    format!("{}.{}", $prefix, stringify!($field)),
+                    &$running.$field,
+                    &$reloaded.$field,
+                ));
+                $running.$field = $reloaded.$field.clone();
+            }
+        )+
+    };
+}
+
+/// Records the `fields` of `reloaded` that differ as waiting for a restart.
+macro_rules! restart_fields {
+    ($reload:expr, $prefix:expr, $running:expr, $reloaded:expr, $($field:ident),+ $(,)?) => {
+        $(
+            if $running.$field != $reloaded.$field {
+                $reload.needs_restart.push(match $prefix {
+                    "" => stringify!($field).to_owned(),
+                    prefix => format!("{}.{}", prefix, stringify!($field)),
+                });
+            }
+        )+
+    };
+}
+
+/// Applies the settings of `reloaded` that can change on the fly to
+/// `running`, keeping the others, and returns what changed.
+pub fn apply(running: &mut Config, reloaded: &Config) -> Reload {
+    // Every key is either applied or reported, adding one fails here
+    // until it is sorted.
+    let Config {
+        network: _,
+        key_source: _,
+        derivation_index: _,
+        wallets: _,
+        provider_url: _,
+        fee_tokens: _,
+        zigzag_url: _,
+        zigzag_chain_id: _,
+        chain_id_check: _,
+        exchange_address: _,
+        proxy: _,
+        reconnect_min_delay_ms: _,
+        reconnect_max_delay_ms: _,
+        ping_interval_secs: _,
+        pong_timeout_secs: _,
+        cancel_on_exit: _,
+        wait_for_activation: _,
+        activation_timeout_secs: _,
+        cancel_missing_orders: _,
+        ttl_cancel_all: _,
+        latency_log_secs: _,
+        valuation_currency: _,
+        valuation_log_secs: _,
+        db_path: _,
+        snapshot_dir: _,
+        snapshot_secs: _,
+        unsettled_fill_secs: _,
+        markets: _,
+        market_maker: _,
+        market_overrides: _,
+        feeds: _,
+        risk: _,
+        risk_override: _,
+        strict_protocol: _,
+        kill_switch: _,
+        stops: _,
+        triangles: _,
+        hedge: _,
+        rpc: _,
+        rate_limit: _,
+        dedup: _,
+        clock: _,
+        balances: _,
+        auto_deposit: _,
+        notify: _,
+    } = reloaded;
+    let mut reload = Reload::default();
+    restart_fields!(
+        reload,
+        "",
+        running,
+        reloaded,
+        network,
+        key_source,
+        derivation_index,
+        wallets,
+        provider_url,
+        fee_tokens,
+        zigzag_url,
+        zigzag_chain_id,
+        chain_id_check,
+        exchange_address,
+        proxy,
+        reconnect_min_delay_ms,
+        reconnect_max_delay_ms,
+        ping_interval_secs,
+        pong_timeout_secs,
+        cancel_on_exit,
+        wait_for_activation,
+        activation_timeout_secs,
+        cancel_missing_orders,
+        ttl_cancel_all,
+        latency_log_secs,
+        valuation_currency,
+        valuation_log_secs,
+        db_path,
+        snapshot_dir,
+        snapshot_secs,
+        unsettled_fill_secs,
+        markets,
+        risk_override,
+        strict_protocol,
+        kill_switch,
+        stops,
+        triangles,
+        hedge,
+        rpc,
+        rate_limit,
+        dedup,
+        clock,
+        balances,
+        auto_deposit,
+    );
+    apply_market_makers(running, reloaded, &mut reload);
+    apply_feeds(&mut running.feeds, &reloaded.feeds, &mut reload);
+    // Without limits at startup no engine checks the orders.
+    if running.risk.is_empty() && !reloaded.risk.is_empty() {
+        reload.needs_restart.push("risk".into());
+    } else {
+        apply_fields!(
+            reload,
+            "risk",
+            running.risk,
+            reloaded.risk,
+            max_position,
+            max_order_size,
+            max_open_notional,
+        );
+    }
+    apply_fields!(
+        reload,
+        "notify",
+        running.notify,
+        reloaded.notify,
+        min_interval_secs,
+        telegram,
+        discord,
+    );
+    // The connection watcher is started with it.
+    restart_fields!(
+        reload,
+        "notify",
+        running.notify,
+        reloaded.notify,
+        disconnected_secs
+    );
+    reload
+}
+
+/// Settings of the running markets, reported per market.
+fn apply_market_makers(running: &mut Config, reloaded: &Config, reload: &mut Reload) {
+    let defaults = merge_settings(
+        "market_maker",
+        &running.market_maker,
+        &reloaded.market_maker,
+        &mut Reload::default(),
+    );
+    let mut overrides = std::collections::HashMap::new();
+    for market in &running.markets {
+        let settings = merge_settings(
+            &format!("markets.{}", market),
+            running.market_settings(market),
+            reloaded.market_settings(market),
+            reload,
+        );
+        if settings != defaults {
+            overrides.insert(market.clone(), settings);
+        }
+    }
+    running.market_maker = defaults;
+    running.market_overrides = overrides;
+}
+
+fn merge_settings(
+    prefix: &str,
+    running: &MarketMakerSettings,
+    reloaded: &MarketMakerSettings,
+    reload: &mut Reload,
+) -> MarketMakerSettings {
+    let MarketMakerSettings {
+        spread_bps: _,
+        quote_size: _,
+        quote_expires_secs: _,
+        requote_threshold_bps: _,
+        requote_margin_secs: _,
+        clock_skew_secs: _,
+        rfq_markup_bps: _,
+        rfq_max_size: _,
+        max_position: _,
+        price_skew_bps: _,
+        size_skew: _,
+        fees: _,
+        ladder: _,
+        volatility: _,
+        strategy: _,
+        avellaneda: _,
+        fill_cooldown: _,
+        feed: _,
+        order_ttl_secs: _,
+        quote_wallet: _,
+        take_wallet: _,
+    } = reloaded;
+    let mut merged = running.clone();
+    apply_fields!(
+        reload,
+        prefix,
+        merged,
+        reloaded,
+        spread_bps,
+        quote_size,
+        quote_expires_secs,
+        requote_threshold_bps,
+        requote_margin_secs,
+        clock_skew_secs,
+        rfq_markup_bps,
+        rfq_max_size,
+        max_position,
+        price_skew_bps,
+        size_skew,
+        ladder,
+        avellaneda,
+        fill_cooldown,
+    );
+    // Fees and volatility are estimated once for every market, the rest
+    // picks what runs where.
+    restart_fields!(
+        reload,
+        prefix,
+        merged,
+        reloaded,
+        fees,
+        volatility,
+        strategy,
+        feed,
+        order_ttl_secs,
+        quote_wallet,
+        take_wallet,
+    );
+    merged
+}
+
+/// Only the age of the references can change, the feeds keep their
+/// connections and subscriptions.
+fn apply_feeds(running: &mut FeedsConfig, reloaded: &FeedsConfig, reload: &mut Reload) {
+    if let (Some(running), Some(reloaded)) = (&mut running.binance, &reloaded.binance) {
+        apply_fields!(reload, "feeds.binance", running, reloaded, max_age_secs);
+    }
+    if let (Some(running), Some(reloaded)) = (&mut running.chainlink, &reloaded.chainlink) {
+        apply_fields!(reload, "feeds.chainlink", running, reloaded, max_age_secs);
+    }
+    if let (Some(running), Some(reloaded)) = (&mut running.coingecko, &reloaded.coingecko) {
+        apply_fields!(reload, "feeds.coingecko", running, reloaded, max_age_secs);
+    }
+    if *running != *reloaded {
+        reload.needs_restart.push("feeds".into());
+    }
+}
+
+/// SIGHUP, which asks for a reload. The default signal never comes, nor
+/// does it on other platforms.
+#[derive(Default)]
+pub struct ReloadSignal {
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl ReloadSignal {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            hangup: Some(tokio::signal::unix::signal(
+                tokio::signal::unix::SignalKind::hangup(),
+            )?),
+        })
+    }
+
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(hangup) = &mut self.hangup {
+            if hangup.recv().await.is_some() {
+                return;
+            }
+        }
+        futures::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Args;
+    use crate::config::ConfigFile;
+    use clap::Parser;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn config(text: &str) -> Config {
+        let args = Args::parse_from(["zigzag-bots"]);
+        let file = ConfigFile::parse(text).expect("parse");
+        Config::resolve(&args, no_env, file).expect("resolve")
+    }
+
+    const RUNNING: &str = r#"
+        [defaults]
+        spread_bps = 30
+        order_ttl_secs = 300
+
+        [markets.ETH-USDC]
+        spread_bps = 15
+
+        [markets.WBTC-USDC]
+
+        [feeds.binance]
+        max_age_secs = 10
+        symbols = { "ETH-USDC" = "ethusdc" }
+
+        [risk]
+        max_position = 5
+
+        [notify]
+        min_interval_secs = 30
+    "#;
+
+    #[test]
+    fn test_apply() {
+        let mut running = config(RUNNING);
+        let reloaded = config(
+            r#"
+            [defaults]
+            spread_bps = 40
+            order_ttl_secs = 300
+
+            [markets.ETH-USDC]
+            spread_bps = 15
+            quote_size = 2
+
+            [markets.WBTC-USDC]
+
+            [feeds.binance]
+            max_age_secs = 20
+            symbols = { "ETH-USDC" = "ethusdc" }
+
+            [risk]
+            max_position = 3
+
+            [notify]
+            min_interval_secs = 60
+            "#,
+        );
+        let reload = apply(&mut running, &reloaded);
+        let applied: Vec<_> = reload.applied.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            applied,
+            [
+                "markets.ETH-USDC.quote_size: 0.1 -> 2",
+                "markets.WBTC-USDC.spread_bps: 30 -> 40",
+                "feeds.binance.max_age_secs: 10 -> 20",
+                "risk.max_position: Some(5) -> Some(3)",
+                "notify.min_interval_secs: 30 -> 60",
+            ]
+        );
+        assert!(reload.needs_restart.is_empty());
+        assert_eq!(running, reloaded);
+        assert!(reload.applied_under("markets.ETH-USDC."));
+        assert!(!reload.applied_under("notify.telegram"));
+
+        // Reloading the same file again changes nothing.
+        assert!(apply(&mut running, &reloaded).is_empty());
+    }
+
+    #[test]
+    fn test_restart_reported() {
+        let mut running = config(RUNNING);
+        let before = running.clone();
+        let reloaded = config(
+            r#"
+            network = "goerli"
+            private_key = "0x0101010101010101010101010101010101010101010101010101010101010101"
+
+            [defaults]
+            spread_bps = 30
+            order_ttl_secs = 60
+            strategy = "avellaneda"
+
+            [markets.ETH-USDC]
+            spread_bps = 15
+
+            [markets.WBTC-USDC]
+
+            [markets.LINK-USDC]
+
+            [feeds.binance]
+            max_age_secs = 10
+            symbols = { "ETH-USDC" = "ethusdc", "WBTC-USDC" = "wbtcusdc" }
+
+            [risk]
+            max_position = 5
+
+            [notify]
+            disconnected_secs = 10
+            "#,
+        );
+        let reload = apply(&mut running, &reloaded);
+        assert!(reload.applied.is_empty());
+        assert_eq!(
+            reload.needs_restart,
+            [
+                "network",
+                "key_source",
+                "zigzag_chain_id",
+                "markets",
+                "markets.ETH-USDC.strategy",
+                "markets.ETH-USDC.order_ttl_secs",
+                "markets.WBTC-USDC.strategy",
+                "markets.WBTC-USDC.order_ttl_secs",
+                "feeds",
+                "notify.disconnected_secs",
+            ]
+        );
+        // Nothing was taken over.
+        assert_eq!(running, before);
+
+        // Limits only apply with the engine started with some.
+        let mut running = config("");
+        let reload = apply(&mut running, &config("[risk]\nmax_order_size = 1"));
+        assert_eq!(reload.needs_restart, ["risk"]);
+        assert_eq!(running.risk.max_order_size, None);
+        assert_eq!(
+            apply(&mut config(RUNNING), &config(RUNNING)),
+            Reload::default()
+        );
+    }
+}
@@ -0,0 +1,554 @@
+/// Line-based prompt for trading by hand, run by the `repl` subcommand.
+/// Commands go through the dispatcher handle and the order builder, like
+/// the bot's own, and updates are shown as they arrive.
+use crate::balances::BalanceSource;
+use crate::client::DEFAULT_REQUEST_TIMEOUT;
+use crate::clock::SyncedClock;
+use crate::dispatcher::{DispatcherHandle, Receivers};
+use crate::display;
+use crate::orderbook::Snapshot;
+use crate::orders::{build_order, to_units, OrderSigner};
+use crate::reconcile::OpenOrders;
+use crate::zigzag::{
+    unix_timestamp, Amount, CancelorderArgs, ChainId, Decimal, Fill, FillsArgs, Market, MarketInfo,
+    MarketPair, MarketinfoArgs, Operation, OrderId, Price, Side, SubscribemarketArgs, Timestamp,
+    UnsubscribemarketArgs, UserId,
+};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use zksync::zksync_types::TokenId;
+
+pub const USAGE: &str = "\
+Commands:
+  sub MARKET                    subscribe to a market, e.g. sub ETH-USDT
+  unsub MARKET                  unsubscribe from it
+  book MARKET                   show its order book
+  buy MARKET QUANTITY @ PRICE   submit a limit order, e.g. buy ETH-USDT 0.5 @ 1850
+  sell MARKET QUANTITY @ PRICE
+  cancel ORDER_ID               cancel one of our orders
+  cancelall                     cancel all our open orders
+  orders                        list our open orders
+  fills                         list our fills of the session
+  balance                       committed balances of the subscribed tokens
+  help                          show this
+  quit";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Sub(Market),
+    Unsub(Market),
+    Book(Market),
+    Order {
+        side: Side,
+        market: Market,
+        base_quantity: Amount,
+        price: Decimal,
+    },
+    Cancel(OrderId),
+    CancelAll,
+    Orders,
+    Fills,
+    Balance,
+    Help,
+    Quit,
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> anyhow::Result<Self> {
+        // "0.5@1850" is as good as "0.5 @ 1850".
+        let line = line.replace('@', " @ ");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let market = |word: &str| word.parse::<MarketPair>().map(Market::from);
+        let decimal = |word: &str| {
+            Decimal::from_str(word).map_err(|e| anyhow::anyhow!("Invalid number {}: {}", word, e))
+        };
+        let command = match words.as_slice() {
+            ["sub", m] => Action::Sub(market(m)?),
+            ["unsub", m] => Action::Unsub(market(m)?),
+            ["book", m] => Action::Book(market(m)?),
+            [side @ ("buy" | "sell"), m, quantity, "@", price] => Action::Order {
+                side: side.parse()?,
+                market: market(m)?,
+                base_quantity: decimal(quantity)?,
+                price: decimal(price)?,
+            },
+            ["cancel", id] => Action::Cancel(
+                id.parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid order id {}: {}", id, e))?,
+            ),
+            ["cancelall"] => Action::CancelAll,
+            ["orders"] => Action::Orders,
+            ["fills"] => Action::Fills,
+            ["balance"] => Action::Balance,
+            ["help"] => Action::Help,
+            ["quit" | "exit"] => Action::Quit,
+            _ => return Err(anyhow::anyhow!("Invalid command {:?}", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+/// Left-aligned columns, each as wide as its widest cell.
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_owned()
+    };
+    let mut lines = vec![line(headers.to_vec())];
+    lines.extend(
+        rows.iter()
+            .map(|row| line(row.iter().map(String::as_str).collect())),
+    );
+    lines.join("\n")
+}
+
+/// Rustyline editor on a thread of its own, reading a line per prompt.
+struct LineReader {
+    prompts: std::sync::mpsc::Sender<String>,
+    lines: mpsc::UnboundedReceiver<String>,
+}
+
+impl LineReader {
+    fn spawn() -> Self {
+        let (prompt_tx, prompt_rx) = std::sync::mpsc::channel::<String>();
+        let (line_tx, line_rx) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let mut editor = match Editor::<()>::new() {
+                Ok(editor) => editor,
+                Err(e) => {
+                    log::error!("Opening the terminal: {}", e);
+                    return;
+                }
+            };
+            for prompt in prompt_rx {
+                let line = match editor.readline(&prompt) {
+                    Ok(line) => {
+                        editor.add_history_entry(line.as_str());
+                        line
+                    }
+                    // Ctrl-C drops the line, Ctrl-D ends the session.
+                    Err(ReadlineError::Interrupted) => String::new(),
+                    Err(_) => return,
+                };
+                if line_tx.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            prompts: prompt_tx,
+            lines: line_rx,
+        }
+    }
+
+    fn prompt(&self, prompt: &str) {
+        let _ = self.prompts.send(prompt.to_owned());
+    }
+
+    /// Next line, `None` at the end of the input.
+    async fn next(&mut self) -> Option<String> {
+        self.lines.recv().await
+    }
+}
+
+pub struct Repl<O> {
+    chain_id: ChainId,
+    user_id: UserId,
+    handle: DispatcherHandle,
+    signer: Arc<O>,
+    balances: Arc<dyn BalanceSource>,
+    /// Send without asking first
+    yes: bool,
+    order_expires_secs: u64,
+    clock: SyncedClock,
+    infos: HashMap<Market, MarketInfo>,
+    books: HashMap<Market, Snapshot>,
+    orders: OpenOrders,
+    fills: Vec<Fill>,
+}
+
+impl<O: OrderSigner> Repl<O> {
+    pub fn new(
+        chain_id: ChainId,
+        user_id: UserId,
+        handle: DispatcherHandle,
+        signer: Arc<O>,
+        balances: Arc<dyn BalanceSource>,
+    ) -> Self {
+        Self {
+            chain_id,
+            orders: OpenOrders::new(user_id.clone()),
+            user_id,
+            handle,
+            signer,
+            balances,
+            yes: false,
+            order_expires_secs: 3600,
+            clock: SyncedClock::default(),
+            infos: HashMap::new(),
+            books: HashMap::new(),
+            fills: Vec::new(),
+        }
+    }
+
+    /// Sends orders and cancels without echoing them for confirmation.
+    pub fn with_yes(mut self, yes: bool) -> Self {
+        self.yes = yes;
+        self
+    }
+
+    pub fn with_order_expires_secs(mut self, secs: u64) -> Self {
+        self.order_expires_secs = secs;
+        self
+    }
+
+    /// Sets order expiries by the backend's time as estimated by `clock`,
+    /// which learns from the fills shown.
+    pub fn with_clock(mut self, clock: SyncedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Prompts for commands until `quit` or the end of the input.
+    pub async fn run(mut self, mut receivers: Receivers) -> anyhow::Result<()> {
+        let mut lines = LineReader::spawn();
+        println!("{}", USAGE);
+        loop {
+            lines.prompt("zigzag> ");
+            let line = loop {
+                tokio::select! {
+                    line = lines.next() => break line,
+                    Some(op) = receivers.market_data.recv() => self.on_operation(op, unix_timestamp()),
+                    Some(op) = receivers.orders.recv() => {
+                        self.clock.on_operation(&op);
+                        self.on_operation(op, unix_timestamp());
+                    }
+                    Some(op) = receivers.other.recv() => self.on_operation(op, unix_timestamp()),
+                    Some(e) = receivers.errors.recv() => {
+                        self.signer.on_rejected(&e.error);
+                        println!("{}", e);
+                    }
+                }
+            };
+            let line = match line {
+                Some(line) => line,
+                None => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse::<Action>() {
+                Ok(Action::Quit) => break,
+                Ok(command) => {
+                    if let Err(e) = self.execute(command, &mut lines).await {
+                        println!("Error: {}", e);
+                    }
+                }
+                Err(e) => println!("{}\n\n{}", e, USAGE),
+            }
+        }
+        Ok(())
+    }
+
+    fn on_operation(&mut self, op: Operation, now: Timestamp) {
+        match op {
+            Operation::Liquidity2(args) => {
+                self.books
+                    .insert(args.market.clone(), Snapshot::new(&args.liquidity, now));
+            }
+            Operation::Marketinfo(MarketinfoArgs { market_info }) => {
+                self.infos.insert(market_info.alias.clone(), market_info);
+            }
+            Operation::Fills(args) => {
+                let fills = self.orders.unseen_fills(&args);
+                self.fills.extend(fills);
+            }
+            Operation::Fillreceipt(fill) => {
+                let fills = self.orders.unseen_fills(&FillsArgs { fills: vec![fill] });
+                for fill in fills {
+                    println!(
+                        "Filled {} {} {} @ {}",
+                        fill.side,
+                        fill.base_quantity,
+                        fill.market,
+                        show_price(&fill.price)
+                    );
+                    self.fills.push(fill);
+                }
+            }
+            Operation::Orders(args) => {
+                self.orders.reconcile_orders(&args, &[]);
+            }
+            op @ (Operation::Userorderack(_)
+            | Operation::Orderreceipt(_)
+            | Operation::Orderstatus(_)
+            | Operation::Cancelorderack(_)) => {
+                self.orders.on_operation(&op, now);
+                let update = display::order_update(&op, |market| {
+                    self.infos
+                        .get(market)
+                        .map(|info| info.price_precision_decimal)
+                });
+                log::info!("Order update: {}", update);
+            }
+            op => log::debug!("Received from zigzag: {:?}", op),
+        }
+    }
+
+    async fn execute(&mut self, command: Action, lines: &mut LineReader) -> anyhow::Result<()> {
+        match command {
+            Action::Sub(market) => {
+                self.handle
+                    .send(Operation::Subscribemarket(SubscribemarketArgs {
+                        chain_id: self.chain_id,
+                        market,
+                    }))?;
+            }
+            Action::Unsub(market) => {
+                self.books.remove(&market);
+                self.handle
+                    .send(Operation::Unsubscribemarket(UnsubscribemarketArgs {
+                        chain_id: self.chain_id,
+                        market,
+                    }))?;
+            }
+            Action::Book(market) => {
+                let book = self.books.get(&market).ok_or_else(|| {
+                    anyhow::anyhow!("No liquidity seen on {} yet, sub to it first", market)
+                })?;
+                println!("{}", book_table(book));
+            }
+            Action::Order {
+                side,
+                market,
+                base_quantity,
+                price,
+            } => {
+                let info = self.infos.get(&market).ok_or_else(|| {
+                    anyhow::anyhow!("No market info for {} yet, sub to it first", market)
+                })?;
+                let expires = self.clock.now() + self.order_expires_secs;
+                let action = format!(
+                    "Submitting {} {} {} @ {}, expiring in {}s",
+                    side, base_quantity, market, price, self.order_expires_secs
+                );
+                if !self.confirm(&action, lines).await {
+                    return Ok(());
+                }
+                let order =
+                    build_order(&*self.signer, info, side, price, base_quantity, expires).await?;
+                let ack = self
+                    .handle
+                    .submit_order(info, order, DEFAULT_REQUEST_TIMEOUT)
+                    .await?;
+                self.orders
+                    .on_operation(&Operation::Userorderack(ack.clone()), unix_timestamp());
+                println!("Order {} {}", ack.id, ack.order_status);
+            }
+            Action::Cancel(order_id) => {
+                if !self
+                    .confirm(&format!("Canceling order {}", order_id), lines)
+                    .await
+                {
+                    return Ok(());
+                }
+                self.handle.send(Operation::Cancelorder(CancelorderArgs {
+                    chain_id: self.chain_id,
+                    order_id,
+                }))?;
+            }
+            Action::CancelAll => {
+                if !self.confirm("Canceling all open orders", lines).await {
+                    return Ok(());
+                }
+                self.handle
+                    .cancel_all(self.chain_id, self.user_id.clone(), DEFAULT_REQUEST_TIMEOUT)
+                    .await?;
+                println!("Canceled all open orders");
+            }
+            Action::Orders => {
+                let rows: Vec<_> = self
+                    .orders
+                    .open()
+                    .map(|(id, order)| {
+                        vec![
+                            id.to_string(),
+                            order.market.clone(),
+                            order.status.to_string(),
+                        ]
+                    })
+                    .collect();
+                println!("{}", table(&["id", "market", "status"], &rows));
+            }
+            Action::Fills => {
+                let rows: Vec<_> = self
+                    .fills
+                    .iter()
+                    .map(|fill| {
+                        vec![
+                            fill.id.to_string(),
+                            fill.market.clone(),
+                            fill.side.to_string(),
+                            fill.base_quantity.to_string(),
+                            show_price(&fill.price),
+                            fill.fill_status.to_string(),
+                        ]
+                    })
+                    .collect();
+                println!(
+                    "{}",
+                    table(
+                        &["id", "market", "side", "quantity", "price", "status"],
+                        &rows
+                    )
+                );
+            }
+            Action::Balance => {
+                let mut assets: Vec<_> = self
+                    .infos
+                    .values()
+                    .flat_map(|info| [&info.base_asset, &info.quote_asset])
+                    .collect();
+                assets.sort_by_key(|asset| asset.id);
+                assets.dedup_by_key(|asset| asset.id);
+                let mut rows = Vec::new();
+                for asset in assets {
+                    let raw = self.balances.committed_balance(TokenId(asset.id)).await?;
+                    rows.push(vec![
+                        asset.symbol.clone(),
+                        to_units(&raw, asset.decimals)?.to_string(),
+                    ]);
+                }
+                println!("{}", table(&["token", "committed"], &rows));
+            }
+            Action::Help => println!("{}", USAGE),
+            Action::Quit => {}
+        }
+        Ok(())
+    }
+
+    /// Echoes what is about to be sent and asks to go ahead, unless the
+    /// session runs with `--yes`.
+    async fn confirm(&self, action: &str, lines: &mut LineReader) -> bool {
+        println!("{}", action);
+        if self.yes {
+            return true;
+        }
+        lines.prompt("Proceed? [y/N] ");
+        let proceed = matches!(
+            lines.next().await.as_deref().map(str::trim),
+            Some("y" | "Y" | "yes")
+        );
+        if !proceed {
+            println!("Aborted");
+        }
+        proceed
+    }
+}
+
+fn show_price(price: &Price) -> String {
+    match price.value() {
+        Ok(price) => price.to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Asks above bids, best prices next to each other.
+fn book_table(book: &Snapshot) -> String {
+    let rows: Vec<_> = book
+        .asks
+        .iter()
+        .rev()
+        .map(|level| ("ask", level))
+        .chain(book.bids.iter().map(|level| ("bid", level)))
+        .map(|(side, level)| {
+            vec![
+                side.to_owned(),
+                level.price.to_string(),
+                level.base_quantity.to_string(),
+            ]
+        })
+        .collect();
+    table(&["side", "price", "quantity"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "buy eth-usdt 0.5 @ 1850".parse::<Action>().expect("parse"),
+            Action::Order {
+                side: Side::Buy,
+                market: "ETH-USDT".into(),
+                base_quantity: dec!(0.5),
+                price: dec!(1850),
+            }
+        );
+        assert_eq!(
+            "  sell ETH-USDT 1@1900 ".parse::<Action>().expect("parse"),
+            Action::Order {
+                side: Side::Sell,
+                market: "ETH-USDT".into(),
+                base_quantity: dec!(1),
+                price: dec!(1900),
+            }
+        );
+        assert_eq!(
+            "cancel 12345".parse::<Action>().expect("parse"),
+            Action::Cancel(12345)
+        );
+        assert_eq!(
+            "sub WBTC-USDT".parse::<Action>().expect("parse"),
+            Action::Sub("WBTC-USDT".into())
+        );
+        for line in [
+            "buy ETH-USDT 0.5 1850",
+            "buy ETHUSDT 0.5 @ 1850",
+            "sell ETH-USDT half @ 1850",
+            "cancel x",
+            "cancelall now",
+            "frobnicate",
+        ] {
+            assert!(line.parse::<Action>().is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_table() {
+        let rows = vec![
+            vec!["12".to_owned(), "ETH-USDT".to_owned(), "open".to_owned()],
+            vec![
+                "3".to_owned(),
+                "WBTC-USDT".to_owned(),
+                "partial_fill".to_owned(),
+            ],
+        ];
+        assert_eq!(
+            table(&["id", "market", "status"], &rows),
+            "id  market     status\n\
+             12  ETH-USDT   open\n\
+             3   WBTC-USDT  partial_fill"
+        );
+        assert_eq!(table(&["id"], &[]), "id");
+    }
+}
@@ -0,0 +1,337 @@
+/// ZigZag RFQ: answering `requestquote` messages with a `quote` priced off
+/// the reference mid, and requesting quotes as a taker.
+use crate::zigzag::{approx_eq, Amount, Decimal, MarketInfo, QuoteArgs, RequestquoteArgs, Side};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RfqConfig {
+    /// Markup over the reference mid, in basis points
+    pub markup_bps: Decimal,
+    /// Largest base quantity quoted, larger requests are declined
+    pub max_base_quantity: Amount,
+}
+
+pub struct RfqMaker {
+    config: RfqConfig,
+}
+
+impl RfqMaker {
+    pub fn new(config: RfqConfig) -> Self {
+        Self { config }
+    }
+
+    /// Prices a quote request against `mid`. The request side is the
+    /// taker's, so takers buying pay the markup and takers selling receive
+    /// it less. Prices and quantities are rounded the same way as our
+    /// orders on the market. Returns `None` when the request is declined.
+    pub fn quote(
+        &self,
+        request: &RequestquoteArgs,
+        mid: Decimal,
+        market_info: &MarketInfo,
+    ) -> Option<QuoteArgs> {
+        if mid <= Decimal::ZERO {
+            return None;
+        }
+        let markup = mid * self.config.markup_bps / Decimal::from(10_000);
+        // We trade the other side of the taker.
+        let (price, side) = match request.side {
+            Side::Buy => (mid + markup, Side::Sell),
+            Side::Sell => (mid - markup, Side::Buy),
+        };
+        let price = market_info.round_price(price, &side);
+        if price <= Decimal::ZERO {
+            return None;
+        }
+        let base_quantity = match (
+            specified(request.base_quantity),
+            specified(request.quote_quantity),
+        ) {
+            (Some(base), _) => base,
+            (None, Some(quote)) => quote / price,
+            (None, None) => {
+                log::warn!("Ignoring quote request without a quantity: {:?}", request);
+                return None;
+            }
+        };
+        let base_quantity = match market_info.round_quantity(base_quantity) {
+            Ok(base_quantity) => base_quantity,
+            Err(e) => {
+                log::info!("Declining quote request on {}: {}", request.market, e);
+                return None;
+            }
+        };
+        // A quote specified request keeps its quote, which `answers`
+        // matches on, others are priced on the rounded base.
+        let quote_quantity = match (
+            specified(request.base_quantity),
+            specified(request.quote_quantity),
+        ) {
+            (None, Some(quote)) => quote,
+            _ => base_quantity * price,
+        };
+        if base_quantity > self.config.max_base_quantity {
+            log::info!(
+                "Declining quote request on {} for {}, above max size {}",
+                request.market,
+                base_quantity,
+                self.config.max_base_quantity
+            );
+            return None;
+        }
+        log::info!(
+            "Quoting RFQ on {}: taker {:?} {} @ {} (mid {}, edge {} bps, {} quote)",
+            request.market,
+            request.side,
+            base_quantity,
+            price,
+            mid,
+            self.config.markup_bps,
+            base_quantity * markup
+        );
+        Some(QuoteArgs {
+            chain_id: request.chain_id,
+            market: request.market.clone(),
+            side: request.side.clone(),
+            base_quantity,
+            price: price.into(),
+            quote_quantity,
+        })
+    }
+}
+
+/// The backend marks the quantity it wants us to fill in with -1 or null.
+fn specified(quantity: Option<Amount>) -> Option<Amount> {
+    quantity.filter(|q| *q > Decimal::ZERO)
+}
+
+/// Whether `quote` answers `request`. Quotes carry no request id, so they
+/// are matched on market, side and the quantity that was asked for.
+pub fn answers(request: &RequestquoteArgs, quote: &QuoteArgs) -> bool {
+    quote.market == request.market
+        && quote.side == request.side
+        && match (
+            specified(request.base_quantity),
+            specified(request.quote_quantity),
+        ) {
+            (Some(base), _) => approx_eq(quote.base_quantity, base),
+            (None, Some(quote_quantity)) => approx_eq(quote.quote_quantity, quote_quantity),
+            (None, None) => false,
+        }
+}
+
+/// Whether a taker trading `side` at `price` pays at most
+/// `max_slippage_bps` over `last_price`. Without a positive last price there
+/// is nothing to measure against, so no price is within slippage.
+pub fn within_slippage(
+    side: &Side,
+    price: Decimal,
+    last_price: Decimal,
+    max_slippage_bps: Decimal,
+) -> bool {
+    if last_price <= Decimal::ZERO {
+        return false;
+    }
+    let slippage = match side {
+        Side::Buy => price - last_price,
+        Side::Sell => last_price - price,
+    };
+    let slippage_bps = slippage / last_price * Decimal::from(10_000);
+    slippage_bps <= max_slippage_bps
+}
+
+/// Failures of a quote request that scripts may want to tell apart, see
+/// `exit_code`.
+#[derive(Debug, PartialEq)]
+pub enum QuoteError {
+    Timeout(Duration),
+    Rejected(String),
+}
+
+impl QuoteError {
+    /// Process exit code, distinct from the 1 of other errors and the 2 of
+    /// invalid arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            QuoteError::Timeout(_) => 3,
+            QuoteError::Rejected(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for QuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuoteError::Timeout(timeout) => write!(f, "No quote received within {:?}", timeout),
+            QuoteError::Rejected(reason) => write!(f, "Not executing quote: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for QuoteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, Operation};
+    use rust_decimal_macros::dec;
+
+    fn maker() -> RfqMaker {
+        RfqMaker::new(RfqConfig {
+            markup_bps: dec!(10),
+            max_base_quantity: dec!(2),
+        })
+    }
+
+    fn request(json: &str) -> RequestquoteArgs {
+        match serde_json::from_str(json).expect("from_str") {
+            Operation::Requestquote(args) => args,
+            op => panic!("Invalid op type: {:?}", op),
+        }
+    }
+
+    #[test]
+    fn test_buy_request() {
+        let quote = maker()
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","b",1.5,-1]}"#),
+                dec!(2000),
+                &fixtures::market_info("ETH-USDC", 0, 2),
+            )
+            .expect("quote");
+        assert_eq!(quote.side, Side::Buy);
+        assert_eq!(quote.price.value().expect("price"), dec!(2002));
+        assert_eq!(quote.base_quantity, dec!(1.5));
+        assert_eq!(quote.quote_quantity, dec!(3003));
+    }
+
+    #[test]
+    fn test_quote_quantity_of_rounded_base() {
+        let mut market_info = fixtures::market_info("ETH-USDC", 0, 2);
+        market_info.base_asset.decimals = 2;
+        let quote = maker()
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","b",1.555,-1]}"#),
+                dec!(2000),
+                &market_info,
+            )
+            .expect("quote");
+        assert_eq!(quote.base_quantity, dec!(1.55));
+        assert_eq!(quote.quote_quantity, dec!(3103.1));
+    }
+
+    #[test]
+    fn test_sell_request_by_quote_quantity() {
+        let quote = maker()
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","s",null,998]}"#),
+                dec!(1000),
+                &fixtures::market_info("ETH-USDC", 0, 2),
+            )
+            .expect("quote");
+        assert_eq!(quote.market, "ETH-USDC");
+        assert_eq!(quote.price.value().expect("price"), dec!(999));
+        assert!(approx_eq(quote.base_quantity, dec!(998) / dec!(999)));
+        assert_eq!(quote.quote_quantity, dec!(998));
+        assert_eq!(
+            serde_json::to_value(Operation::Quote(quote)).expect("to_value")["args"][1],
+            "ETH-USDC"
+        );
+    }
+
+    #[test]
+    fn test_taker_matching_and_slippage() {
+        let request = request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","b",1.5]}"#);
+        let mut quote = maker()
+            .quote(
+                &request,
+                dec!(2000),
+                &fixtures::market_info("ETH-USDC", 0, 2),
+            )
+            .expect("quote");
+        assert!(answers(&request, &quote));
+        quote.base_quantity = dec!(1.4);
+        assert!(!answers(&request, &quote));
+        quote.base_quantity = dec!(1.5);
+        quote.side = Side::Sell;
+        assert!(!answers(&request, &quote));
+
+        // Buying 10 bps above the last price, selling 10 bps below it.
+        assert!(within_slippage(
+            &Side::Buy,
+            dec!(2002),
+            dec!(2000),
+            dec!(10)
+        ));
+        assert!(!within_slippage(
+            &Side::Buy,
+            dec!(2002),
+            dec!(2000),
+            dec!(5)
+        ));
+        assert!(within_slippage(
+            &Side::Sell,
+            dec!(1998),
+            dec!(2000),
+            dec!(10)
+        ));
+        assert!(!within_slippage(
+            &Side::Sell,
+            dec!(1998),
+            dec!(2000),
+            dec!(5)
+        ));
+        assert!(within_slippage(
+            &Side::Sell,
+            dec!(2010),
+            dec!(2000),
+            dec!(0)
+        ));
+
+        assert_ne!(
+            QuoteError::Timeout(Duration::from_secs(1)).exit_code(),
+            QuoteError::Rejected("".into()).exit_code()
+        );
+    }
+
+    #[test]
+    fn test_slippage_without_last_price() {
+        assert!(!within_slippage(&Side::Buy, dec!(2002), dec!(0), dec!(10)));
+        assert!(!within_slippage(&Side::Sell, dec!(1998), dec!(0), dec!(10)));
+    }
+
+    #[test]
+    fn test_declined_requests() {
+        let maker = maker();
+        // Above the size cap, whichever side the size is given in.
+        assert!(maker
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","b",2.5]}"#),
+                dec!(2000),
+                &fixtures::market_info("ETH-USDC", 0, 2)
+            )
+            .is_none());
+        assert!(maker
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","s",-1,5000]}"#),
+                dec!(2000),
+                &fixtures::market_info("ETH-USDC", 0, 2)
+            )
+            .is_none());
+        assert!(maker
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","s",-1,-1]}"#),
+                dec!(2000),
+                &fixtures::market_info("ETH-USDC", 0, 2)
+            )
+            .is_none());
+        assert!(maker
+            .quote(
+                &request(r#"{"op":"requestquote","args":[1000,"ETH-USDC","b",2.0]}"#),
+                dec!(0),
+                &fixtures::market_info("ETH-USDC", 0, 2)
+            )
+            .is_none());
+    }
+}
@@ -0,0 +1,703 @@
+/// Hard risk limits checked by the dispatcher on every outgoing order,
+/// liquidity indication and RFQ quote. The engine follows our fills, open
+/// orders and advertised liquidity from the operations the dispatcher
+/// receives and sends.
+use crate::logging;
+use crate::metrics::Metrics;
+use crate::orders::{OrderParams, OrderTerms};
+use crate::portfolio::FillTracker;
+use crate::zigzag::{
+    Amount, Decimal, Fill, Indicateliq2Args, Market, MarketInfo, Operation, OrderId,
+    PriceParseError, QuoteArgs, RemainingOrError, Side, Submitorder3Args, UserId,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// `[risk]` table of the config file. Unset limits are not enforced.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskLimits {
+    /// Largest absolute position of a market, in base units
+    pub max_position: Option<Amount>,
+    /// Largest base quantity of an order, liquidity level or quote
+    pub max_order_size: Option<Amount>,
+    /// Largest quote notional of open orders and advertised liquidity,
+    /// across markets
+    pub max_open_notional: Option<Decimal>,
+}
+
+impl RiskLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_position.is_none()
+            && self.max_order_size.is_none()
+            && self.max_open_notional.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    MaxPosition,
+    MaxOrderSize,
+    MaxOpenNotional,
+    /// Orders of markets without market info cannot be checked
+    UnknownMarket,
+}
+
+impl Limit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Limit::MaxPosition => "max_position",
+            Limit::MaxOrderSize => "max_order_size",
+            Limit::MaxOpenNotional => "max_open_notional",
+            Limit::UnknownMarket => "unknown_market",
+        }
+    }
+}
+
+struct State {
+    markets: HashMap<Market, MarketInfo>,
+    fills: FillTracker,
+    /// Quote notional of the liquidity advertised on each market
+    liquidity: HashMap<Market, Decimal>,
+    /// Quote notional of each of our open orders
+    orders: HashMap<OrderId, Decimal>,
+}
+
+impl State {
+    fn position(&self, market: &str) -> Amount {
+        self.fills.position(market)
+    }
+
+    fn open_notional(&self) -> Decimal {
+        self.liquidity.values().sum::<Decimal>() + self.orders.values().sum::<Decimal>()
+    }
+}
+
+pub struct RiskEngine {
+    /// Replaced by `set_limits` when the config is reloaded
+    limits: Mutex<RiskLimits>,
+    user_id: UserId,
+    override_blocks: bool,
+    metrics: Arc<Metrics>,
+    state: Mutex<State>,
+}
+
+impl RiskEngine {
+    pub fn new(limits: RiskLimits, user_id: UserId, metrics: Arc<Metrics>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                markets: HashMap::new(),
+                fills: FillTracker::new(user_id.clone()),
+                liquidity: HashMap::new(),
+                orders: HashMap::new(),
+            }),
+            limits: Mutex::new(limits),
+            user_id,
+            override_blocks: false,
+            metrics,
+        }
+    }
+
+    /// Only warns about breaches instead of blocking, for testing.
+    pub fn with_override(mut self, override_blocks: bool) -> Self {
+        self.override_blocks = override_blocks;
+        self
+    }
+
+    /// Enforces `limits` from now on, the state followed so far is kept.
+    pub fn set_limits(&self, limits: RiskLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    fn limits(&self) -> RiskLimits {
+        self.limits.lock().unwrap().clone()
+    }
+
+    pub fn position(&self, market: &str) -> Amount {
+        self.state.lock().unwrap().position(market)
+    }
+
+    /// Rebuilds positions from stored fills.
+    pub fn restore(&self, fills: &[Fill]) {
+        self.state.lock().unwrap().fills.restore(fills);
+    }
+
+    pub fn open_notional(&self) -> Decimal {
+        self.state.lock().unwrap().open_notional()
+    }
+
+    /// Follows market infos, our fills and the state of our orders.
+    pub fn on_incoming(&self, op: &Operation) {
+        let mut state = self.state.lock().unwrap();
+        state.fills.on_operation(op);
+        match op {
+            Operation::Marketinfo(args) => {
+                let info = &args.market_info;
+                state.markets.insert(info.alias.clone(), info.clone());
+            }
+            Operation::Marketinfo2(args) => {
+                for info in &args.market_infos {
+                    state.markets.insert(info.alias.clone(), info.clone());
+                }
+            }
+            // An error in place of the remaining quantity rejects the order.
+            Operation::Userorderack(ack)
+                if ack.user_id == self.user_id
+                    && ack.order_status.is_open()
+                    && !matches!(ack.remaining, Some(RemainingOrError::Error(_))) =>
+            {
+                let remaining = ack
+                    .remaining
+                    .as_ref()
+                    .and_then(RemainingOrError::amount)
+                    .unwrap_or(ack.base_quantity);
+                match ack.price.value() {
+                    Ok(price) => {
+                        state.orders.insert(ack.id, remaining * price);
+                    }
+                    Err(e) => logging::with_order(ack.id, || {
+                        log::warn!("Not tracking order {}: {}", ack.id, e)
+                    }),
+                }
+            }
+            Operation::Orderstatus(args) => {
+                for update in &args.updates {
+                    if !update.status().is_open() {
+                        state.orders.remove(&update.order_id);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the operation to send, `None` when it is blocked. Liquidity
+    /// levels breaching a limit are dropped from the indication, so that
+    /// only the offending side stops being quoted.
+    pub fn check(&self, op: Operation) -> Option<Operation> {
+        match op {
+            Operation::Submitorder3(args) => self
+                .check_order(&args)
+                .then_some(Operation::Submitorder3(args)),
+            Operation::Indicateliq2(args) => {
+                self.check_liquidity(args).map(Operation::Indicateliq2)
+            }
+            Operation::Quote(args) => self.check_quote(&args).then_some(Operation::Quote(args)),
+            op => Some(op),
+        }
+    }
+
+    /// Reports a breach. Returns whether the message must be blocked.
+    fn breach(&self, limit: Limit, detail: String) -> bool {
+        self.metrics.incr(&format!("risk_blocked_{}", limit.name()));
+        if self.override_blocks {
+            log::warn!(
+                "Risk limit {} breached, overridden: {}",
+                limit.name(),
+                detail
+            );
+            false
+        } else {
+            log::warn!("Risk limit {} breached, blocking: {}", limit.name(), detail);
+            true
+        }
+    }
+
+    /// Checks the size of a trade of ours and the position it would leave.
+    fn check_trade(
+        &self,
+        state: &State,
+        market: &str,
+        side: &Side,
+        base_quantity: Amount,
+    ) -> Option<(Limit, String)> {
+        self.check_size(market, side, base_quantity).or_else(|| {
+            let change = match side {
+                Side::Buy => base_quantity,
+                Side::Sell => -base_quantity,
+            };
+            self.check_position(
+                state,
+                market,
+                change,
+                format!("{:?} {}", side, base_quantity),
+            )
+        })
+    }
+
+    fn check_size(
+        &self,
+        market: &str,
+        side: &Side,
+        base_quantity: Amount,
+    ) -> Option<(Limit, String)> {
+        let max = self.limits().max_order_size?;
+        (base_quantity > max).then(|| {
+            (
+                Limit::MaxOrderSize,
+                format!("{:?} {} on {} above {}", side, base_quantity, market, max),
+            )
+        })
+    }
+
+    /// Checks the position left by `what`, changing it by `change`.
+    fn check_position(
+        &self,
+        state: &State,
+        market: &str,
+        change: Amount,
+        what: String,
+    ) -> Option<(Limit, String)> {
+        let max = self.limits().max_position?;
+        let position = state.position(market);
+        let after = position + change;
+        // Trades reducing a position over the limit stay allowed.
+        (after.abs() > max && after.abs() > position.abs()).then(|| {
+            (
+                Limit::MaxPosition,
+                format!(
+                    "{} on {} takes the position from {} to {}, above {}",
+                    what, market, position, after, max
+                ),
+            )
+        })
+    }
+
+    fn check_notional(&self, open: Decimal, added: Decimal) -> Option<(Limit, String)> {
+        let max = self.limits().max_open_notional?;
+        (open + added > max).then(|| {
+            (
+                Limit::MaxOpenNotional,
+                format!("{} open plus {} above {}", open, added, max),
+            )
+        })
+    }
+
+    fn check_order(&self, args: &Submitorder3Args) -> bool {
+        let state = self.state.lock().unwrap();
+        let breach = match order_terms(&state, args) {
+            Ok(terms) => self
+                .check_trade(&state, &args.market, &terms.side, terms.base_quantity)
+                .or_else(|| {
+                    self.check_notional(state.open_notional(), terms.base_quantity * terms.price)
+                }),
+            Err(e) => Some((
+                Limit::UnknownMarket,
+                format!("order on {}: {}", args.market, e),
+            )),
+        };
+        match breach {
+            Some((limit, detail)) => !self.breach(limit, detail),
+            None => true,
+        }
+    }
+
+    fn check_liquidity(&self, mut args: Indicateliq2Args) -> Option<Indicateliq2Args> {
+        let mut state = self.state.lock().unwrap();
+        let market = args.market.clone();
+        let notional = self.check_levels(&state, &mut args)?;
+        // The indication replaces what was advertised on the market.
+        let open =
+            state.open_notional() - state.liquidity.get(&market).copied().unwrap_or_default();
+        if let Some((limit, detail)) = self.check_notional(open, notional) {
+            if self.breach(limit, format!("liquidity on {}: {}", market, detail)) {
+                return None;
+            }
+        }
+        state.liquidity.insert(market, notional);
+        Some(args)
+    }
+
+    /// Drops the liquidity levels breaching a limit on their own. Returns
+    /// the quote notional of the others, `None` when a price is invalid.
+    fn check_levels(&self, state: &State, args: &mut Indicateliq2Args) -> Option<Decimal> {
+        let market = args.market.clone();
+        args.liquidity.retain(|level| {
+            match self.check_trade(state, &market, &level.side, level.base_quantity) {
+                Some((limit, detail)) => !self.breach(limit, detail),
+                None => true,
+            }
+        });
+        let notional = args
+            .liquidity
+            .iter()
+            .map(|level| Ok(level.base_quantity * level.price.value()?))
+            .sum::<Result<Decimal, PriceParseError>>();
+        match notional {
+            Ok(notional) => Some(notional),
+            Err(e) => {
+                log::warn!("Blocking liquidity on {}: {}", market, e);
+                None
+            }
+        }
+    }
+
+    /// Checks a batch as a unit: the position left by all its orders and
+    /// quotes on each market, and the notional of all its orders, quotes and
+    /// liquidity, a `cancelall` ahead of them freeing that of our open
+    /// orders. Sizes are checked one by one, and liquidity levels breaching
+    /// a limit dropped as with `check`. Returns the operations to send, `None` when the batch
+    /// is blocked.
+    pub fn check_batch(&self, ops: Vec<Operation>) -> Option<Vec<Operation>> {
+        let mut state = self.state.lock().unwrap();
+        let mut orders = state.orders.values().sum::<Decimal>();
+        let mut added = Decimal::ZERO;
+        let mut liquidity = HashMap::new();
+        let mut changes: HashMap<Market, Amount> = HashMap::new();
+        let mut checked = Vec::with_capacity(ops.len());
+        // Adds up a trade of ours, returns whether its size is allowed.
+        let mut trade = |market: &Market, terms: OrderTerms| {
+            let change = match terms.side {
+                Side::Buy => terms.base_quantity,
+                Side::Sell => -terms.base_quantity,
+            };
+            *changes.entry(market.clone()).or_default() += change;
+            added += terms.base_quantity * terms.price;
+            match self.check_size(market, &terms.side, terms.base_quantity) {
+                Some((limit, detail)) => !self.breach(limit, format!("batch: {}", detail)),
+                None => true,
+            }
+        };
+        for op in ops {
+            let allowed = match &op {
+                Operation::Cancelall(_) => {
+                    orders = Decimal::ZERO;
+                    true
+                }
+                Operation::Submitorder3(args) => match order_terms(&state, args) {
+                    Ok(terms) => trade(&args.market, terms),
+                    Err(e) => !self.breach(
+                        Limit::UnknownMarket,
+                        format!("batch: order on {}: {}", args.market, e),
+                    ),
+                },
+                // The quote side is the taker's, we trade the other way.
+                Operation::Quote(args) => match args.price.value() {
+                    Ok(price) => trade(
+                        &args.market,
+                        OrderTerms {
+                            side: args.side.opposite(),
+                            price,
+                            base_quantity: args.base_quantity,
+                        },
+                    ),
+                    Err(e) => {
+                        log::warn!("Blocking batch, quote: {}", e);
+                        false
+                    }
+                },
+                _ => true,
+            };
+            if !allowed {
+                return None;
+            }
+            match op {
+                Operation::Indicateliq2(mut args) => {
+                    let notional = self.check_levels(&state, &mut args)?;
+                    liquidity.insert(args.market.clone(), notional);
+                    checked.push(Operation::Indicateliq2(args));
+                }
+                op => checked.push(op),
+            }
+        }
+        for (market, change) in &changes {
+            let what = format!("Batch of {} net", change);
+            if let Some((limit, detail)) = self.check_position(&state, market, *change, what) {
+                if self.breach(limit, detail) {
+                    return None;
+                }
+            }
+        }
+        // Liquidity of the batch replaces what was advertised on its market.
+        let advertised = state
+            .liquidity
+            .iter()
+            .filter(|(market, _)| !liquidity.contains_key(*market))
+            .map(|(_, notional)| *notional)
+            .sum::<Decimal>();
+        let added = added + liquidity.values().sum::<Decimal>();
+        if added > Decimal::ZERO {
+            if let Some((limit, detail)) = self.check_notional(orders + advertised, added) {
+                if self.breach(limit, format!("batch: {}", detail)) {
+                    return None;
+                }
+            }
+        }
+        state.liquidity.extend(liquidity);
+        Some(checked)
+    }
+
+    fn check_quote(&self, args: &QuoteArgs) -> bool {
+        let state = self.state.lock().unwrap();
+        // The quote side is the taker's, we trade the other way.
+        let side = args.side.opposite();
+        let notional = match args.price.value() {
+            Ok(price) => args.base_quantity * price,
+            Err(e) => {
+                log::warn!("Blocking quote: {}", e);
+                return false;
+            }
+        };
+        let breach = self
+            .check_trade(&state, &args.market, &side, args.base_quantity)
+            .or_else(|| self.check_notional(state.open_notional(), notional));
+        match breach {
+            Some((limit, detail)) => !self.breach(limit, format!("quote: {}", detail)),
+            None => true,
+        }
+    }
+}
+
+/// Terms of a zksync order of a market the engine has the info of.
+fn order_terms(state: &State, args: &Submitorder3Args) -> anyhow::Result<OrderTerms> {
+    match (state.markets.get(&args.market), args.zk_order.zksync()) {
+        (Some(info), Some(order)) => OrderParams::from_order(order).terms(info),
+        (None, _) => Err(anyhow::anyhow!("no market info")),
+        (_, None) => Err(anyhow::anyhow!("not a zksync order")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, Liquidity, MarketinfoArgs, OrderStatus};
+    use rust_decimal_macros::dec;
+
+    fn engine(limits: RiskLimits) -> (RiskEngine, Arc<Metrics>) {
+        let metrics = Arc::new(Metrics::new());
+        let engine = RiskEngine::new(limits, "23".into(), metrics.clone());
+        engine.on_incoming(&Operation::Marketinfo(MarketinfoArgs {
+            market_info: fixtures::market_info("ETH-USDC", 0, 2),
+        }));
+        (engine, metrics)
+    }
+
+    fn quotes(bid: Amount, ask: Amount) -> Operation {
+        let level = |side, price: Decimal, base_quantity| Liquidity {
+            side,
+            price: price.into(),
+            base_quantity,
+            expires: None,
+        };
+        Operation::Indicateliq2(Indicateliq2Args {
+            chain_id: 1000,
+            market: "ETH-USDC".into(),
+            liquidity: vec![
+                level(Side::Buy, dec!(1999), bid),
+                level(Side::Sell, dec!(2001), ask),
+            ],
+        })
+    }
+
+    /// Quote of ours to a taker on the `side`, at 2000.
+    fn quote(side: Side, base_quantity: Amount) -> Operation {
+        Operation::Quote(QuoteArgs {
+            chain_id: 1000,
+            market: "ETH-USDC".into(),
+            side,
+            base_quantity,
+            price: dec!(2000).into(),
+            quote_quantity: base_quantity * dec!(2000),
+        })
+    }
+
+    fn sides(op: Option<Operation>) -> Vec<Side> {
+        match op {
+            Some(Operation::Indicateliq2(args)) => {
+                args.liquidity.into_iter().map(|l| l.side).collect()
+            }
+            op => panic!("Unexpected {:?}", op),
+        }
+    }
+
+    #[test]
+    fn test_position_limit_suppresses_side() {
+        let receipt = |id, side, quantity| {
+            let fill = fixtures::fill(id, side, dec!(2000), quantity, OrderStatus::Filled);
+            Operation::Fillreceipt(fill)
+        };
+        let (engine, metrics) = engine(RiskLimits {
+            max_position: Some(dec!(1)),
+            ..Default::default()
+        });
+        assert_eq!(
+            sides(engine.check(quotes(dec!(0.5), dec!(0.5)))),
+            vec![Side::Buy, Side::Sell]
+        );
+
+        // We take buys up to the limit.
+        engine.on_incoming(&receipt(1, Side::Buy, dec!(0.5)));
+        assert_eq!(engine.position("ETH-USDC"), dec!(0.5));
+        assert_eq!(
+            sides(engine.check(quotes(dec!(0.5), dec!(0.5)))),
+            vec![Side::Buy, Side::Sell]
+        );
+        engine.on_incoming(&receipt(2, Side::Buy, dec!(0.5)));
+        assert_eq!(
+            sides(engine.check(quotes(dec!(0.5), dec!(0.5)))),
+            vec![Side::Sell]
+        );
+        assert_eq!(metrics.counter("risk_blocked_max_position"), 1);
+
+        // Selling down reopens the bid.
+        engine.on_incoming(&receipt(3, Side::Sell, dec!(0.3)));
+        assert_eq!(
+            sides(engine.check(quotes(dec!(0.3), dec!(0.5)))),
+            vec![Side::Buy, Side::Sell]
+        );
+
+        let quote = |side: Side| {
+            Operation::Quote(QuoteArgs {
+                chain_id: 1000,
+                market: "ETH-USDC".into(),
+                side,
+                base_quantity: dec!(0.5),
+                price: dec!(2000).into(),
+                quote_quantity: dec!(1000),
+            })
+        };
+        // Takers selling to us would push the position to 1.2.
+        assert!(engine.check(quote(Side::Sell)).is_none());
+        assert!(engine.check(quote(Side::Buy)).is_some());
+        assert_eq!(metrics.counter("risk_blocked_max_position"), 2);
+    }
+
+    #[test]
+    fn test_size_and_notional_limits() {
+        let (engine, metrics) = engine(RiskLimits {
+            max_order_size: Some(dec!(1)),
+            max_open_notional: Some(dec!(3000)),
+            ..Default::default()
+        });
+        assert_eq!(
+            sides(engine.check(quotes(dec!(2), dec!(0.5)))),
+            vec![Side::Sell]
+        );
+        assert_eq!(metrics.counter("risk_blocked_max_order_size"), 1);
+        assert_eq!(engine.open_notional(), dec!(1000.5));
+
+        // Requoting replaces the advertised notional rather than adding up.
+        assert!(engine.check(quotes(dec!(0.7), dec!(0.7))).is_some());
+        assert_eq!(engine.open_notional(), dec!(2800));
+        assert!(engine.check(quotes(dec!(0.8), dec!(0.8))).is_none());
+        assert_eq!(metrics.counter("risk_blocked_max_open_notional"), 1);
+
+        // Open orders count until they are done.
+        let ack = r#"{"op":"userorderack","args":[1000,40,"ETH-USDC","s",2000,0.1,200,4294967295,"23","o",null,0.1]}"#;
+        engine.on_incoming(&serde_json::from_str(ack).expect("from_str"));
+        assert_eq!(engine.open_notional(), dec!(3000));
+        let status = r#"{"op":"orderstatus","args":[[[1000,40,"c"]]]}"#;
+        engine.on_incoming(&serde_json::from_str(status).expect("from_str"));
+        assert_eq!(engine.open_notional(), dec!(2800));
+
+        // Other operations go through untouched.
+        let op = r#"{"op":"subscribemarket","args":[1000,"ETH-USDC"]}"#;
+        assert!(matches!(
+            engine.check(serde_json::from_str(op).expect("from_str")),
+            Some(Operation::Subscribemarket(_))
+        ));
+    }
+
+    #[test]
+    fn test_batch_as_a_unit() {
+        let (engine, metrics) = engine(RiskLimits {
+            max_position: Some(dec!(1)),
+            max_order_size: Some(dec!(1)),
+            max_open_notional: Some(dec!(5000)),
+        });
+        // Each fits, together they would take the position over the limit.
+        assert!(engine
+            .check_batch(vec![
+                quote(Side::Sell, dec!(0.6)),
+                quote(Side::Sell, dec!(0.6))
+            ])
+            .is_none());
+        assert_eq!(metrics.counter("risk_blocked_max_position"), 1);
+        // The other way in the same batch nets them back under it.
+        let batch = engine
+            .check_batch(vec![
+                quote(Side::Sell, dec!(0.6)),
+                quote(Side::Sell, dec!(0.6)),
+                quote(Side::Buy, dec!(0.3)),
+            ])
+            .expect("batch");
+        assert_eq!(batch.len(), 3);
+
+        // Notionals add up across quotes and liquidity, whatever the side.
+        assert!(engine
+            .check_batch(vec![
+                quote(Side::Sell, dec!(0.6)),
+                quote(Side::Buy, dec!(0.6)),
+                quotes(dec!(0.7), dec!(0.7)),
+            ])
+            .is_none());
+        assert_eq!(metrics.counter("risk_blocked_max_open_notional"), 1);
+        assert_eq!(engine.open_notional(), Decimal::ZERO);
+        // Oversized levels are dropped on their own.
+        let batch = engine
+            .check_batch(vec![
+                quote(Side::Sell, dec!(0.6)),
+                quotes(dec!(2), dec!(0.5)),
+            ])
+            .expect("batch");
+        assert_eq!(sides(batch.into_iter().nth(1)), vec![Side::Sell]);
+        assert_eq!(metrics.counter("risk_blocked_max_order_size"), 1);
+        assert_eq!(engine.open_notional(), dec!(1000.5));
+
+        // A cancelall ahead frees the notional of our open orders.
+        let ack = r#"{"op":"userorderack","args":[1000,41,"ETH-USDC","b",2000,1,2000,4294967295,"23","o",null,1]}"#;
+        engine.on_incoming(&serde_json::from_str(ack).expect("from_str"));
+        assert_eq!(engine.open_notional(), dec!(3000.5));
+        assert!(engine
+            .check_batch(vec![quote(Side::Sell, dec!(1))])
+            .is_none());
+        assert_eq!(metrics.counter("risk_blocked_max_open_notional"), 2);
+        let cancel = r#"{"op":"cancelall","args":[1000,"23"]}"#;
+        let batch = engine
+            .check_batch(vec![
+                serde_json::from_str(cancel).expect("from_str"),
+                quote(Side::Sell, dec!(1)),
+            ])
+            .expect("batch");
+        assert!(matches!(
+            batch.as_slice(),
+            [Operation::Cancelall(_), Operation::Quote(_)]
+        ));
+    }
+
+    #[test]
+    fn test_override() {
+        let metrics = Arc::new(Metrics::new());
+        let limits = RiskLimits {
+            max_order_size: Some(dec!(1)),
+            ..Default::default()
+        };
+        let engine = RiskEngine::new(limits, "23".into(), metrics.clone()).with_override(true);
+        assert_eq!(
+            sides(engine.check(quotes(dec!(2), dec!(2)))),
+            vec![Side::Buy, Side::Sell]
+        );
+        assert_eq!(metrics.counter("risk_blocked_max_order_size"), 2);
+    }
+
+    #[test]
+    fn test_set_limits() {
+        let (engine, _) = engine(RiskLimits {
+            max_order_size: Some(dec!(1)),
+            ..Default::default()
+        });
+        assert_eq!(
+            sides(engine.check(quotes(dec!(2), dec!(0.5)))),
+            vec![Side::Sell]
+        );
+        engine.set_limits(RiskLimits {
+            max_order_size: Some(dec!(3)),
+            ..Default::default()
+        });
+        assert_eq!(
+            sides(engine.check(quotes(dec!(2), dec!(0.5)))),
+            vec![Side::Buy, Side::Sell]
+        );
+    }
+}
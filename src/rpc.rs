@@ -0,0 +1,531 @@
+/// Retries and failover of the zksync RPC reads: balance polls, fee
+/// lookups, token refreshes and nonces. Each call gets a timeout, transport
+/// errors are retried with exponential backoff, and an endpoint failing
+/// repeatedly is marked unhealthy for a while, calls rotating to the next
+/// one of the config. Deterministic errors, such as an unknown account,
+/// are returned as is.
+use crate::activation::AccountSource;
+use crate::balances::BalanceSource;
+use crate::connection::Backoff;
+use crate::fees::FeeSource;
+use crate::metrics::Metrics;
+use crate::orders::{NonceSigner, OrderParams};
+use crate::tokens::{TokenInfo, TokenSource};
+use crate::zigzag::{Decimal, ZksyncOrder};
+use async_trait::async_trait;
+use futures::Future;
+use num::BigUint;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use zksync::zksync_types::{AccountId, Nonce, TokenId};
+
+/// `[rpc]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// zksync API endpoints, in order of preference. The network's default
+    /// one when empty
+    pub urls: Vec<String>,
+    pub timeout_ms: u64,
+    /// Retries of a call failing with a transport error
+    pub retries: u32,
+    pub min_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Consecutive failures after which an endpoint is unhealthy
+    pub unhealthy_after: u32,
+    /// How long an unhealthy endpoint is skipped
+    pub unhealthy_secs: u64,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            timeout_ms: 10_000,
+            retries: 3,
+            min_backoff_ms: 200,
+            max_backoff_ms: 5_000,
+            unhealthy_after: 3,
+            unhealthy_secs: 60,
+        }
+    }
+}
+
+impl RpcConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.timeout_ms == 0 {
+            return Err(anyhow::anyhow!("rpc.timeout_ms must be at least 1!"));
+        }
+        if self.unhealthy_after == 0 {
+            return Err(anyhow::anyhow!("rpc.unhealthy_after must be at least 1!"));
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    fn backoff(&self) -> Backoff {
+        Backoff::new(
+            Duration::from_millis(self.min_backoff_ms),
+            Duration::from_millis(self.max_backoff_ms),
+        )
+    }
+}
+
+/// Name of the endpoint at `url` in metrics and health checks: its host.
+pub fn endpoint_name(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?']).next().unwrap_or(rest);
+    host.to_owned()
+}
+
+/// A call that got no answer in time.
+#[derive(Debug)]
+pub struct Timeout(pub Duration);
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RPC call timed out after {}ms", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Messages of the zksync client errors worth another try: the request
+/// did not make it, or the answer did not.
+const TRANSIENT: &[&str] = &[
+    "network error",
+    "operation timeout",
+    "unable to decode server response",
+    "timed out",
+    "connection",
+    "too many requests",
+    "502",
+    "503",
+    "504",
+];
+
+/// Whether `error` is a transport error, worth retrying, rather than one
+/// the same call would get again.
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<Timeout>() {
+            return true;
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout() || e.is_connect() || e.is_request();
+        }
+        let message = cause.to_string().to_lowercase();
+        TRANSIENT.iter().any(|marker| message.contains(marker))
+    })
+}
+
+/// State of an endpoint in `/healthz`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct EndpointHealth {
+    pub name: String,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+}
+
+/// Endpoints a health check looks at.
+pub trait RpcHealth: Send + Sync {
+    fn endpoints(&self) -> Vec<EndpointHealth>;
+}
+
+struct Endpoint<W: ?Sized> {
+    name: String,
+    backend: Arc<W>,
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl<W: ?Sized> Endpoint<W> {
+    fn healthy(&self, now: Instant) -> bool {
+        !matches!(self.unhealthy_until, Some(until) if now < until)
+    }
+}
+
+/// Backends of the same wallet on several endpoints, called through the
+/// current one.
+pub struct Failover<W: ?Sized> {
+    config: RpcConfig,
+    endpoints: Mutex<Vec<Endpoint<W>>>,
+    current: Mutex<usize>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<W: ?Sized + Send + Sync> Failover<W> {
+    /// `endpoints` are named backends, in order of preference.
+    pub fn new(config: RpcConfig, endpoints: Vec<(String, Arc<W>)>) -> anyhow::Result<Self> {
+        if endpoints.is_empty() {
+            return Err(anyhow::anyhow!("No zksync RPC endpoint to call!"));
+        }
+        Ok(Self {
+            config,
+            endpoints: Mutex::new(
+                endpoints
+                    .into_iter()
+                    .map(|(name, backend)| Endpoint {
+                        name,
+                        backend,
+                        consecutive_failures: 0,
+                        unhealthy_until: None,
+                    })
+                    .collect(),
+            ),
+            current: Mutex::new(0),
+            metrics: None,
+        })
+    }
+
+    /// Counts errors per endpoint, retries and failovers into `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.set_gauge("rpc_healthy_endpoints", self.healthy_count());
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Backend of the endpoint calls currently go to.
+    pub fn current(&self) -> Arc<W> {
+        let index = *self.current.lock().unwrap();
+        self.endpoints.lock().unwrap()[index].backend.clone()
+    }
+
+    pub fn current_name(&self) -> String {
+        let index = *self.current.lock().unwrap();
+        self.endpoints.lock().unwrap()[index].name.clone()
+    }
+
+    fn healthy_count(&self) -> Decimal {
+        let now = Instant::now();
+        let endpoints = self.endpoints.lock().unwrap();
+        Decimal::from(endpoints.iter().filter(|e| e.healthy(now)).count())
+    }
+
+    fn incr(&self, name: &str) {
+        if let Some(metrics) = &self.metrics {
+            metrics.incr(name);
+        }
+    }
+
+    /// Index and backend of the endpoint to call: the current one while
+    /// healthy, else the next healthy one. When none is, the one back the
+    /// soonest.
+    fn pick(&self) -> (usize, Arc<W>) {
+        let now = Instant::now();
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut current = self.current.lock().unwrap();
+        if !endpoints[*current].healthy(now) {
+            let count = endpoints.len();
+            let next = (1..count)
+                .map(|offset| (*current + offset) % count)
+                .find(|&index| endpoints[index].healthy(now))
+                .unwrap_or_else(|| {
+                    (0..count)
+                        .min_by_key(|&index| endpoints[index].unhealthy_until)
+                        .unwrap_or(*current)
+                });
+            if next != *current {
+                log::warn!(
+                    "zksync RPC endpoint {} is unhealthy, failing over to {}",
+                    endpoints[*current].name,
+                    endpoints[next].name
+                );
+                *current = next;
+                self.incr("rpc_failovers");
+            }
+        }
+        (*current, endpoints[*current].backend.clone())
+    }
+
+    fn on_success(&self, index: usize) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = &mut endpoints[index];
+        if endpoint.unhealthy_until.take().is_some() {
+            log::info!("zksync RPC endpoint {} is back", endpoint.name);
+        }
+        endpoint.consecutive_failures = 0;
+        drop(endpoints);
+        if let Some(metrics) = &self.metrics {
+            metrics.set_gauge("rpc_healthy_endpoints", self.healthy_count());
+        }
+    }
+
+    fn on_failure(&self, index: usize, error: &anyhow::Error) {
+        let now = Instant::now();
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = &mut endpoints[index];
+        endpoint.consecutive_failures += 1;
+        self.incr(&format!("rpc_errors_{}", endpoint.name));
+        log::warn!("zksync RPC call to {} failed: {:#}", endpoint.name, error);
+        if endpoint.consecutive_failures >= self.config.unhealthy_after && endpoint.healthy(now) {
+            endpoint.unhealthy_until = Some(now + Duration::from_secs(self.config.unhealthy_secs));
+            log::warn!(
+                "zksync RPC endpoint {} is unhealthy after {} failures",
+                endpoint.name,
+                endpoint.consecutive_failures
+            );
+        }
+        drop(endpoints);
+        if let Some(metrics) = &self.metrics {
+            metrics.set_gauge("rpc_healthy_endpoints", self.healthy_count());
+        }
+    }
+
+    /// Runs `call` on the current endpoint with a timeout, retrying
+    /// transport errors with backoff, on another endpoint once the current
+    /// one turns unhealthy.
+    pub async fn call<T, F, Fut>(&self, call: F) -> anyhow::Result<T>
+    where
+        F: Fn(Arc<W>) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let timeout = self.config.timeout();
+        let mut backoff = self.config.backoff();
+        let mut attempts = 0;
+        loop {
+            let (index, backend) = self.pick();
+            let result = match tokio::time::timeout(timeout, call(backend)).await {
+                Ok(result) => result,
+                Err(_) => Err(Timeout(timeout).into()),
+            };
+            let error = match result {
+                Ok(value) => {
+                    self.on_success(index);
+                    return Ok(value);
+                }
+                // The endpoint answered, another try would get the same.
+                Err(e) if !is_retryable(&e) => return Err(e),
+                Err(e) => e,
+            };
+            self.on_failure(index, &error);
+            attempts += 1;
+            if attempts > self.config.retries {
+                return Err(error.context(format!("zksync RPC failed {} times", attempts)));
+            }
+            self.incr("rpc_retries");
+            tokio::time::sleep(backoff.next_delay()).await;
+        }
+    }
+}
+
+impl<W: ?Sized + Send + Sync> RpcHealth for Failover<W> {
+    fn endpoints(&self) -> Vec<EndpointHealth> {
+        let now = Instant::now();
+        self.endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|endpoint| EndpointHealth {
+                name: endpoint.name.clone(),
+                healthy: endpoint.healthy(now),
+                consecutive_failures: endpoint.consecutive_failures,
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl<W: BalanceSource + ?Sized + 'static> BalanceSource for Failover<W> {
+    async fn committed_balance(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        self.call(|backend| async move { backend.committed_balance(token).await })
+            .await
+    }
+}
+
+#[async_trait]
+impl<W: FeeSource + ?Sized + 'static> FeeSource for Failover<W> {
+    async fn swap_fee(&self, token: TokenId) -> anyhow::Result<BigUint> {
+        self.call(|backend| async move { backend.swap_fee(token).await })
+            .await
+    }
+}
+
+#[async_trait]
+impl<W: TokenSource + ?Sized + 'static> TokenSource for Failover<W> {
+    async fn tokens(&self) -> anyhow::Result<Vec<TokenInfo>> {
+        self.call(|backend| async move { backend.tokens().await })
+            .await
+    }
+}
+
+#[async_trait]
+impl<W: AccountSource + ?Sized + 'static> AccountSource for Failover<W> {
+    async fn account_id(&self) -> anyhow::Result<Option<AccountId>> {
+        self.call(|backend| async move { backend.account_id().await })
+            .await
+    }
+}
+
+#[async_trait]
+impl<W: NonceSigner + ?Sized + 'static> NonceSigner for Failover<W> {
+    async fn committed_nonce(&self) -> anyhow::Result<Nonce> {
+        self.call(|backend| async move { backend.committed_nonce().await })
+            .await
+    }
+
+    /// Signing happens locally, on the current endpoint's wallet.
+    async fn sign_with_nonce(
+        &self,
+        params: OrderParams,
+        nonce: Nonce,
+    ) -> anyhow::Result<ZksyncOrder> {
+        self.current().sign_with_nonce(params, nonce).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Answers balances from a script, then 100.
+    struct Scripted {
+        script: Mutex<VecDeque<anyhow::Result<u32>>>,
+        calls: Mutex<u32>,
+    }
+
+    impl Scripted {
+        fn new(script: Vec<anyhow::Result<u32>>) -> Arc<Self> {
+            Arc::new(Self {
+                script: Mutex::new(script.into()),
+                calls: Mutex::new(0),
+            })
+        }
+
+        fn calls(&self) -> u32 {
+            *self.calls.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl BalanceSource for Scripted {
+        async fn committed_balance(&self, _token: TokenId) -> anyhow::Result<BigUint> {
+            *self.calls.lock().unwrap() += 1;
+            let next = self.script.lock().unwrap().pop_front();
+            match next {
+                Some(Ok(u32::MAX)) => {
+                    // Hangs past the timeout.
+                    tokio::time::sleep(Duration::from_secs(3600)).await;
+                    Ok(BigUint::from(0u32))
+                }
+                Some(result) => result.map(BigUint::from),
+                None => Ok(BigUint::from(100u32)),
+            }
+        }
+    }
+
+    fn network_error() -> anyhow::Result<u32> {
+        Err(anyhow::anyhow!("Network error: connection reset by peer"))
+    }
+
+    fn config() -> RpcConfig {
+        RpcConfig {
+            timeout_ms: 50,
+            retries: 2,
+            min_backoff_ms: 1,
+            max_backoff_ms: 10,
+            unhealthy_after: 2,
+            unhealthy_secs: 1,
+            ..RpcConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&network_error().unwrap_err()));
+        assert!(is_retryable(&anyhow::Error::new(Timeout(
+            Duration::from_secs(1)
+        ))));
+        assert!(is_retryable(
+            &anyhow::anyhow!("Unable to decode server response: 503 Service Unavailable")
+                .context("balance")
+        ));
+        assert!(!is_retryable(&anyhow::anyhow!(
+            "RPC error: account not found"
+        )));
+        assert!(!is_retryable(&anyhow::anyhow!(
+            "Token is not supported by zkSync"
+        )));
+        assert_eq!(
+            endpoint_name("https://api.zksync.io/jsrpc"),
+            "api.zksync.io"
+        );
+        assert_eq!(endpoint_name("localhost:3030"), "localhost:3030");
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let backend = Scripted::new(vec![network_error(), Ok(u32::MAX), Ok(7)]);
+        let metrics = Arc::new(Metrics::new());
+        let rpc = Failover::new(config(), vec![("main".into(), backend.clone())])
+            .expect("new")
+            .with_metrics(metrics.clone());
+        // A network error then a timeout are retried.
+        let balance = rpc.committed_balance(TokenId(0)).await.expect("balance");
+        assert_eq!(balance, BigUint::from(7u32));
+        assert_eq!(backend.calls(), 3);
+        assert_eq!(metrics.counter("rpc_retries"), 2);
+        assert_eq!(metrics.counter("rpc_errors_main"), 2);
+        // The answer makes the endpoint healthy again.
+        assert!(rpc.endpoints()[0].healthy);
+        assert_eq!(rpc.endpoints()[0].consecutive_failures, 0);
+
+        // Deterministic errors are not.
+        let backend = Scripted::new(vec![Err(anyhow::anyhow!("Account does not exist"))]);
+        let rpc = Failover::new(config(), vec![("main".into(), backend.clone())]).expect("new");
+        assert!(rpc.committed_balance(TokenId(0)).await.is_err());
+        assert_eq!(backend.calls(), 1);
+        assert_eq!(rpc.endpoints()[0].consecutive_failures, 0);
+
+        // Giving up after the retries.
+        let backend = Scripted::new(vec![network_error(), network_error(), network_error()]);
+        let rpc = Failover::new(config(), vec![("main".into(), backend.clone())]).expect("new");
+        let err = rpc.committed_balance(TokenId(0)).await.unwrap_err();
+        assert!(err.to_string().contains("3 times"), "{}", err);
+        assert!(!rpc.endpoints()[0].healthy);
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let first = Scripted::new(vec![network_error(), network_error(), network_error()]);
+        let second = Scripted::new(vec![Ok(42)]);
+        let metrics = Arc::new(Metrics::new());
+        let rpc = Failover::new(
+            config(),
+            vec![
+                ("first".into(), first.clone()),
+                ("second".into(), second.clone()),
+            ],
+        )
+        .expect("new")
+        .with_metrics(metrics.clone());
+        let balance = rpc.committed_balance(TokenId(0)).await.expect("balance");
+        assert_eq!(balance, BigUint::from(42u32));
+        assert_eq!(first.calls(), 2);
+        assert_eq!(rpc.current_name(), "second");
+        assert_eq!(metrics.counter("rpc_failovers"), 1);
+        assert_eq!(metrics.gauge("rpc_healthy_endpoints"), Some(Decimal::ONE));
+        assert_eq!(
+            rpc.endpoints()
+                .into_iter()
+                .map(|e| (e.name, e.healthy))
+                .collect::<Vec<_>>(),
+            [("first".to_owned(), false), ("second".to_owned(), true)]
+        );
+
+        // The first one is healthy again after a while, calls staying on
+        // the second one.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(rpc.endpoints()[0].healthy);
+        rpc.committed_balance(TokenId(0)).await.expect("balance");
+        assert_eq!(rpc.current_name(), "second");
+        assert!(Failover::<Scripted>::new(config(), Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+/// Stateful operations the backend forgets when the connection drops: our
+/// login, market subscriptions and advertised liquidity. `Connection`
+/// records them as they are sent and replays them after reconnecting.
+use crate::zigzag::{
+    unix_timestamp, ChainId, Indicateliq2Args, LoginArgs, Market, Operation, SubscribemarketArgs,
+    Timestamp,
+};
+use async_tungstenite::tungstenite::Message;
+use futures::prelude::*;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionState {
+    login: Option<LoginArgs>,
+    subscriptions: Vec<SubscribemarketArgs>,
+    /// Latest liquidity of each market, in the order first advertised
+    liquidity: Vec<((ChainId, Market), Indicateliq2Args)>,
+}
+
+impl SessionState {
+    /// Records an outgoing operation. Returns whether it is part of the
+    /// session, and so replayed after a reconnect.
+    pub fn record(&mut self, op: &Operation) -> bool {
+        match op {
+            Operation::Login(args) => {
+                self.login = Some(args.clone());
+                true
+            }
+            Operation::Subscribemarket(args) => {
+                if !self.subscriptions.contains(args) {
+                    self.subscriptions.push(args.clone());
+                }
+                true
+            }
+            Operation::Unsubscribemarket(args) => {
+                self.subscriptions
+                    .retain(|s| s.chain_id != args.chain_id || s.market != args.market);
+                self.forget_liquidity(args.chain_id, &args.market);
+                false
+            }
+            Operation::Indicateliq2(args) => {
+                let key = (args.chain_id, args.market.clone());
+                if args.liquidity.is_empty() {
+                    self.forget_liquidity(args.chain_id, &args.market);
+                } else if let Some((_, liquidity)) =
+                    self.liquidity.iter_mut().find(|(k, _)| *k == key)
+                {
+                    *liquidity = args.clone();
+                } else {
+                    self.liquidity.push((key, args.clone()));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn forget_liquidity(&mut self, chain_id: ChainId, market: &str) {
+        self.liquidity
+            .retain(|((c, m), _)| *c != chain_id || m != market);
+    }
+
+    /// Operations restoring the session at `now`: the login first, then the
+    /// subscriptions, then the liquidity levels that have not expired.
+    pub fn operations(&self, now: Timestamp) -> Vec<Operation> {
+        let mut ops = Vec::new();
+        ops.extend(self.login.clone().map(Operation::Login));
+        ops.extend(
+            self.subscriptions
+                .iter()
+                .cloned()
+                .map(Operation::Subscribemarket),
+        );
+        for (_, args) in &self.liquidity {
+            let mut args = args.clone();
+            args.liquidity
+                .retain(|level| !matches!(level.expires, Some(expires) if expires <= now));
+            if !args.liquidity.is_empty() {
+                ops.push(Operation::Indicateliq2(args));
+            }
+        }
+        ops
+    }
+
+    /// Sends the operations restoring the session to a fresh connection.
+    pub async fn replay<S>(&self, sink: &mut S) -> anyhow::Result<()>
+    where
+        S: Sink<Message> + Unpin,
+        S::Error: std::error::Error + Send + Sync + 'static,
+    {
+        for op in self.operations(unix_timestamp()) {
+            sink.send(Message::Text(serde_json::to_string(&op)?))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const EXPIRES: Timestamp = 4_294_967_295;
+
+    fn op(value: Value) -> Operation {
+        serde_json::from_value(value).expect("from_value")
+    }
+
+    #[tokio::test]
+    async fn test_replay_order() {
+        let mut state = SessionState::default();
+        let sent = [
+            op(json!({"op": "indicateliq2", "args": [1000, "ETH-USDC", [["b", 1999, 0.5]]]})),
+            op(json!({"op": "subscribemarket", "args": [1000, "ETH-USDC"]})),
+            op(json!({"op": "login", "args": [1000, "23"]})),
+            op(json!({"op": "subscribemarket", "args": [1000, "WBTC-USDC"]})),
+            op(json!({"op": "subscribemarket", "args": [1000, "ETH-USDC"]})),
+            op(json!({"op": "indicateliq2", "args": [1000, "WBTC-USDC", [["s", 30000.0, 0.1]]]})),
+            op(json!({"op": "indicateliq2", "args": [1000, "DAI-USDC", [["s", 1, 100, 100]]]})),
+            // Replaces the first indication.
+            op(
+                json!({"op": "indicateliq2", "args": [1000, "ETH-USDC", [["b", 2000.0, 0.5, EXPIRES], ["s", 2002, 0.5, 100]]]}),
+            ),
+            op(json!({"op": "cancelorder", "args": [1000, 5]})),
+        ];
+        let stateful: Vec<_> = sent.iter().map(|op| state.record(op)).collect();
+        assert_eq!(
+            stateful,
+            [true, true, true, true, true, true, true, true, false]
+        );
+
+        let mut messages: Vec<Message> = Vec::new();
+        state.replay(&mut messages).await.expect("replay");
+        let replayed: Vec<Value> = messages
+            .iter()
+            .map(|m| serde_json::from_str(m.to_text().expect("text")).expect("json"))
+            .collect();
+        assert_eq!(
+            replayed,
+            [
+                json!({"op": "login", "args": [1000, "23"]}),
+                json!({"op": "subscribemarket", "args": [1000, "ETH-USDC"]}),
+                json!({"op": "subscribemarket", "args": [1000, "WBTC-USDC"]}),
+                json!({"op": "indicateliq2", "args": [1000, "ETH-USDC", [["b", 2000.0, 0.5, EXPIRES]]]}),
+                json!({"op": "indicateliq2", "args": [1000, "WBTC-USDC", [["s", 30000.0, 0.1]]]}),
+            ]
+        );
+
+        // Expired levels are left out, and so are markets without any left.
+        assert_eq!(state.operations(0).len(), 6);
+
+        // Pulled liquidity and unsubscribed markets are forgotten.
+        state.record(&op(
+            json!({"op": "indicateliq2", "args": [1000, "ETH-USDC", []]}),
+        ));
+        state.record(&op(
+            json!({"op": "unsubscribemarket", "args": [1000, "WBTC-USDC"]}),
+        ));
+        assert_eq!(state.operations(0).len(), 3);
+    }
+}
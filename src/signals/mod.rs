@@ -0,0 +1,208 @@
+/// Signals derived from the reference prices. `Volatility` keeps an EWMA of
+/// squared log-returns per market, which market makers widen their spreads
+/// with. `triangle` checks the crosses of three markets against each other.
+pub mod triangle;
+
+use crate::zigzag::{Decimal, Market, Timestamp};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const SECS_PER_MINUTE: f64 = 60.0;
+const SECS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+
+/// `[market_maker.volatility]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolatilityConfig {
+    /// Age at which a squared return weighs half as much, in seconds
+    pub half_life_secs: u64,
+    /// Basis points of spread added per basis point of per-minute
+    /// volatility
+    pub multiplier: Decimal,
+    pub min_spread_bps: Decimal,
+    pub max_spread_bps: Decimal,
+}
+
+impl Default for VolatilityConfig {
+    fn default() -> Self {
+        Self {
+            half_life_secs: 300,
+            multiplier: Decimal::ONE,
+            min_spread_bps: Decimal::from(10),
+            max_spread_bps: Decimal::from(200),
+        }
+    }
+}
+
+impl VolatilityConfig {
+    /// `base_spread_bps + multiplier * volatility`, within the bounds, for a
+    /// per-minute volatility in basis points.
+    pub fn spread_bps(&self, base_spread_bps: Decimal, volatility_bps: Decimal) -> Decimal {
+        (base_spread_bps + self.multiplier * volatility_bps)
+            .max(self.min_spread_bps)
+            .min(self.max_spread_bps)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.half_life_secs == 0 {
+            return Err(anyhow::anyhow!("half_life_secs must be at least 1!"));
+        }
+        if self.min_spread_bps > self.max_spread_bps {
+            return Err(anyhow::anyhow!(
+                "min_spread_bps must not exceed max_spread_bps!"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Estimate {
+    /// Last sampled price and when
+    price: f64,
+    sampled: Timestamp,
+    /// Variance of log-returns per second, once there is a return
+    variance: Option<f64>,
+}
+
+/// Cloning the estimator shares it.
+#[derive(Clone, Debug)]
+pub struct Volatility {
+    half_life_secs: f64,
+    markets: Arc<RwLock<HashMap<Market, Estimate>>>,
+}
+
+impl Volatility {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life_secs: half_life.as_secs_f64(),
+            markets: Arc::default(),
+        }
+    }
+
+    /// Samples a reference price of `market` at `now`. Prices within the
+    /// second of the last sample are skipped, their move shows in the next
+    /// one.
+    pub fn update(&self, market: &str, price: Decimal, now: Timestamp) {
+        let price = match price.to_f64() {
+            Some(price) if price > 0.0 => price,
+            _ => return,
+        };
+        let mut markets = self.markets.write().unwrap();
+        let estimate = match markets.get_mut(market) {
+            Some(estimate) if now > estimate.sampled => estimate,
+            Some(_) => return,
+            None => {
+                markets.insert(
+                    market.to_owned(),
+                    Estimate {
+                        price,
+                        sampled: now,
+                        variance: None,
+                    },
+                );
+                return;
+            }
+        };
+        let elapsed = (now - estimate.sampled) as f64;
+        let rate = (price / estimate.price).ln().powi(2) / elapsed;
+        // Older returns decay by half every half-life, however irregular the
+        // samples.
+        let weight = 1.0 - 0.5f64.powf(elapsed / self.half_life_secs);
+        estimate.variance = Some(match estimate.variance {
+            Some(variance) => variance + weight * (rate - variance),
+            None => rate,
+        });
+        estimate.price = price;
+        estimate.sampled = now;
+    }
+
+    fn variance(&self, market: &str) -> Option<f64> {
+        self.markets.read().unwrap().get(market)?.variance
+    }
+
+    /// Standard deviation of one-minute log-returns.
+    pub fn per_minute(&self, market: &str) -> Option<f64> {
+        Some((self.variance(market)? * SECS_PER_MINUTE).sqrt())
+    }
+
+    /// Standard deviation of yearly log-returns.
+    pub fn annualized(&self, market: &str) -> Option<f64> {
+        Some((self.variance(market)? * SECS_PER_YEAR).sqrt())
+    }
+
+    /// Per-minute volatility in basis points.
+    pub fn per_minute_bps(&self, market: &str) -> Option<Decimal> {
+        Decimal::from_f64(self.per_minute(market)? * 10_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("volatility");
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_ewma() {
+        let volatility = Volatility::new(Duration::from_secs(60));
+        let update = |price, now| volatility.update("ETH-USDC", price, now);
+        update(dec!(100), 0);
+        assert_eq!(volatility.per_minute("ETH-USDC"), None);
+
+        // A 1% move over a minute, then back: the estimate is that move.
+        update(dec!(101), 60);
+        let move_ = (1.01f64).ln();
+        assert_close(volatility.per_minute("ETH-USDC"), move_);
+        update(dec!(100), 120);
+        assert_close(volatility.per_minute("ETH-USDC"), move_);
+        // Same second: skipped, the move counts in the next sample.
+        update(dec!(150), 120);
+        assert_close(volatility.per_minute("ETH-USDC"), move_);
+
+        // A calm half-life halves the variance.
+        update(dec!(100), 180);
+        assert_close(volatility.per_minute("ETH-USDC"), move_ / 2f64.sqrt());
+        assert_close(
+            volatility.annualized("ETH-USDC"),
+            move_ / 2f64.sqrt() * (SECS_PER_YEAR / SECS_PER_MINUTE).sqrt(),
+        );
+        assert_eq!(volatility.per_minute("WBTC-USDC"), None);
+
+        // Clones share the estimates.
+        let shared = volatility.clone();
+        assert!(shared.per_minute_bps("ETH-USDC").is_some());
+    }
+
+    #[test]
+    fn test_spread() {
+        let config = VolatilityConfig {
+            half_life_secs: 60,
+            multiplier: dec!(0.5),
+            min_spread_bps: dec!(10),
+            max_spread_bps: dec!(100),
+        };
+        assert_eq!(config.spread_bps(dec!(20), dec!(0)), dec!(20));
+        assert_eq!(config.spread_bps(dec!(20), dec!(100)), dec!(70));
+        assert_eq!(config.spread_bps(dec!(20), dec!(500)), dec!(100));
+        assert_eq!(config.spread_bps(dec!(5), dec!(0)), dec!(10));
+        assert!(config.validate().is_ok());
+        let inverted = VolatilityConfig {
+            min_spread_bps: dec!(200),
+            ..config
+        };
+        assert!(inverted.validate().is_err());
+    }
+}
@@ -0,0 +1,491 @@
+/// Triangular consistency of three markets over three tokens, such as
+/// ETH-USDT, WBTC-ETH and WBTC-USDT: going around the cycle at the best bid
+/// and ask of the local books should not end with more than it started
+/// with. When it does by more than a threshold net of the fees, which
+/// ZigZag takes out of the amount each leg sells, the cycle is an
+/// opportunity.
+use crate::orderbook::Snapshot;
+use crate::zigzag::{Amount, Asset, Decimal, Market, MarketInfo, Side};
+use serde::Deserialize;
+use std::fmt;
+
+/// `[[triangles]]` tables of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TriangleConfig {
+    /// Cycles start and end in the token the first and last markets share
+    pub markets: [Market; 3],
+    /// Edge net of fees an opportunity needs, in basis points
+    pub min_edge_bps: Decimal,
+    /// Largest amount of the starting token put through a cycle
+    pub max_start_amount: Option<Amount>,
+    /// Take the opportunities instead of only logging them
+    pub active: bool,
+    /// Worst price accepted over the book price of a leg, in basis points
+    pub max_slippage_bps: Decimal,
+    /// Wait for a quote, then for the fill of each leg
+    pub timeout_secs: u64,
+    pub order_expires_secs: u64,
+}
+
+impl Default for TriangleConfig {
+    fn default() -> Self {
+        Self {
+            markets: Default::default(),
+            min_edge_bps: Decimal::from(20),
+            max_start_amount: None,
+            active: false,
+            max_slippage_bps: Decimal::from(10),
+            timeout_secs: 10,
+            order_expires_secs: 60,
+        }
+    }
+}
+
+/// One taker order of a cycle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leg {
+    pub market: Market,
+    pub side: Side,
+    /// Best price of the side of the book taken
+    pub price: Decimal,
+    pub base_quantity: Amount,
+    /// Amount sold, fee included
+    pub sold: Amount,
+    /// Amount bought with what the fee leaves of it
+    pub bought: Amount,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opportunity {
+    /// Token the cycle starts and ends in
+    pub token: String,
+    pub legs: Vec<Leg>,
+    pub start: Amount,
+    pub end: Amount,
+    /// Edge at the book prices alone, in basis points
+    pub gross_edge_bps: Decimal,
+    /// Edge net of the fees, in basis points
+    pub edge_bps: Decimal,
+    /// Price of the market of the last leg implied by the first two
+    pub implied_price: Decimal,
+}
+
+impl fmt::Display for Opportunity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token)?;
+        for leg in &self.legs {
+            let side = match leg.side {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            };
+            write!(f, " -> {} {} @ {}", side, leg.market, leg.price.normalize())?;
+        }
+        write!(
+            f,
+            " (implied {}): {} -> {} {}, edge {} bps net of fees, {} gross",
+            self.implied_price.round_dp(8).normalize(),
+            self.start.round_dp(8).normalize(),
+            self.end.round_dp(8).normalize(),
+            self.token,
+            self.edge_bps.round_dp(2).normalize(),
+            self.gross_edge_bps.round_dp(2).normalize()
+        )
+    }
+}
+
+/// A leg of a cycle: the market it trades, by index, and whether it sells
+/// the base asset of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Step {
+    market: usize,
+    sells_base: bool,
+}
+
+#[derive(Debug)]
+pub struct Triangle {
+    config: TriangleConfig,
+    infos: Vec<MarketInfo>,
+    /// Base and quote fees of each market
+    fees: Vec<(Amount, Amount)>,
+    token: Asset,
+    /// Through the markets in order, then backwards
+    cycles: [Vec<Step>; 2],
+    /// Whether the books last evaluated held an opportunity
+    open: bool,
+}
+
+/// Token of `info` that is not `token`.
+fn other(info: &MarketInfo, token: u32) -> u32 {
+    match info.base_asset.id == token {
+        true => info.quote_asset.id,
+        false => info.base_asset.id,
+    }
+}
+
+fn cycle(infos: &[MarketInfo], order: [usize; 3], start: u32) -> Vec<Step> {
+    let mut token = start;
+    order
+        .into_iter()
+        .map(|market| {
+            let info = &infos[market];
+            let sells_base = info.base_asset.id == token;
+            token = other(info, token);
+            Step { market, sells_base }
+        })
+        .collect()
+}
+
+impl Triangle {
+    /// Checks that the markets of `config`, whose infos are among `infos`,
+    /// trade three tokens in a cycle.
+    pub fn new(config: TriangleConfig, infos: &[MarketInfo]) -> anyhow::Result<Self> {
+        let name = config.markets.join("/");
+        let infos = config
+            .markets
+            .iter()
+            .map(|market| {
+                infos
+                    .iter()
+                    .find(|info| info.alias == *market)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Triangle {}: {} is not listed!", name, market))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let tokens = |info: &MarketInfo| [info.base_asset.id, info.quote_asset.id];
+        let not_a_cycle = || {
+            anyhow::anyhow!(
+                "Triangle {}: the markets must trade three tokens in a cycle!",
+                name
+            )
+        };
+        let start = tokens(&infos[0])
+            .into_iter()
+            .find(|token| tokens(&infos[2]).contains(token) && !tokens(&infos[1]).contains(token))
+            .ok_or_else(not_a_cycle)?;
+        let (second, third) = (other(&infos[0], start), other(&infos[2], start));
+        let middle = tokens(&infos[1]);
+        if second == third || !(middle == [second, third] || middle == [third, second]) {
+            return Err(not_a_cycle());
+        }
+        let fees = infos
+            .iter()
+            .map(|info| Ok((info.base_fee.value()?, info.quote_fee.value()?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let token = match infos[0].base_asset.id == start {
+            true => infos[0].base_asset.clone(),
+            false => infos[0].quote_asset.clone(),
+        };
+        Ok(Self {
+            cycles: [
+                cycle(&infos, [0, 1, 2], start),
+                cycle(&infos, [2, 1, 0], start),
+            ],
+            config,
+            infos,
+            fees,
+            token,
+            open: false,
+        })
+    }
+
+    pub fn config(&self) -> &TriangleConfig {
+        &self.config
+    }
+
+    pub fn markets(&self) -> &[Market; 3] {
+        &self.config.markets
+    }
+
+    pub fn name(&self) -> String {
+        self.config.markets.join("/")
+    }
+
+    pub fn market_infos(&self) -> &[MarketInfo] {
+        &self.infos
+    }
+
+    /// Most profitable cycle through `books`, in the order of the markets.
+    /// None without a bid and an ask on every book, or when the fees take
+    /// the whole amount.
+    pub fn evaluate(&self, books: [&Snapshot; 3]) -> Option<Opportunity> {
+        self.cycles
+            .iter()
+            .filter_map(|cycle| self.run(cycle, &books))
+            .max_by_key(|opportunity| opportunity.edge_bps)
+    }
+
+    /// Evaluates `books`, returning the opportunity when one opens: the
+    /// edge net of fees reaches `min_edge_bps` where it did not on the
+    /// books before.
+    pub fn on_books(&mut self, books: [&Snapshot; 3]) -> Option<Opportunity> {
+        let opportunity = self
+            .evaluate(books)
+            .filter(|opportunity| opportunity.edge_bps >= self.config.min_edge_bps);
+        let opened = opportunity.is_some() && !self.open;
+        self.open = opportunity.is_some();
+        opportunity.filter(|_| opened)
+    }
+
+    fn run(&self, cycle: &[Step], books: &[&Snapshot; 3]) -> Option<Opportunity> {
+        // Amount of the token a leg buys per unit sold at the top of the
+        // book, before fees. The starting amount is capped by the thinnest
+        // top level, counted in the starting token.
+        let mut rates = Vec::with_capacity(cycle.len());
+        let mut start = self.config.max_start_amount;
+        let mut rate = Decimal::ONE;
+        for step in cycle {
+            let book = books[step.market];
+            let (price, step_rate, depth) = match step.sells_base {
+                true => {
+                    let bid = book.best_bid()?;
+                    (bid.price, bid.price, bid.base_quantity)
+                }
+                false => {
+                    let ask = book.best_ask()?;
+                    (
+                        ask.price,
+                        Decimal::ONE / ask.price,
+                        ask.base_quantity * ask.price,
+                    )
+                }
+            };
+            let depth = depth / rate;
+            start = Some(start.map_or(depth, |start| start.min(depth)));
+            rate *= step_rate;
+            rates.push((price, step_rate));
+        }
+        let start = start.filter(|start| *start > Decimal::ZERO)?;
+
+        let mut amount = start;
+        let mut legs = Vec::with_capacity(cycle.len());
+        for (step, (price, _)) in cycle.iter().zip(&rates) {
+            let (base_fee, quote_fee) = self.fees[step.market];
+            let (side, fee) = match step.sells_base {
+                true => (Side::Sell, base_fee),
+                false => (Side::Buy, quote_fee),
+            };
+            let net = amount - fee;
+            if net <= Decimal::ZERO {
+                return None;
+            }
+            let bought = match step.sells_base {
+                true => net * price,
+                false => net / price,
+            };
+            legs.push(Leg {
+                market: self.infos[step.market].alias.clone(),
+                side,
+                price: *price,
+                base_quantity: match step.sells_base {
+                    true => amount,
+                    false => bought,
+                },
+                sold: amount,
+                bought,
+            });
+            amount = bought;
+        }
+        // Consistent books have the rates multiply to one around the cycle.
+        let implied_rate = Decimal::ONE / (rates[0].1 * rates[1].1);
+        let implied_price = match cycle[2].sells_base {
+            true => implied_rate,
+            false => Decimal::ONE / implied_rate,
+        };
+        Some(Opportunity {
+            token: self.token.symbol.clone(),
+            legs,
+            start,
+            end: amount,
+            gross_edge_bps: (rate - Decimal::ONE) * Decimal::from(10_000),
+            edge_bps: (amount - start) / start * Decimal::from(10_000),
+            implied_price,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Level;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+
+    /// ETH is token 0, USDT 1 and WBTC 2.
+    fn infos(fees: bool) -> Vec<MarketInfo> {
+        let mut infos = vec![
+            fixtures::market_info("ETH-USDT", 0, 1),
+            fixtures::market_info("WBTC-ETH", 2, 0),
+            fixtures::market_info("WBTC-USDT", 2, 1),
+        ];
+        let (base_fees, quote_fees) = match fees {
+            true => (
+                [dec!(0.0005), dec!(0.0001), dec!(0.0001)],
+                [dec!(1), dec!(0.0015), dec!(3)],
+            ),
+            false => ([Decimal::ZERO; 3], [Decimal::ZERO; 3]),
+        };
+        for (info, (base_fee, quote_fee)) in
+            infos.iter_mut().zip(base_fees.into_iter().zip(quote_fees))
+        {
+            info.base_fee = base_fee.into();
+            info.quote_fee = quote_fee.into();
+        }
+        infos
+    }
+
+    fn triangle(fees: bool, min_edge_bps: Decimal) -> Triangle {
+        let config = TriangleConfig {
+            markets: ["ETH-USDT".into(), "WBTC-ETH".into(), "WBTC-USDT".into()],
+            min_edge_bps,
+            ..TriangleConfig::default()
+        };
+        Triangle::new(config, &infos(fees)).expect("new")
+    }
+
+    fn book(bid: (Decimal, Amount), ask: (Decimal, Amount)) -> Snapshot {
+        let level = |(price, base_quantity)| Level {
+            price,
+            base_quantity,
+            expires: None,
+        };
+        Snapshot {
+            bids: vec![level(bid)],
+            asks: vec![level(ask)],
+        }
+    }
+
+    fn books(wbtc_usdt: (Decimal, Decimal)) -> [Snapshot; 3] {
+        [
+            book((dec!(1999), dec!(10)), (dec!(2000), dec!(10))),
+            book((dec!(14.9), dec!(1)), (dec!(15), dec!(1))),
+            book((wbtc_usdt.0, dec!(0.5)), (wbtc_usdt.1, dec!(0.5))),
+        ]
+    }
+
+    fn refs(books: &[Snapshot; 3]) -> [&Snapshot; 3] {
+        [&books[0], &books[1], &books[2]]
+    }
+
+    fn evaluate(triangle: &Triangle, books: &[Snapshot; 3]) -> Option<Opportunity> {
+        triangle.evaluate(refs(books))
+    }
+
+    #[test]
+    fn test_forward() {
+        // WBTC-USDT bids 30300 where ETH-USDT and WBTC-ETH imply 30000:
+        // buy ETH, buy WBTC with it, sell the WBTC.
+        let books = books((dec!(30300), dec!(30400)));
+        let opportunity = evaluate(&triangle(false, dec!(20)), &books).expect("opportunity");
+        let sides: Vec<_> = opportunity
+            .legs
+            .iter()
+            .map(|leg| leg.side.clone())
+            .collect();
+        assert_eq!(sides, [Side::Buy, Side::Buy, Side::Sell]);
+        assert_eq!(opportunity.token, "USDT");
+        assert_eq!(opportunity.implied_price.round_dp(8), dec!(30000));
+        assert_eq!(opportunity.gross_edge_bps.round_dp(8), dec!(100));
+        // 0.5 WBTC on the WBTC-USDT bid is the thinnest level, worth
+        // 15000 USDT at the other two.
+        assert_eq!(opportunity.start.round_dp(8), dec!(15000));
+        assert_eq!(opportunity.legs[1].base_quantity.round_dp(8), dec!(0.5));
+        assert_eq!(opportunity.edge_bps.round_dp(8), dec!(100));
+
+        // Fees come out of the amount each leg sells: 1 USDT, then 0.0015
+        // ETH, then 0.0001 WBTC.
+        let mut triangle = triangle(true, dec!(20));
+        triangle.config.max_start_amount = Some(dec!(3000));
+        let opportunity = evaluate(&triangle, &books).expect("opportunity");
+        assert_eq!(opportunity.start, dec!(3000));
+        let legs = &opportunity.legs;
+        assert_eq!(legs[0].bought.round_dp(8), dec!(1.4995));
+        assert_eq!(legs[1].sold.round_dp(8), dec!(1.4995));
+        assert_eq!(legs[1].bought.round_dp(8), dec!(0.09986667));
+        assert_eq!(legs[2].base_quantity, legs[1].bought);
+        assert_eq!(opportunity.end.round_dp(8), dec!(3022.93));
+        assert_eq!(opportunity.edge_bps.round_dp(2), dec!(76.43));
+        assert_eq!(
+            opportunity.to_string(),
+            "USDT -> buy ETH-USDT @ 2000 -> buy WBTC-ETH @ 15 -> sell WBTC-USDT @ 30300 \
+             (implied 30000): 3000 -> 3022.93 USDT, edge 76.43 bps net of fees, 100 gross"
+        );
+    }
+
+    #[test]
+    fn test_backward() {
+        // WBTC-USDT asks 29700 where the others imply 29785.1 on their
+        // bids: buy WBTC, sell it for ETH, sell the ETH.
+        let backward = books((dec!(29600), dec!(29700)));
+        let opportunity = evaluate(&triangle(false, dec!(20)), &backward).expect("opportunity");
+        let legs: Vec<_> = opportunity
+            .legs
+            .iter()
+            .map(|leg| (leg.market.as_str(), leg.side.clone()))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                ("WBTC-USDT", Side::Buy),
+                ("WBTC-ETH", Side::Sell),
+                ("ETH-USDT", Side::Sell)
+            ]
+        );
+        // The ETH-USDT price the first two legs imply.
+        assert_eq!(opportunity.implied_price.round_dp(2), dec!(1993.29));
+        assert_eq!(opportunity.gross_edge_bps.round_dp(2), dec!(28.65));
+        // 0.5 WBTC on the ask is the thinnest.
+        assert_eq!(opportunity.start.round_dp(8), dec!(14850));
+        assert_eq!(opportunity.legs[0].base_quantity.round_dp(8), dec!(0.5));
+
+        // Consistent books have no edge, and a missing side no cycle.
+        let consistent = books((dec!(29800), dec!(30000)));
+        let opportunity = evaluate(&triangle(false, dec!(20)), &consistent).expect("opportunity");
+        assert!(opportunity.edge_bps < Decimal::ZERO);
+        let mut empty = backward.clone();
+        empty[1].asks.clear();
+        empty[1].bids.clear();
+        assert_eq!(evaluate(&triangle(false, dec!(20)), &empty), None);
+    }
+
+    #[test]
+    fn test_signal() {
+        let mut triangle = triangle(true, dec!(80));
+        triangle.config.max_start_amount = Some(dec!(3000));
+        let profitable = books((dec!(30300), dec!(30400)));
+        // 76.43 bps net of fees are not enough.
+        assert_eq!(triangle.on_books(refs(&profitable)), None);
+        triangle.config.min_edge_bps = dec!(50);
+        assert!(triangle.on_books(refs(&profitable)).is_some());
+        // Signaled once while it lasts, again once it closed and reopened.
+        assert_eq!(triangle.on_books(refs(&profitable)), None);
+        assert_eq!(
+            triangle.on_books(refs(&books((dec!(29800), dec!(30000))))),
+            None
+        );
+        assert!(triangle.on_books(refs(&profitable)).is_some());
+
+        // Fees taking the whole amount leave no cycle.
+        triangle.config.max_start_amount = Some(dec!(0.5));
+        assert_eq!(evaluate(&triangle, &profitable), None);
+    }
+
+    #[test]
+    fn test_new() {
+        let config = |markets: [&str; 3]| TriangleConfig {
+            markets: markets.map(String::from),
+            ..TriangleConfig::default()
+        };
+        let mut infos = infos(false);
+        infos.push(fixtures::market_info("ETH-USDC", 0, 3));
+        let triangle =
+            Triangle::new(config(["WBTC-USDT", "WBTC-ETH", "ETH-USDT"]), &infos).expect("new");
+        assert_eq!(triangle.token.symbol, "USDT");
+        let e = Triangle::new(config(["ETH-USDT", "WBTC-ETH", "ETH-USDC"]), &infos)
+            .expect_err("not a cycle");
+        assert!(e.to_string().contains("three tokens in a cycle"), "{}", e);
+        let e = Triangle::new(config(["ETH-USDT", "WBTC-ETH", "WBTC-DAI"]), &infos)
+            .expect_err("unlisted");
+        assert!(e.to_string().contains("WBTC-DAI is not listed"), "{}", e);
+    }
+}
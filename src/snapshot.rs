@@ -0,0 +1,291 @@
+/// Snapshots of the bot's state for `--resume`: our orders, the liquidity
+/// last advertised, positions, PnL and the fills already accounted, saved
+/// as JSON per wallet address and chain. A resumed bot checks them against
+/// the `orders` and `fills` snapshots of the backend as after a reconnect,
+/// so fills from before the restart are not accounted or notified again.
+use crate::portfolio::{FillState, FillTracker};
+use crate::reconcile::{OpenOrders, OrdersState};
+use crate::zigzag::{ChainId, FillId, Liquidity, Market, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Version of the snapshot format, bumped on any change to it.
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    pub version: u64,
+    pub address: String,
+    pub chain_id: ChainId,
+    pub saved_at: Timestamp,
+    /// Highest id of the fills accounted
+    pub last_fill_id: Option<FillId>,
+    pub positions: FillState,
+    pub orders: OrdersState,
+    /// Liquidity last advertised on each market
+    pub liquidity: BTreeMap<Market, Vec<Liquidity>>,
+}
+
+impl Snapshot {
+    /// Hands the state over to the trackers of a new run, and returns the
+    /// liquidity still live at `now`.
+    pub fn resume(
+        self,
+        fills: &mut FillTracker,
+        open_orders: &mut OpenOrders,
+        now: Timestamp,
+    ) -> BTreeMap<Market, Vec<Liquidity>> {
+        fills.resume(self.positions);
+        open_orders.resume(self.orders);
+        self.liquidity
+            .into_iter()
+            .map(|(market, levels)| {
+                let live = levels
+                    .into_iter()
+                    .filter(|level| matches!(level.expires, Some(expires) if expires > now))
+                    .collect::<Vec<_>>();
+                (market, live)
+            })
+            .filter(|(_, levels)| !levels.is_empty())
+            .collect()
+    }
+}
+
+/// Snapshot file of one wallet on one chain.
+pub struct SnapshotStore {
+    path: PathBuf,
+    address: String,
+    chain_id: ChainId,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl AsRef<Path>, address: &str, chain_id: ChainId) -> Self {
+        let address = address.to_lowercase();
+        Self {
+            path: dir
+                .as_ref()
+                .join(format!("snapshot-{}-{}.json", chain_id, address)),
+            address,
+            chain_id,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn take(
+        &self,
+        fills: &FillTracker,
+        open_orders: &OpenOrders,
+        liquidity: &BTreeMap<Market, Vec<Liquidity>>,
+        now: Timestamp,
+    ) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            address: self.address.clone(),
+            chain_id: self.chain_id,
+            saved_at: now,
+            last_fill_id: fills.last_fill_id(),
+            positions: fills.state(),
+            orders: open_orders.state(),
+            liquidity: liquidity.clone(),
+        }
+    }
+
+    /// Writes the snapshot next to the previous one and then replaces it,
+    /// so that a crash midway leaves the previous one whole.
+    pub fn save(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let partial = self.path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec_pretty(snapshot)?)
+            .map_err(|e| anyhow::anyhow!("Writing snapshot {} failed: {}", partial.display(), e))?;
+        fs::rename(&partial, &self.path).map_err(|e| {
+            anyhow::anyhow!("Replacing snapshot {} failed: {}", self.path.display(), e)
+        })
+    }
+
+    /// The saved snapshot, `None` if there is none yet. Snapshots that do
+    /// not parse whole, of another version, wallet or chain are errors.
+    pub fn load(&self) -> anyhow::Result<Option<Snapshot>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Reading snapshot {} failed: {}",
+                    self.path.display(),
+                    e
+                ))
+            }
+        };
+        let corrupt = |e: serde_json::Error| {
+            anyhow::anyhow!(
+                "Snapshot {} is corrupt ({}), delete it to start afresh!",
+                self.path.display(),
+                e
+            )
+        };
+        let value: serde_json::Value = serde_json::from_slice(&data).map_err(corrupt)?;
+        match value.get("version").and_then(serde_json::Value::as_u64) {
+            Some(SNAPSHOT_VERSION) => (),
+            version => {
+                return Err(anyhow::anyhow!(
+                    "Snapshot {} is version {}, this bot only reads version {}, delete it to start afresh!",
+                    self.path.display(),
+                    version.map_or("unknown".to_owned(), |v| v.to_string()),
+                    SNAPSHOT_VERSION
+                ))
+            }
+        }
+        let snapshot: Snapshot = serde_json::from_value(value).map_err(corrupt)?;
+        if snapshot.address != self.address || snapshot.chain_id != self.chain_id {
+            return Err(anyhow::anyhow!(
+                "Snapshot {} is of {} on chain {}, not {} on chain {}!",
+                self.path.display(),
+                snapshot.address,
+                snapshot.chain_id,
+                self.address,
+                self.chain_id
+            ));
+        }
+        Ok(Some(snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, FillsArgs, Operation, OrderStatus, OrdersArgs, Price, Side};
+    use rust_decimal_macros::dec;
+
+    const ADDRESS: &str = "0x5A0b54D5dc17e0AadC383d2db43B0a0D3E029c4c";
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "zigzag-bots-snapshot-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).expect("create_dir_all");
+        dir
+    }
+
+    #[test]
+    fn test_save_load_reconcile() {
+        let dir = dir("resume");
+        let store = SnapshotStore::new(&dir, ADDRESS, 1000);
+        assert_eq!(store.load().expect("load"), None);
+
+        let mut fills = FillTracker::new("23".into());
+        let mut open_orders = OpenOrders::new("23".into());
+        open_orders.reconcile_orders(
+            &OrdersArgs {
+                orders: vec![
+                    fixtures::order(1, OrderStatus::Open),
+                    fixtures::order(2, OrderStatus::Open),
+                ],
+            },
+            &["ETH-USDC".to_owned()],
+        );
+        for fill in [
+            fixtures::fill(7, Side::Buy, dec!(3300), dec!(0.5), OrderStatus::Filled),
+            fixtures::fill(8, Side::Buy, dec!(3300), dec!(0.25), OrderStatus::Filled),
+        ] {
+            open_orders.on_operation(&Operation::Fillreceipt(fill.clone()), 1000);
+            fills.on_operation(&Operation::Fillreceipt(fill));
+        }
+        let liquidity = [(
+            "ETH-USDC".to_owned(),
+            vec![
+                Liquidity {
+                    side: Side::Buy,
+                    price: Price::Decimal(dec!(3290)),
+                    base_quantity: dec!(0.1),
+                    expires: Some(1030),
+                },
+                Liquidity {
+                    side: Side::Sell,
+                    price: Price::Decimal(dec!(3310)),
+                    base_quantity: dec!(0.1),
+                    expires: Some(1010),
+                },
+            ],
+        )]
+        .into_iter()
+        .collect();
+        let saved = store.take(&fills, &open_orders, &liquidity, 1000);
+        assert_eq!(saved.last_fill_id, Some(8));
+        store.save(&saved).expect("save");
+
+        // A new run, keyed the same however the address is written.
+        let store = SnapshotStore::new(&dir, &ADDRESS.to_lowercase(), 1000);
+        let loaded = store.load().expect("load").expect("snapshot");
+        assert_eq!(loaded, saved);
+        let mut fills = FillTracker::new("23".into());
+        let mut open_orders = OpenOrders::new("23".into());
+        let live = loaded.resume(&mut fills, &mut open_orders, 1020);
+        assert_eq!(live["ETH-USDC"].len(), 1);
+        assert_eq!(fills.position("ETH-USDC"), dec!(0.75));
+
+        // Order 2 filled while the bot was down, fill 8 went on and fill 9
+        // is new: only those are accounted.
+        let result = open_orders.reconcile_orders(
+            &OrdersArgs {
+                orders: vec![
+                    fixtures::order(1, OrderStatus::Open),
+                    fixtures::order(2, OrderStatus::Filled),
+                ],
+            },
+            &["ETH-USDC".to_owned()],
+        );
+        assert_eq!(result.changed, vec![(2, OrderStatus::Filled)]);
+        assert!(result.missing.is_empty() && result.adopted.is_empty());
+        let unseen = open_orders.unseen_fills(&FillsArgs {
+            fills: vec![
+                fixtures::fill(7, Side::Buy, dec!(3300), dec!(0.5), OrderStatus::Filled),
+                fixtures::fill(8, Side::Buy, dec!(3300), dec!(0.5), OrderStatus::Filled),
+                fixtures::fill(9, Side::Buy, dec!(3300), dec!(0.1), OrderStatus::Filled),
+            ],
+        });
+        let trades = fills.on_operation(&Operation::Fills(FillsArgs { fills: unseen }));
+        let traded: Vec<_> = trades.iter().map(|trade| trade.quantity).collect();
+        assert_eq!(traded, vec![dec!(0.25), dec!(0.1)]);
+        assert_eq!(fills.position("ETH-USDC"), dec!(1.1));
+        fs::remove_dir_all(dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn test_rejected_snapshots() {
+        let dir = dir("rejected");
+        let store = SnapshotStore::new(&dir, ADDRESS, 1000);
+        let saved = store.take(
+            &FillTracker::new("23".into()),
+            &OpenOrders::new("23".into()),
+            &BTreeMap::new(),
+            1000,
+        );
+        let error = |data: &str| {
+            fs::write(store.path(), data).expect("write");
+            store.load().expect_err("load").to_string()
+        };
+        let json = serde_json::to_string(&saved).expect("to_string");
+        assert!(error(&json[..json.len() / 2]).contains("is corrupt"));
+        assert!(error(&json.replace("\"version\":1", "\"version\":2"))
+            .contains("is version 2, this bot only reads version 1"));
+        assert!(error(&json.replace("\"positions\"", "\"position\"")).contains("is corrupt"));
+
+        store.save(&saved).expect("save");
+        let other = SnapshotStore::new(&dir, ADDRESS, 1002);
+        fs::rename(store.path(), other.path()).expect("rename");
+        assert!(other
+            .load()
+            .expect_err("load")
+            .to_string()
+            .contains("on chain 1000, not"));
+        fs::remove_dir_all(dir).expect("remove_dir_all");
+    }
+}
@@ -0,0 +1,576 @@
+/// HTTP status endpoint of `--status-addr`, for supervisors: `/healthz`
+/// answers 200 while the bot is connected, logged in, has fresh references
+/// and a healthy zksync RPC endpoint, 503 with the failed checks otherwise, `/status` returns a
+/// JSON snapshot of every market and `/metrics` the counters, gauges and
+/// latency histograms of each account.
+use crate::feeds::FeedsConfig;
+use crate::marketdata::SummaryCache;
+use crate::metrics::{Histogram, Metrics, LATENCY_BUCKETS_MS};
+use crate::portfolio::{FillTracker, Trade};
+use crate::reconcile::OpenOrders;
+use crate::rpc::RpcHealth;
+use crate::strategy::cooldown::CooldownStatus;
+use crate::valuation::Valuation;
+use crate::zigzag::{unix_timestamp, Amount, Decimal, Market, Operation, OrderId, Timestamp};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+/// Largest request head read.
+const MAX_REQUEST: usize = 8192;
+/// Time a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body of `/status`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    pub connected: bool,
+    /// The backend answered our login with the snapshot of our orders
+    pub logged_in: bool,
+    pub reconnects: u64,
+    pub markets: BTreeMap<Market, MarketStatus>,
+    /// Currency of `equity` and `unrealized_pnl`
+    pub valuation_currency: String,
+    /// Value of the token balances, null when unknown
+    pub equity: Option<Decimal>,
+    pub unrealized_pnl: Option<Decimal>,
+    /// Estimated backend time minus ours, null until a fill told
+    pub clock_offset_ms: Option<i64>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MarketStatus {
+    pub open_orders: Vec<OrderId>,
+    pub position: Amount,
+    pub realized_pnl: Decimal,
+    pub mark_price: Option<Decimal>,
+    /// At `mark_price`, in quote units
+    pub unrealized_pnl: Option<Decimal>,
+    /// When we last sent liquidity
+    pub last_quote: Option<Timestamp>,
+    pub last_fill: Option<Trade>,
+    pub last_fill_time: Option<Timestamp>,
+    /// Sides resting after fills, null without a fill cooldown
+    pub fill_cooldown: Option<CooldownStatus>,
+}
+
+/// Body of `/healthz`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Health {
+    pub healthy: bool,
+    /// Checks that failed
+    pub failed: Vec<String>,
+}
+
+/// The status, kept up to date by the bot and shared with the server.
+#[derive(Clone, Debug, Default)]
+pub struct StatusBoard {
+    status: Arc<Mutex<Status>>,
+}
+
+impl StatusBoard {
+    pub fn new(markets: &[Market]) -> Self {
+        let status = Status {
+            markets: markets
+                .iter()
+                .map(|market| (market.clone(), MarketStatus::default()))
+                .collect(),
+            ..Status::default()
+        };
+        Self {
+            status: Arc::new(Mutex::new(status)),
+        }
+    }
+
+    fn status(&self) -> std::sync::MutexGuard<'_, Status> {
+        self.status.lock().unwrap()
+    }
+
+    pub fn snapshot(&self) -> Status {
+        self.status().clone()
+    }
+
+    /// Follows the connection until its sender is dropped, counting
+    /// reconnects.
+    pub async fn watch_connection(self, mut connected: watch::Receiver<bool>) {
+        self.status().connected = *connected.borrow();
+        while connected.changed().await.is_ok() {
+            let up = *connected.borrow();
+            let mut status = self.status();
+            // A quick reconnect can come as a single change.
+            if up {
+                status.reconnects += 1;
+            }
+            status.connected = up;
+        }
+    }
+
+    pub fn on_received(&self, op: &Operation) {
+        if let Operation::Orders(_) = op {
+            self.status().logged_in = true;
+        }
+    }
+
+    pub fn on_sent(&self, op: &Operation, now: Timestamp) {
+        if let Operation::Indicateliq2(args) = op {
+            if let Some(market) = self.status().markets.get_mut(&args.market) {
+                market.last_quote = Some(now);
+            }
+        }
+    }
+
+    pub fn on_trade(&self, trade: &Trade, now: Timestamp) {
+        if let Some(market) = self.status().markets.get_mut(&trade.market) {
+            market.last_fill = Some(trade.clone());
+            market.last_fill_time = Some(now);
+        }
+    }
+
+    pub fn set_fill_cooldown(&self, market: &str, cooldown: CooldownStatus) {
+        if let Some(market) = self.status().markets.get_mut(market) {
+            market.fill_cooldown = Some(cooldown);
+        }
+    }
+
+    /// Takes the positions and open orders of `markets`, from the trackers
+    /// of the account quoting them.
+    pub fn refresh(&self, markets: &[Market], fills: &FillTracker, open_orders: &OpenOrders) {
+        let mut status = self.status();
+        let quoted = status
+            .markets
+            .iter_mut()
+            .filter(|(market, _)| markets.contains(market));
+        for (market, market_status) in quoted {
+            market_status.position = fills.position(market);
+            market_status.realized_pnl = fills.realized_pnl(market);
+            market_status.open_orders = open_orders
+                .open()
+                .filter(|(_, order)| order.market == *market)
+                .map(|(id, _)| *id)
+                .collect();
+            market_status.open_orders.sort_unstable();
+        }
+    }
+
+    pub fn set_clock_offset(&self, offset_ms: Option<i64>) {
+        self.status().clock_offset_ms = offset_ms;
+    }
+
+    /// Takes the marks and unrealized PnL of every market, and the totals.
+    pub fn set_valuation(&self, valuation: &Valuation) {
+        let mut status = self.status();
+        status.valuation_currency = valuation.currency.clone();
+        status.equity = valuation.equity;
+        status.unrealized_pnl = valuation.unrealized_pnl;
+        for (market, market_valuation) in &valuation.markets {
+            if let Some(market_status) = status.markets.get_mut(market) {
+                market_status.mark_price = market_valuation.mark_price;
+                market_status.unrealized_pnl = market_valuation.unrealized_pnl;
+            }
+        }
+    }
+}
+
+/// Histogram of `/metrics`, with its quantiles.
+#[derive(Serialize)]
+struct HistogramBody {
+    #[serde(flatten)]
+    histogram: Histogram,
+    p50_ms: Option<u64>,
+    p99_ms: Option<u64>,
+}
+
+/// Counters, gauges and histograms of an account in `/metrics`.
+fn account_metrics(metrics: &Metrics) -> serde_json::Value {
+    let histograms: BTreeMap<_, _> = metrics
+        .histograms()
+        .into_iter()
+        .map(|(name, histogram)| {
+            let body = HistogramBody {
+                p50_ms: histogram.quantile(0.5),
+                p99_ms: histogram.quantile(0.99),
+                histogram,
+            };
+            (name, body)
+        })
+        .collect();
+    json!({
+        "counters": metrics.counters(),
+        "gauges": metrics.gauges(),
+        "histograms": histograms,
+    })
+}
+
+/// Serves `/healthz`, `/status` and `/metrics`.
+#[derive(Clone)]
+pub struct StatusServer {
+    board: StatusBoard,
+    summaries: SummaryCache,
+    /// Feeds as last reloaded, for the age of their references
+    feeds: watch::Receiver<FeedsConfig>,
+    /// Metrics of each account, by wallet name
+    metrics: BTreeMap<String, Arc<Metrics>>,
+    /// zksync RPC endpoints of each account, by wallet name
+    rpc: BTreeMap<String, Arc<dyn RpcHealth>>,
+}
+
+impl StatusServer {
+    pub fn new(
+        board: StatusBoard,
+        summaries: SummaryCache,
+        feeds: watch::Receiver<FeedsConfig>,
+    ) -> Self {
+        Self {
+            board,
+            summaries,
+            feeds,
+            metrics: BTreeMap::new(),
+            rpc: BTreeMap::new(),
+        }
+    }
+
+    /// Serves `metrics` as those of the `account` wallet.
+    pub fn with_metrics(mut self, account: &str, metrics: Arc<Metrics>) -> Self {
+        self.metrics.insert(account.to_owned(), metrics);
+        self
+    }
+
+    /// Checks the zksync RPC endpoints of the `account` wallet.
+    pub fn with_rpc(mut self, account: &str, rpc: Arc<dyn RpcHealth>) -> Self {
+        self.rpc.insert(account.to_owned(), rpc);
+        self
+    }
+
+    fn metrics_body(&self) -> serde_json::Value {
+        let accounts: BTreeMap<_, _> = self
+            .metrics
+            .iter()
+            .map(|(account, metrics)| (account, account_metrics(metrics)))
+            .collect();
+        json!({
+            "accounts": accounts,
+            "latency_buckets_ms": LATENCY_BUCKETS_MS,
+        })
+    }
+
+    /// Markets priced off the order book have no reference to check.
+    pub fn health(&self, now: Timestamp) -> Health {
+        let status = self.board.snapshot();
+        let mut failed = Vec::new();
+        if !status.connected {
+            failed.push("websocket disconnected".to_owned());
+        }
+        if !status.logged_in {
+            failed.push("login not acknowledged".to_owned());
+        }
+        let feeds = self.feeds.borrow();
+        for market in status.markets.keys() {
+            let max_age = match feeds.source(market) {
+                Some(source) => feeds.max_age(source),
+                None => continue,
+            };
+            match self.summaries.reference(market) {
+                None => failed.push(format!("no reference price for {}", market)),
+                Some(reference) if matches!(max_age, Some(max_age) if reference.is_stale(max_age, now)) => {
+                    failed.push(format!("stale reference price for {}", market))
+                }
+                Some(_) => (),
+            }
+        }
+        // One healthy endpoint is enough, calls fail over to it.
+        for (account, rpc) in &self.rpc {
+            let endpoints = rpc.endpoints();
+            if !endpoints.iter().any(|endpoint| endpoint.healthy) {
+                let names: Vec<_> = endpoints.into_iter().map(|e| e.name).collect();
+                failed.push(format!(
+                    "zksync RPC of the {} wallet unhealthy: {}",
+                    account,
+                    names.join(", ")
+                ));
+            }
+        }
+        Health {
+            healthy: failed.is_empty(),
+            failed,
+        }
+    }
+
+    /// Answers requests on `listener` until the task is dropped.
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::warn!("Accepting a status request: {}", e);
+                    continue;
+                }
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(REQUEST_TIMEOUT, server.respond(stream)).await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => log::debug!("Answering a status request: {}", e),
+                    Err(_) => log::debug!("Status request timed out"),
+                }
+            });
+        }
+    }
+
+    async fn respond(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            if request.len() > MAX_REQUEST {
+                return Err(anyhow::anyhow!("Request too long"));
+            }
+            let read = stream.read(&mut buf).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        let head = String::from_utf8_lossy(&request);
+        let mut words = head.split_whitespace();
+        let method = words.next();
+        let path = words.next().and_then(|target| target.split('?').next());
+        let (code, body) = match (method, path) {
+            (Some("GET"), Some("/healthz")) => {
+                let health = self.health(unix_timestamp());
+                let code = match health.healthy {
+                    true => "200 OK",
+                    false => "503 Service Unavailable",
+                };
+                (code, serde_json::to_string(&health)?)
+            }
+            (Some("GET"), Some("/status")) => {
+                ("200 OK", serde_json::to_string(&self.board.snapshot())?)
+            }
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.metrics_body().to_string()),
+            (Some("GET"), _) => ("404 Not Found", json!({"error": "not found"}).to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                json!({"error": "method not allowed"}).to_string(),
+            ),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ZigzagClient;
+    use crate::connection::{Backoff, Connection, Heartbeat};
+    use crate::feeds::{Reference, Source};
+    use crate::mockserver::MockServer;
+    use crate::rpc::EndpointHealth;
+    use crate::valuation::MarketValuation;
+    use crate::zigzag::Side;
+    use rust_decimal_macros::dec;
+    use serde_json::Value;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn get(url: &str) -> (u16, Value) {
+        let response = reqwest::get(url).await.expect("get");
+        let code = response.status().as_u16();
+        (code, response.json().await.expect("json"))
+    }
+
+    fn feeds() -> FeedsConfig {
+        toml::from_str(
+            r#"
+            [binance]
+            max_age_secs = 30
+            symbols = { "ETH-USDC" = "ethusdc" }
+            "#,
+        )
+        .expect("from_str")
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let server = MockServer::start().await.expect("start");
+        let connection = Connection::connect(
+            server.url(),
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(10)),
+            Heartbeat {
+                interval: Duration::from_secs(60),
+                timeout: Duration::from_secs(60),
+            },
+        )
+        .await
+        .expect("connect");
+        let markets = ["ETH-USDC".to_owned(), "WBTC-USDC".to_owned()];
+        let board = StatusBoard::new(&markets);
+        tokio::spawn(board.clone().watch_connection(connection.status()));
+        let summaries = SummaryCache::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("http://{}", listener.local_addr().expect("local_addr"));
+        let metrics = Arc::new(Metrics::new());
+        let status_server =
+            StatusServer::new(board.clone(), summaries.clone(), watch::channel(feeds()).1)
+                .with_metrics("default", metrics.clone());
+        tokio::spawn(status_server.serve(listener));
+
+        let (code, body) = get(&format!("{}/healthz", url)).await;
+        assert_eq!(code, 503);
+        assert_eq!(
+            body["failed"],
+            serde_json::json!(["login not acknowledged", "no reference price for ETH-USDC"])
+        );
+
+        let mut client = ZigzagClient::new(connection);
+        client.login(1000, "23".into()).await.expect("login");
+        let op = tokio::time::timeout(TIMEOUT, client.recv())
+            .await
+            .expect("timeout")
+            .expect("recv");
+        assert!(matches!(op, Operation::Orders(_)));
+        board.on_received(&op);
+        // Then the fills snapshot.
+        tokio::time::timeout(TIMEOUT, client.recv())
+            .await
+            .expect("timeout")
+            .expect("recv");
+        summaries.set_reference(
+            "ETH-USDC".into(),
+            Reference {
+                price: dec!(2000),
+                source: Source::Binance,
+                updated: unix_timestamp(),
+            },
+        );
+        let (code, body) = get(&format!("{}/healthz", url)).await;
+        assert_eq!(code, 200, "{}", body);
+
+        board.on_trade(
+            &Trade {
+                market: "ETH-USDC".into(),
+                side: Side::Buy,
+                quantity: dec!(0.5),
+                price: dec!(1999),
+            },
+            1000,
+        );
+        board.set_valuation(&Valuation {
+            currency: "USDC".into(),
+            markets: [(
+                "ETH-USDC".to_owned(),
+                MarketValuation {
+                    position: dec!(0.5),
+                    mark_price: Some(dec!(2000)),
+                    unrealized_pnl: Some(dec!(0.5)),
+                    unrealized_value: Some(dec!(0.5)),
+                },
+            )]
+            .into(),
+            unrealized_pnl: Some(dec!(0.5)),
+            equity: None,
+        });
+        board.set_clock_offset(Some(-20_000));
+        let (code, body) = get(&format!("{}/status", url)).await;
+        assert_eq!(code, 200);
+        assert_eq!(body["connected"], true);
+        assert_eq!(body["valuation_currency"], "USDC");
+        assert_eq!(body["equity"], Value::Null);
+        assert_eq!(body["clock_offset_ms"], -20_000);
+        assert_ne!(body["markets"]["ETH-USDC"]["unrealized_pnl"], Value::Null);
+        assert_eq!(body["markets"]["WBTC-USDC"]["unrealized_pnl"], Value::Null);
+        assert_eq!(body["reconnects"], 0);
+        assert_eq!(body["markets"]["ETH-USDC"]["last_fill"]["side"], "b");
+        assert_eq!(body["markets"]["ETH-USDC"]["last_fill_time"], 1000);
+        assert_eq!(body["markets"]["WBTC-USDC"]["last_fill"], Value::Null);
+
+        // The login is replayed on reconnect, answered with orders again.
+        server.disconnect().expect("disconnect");
+        tokio::time::timeout(TIMEOUT, client.recv())
+            .await
+            .expect("timeout")
+            .expect("recv");
+        let reconnected = async {
+            while get(&format!("{}/status", url)).await.1["reconnects"] != 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(TIMEOUT, reconnected)
+            .await
+            .expect("reconnects");
+        metrics.incr("sent_login");
+        metrics.observe("latency_submitorder3", Duration::from_millis(42));
+        let (code, body) = get(&format!("{}/metrics", url)).await;
+        assert_eq!(code, 200);
+        let account = &body["accounts"]["default"];
+        assert_eq!(account["counters"]["sent_login"], 1);
+        assert_eq!(account["histograms"]["latency_submitorder3"]["count"], 1);
+        assert_eq!(account["histograms"]["latency_submitorder3"]["p99_ms"], 42);
+        assert_eq!(get(&format!("{}/nothing", url)).await.0, 404);
+    }
+
+    #[test]
+    fn test_stale_reference() {
+        let board = StatusBoard::new(&["ETH-USDC".to_owned()]);
+        board.status().connected = true;
+        board.on_received(&Operation::Orders(crate::zigzag::OrdersArgs {
+            orders: Vec::new(),
+        }));
+        let summaries = SummaryCache::new();
+        summaries.set_reference(
+            "ETH-USDC".into(),
+            Reference {
+                price: dec!(2000),
+                source: Source::Binance,
+                updated: 1000,
+            },
+        );
+        let server = StatusServer::new(board, summaries, watch::channel(feeds()).1);
+        assert!(server.health(1030).healthy);
+        assert_eq!(
+            server.health(1031).failed,
+            vec!["stale reference price for ETH-USDC"]
+        );
+    }
+
+    struct Endpoints(Vec<bool>);
+
+    impl RpcHealth for Endpoints {
+        fn endpoints(&self) -> Vec<EndpointHealth> {
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(i, healthy)| EndpointHealth {
+                    name: format!("rpc{}", i),
+                    healthy: *healthy,
+                    consecutive_failures: if *healthy { 0 } else { 3 },
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_rpc_health() {
+        let board = StatusBoard::new(&[]);
+        board.status().connected = true;
+        board.status().logged_in = true;
+        let server = StatusServer::new(board, SummaryCache::new(), watch::channel(feeds()).1)
+            .with_rpc("default", Arc::new(Endpoints(vec![false, true])))
+            .with_rpc("taker", Arc::new(Endpoints(vec![false, false])));
+        assert_eq!(
+            server.health(0).failed,
+            vec!["zksync RPC of the taker wallet unhealthy: rpc0, rpc1"]
+        );
+    }
+}
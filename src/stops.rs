@@ -0,0 +1,337 @@
+/// Client-side stop-loss and take-profit of the positions, in basis points
+/// from their average entry price. Triggers are edge-triggered: once fired,
+/// a level re-arms only after the price moved back past it by the
+/// hysteresis. A triggered market cools down before it may fire again.
+use crate::zigzag::{Amount, Decimal, Market, Timestamp};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// `[stops]` table of the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StopsConfig {
+    pub markets: HashMap<Market, StopLevels>,
+    /// How far back past a level the price must go to re-arm it
+    pub hysteresis_bps: Decimal,
+    /// Time a triggered market stops quoting
+    pub cooldown_secs: u64,
+    /// Taker orders the position is flattened in
+    pub slices: u32,
+    /// Worst price accepted over the reference when flattening
+    pub max_slippage_bps: Decimal,
+    /// Wait for a quote, then for the fill of each slice
+    pub timeout_secs: u64,
+    pub order_expires_secs: u64,
+}
+
+impl Default for StopsConfig {
+    fn default() -> Self {
+        Self {
+            markets: HashMap::new(),
+            hysteresis_bps: Decimal::from(20),
+            cooldown_secs: 600,
+            slices: 1,
+            max_slippage_bps: Decimal::from(100),
+            timeout_secs: 10,
+            order_expires_secs: 60,
+        }
+    }
+}
+
+impl StopsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.slices == 0 {
+            return Err(anyhow::anyhow!("stops.slices must be at least 1!"));
+        }
+        if self.hysteresis_bps < Decimal::ZERO {
+            return Err(anyhow::anyhow!("stops.hysteresis_bps cannot be negative!"));
+        }
+        for (market, levels) in &self.markets {
+            let levels = [levels.stop_loss_bps, levels.take_profit_bps];
+            if levels.iter().flatten().any(|bps| *bps <= Decimal::ZERO) {
+                return Err(anyhow::anyhow!(
+                    "stops.markets.{}: levels must be positive!",
+                    market
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Levels of one market, away from the average entry price.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StopLevels {
+    pub stop_loss_bps: Option<Decimal>,
+    pub take_profit_bps: Option<Decimal>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopKind {
+    StopLoss,
+    TakeProfit,
+}
+
+impl fmt::Display for StopKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopKind::StopLoss => write!(f, "stop-loss"),
+            StopKind::TakeProfit => write!(f, "take-profit"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trigger {
+    pub market: Market,
+    pub kind: StopKind,
+    pub price: Decimal,
+    pub entry_price: Decimal,
+    pub position: Amount,
+    /// Profit of the position at `price`, negative for a loss
+    pub pnl_bps: Decimal,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} {} at {}, entry {} ({} bps)",
+            self.kind,
+            self.market,
+            self.position.normalize(),
+            self.price.normalize(),
+            self.entry_price.round_dp(8).normalize(),
+            self.pnl_bps.round_dp(1).normalize()
+        )
+    }
+}
+
+struct MarketState {
+    stop_loss_armed: bool,
+    take_profit_armed: bool,
+    cooldown_until: Option<Timestamp>,
+}
+
+impl Default for MarketState {
+    fn default() -> Self {
+        Self {
+            stop_loss_armed: true,
+            take_profit_armed: true,
+            cooldown_until: None,
+        }
+    }
+}
+
+pub struct Stops {
+    config: StopsConfig,
+    markets: HashMap<Market, MarketState>,
+}
+
+impl Stops {
+    pub fn new(config: StopsConfig) -> Self {
+        Self {
+            config,
+            markets: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &StopsConfig {
+        &self.config
+    }
+
+    /// Whether `market` is cooling down from a trigger.
+    pub fn in_cooldown(&self, market: &str, now: Timestamp) -> bool {
+        matches!(
+            self.markets.get(market).and_then(|state| state.cooldown_until),
+            Some(until) if now < until
+        )
+    }
+
+    /// Records the reference price of `market`, where `position` was
+    /// entered at `entry_price` on average. Returns the trigger when a
+    /// level is crossed while armed, which starts the cooldown.
+    pub fn on_price(
+        &mut self,
+        market: &str,
+        price: Decimal,
+        position: Amount,
+        entry_price: Option<Decimal>,
+        now: Timestamp,
+    ) -> Option<Trigger> {
+        let levels = self.config.markets.get(market)?;
+        let hysteresis = self.config.hysteresis_bps;
+        let state = self.markets.entry(market.to_owned()).or_default();
+        let entry_price = match entry_price {
+            Some(entry) if !position.is_zero() && entry > Decimal::ZERO => entry,
+            // A new position starts with both levels armed.
+            _ => {
+                state.stop_loss_armed = true;
+                state.take_profit_armed = true;
+                return None;
+            }
+        };
+        if matches!(state.cooldown_until, Some(until) if now < until) {
+            return None;
+        }
+        let moved_bps = (price - entry_price) / entry_price * Decimal::from(10_000);
+        let pnl_bps = match position > Decimal::ZERO {
+            true => moved_bps,
+            false => -moved_bps,
+        };
+        let mut kind = None;
+        if let Some(stop_loss) = levels.stop_loss_bps {
+            if pnl_bps <= -stop_loss {
+                if state.stop_loss_armed {
+                    state.stop_loss_armed = false;
+                    kind = Some(StopKind::StopLoss);
+                }
+            } else if pnl_bps > -stop_loss + hysteresis {
+                state.stop_loss_armed = true;
+            }
+        }
+        if let Some(take_profit) = levels.take_profit_bps {
+            if pnl_bps >= take_profit {
+                if state.take_profit_armed {
+                    state.take_profit_armed = false;
+                    kind = Some(StopKind::TakeProfit);
+                }
+            } else if pnl_bps < take_profit - hysteresis {
+                state.take_profit_armed = true;
+            }
+        }
+        let kind = kind?;
+        state.cooldown_until = Some(now + self.config.cooldown_secs);
+        Some(Trigger {
+            market: market.to_owned(),
+            kind,
+            price,
+            entry_price,
+            position,
+            pnl_bps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn stops(cooldown_secs: u64) -> Stops {
+        Stops::new(StopsConfig {
+            markets: HashMap::from([(
+                "ETH-USDC".into(),
+                StopLevels {
+                    stop_loss_bps: Some(dec!(500)),
+                    take_profit_bps: Some(dec!(1000)),
+                },
+            )]),
+            hysteresis_bps: dec!(50),
+            cooldown_secs,
+            ..StopsConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_trigger() {
+        let mut stops = stops(0);
+        let entry = Some(dec!(2000));
+        assert_eq!(
+            stops.on_price("ETH-USDC", dec!(1950), dec!(1), entry, 0),
+            None
+        );
+        let trigger = stops
+            .on_price("ETH-USDC", dec!(1900), dec!(1), entry, 1)
+            .expect("stop-loss");
+        assert_eq!(trigger.kind, StopKind::StopLoss);
+        assert_eq!(
+            trigger.to_string(),
+            "stop-loss of ETH-USDC 1 at 1900, entry 2000 (-500 bps)"
+        );
+
+        // A short loses as the price rises.
+        let mut stops = self::stops(0);
+        let trigger = stops
+            .on_price("ETH-USDC", dec!(2100), dec!(-2), entry, 0)
+            .expect("stop-loss");
+        assert_eq!(trigger.kind, StopKind::StopLoss);
+        let trigger = stops
+            .on_price("ETH-USDC", dec!(1800), dec!(-2), entry, 1)
+            .expect("take-profit");
+        assert_eq!(trigger.kind, StopKind::TakeProfit);
+
+        // Flat, or on markets without levels, nothing triggers.
+        assert_eq!(
+            stops.on_price("ETH-USDC", dec!(100), dec!(0), entry, 2),
+            None
+        );
+        assert_eq!(
+            stops.on_price("WBTC-USDC", dec!(100), dec!(1), entry, 2),
+            None
+        );
+        assert!(stops.config().validate().is_ok());
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut stops = stops(0);
+        let entry = Some(dec!(2000));
+        let mut fired = Vec::new();
+        // Oscillating around the stop-loss at 1900 fires once, until the
+        // price is back above 1910.
+        for (now, price) in [1899, 1901, 1899, 1905, 1899, 1911, 1899]
+            .into_iter()
+            .enumerate()
+        {
+            let price = Decimal::from(price);
+            if let Some(trigger) = stops.on_price("ETH-USDC", price, dec!(1), entry, now as u64) {
+                fired.push(trigger.price);
+            }
+        }
+        assert_eq!(fired, [dec!(1899), dec!(1899)]);
+
+        // A flat market re-arms.
+        let mut stops = self::stops(0);
+        assert!(stops
+            .on_price("ETH-USDC", dec!(2200), dec!(1), entry, 0)
+            .is_some());
+        assert!(stops
+            .on_price("ETH-USDC", dec!(2200), dec!(1), entry, 1)
+            .is_none());
+        assert!(stops
+            .on_price("ETH-USDC", dec!(2200), dec!(0), None, 2)
+            .is_none());
+        assert!(stops
+            .on_price("ETH-USDC", dec!(2200), dec!(1), entry, 3)
+            .is_some());
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut stops = stops(60);
+        let entry = Some(dec!(2000));
+        assert!(!stops.in_cooldown("ETH-USDC", 0));
+        assert!(stops
+            .on_price("ETH-USDC", dec!(1800), dec!(1), entry, 100)
+            .is_some());
+        assert!(stops.in_cooldown("ETH-USDC", 159));
+        // A new position is not stopped out while cooling down.
+        assert!(stops
+            .on_price("ETH-USDC", dec!(1800), dec!(0), None, 110)
+            .is_none());
+        assert!(stops
+            .on_price("ETH-USDC", dec!(1800), dec!(1), entry, 120)
+            .is_none());
+        assert!(!stops.in_cooldown("ETH-USDC", 160));
+        assert!(stops
+            .on_price("ETH-USDC", dec!(1800), dec!(1), entry, 160)
+            .is_some());
+
+        let mut invalid = stops.config().clone();
+        invalid.slices = 0;
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,567 @@
+/// SQLite history of our orders, fills and advertised liquidity, so that
+/// positions and PnL survive restarts. Records are queued by a `Recorder`
+/// and written in batches by a blocking task, off the dispatcher's path.
+use crate::portfolio;
+use crate::zigzag::{
+    unix_timestamp, ChainId, Decimal, Fill, FillStatus, Liquidity, Market, Operation, Order, Price,
+    Timestamp, UserId, UserorderackArgs,
+};
+use rusqlite::{params, Connection};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Schema migrations, the database is at version `n` once the first `n`
+/// ran. Append new ones, never edit released ones.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE orders (
+        recorded_at INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        chain_id INTEGER NOT NULL,
+        order_id INTEGER NOT NULL,
+        market TEXT NOT NULL,
+        side TEXT NOT NULL,
+        price TEXT NOT NULL,
+        base_quantity TEXT NOT NULL,
+        status TEXT NOT NULL,
+        raw TEXT NOT NULL
+    );
+    CREATE INDEX orders_order_id ON orders (order_id);
+    CREATE TABLE fills (
+        recorded_at INTEGER NOT NULL,
+        chain_id INTEGER NOT NULL,
+        fill_id INTEGER NOT NULL,
+        market TEXT NOT NULL,
+        side TEXT NOT NULL,
+        price TEXT NOT NULL,
+        base_quantity TEXT NOT NULL,
+        status TEXT NOT NULL,
+        raw TEXT NOT NULL
+    );
+    CREATE INDEX fills_market ON fills (market);
+    CREATE TABLE liquidity (
+        recorded_at INTEGER NOT NULL,
+        chain_id INTEGER NOT NULL,
+        market TEXT NOT NULL,
+        side TEXT NOT NULL,
+        price TEXT NOT NULL,
+        base_quantity TEXT NOT NULL,
+        expires INTEGER
+    );
+"#];
+
+/// Most records written in one transaction.
+const MAX_BATCH: usize = 500;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    OrderAck(UserorderackArgs),
+    OrderReceipt(Order),
+    Fill(Fill),
+    /// Settlement update of a fill, applied to the stored receipts of the
+    /// fill if it is ours
+    FillStatus(FillStatus),
+    /// One level of liquidity we advertised
+    Liquidity {
+        chain_id: ChainId,
+        market: Market,
+        level: Liquidity,
+    },
+}
+
+impl Record {
+    /// Records of our orders and fills among an incoming operation.
+    pub fn incoming(op: &Operation, user_id: &str) -> Vec<Record> {
+        let ours = |fill: &Fill| fill.taker_user_id == user_id || fill.maker_user_id == user_id;
+        match op {
+            Operation::Userorderack(ack) if ack.user_id == user_id => {
+                vec![Record::OrderAck(ack.clone())]
+            }
+            Operation::Orderreceipt(order) if order.user_id == user_id => {
+                vec![Record::OrderReceipt(order.clone())]
+            }
+            Operation::Fillreceipt(fill) if ours(fill) => vec![Record::Fill(fill.clone())],
+            Operation::Fills(args) => args
+                .fills
+                .iter()
+                .filter(|fill| ours(fill))
+                .cloned()
+                .map(Record::Fill)
+                .collect(),
+            Operation::Fillstatus(args) => args
+                .statuses
+                .iter()
+                .cloned()
+                .map(Record::FillStatus)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn price(&self) -> Option<&Price> {
+        match self {
+            Record::OrderAck(ack) => Some(&ack.price),
+            Record::OrderReceipt(order) => Some(&order.price),
+            Record::Fill(fill) => Some(&fill.price),
+            Record::FillStatus(_) => None,
+            Record::Liquidity { level, .. } => Some(&level.price),
+        }
+    }
+
+    /// Records of the liquidity levels of an outgoing operation.
+    pub fn outgoing(op: &Operation) -> Vec<Record> {
+        match op {
+            Operation::Indicateliq2(args) => args
+                .liquidity
+                .iter()
+                .map(|level| Record::Liquidity {
+                    chain_id: args.chain_id,
+                    market: args.market.clone(),
+                    level: level.clone(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+pub struct Storage {
+    connection: Connection,
+}
+
+impl Storage {
+    /// Opens or creates the database at `path` and brings its schema up to
+    /// date.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .map_err(|e| anyhow::anyhow!("Opening database {} failed: {}", path.display(), e))?;
+        Self::new(connection)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(connection: Connection) -> anyhow::Result<Self> {
+        let mut storage = Self { connection };
+        storage.migrate()?;
+        Ok(storage)
+    }
+
+    pub fn version(&self) -> anyhow::Result<usize> {
+        let version: u32 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(version as usize)
+    }
+
+    fn migrate(&mut self) -> anyhow::Result<()> {
+        let version = self.version()?;
+        if version > MIGRATIONS.len() {
+            return Err(anyhow::anyhow!(
+                "Database schema version {} is newer than this bot's {}!",
+                version,
+                MIGRATIONS.len()
+            ));
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = self.connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", i as u32 + 1)?;
+            transaction.commit()?;
+            log::info!("Migrated database schema to version {}", i + 1);
+        }
+        Ok(())
+    }
+
+    /// Writes records, all or none of them.
+    pub fn insert(&mut self, records: &[(Timestamp, Record)]) -> anyhow::Result<()> {
+        let transaction = self.connection.transaction()?;
+        for (recorded_at, record) in records {
+            let price = match record.price().map(Price::value) {
+                Some(Ok(price)) => price.to_string(),
+                None => String::new(),
+                Some(Err(e)) => {
+                    log::warn!("Not storing a record: {}", e);
+                    continue;
+                }
+            };
+            match record {
+                Record::OrderAck(ack) => transaction.execute(
+                    "INSERT INTO orders VALUES (?1, 'ack', ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        recorded_at,
+                        ack.chain_id,
+                        ack.id,
+                        ack.market,
+                        code(&ack.side)?,
+                        price,
+                        ack.base_quantity.to_string(),
+                        code(&ack.order_status)?,
+                        serde_json::to_string(ack)?,
+                    ],
+                )?,
+                Record::OrderReceipt(order) => transaction.execute(
+                    "INSERT INTO orders VALUES (?1, 'receipt', ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        recorded_at,
+                        order.chain_id,
+                        order.id,
+                        order.market,
+                        code(&order.side)?,
+                        price,
+                        order.base_quantity.to_string(),
+                        code(&order.order_status)?,
+                        serde_json::to_string(order)?,
+                    ],
+                )?,
+                Record::Fill(fill) => transaction.execute(
+                    "INSERT INTO fills VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        recorded_at,
+                        fill.chain_id,
+                        fill.id,
+                        fill.market,
+                        code(&fill.side)?,
+                        price,
+                        fill.base_quantity.to_string(),
+                        code(&fill.fill_status)?,
+                        serde_json::to_string(fill)?,
+                    ],
+                )?,
+                Record::FillStatus(status) => {
+                    let mut statement = transaction.prepare(
+                        "SELECT rowid, raw FROM fills WHERE chain_id = ?1 AND fill_id = ?2",
+                    )?;
+                    let rows = statement
+                        .query_map(params![status.chain_id, status.full_id], |row| {
+                            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for (rowid, raw) in &rows {
+                        let mut fill: Fill = serde_json::from_str(raw)?;
+                        portfolio::apply_status(&mut fill, status);
+                        transaction.execute(
+                            "UPDATE fills SET status = ?1, raw = ?2 WHERE rowid = ?3",
+                            params![
+                                code(&fill.fill_status)?,
+                                serde_json::to_string(&fill)?,
+                                rowid
+                            ],
+                        )?;
+                    }
+                    rows.len()
+                }
+                Record::Liquidity {
+                    chain_id,
+                    market,
+                    level,
+                } => transaction.execute(
+                    "INSERT INTO liquidity VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        recorded_at,
+                        chain_id,
+                        market,
+                        code(&level.side)?,
+                        price,
+                        level.base_quantity.to_string(),
+                        level.expires,
+                    ],
+                )?,
+            };
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Every stored fill receipt, oldest first.
+    pub fn fills(&self) -> anyhow::Result<Vec<Fill>> {
+        Ok(self
+            .fill_history()?
+            .into_iter()
+            .map(|(_, fill)| fill)
+            .collect())
+    }
+
+    /// Every stored fill receipt with the time it was recorded at, oldest
+    /// first.
+    pub fn fill_history(&self) -> anyhow::Result<Vec<(Timestamp, Fill)>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT recorded_at, raw FROM fills ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, Timestamp>(0)?, row.get::<_, String>(1)?))
+        })?;
+        rows.map(|row| {
+            let (recorded_at, raw) = row?;
+            Ok((recorded_at, serde_json::from_str(&raw)?))
+        })
+        .collect()
+    }
+
+    /// Every stored order ack and receipt, oldest first.
+    pub fn orders(&self) -> anyhow::Result<Vec<Record>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT kind, raw FROM orders ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        rows.map(|row| {
+            let (kind, raw) = row?;
+            match kind.as_str() {
+                "ack" => Ok(Record::OrderAck(serde_json::from_str(&raw)?)),
+                "receipt" => Ok(Record::OrderReceipt(serde_json::from_str(&raw)?)),
+                _ => Err(anyhow::anyhow!("Unknown order record kind {}", kind)),
+            }
+        })
+        .collect()
+    }
+
+    /// Every stored liquidity level, oldest first.
+    pub fn liquidity(&self) -> anyhow::Result<Vec<Record>> {
+        let mut statement = self.connection.prepare(
+            "SELECT chain_id, market, side, price, base_quantity, expires FROM liquidity ORDER BY rowid",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, ChainId>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<Timestamp>>(5)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (chain_id, market, side, price, base_quantity, expires) = row?;
+            Ok(Record::Liquidity {
+                chain_id,
+                market,
+                level: Liquidity {
+                    side: from_code(&side)?,
+                    price: Price::Decimal(Decimal::from_str(&price)?),
+                    base_quantity: Decimal::from_str(&base_quantity)?,
+                    expires,
+                },
+            })
+        })
+        .collect()
+    }
+}
+
+/// Wire code of a side or status, e.g. "b" or "f".
+fn code(value: &impl Serialize) -> anyhow::Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(code) => Ok(code),
+        other => Err(anyhow::anyhow!("{} is not a code", other)),
+    }
+}
+
+fn from_code<T: DeserializeOwned>(code: &str) -> anyhow::Result<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(
+        code.to_owned(),
+    ))?)
+}
+
+/// Cloneable handle queueing records for the writer task.
+#[derive(Clone)]
+pub struct Recorder {
+    user_id: UserId,
+    sender: mpsc::UnboundedSender<(Timestamp, Record)>,
+}
+
+impl Recorder {
+    /// Starts the writer task, which stops once every `Recorder` is dropped
+    /// and the queue is written.
+    pub fn spawn(storage: Storage, user_id: UserId) -> (Self, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let writer = tokio::task::spawn_blocking(move || write_batches(storage, receiver));
+        (Self { user_id, sender }, writer)
+    }
+
+    pub fn incoming(&self, op: &Operation) {
+        self.queue(Record::incoming(op, &self.user_id));
+    }
+
+    pub fn outgoing(&self, op: &Operation) {
+        self.queue(Record::outgoing(op));
+    }
+
+    fn queue(&self, records: Vec<Record>) {
+        let now = unix_timestamp();
+        for record in records {
+            if self.sender.send((now, record)).is_err() {
+                log::warn!("Database writer has stopped, dropping records");
+                return;
+            }
+        }
+    }
+}
+
+fn write_batches(mut storage: Storage, mut receiver: mpsc::UnboundedReceiver<(Timestamp, Record)>) {
+    while let Some(first) = receiver.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(record) => batch.push(record),
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = storage.insert(&batch) {
+            log::error!("Storing {} records failed: {}", batch.len(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::FillTracker;
+    use crate::zigzag::{fixtures, OrderStatus, Side};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_round_trip() {
+        let ack: Operation = serde_json::from_str(
+            r#"{"op":"userorderack","args":[1000,40,"ETH-USDC","s","3370.93",0.1,337.093,4294967295,"23","o",null,0.1]}"#,
+        )
+        .expect("from_str");
+        let receipt: Operation = serde_json::from_str(
+            r#"{"op":"orderreceipt","args":[1000,40,"ETH-USDC","s",3370.93,0.1,337.093,4294967295,"23","f"]}"#,
+        )
+        .expect("from_str");
+        let fills = Operation::Fillreceipt(fixtures::fill(
+            7,
+            Side::Buy,
+            dec!(3370.93),
+            dec!(0.1),
+            OrderStatus::Filled,
+        ));
+        let liquidity: Operation = serde_json::from_str(
+            r#"{"op":"indicateliq2","args":[1000,"ETH-USDC",[["b",3300.5,0.5,1700000000],["s",3400,0.25]]]}"#,
+        )
+        .expect("from_str");
+
+        let mut records = Vec::new();
+        for op in [&ack, &receipt, &fills] {
+            records.extend(Record::incoming(op, "23"));
+        }
+        records.extend(Record::outgoing(&liquidity));
+        assert_eq!(records.len(), 5);
+        // Other users' orders and fills are left out.
+        assert!(Record::incoming(&ack, "7").is_empty());
+        assert!(Record::incoming(&fills, "7").is_empty());
+
+        let mut storage = Storage::open_in_memory().expect("open");
+        assert_eq!(storage.version().expect("version"), MIGRATIONS.len());
+        let timestamped: Vec<_> = records.iter().cloned().map(|r| (100, r)).collect();
+        storage.insert(&timestamped).expect("insert");
+
+        assert_eq!(storage.orders().expect("orders"), records[..2]);
+        assert_eq!(
+            storage.fills().expect("fills"),
+            vec![fixtures::fill(
+                7,
+                Side::Buy,
+                dec!(3370.93),
+                dec!(0.1),
+                OrderStatus::Filled
+            )]
+        );
+        let levels = storage.liquidity().expect("liquidity");
+        assert_eq!(levels, records[3..]);
+        assert!(matches!(
+            &levels[1],
+            Record::Liquidity {
+                level: Liquidity {
+                    side: Side::Sell,
+                    expires: None,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_fill_status() {
+        let mut storage = Storage::open_in_memory().expect("open");
+        let matched = |id| fixtures::fill(id, Side::Buy, dec!(3000), dec!(1), OrderStatus::Matched);
+        let statuses: Operation = serde_json::from_str(
+            r#"{"op":"fillstatus","args":[[
+                [1000,1,"f","0x600a2d8a66d8a23c7d9d7b2a5e91e4f2b9a6c3d2e1f0a9b8c7d6e5f4a3b21ed9",0,1.5,"USDC",1700000000],
+                [1000,2,"r",null,0,0,"USDC",1700000000],
+                [1000,9,"f",null,0,0,"USDC",1700000000]
+            ]]}"#,
+        )
+        .expect("from_str");
+        let records = Record::incoming(&statuses, "23");
+        assert_eq!(records.len(), 3);
+        let mut batch = vec![
+            (100, Record::Fill(matched(1))),
+            (100, Record::Fill(matched(2))),
+        ];
+        batch.extend(records.into_iter().map(|record| (110, record)));
+        storage.insert(&batch).expect("insert");
+
+        let fills = storage.fills().expect("fills");
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].fill_status, OrderStatus::Filled);
+        assert_eq!(fills[0].fee_amount, Some(dec!(1.5)));
+        assert_eq!(fills[0].fee_token.as_deref(), Some("USDC"));
+        assert_eq!(fills[0].timestamp.as_deref(), Some("2023-11-14T22:13:20Z"));
+        assert!(fills[0].tx_hash.is_some());
+        assert_eq!(fills[1].fill_status, OrderStatus::Rejected);
+
+        // The rejected fill no longer counts once restored.
+        let mut restored = FillTracker::new("23".into());
+        restored.restore(&fills);
+        assert_eq!(restored.position("ETH-USDC"), dec!(1));
+        assert_eq!(restored.realized_pnl("ETH-USDC"), dec!(-1.5));
+    }
+
+    #[test]
+    fn test_rebuild_positions() {
+        let path = std::env::temp_dir().join(format!("zigzag-bots-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fills = [
+            fixtures::fill(1, Side::Buy, dec!(3000), dec!(1), OrderStatus::Filled),
+            fixtures::fill(2, Side::Buy, dec!(3300), dec!(1), OrderStatus::Filled),
+            fixtures::fill(3, Side::Sell, dec!(3250), dec!(0.5), OrderStatus::Filled),
+            // A later receipt of the same partially filled fill.
+            fixtures::fill(3, Side::Sell, dec!(3250), dec!(1), OrderStatus::Filled),
+        ];
+        let mut live = FillTracker::new("23".into());
+        {
+            let mut storage = Storage::open(&path).expect("open");
+            for fill in &fills {
+                live.apply(fill);
+                storage
+                    .insert(&[(100, Record::Fill(fill.clone()))])
+                    .expect("insert");
+            }
+        }
+
+        // Reopening keeps the schema and the history.
+        let storage = Storage::open(&path).expect("reopen");
+        let mut restored = FillTracker::new("23".into());
+        restored.restore(&storage.fills().expect("fills"));
+        assert_eq!(restored.market("ETH-USDC"), live.market("ETH-USDC"));
+        assert_eq!(restored.position("ETH-USDC"), dec!(1));
+        assert_eq!(restored.realized_pnl("ETH-USDC"), dec!(100));
+
+        // Databases of newer versions are refused.
+        storage
+            .connection
+            .pragma_update(None, "user_version", 99)
+            .expect("pragma_update");
+        drop(storage);
+        assert!(Storage::open(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
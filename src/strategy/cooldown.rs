@@ -0,0 +1,236 @@
+/// Cooldown of the market maker after its fills. A filled side rests for a
+/// while, pulled or quoted wider, so that the move that filled it does not
+/// fill it again at the same level. Consecutive fills closer together than
+/// the minimum interval halt the whole market.
+use crate::zigzag::{Decimal, Side, Timestamp};
+use serde::{Deserialize, Serialize};
+
+/// `fill_cooldown` table of `[market_maker]` and its per-market overrides.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FillCooldownConfig {
+    /// Time a side rests after one of its levels got filled
+    pub secs: u64,
+    /// Quote a resting side this much wider instead of pulling it, in
+    /// basis points
+    pub widen_bps: Option<Decimal>,
+    /// Fills of the market closer together than this halt it
+    pub min_fill_interval_secs: Option<u64>,
+    /// Time a halted market stops quoting
+    pub halt_secs: u64,
+}
+
+impl Default for FillCooldownConfig {
+    fn default() -> Self {
+        Self {
+            secs: 10,
+            widen_bps: None,
+            min_fill_interval_secs: None,
+            halt_secs: 300,
+        }
+    }
+}
+
+impl FillCooldownConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.secs == 0 {
+            return Err(anyhow::anyhow!("fill_cooldown.secs must be at least 1!"));
+        }
+        if matches!(self.widen_bps, Some(bps) if bps <= Decimal::ZERO) {
+            return Err(anyhow::anyhow!("fill_cooldown.widen_bps must be positive!"));
+        }
+        if self.min_fill_interval_secs == Some(0) {
+            return Err(anyhow::anyhow!(
+                "fill_cooldown.min_fill_interval_secs must be at least 1!"
+            ));
+        }
+        if self.halt_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "fill_cooldown.halt_secs must be at least 1!"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Longest a fill matters for: older ones neither rest a side nor
+    /// count toward the interval.
+    pub fn window_secs(&self) -> u64 {
+        self.secs
+            .max(self.min_fill_interval_secs.unwrap_or_default())
+    }
+}
+
+/// How a side is quoted at some time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SideQuoting {
+    Normal,
+    /// Offsets widened by this many basis points
+    Widened(Decimal),
+    Pulled,
+}
+
+/// Cooldown of a market in `/status`, with what is over left out.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct CooldownStatus {
+    /// Our bids rest until then
+    pub bids_until: Option<Timestamp>,
+    pub asks_until: Option<Timestamp>,
+    /// Nothing is quoted until then
+    pub halted_until: Option<Timestamp>,
+    /// Extra offset of a resting side, pulled when null
+    pub widen_bps: Option<Decimal>,
+}
+
+pub struct FillCooldown {
+    config: FillCooldownConfig,
+    bids_until: Option<Timestamp>,
+    asks_until: Option<Timestamp>,
+    halted_until: Option<Timestamp>,
+    last_fill: Option<Timestamp>,
+}
+
+impl FillCooldown {
+    pub fn new(config: FillCooldownConfig) -> Self {
+        Self {
+            config,
+            bids_until: None,
+            asks_until: None,
+            halted_until: None,
+            last_fill: None,
+        }
+    }
+
+    pub fn config(&self) -> &FillCooldownConfig {
+        &self.config
+    }
+
+    /// Changes the settings. Rests and halts already started keep their
+    /// end, later fills follow the new settings.
+    pub fn set_config(&mut self, config: FillCooldownConfig) {
+        self.config = config;
+    }
+
+    /// Rests our `side` after it got filled at `time`. Returns the interval
+    /// since the previous fill when it is too short, which halts the market
+    /// unless it already is.
+    pub fn on_fill(&mut self, side: &Side, time: Timestamp) -> Option<u64> {
+        let until = match side {
+            Side::Buy => &mut self.bids_until,
+            Side::Sell => &mut self.asks_until,
+        };
+        *until = (*until).max(Some(time + self.config.secs));
+        let previous = self.last_fill;
+        self.last_fill = previous.max(Some(time));
+        let (previous, min_interval) = match (previous, self.config.min_fill_interval_secs) {
+            (Some(previous), Some(min_interval)) => (previous, min_interval),
+            _ => return None,
+        };
+        let interval = time.saturating_sub(previous);
+        if interval >= min_interval || self.halted(time) {
+            return None;
+        }
+        self.halted_until = Some(time + self.config.halt_secs);
+        Some(interval)
+    }
+
+    /// Whether the market is halted at `now`.
+    pub fn halted(&self, now: Timestamp) -> bool {
+        matches!(self.halted_until, Some(until) if now < until)
+    }
+
+    /// How our `side` is quoted at `now`.
+    pub fn side(&self, side: &Side, now: Timestamp) -> SideQuoting {
+        let until = match side {
+            Side::Buy => self.bids_until,
+            Side::Sell => self.asks_until,
+        };
+        match (until, self.config.widen_bps) {
+            (Some(until), Some(bps)) if now < until => SideQuoting::Widened(bps),
+            (Some(until), None) if now < until => SideQuoting::Pulled,
+            _ => SideQuoting::Normal,
+        }
+    }
+
+    pub fn status(&self, now: Timestamp) -> CooldownStatus {
+        let pending = |until: Option<Timestamp>| until.filter(|until| now < *until);
+        CooldownStatus {
+            bids_until: pending(self.bids_until),
+            asks_until: pending(self.asks_until),
+            halted_until: pending(self.halted_until),
+            widen_bps: self.config.widen_bps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config() -> FillCooldownConfig {
+        FillCooldownConfig {
+            secs: 10,
+            widen_bps: None,
+            min_fill_interval_secs: Some(2),
+            halt_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_side_rests() {
+        let mut cooldown = FillCooldown::new(FillCooldownConfig {
+            min_fill_interval_secs: None,
+            ..config()
+        });
+        assert_eq!(cooldown.on_fill(&Side::Buy, 100), None);
+        assert_eq!(cooldown.side(&Side::Buy, 109), SideQuoting::Pulled);
+        assert_eq!(cooldown.side(&Side::Sell, 109), SideQuoting::Normal);
+        assert_eq!(cooldown.side(&Side::Buy, 110), SideQuoting::Normal);
+        // A later fill extends the rest, an older one does not shorten it.
+        assert_eq!(cooldown.on_fill(&Side::Buy, 105), None);
+        assert_eq!(cooldown.on_fill(&Side::Buy, 103), None);
+        assert_eq!(cooldown.side(&Side::Buy, 114), SideQuoting::Pulled);
+        assert_eq!(
+            cooldown.status(112),
+            CooldownStatus {
+                bids_until: Some(115),
+                ..CooldownStatus::default()
+            }
+        );
+        assert_eq!(cooldown.status(115), CooldownStatus::default());
+
+        let mut widened = FillCooldown::new(FillCooldownConfig {
+            widen_bps: Some(dec!(30)),
+            ..config()
+        });
+        widened.on_fill(&Side::Sell, 100);
+        assert_eq!(
+            widened.side(&Side::Sell, 100),
+            SideQuoting::Widened(dec!(30))
+        );
+        assert_eq!(widened.side(&Side::Buy, 100), SideQuoting::Normal);
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut cooldown = FillCooldown::new(config());
+        assert_eq!(cooldown.on_fill(&Side::Buy, 100), None);
+        assert_eq!(cooldown.on_fill(&Side::Sell, 102), None);
+        assert!(!cooldown.halted(102));
+        // 1 second after the previous fill is too soon.
+        assert_eq!(cooldown.on_fill(&Side::Sell, 103), Some(1));
+        assert!(cooldown.halted(103));
+        assert_eq!(cooldown.status(150).halted_until, Some(163));
+        // Already halted, the rest of the burst does not halt it again.
+        assert_eq!(cooldown.on_fill(&Side::Sell, 103), None);
+        assert!(!cooldown.halted(163));
+
+        let mut no_interval = FillCooldown::new(FillCooldownConfig {
+            min_fill_interval_secs: None,
+            ..config()
+        });
+        no_interval.on_fill(&Side::Buy, 100);
+        assert_eq!(no_interval.on_fill(&Side::Buy, 100), None);
+        assert!(!no_interval.halted(100));
+    }
+}
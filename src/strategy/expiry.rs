@@ -0,0 +1,122 @@
+/// Expiry of the liquidity a strategy advertised. ZigZag drops levels once
+/// their `expires` passes without telling us, so they are refreshed ahead
+/// of it, and the time the market still went without liquidity is counted.
+use crate::zigzag::{Liquidity, Timestamp};
+
+#[derive(Clone, Debug, Default)]
+pub struct QuoteExpiry {
+    /// Refresh this many seconds before the first level expires
+    margin_secs: u64,
+    /// Taken off every expiry, in case the backend's clock runs ahead of
+    /// ours
+    skew_secs: u64,
+    /// Levels last sent, with their expiry
+    levels: Vec<Liquidity>,
+    /// Last time `uncovered` accounted for
+    checked: Option<Timestamp>,
+}
+
+impl QuoteExpiry {
+    pub fn new(margin_secs: u64, skew_secs: u64) -> Self {
+        Self {
+            margin_secs,
+            skew_secs,
+            ..Self::default()
+        }
+    }
+
+    /// Changes the margins, keeping the levels sent.
+    pub fn set_margins(&mut self, margin_secs: u64, skew_secs: u64) {
+        self.margin_secs = margin_secs;
+        self.skew_secs = skew_secs;
+    }
+
+    /// Remembers the levels just sent, replacing the previous ones.
+    pub fn sent(&mut self, levels: &[Liquidity]) {
+        self.levels = levels.to_vec();
+    }
+
+    /// Forgets the levels, which were withdrawn on purpose.
+    pub fn pulled(&mut self) {
+        self.levels.clear();
+    }
+
+    /// Expiries of the levels by the backend's clock, as far as we can
+    /// tell. Levels without one never expire.
+    fn expiries(&self) -> impl Iterator<Item = Timestamp> + '_ {
+        self.levels
+            .iter()
+            .filter_map(|level| level.expires)
+            .map(|expires| expires.saturating_sub(self.skew_secs))
+    }
+
+    /// When the levels must be sent again, `None` when none expires.
+    pub fn refresh_at(&self) -> Option<Timestamp> {
+        self.expiries()
+            .min()
+            .map(|expires| expires.saturating_sub(self.margin_secs))
+    }
+
+    pub fn due(&self, now: Timestamp) -> bool {
+        matches!(self.refresh_at(), Some(at) if at <= now)
+    }
+
+    /// Seconds since the previous call during which every level sent had
+    /// expired, the market going unquoted while we meant to quote it.
+    pub fn uncovered(&mut self, now: Timestamp) -> u64 {
+        let since = self.checked.replace(now).unwrap_or(now);
+        if self.levels.is_empty() || self.levels.iter().any(|level| level.expires.is_none()) {
+            return 0;
+        }
+        let covered_until = self.expiries().max().unwrap_or_default();
+        now.saturating_sub(since.max(covered_until))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::Side;
+    use rust_decimal_macros::dec;
+
+    fn level(side: Side, expires: Option<Timestamp>) -> Liquidity {
+        Liquidity {
+            side,
+            price: dec!(2000).into(),
+            base_quantity: dec!(0.5),
+            expires,
+        }
+    }
+
+    #[test]
+    fn test_refresh_at() {
+        let mut expiry = QuoteExpiry::new(5, 2);
+        assert_eq!(expiry.refresh_at(), None);
+        assert!(!expiry.due(1000));
+        expiry.sent(&[level(Side::Buy, Some(130)), level(Side::Sell, Some(140))]);
+        // The first level to expire, less the margin and the skew.
+        assert_eq!(expiry.refresh_at(), Some(123));
+        assert!(!expiry.due(122));
+        assert!(expiry.due(123));
+        expiry.sent(&[level(Side::Buy, None)]);
+        assert!(!expiry.due(1000));
+        expiry.sent(&[level(Side::Buy, Some(1))]);
+        assert_eq!(expiry.refresh_at(), Some(0));
+    }
+
+    #[test]
+    fn test_uncovered() {
+        let mut expiry = QuoteExpiry::new(5, 2);
+        assert_eq!(expiry.uncovered(100), 0);
+        expiry.sent(&[level(Side::Buy, Some(130)), level(Side::Sell, Some(140))]);
+        assert_eq!(expiry.uncovered(130), 0);
+        // Covered until 138 by the backend's clock.
+        assert_eq!(expiry.uncovered(140), 2);
+        assert_eq!(expiry.uncovered(145), 5);
+        expiry.sent(&[level(Side::Buy, Some(175))]);
+        assert_eq!(expiry.uncovered(146), 0);
+        // Withdrawn liquidity is not missed.
+        expiry.pulled();
+        assert_eq!(expiry.uncovered(300), 0);
+    }
+}
@@ -0,0 +1,128 @@
+/// Strategy that only logs what it sees, for trying out a connection or
+/// testing the runner. It never sends anything.
+use super::{MarketMakerConfig, Strategy, StrategyContext};
+use crate::zigzag::{
+    Decimal, ErrorArgs, Fill, FillrequestArgs, Liquidity2Args, RequestquoteArgs, Timestamp,
+};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Number of events kept in the journal.
+const JOURNAL_LEN: usize = 1000;
+
+/// Cloning the strategy shares its journal.
+#[derive(Clone, Debug, Default)]
+pub struct LoggerStrategy {
+    journal: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LoggerStrategy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Latest events seen, oldest first. Ticks are only logged.
+    pub fn journal(&self) -> Vec<String> {
+        self.journal.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, ctx: &StrategyContext, event: String) {
+        log::info!("{}: {}", ctx.market(), event);
+        let mut journal = self.journal.lock().unwrap();
+        if journal.len() == JOURNAL_LEN {
+            journal.pop_front();
+        }
+        journal.push_back(event);
+    }
+}
+
+#[async_trait]
+impl Strategy for LoggerStrategy {
+    async fn on_start(&mut self, ctx: &StrategyContext) -> anyhow::Result<()> {
+        self.record(ctx, "start".into());
+        Ok(())
+    }
+
+    async fn on_tick(&mut self, ctx: &StrategyContext, now: Timestamp) -> anyhow::Result<()> {
+        log::debug!(
+            "{}: tick at {}, mid {:?}, position {}",
+            ctx.market(),
+            now,
+            ctx.book().mid_price(),
+            ctx.position()
+        );
+        Ok(())
+    }
+
+    async fn on_last_price(
+        &mut self,
+        ctx: &StrategyContext,
+        price: Decimal,
+        _now: Timestamp,
+    ) -> anyhow::Result<()> {
+        self.record(ctx, format!("last price {}", price));
+        Ok(())
+    }
+
+    async fn on_liquidity(
+        &mut self,
+        ctx: &StrategyContext,
+        liquidity: &Liquidity2Args,
+    ) -> anyhow::Result<()> {
+        self.record(ctx, format!("liquidity {}", liquidity.liquidity.len()));
+        Ok(())
+    }
+
+    async fn on_fill(&mut self, ctx: &StrategyContext, fill: &Fill) -> anyhow::Result<()> {
+        self.record(ctx, format!("fill {}", fill.id));
+        Ok(())
+    }
+
+    async fn on_fill_request(
+        &mut self,
+        ctx: &StrategyContext,
+        request: FillrequestArgs,
+    ) -> anyhow::Result<()> {
+        self.record(ctx, format!("fill request {}", request.order_id));
+        Ok(())
+    }
+
+    async fn on_quote_request(
+        &mut self,
+        ctx: &StrategyContext,
+        request: &RequestquoteArgs,
+    ) -> anyhow::Result<()> {
+        self.record(ctx, format!("quote request {:?}", request.side));
+        Ok(())
+    }
+
+    async fn on_error(&mut self, ctx: &StrategyContext, error: &ErrorArgs) -> anyhow::Result<()> {
+        self.record(ctx, format!("error {}", error.operation));
+        Ok(())
+    }
+
+    async fn on_reconnect(&mut self, ctx: &StrategyContext, _now: Timestamp) -> anyhow::Result<()> {
+        self.record(ctx, "reconnect".into());
+        Ok(())
+    }
+
+    async fn on_config(
+        &mut self,
+        ctx: &StrategyContext,
+        config: &MarketMakerConfig,
+    ) -> anyhow::Result<()> {
+        self.record(ctx, format!("config spread {}", config.spread_bps));
+        Ok(())
+    }
+
+    async fn on_snapshot(&mut self, ctx: &StrategyContext) -> anyhow::Result<()> {
+        self.record(ctx, "snapshot".into());
+        Ok(())
+    }
+
+    async fn on_shutdown(&mut self, ctx: &StrategyContext) -> anyhow::Result<()> {
+        self.record(ctx, "shutdown".into());
+        Ok(())
+    }
+}
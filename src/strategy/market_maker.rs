@@ -0,0 +1,1852 @@
+/// Basic market maker advertising a ladder of bids and asks around a
+/// reference price, registered as the "spread" and "avellaneda" strategies.
+use super::cooldown::{CooldownStatus, FillCooldown, FillCooldownConfig, SideQuoting};
+use super::expiry::QuoteExpiry;
+use super::{Strategy, StrategyContext};
+use crate::avellaneda::AvellanedaConfig;
+use crate::balances::clamp_sizes;
+use crate::display::{format_amount, format_price, Render};
+use crate::fees::{FeeConfig, FeeEstimator};
+use crate::metrics::Metrics;
+use crate::notify::{Event, Notifications};
+use crate::orders::{OrderParams, OrderSigner, OrderTerms};
+use crate::portfolio::{is_traded, our_side};
+use crate::rfq::{RfqConfig, RfqMaker};
+use crate::signals::{Volatility, VolatilityConfig};
+use crate::status::StatusBoard;
+use crate::zigzag::{
+    unix_timestamp, Amount, Decimal, ErrorArgs, Fill, FillId, FillrequestArgs, Indicateliq2Args,
+    Liquidity, Market, Operation, OperationName, RequestquoteArgs, Side, Timestamp, UserId,
+};
+use async_trait::async_trait;
+use chrono::DateTime;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// ZigZag hands a fill request to another maker when we do not answer
+/// within a few seconds.
+const FILL_REQUEST_DEADLINE: Duration = Duration::from_secs(5);
+
+/// How long fill requests are refused after a reconnect when no orders or
+/// fills snapshot arrives.
+const SNAPSHOT_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarketMakerConfig {
+    pub market: Market,
+    /// Distance between bid and ask, in basis points of the reference price
+    pub spread_bps: Decimal,
+    /// Base quantity advertised on each side
+    pub quote_size: Amount,
+    /// Lifetime of advertised liquidity, in seconds
+    pub expires_secs: u64,
+    /// Requote when the reference price moves by more than this, in basis points
+    pub requote_threshold_bps: Decimal,
+    /// Requote this many seconds before advertised liquidity expires
+    pub requote_margin_secs: u64,
+    /// How far ahead of our estimate the backend's clock may run, taken
+    /// off the expiry of advertised liquidity, in seconds
+    pub clock_skew_secs: u64,
+    /// Answer RFQ quote requests, if set
+    pub rfq: Option<RfqConfig>,
+    /// Skew quotes against the inventory, if set
+    pub skew: Option<SkewConfig>,
+    /// Cover zksync swap fees in the quotes, if set
+    pub fees: Option<FeeConfig>,
+    /// Quote several levels per side instead of `spread_bps` and
+    /// `quote_size`, if set
+    pub ladder: Option<LadderConfig>,
+    /// Widen the spread and skew with the volatility, if set
+    pub volatility: Option<VolatilityConfig>,
+    /// Quote with the Avellaneda–Stoikov model instead of `spread_bps` and
+    /// the skew, if set
+    pub avellaneda: Option<AvellanedaConfig>,
+    /// Rest a side after its fills and halt on bursts of fills, if set
+    pub fill_cooldown: Option<FillCooldownConfig>,
+    /// Quote around the reference of an external feed, pulling the quotes
+    /// once it is older than this, if set
+    pub reference_max_age: Option<Duration>,
+}
+
+/// One level of a quote ladder.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LadderLevel {
+    /// Distance from the mid, in basis points of it
+    pub offset_bps: Decimal,
+    /// Base quantity advertised at this level
+    pub size: Amount,
+}
+
+/// `[market_maker.ladder]` table of the config file: the levels of each
+/// side, closest to the mid first. A side without levels is not quoted.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LadderConfig {
+    pub bids: Vec<LadderLevel>,
+    pub asks: Vec<LadderLevel>,
+}
+
+impl LadderConfig {
+    /// A single level per side, `spread_bps` apart.
+    pub fn single(spread_bps: Decimal, size: Amount) -> Self {
+        let level = LadderLevel {
+            offset_bps: spread_bps / Decimal::from(2),
+            size,
+        };
+        Self {
+            bids: vec![level.clone()],
+            asks: vec![level],
+        }
+    }
+
+    /// Multiplies every offset by `factor`.
+    pub fn widen(&mut self, factor: Decimal) {
+        for level in self.bids.iter_mut().chain(&mut self.asks) {
+            level.offset_bps *= factor;
+        }
+    }
+
+    /// Scales the offsets of each side so that its first level sits
+    /// `offset_bps` from the mid, keeping the spacing proportional.
+    pub fn anchor(&mut self, offset_bps: Decimal) {
+        for levels in [&mut self.bids, &mut self.asks] {
+            let first = match levels.first() {
+                Some(first) if first.offset_bps > Decimal::ZERO => first.offset_bps,
+                _ => continue,
+            };
+            for level in levels.iter_mut() {
+                level.offset_bps = level.offset_bps * offset_bps / first;
+            }
+        }
+    }
+
+    /// Largest total size advertised on one side.
+    pub fn max_side_size(&self) -> Amount {
+        let total = |levels: &[LadderLevel]| levels.iter().map(|l| l.size).sum::<Amount>();
+        total(&self.bids).max(total(&self.asks))
+    }
+
+    /// Checks that sizes are positive and that offsets are positive and
+    /// strictly increase away from the mid.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (side, levels) in [("bids", &self.bids), ("asks", &self.asks)] {
+            let mut previous = Decimal::ZERO;
+            for level in levels {
+                if level.size <= Decimal::ZERO {
+                    return Err(anyhow::anyhow!("Ladder {} sizes must be positive!", side));
+                }
+                if level.offset_bps <= previous {
+                    return Err(anyhow::anyhow!(
+                        "Ladder {} offsets must be positive and increasing!",
+                        side
+                    ));
+                }
+                previous = level.offset_bps;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Inventory skew. Both quotes shift away from the side that would grow the
+/// position, and that side shrinks, proportionally to `position /
+/// max_position`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkewConfig {
+    /// Position, in base units either way, at which the side growing it
+    /// stops quoting
+    pub max_position: Amount,
+    /// Shift of both quotes at `max_position`, in basis points of the mid
+    pub price_skew_bps: Decimal,
+    /// Share of `quote_size` removed from the side growing the position at
+    /// `max_position`, between 0 and 1
+    pub size_skew: Decimal,
+}
+
+/// One advertised level, with the size left of it.
+#[derive(Clone, Debug, PartialEq)]
+struct Level {
+    price: Decimal,
+    size: Amount,
+}
+
+/// Quotes currently advertised on ZigZag. A side without levels is not
+/// quoted.
+#[derive(Clone, Debug, PartialEq)]
+struct Quotes {
+    mid: Decimal,
+    /// Levels of each side, best first
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+    /// Position the quotes were skewed for
+    position: Amount,
+    /// Swap fee the quotes cover, in the quote asset
+    fee: Decimal,
+    /// Base and quote balances the sizes were clamped to
+    balances: (Option<Amount>, Option<Amount>),
+    /// How the bids and asks were quoted for the fill cooldown
+    cooldown: (SideQuoting, SideQuoting),
+    expires: Timestamp,
+}
+
+impl Quotes {
+    /// Levels a taker on `side` trades against: sellers hit our bids,
+    /// buyers our asks.
+    fn levels(&self, side: &Side) -> &[Level] {
+        match side {
+            Side::Sell => &self.bids,
+            Side::Buy => &self.asks,
+        }
+    }
+
+    /// Index of the level a taker order at `price` hits. The order fills at
+    /// its own price, so this is the level furthest from the mid that the
+    /// price still reaches.
+    fn level_hit(&self, side: &Side, price: Decimal) -> Option<usize> {
+        self.levels(side).iter().rposition(|level| match side {
+            Side::Sell => price <= level.price,
+            Side::Buy => price >= level.price,
+        })
+    }
+
+    /// Whether a level has been filled completely.
+    fn exhausted(&self) -> bool {
+        self.bids
+            .iter()
+            .chain(&self.asks)
+            .any(|level| level.size <= Decimal::ZERO)
+    }
+}
+
+/// Applies the fill cooldown of a side to its ladder, returning the share
+/// of it quoted.
+fn rest(
+    quoting: SideQuoting,
+    ladder: &mut [LadderLevel],
+    scale: Option<Decimal>,
+) -> Option<Decimal> {
+    match quoting {
+        SideQuoting::Normal => scale,
+        SideQuoting::Widened(bps) => {
+            for rung in ladder {
+                rung.offset_bps += bps;
+            }
+            scale
+        }
+        SideQuoting::Pulled => None,
+    }
+}
+
+/// Levels of `liquidity` rendered at `price_decimals`, for logs.
+fn describe(liquidity: &[Liquidity], price_decimals: u32) -> String {
+    let levels: Vec<_> = liquidity
+        .iter()
+        .map(|level| level.with_precision(Some(price_decimals)).to_string())
+        .collect();
+    if levels.is_empty() {
+        "nothing".into()
+    } else {
+        levels.join(", ")
+    }
+}
+
+pub struct MarketMaker<O> {
+    config: MarketMakerConfig,
+    signer: Arc<O>,
+    reference: Option<Decimal>,
+    /// Quote around the reference price an external feed publishes in the
+    /// summaries, pulling quotes once it is older than this
+    external: Option<Duration>,
+    fees: Option<FeeEstimator>,
+    /// Last swap fee estimate, in the quote asset
+    fee: Option<Decimal>,
+    quotes: Option<Quotes>,
+    /// Expiry of the liquidity last sent
+    expiry: QuoteExpiry,
+    rfq: Option<RfqMaker>,
+    volatility: Option<Volatility>,
+    /// Counts the seconds the market went unquoted, if set
+    metrics: Option<Arc<Metrics>>,
+    /// Set on reconnect: fill requests answered before the drop may or may
+    /// not have gone through, so our orders are unknown until the backend
+    /// sends its orders or fills snapshot.
+    unknown_since: Option<Timestamp>,
+    /// Kept apart from the quotes, so that it survives requotes
+    cooldown: Option<FillCooldown>,
+    /// Only the fills this user made as maker rest a side when set, every
+    /// fill of the market otherwise
+    user_id: Option<UserId>,
+    last_fill_id: Option<FillId>,
+    /// Time of the latest hook, that of fills without a timestamp
+    clock: Timestamp,
+    notifications: Notifications,
+    /// Where the cooldown is published, with what was last published
+    status: Option<(StatusBoard, Option<CooldownStatus>)>,
+    /// Set when the settings changed, until quoted with them
+    reconfigured: bool,
+}
+
+impl<O: OrderSigner> MarketMaker<O> {
+    pub fn new(config: MarketMakerConfig, signer: Arc<O>) -> Self {
+        Self {
+            rfq: config.rfq.clone().map(RfqMaker::new),
+            expiry: QuoteExpiry::new(config.requote_margin_secs, config.clock_skew_secs),
+            cooldown: config.fill_cooldown.clone().map(FillCooldown::new),
+            external: config.reference_max_age,
+            config,
+            signer,
+            reference: None,
+            fees: None,
+            fee: None,
+            quotes: None,
+            volatility: None,
+            metrics: None,
+            unknown_since: None,
+            user_id: None,
+            last_fill_id: None,
+            clock: 0,
+            notifications: Notifications::default(),
+            status: None,
+            reconfigured: false,
+        }
+    }
+
+    /// Quotes around the reference price an external feed publishes in the
+    /// summaries of the context, pulling the quotes when it is older than
+    /// `max_age`.
+    pub fn with_reference(mut self, max_age: Duration) -> Self {
+        self.external = Some(max_age);
+        self
+    }
+
+    /// Looks up swap fees with `fees`. Only used when the config has fees.
+    pub fn with_fees(mut self, fees: FeeEstimator) -> Self {
+        self.fees = Some(fees);
+        self
+    }
+
+    /// Samples the reference prices into `volatility`. Only widens the
+    /// quotes when the config has volatility.
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.volatility = Some(volatility);
+        self
+    }
+
+    /// Counts the seconds the market went without our liquidity in the
+    /// `uncovered_secs_<market>` counter of `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Only rests a side after the fills `user_id` made as maker.
+    pub fn with_user(mut self, user_id: UserId) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// Alerts on `notifications` when a burst of fills halts the market.
+    pub fn with_notifications(mut self, notifications: Notifications) -> Self {
+        self.notifications = notifications;
+        self
+    }
+
+    /// Publishes the fill cooldown of the market on `status`.
+    pub fn with_status(mut self, status: StatusBoard) -> Self {
+        self.status = Some((status, None));
+        self
+    }
+
+    /// Takes the settings of a reloaded config. The quotes, fills and
+    /// cooldowns followed so far are kept, the next tick quotes with them.
+    pub fn reconfigure(&mut self, config: MarketMakerConfig) {
+        self.rfq = config.rfq.clone().map(RfqMaker::new);
+        self.expiry
+            .set_margins(config.requote_margin_secs, config.clock_skew_secs);
+        self.cooldown = match (self.cooldown.take(), config.fill_cooldown.clone()) {
+            (Some(mut cooldown), Some(cooldown_config)) => {
+                cooldown.set_config(cooldown_config);
+                Some(cooldown)
+            }
+            (None, Some(cooldown_config)) => Some(FillCooldown::new(cooldown_config)),
+            (_, None) => None,
+        };
+        self.external = config.reference_max_age;
+        self.config = config;
+        self.reconfigured = true;
+    }
+
+    fn sample_volatility(&self, mid: Option<Decimal>, now: Timestamp) {
+        if let (Some(volatility), Some(mid)) = (&self.volatility, mid) {
+            volatility.update(&self.config.market, mid, now);
+        }
+    }
+
+    /// Factor the spread and skew are widened by: the volatility adjusted
+    /// spread over `spread_bps`. 1 until there is an estimate.
+    fn widening(&self) -> Decimal {
+        let (config, volatility) = match (&self.config.volatility, &self.volatility) {
+            (Some(config), Some(volatility)) if self.config.spread_bps > Decimal::ZERO => {
+                (config, volatility)
+            }
+            _ => return Decimal::ONE,
+        };
+        match volatility.per_minute_bps(&self.config.market) {
+            Some(volatility) => {
+                config.spread_bps(self.config.spread_bps, volatility) / self.config.spread_bps
+            }
+            None => Decimal::ONE,
+        }
+    }
+
+    /// Whether our orders are unknown at `now`. Gives up waiting for the
+    /// snapshot after `SNAPSHOT_TIMEOUT_SECS`.
+    fn orders_unknown(&mut self, now: Timestamp) -> bool {
+        match self.unknown_since {
+            Some(since) if now < since + SNAPSHOT_TIMEOUT_SECS => true,
+            Some(_) => {
+                log::warn!(
+                    "No orders snapshot on {} after reconnecting, resuming fills",
+                    self.config.market
+                );
+                self.unknown_since = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Requotes around the current reference price when it moved or the
+    /// advertised liquidity is about to expire.
+    async fn requote(&mut self, ctx: &StrategyContext, now: Timestamp) -> anyhow::Result<()> {
+        self.clock = self.clock.max(now);
+        self.publish_cooldown(now);
+        let mid = self.reference_price(ctx, now);
+        self.sample_volatility(mid, now);
+        self.refresh_fee(ctx, mid, now).await;
+        self.maybe_requote(ctx, mid, now)
+    }
+
+    /// Price to quote around: the external reference when one is configured,
+    /// ZigZag's last price otherwise. A stale external reference gives none.
+    fn reference_price(&self, ctx: &StrategyContext, now: Timestamp) -> Option<Decimal> {
+        match self.external {
+            Some(max_age) => ctx
+                .summaries()
+                .reference(&self.config.market)
+                .filter(|reference| !reference.is_stale(max_age, now))
+                .map(|reference| reference.price),
+            None => self.reference,
+        }
+    }
+
+    /// Updates the swap fee estimate at `mid`. Failed lookups keep the
+    /// previous estimate.
+    async fn refresh_fee(&mut self, ctx: &StrategyContext, mid: Option<Decimal>, now: Timestamp) {
+        let (fees, mid) = match (&self.fees, mid) {
+            (Some(fees), Some(mid)) if self.config.fees.is_some() => (fees, mid),
+            _ => return,
+        };
+        match fees.fee_in_quote(ctx.market_info(), mid, now).await {
+            Ok(fee) => self.fee = Some(fee),
+            Err(e) => log::warn!("Fee lookup on {} failed: {}", self.config.market, e),
+        }
+    }
+
+    /// How the bids and asks are quoted at `now` for the fill cooldown.
+    fn side_quoting(&self, now: Timestamp) -> (SideQuoting, SideQuoting) {
+        match &self.cooldown {
+            Some(cooldown) => (
+                cooldown.side(&Side::Buy, now),
+                cooldown.side(&Side::Sell, now),
+            ),
+            None => (SideQuoting::Normal, SideQuoting::Normal),
+        }
+    }
+
+    /// Records one of our fills in the fill cooldown, once per fill and
+    /// only within its window. Returns whether it rested a side. A burst
+    /// of fills halts the market and alerts.
+    fn record_fill(&mut self, fill: &Fill) -> bool {
+        let cooldown = match &mut self.cooldown {
+            Some(cooldown) if is_traded(fill) => cooldown,
+            _ => return false,
+        };
+        // Receipts come again as the fill settles, and snapshots repeat them.
+        if matches!(self.last_fill_id, Some(id) if fill.id <= id) {
+            return false;
+        }
+        let side = match &self.user_id {
+            Some(user_id) => match our_side(fill, user_id) {
+                Some((side, false)) => side,
+                _ => return false,
+            },
+            None => fill.side.opposite(),
+        };
+        self.last_fill_id = Some(fill.id);
+        let time = fill
+            .timestamp
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map_or(self.clock, |date| date.timestamp().max(0) as Timestamp);
+        if time + cooldown.config().window_secs() <= self.clock {
+            return false;
+        }
+        if let Some(interval) = cooldown.on_fill(&side, time) {
+            let halt_secs = cooldown.config().halt_secs;
+            log::error!(
+                "Halting {} for {}s: filled again {}s after the previous fill",
+                self.config.market,
+                halt_secs,
+                interval
+            );
+            self.notifications.notify(Event::FillBurst {
+                market: self.config.market.clone(),
+                interval_secs: interval,
+                halt_secs,
+            });
+        }
+        log::info!(
+            "Resting {:?} side of {} for {}s after fill {}",
+            side,
+            self.config.market,
+            cooldown.config().secs,
+            fill.id
+        );
+        true
+    }
+
+    /// Publishes the fill cooldown at `now` when it changed.
+    fn publish_cooldown(&mut self, now: Timestamp) {
+        let (cooldown, (board, published)) = match (&self.cooldown, &mut self.status) {
+            (Some(cooldown), Some(status)) => (cooldown, status),
+            _ => return,
+        };
+        let status = cooldown.status(now);
+        if published.as_ref() != Some(&status) {
+            board.set_fill_cooldown(&self.config.market, status.clone());
+            *published = Some(status);
+        }
+    }
+
+    /// Quotes around `mid`, skewed against the current position, sized
+    /// within the balances and rounded to the market's price precision.
+    /// The spread model widens offsets and skew with the volatility, the
+    /// Avellaneda–Stoikov model anchors the ladder at its optimal spread
+    /// around its reservation price. Sides resting after a fill are pulled
+    /// or widened further.
+    fn quotes_for(&self, ctx: &StrategyContext, mid: Decimal, now: Timestamp) -> Quotes {
+        let position = ctx.position();
+        let mut ladder = self.config.ladder.clone().unwrap_or_else(|| {
+            LadderConfig::single(self.config.spread_bps, self.config.quote_size)
+        });
+        // Share of the ladder quoted on each side, none when pulled.
+        let (shift, bid_scale, ask_scale) = match &self.config.avellaneda {
+            Some(model) => {
+                let volatility = self
+                    .volatility
+                    .as_ref()
+                    .and_then(|volatility| volatility.per_minute(&self.config.market))
+                    .unwrap_or_default();
+                let quote = model.quote(mid, position, volatility);
+                ladder.anchor(quote.half_spread / mid * Decimal::from(10_000));
+                let scale = |quoted: bool| quoted.then_some(Decimal::ONE);
+                (quote.reservation - mid, scale(quote.bid), scale(quote.ask))
+            }
+            None => {
+                let widening = self.widening();
+                ladder.widen(widening);
+                self.skew(mid, position, widening)
+            }
+        };
+        let cooldown = self.side_quoting(now);
+        let bid_scale = rest(cooldown.0, &mut ladder.bids, bid_scale);
+        let ask_scale = rest(cooldown.1, &mut ladder.asks, ask_scale);
+        Quotes {
+            mid,
+            bids: self.side_levels(ctx, Side::Buy, &ladder.bids, mid, shift, bid_scale),
+            asks: self.side_levels(ctx, Side::Sell, &ladder.asks, mid, shift, ask_scale),
+            position,
+            fee: self.fee.unwrap_or_default(),
+            balances: ctx.balances(),
+            cooldown,
+            expires: ctx.backend_time(now) + self.config.expires_secs,
+        }
+    }
+
+    /// Inventory skew of the spread model: the shift of the quotes and the
+    /// share of the ladder quoted on each side.
+    fn skew(
+        &self,
+        mid: Decimal,
+        position: Amount,
+        widening: Decimal,
+    ) -> (Decimal, Option<Decimal>, Option<Decimal>) {
+        match &self.config.skew {
+            Some(skew) if skew.max_position > Decimal::ZERO => {
+                let ratio = (position / skew.max_position).clamp(-Decimal::ONE, Decimal::ONE);
+                let shift = -ratio * mid * skew.price_skew_bps * widening / Decimal::from(10_000);
+                // Long positions shrink the bid, short ones the ask.
+                let shrink = |ratio: Decimal| {
+                    (ratio < Decimal::ONE)
+                        .then(|| Decimal::ONE - skew.size_skew * ratio.max(Decimal::ZERO))
+                };
+                (shift, shrink(ratio), shrink(-ratio))
+            }
+            _ => (Decimal::ZERO, Some(Decimal::ONE), Some(Decimal::ONE)),
+        }
+    }
+
+    /// Levels of one side, with sizes scaled by `scale`. Deeper levels get
+    /// what the balances have left after the ones before them. With fees
+    /// configured, each level stays at least the fee per unit of a full
+    /// fill plus the minimum edge away from `mid`, and levels too small for
+    /// the fee are dropped.
+    fn side_levels(
+        &self,
+        ctx: &StrategyContext,
+        side: Side,
+        ladder: &[LadderLevel],
+        mid: Decimal,
+        shift: Decimal,
+        scale: Option<Decimal>,
+    ) -> Vec<Level> {
+        let scale = match scale {
+            Some(scale) => scale,
+            None => return Vec::new(),
+        };
+        let fee = self.fee.unwrap_or_default();
+        let mut levels = Vec::new();
+        let (mut wanted, mut covered) = (Decimal::ZERO, Decimal::ZERO);
+        for rung in ladder {
+            wanted += rung.size * scale;
+            let available = match ctx.balance_map() {
+                Some(balances) => {
+                    let (bid, ask) = match side {
+                        Side::Buy => (Some(wanted), None),
+                        Side::Sell => (None, Some(wanted)),
+                    };
+                    let (bid, ask) = clamp_sizes(ctx.market_info(), &balances, mid, bid, ask);
+                    bid.or(ask).unwrap_or_default()
+                }
+                None => wanted,
+            };
+            let size = available - covered;
+            if size <= Decimal::ZERO {
+                break;
+            }
+            covered = available;
+            let mut distance = mid * rung.offset_bps / Decimal::from(10_000);
+            if let Some(fees) = &self.config.fees {
+                if fee > fees.max_fee_share * size * mid {
+                    log::warn!(
+                        "Not quoting {:?} {} on {}: fee {} is too large",
+                        side,
+                        size,
+                        self.config.market,
+                        fee
+                    );
+                    continue;
+                }
+                let edge = mid * fees.min_edge_bps / Decimal::from(10_000);
+                distance = distance.max(fee / size + edge);
+            }
+            let price = match side {
+                Side::Buy => mid - distance + shift,
+                Side::Sell => mid + distance + shift,
+            };
+            levels.push(Level {
+                price: ctx.market_info().round_price(price, &side),
+                size,
+            });
+        }
+        levels
+    }
+
+    fn needs_requote(&self, ctx: &StrategyContext, mid: Decimal, now: Timestamp) -> bool {
+        match &self.quotes {
+            None => true,
+            Some(quotes) => {
+                if self.reconfigured {
+                    return true;
+                }
+                let moved_bps = (mid - quotes.mid).abs() / quotes.mid * Decimal::from(10_000);
+                moved_bps > self.config.requote_threshold_bps
+                    || self.expiry.due(ctx.backend_time(now))
+                    || (self.config.skew.is_some() && ctx.position() != quotes.position)
+                    || self.fee.unwrap_or_default() != quotes.fee
+                    || ctx.balances() != quotes.balances
+                    || self.side_quoting(now) != quotes.cooldown
+                    || quotes.exhausted()
+            }
+        }
+    }
+
+    fn maybe_requote(
+        &mut self,
+        ctx: &StrategyContext,
+        mid: Option<Decimal>,
+        now: Timestamp,
+    ) -> anyhow::Result<()> {
+        if ctx.is_paused() {
+            return self.pull_quotes(ctx, "paused");
+        }
+        if ctx.in_cooldown() {
+            return self.pull_quotes(ctx, "cooling down from a stop");
+        }
+        if matches!(&self.cooldown, Some(cooldown) if cooldown.halted(now)) {
+            return self.pull_quotes(ctx, "halted after a burst of fills");
+        }
+        let mid = match mid {
+            Some(mid) if mid > Decimal::ZERO => mid,
+            _ => return self.pull_quotes(ctx, "no reference price"),
+        };
+        if self.config.fees.is_some() && self.fee.is_none() {
+            return self.pull_quotes(ctx, "no fee estimate");
+        }
+        if !self.needs_requote(ctx, mid, now) {
+            return Ok(());
+        }
+        let quotes = self.quotes_for(ctx, mid, now);
+        if let (Some(bid), Some(ask)) = (quotes.bids.first(), quotes.asks.first()) {
+            if bid.price >= ask.price {
+                log::warn!(
+                    "Not quoting {}: bid {} and ask {} cross after rounding, widen the spread",
+                    self.config.market,
+                    bid.price,
+                    ask.price
+                );
+                return Ok(());
+            }
+        }
+        let liquidity = self.liquidity(ctx, &quotes);
+        log::info!(
+            "Quoting {} {} (mid {}, position {})",
+            self.config.market,
+            describe(
+                &liquidity.liquidity,
+                ctx.market_info().price_precision_decimal
+            ),
+            format_price(mid, None),
+            format_amount(quotes.position)
+        );
+        self.expiry.sent(&liquidity.liquidity);
+        ctx.send(Operation::Indicateliq2(liquidity))?;
+        self.quotes = Some(quotes);
+        self.reconfigured = false;
+        Ok(())
+    }
+
+    /// Withdraws the advertised liquidity, if any.
+    fn pull_quotes(&mut self, ctx: &StrategyContext, reason: &str) -> anyhow::Result<()> {
+        if self.quotes.take().is_none() {
+            return Ok(());
+        }
+        log::warn!("Pulling quotes on {}: {}", self.config.market, reason);
+        self.expiry.pulled();
+        ctx.send(Operation::Indicateliq2(Indicateliq2Args {
+            chain_id: ctx.market_info().zigzag_chain_id,
+            market: self.config.market.clone(),
+            liquidity: Vec::new(),
+        }))
+    }
+
+    /// Liquidity of the quoted levels, all in one message. Levels whose size
+    /// rounds below the market minimum are left out.
+    fn liquidity(&self, ctx: &StrategyContext, quotes: &Quotes) -> Indicateliq2Args {
+        let level = |side: Side, level: &Level| {
+            let base_quantity = match ctx.market_info().round_quantity(level.size) {
+                Ok(base_quantity) => base_quantity,
+                Err(e) => {
+                    log::warn!(
+                        "Not quoting {:?} @ {} on {}: {}",
+                        side,
+                        level.price,
+                        self.config.market,
+                        e
+                    );
+                    return None;
+                }
+            };
+            Some(Liquidity {
+                side,
+                price: level.price.into(),
+                base_quantity,
+                expires: Some(quotes.expires),
+            })
+        };
+        let bids = quotes.bids.iter().map(|l| level(Side::Buy, l));
+        let asks = quotes.asks.iter().map(|l| level(Side::Sell, l));
+        Indicateliq2Args {
+            chain_id: ctx.market_info().zigzag_chain_id,
+            market: self.config.market.clone(),
+            liquidity: bids.chain(asks).flatten().collect(),
+        }
+    }
+
+    /// Checks a taker's order against our advertised quotes at `now`,
+    /// returning its terms. The order must be for our market, at a price
+    /// reaching one of our levels on its side and within the size left
+    /// there.
+    fn check_fill_request(
+        &self,
+        ctx: &StrategyContext,
+        order: &OrderParams,
+        now: Timestamp,
+    ) -> anyhow::Result<OrderTerms> {
+        let quotes = self
+            .quotes
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no liquidity advertised"))?;
+        let backend_now = ctx.backend_time(now);
+        if quotes.expires <= backend_now {
+            return Err(anyhow::anyhow!("quotes expired at {}", quotes.expires));
+        }
+        if order.valid_until <= backend_now {
+            return Err(anyhow::anyhow!("order expired at {}", order.valid_until));
+        }
+        let terms = order.terms(ctx.market_info())?;
+        let best = quotes
+            .levels(&terms.side)
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("{:?} side not quoted", terms.side))?;
+        let level = match quotes.level_hit(&terms.side, terms.price) {
+            Some(index) => &quotes.levels(&terms.side)[index],
+            None => {
+                return Err(anyhow::anyhow!(
+                    "price {} is worse than our quotes, best {}",
+                    terms.price,
+                    best.price
+                ))
+            }
+        };
+        if terms.base_quantity > level.size {
+            return Err(anyhow::anyhow!(
+                "size {} exceeds remaining quoted size {} at {}",
+                terms.base_quantity,
+                level.size,
+                level.price
+            ));
+        }
+        Ok(terms)
+    }
+
+    /// Takes a filled size off the level it hit. Exhausted levels are
+    /// requoted on the next tick.
+    fn take_quoted(&mut self, terms: &OrderTerms) {
+        if let Some(quotes) = &mut self.quotes {
+            if let Some(index) = quotes.level_hit(&terms.side, terms.price) {
+                let levels = match terms.side {
+                    Side::Sell => &mut quotes.bids,
+                    Side::Buy => &mut quotes.asks,
+                };
+                let level = &mut levels[index];
+                level.size = (level.size - terms.base_quantity).max(Decimal::ZERO);
+            }
+        }
+    }
+
+    async fn fill(&mut self, ctx: &StrategyContext, args: FillrequestArgs) -> anyhow::Result<()> {
+        let now = unix_timestamp();
+        if self.orders_unknown(now) {
+            return Err(anyhow::anyhow!("orders unknown since reconnect"));
+        }
+        let fill_order = args
+            .fill_order
+            .zksync()
+            .ok_or_else(|| anyhow::anyhow!("not a zksync order"))?;
+        let order = OrderParams::from_order(fill_order);
+        let terms = self.check_fill_request(ctx, &order, now)?;
+        let expires = self
+            .quotes
+            .as_ref()
+            .map_or(order.valid_until, |q| q.expires);
+        let params = OrderParams::counter(fill_order, expires)?;
+        let fill_order =
+            tokio::time::timeout(FILL_REQUEST_DEADLINE, self.signer.sign_order(params))
+                .await
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "signing took longer than {}s",
+                        FILL_REQUEST_DEADLINE.as_secs()
+                    )
+                })??;
+        log::info!(
+            "Filling order {} on {}: taker {:?} {} @ {}",
+            args.order_id,
+            self.config.market,
+            terms.side,
+            format_amount(terms.base_quantity),
+            format_price(terms.price, Some(ctx.market_info().price_precision_decimal))
+        );
+        ctx.send(Operation::Fillrequest(Box::new(FillrequestArgs {
+            chain_id: args.chain_id,
+            order_id: args.order_id,
+            fill_order: fill_order.into(),
+        })))?;
+        self.take_quoted(&terms);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<O: OrderSigner + 'static> Strategy for MarketMaker<O> {
+    async fn on_tick(&mut self, ctx: &StrategyContext, now: Timestamp) -> anyhow::Result<()> {
+        let uncovered = self.expiry.uncovered(ctx.backend_time(now));
+        if uncovered > 0 {
+            log::warn!(
+                "No liquidity on {} for {}s, it expired before being refreshed",
+                self.config.market,
+                uncovered
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.add(&format!("uncovered_secs_{}", self.config.market), uncovered);
+            }
+        }
+        self.requote(ctx, now).await
+    }
+
+    async fn on_last_price(
+        &mut self,
+        ctx: &StrategyContext,
+        price: Decimal,
+        now: Timestamp,
+    ) -> anyhow::Result<()> {
+        self.reference = Some(price);
+        self.requote(ctx, now).await
+    }
+
+    async fn on_fill(&mut self, ctx: &StrategyContext, fill: &Fill) -> anyhow::Result<()> {
+        if !self.record_fill(fill) {
+            return Ok(());
+        }
+        // Pull or widen the side right away rather than on the next tick.
+        let now = self.clock;
+        self.publish_cooldown(now);
+        let mid = self.reference_price(ctx, now);
+        self.maybe_requote(ctx, mid, now)
+    }
+
+    async fn on_fill_request(
+        &mut self,
+        ctx: &StrategyContext,
+        request: FillrequestArgs,
+    ) -> anyhow::Result<()> {
+        if let Err(e) = self.fill(ctx, request).await {
+            log::warn!("Ignoring fill request on {}: {}", self.config.market, e);
+        }
+        Ok(())
+    }
+
+    async fn on_quote_request(
+        &mut self,
+        ctx: &StrategyContext,
+        request: &RequestquoteArgs,
+    ) -> anyhow::Result<()> {
+        let mid = match self.external {
+            Some(_) => self.reference_price(ctx, unix_timestamp()),
+            None => self.reference.or_else(|| ctx.book().mid_price()),
+        };
+        let quote = match (&self.rfq, mid) {
+            (Some(rfq), Some(mid)) => rfq.quote(request, mid, ctx.market_info()),
+            _ => None,
+        };
+        match quote {
+            Some(quote) => ctx.send(Operation::Quote(quote)),
+            None => Ok(()),
+        }
+    }
+
+    async fn on_error(&mut self, _ctx: &StrategyContext, error: &ErrorArgs) -> anyhow::Result<()> {
+        // The liquidity was not taken, send it again on the next tick.
+        if error.error.is_retryable()
+            && error.operation == OperationName::Indicateliq2
+            && self.quotes.is_some()
+        {
+            log::warn!("Requoting {}: {}", self.config.market, error.error);
+            self.quotes = None;
+        }
+        Ok(())
+    }
+
+    async fn on_refresh_liquidity(
+        &mut self,
+        ctx: &StrategyContext,
+        now: Timestamp,
+    ) -> anyhow::Result<()> {
+        if self.quotes.take().is_some() {
+            log::info!("Sending liquidity on {} again", self.config.market);
+        }
+        self.requote(ctx, now).await
+    }
+
+    async fn on_reconnect(&mut self, _ctx: &StrategyContext, now: Timestamp) -> anyhow::Result<()> {
+        log::warn!(
+            "Reconnected, orders on {} unknown until the next snapshot",
+            self.config.market
+        );
+        self.unknown_since = Some(now);
+        Ok(())
+    }
+
+    async fn on_config(
+        &mut self,
+        _ctx: &StrategyContext,
+        config: &MarketMakerConfig,
+    ) -> anyhow::Result<()> {
+        self.reconfigure(config.clone());
+        Ok(())
+    }
+
+    async fn on_snapshot(&mut self, _ctx: &StrategyContext) -> anyhow::Result<()> {
+        if self.unknown_since.take().is_some() {
+            log::info!("Orders on {} known again", self.config.market);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::balances::Balances;
+    use crate::client::{tests::MockTransport, ZigzagClient};
+    use crate::dispatcher::{Dispatcher, DispatcherHandle};
+    use crate::feeds::{Reference, Source};
+    use crate::fees::tests::FixedFee;
+    use crate::marketdata::SummaryCache;
+    use crate::strategy::{dispatch, dispatch_at};
+    use crate::zigzag::{fixtures, OrderStatus, ZksyncOrder};
+    use rust_decimal_macros::dec;
+    use tokio::sync::watch;
+
+    struct NoSigner;
+
+    #[async_trait::async_trait]
+    impl OrderSigner for NoSigner {
+        async fn sign_order(&self, _params: OrderParams) -> anyhow::Result<ZksyncOrder> {
+            Err(anyhow::anyhow!("not signing in tests"))
+        }
+    }
+
+    pub fn config() -> MarketMakerConfig {
+        MarketMakerConfig {
+            market: "ETH-USDC".into(),
+            spread_bps: dec!(20),
+            quote_size: dec!(0.5),
+            expires_secs: 30,
+            requote_threshold_bps: dec!(5),
+            requote_margin_secs: 5,
+            clock_skew_secs: 0,
+            rfq: Some(RfqConfig {
+                markup_bps: dec!(10),
+                max_base_quantity: dec!(1),
+            }),
+            skew: None,
+            fees: None,
+            ladder: None,
+            volatility: None,
+            avellaneda: None,
+            fill_cooldown: None,
+            reference_max_age: None,
+        }
+    }
+
+    // The dispatcher is returned so that its outgoing queue stays open.
+    fn market_maker() -> (
+        MarketMaker<NoSigner>,
+        StrategyContext,
+        Dispatcher<MockTransport>,
+    ) {
+        let client = ZigzagClient::new(MockTransport::default());
+        let (dispatcher, handle, _receivers) = Dispatcher::new(client);
+        let ctx = StrategyContext::new(
+            fixtures::market_info("ETH-USDC", 0, 2),
+            handle,
+            SummaryCache::new(),
+        );
+        (
+            MarketMaker::new(config(), Arc::new(NoSigner)),
+            ctx,
+            dispatcher,
+        )
+    }
+
+    fn prices(quotes: &Quotes) -> (Vec<Decimal>, Vec<Decimal>) {
+        let prices = |levels: &[Level]| levels.iter().map(|level| level.price).collect();
+        (prices(&quotes.bids), prices(&quotes.asks))
+    }
+
+    fn ladder() -> LadderConfig {
+        let level = |offset_bps, size| LadderLevel { offset_bps, size };
+        LadderConfig {
+            bids: vec![level(dec!(10), dec!(0.5)), level(dec!(25), dec!(1))],
+            asks: vec![
+                level(dec!(10), dec!(0.5)),
+                level(dec!(25), dec!(1)),
+                level(dec!(50), dec!(2)),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_quotes_around_mid() {
+        let (mm, ctx, _dispatcher) = market_maker();
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        assert_eq!(prices(&quotes), (vec![dec!(1998)], vec![dec!(2002)]));
+        assert_eq!(quotes.expires, 130);
+        let liquidity = mm.liquidity(&ctx, &quotes).liquidity;
+        assert_eq!(liquidity.len(), 2);
+        assert_eq!(liquidity[0].side, Side::Buy);
+        assert_eq!(liquidity[1].side, Side::Sell);
+        assert_eq!(liquidity[1].expires, Some(130));
+    }
+
+    #[test]
+    fn test_requote_on_move_and_expiry() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        assert!(mm.needs_requote(&ctx, dec!(2000), 100));
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 100)
+            .expect("maybe_requote");
+        // 4 bps move is within the threshold, 6 bps is not.
+        assert!(!mm.needs_requote(&ctx, dec!(2000.8), 101));
+        assert!(mm.needs_requote(&ctx, dec!(2001.2), 101));
+        // Liquidity expires at 130, requote 5 seconds before.
+        assert!(!mm.needs_requote(&ctx, dec!(2000), 124));
+        assert!(mm.needs_requote(&ctx, dec!(2000), 125));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_before_expiry() {
+        let (handle, mut outbox) = DispatcherHandle::offline();
+        let ctx = StrategyContext::new(
+            fixtures::market_info("ETH-USDC", 0, 2),
+            handle,
+            SummaryCache::new(),
+        );
+        let metrics = Arc::new(Metrics::new());
+        let mut config = config();
+        config.clock_skew_secs = 2;
+        let mut mm = MarketMaker::new(config, Arc::new(NoSigner)).with_metrics(metrics.clone());
+        mm.reference = Some(dec!(2000));
+        let expires = |ops: Vec<Operation>| -> Vec<_> {
+            ops.into_iter()
+                .map(|op| match op {
+                    Operation::Indicateliq2(args) => args.liquidity[0].expires,
+                    op => panic!("Unexpected {:?}", op),
+                })
+                .collect()
+        };
+
+        mm.on_tick(&ctx, 100).await.expect("on_tick");
+        assert_eq!(expires(outbox.drain()), vec![Some(130)]);
+        // Expiring at 130, the same quotes are sent again 5 seconds before,
+        // less 2 for the skew.
+        for now in 101..123 {
+            mm.on_tick(&ctx, now).await.expect("on_tick");
+        }
+        assert!(outbox.drain().is_empty());
+        mm.on_tick(&ctx, 123).await.expect("on_tick");
+        assert_eq!(expires(outbox.drain()), vec![Some(153)]);
+
+        // Asked for by ZigZag, right away.
+        let refresh = serde_json::from_str(r#"{"op":"refreshliquidity","args":[1000,"ETH-USDC"]}"#)
+            .expect("from_str");
+        dispatch_at(&mut mm, &ctx, refresh, 124)
+            .await
+            .expect("dispatch_at");
+        assert_eq!(expires(outbox.drain()), vec![Some(154)]);
+        assert_eq!(metrics.counter("uncovered_secs_ETH-USDC"), 0);
+
+        // Ticks missed for a while leave the market dark from 152 on.
+        mm.on_tick(&ctx, 160).await.expect("on_tick");
+        assert_eq!(expires(outbox.drain()), vec![Some(190)]);
+        assert_eq!(metrics.counter("uncovered_secs_ETH-USDC"), 8);
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure() {
+        let (handle, mut outbox) = DispatcherHandle::offline();
+        let ctx = StrategyContext::new(
+            fixtures::market_info("ETH-USDC", 0, 2),
+            handle,
+            SummaryCache::new(),
+        );
+        let mut mm = MarketMaker::new(config(), Arc::new(NoSigner));
+        mm.reference = Some(dec!(2000));
+        let prices = |ops: Vec<Operation>| -> Vec<_> {
+            ops.into_iter()
+                .flat_map(|op| match op {
+                    Operation::Indicateliq2(args) => args.liquidity,
+                    op => panic!("Unexpected {:?}", op),
+                })
+                .map(|level| level.price.value().expect("price"))
+                .collect()
+        };
+
+        mm.on_tick(&ctx, 100).await.expect("on_tick");
+        assert_eq!(prices(outbox.drain()), vec![dec!(1998), dec!(2002)]);
+        let mut wider = config();
+        wider.spread_bps = dec!(40);
+        wider.requote_margin_secs = 10;
+        mm.on_config(&ctx, &wider).await.expect("on_config");
+        // The quotes stay up until the next tick replaces them.
+        assert!(mm.quotes.is_some());
+        assert!(outbox.drain().is_empty());
+        mm.on_tick(&ctx, 101).await.expect("on_tick");
+        assert_eq!(prices(outbox.drain()), vec![dec!(1996), dec!(2004)]);
+        mm.on_tick(&ctx, 102).await.expect("on_tick");
+        assert!(outbox.drain().is_empty());
+        // Expiring at 131, requoted 10 seconds before.
+        mm.on_tick(&ctx, 121).await.expect("on_tick");
+        assert_eq!(prices(outbox.drain()).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let (handle, mut outbox) = DispatcherHandle::offline();
+        let (paused_tx, paused_rx) = watch::channel(false);
+        let (cooldown_tx, cooldown_rx) = watch::channel(false);
+        let ctx = StrategyContext::new(
+            fixtures::market_info("ETH-USDC", 0, 2),
+            handle,
+            SummaryCache::new(),
+        )
+        .with_pause(paused_rx)
+        .with_cooldown(cooldown_rx);
+        let mut mm = MarketMaker::new(config(), Arc::new(NoSigner));
+        mm.reference = Some(dec!(2000));
+        let levels = |ops: Vec<Operation>| -> Vec<_> {
+            ops.into_iter()
+                .map(|op| match op {
+                    Operation::Indicateliq2(args) => args.liquidity.len(),
+                    op => panic!("Unexpected {:?}", op),
+                })
+                .collect()
+        };
+
+        mm.on_tick(&ctx, 100).await.expect("on_tick");
+        assert_eq!(levels(outbox.drain()), vec![2]);
+        paused_tx.send(true).expect("send");
+        mm.on_tick(&ctx, 101).await.expect("on_tick");
+        mm.on_tick(&ctx, 102).await.expect("on_tick");
+        assert_eq!(levels(outbox.drain()), vec![0]);
+        paused_tx.send(false).expect("send");
+        mm.on_tick(&ctx, 103).await.expect("on_tick");
+        assert_eq!(levels(outbox.drain()), vec![2]);
+        // A stop cooling down pulls the quotes too.
+        cooldown_tx.send(true).expect("send");
+        mm.on_tick(&ctx, 104).await.expect("on_tick");
+        assert_eq!(levels(outbox.drain()), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn test_requote_on_last_price() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        let op = |json: &str| serde_json::from_str::<Operation>(json).expect("from_str");
+        dispatch(
+            &mut mm,
+            &ctx,
+            op(r#"{"op":"lastprice","args":[[["WBTC-USDC",30000,1],["ETH-USDC",2000,1]]]}"#),
+        )
+        .await
+        .expect("dispatch");
+        assert_eq!(mm.reference, Some(dec!(2000)));
+        assert_eq!(mm.quotes.as_ref().map(|q| q.mid), Some(dec!(2000)));
+
+        dispatch(
+            &mut mm,
+            &ctx,
+            op(r#"{"op":"liquidity2","args":[1000,"ETH-USDC",[["b",1999,0.5],["s",2001,0.5]]]}"#),
+        )
+        .await
+        .expect("dispatch");
+        assert_eq!(ctx.book().mid_price(), Some(dec!(2000)));
+
+        // Rate limited liquidity is sent again on the next tick.
+        dispatch(
+            &mut mm,
+            &ctx,
+            op(r#"{"op":"error","args":["indicateliq2","Order is too small"]}"#),
+        )
+        .await
+        .expect("dispatch");
+        assert!(mm.quotes.is_some());
+        dispatch(
+            &mut mm,
+            &ctx,
+            op(r#"{"op":"error","args":["indicateliq2","Rate limit exceeded"]}"#),
+        )
+        .await
+        .expect("dispatch");
+        assert!(mm.quotes.is_none());
+        assert!(mm.needs_requote(&ctx, dec!(2000), 101));
+    }
+
+    #[tokio::test]
+    async fn test_orders_unknown_after_reconnect() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        assert!(!mm.orders_unknown(100));
+
+        // The snapshot sent after the replayed login settles our orders.
+        mm.on_reconnect(&ctx, 100).await.expect("on_reconnect");
+        assert!(mm.orders_unknown(101));
+        let orders = serde_json::from_str(r#"{"op":"orders","args":[[]]}"#).expect("from_str");
+        dispatch(&mut mm, &ctx, orders).await.expect("dispatch");
+        assert!(!mm.orders_unknown(101));
+
+        // Without a snapshot, fills resume after a while.
+        mm.on_reconnect(&ctx, 100).await.expect("on_reconnect");
+        assert!(mm.orders_unknown(129));
+        assert!(!mm.orders_unknown(130));
+        assert!(mm.unknown_since.is_none());
+    }
+
+    #[test]
+    fn test_quotes_rounded_to_precision() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        // Bids round up and asks down to the 2 decimals of the market.
+        let quotes = mm.quotes_for(&ctx, dec!(2000.005), 100);
+        assert_eq!(prices(&quotes), (vec![dec!(1998.01)], vec![dec!(2002.00)]));
+        // Quotes that would cross after rounding are not sent.
+        mm.config.spread_bps = dec!(0.01);
+        mm.maybe_requote(&ctx, Some(dec!(1.005)), 100)
+            .expect("maybe_requote");
+        assert!(mm.quotes.is_none());
+    }
+
+    #[test]
+    fn test_inventory_skew() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        let (position, rx) = watch::channel(dec!(0));
+        let ctx = ctx.with_position(rx);
+        mm.config.skew = Some(SkewConfig {
+            max_position: dec!(2),
+            price_skew_bps: dec!(10),
+            size_skew: dec!(0.5),
+        });
+        let levels = |mm: &MarketMaker<NoSigner>| {
+            mm.liquidity(&ctx, &mm.quotes_for(&ctx, dec!(2000), 100))
+                .liquidity
+                .into_iter()
+                .map(|l| (l.side, l.price.value().expect("price"), l.base_quantity))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            levels(&mm),
+            vec![
+                (Side::Buy, dec!(1998), dec!(0.5)),
+                (Side::Sell, dec!(2002), dec!(0.5))
+            ]
+        );
+
+        // Half long: 5 bps lower, bid shrunk by a quarter.
+        position.send(dec!(1)).unwrap();
+        assert_eq!(
+            levels(&mm),
+            vec![
+                (Side::Buy, dec!(1997), dec!(0.375)),
+                (Side::Sell, dec!(2001), dec!(0.5))
+            ]
+        );
+        // At the max long position the bid is pulled.
+        position.send(dec!(2.5)).unwrap();
+        assert_eq!(levels(&mm), vec![(Side::Sell, dec!(2000), dec!(0.5))]);
+
+        // Short positions mirror it.
+        position.send(dec!(-1)).unwrap();
+        assert_eq!(
+            levels(&mm),
+            vec![
+                (Side::Buy, dec!(1999), dec!(0.5)),
+                (Side::Sell, dec!(2003), dec!(0.375))
+            ]
+        );
+        position.send(dec!(-2)).unwrap();
+        assert_eq!(levels(&mm), vec![(Side::Buy, dec!(2000), dec!(0.5))]);
+
+        // A position change requotes even though the mid did not move.
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 100)
+            .expect("maybe_requote");
+        assert!(!mm.needs_requote(&ctx, dec!(2000), 101));
+        position.send(dec!(-1.5)).unwrap();
+        assert!(mm.needs_requote(&ctx, dec!(2000), 101));
+    }
+
+    #[test]
+    fn test_external_reference() {
+        let (mm, ctx, _dispatcher) = market_maker();
+        let cache = ctx.summaries().clone();
+        let mut mm = mm.with_reference(Duration::from_secs(10));
+        mm.reference = Some(dec!(1500));
+        assert_eq!(mm.reference_price(&ctx, 100), None);
+
+        cache.set_reference(
+            "ETH-USDC".into(),
+            Reference {
+                price: dec!(2000),
+                source: Source::Binance,
+                updated: 100,
+            },
+        );
+        assert_eq!(mm.reference_price(&ctx, 110), Some(dec!(2000)));
+        mm.maybe_requote(&ctx, mm.reference_price(&ctx, 110), 110)
+            .expect("maybe_requote");
+        assert_eq!(mm.quotes.as_ref().map(|q| q.mid), Some(dec!(2000)));
+
+        // The feed went quiet: quotes are pulled.
+        assert_eq!(mm.reference_price(&ctx, 111), None);
+        mm.maybe_requote(&ctx, mm.reference_price(&ctx, 111), 111)
+            .expect("maybe_requote");
+        assert!(mm.quotes.is_none());
+    }
+
+    #[test]
+    fn test_quotes_within_balances() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        let (balances, rx) = watch::channel(Balances::new());
+        let ctx = ctx.with_balances(rx);
+        let sizes = |mm: &MarketMaker<NoSigner>| {
+            let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+            let size = |levels: &[Level]| levels.first().map(|level| level.size);
+            (size(&quotes.bids), size(&quotes.asks))
+        };
+        assert_eq!(sizes(&mm), (Some(dec!(0.5)), Some(dec!(0.5))));
+
+        // 400 USDC cover a 0.2 bid, no ETH left to sell.
+        balances.send_replace([(0, dec!(0)), (2, dec!(400))].into_iter().collect());
+        assert_eq!(sizes(&mm), (Some(dec!(0.2)), None));
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 100)
+            .expect("maybe_requote");
+        let liquidity = mm.liquidity(&ctx, mm.quotes.as_ref().expect("quotes"));
+        assert_eq!(liquidity.liquidity.len(), 1);
+        assert_eq!(liquidity.liquidity[0].side, Side::Buy);
+
+        // A balance change requotes even though the mid did not move.
+        assert!(!mm.needs_requote(&ctx, dec!(2000), 101));
+        balances.send_replace([(0, dec!(1)), (2, dec!(400))].into_iter().collect());
+        assert!(mm.needs_requote(&ctx, dec!(2000), 101));
+    }
+
+    #[test]
+    fn test_no_quotes_without_reference() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        mm.maybe_requote(&ctx, None, 100).expect("maybe_requote");
+        mm.maybe_requote(&ctx, Some(dec!(0)), 100)
+            .expect("maybe_requote");
+        assert!(mm.quotes.is_none());
+    }
+
+    #[test]
+    fn test_fill_request_validation() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        let info = ctx.market_info().clone();
+        let order = |side, price, base_quantity| {
+            OrderParams::new(&info, side, price, base_quantity, 200).expect("new")
+        };
+        let rejected = |mm: &MarketMaker<NoSigner>, order: &OrderParams, now| {
+            mm.check_fill_request(&ctx, order, now)
+                .expect_err("check_fill_request")
+                .to_string()
+        };
+        let sell = order(Side::Sell, dec!(1998), dec!(0.3));
+        assert!(rejected(&mm, &sell, 101).contains("no liquidity"));
+
+        // Quotes 0.5 @ 1998 / 0.5 @ 2002 until 130.
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 100)
+            .expect("maybe_requote");
+        let terms = mm
+            .check_fill_request(&ctx, &sell, 101)
+            .expect("check_fill_request");
+        assert_eq!(terms.side, Side::Sell);
+        assert_eq!(terms.base_quantity, dec!(0.3));
+        let buy = order(Side::Buy, dec!(2002), dec!(0.4));
+        assert!(mm.check_fill_request(&ctx, &buy, 101).is_ok());
+
+        let worse = order(Side::Sell, dec!(1999), dec!(0.3));
+        assert!(rejected(&mm, &worse, 101).contains("worse than our quotes"));
+        let worse = order(Side::Buy, dec!(2001), dec!(0.3));
+        assert!(rejected(&mm, &worse, 101).contains("worse than our quotes"));
+        let too_big = order(Side::Sell, dec!(1990), dec!(0.6));
+        assert!(rejected(&mm, &too_big, 101).contains("exceeds remaining"));
+        assert!(rejected(&mm, &sell, 130).contains("quotes expired"));
+        let expired = OrderParams {
+            valid_until: 101,
+            ..sell.clone()
+        };
+        assert!(rejected(&mm, &expired, 101).contains("order expired"));
+        let other_market = OrderParams::new(
+            &fixtures::market_info("WBTC-USDC", 5, 2),
+            Side::Sell,
+            dec!(1998),
+            dec!(0.3),
+            200,
+        )
+        .expect("new");
+        assert!(rejected(&mm, &other_market, 101).contains("not for market"));
+
+        // Filled size is no longer available until the next requote.
+        mm.take_quoted(&terms);
+        assert!(rejected(&mm, &sell, 102).contains("remaining quoted size 0.2"));
+        assert!(mm.check_fill_request(&ctx, &buy, 102).is_ok());
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 125)
+            .expect("maybe_requote");
+        assert!(mm.check_fill_request(&ctx, &sell, 126).is_ok());
+    }
+
+    #[test]
+    fn test_ladder_liquidity() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        mm.config.ladder = Some(ladder());
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        let levels: Vec<_> = mm
+            .liquidity(&ctx, &quotes)
+            .liquidity
+            .into_iter()
+            .map(|l| {
+                let price = l.price.value().expect("price");
+                (l.side, price, l.base_quantity, l.expires)
+            })
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                (Side::Buy, dec!(1998), dec!(0.5), Some(130)),
+                (Side::Buy, dec!(1995), dec!(1), Some(130)),
+                (Side::Sell, dec!(2002), dec!(0.5), Some(130)),
+                (Side::Sell, dec!(2005), dec!(1), Some(130)),
+                (Side::Sell, dec!(2010), dec!(2), Some(130)),
+            ]
+        );
+
+        // 2 ETH cover the first two asks and half of the third.
+        let (_balances, rx) = watch::channel([(0, dec!(2))].into_iter().collect());
+        let ctx = ctx.with_balances(rx);
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        let sizes: Vec<_> = quotes.asks.iter().map(|level| level.size).collect();
+        assert_eq!(sizes, vec![dec!(0.5), dec!(1), dec!(0.5)]);
+        assert_eq!(quotes.bids.len(), 2);
+
+        let invalid = |bids| LadderConfig {
+            bids,
+            asks: Vec::new(),
+        };
+        assert!(ladder().validate().is_ok());
+        assert!(invalid(vec![LadderLevel {
+            offset_bps: dec!(10),
+            size: dec!(0)
+        }])
+        .validate()
+        .is_err());
+        let mut unordered = ladder();
+        unordered.asks.swap(0, 1);
+        assert!(unordered.validate().is_err());
+        assert_eq!(ladder().max_side_size(), dec!(3.5));
+    }
+
+    #[test]
+    fn test_fill_request_hits_level() {
+        let (mut mm, ctx, _dispatcher) = market_maker();
+        mm.config.ladder = Some(ladder());
+        let info = ctx.market_info().clone();
+        let order = |side, price, base_quantity| {
+            OrderParams::new(&info, side, price, base_quantity, 200).expect("new")
+        };
+        // Bids 0.5 @ 1998, 1 @ 1995; asks 0.5 @ 2002, 1 @ 2005, 2 @ 2010.
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 100)
+            .expect("maybe_requote");
+        let quotes = mm.quotes.clone().expect("quotes");
+        assert_eq!(quotes.level_hit(&Side::Sell, dec!(1998)), Some(0));
+        assert_eq!(quotes.level_hit(&Side::Sell, dec!(1996)), Some(0));
+        assert_eq!(quotes.level_hit(&Side::Sell, dec!(1995)), Some(1));
+        assert_eq!(quotes.level_hit(&Side::Sell, dec!(1990)), Some(1));
+        assert_eq!(quotes.level_hit(&Side::Sell, dec!(1999)), None);
+        assert_eq!(quotes.level_hit(&Side::Buy, dec!(2007)), Some(1));
+        assert_eq!(quotes.level_hit(&Side::Buy, dec!(2010)), Some(2));
+
+        // 0.8 fits the second bid but not the first.
+        let deep = order(Side::Sell, dec!(1995), dec!(0.8));
+        let terms = mm
+            .check_fill_request(&ctx, &deep, 101)
+            .expect("check_fill_request");
+        let shallow = order(Side::Sell, dec!(1998), dec!(0.8));
+        let error = mm
+            .check_fill_request(&ctx, &shallow, 101)
+            .expect_err("check_fill_request");
+        assert!(error
+            .to_string()
+            .contains("remaining quoted size 0.5 at 1998"));
+
+        // Only the level hit shrinks, and emptying it requotes.
+        mm.take_quoted(&terms);
+        let sizes = |mm: &MarketMaker<NoSigner>| {
+            let quotes = mm.quotes.as_ref().expect("quotes");
+            quotes
+                .bids
+                .iter()
+                .map(|level| level.size)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(&mm), vec![dec!(0.5), dec!(0.2)]);
+        assert!(!mm.needs_requote(&ctx, dec!(2000), 101));
+        let rest = order(Side::Sell, dec!(1990), dec!(0.2));
+        let terms = mm
+            .check_fill_request(&ctx, &rest, 101)
+            .expect("check_fill_request");
+        mm.take_quoted(&terms);
+        assert_eq!(sizes(&mm), vec![dec!(0.5), dec!(0)]);
+        assert!(mm.needs_requote(&ctx, dec!(2000), 101));
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 101)
+            .expect("maybe_requote");
+        assert_eq!(sizes(&mm), vec![dec!(0.5), dec!(1)]);
+    }
+
+    #[test]
+    fn test_volatility_widens_spread() {
+        let (mm, ctx, _dispatcher) = market_maker();
+        let volatility = Volatility::new(Duration::from_secs(60));
+        let mut mm = mm.with_volatility(volatility.clone());
+        mm.config.volatility = Some(VolatilityConfig {
+            half_life_secs: 60,
+            multiplier: dec!(0.1),
+            min_spread_bps: dec!(10),
+            max_spread_bps: dec!(40),
+        });
+        mm.sample_volatility(Some(dec!(2000)), 0);
+        assert_eq!(mm.widening(), Decimal::ONE);
+        assert_eq!(
+            prices(&mm.quotes_for(&ctx, dec!(2000), 60)),
+            (vec![dec!(1998)], vec![dec!(2002)])
+        );
+
+        // About 99.5 bps a minute: 20 + 0.1 * 99.5 bps.
+        mm.sample_volatility(Some(dec!(2020)), 60);
+        let volatility = volatility.per_minute_bps("ETH-USDC").expect("volatility");
+        assert_eq!(volatility.round_dp(1), dec!(99.5));
+        assert_eq!(mm.widening(), (dec!(20) + volatility / dec!(10)) / dec!(20));
+        assert_eq!(
+            prices(&mm.quotes_for(&ctx, dec!(2000), 60)),
+            (vec![dec!(1997.01)], vec![dec!(2002.99)])
+        );
+
+        // Up to the max spread of 40 bps.
+        mm.config
+            .volatility
+            .as_mut()
+            .expect("volatility")
+            .multiplier = dec!(1);
+        assert_eq!(mm.widening(), dec!(2));
+        assert_eq!(
+            prices(&mm.quotes_for(&ctx, dec!(2000), 60)),
+            (vec![dec!(1996)], vec![dec!(2004)])
+        );
+    }
+
+    #[test]
+    fn test_avellaneda_quotes() {
+        let (mm, ctx, _dispatcher) = market_maker();
+        let (position, rx) = watch::channel(dec!(0));
+        let ctx = ctx.with_position(rx);
+        let volatility = Volatility::new(Duration::from_secs(60));
+        let mut mm = mm.with_volatility(volatility.clone());
+        mm.config.avellaneda = Some(AvellanedaConfig {
+            gamma: dec!(0.1),
+            kappa: dec!(1.5),
+            horizon_secs: 300,
+            max_inventory: Some(dec!(2)),
+            min_spread_bps: dec!(1),
+            max_spread_bps: dec!(500),
+        });
+        // Without volatility: 20 * ln(1 + 1 / 15) / 2 each side, about 0.65.
+        assert_eq!(
+            prices(&mm.quotes_for(&ctx, dec!(2000), 100)),
+            (vec![dec!(1999.36)], vec![dec!(2000.64)])
+        );
+
+        // 0.1% a minute, long 1: the reservation price drops by 2 and each
+        // side widens by 1.
+        volatility.update("ETH-USDC", dec!(2000), 0);
+        volatility.update("ETH-USDC", dec!(2000) * dec!(1.001000500166708), 60);
+        position.send_replace(dec!(1));
+        assert_eq!(
+            prices(&mm.quotes_for(&ctx, dec!(2000), 100)),
+            (vec![dec!(1996.36)], vec![dec!(1999.64)])
+        );
+
+        // Ladders keep their spacing from the optimal spread.
+        mm.config.ladder = Some(ladder());
+        let (bids, asks) = prices(&mm.quotes_for(&ctx, dec!(2000), 100));
+        assert_eq!(bids.len(), 2);
+        assert_eq!(asks.len(), 3);
+        assert!(bids[0] - bids[1] > dec!(2.4) && asks[2] - asks[0] > dec!(6.5));
+
+        // At the cap, bids are pulled.
+        position.send_replace(dec!(2));
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        assert!(quotes.bids.is_empty() && !quotes.asks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spread_covers_fee() {
+        let (mm, ctx, _dispatcher) = market_maker();
+        // 0.0005 ETH, 1 USDC at 2000.
+        let source = Arc::new(FixedFee {
+            raw: 500_000_000_000_000,
+            ..FixedFee::default()
+        });
+        let mut mm = mm.with_fees(FeeEstimator::new(source, 60));
+        mm.config.fees = Some(FeeConfig {
+            min_edge_bps: dec!(5),
+            max_fee_share: dec!(0.01),
+            ttl_secs: 60,
+        });
+        // No quotes before the fee is known.
+        mm.maybe_requote(&ctx, Some(dec!(2000)), 100)
+            .expect("maybe_requote");
+        assert!(mm.quotes.is_none());
+
+        // 2 per unit of 0.5 plus 1 of edge, instead of the spread's 2.
+        mm.refresh_fee(&ctx, Some(dec!(2000)), 100).await;
+        assert_eq!(mm.fee, Some(dec!(1)));
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        assert_eq!(prices(&quotes), (vec![dec!(1997)], vec![dec!(2003)]));
+
+        // A wide enough spread already covers the fee.
+        mm.config.spread_bps = dec!(40);
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        assert_eq!(prices(&quotes), (vec![dec!(1996)], vec![dec!(2004)]));
+
+        // 1 USDC is more than 1% of 0.04 ETH.
+        mm.config.quote_size = dec!(0.04);
+        let quotes = mm.quotes_for(&ctx, dec!(2000), 100);
+        assert!(quotes.bids.is_empty() && quotes.asks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fill_cooldown() {
+        // Fills taken by "42" from `maker`.
+        let our_fill = |id, taker_side, maker: &str, status| Fill {
+            taker_user_id: "42".into(),
+            maker_user_id: maker.into(),
+            ..fixtures::fill(id, taker_side, dec!(2000), dec!(0.1), status)
+        };
+        let (handle, mut outbox) = DispatcherHandle::offline();
+        let ctx = StrategyContext::new(
+            fixtures::market_info("ETH-USDC", 0, 2),
+            handle,
+            SummaryCache::new(),
+        );
+        let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let board = StatusBoard::new(&["ETH-USDC".to_owned()]);
+        let mut config = config();
+        config.fill_cooldown = Some(FillCooldownConfig {
+            secs: 10,
+            widen_bps: None,
+            min_fill_interval_secs: Some(2),
+            halt_secs: 60,
+        });
+        let mut mm = MarketMaker::new(config, Arc::new(NoSigner))
+            .with_user("23".into())
+            .with_notifications(Notifications::from_senders(vec![events_tx]))
+            .with_status(board.clone());
+        mm.reference = Some(dec!(2000));
+        let quoted = |ops: Vec<Operation>| -> Vec<_> {
+            ops.into_iter()
+                .map(|op| match op {
+                    Operation::Indicateliq2(args) => {
+                        let (bids, asks): (Vec<_>, Vec<_>) =
+                            args.liquidity.iter().partition(|l| l.side == Side::Buy);
+                        let prices = |levels: Vec<&Liquidity>| -> Vec<Decimal> {
+                            levels.iter().map(|l| l.price.value().unwrap()).collect()
+                        };
+                        (prices(bids), prices(asks))
+                    }
+                    op => panic!("Unexpected {:?}", op),
+                })
+                .collect()
+        };
+        let cooldown =
+            |board: &StatusBoard| board.snapshot().markets["ETH-USDC"].fill_cooldown.clone();
+
+        mm.on_tick(&ctx, 100).await.expect("on_tick");
+        assert_eq!(
+            quoted(outbox.drain()),
+            vec![(vec![dec!(1998)], vec![dec!(2002)])]
+        );
+        // A taker selling into our bid pulls the bids right away.
+        mm.on_fill(&ctx, &our_fill(1, Side::Sell, "23", OrderStatus::Matched))
+            .await
+            .expect("on_fill");
+        assert_eq!(quoted(outbox.drain()), vec![(vec![], vec![dec!(2002)])]);
+        // The receipts of the fill settling, and fills of others, do not count.
+        mm.on_fill(&ctx, &our_fill(1, Side::Sell, "23", OrderStatus::Filled))
+            .await
+            .expect("on_fill");
+        mm.on_fill(&ctx, &our_fill(2, Side::Buy, "7", OrderStatus::Filled))
+            .await
+            .expect("on_fill");
+        assert!(outbox.drain().is_empty());
+        // The cooldown survives refreshes.
+        mm.on_refresh_liquidity(&ctx, 101)
+            .await
+            .expect("on_refresh_liquidity");
+        assert_eq!(quoted(outbox.drain()), vec![(vec![], vec![dec!(2002)])]);
+        assert_eq!(
+            cooldown(&board),
+            Some(CooldownStatus {
+                bids_until: Some(110),
+                ..CooldownStatus::default()
+            })
+        );
+        mm.on_tick(&ctx, 109).await.expect("on_tick");
+        assert!(outbox.drain().is_empty());
+        mm.on_tick(&ctx, 110).await.expect("on_tick");
+        assert_eq!(
+            quoted(outbox.drain()),
+            vec![(vec![dec!(1998)], vec![dec!(2002)])]
+        );
+        assert_eq!(cooldown(&board), Some(CooldownStatus::default()));
+
+        // A burst of fills on the asks halts the market and alerts.
+        mm.on_fill(&ctx, &our_fill(3, Side::Buy, "23", OrderStatus::Filled))
+            .await
+            .expect("on_fill");
+        assert_eq!(quoted(outbox.drain()), vec![(vec![dec!(1998)], vec![])]);
+        mm.on_tick(&ctx, 111).await.expect("on_tick");
+        mm.on_fill(&ctx, &our_fill(4, Side::Buy, "23", OrderStatus::Filled))
+            .await
+            .expect("on_fill");
+        assert_eq!(quoted(outbox.drain()), vec![(vec![], vec![])]);
+        assert_eq!(
+            events.try_recv().ok(),
+            Some(Event::FillBurst {
+                market: "ETH-USDC".into(),
+                interval_secs: 1,
+                halt_secs: 60,
+            })
+        );
+        assert_eq!(cooldown(&board).and_then(|c| c.halted_until), Some(171));
+        mm.on_tick(&ctx, 170).await.expect("on_tick");
+        assert!(outbox.drain().is_empty());
+        mm.on_tick(&ctx, 171).await.expect("on_tick");
+        assert_eq!(
+            quoted(outbox.drain()),
+            vec![(vec![dec!(1998)], vec![dec!(2002)])]
+        );
+
+        // Widened instead of pulled, 30 bps further out.
+        mm.cooldown = Some(FillCooldown::new(FillCooldownConfig {
+            widen_bps: Some(dec!(30)),
+            ..FillCooldownConfig::default()
+        }));
+        mm.on_fill(&ctx, &our_fill(5, Side::Buy, "23", OrderStatus::Filled))
+            .await
+            .expect("on_fill");
+        assert_eq!(
+            quoted(outbox.drain()),
+            vec![(vec![dec!(1998)], vec![dec!(2008)])]
+        );
+    }
+}
@@ -0,0 +1,530 @@
+/// Trading strategies and the plumbing driving them. A `Strategy` reacts to
+/// the hooks `run` calls with the operations of its market, and acts
+/// through a `StrategyContext`. Strategies are built by name from a
+/// `StrategyRegistry`, one instance per market.
+pub mod cooldown;
+pub mod expiry;
+pub mod logger;
+pub mod market_maker;
+
+pub use logger::LoggerStrategy;
+pub use market_maker::{LadderConfig, LadderLevel, MarketMaker, MarketMakerConfig, SkewConfig};
+
+use crate::balances::Balances;
+use crate::clock::SyncedClock;
+use crate::dispatcher::{Batch, DispatcherHandle};
+use crate::marketdata::SummaryCache;
+use crate::orderbook::OrderBook;
+use crate::zigzag::{
+    unix_timestamp, Amount, Decimal, ErrorArgs, Fill, FillrequestArgs, Liquidity2Args, MarketInfo,
+    Operation, RequestquoteArgs, Timestamp,
+};
+use async_trait::async_trait;
+use futures::future;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// How often `Strategy::on_tick` is called.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Strategy of the market makers when the config names none.
+pub const DEFAULT_STRATEGY: &str = "spread";
+
+/// What a strategy sees of the bot: its market, the order book and market
+/// summaries, our balances and position, the backend's time and the way
+/// out to ZigZag.
+pub struct StrategyContext {
+    market_info: MarketInfo,
+    handle: DispatcherHandle,
+    book: Arc<OrderBook>,
+    summaries: SummaryCache,
+    balances: Option<watch::Receiver<Balances>>,
+    position: Option<watch::Receiver<Amount>>,
+    connection: Option<watch::Receiver<bool>>,
+    paused: Option<watch::Receiver<bool>>,
+    cooldown: Option<watch::Receiver<bool>>,
+    config: Option<watch::Receiver<MarketMakerConfig>>,
+    clock: SyncedClock,
+}
+
+impl StrategyContext {
+    pub fn new(market_info: MarketInfo, handle: DispatcherHandle, summaries: SummaryCache) -> Self {
+        Self {
+            book: Arc::new(OrderBook::new(market_info.alias.clone())),
+            market_info,
+            handle,
+            summaries,
+            balances: None,
+            position: None,
+            connection: None,
+            paused: None,
+            cooldown: None,
+            config: None,
+            clock: SyncedClock::default(),
+        }
+    }
+
+    /// Exposes the balances published on `balances`.
+    pub fn with_balances(mut self, balances: watch::Receiver<Balances>) -> Self {
+        self.balances = Some(balances);
+        self
+    }
+
+    /// Exposes the position of the market published on `position`.
+    pub fn with_position(mut self, position: watch::Receiver<Amount>) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    /// Calls `on_reconnect` whenever the connection published on `status`
+    /// comes back.
+    pub fn with_connection(mut self, status: watch::Receiver<bool>) -> Self {
+        self.connection = Some(status);
+        self
+    }
+
+    /// Stops quoting while `paused` is true.
+    pub fn with_pause(mut self, paused: watch::Receiver<bool>) -> Self {
+        self.paused = Some(paused);
+        self
+    }
+
+    /// Stops quoting while the market cools down from a triggered stop,
+    /// as published on `cooldown`.
+    pub fn with_cooldown(mut self, cooldown: watch::Receiver<bool>) -> Self {
+        self.cooldown = Some(cooldown);
+        self
+    }
+
+    /// Calls `on_config` whenever the settings published on `config`
+    /// change, as after a config reload.
+    pub fn with_config(mut self, config: watch::Receiver<MarketMakerConfig>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Checks expiries against the backend's time as estimated by `clock`,
+    /// instead of the local time.
+    pub fn with_clock(mut self, clock: SyncedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn market_info(&self) -> &MarketInfo {
+        &self.market_info
+    }
+
+    pub fn market(&self) -> &str {
+        &self.market_info.alias
+    }
+
+    /// Sends an operation to ZigZag through the dispatcher.
+    pub fn send(&self, op: Operation) -> anyhow::Result<()> {
+        self.handle.send(op)
+    }
+
+    /// Sends the operations of `batch` back to back, or none of them.
+    pub fn send_batch(&self, batch: Batch) -> anyhow::Result<()> {
+        self.handle.send_batch(batch)
+    }
+
+    pub fn handle(&self) -> &DispatcherHandle {
+        &self.handle
+    }
+
+    /// Order book of the market, kept up to date before the hooks run.
+    pub fn book(&self) -> &Arc<OrderBook> {
+        &self.book
+    }
+
+    pub fn summaries(&self) -> &SummaryCache {
+        &self.summaries
+    }
+
+    /// Committed balances by token id, when published.
+    pub fn balance_map(&self) -> Option<watch::Ref<'_, Balances>> {
+        self.balances.as_ref().map(|balances| balances.borrow())
+    }
+
+    /// Committed balance of a token, when known.
+    pub fn balance(&self, token: u32) -> Option<Amount> {
+        self.balance_map()?.get(&token).copied()
+    }
+
+    /// Base and quote balances, when known.
+    pub fn balances(&self) -> (Option<Amount>, Option<Amount>) {
+        (
+            self.balance(self.market_info.base_asset.id),
+            self.balance(self.market_info.quote_asset.id),
+        )
+    }
+
+    /// Whether quoting is paused by hand.
+    pub fn is_paused(&self) -> bool {
+        matches!(&self.paused, Some(paused) if *paused.borrow())
+    }
+
+    /// Whether the market cools down from a triggered stop.
+    pub fn in_cooldown(&self) -> bool {
+        matches!(&self.cooldown, Some(cooldown) if *cooldown.borrow())
+    }
+
+    /// The backend's time at the local time `now`, which expiries are
+    /// checked against.
+    pub fn backend_time(&self, now: Timestamp) -> Timestamp {
+        self.clock.backend_time(now)
+    }
+
+    /// Our position in the base asset, zero when not published.
+    pub fn position(&self) -> Amount {
+        self.position
+            .as_ref()
+            .map_or(Decimal::ZERO, |position| *position.borrow())
+    }
+}
+
+/// Hooks of a strategy, all doing nothing by default. An error stops the
+/// strategy.
+#[async_trait]
+pub trait Strategy: Send {
+    async fn on_start(&mut self, _ctx: &StrategyContext) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called every `TICK_INTERVAL`.
+    async fn on_tick(&mut self, _ctx: &StrategyContext, _now: Timestamp) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// ZigZag's last price of the market.
+    async fn on_last_price(
+        &mut self,
+        _ctx: &StrategyContext,
+        _price: Decimal,
+        _now: Timestamp,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Liquidity of the market, already applied to `ctx.book()`.
+    async fn on_liquidity(
+        &mut self,
+        _ctx: &StrategyContext,
+        _liquidity: &Liquidity2Args,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A fill of the market, from a receipt or the fills snapshot.
+    async fn on_fill(&mut self, _ctx: &StrategyContext, _fill: &Fill) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A taker asks us to fill its order.
+    async fn on_fill_request(
+        &mut self,
+        _ctx: &StrategyContext,
+        _request: FillrequestArgs,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn on_quote_request(
+        &mut self,
+        _ctx: &StrategyContext,
+        _request: &RequestquoteArgs,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// ZigZag rejected one of our operations. Errors do not name their
+    /// market, so every strategy gets them.
+    async fn on_error(&mut self, _ctx: &StrategyContext, _error: &ErrorArgs) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The connection came back and the session was replayed.
+    async fn on_reconnect(
+        &mut self,
+        _ctx: &StrategyContext,
+        _now: Timestamp,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// ZigZag asks makers to send their liquidity of the market again.
+    async fn on_refresh_liquidity(
+        &mut self,
+        _ctx: &StrategyContext,
+        _now: Timestamp,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The settings of the market changed, as after a config reload.
+    /// Applies to the next tick, what the strategy followed so far is kept.
+    async fn on_config(
+        &mut self,
+        _ctx: &StrategyContext,
+        _config: &MarketMakerConfig,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The backend sent a snapshot of our orders or fills.
+    async fn on_snapshot(&mut self, _ctx: &StrategyContext) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn on_shutdown(&mut self, _ctx: &StrategyContext) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Drives `strategy` with the operations of its market until `shutdown`
+/// flips or the operations stop.
+pub async fn run(
+    mut strategy: Box<dyn Strategy>,
+    mut ctx: StrategyContext,
+    mut ops: mpsc::UnboundedReceiver<Operation>,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut connection = ctx.connection.take();
+    let mut config = ctx.config.take();
+    strategy.on_start(&ctx).await?;
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        tokio::select! {
+            Some(true) = reconnected(&mut connection) => {
+                strategy.on_reconnect(&ctx, unix_timestamp()).await?;
+            }
+            Some(config) = reconfigured(&mut config) => {
+                strategy.on_config(&ctx, &config).await?;
+            }
+            _ = ticker.tick() => {
+                let now = unix_timestamp();
+                ctx.book.prune(ctx.backend_time(now));
+                strategy.on_tick(&ctx, now).await?;
+            }
+            op = ops.recv() => match op {
+                Some(op) => dispatch(strategy.as_mut(), &ctx, op).await?,
+                None => break,
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+    strategy.on_shutdown(&ctx).await?;
+    log::info!("Strategy on {} stopped", ctx.market());
+    Ok(())
+}
+
+/// Calls the hook of an operation routed to the strategy's market.
+pub async fn dispatch<S: Strategy + ?Sized>(
+    strategy: &mut S,
+    ctx: &StrategyContext,
+    op: Operation,
+) -> anyhow::Result<()> {
+    dispatch_at(strategy, ctx, op, unix_timestamp()).await
+}
+
+/// Like `dispatch`, as if the operation arrived at `now`.
+pub async fn dispatch_at<S: Strategy + ?Sized>(
+    strategy: &mut S,
+    ctx: &StrategyContext,
+    op: Operation,
+    now: Timestamp,
+) -> anyhow::Result<()> {
+    match op {
+        Operation::Lastprice(args) => {
+            let market = ctx.market();
+            if let Some(update) = args.updates.iter().rev().find(|u| u.market == market) {
+                match update.price.value() {
+                    Ok(price) => strategy.on_last_price(ctx, price, now).await?,
+                    Err(e) => log::warn!("Ignoring last price of {}: {}", market, e),
+                }
+            }
+        }
+        Operation::Liquidity2(args) => {
+            ctx.book.apply(&args, now);
+            strategy.on_liquidity(ctx, &args).await?;
+        }
+        Operation::Fillreceipt(fill) if fill.market == ctx.market() => {
+            strategy.on_fill(ctx, &fill).await?;
+        }
+        Operation::Fills(args) => {
+            for fill in args.fills.iter().filter(|fill| fill.market == ctx.market()) {
+                strategy.on_fill(ctx, fill).await?;
+            }
+            strategy.on_snapshot(ctx).await?;
+        }
+        Operation::Orders(_) => strategy.on_snapshot(ctx).await?,
+        Operation::Refreshliquidity(args) if args.market == ctx.market() => {
+            strategy.on_refresh_liquidity(ctx, now).await?;
+        }
+        Operation::Fillrequest(args) => strategy.on_fill_request(ctx, *args).await?,
+        Operation::Requestquote(args) => strategy.on_quote_request(ctx, &args).await?,
+        Operation::Error(args) => strategy.on_error(ctx, &args).await?,
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Resolves with the new status when the connection changes, never without
+/// one.
+async fn reconnected(connection: &mut Option<watch::Receiver<bool>>) -> Option<bool> {
+    if let Some(status) = connection {
+        if status.changed().await.is_ok() {
+            return Some(*status.borrow());
+        }
+    }
+    future::pending().await
+}
+
+/// Settings published next, pending forever without a channel.
+async fn reconfigured(
+    config: &mut Option<watch::Receiver<MarketMakerConfig>>,
+) -> Option<MarketMakerConfig> {
+    if let Some(config) = config {
+        if config.changed().await.is_ok() {
+            return Some(config.borrow().clone());
+        }
+    }
+    future::pending().await
+}
+
+pub type StrategyFactory =
+    Box<dyn Fn(&MarketMakerConfig) -> anyhow::Result<Box<dyn Strategy>> + Send + Sync>;
+
+/// Strategies by name, each built once per market from the market maker
+/// settings of that market.
+pub struct StrategyRegistry {
+    factories: HashMap<String, StrategyFactory>,
+}
+
+impl Default for StrategyRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StrategyRegistry {
+    /// Registry with the strategies needing nothing but their config.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            factories: HashMap::new(),
+        };
+        registry.register("logger", |_| Ok(Box::new(LoggerStrategy::new())));
+        registry
+    }
+
+    /// Registers a strategy, replacing any previous one of that name.
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&MarketMakerConfig) -> anyhow::Result<Box<dyn Strategy>> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.to_owned(), Box::new(factory));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.factories.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn build(
+        &self,
+        name: &str,
+        config: &MarketMakerConfig,
+    ) -> anyhow::Result<Box<dyn Strategy>> {
+        match self.factories.get(name) {
+            Some(factory) => factory(config),
+            None => Err(anyhow::anyhow!(
+                "Unknown strategy {}, expected one of {}!",
+                name,
+                self.names().join(", ")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{tests::MockTransport, ZigzagClient};
+    use crate::dispatcher::Dispatcher;
+    use crate::zigzag::fixtures;
+
+    #[tokio::test]
+    async fn test_run_calls_hooks() {
+        let client = ZigzagClient::new(MockTransport::default());
+        let (_dispatcher, handle, _receivers) = Dispatcher::new(client);
+        let (status, status_rx) = watch::channel(true);
+        let (config, config_rx) = watch::channel(market_maker::tests::config());
+        let ctx = StrategyContext::new(
+            fixtures::market_info("ETH-USDC", 0, 2),
+            handle,
+            SummaryCache::new(),
+        )
+        .with_connection(status_rx)
+        .with_config(config_rx);
+        let book = ctx.book().clone();
+        let strategy = LoggerStrategy::new();
+        let (ops_tx, ops) = mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let task = tokio::spawn(run(Box::new(strategy.clone()), ctx, ops, shutdown));
+
+        let op = |json: &str| serde_json::from_str::<Operation>(json).expect("from_str");
+        for json in [
+            r#"{"op":"lastprice","args":[[["WBTC-USDC",30000,1],["ETH-USDC",2000,1]]]}"#,
+            r#"{"op":"liquidity2","args":[1000,"ETH-USDC",[["b",1999,0.5],["s",2001,0.5]]]}"#,
+            r#"{"op":"error","args":["indicateliq2","Rate limit exceeded"]}"#,
+            r#"{"op":"orders","args":[[]]}"#,
+        ] {
+            ops_tx.send(op(json)).expect("send");
+        }
+        tokio::task::yield_now().await;
+        status.send_replace(false);
+        status.send_replace(true);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        config.send_modify(|config| config.spread_bps = Decimal::from(30));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown_tx.send_replace(true);
+        task.await.expect("join").expect("run");
+
+        assert_eq!(
+            strategy.journal(),
+            [
+                "start",
+                "last price 2000",
+                "liquidity 2",
+                "error indicateliq2",
+                "snapshot",
+                "reconnect",
+                "config spread 30",
+                "shutdown"
+            ]
+        );
+        assert_eq!(book.mid_price(), Some(Decimal::from(2000)));
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = StrategyRegistry::new();
+        let config = market_maker::tests::config();
+        assert!(registry.build("logger", &config).is_ok());
+        let error = registry
+            .build("martingale", &config)
+            .err()
+            .expect("unknown strategy");
+        assert_eq!(
+            error.to_string(),
+            "Unknown strategy martingale, expected one of logger!"
+        );
+
+        let mut registry = registry;
+        registry.register("quiet", |_| Ok(Box::new(LoggerStrategy::new())));
+        assert_eq!(registry.names(), ["logger", "quiet"]);
+    }
+}
@@ -0,0 +1,309 @@
+/// zksync token list, by id, symbol and address, loaded from the provider at
+/// startup and refreshed in the background. Market infos only describe the
+/// assets of their market, the registry knows every token.
+use crate::orders::to_raw;
+use crate::zigzag::{Amount, Asset, MarketInfo};
+use async_trait::async_trait;
+use num::BigUint;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use zksync::{provider::Provider, Wallet};
+use zksync_eth_signer::EthereumSigner;
+
+/// How often the token list is fetched again.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenInfo {
+    pub id: u32,
+    pub symbol: String,
+    /// Lowercase 0x-prefixed hex address of the L1 contract, zero for ETH
+    pub address: String,
+    pub decimals: u32,
+}
+
+#[async_trait]
+pub trait TokenSource: Send + Sync {
+    async fn tokens(&self) -> anyhow::Result<Vec<TokenInfo>>;
+}
+
+#[async_trait]
+impl<S, P> TokenSource for Wallet<S, P>
+where
+    S: EthereumSigner,
+    P: Provider + Clone,
+{
+    async fn tokens(&self) -> anyhow::Result<Vec<TokenInfo>> {
+        Ok(self
+            .provider
+            .tokens()
+            .await?
+            .into_values()
+            .map(|token| TokenInfo {
+                id: *token.id,
+                symbol: token.symbol,
+                address: format!("{:?}", token.address),
+                decimals: u32::from(token.decimals),
+            })
+            .collect())
+    }
+}
+
+#[derive(Default)]
+struct Tokens {
+    by_id: BTreeMap<u32, TokenInfo>,
+    by_symbol: HashMap<String, u32>,
+    by_address: HashMap<String, u32>,
+}
+
+impl Tokens {
+    fn new(tokens: Vec<TokenInfo>) -> Self {
+        let mut indexed = Self::default();
+        for token in tokens {
+            indexed
+                .by_symbol
+                .insert(token.symbol.to_uppercase(), token.id);
+            indexed
+                .by_address
+                .insert(token.address.to_lowercase(), token.id);
+            indexed.by_id.insert(token.id, token);
+        }
+        indexed
+    }
+}
+
+/// Token metadata shared by every task, swapped whole on refresh.
+#[derive(Clone, Default)]
+pub struct Registry {
+    tokens: Arc<RwLock<Tokens>>,
+}
+
+impl Registry {
+    pub fn new(tokens: Vec<TokenInfo>) -> Self {
+        Self {
+            tokens: Arc::new(RwLock::new(Tokens::new(tokens))),
+        }
+    }
+
+    pub async fn load(source: &dyn TokenSource) -> anyhow::Result<Self> {
+        let tokens = source.tokens().await?;
+        log::debug!("Loaded {} zksync tokens", tokens.len());
+        Ok(Self::new(tokens))
+    }
+
+    /// Fetches the list again, keeping the current one on failure.
+    pub async fn refresh(&self, source: &dyn TokenSource) -> anyhow::Result<()> {
+        let tokens = Tokens::new(source.tokens().await?);
+        *self.tokens.write().unwrap() = tokens;
+        Ok(())
+    }
+
+    /// Refreshes the list every `interval` until `shutdown` flips.
+    pub async fn run(
+        self,
+        source: Arc<dyn TokenSource>,
+        interval: Duration,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick is immediate, and the list was just loaded.
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(e) = self.refresh(source.as_ref()).await {
+                        log::warn!("Refreshing zksync tokens failed: {}", e);
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.read().unwrap().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn by_id(&self, id: u32) -> Option<TokenInfo> {
+        self.tokens.read().unwrap().by_id.get(&id).cloned()
+    }
+
+    /// Token of `symbol`, whatever its case.
+    pub fn by_symbol(&self, symbol: &str) -> Option<TokenInfo> {
+        let tokens = self.tokens.read().unwrap();
+        let id = tokens.by_symbol.get(&symbol.to_uppercase())?;
+        tokens.by_id.get(id).cloned()
+    }
+
+    pub fn by_address(&self, address: &str) -> Option<TokenInfo> {
+        let tokens = self.tokens.read().unwrap();
+        let id = tokens.by_address.get(&address.to_lowercase())?;
+        tokens.by_id.get(id).cloned()
+    }
+
+    /// Raw units of `amount` of the token of `symbol`, which may not have
+    /// more decimals than the token.
+    pub fn scale_amount(&self, symbol: &str, amount: Amount) -> anyhow::Result<BigUint> {
+        let token = self
+            .by_symbol(symbol)
+            .ok_or_else(|| anyhow::anyhow!("Unknown token {}!", symbol))?;
+        if amount.normalize().scale() > token.decimals {
+            return Err(anyhow::anyhow!(
+                "{} only has {} decimals!",
+                token.symbol,
+                token.decimals
+            ));
+        }
+        to_raw(amount, token.decimals)
+    }
+
+    /// Differences between the assets of `market_info` and the tokens of
+    /// the same ids, one line each.
+    pub fn check_market(&self, market_info: &MarketInfo) -> Vec<String> {
+        [&market_info.base_asset, &market_info.quote_asset]
+            .into_iter()
+            .flat_map(|asset| self.check_asset(asset))
+            .collect()
+    }
+
+    fn check_asset(&self, asset: &Asset) -> Vec<String> {
+        let token = match self.by_id(asset.id) {
+            Some(token) => token,
+            None => {
+                return vec![format!(
+                    "{} has unknown token id {}",
+                    asset.symbol, asset.id
+                )]
+            }
+        };
+        let mut problems = Vec::new();
+        if !token.symbol.eq_ignore_ascii_case(&asset.symbol) {
+            problems.push(format!(
+                "token {} is {}, not {}",
+                asset.id, token.symbol, asset.symbol
+            ));
+        }
+        if token.decimals != asset.decimals {
+            problems.push(format!(
+                "{} has {} decimals, not {}",
+                token.symbol, token.decimals, asset.decimals
+            ));
+        }
+        if !token.address.eq_ignore_ascii_case(&asset.address) {
+            problems.push(format!(
+                "{} is at {}, not {}",
+                token.symbol, token.address, asset.address
+            ));
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::fixtures;
+    use rust_decimal_macros::dec;
+    use std::sync::Mutex;
+
+    /// Canned token list, which tests can change.
+    struct CannedTokens(Mutex<Vec<TokenInfo>>);
+
+    #[async_trait]
+    impl TokenSource for CannedTokens {
+        async fn tokens(&self) -> anyhow::Result<Vec<TokenInfo>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    fn token(id: u32, symbol: &str, address: &str, decimals: u32) -> TokenInfo {
+        TokenInfo {
+            id,
+            symbol: symbol.into(),
+            address: address.into(),
+            decimals,
+        }
+    }
+
+    fn canned() -> Vec<TokenInfo> {
+        vec![
+            token(0, "ETH", "0x0000000000000000000000000000000000000000", 18),
+            token(1, "USDT", "0x3b00ef435fa4fcff5c209a37d1f3dcff37c705ad", 6),
+            token(2, "USDC", "0xeb8f08a975ab53e34d8a0330e0d34de942c95926", 6),
+            token(4, "WBTC", "0x577d296678535e4903d59a4c929b718e1d575e0a", 8),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_lookups() {
+        let source = CannedTokens(Mutex::new(canned()));
+        let registry = Registry::load(&source).await.expect("load");
+        assert_eq!(registry.len(), 4);
+        assert_eq!(registry.by_symbol("USDC").map(|t| t.id), Some(2));
+        assert_eq!(registry.by_symbol("usdc").map(|t| t.id), Some(2));
+        assert_eq!(registry.by_id(4).map(|t| t.symbol), Some("WBTC".into()));
+        assert_eq!(
+            registry
+                .by_address("0xEB8F08A975AB53E34D8A0330E0D34DE942C95926")
+                .map(|t| t.id),
+            Some(2)
+        );
+        assert!(registry.by_symbol("DAI").is_none());
+        assert!(registry.by_id(3).is_none());
+    }
+
+    #[test]
+    fn test_scale_amount() {
+        let registry = Registry::new(canned());
+        assert_eq!(
+            registry.scale_amount("USDC", dec!(12.5)).expect("scale"),
+            BigUint::from(12_500_000u32)
+        );
+        assert_eq!(
+            registry.scale_amount("ETH", dec!(0.1)).expect("scale"),
+            BigUint::from(100_000_000_000_000_000u64)
+        );
+        assert!(registry.scale_amount("USDC", dec!(0.0000001)).is_err());
+        assert!(registry.scale_amount("DAI", dec!(1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let source = CannedTokens(Mutex::new(canned()));
+        let registry = Registry::load(&source).await.expect("load");
+        let shared = registry.clone();
+        source.0.lock().unwrap().push(token(
+            5,
+            "DAI",
+            "0x2e055eee18284513b993db7568a592679ab13188",
+            18,
+        ));
+        registry.refresh(&source).await.expect("refresh");
+        assert_eq!(shared.by_symbol("DAI").map(|t| t.id), Some(5));
+    }
+
+    #[test]
+    fn test_check_market() {
+        let registry = Registry::new(canned());
+        let mut info = fixtures::market_info("ETH-USDC", 0, 2);
+        info.base_asset.address = "0x0000000000000000000000000000000000000000".into();
+        info.quote_asset.address = "0xEB8F08A975AB53E34D8A0330E0D34DE942C95926".into();
+        assert_eq!(registry.check_market(&info), Vec::<String>::new());
+
+        info.quote_asset.decimals = 18;
+        info.base_asset.id = 3;
+        assert_eq!(
+            registry.check_market(&info),
+            vec![
+                "ETH has unknown token id 3".to_owned(),
+                "USDC has 6 decimals, not 18".to_owned()
+            ]
+        );
+    }
+}
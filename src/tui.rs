@@ -0,0 +1,440 @@
+/// Terminal dashboard of the `--tui` mode: for each market the top of the
+/// book with our quotes highlighted, the reference price, our position and
+/// PnL, under them a log of fills and errors. The bot redraws it whenever it
+/// handles an event.
+use crate::feeds::Reference;
+use crate::orderbook::{Level, OrderBook};
+use crate::zigzag::{Amount, Decimal, Liquidity, Market, Operation, Side, Timestamp};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Book levels shown on each side, fewer when the terminal is short.
+const BOOK_DEPTH: usize = 5;
+/// Events kept for the log.
+const MAX_EVENTS: usize = 200;
+/// Smallest market pane worth drawing.
+const MIN_PANE_WIDTH: u16 = 36;
+const MIN_HEIGHT: u16 = 10;
+const BOOK_WIDTHS: [Constraint; 3] = [
+    Constraint::Length(4),
+    Constraint::Percentage(50),
+    Constraint::Percentage(50),
+];
+
+/// What the dashboard shows of a market.
+pub struct MarketView {
+    market: Market,
+    book: Arc<OrderBook>,
+    /// Liquidity we last advertised
+    quotes: Vec<Liquidity>,
+    pub reference: Option<Reference>,
+    /// Age past which the reference is shown as stale
+    pub reference_max_age: Option<Duration>,
+    pub position: Amount,
+    pub realized_pnl: Decimal,
+    pub avg_entry_price: Option<Decimal>,
+}
+
+impl MarketView {
+    pub fn new(market: Market, book: Arc<OrderBook>) -> Self {
+        Self {
+            market,
+            book,
+            quotes: Vec::new(),
+            reference: None,
+            reference_max_age: None,
+            position: Decimal::ZERO,
+            realized_pnl: Decimal::ZERO,
+            avg_entry_price: None,
+        }
+    }
+
+    pub fn market(&self) -> &str {
+        &self.market
+    }
+
+    /// Price the position is marked at: the reference, the mid otherwise.
+    fn mark_price(&self) -> Option<Decimal> {
+        self.reference
+            .as_ref()
+            .map(|reference| reference.price)
+            .or_else(|| self.book.mid_price())
+    }
+
+    pub fn unrealized_pnl(&self) -> Option<Decimal> {
+        Some((self.mark_price()? - self.avg_entry_price?) * self.position)
+    }
+
+    fn is_ours(&self, side: &Side, level: &Level) -> bool {
+        self.quotes
+            .iter()
+            .any(|quote| quote.side == *side && quote.price.value().ok() == Some(level.price))
+    }
+
+    fn render<B: Backend>(&self, frame: &mut Frame<B>, area: Rect, now: Timestamp) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(self.market.as_str());
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0)])
+            .split(inner);
+        frame.render_widget(Paragraph::new(self.summary(now)), chunks[0]);
+
+        // A line for the header, then as many levels as fit on each side.
+        let depth = BOOK_DEPTH.min(usize::from(chunks[1].height.saturating_sub(1)) / 2);
+        let snapshot = self.book.snapshot();
+        let row = |side: Side, level: &Level| {
+            let (label, color) = match side {
+                Side::Buy => ("bid", Color::Green),
+                Side::Sell => ("ask", Color::Red),
+            };
+            let mut style = Style::default().fg(color);
+            if self.is_ours(&side, level) {
+                style = style.add_modifier(Modifier::REVERSED | Modifier::BOLD);
+            }
+            Row::new(vec![
+                label.to_owned(),
+                level.price.to_string(),
+                level.base_quantity.to_string(),
+            ])
+            .style(style)
+        };
+        let asks = snapshot.asks.iter().take(depth).rev();
+        let bids = snapshot.bids.iter().take(depth);
+        let rows: Vec<_> = asks
+            .map(|level| row(Side::Sell, level))
+            .chain(bids.map(|level| row(Side::Buy, level)))
+            .collect();
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec!["", "price", "quantity"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .widths(&BOOK_WIDTHS);
+        frame.render_widget(table, chunks[1]);
+    }
+
+    /// Reference, position and PnL lines.
+    fn summary(&self, now: Timestamp) -> Vec<Spans<'static>> {
+        let reference = match &self.reference {
+            Some(reference) => {
+                let age = now.saturating_sub(reference.updated);
+                let stale = matches!(self.reference_max_age, Some(max_age) if reference.is_stale(max_age, now));
+                let mut spans = vec![Span::raw(format!(
+                    "ref {} {:?} {}s",
+                    reference.price, reference.source, age
+                ))];
+                if stale {
+                    spans.push(Span::styled(" stale", Style::default().fg(Color::Red)));
+                }
+                Spans::from(spans)
+            }
+            None => Spans::from("ref -"),
+        };
+        let position = match self.avg_entry_price {
+            Some(entry) => format!("pos {} @ {}", self.position, entry),
+            None => format!("pos {}", self.position),
+        };
+        let unrealized = self
+            .unrealized_pnl()
+            .map_or_else(|| "-".to_owned(), |pnl| pnl.round_dp(2).to_string());
+        vec![
+            reference,
+            Spans::from(position),
+            Spans::from(format!(
+                "pnl {} realized, {} unrealized",
+                self.realized_pnl.round_dp(2),
+                unrealized
+            )),
+        ]
+    }
+}
+
+/// State of the dashboard, drawn by `render`.
+#[derive(Default)]
+pub struct Dashboard {
+    markets: Vec<MarketView>,
+    events: VecDeque<String>,
+    paused: bool,
+}
+
+impl Dashboard {
+    pub fn add_market(&mut self, view: MarketView) {
+        self.markets.push(view);
+    }
+
+    pub fn market_mut(&mut self, market: &str) -> Option<&mut MarketView> {
+        self.markets.iter_mut().find(|view| view.market == market)
+    }
+
+    pub fn markets_mut(&mut self) -> impl Iterator<Item = &mut MarketView> {
+        self.markets.iter_mut()
+    }
+
+    /// Follows the liquidity we send.
+    pub fn on_sent(&mut self, op: &Operation) {
+        if let Operation::Indicateliq2(args) = op {
+            if let Some(view) = self.market_mut(&args.market) {
+                view.quotes = args.liquidity.clone();
+            }
+        }
+    }
+
+    /// Adds a line to the event log, dropping the oldest past
+    /// `MAX_EVENTS`.
+    pub fn log(&mut self, event: String) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn render<B: Backend>(&self, frame: &mut Frame<B>, now: Timestamp) {
+        let area = frame.size();
+        if area.width < MIN_PANE_WIDTH || area.height < MIN_HEIGHT {
+            frame.render_widget(Paragraph::new("Terminal too small, q to quit"), area);
+            return;
+        }
+        let events_height = (area.height / 4).max(3);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Min(0),
+                Constraint::Length(events_height),
+            ])
+            .split(area);
+
+        // Markets that do not fit side by side are left out.
+        let shown = self
+            .markets
+            .len()
+            .min(usize::from(area.width / MIN_PANE_WIDTH))
+            .max(1);
+        let mut status = vec![Span::raw("q quit  p pause quoting")];
+        if self.paused {
+            status.push(Span::styled(
+                "  PAUSED",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ));
+        }
+        if shown < self.markets.len() {
+            status.push(Span::raw(format!(
+                "  {} more markets, widen the terminal",
+                self.markets.len() - shown
+            )));
+        }
+        frame.render_widget(Paragraph::new(Spans::from(status)), chunks[0]);
+
+        if !self.markets.is_empty() {
+            let constraints = vec![Constraint::Ratio(1, shown as u32); shown];
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(constraints)
+                .split(chunks[1]);
+            for (view, pane) in self.markets.iter().zip(panes.iter()) {
+                view.render(frame, *pane, now);
+            }
+        }
+
+        let block = Block::default().borders(Borders::ALL).title("events");
+        let visible = usize::from(block.inner(chunks[2]).height);
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .skip(self.events.len().saturating_sub(visible))
+            .map(|event| ListItem::new(event.as_str()))
+            .collect();
+        frame.render_widget(List::new(events).block(block), chunks[2]);
+    }
+}
+
+/// Keys the dashboard reacts to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Quit,
+    TogglePause,
+    Resize,
+}
+
+/// The terminal while the dashboard owns it, restored when dropped.
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    inputs: mpsc::UnboundedReceiver<Input>,
+}
+
+impl Tui {
+    pub fn start() -> anyhow::Result<Self> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        let (tx, inputs) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            // Polls, to end soon after the dashboard.
+            while !tx.is_closed() {
+                let input = match event::poll(Duration::from_millis(200)).and_then(|ready| {
+                    Ok(match ready {
+                        true => Some(event::read()?),
+                        false => None,
+                    })
+                }) {
+                    Ok(Some(Event::Key(key))) => read_key(key),
+                    Ok(Some(Event::Resize(_, _))) => Some(Input::Resize),
+                    Ok(_) => None,
+                    Err(e) => {
+                        log::error!("Reading the terminal: {}", e);
+                        return;
+                    }
+                };
+                if let Some(input) = input {
+                    let _ = tx.send(input);
+                }
+            }
+        });
+        Ok(Self { terminal, inputs })
+    }
+
+    pub fn draw(&mut self, dashboard: &Dashboard, now: Timestamp) -> anyhow::Result<()> {
+        self.terminal.draw(|frame| dashboard.render(frame, now))?;
+        Ok(())
+    }
+
+    pub async fn input(&mut self) -> Option<Input> {
+        self.inputs.recv().await
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+fn read_key(key: KeyEvent) -> Option<Input> {
+    match (key.code, key.modifiers) {
+        // Raw mode swallows the signal of Ctrl-C.
+        (KeyCode::Char('q'), _) | (KeyCode::Char('c'), KeyModifiers::CONTROL) => Some(Input::Quit),
+        (KeyCode::Char('p'), _) => Some(Input::TogglePause),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeds::Source;
+    use crate::zigzag::{Indicateliq2Args, Liquidity2Args};
+    use ratatui::backend::TestBackend;
+    use rust_decimal_macros::dec;
+
+    fn draw(dashboard: &Dashboard, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).expect("terminal");
+        terminal
+            .draw(|frame| dashboard.render(frame, 1000))
+            .expect("draw");
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(usize::from(buffer.area.width))
+            .map(|line| {
+                line.iter()
+                    .map(|cell| cell.symbol.as_str())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn dashboard() -> Dashboard {
+        let book = Arc::new(OrderBook::new("ETH-USDC".into()));
+        book.apply(
+            &Liquidity2Args {
+                chain_id: 1000,
+                market: "ETH-USDC".into(),
+                liquidity: serde_json::from_str(r#"[["s",2010,1.0],["b",1990,0.5]]"#)
+                    .expect("from_str"),
+            },
+            1000,
+        );
+        let mut view = MarketView::new("ETH-USDC".into(), book);
+        view.reference = Some(Reference {
+            price: dec!(2000),
+            source: Source::Binance,
+            updated: 900,
+        });
+        view.reference_max_age = Some(Duration::from_secs(30));
+        view.position = dec!(0.5);
+        view.avg_entry_price = Some(dec!(1900));
+        let mut dashboard = Dashboard::default();
+        dashboard.add_market(view);
+        dashboard
+    }
+
+    #[test]
+    fn test_render() {
+        let mut dashboard = dashboard();
+        dashboard.on_sent(&Operation::Indicateliq2(Indicateliq2Args {
+            chain_id: 1000,
+            market: "ETH-USDC".into(),
+            liquidity: serde_json::from_str(r#"[["b",1990,0.5]]"#).expect("from_str"),
+        }));
+        dashboard.log("Filled 0.5 ETH-USDC".into());
+        dashboard.set_paused(true);
+        let market = dashboard.market_mut("ETH-USDC").expect("market");
+        assert!(market.is_ours(&Side::Buy, &market.book.snapshot().bids[0]));
+        assert!(!market.is_ours(&Side::Sell, &market.book.snapshot().asks[0]));
+        assert_eq!(market.unrealized_pnl(), Some(dec!(50)));
+
+        let screen = draw(&dashboard, 80, 24);
+        assert!(screen.contains("PAUSED"));
+        assert!(screen.contains("ETH-USDC"));
+        assert!(screen.contains("ref 2000 Binance 100s stale"));
+        assert!(screen.contains("pos 0.5 @ 1900"));
+        assert!(screen.contains("pnl 0 realized, 50.0 unrealized"));
+        assert!(screen.contains("1990"));
+        assert!(screen.contains("2010"));
+        assert!(screen.contains("Filled 0.5 ETH-USDC"));
+    }
+
+    #[test]
+    fn test_small_terminal() {
+        let mut dashboard = dashboard();
+        let book = Arc::new(OrderBook::new("WBTC-USDC".into()));
+        dashboard.add_market(MarketView::new("WBTC-USDC".into(), book));
+        let screen = draw(&dashboard, 60, 24);
+        assert!(screen.contains("ETH-USDC"));
+        assert!(!screen.contains("WBTC-USDC"));
+        assert!(screen.contains("1 more markets"));
+        assert!(draw(&dashboard, 30, 6).contains("Terminal too small"));
+    }
+}
@@ -0,0 +1,234 @@
+/// Time-weighted execution of a large taker order: the quantity is split
+/// into even slices spread over a window, each taken only while its price
+/// stays within a tolerance of the reference at that time. Skipped slices
+/// are given up, or carried over to the next ones with catch-up.
+use crate::rfq;
+use crate::zigzag::{Amount, Decimal, Side};
+use rust_decimal::RoundingStrategy;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TwapConfig {
+    pub side: Side,
+    /// Base quantity to trade in total
+    pub quantity: Amount,
+    pub slices: u32,
+    pub window: Duration,
+    /// Worst price accepted over the reference, in basis points
+    pub tolerance_bps: Decimal,
+    pub catch_up: bool,
+    /// Decimals of the base asset, slices are rounded down to them
+    pub decimals: u32,
+}
+
+/// Progress of a TWAP execution, one slice after the other.
+#[derive(Clone, Debug)]
+pub struct Twap {
+    config: TwapConfig,
+    /// Reference price when the execution started
+    arrival_price: Decimal,
+    /// Slices run or skipped so far
+    done: u32,
+    filled: Amount,
+    notional: Decimal,
+    /// Quantity of the skipped slices not carried over
+    given_up: Amount,
+}
+
+impl Twap {
+    pub fn new(config: TwapConfig, arrival_price: Decimal) -> anyhow::Result<Self> {
+        if config.slices == 0 || config.quantity <= Decimal::ZERO {
+            return Err(anyhow::anyhow!(
+                "A TWAP needs a positive quantity and at least one slice!"
+            ));
+        }
+        Ok(Self {
+            config,
+            arrival_price,
+            done: 0,
+            filled: Decimal::ZERO,
+            notional: Decimal::ZERO,
+            given_up: Decimal::ZERO,
+        })
+    }
+
+    /// Index of the next slice, none once every slice ran.
+    pub fn next_slice(&self) -> Option<u32> {
+        (self.done < self.config.slices).then_some(self.done)
+    }
+
+    /// Time of `slice` from the start, the first one right away.
+    pub fn offset(&self, slice: u32) -> Duration {
+        self.config.window * slice / self.config.slices
+    }
+
+    /// Quantity the slices up to `slice` should have traded together. The
+    /// last one takes what rounding left.
+    fn target(&self, slice: u32) -> Amount {
+        if slice + 1 >= self.config.slices {
+            return self.config.quantity;
+        }
+        let share = (self.config.quantity / Decimal::from(self.config.slices))
+            .round_dp_with_strategy(self.config.decimals, RoundingStrategy::ToZero);
+        share * Decimal::from(slice + 1)
+    }
+
+    /// Quantity of the next slice, with what earlier ones missed when
+    /// catching up.
+    pub fn quantity(&self) -> Amount {
+        match self.next_slice() {
+            Some(slice) => (self.target(slice) - self.filled - self.given_up).max(Decimal::ZERO),
+            None => Decimal::ZERO,
+        }
+    }
+
+    /// Worst price of a slice taken when the reference is `reference`.
+    pub fn limit_price(&self, reference: Decimal) -> Decimal {
+        let tolerance = self.config.tolerance_bps / Decimal::from(10_000);
+        match self.config.side {
+            Side::Buy => reference * (Decimal::ONE + tolerance),
+            Side::Sell => reference * (Decimal::ONE - tolerance),
+        }
+    }
+
+    /// Whether a slice may be taken at `price` when the reference is
+    /// `reference`.
+    pub fn accepts(&self, price: Decimal, reference: Decimal) -> bool {
+        rfq::within_slippage(
+            &self.config.side,
+            price,
+            reference,
+            self.config.tolerance_bps,
+        )
+    }
+
+    /// Records the next slice as traded, `quantity` at `price`.
+    pub fn fill(&mut self, quantity: Amount, price: Decimal) {
+        self.filled += quantity;
+        self.notional += quantity * price;
+        self.done += 1;
+    }
+
+    /// Records the next slice as skipped, `quantity` untraded.
+    pub fn skip(&mut self, quantity: Amount) {
+        if !self.config.catch_up {
+            self.given_up += quantity;
+        }
+        self.done += 1;
+    }
+
+    pub fn filled(&self) -> Amount {
+        self.filled
+    }
+
+    pub fn avg_price(&self) -> Option<Decimal> {
+        (!self.filled.is_zero()).then(|| self.notional / self.filled)
+    }
+
+    /// How much worse the average price is than the arrival price, in
+    /// basis points.
+    pub fn slippage_bps(&self) -> Option<Decimal> {
+        let avg = self.avg_price()?;
+        if self.arrival_price.is_zero() {
+            return None;
+        }
+        let worse = match self.config.side {
+            Side::Buy => avg - self.arrival_price,
+            Side::Sell => self.arrival_price - avg,
+        };
+        Some(worse / self.arrival_price * Decimal::from(10_000))
+    }
+
+    /// One line for the log.
+    pub fn progress(&self) -> String {
+        let avg = match (self.avg_price(), self.slippage_bps()) {
+            (Some(avg), Some(bps)) => format!(
+                "avg price {} vs arrival {} ({} bps)",
+                avg.round_dp(8).normalize(),
+                self.arrival_price,
+                bps.round_dp(1).normalize()
+            ),
+            _ => format!("nothing traded, arrival {}", self.arrival_price),
+        };
+        format!(
+            "{}/{} slices, filled {} of {}, {}",
+            self.done, self.config.slices, self.filled, self.config.quantity, avg
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config(side: Side, catch_up: bool) -> TwapConfig {
+        TwapConfig {
+            side,
+            quantity: dec!(10),
+            slices: 3,
+            window: Duration::from_secs(90),
+            tolerance_bps: dec!(50),
+            catch_up,
+            decimals: 2,
+        }
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut twap = Twap::new(config(Side::Buy, false), dec!(2000)).expect("new");
+        let offsets: Vec<_> = (0..3).map(|slice| twap.offset(slice)).collect();
+        assert_eq!(offsets, [0, 30, 60].map(Duration::from_secs).to_vec());
+        assert_eq!(twap.quantity(), dec!(3.33));
+        twap.fill(dec!(3.33), dec!(2000));
+        // Skipped slices are given up.
+        twap.skip(dec!(3.33));
+        assert_eq!(twap.next_slice(), Some(2));
+        assert_eq!(twap.quantity(), dec!(3.34));
+        twap.fill(dec!(3.34), dec!(2010));
+        assert_eq!(twap.next_slice(), None);
+        assert_eq!(twap.filled(), dec!(6.67));
+        assert_eq!(twap.quantity(), Decimal::ZERO);
+
+        let mut twap = Twap::new(config(Side::Buy, true), dec!(2000)).expect("new");
+        twap.skip(dec!(3.33));
+        assert_eq!(twap.quantity(), dec!(6.66));
+        twap.fill(dec!(6.66), dec!(2000));
+        assert_eq!(twap.quantity(), dec!(3.34));
+
+        let mut zero = config(Side::Buy, false);
+        zero.slices = 0;
+        assert!(Twap::new(zero, dec!(2000)).is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        let buy = Twap::new(config(Side::Buy, false), dec!(2000)).expect("new");
+        assert_eq!(buy.limit_price(dec!(2000)), dec!(2010));
+        assert!(buy.accepts(dec!(2010), dec!(2000)));
+        assert!(!buy.accepts(dec!(2010.5), dec!(2000)));
+        // The reference of the slice counts, not the arrival price.
+        assert!(buy.accepts(dec!(2100), dec!(2090)));
+
+        let sell = Twap::new(config(Side::Sell, false), dec!(2000)).expect("new");
+        assert_eq!(sell.limit_price(dec!(2000)), dec!(1990));
+        assert!(sell.accepts(dec!(1995), dec!(2000)));
+        assert!(!sell.accepts(dec!(1989), dec!(2000)));
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut twap = Twap::new(config(Side::Buy, false), dec!(2000)).expect("new");
+        assert_eq!(
+            twap.progress(),
+            "0/3 slices, filled 0 of 10, nothing traded, arrival 2000"
+        );
+        twap.fill(dec!(3.33), dec!(2000));
+        twap.fill(dec!(3.33), dec!(2006));
+        assert_eq!(twap.avg_price(), Some(dec!(2003)));
+        assert_eq!(
+            twap.progress(),
+            "2/3 slices, filled 6.66 of 10, avg price 2003 vs arrival 2000 (15 bps)"
+        );
+    }
+}
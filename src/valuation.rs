@@ -0,0 +1,310 @@
+/// Mark-to-market valuation of an account: unrealized PnL of the positions
+/// at the latest prices, and equity of the token balances in one valuation
+/// currency. Tokens without a market against that currency are converted
+/// through other markets. Values that cannot be priced are unknown, never
+/// zero.
+use crate::balances::Balances;
+use crate::metrics::Metrics;
+use crate::portfolio::FillTracker;
+use crate::zigzag::{Amount, Decimal, Market, MarketInfo};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MarketValuation {
+    pub position: Amount,
+    pub mark_price: Option<Decimal>,
+    /// In quote units
+    pub unrealized_pnl: Option<Decimal>,
+    /// In the valuation currency
+    pub unrealized_value: Option<Decimal>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Valuation {
+    pub currency: String,
+    pub markets: BTreeMap<Market, MarketValuation>,
+    /// Unrealized PnL of every market, in the valuation currency
+    pub unrealized_pnl: Option<Decimal>,
+    /// Value of the token balances, in the valuation currency
+    pub equity: Option<Decimal>,
+}
+
+impl Valuation {
+    /// Valuation of several accounts together, unknown totals staying
+    /// unknown.
+    pub fn combine(currency: &str, valuations: &[Valuation]) -> Self {
+        let sum = |total: fn(&Valuation) -> Option<Decimal>| {
+            valuations
+                .iter()
+                .map(total)
+                .sum::<Option<Decimal>>()
+                .filter(|_| !valuations.is_empty())
+        };
+        Self {
+            currency: currency.to_owned(),
+            markets: valuations.iter().flat_map(|v| v.markets.clone()).collect(),
+            unrealized_pnl: sum(|v| v.unrealized_pnl),
+            equity: sum(|v| v.equity),
+        }
+    }
+
+    /// Sets the `equity`, `unrealized_pnl` and `unrealized_pnl_<market>`
+    /// gauges, removing those that became unknown.
+    pub fn record(&self, metrics: &Metrics) {
+        let gauges = [
+            ("equity".to_owned(), self.equity),
+            ("unrealized_pnl".to_owned(), self.unrealized_pnl),
+        ]
+        .into_iter()
+        .chain(self.markets.iter().map(|(market, valuation)| {
+            (
+                format!("unrealized_pnl_{}", market),
+                valuation.unrealized_value,
+            )
+        }));
+        for (name, value) in gauges {
+            match value {
+                Some(value) => metrics.set_gauge(&name, value),
+                None => metrics.remove_gauge(&name),
+            }
+        }
+    }
+
+    /// One line for the log.
+    pub fn summary(&self) -> String {
+        let known = |value: Option<Decimal>| match value {
+            Some(value) => format!("{} {}", value.round_dp(2).normalize(), self.currency),
+            None => "unknown".to_owned(),
+        };
+        let markets: Vec<_> = self
+            .markets
+            .iter()
+            .map(|(market, v)| format!("{} {}", market, known(v.unrealized_value)))
+            .collect();
+        format!(
+            "equity {}, unrealized PnL {} ({})",
+            known(self.equity),
+            known(self.unrealized_pnl),
+            markets.join(", ")
+        )
+    }
+}
+
+/// Values accounts in `currency` from the prices of `markets`.
+pub struct Valuer {
+    currency: String,
+    markets: Vec<MarketInfo>,
+}
+
+impl Valuer {
+    pub fn new(currency: &str, markets: Vec<MarketInfo>) -> Self {
+        Self {
+            currency: currency.to_owned(),
+            markets,
+        }
+    }
+
+    /// `amount` of `from` in `to`, through as few markets as possible.
+    /// `price` gives the price of a market, in quote units per base unit.
+    pub fn convert(
+        &self,
+        amount: Decimal,
+        from: &str,
+        to: &str,
+        price: &dyn Fn(&str) -> Option<Decimal>,
+    ) -> Option<Decimal> {
+        let mut seen = HashSet::from([from.to_uppercase()]);
+        let mut queue = VecDeque::from([(from.to_uppercase(), amount)]);
+        while let Some((symbol, amount)) = queue.pop_front() {
+            if symbol.eq_ignore_ascii_case(to) {
+                return Some(amount);
+            }
+            for info in &self.markets {
+                let (base, quote) = (
+                    info.base_asset.symbol.to_uppercase(),
+                    info.quote_asset.symbol.to_uppercase(),
+                );
+                let next = match price(&info.alias).filter(|p| *p > Decimal::ZERO) {
+                    Some(p) if base == symbol => (quote, amount * p),
+                    Some(p) if quote == symbol => (base, amount / p),
+                    _ => continue,
+                };
+                if seen.insert(next.0.clone()) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Valuation of the positions of `fills` on `markets`, and of the
+    /// `balances` of their tokens when they are known.
+    pub fn value(
+        &self,
+        markets: &[Market],
+        fills: &FillTracker,
+        balances: Option<&Balances>,
+        price: &dyn Fn(&str) -> Option<Decimal>,
+    ) -> Valuation {
+        let mut valuation = Valuation {
+            currency: self.currency.clone(),
+            ..Valuation::default()
+        };
+        for info in self.markets.iter().filter(|i| markets.contains(&i.alias)) {
+            let position = fills.position(&info.alias);
+            let mark_price = price(&info.alias);
+            let unrealized_pnl = match (fills.avg_entry_price(&info.alias), mark_price) {
+                (None, _) => Some(Decimal::ZERO),
+                (Some(entry), Some(mark)) => Some(position * (mark - entry)),
+                (Some(_), None) => None,
+            };
+            let unrealized_value = unrealized_pnl
+                .and_then(|pnl| self.convert(pnl, &info.quote_asset.symbol, &self.currency, price));
+            valuation.markets.insert(
+                info.alias.clone(),
+                MarketValuation {
+                    position,
+                    mark_price,
+                    unrealized_pnl,
+                    unrealized_value,
+                },
+            );
+        }
+        valuation.unrealized_pnl = valuation.markets.values().map(|v| v.unrealized_value).sum();
+        valuation.equity = balances.and_then(|balances| self.equity(markets, balances, price));
+        valuation
+    }
+
+    /// Value of the balances of the tokens of `markets`.
+    fn equity(
+        &self,
+        markets: &[Market],
+        balances: &Balances,
+        price: &dyn Fn(&str) -> Option<Decimal>,
+    ) -> Option<Decimal> {
+        let mut seen = HashSet::new();
+        self.markets
+            .iter()
+            .filter(|info| markets.contains(&info.alias))
+            .flat_map(|info| [&info.base_asset, &info.quote_asset])
+            .filter(|asset| seen.insert(asset.id))
+            .map(|asset| match balances.get(&asset.id) {
+                Some(balance) if balance.is_zero() => Some(Decimal::ZERO),
+                Some(balance) => self.convert(*balance, &asset.symbol, &self.currency, price),
+                None => None,
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::{fixtures, Fill, OrderStatus, Side};
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+
+    fn markets() -> Vec<MarketInfo> {
+        vec![
+            fixtures::market_info("ETH-USDC", 0, 2),
+            fixtures::market_info("WBTC-ETH", 4, 0),
+            fixtures::market_info("DAI-USDT", 5, 1),
+        ]
+    }
+
+    fn prices(market: &str) -> Option<Decimal> {
+        match market {
+            "ETH-USDC" => Some(dec!(2000)),
+            "WBTC-ETH" => Some(dec!(15)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let valuer = Valuer::new("USDC", markets());
+        assert_eq!(
+            valuer.convert(dec!(2), "usdc", "USDC", &prices),
+            Some(dec!(2))
+        );
+        assert_eq!(
+            valuer.convert(dec!(2), "ETH", "USDC", &prices),
+            Some(dec!(4000))
+        );
+        assert_eq!(
+            valuer.convert(dec!(3000), "USDC", "ETH", &prices),
+            Some(dec!(1.5))
+        );
+        // Through ETH-USDC.
+        assert_eq!(
+            valuer.convert(dec!(0.5), "WBTC", "USDC", &prices),
+            Some(dec!(15000))
+        );
+        assert_eq!(valuer.convert(dec!(1), "DAI", "USDC", &prices), None);
+        assert_eq!(valuer.convert(dec!(1), "WBTC", "USDC", &|_| None), None);
+    }
+
+    #[test]
+    fn test_value() {
+        let valuer = Valuer::new("USDC", markets());
+        let mut fills = FillTracker::new("23".into());
+        fills.apply(&Fill {
+            market: "WBTC-ETH".into(),
+            ..fixtures::fill(1, Side::Buy, dec!(14), dec!(2), OrderStatus::Filled)
+        });
+        fills.apply(&Fill {
+            market: "DAI-USDT".into(),
+            ..fixtures::fill(2, Side::Buy, dec!(1), dec!(100), OrderStatus::Filled)
+        });
+        let markets: Vec<Market> = vec!["ETH-USDC".into(), "WBTC-ETH".into(), "DAI-USDT".into()];
+        let balances: Balances = HashMap::from([
+            (0, dec!(1)),
+            (2, dec!(500)),
+            (4, dec!(2)),
+            (5, Decimal::ZERO),
+        ]);
+
+        let valuation = valuer.value(&markets, &fills, Some(&balances), &prices);
+        let wbtc = &valuation.markets["WBTC-ETH"];
+        // 2 WBTC bought at 14 ETH are worth 2 ETH more at 15.
+        assert_eq!(wbtc.unrealized_pnl, Some(dec!(2)));
+        assert_eq!(wbtc.unrealized_value, Some(dec!(4000)));
+        assert_eq!(
+            valuation.markets["ETH-USDC"].unrealized_value,
+            Some(dec!(0))
+        );
+        // No DAI-USDT price, unknown rather than zero.
+        assert_eq!(valuation.markets["DAI-USDT"].unrealized_pnl, None);
+        assert_eq!(valuation.unrealized_pnl, None);
+        // USDT has no balance known.
+        assert_eq!(valuation.equity, None);
+
+        let balances: Balances = HashMap::from([
+            (0, dec!(1)),
+            (1, Decimal::ZERO),
+            (2, dec!(500)),
+            (4, dec!(2)),
+            (5, Decimal::ZERO),
+        ]);
+        let valuation = valuer.value(&markets[..2], &fills, Some(&balances), &prices);
+        assert_eq!(valuation.unrealized_pnl, Some(dec!(4000)));
+        assert_eq!(valuation.equity, Some(dec!(62500)));
+        assert_eq!(
+            valuation.summary(),
+            "equity 62500 USDC, unrealized PnL 4000 USDC (ETH-USDC 0 USDC, WBTC-ETH 4000 USDC)"
+        );
+
+        let metrics = Metrics::new();
+        valuation.record(&metrics);
+        assert_eq!(metrics.gauge("unrealized_pnl_WBTC-ETH"), Some(dec!(4000)));
+        let combined = Valuation::combine(
+            "USDC",
+            &[valuation, valuer.value(&markets, &fills, None, &prices)],
+        );
+        assert_eq!(combined.equity, None);
+        combined.record(&metrics);
+        assert_eq!(metrics.gauge("equity"), None);
+        assert_eq!(metrics.gauge("unrealized_pnl_DAI-USDT"), None);
+    }
+}
@@ -0,0 +1,235 @@
+/// Daily volume report of the `volume` subcommand, aggregated frame by frame
+/// as the backend answers `dailyvolumereq`, which it may split.
+use crate::repl::table;
+use crate::zigzag::{Amount, ChainId, Date, Market, Operation, Volume};
+use chrono::{Duration as Days, NaiveDate};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Time without a new frame after which the response is complete.
+pub const FRAME_GAP: Duration = Duration::from_secs(1);
+
+/// Volumes to report. Days are UTC, the last `days` up to today.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VolumeFilter {
+    pub market: Option<Market>,
+    pub since: Option<NaiveDate>,
+}
+
+impl VolumeFilter {
+    pub fn new(market: Option<Market>, days: Option<u32>, today: NaiveDate) -> Self {
+        Self {
+            market,
+            since: days.map(|days| today - Days::days(i64::from(days.saturating_sub(1)))),
+        }
+    }
+
+    fn contains(&self, volume: &Volume) -> bool {
+        self.market.iter().all(|m| *m == volume.market)
+            && self
+                .since
+                .iter()
+                .all(|since| matches!(volume.date.parse::<NaiveDate>(), Ok(day) if day >= *since))
+    }
+}
+
+/// Base and quote volume per market and day of one chain.
+#[derive(Debug, Default)]
+pub struct VolumeReport {
+    chain_id: ChainId,
+    filter: VolumeFilter,
+    days: BTreeMap<(Market, Date), (Amount, Amount)>,
+}
+
+impl VolumeReport {
+    pub fn new(chain_id: ChainId, filter: VolumeFilter) -> Self {
+        Self {
+            chain_id,
+            filter,
+            days: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the volumes of a frame. A day sent again replaces the previous
+    /// one rather than adding up.
+    pub fn add(&mut self, volumes: Vec<Volume>) {
+        for volume in volumes {
+            if volume.chain_id != self.chain_id || !self.filter.contains(&volume) {
+                continue;
+            }
+            self.days.insert(
+                (volume.market, volume.date),
+                (volume.base_volume, volume.quote_volume),
+            );
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.days.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.days.is_empty()
+    }
+
+    /// One row per market and day, by market then date.
+    pub fn table(&self) -> String {
+        let rows: Vec<_> = self
+            .days
+            .iter()
+            .map(|((market, date), (base, quote))| {
+                vec![
+                    market.clone(),
+                    date.clone(),
+                    base.normalize().to_string(),
+                    quote.normalize().to_string(),
+                ]
+            })
+            .collect();
+        table(&["market", "date", "base volume", "quote volume"], &rows)
+    }
+
+    /// One JSON object per line, amounts as strings to keep their precision.
+    pub fn json_lines(&self) -> String {
+        self.days
+            .iter()
+            .map(|((market, date), (base, quote))| {
+                serde_json::json!({
+                    "market": market,
+                    "date": date,
+                    "base_volume": base.normalize().to_string(),
+                    "quote_volume": quote.normalize().to_string(),
+                })
+                .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Adds the frames answering the request to `report`, until none came for
+/// `FRAME_GAP` after the first or `timeout` is over. Fails when nothing
+/// arrived in time or the backend rejected the request.
+pub async fn collect(
+    frames: &mut mpsc::UnboundedReceiver<Operation>,
+    report: &mut VolumeReport,
+    timeout: Duration,
+    gap: Duration,
+) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut received = false;
+    loop {
+        let wait = match received {
+            true => deadline.min(Instant::now() + gap),
+            false => deadline,
+        };
+        match tokio::time::timeout_at(wait, frames.recv()).await {
+            Ok(Some(Operation::Dailyvolume(args))) => {
+                received = true;
+                report.add(args.volumes);
+            }
+            Ok(Some(Operation::Error(e))) => return Err(e.into()),
+            Ok(Some(op)) => log::debug!("Ignoring {} while collecting volumes", op.name()),
+            Ok(None) => return Err(anyhow::anyhow!("Zigzag dispatcher has stopped!")),
+            Err(_) if received => return Ok(()),
+            Err(_) => {
+                return Err(anyhow::anyhow!(
+                    "No daily volume received within {:?}",
+                    timeout
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zigzag::DailyvolumeArgs;
+    use rust_decimal_macros::dec;
+
+    fn volume(chain_id: ChainId, market: &str, date: &str, base: Amount, quote: Amount) -> Volume {
+        Volume {
+            chain_id,
+            market: market.into(),
+            date: date.into(),
+            base_volume: base,
+            quote_volume: quote,
+        }
+    }
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 5, 3).unwrap()
+    }
+
+    #[test]
+    fn test_report() {
+        let filter = VolumeFilter::new(None, Some(2), today());
+        let mut report = VolumeReport::new(1000, filter);
+        report.add(vec![
+            volume(1000, "WBTC-USDC", "2022-05-03", dec!(0.10), dec!(3000)),
+            volume(1000, "ETH-USDC", "2022-05-03", dec!(1.5), dec!(4500)),
+            volume(1000, "ETH-USDC", "2022-05-01", dec!(7), dec!(21000)),
+            volume(1, "ETH-USDC", "2022-05-02", dec!(9), dec!(9)),
+        ]);
+        report.add(vec![volume(
+            1000,
+            "ETH-USDC",
+            "2022-05-02",
+            dec!(2),
+            dec!(6000),
+        )]);
+        assert_eq!(
+            report.table(),
+            "market     date        base volume  quote volume\n\
+             ETH-USDC   2022-05-02  2            6000\n\
+             ETH-USDC   2022-05-03  1.5          4500\n\
+             WBTC-USDC  2022-05-03  0.1          3000"
+        );
+
+        let filter = VolumeFilter::new(Some("WBTC-USDC".into()), None, today());
+        let mut report = VolumeReport::new(1000, filter);
+        report.add(vec![
+            volume(1000, "WBTC-USDC", "2022-05-03", dec!(0.10), dec!(3000)),
+            volume(1000, "ETH-USDC", "2022-05-03", dec!(1.5), dec!(4500)),
+        ]);
+        assert_eq!(
+            report.json_lines(),
+            r#"{"base_volume":"0.1","date":"2022-05-03","market":"WBTC-USDC","quote_volume":"3000"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect() {
+        let (tx, mut frames) = mpsc::unbounded_channel();
+        for date in ["2022-05-02", "2022-05-03"] {
+            tx.send(Operation::Dailyvolume(DailyvolumeArgs {
+                volumes: vec![volume(1000, "ETH-USDC", date, dec!(1), dec!(3000))],
+            }))
+            .unwrap();
+        }
+        let mut report = VolumeReport::new(1000, VolumeFilter::default());
+        collect(
+            &mut frames,
+            &mut report,
+            Duration::from_secs(5),
+            Duration::from_millis(10),
+        )
+        .await
+        .expect("collect");
+        assert_eq!(report.len(), 2);
+
+        let mut report = VolumeReport::new(1000, VolumeFilter::default());
+        let e = collect(
+            &mut frames,
+            &mut report,
+            Duration::from_millis(10),
+            Duration::from_millis(10),
+        )
+        .await
+        .expect_err("collect");
+        assert!(e.to_string().contains("No daily volume"), "{}", e);
+    }
+}
@@ -0,0 +1,125 @@
+/// Amounts and addresses of the `withdraw` subcommand, moving funds from
+/// zksync back to L1.
+use crate::orders::{to_raw, to_units};
+use crate::zigzag::{Amount, Decimal};
+use num::BigUint;
+use zksync::zksync_types::Address;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WithdrawAmount {
+    Exact(Amount),
+    /// The whole committed balance, minus the fee
+    All,
+}
+
+/// Raw amount to withdraw from a committed `balance`, the fee included in
+/// neither. Exact amounts may not have more decimals than the token.
+pub fn raw_amount(
+    amount: &WithdrawAmount,
+    symbol: &str,
+    decimals: u32,
+    balance: &BigUint,
+    fee: &BigUint,
+) -> anyhow::Result<BigUint> {
+    let units = |raw: &BigUint| to_units(raw, decimals);
+    let raw = match amount {
+        WithdrawAmount::Exact(amount) => {
+            if *amount <= Decimal::ZERO {
+                return Err(anyhow::anyhow!("Amount must be positive!"));
+            }
+            if amount.normalize().scale() > decimals {
+                return Err(anyhow::anyhow!(
+                    "{} only has {} decimals!",
+                    symbol,
+                    decimals
+                ));
+            }
+            to_raw(*amount, decimals)?
+        }
+        WithdrawAmount::All if balance <= fee => {
+            return Err(anyhow::anyhow!(
+                "Balance of {} {} does not cover the fee of {}!",
+                units(balance)?,
+                symbol,
+                units(fee)?
+            ))
+        }
+        WithdrawAmount::All => balance - fee,
+    };
+    if &raw + fee > *balance {
+        return Err(anyhow::anyhow!(
+            "Balance of {} {} does not cover {} plus the fee of {}!",
+            units(balance)?,
+            symbol,
+            units(&raw)?,
+            units(fee)?
+        ));
+    }
+    Ok(raw)
+}
+
+/// Parses a `0x` prefixed hex address.
+pub fn parse_address(address: &str) -> anyhow::Result<Address> {
+    let hex = address.strip_prefix("0x").unwrap_or(address);
+    let bytes =
+        hex::decode(hex).map_err(|e| anyhow::anyhow!("Invalid address {}: {}", address, e))?;
+    if bytes.len() != Address::len_bytes() {
+        return Err(anyhow::anyhow!(
+            "Invalid address {}: expected 20 bytes",
+            address
+        ));
+    }
+    Ok(Address::from_slice(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_raw_amount() {
+        // 1000 USDC, 0.8 fee.
+        let balance = BigUint::from(1_000_000_000u64);
+        let fee = BigUint::from(800_000u64);
+        let raw = |amount| raw_amount(&amount, "USDC", 6, &balance, &fee);
+        assert_eq!(
+            raw(WithdrawAmount::Exact(dec!(500.25))).expect("raw_amount"),
+            BigUint::from(500_250_000u64)
+        );
+        assert_eq!(
+            raw(WithdrawAmount::Exact(dec!(500.0000000))).expect("raw_amount"),
+            BigUint::from(500_000_000u64)
+        );
+        assert_eq!(
+            raw(WithdrawAmount::All).expect("raw_amount"),
+            BigUint::from(999_200_000u64)
+        );
+        let error = |amount| raw(amount).expect_err("raw_amount").to_string();
+        assert_eq!(
+            error(WithdrawAmount::Exact(dec!(0.0000001))),
+            "USDC only has 6 decimals!"
+        );
+        assert_eq!(
+            error(WithdrawAmount::Exact(dec!(999.5))),
+            "Balance of 1000 USDC does not cover 999.5 plus the fee of 0.8!"
+        );
+        assert_eq!(
+            error(WithdrawAmount::Exact(dec!(0))),
+            "Amount must be positive!"
+        );
+        let empty = raw_amount(&WithdrawAmount::All, "USDC", 6, &fee, &fee);
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+        assert_eq!(
+            parse_address(address).expect("parse_address"),
+            Address::from_slice(&hex::decode(&address[2..]).unwrap())
+        );
+        assert!(parse_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea").is_err());
+        assert!(parse_address("0xzz").is_err());
+    }
+}